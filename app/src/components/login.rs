@@ -47,6 +47,19 @@ pub enum Msg {
     AuthenticationFinishResponse(Result<(String, bool)>),
 }
 
+/// The banner configured on the server, added to the page in a `<meta>` tag.
+fn get_login_banner() -> Option<String> {
+    web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| {
+            d.query_selector(r#"meta[name="login-banner"]"#)
+                .ok()
+                .flatten()
+        })
+        .and_then(|meta| meta.get_attribute("content"))
+        .filter(|banner| !banner.is_empty())
+}
+
 impl CommonComponent<LoginForm> for LoginForm {
     fn handle_msg(
         &mut self,
@@ -157,6 +170,14 @@ impl Component for LoginForm {
             html! {
               <form
                 class="form center-block col-sm-4 col-offset-4">
+                  { if let Some(banner) = get_login_banner() {
+                      html! {
+                        <div class="alert alert-secondary" style="white-space: pre-line;">
+                          {banner}
+                        </div>
+                      }
+                    } else { html! {} }
+                  }
                   <div class="input-group">
                     <div class="input-group-prepend">
                       <span class="input-group-text">
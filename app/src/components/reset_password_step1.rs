@@ -104,7 +104,7 @@ impl Component for ResetPasswordStep1Form {
                 </div>
                 { if self.just_succeeded {
                     html! {
-                      {"A reset token has been sent to your email."}
+                      {"If the account exists, you will receive an email with a reset link."}
                     }
                 } else {
                     html! {
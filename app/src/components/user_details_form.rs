@@ -360,6 +360,7 @@ impl UserDetailsForm {
             firstName: None,
             lastName: None,
             avatar: None,
+            locale: None,
         };
         let default_user_input = user_input.clone();
         let model = self.form.model();
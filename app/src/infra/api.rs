@@ -2,7 +2,7 @@ use super::cookies::set_cookie;
 use anyhow::{anyhow, Context, Result};
 use gloo_net::http::{Method, Request};
 use graphql_client::GraphQLQuery;
-use lldap_auth::{login, registration, JWTClaims};
+use lldap_auth::{login, password_reset, registration, JWTClaims};
 
 use serde::{de::DeserializeOwned, Serialize};
 use web_sys::RequestCredentials;
@@ -67,7 +67,7 @@ async fn call_server_empty_response_with_error_message<Body: Serialize>(
 
 fn set_cookies_from_jwt(response: login::ServerLoginResponse) -> Result<(String, bool)> {
     let jwt_claims = get_claims_from_jwt(response.token.as_str()).context("Could not parse JWT")?;
-    let is_admin = jwt_claims.groups.contains("lldap_admin");
+    let is_admin = jwt_claims.is_admin;
     set_cookie("user_id", &jwt_claims.user, &jwt_claims.exp)
         .map(|_| set_cookie("is_admin", &is_admin.to_string(), &jwt_claims.exp))
         .map(|_| (jwt_claims.user.clone(), is_admin))
@@ -165,8 +165,30 @@ impl HostService {
     }
 
     pub async fn reset_password_step1(username: String) -> Result<()> {
+        let mut url = format!("/auth/reset/step1/{}", url_escape::encode_query(&username));
+        // The server may require a proof of work before sending the email, for this user.
+        let challenge_response = Request::get(&format!(
+            "/auth/reset/challenge/{}",
+            url_escape::encode_query(&username)
+        ))
+        .send()
+        .await?;
+        if challenge_response.ok() {
+            let challenge: password_reset::ResetChallengeResponse = challenge_response
+                .json()
+                .await
+                .context("Could not parse the password reset challenge")?;
+            let nonce =
+                password_reset::solve_proof_of_work(&challenge.challenge, challenge.difficulty);
+            url = format!(
+                "{}?pow_challenge={}&pow_nonce={}",
+                url,
+                url_escape::encode_component(&challenge.challenge),
+                nonce
+            );
+        }
         call_server_empty_response_with_error_message(
-            &format!("/auth/reset/step1/{}", url_escape::encode_query(&username)),
+            &url,
             NO_BODY,
             "Could not initiate password reset",
         )
@@ -52,6 +52,27 @@ pub mod login {
         }
     }
 
+    /// The OPAQUE configuration of the server, to implement the login in other clients.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct ServerOpaqueConfiguration {
+        pub version: String,
+        pub cipher_suite: opaque::CipherSuiteParameters,
+        /// The serialized public key of the server.
+        pub server_public_key: Vec<u8>,
+    }
+
+    /// Fingerprints of the keys of the server, to check that all the replicas use the same ones.
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+    pub struct ServerKeyFingerprint {
+        /// The serialized public key of the server.
+        pub server_public_key: Vec<u8>,
+        /// Hex-encoded SHA-256 of the public key.
+        pub public_key_fingerprint: String,
+        /// Hex-encoded SHA-256 of the whole server setup. The setup includes the private key,
+        /// which can't be recovered from the hash.
+        pub server_setup_fingerprint: String,
+    }
+
     #[derive(Serialize, Deserialize, Clone)]
     pub struct ServerLoginResponse {
         pub token: String,
@@ -102,6 +123,46 @@ pub mod password_reset {
         pub user_id: String,
         pub token: String,
     }
+
+    /// Proof-of-work challenge to solve before requesting a password reset.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct ResetChallengeResponse {
+        pub challenge: String,
+        /// Number of leading zero bits required in the hash.
+        pub difficulty: u8,
+    }
+
+    /// Parameters of the reset request, for the enabled protections.
+    #[derive(Serialize, Deserialize, Clone, Debug, Default)]
+    pub struct ResetProtectionParams {
+        pub pow_challenge: Option<String>,
+        pub pow_nonce: Option<u64>,
+        pub captcha_response: Option<String>,
+    }
+
+    fn leading_zero_bits(bytes: &[u8]) -> u32 {
+        let mut count = 0;
+        for byte in bytes {
+            count += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        count
+    }
+
+    /// Whether SHA-256("{challenge}:{nonce}") starts with `difficulty` zero bits.
+    pub fn is_proof_of_work_valid(challenge: &str, nonce: u64, difficulty: u8) -> bool {
+        use sha2::{Digest, Sha256};
+        let hash = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+        leading_zero_bits(&hash) >= difficulty as u32
+    }
+
+    pub fn solve_proof_of_work(challenge: &str, difficulty: u8) -> u64 {
+        (0..)
+            .find(|&nonce| is_proof_of_work_valid(challenge, nonce, difficulty))
+            .unwrap()
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -110,4 +171,17 @@ pub struct JWTClaims {
     pub iat: DateTime<Utc>,
     pub user: String,
     pub groups: HashSet<String>,
+    /// The user ID or the UUID of the user, depending on the configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// The admin acting as the user, for the tokens issued by impersonation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<String>,
+    /// Whether the user is a member of one of the admin groups, which are configurable.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_admin: bool,
 }
@@ -1,5 +1,6 @@
 use opaque_ke::ciphersuite::CipherSuite;
 use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthenticationError {
@@ -24,7 +25,11 @@ impl ArgonHasher {
     /// Fixed salt, doesn't affect the security. It is only used to make attacks more
     /// computationally intensive, it doesn't serve any security purpose.
     const SALT: &'static [u8] = b"lldap_opaque_salt";
-    /// Config for the argon hasher. Security enthusiasts may want to tweak this for their system.
+    /// Config for the argon hasher.
+    ///
+    /// This is the only key derivation in LLDAP, and it runs on the client (the web app, the CLI
+    /// tools). It is part of every stored password file, so it can't be tuned per deployment:
+    /// changing it would invalidate all the existing passwords.
     const CONFIG: &'static argon2::Config<'static> = &argon2::Config {
         ad: &[],
         hash_length: 128,
@@ -38,6 +43,36 @@ impl ArgonHasher {
     };
 }
 
+/// The parameters of the slow hash, that a client needs to derive the same keys.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SlowHashParameters {
+    /// The argon2 variant, e.g. "argon2id".
+    pub variant: String,
+    /// The argon2 version, e.g. 19 for 0x13.
+    pub version: u32,
+    /// Memory cost, in KB.
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+    /// Length of the output, in bytes.
+    pub hash_length: u32,
+    pub salt: String,
+}
+
+impl ArgonHasher {
+    pub fn parameters() -> SlowHashParameters {
+        SlowHashParameters {
+            variant: Self::CONFIG.variant.as_lowercase_str().to_owned(),
+            version: Self::CONFIG.version.as_u32(),
+            mem_cost: Self::CONFIG.mem_cost,
+            time_cost: Self::CONFIG.time_cost,
+            lanes: Self::CONFIG.lanes,
+            hash_length: Self::CONFIG.hash_length,
+            salt: String::from_utf8_lossy(Self::SALT).into_owned(),
+        }
+    }
+}
+
 impl<D: opaque_ke::hash::Hash> opaque_ke::slow_hash::SlowHash<D> for ArgonHasher {
     fn hash(
         input: generic_array::GenericArray<u8, <D as digest::Digest>::OutputSize>,
@@ -59,6 +94,29 @@ impl CipherSuite for DefaultSuite {
     type SlowHash = ArgonHasher;
 }
 
+/// Version of the OPAQUE implementation: clients have to be compatible with it.
+pub const OPAQUE_VERSION: &str = "opaque-ke 0.6";
+
+/// The primitives of the [`DefaultSuite`], for clients that don't use this crate.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CipherSuiteParameters {
+    pub group: String,
+    pub key_exchange: String,
+    pub hash: String,
+    pub slow_hash: SlowHashParameters,
+}
+
+impl DefaultSuite {
+    pub fn parameters() -> CipherSuiteParameters {
+        CipherSuiteParameters {
+            group: "ristretto255".to_owned(),
+            key_exchange: "3DH".to_owned(),
+            hash: "SHA-512".to_owned(),
+            slow_hash: ArgonHasher::parameters(),
+        }
+    }
+}
+
 /// Client-side code for OPAQUE protocol handling, to register a new user and login.  All methods'
 /// results must be sent to the server using the serialized `.message`. Incoming messages can be
 /// deserialized using the type's `deserialize` method.
@@ -0,0 +1,26 @@
+use std::process::Command;
+
+/// Passes the build information to the server (see `infra::build_info`). Both can be given in the
+/// environment, e.g. when building outside of a git checkout.
+fn main() {
+    println!("cargo:rerun-if-env-changed=LLDAP_GIT_HASH");
+    println!("cargo:rerun-if-env-changed=LLDAP_BUILD_DATE");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/logs/HEAD");
+    let git_hash = std::env::var("LLDAP_GIT_HASH")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|hash| hash.trim().to_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_owned());
+    let build_date = std::env::var("LLDAP_BUILD_DATE")
+        .unwrap_or_else(|_| chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+    println!("cargo:rustc-env=LLDAP_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=LLDAP_BUILD_DATE={}", build_date);
+}
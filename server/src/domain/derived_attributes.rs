@@ -0,0 +1,493 @@
+//! Attributes computed on read from the other attributes of a user, with expressions like
+//! `"/home/" + uid`.
+
+use crate::domain::{
+    handler::{AttributeList, AttributeSchema},
+    ldap::utils::{map_user_field, UserFieldType},
+    types::{AttributeType, AttributeValue, Serialized, User, UserColumn},
+};
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Term {
+    Literal(String),
+    Attribute(String),
+}
+
+/// A concatenation of string literals and attribute names, e.g. `first_name + " " + last_name`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    source: String,
+    terms: Vec<Term>,
+}
+
+fn parse_terms(source: &str) -> Result<Vec<Term>, String> {
+    let mut terms = Vec::new();
+    let mut chars = source.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some('"') => {
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => literal.push(c),
+                            _ => return Err(format!("Invalid escape sequence in `{}`", source)),
+                        },
+                        Some(c) => literal.push(c),
+                        None => return Err(format!("Unterminated string in `{}`", source)),
+                    }
+                }
+                terms.push(Term::Literal(literal));
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+                {
+                    name.push(c);
+                }
+                terms.push(Term::Attribute(name));
+            }
+            Some(c) => return Err(format!("Unexpected character '{}' in `{}`", c, source)),
+            None => return Err(format!("Expected a string or an attribute in `{}`", source)),
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            None => return Ok(terms),
+            Some('+') => (),
+            Some(c) => return Err(format!("Expected '+', found '{}' in `{}`", c, source)),
+        }
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let terms = parse_terms(&source)?;
+        Ok(Self { source, terms })
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> Self {
+        expression.source
+    }
+}
+
+/// An attribute computed from the other attributes of a user.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerivedAttribute {
+    pub name: String,
+    pub expression: Expression,
+}
+
+fn find_attribute<'a>(
+    derived_attributes: &'a [DerivedAttribute],
+    name: &str,
+) -> Option<&'a DerivedAttribute> {
+    derived_attributes
+        .iter()
+        .find(|a| a.name.eq_ignore_ascii_case(name))
+}
+
+fn references(attribute: &DerivedAttribute) -> impl Iterator<Item = &str> {
+    attribute.expression.terms.iter().filter_map(|t| match t {
+        Term::Attribute(name) => Some(name.as_str()),
+        Term::Literal(_) => None,
+    })
+}
+
+fn find_cycle<'a>(
+    derived_attributes: &'a [DerivedAttribute],
+    attribute: &'a DerivedAttribute,
+    visiting: &mut Vec<&'a str>,
+) -> Option<String> {
+    if visiting.contains(&attribute.name.as_str()) {
+        visiting.push(&attribute.name);
+        return Some(visiting.join(" -> "));
+    }
+    visiting.push(&attribute.name);
+    for name in references(attribute) {
+        if let Some(cycle) = find_attribute(derived_attributes, name)
+            .and_then(|dependency| find_cycle(derived_attributes, dependency, visiting))
+        {
+            return Some(cycle);
+        }
+    }
+    visiting.pop();
+    None
+}
+
+/// Checks that the derived attributes don't reference each other recursively.
+pub fn check_derived_attributes(derived_attributes: &[DerivedAttribute]) -> Result<(), String> {
+    for attribute in derived_attributes {
+        if let Some(cycle) = find_cycle(derived_attributes, attribute, &mut Vec::new()) {
+            return Err(format!("Recursive derived attribute: {}", cycle));
+        }
+    }
+    Ok(())
+}
+
+fn get_stored_attribute(user: &User, name: &str, schema: &AttributeList) -> Option<String> {
+    let attribute_schema = schema.get_attribute_schema(name)?;
+    let value = &user
+        .attributes
+        .iter()
+        .find(|a| a.name == attribute_schema.name)?
+        .value;
+    match (attribute_schema.attribute_type, attribute_schema.is_list) {
+        (AttributeType::String, false) => Some(value.unwrap::<String>()),
+        (AttributeType::Integer, false) => Some(value.unwrap::<i64>().to_string()),
+        _ => None,
+    }
+}
+
+fn get_base_value(user: &User, name: &str, schema: &AttributeList) -> Option<String> {
+    match map_user_field(&name.to_ascii_lowercase()) {
+        UserFieldType::PrimaryField(UserColumn::UserId) => Some(user.user_id.to_string()),
+        UserFieldType::PrimaryField(UserColumn::Email) => Some(user.email.clone()),
+        UserFieldType::PrimaryField(UserColumn::DisplayName) => user.display_name.clone(),
+        UserFieldType::PrimaryField(UserColumn::Uuid) => Some(user.uuid.to_string()),
+        UserFieldType::PrimaryField(UserColumn::CreationDate) => Some(
+            chrono::Utc
+                .from_utc_datetime(&user.creation_date)
+                .to_rfc3339(),
+        ),
+        UserFieldType::PrimaryField(_) => None,
+        UserFieldType::Attribute(name) => get_stored_attribute(user, name, schema),
+        UserFieldType::NoMatch => get_stored_attribute(user, name, schema),
+    }
+}
+
+fn evaluate<'a>(
+    derived_attributes: &'a [DerivedAttribute],
+    attribute: &'a DerivedAttribute,
+    user: &User,
+    schema: &AttributeList,
+    visiting: &mut Vec<&'a str>,
+) -> Option<String> {
+    // The configuration is checked for cycles, this is just a safeguard.
+    if visiting.contains(&attribute.name.as_str()) {
+        warn!("Recursive derived attribute: {}", attribute.name);
+        return None;
+    }
+    visiting.push(&attribute.name);
+    let value = evaluate_expression(
+        derived_attributes,
+        &attribute.expression,
+        user,
+        schema,
+        visiting,
+    )?;
+    visiting.pop();
+    Some(value)
+}
+
+fn evaluate_expression<'a>(
+    derived_attributes: &'a [DerivedAttribute],
+    expression: &Expression,
+    user: &User,
+    schema: &AttributeList,
+    visiting: &mut Vec<&'a str>,
+) -> Option<String> {
+    let mut value = String::new();
+    for term in &expression.terms {
+        match term {
+            Term::Literal(literal) => value.push_str(literal),
+            Term::Attribute(name) => match find_attribute(derived_attributes, name) {
+                Some(dependency) => value.push_str(&evaluate(
+                    derived_attributes,
+                    dependency,
+                    user,
+                    schema,
+                    visiting,
+                )?),
+                // Missing attributes are treated as empty.
+                None => value.push_str(&get_base_value(user, name, schema).unwrap_or_default()),
+            },
+        }
+    }
+    Some(value)
+}
+
+/// Gives a display name to the user if they have none: the first expression of the chain that is
+/// not blank, e.g. `first_name + " " + last_name`, then `uid`.
+pub fn apply_display_name_fallback(
+    user: &mut User,
+    fallback: &[Expression],
+    derived_attributes: &[DerivedAttribute],
+    schema: &AttributeList,
+) {
+    if !user.display_name.as_deref().unwrap_or("").trim().is_empty() {
+        return;
+    }
+    if let Some(display_name) = fallback.iter().find_map(|expression| {
+        evaluate_expression(
+            derived_attributes,
+            expression,
+            user,
+            schema,
+            &mut Vec::new(),
+        )
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+    }) {
+        user.display_name = Some(display_name);
+    }
+}
+
+/// Computes the derived attributes of the user, replacing stored attributes with the same name.
+pub fn add_derived_attributes(
+    user: &mut User,
+    derived_attributes: &[DerivedAttribute],
+    schema: &AttributeList,
+) {
+    let values = derived_attributes
+        .iter()
+        .filter_map(|attribute| {
+            evaluate(derived_attributes, attribute, user, schema, &mut Vec::new()).map(|value| {
+                AttributeValue {
+                    name: attribute.name.clone(),
+                    value: Serialized::from(&value),
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    user.attributes
+        .retain(|a| find_attribute(derived_attributes, &a.name).is_none());
+    user.attributes.extend(values);
+}
+
+/// Whether all the attributes that `attribute` is computed from, directly or not, pass `check`.
+/// The user fields are always visible, and the recursive attributes never are.
+fn all_references<'a>(
+    derived_attributes: &'a [DerivedAttribute],
+    attribute: &'a DerivedAttribute,
+    stored_attributes: &[AttributeSchema],
+    check: fn(&AttributeSchema) -> bool,
+    visiting: &mut Vec<&'a str>,
+) -> bool {
+    if visiting.contains(&attribute.name.as_str()) {
+        return false;
+    }
+    visiting.push(&attribute.name);
+    let result = references(attribute).all(|name| {
+        if let Some(dependency) = find_attribute(derived_attributes, name) {
+            return all_references(
+                derived_attributes,
+                dependency,
+                stored_attributes,
+                check,
+                visiting,
+            );
+        }
+        let lowercase_name = name.to_ascii_lowercase();
+        let stored_name = match map_user_field(&lowercase_name) {
+            UserFieldType::PrimaryField(_) => return true,
+            UserFieldType::Attribute(name) => name,
+            UserFieldType::NoMatch => name,
+        };
+        stored_attributes
+            .iter()
+            .find(|a| a.name.eq_ignore_ascii_case(stored_name))
+            .map_or(true, check)
+    });
+    visiting.pop();
+    result
+}
+
+/// The schema of the derived attributes: read-only strings. They are only visible where all the
+/// attributes they are computed from are, to not reveal the hidden ones.
+pub fn get_derived_attributes_schema<'a>(
+    derived_attributes: &'a [DerivedAttribute],
+    stored_attributes: &'a [AttributeSchema],
+) -> impl Iterator<Item = AttributeSchema> + 'a {
+    derived_attributes.iter().map(move |attribute| {
+        let all_references = |check| {
+            all_references(
+                derived_attributes,
+                attribute,
+                stored_attributes,
+                check,
+                &mut Vec::new(),
+            )
+        };
+        AttributeSchema {
+            name: attribute.name.clone(),
+            attribute_type: AttributeType::String,
+            is_list: false,
+            is_visible: all_references(|a| a.is_visible),
+            is_editable: false,
+            is_ldap_visible: all_references(|a| a.is_ldap_visible),
+            is_hardcoded: true,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::UserId;
+
+    fn make_attribute(name: &str, expression: &str) -> DerivedAttribute {
+        DerivedAttribute {
+            name: name.to_owned(),
+            expression: expression.to_owned().try_into().unwrap(),
+        }
+    }
+
+    fn make_schema() -> AttributeList {
+        AttributeList {
+            attributes: vec![AttributeSchema {
+                name: "first_name".to_owned(),
+                attribute_type: AttributeType::String,
+                is_list: false,
+                is_visible: true,
+                is_editable: true,
+                is_ldap_visible: true,
+                is_hardcoded: true,
+            }],
+        }
+    }
+
+    fn get_value(user: &User, name: &str) -> Option<String> {
+        user.attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value.unwrap::<String>())
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        assert_eq!(
+            parse_terms(r#" "/home/" + uid "#),
+            Ok(vec![
+                Term::Literal("/home/".to_owned()),
+                Term::Attribute("uid".to_owned())
+            ])
+        );
+        assert_eq!(
+            parse_terms(r#""a \"quoted\" \\ string""#),
+            Ok(vec![Term::Literal(r#"a "quoted" \ string"#.to_owned())])
+        );
+        parse_terms("").unwrap_err();
+        parse_terms(r#""unterminated"#).unwrap_err();
+        parse_terms("uid +").unwrap_err();
+        parse_terms("uid mail").unwrap_err();
+        parse_terms("uid - mail").unwrap_err();
+    }
+
+    #[test]
+    fn test_derived_attributes() {
+        let derived_attributes = vec![
+            make_attribute("homeDirectory", r#""/home/" + uid"#),
+            make_attribute("gecos", r#"firstName + " <" + mail + ">" + missing"#),
+            make_attribute("profile", r#"homeDirectory + "/.profile""#),
+        ];
+        check_derived_attributes(&derived_attributes).unwrap();
+        let mut user = User {
+            user_id: UserId::new("bob"),
+            email: "bob@bob.bob".to_owned(),
+            attributes: vec![AttributeValue {
+                name: "first_name".to_owned(),
+                value: Serialized::from("Bob"),
+            }],
+            ..Default::default()
+        };
+        add_derived_attributes(&mut user, &derived_attributes, &make_schema());
+        assert_eq!(
+            get_value(&user, "homeDirectory"),
+            Some("/home/bob".to_owned())
+        );
+        assert_eq!(
+            get_value(&user, "gecos"),
+            Some("Bob <bob@bob.bob>".to_owned())
+        );
+        assert_eq!(
+            get_value(&user, "profile"),
+            Some("/home/bob/.profile".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_derived_attributes_visibility() {
+        let derived_attributes = vec![
+            make_attribute("homeDirectory", r#""/home/" + uid"#),
+            make_attribute("gecos", r#"givenName + " <" + mail + ">""#),
+            make_attribute("profile", r#"gecos + "/.profile""#),
+        ];
+        let mut stored_attributes = make_schema().attributes;
+        stored_attributes[0].is_ldap_visible = false;
+        let schema = get_derived_attributes_schema(&derived_attributes, &stored_attributes)
+            .map(|a| (a.name, a.is_visible, a.is_ldap_visible))
+            .collect::<Vec<_>>();
+        // The attributes computed from the hidden first name, even indirectly, are hidden too.
+        assert_eq!(
+            schema,
+            vec![
+                ("homeDirectory".to_owned(), true, true),
+                ("gecos".to_owned(), true, false),
+                ("profile".to_owned(), true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display_name_fallback() {
+        let fallback = vec![
+            r#"first_name + " " + last_name"#.to_owned().try_into().unwrap(),
+            "uid".to_owned().try_into().unwrap(),
+        ];
+        let mut bob = User {
+            user_id: UserId::new("bob"),
+            display_name: Some("Bobby".to_owned()),
+            attributes: vec![AttributeValue {
+                name: "first_name".to_owned(),
+                value: Serialized::from("Bob"),
+            }],
+            ..Default::default()
+        };
+        apply_display_name_fallback(&mut bob, &fallback, &[], &make_schema());
+        assert_eq!(bob.display_name.as_deref(), Some("Bobby"));
+        bob.display_name = Some("".to_owned());
+        apply_display_name_fallback(&mut bob, &fallback, &[], &make_schema());
+        assert_eq!(bob.display_name.as_deref(), Some("Bob"));
+        let mut john = User {
+            user_id: UserId::new("john"),
+            ..Default::default()
+        };
+        apply_display_name_fallback(&mut john, &fallback, &[], &make_schema());
+        assert_eq!(john.display_name.as_deref(), Some("john"));
+        let mut patrick = User::default();
+        apply_display_name_fallback(&mut patrick, &[], &[], &make_schema());
+        assert_eq!(patrick.display_name, None);
+    }
+
+    #[test]
+    fn test_recursive_derived_attributes() {
+        let derived_attributes = vec![
+            make_attribute("a", r#"b + "!""#),
+            make_attribute("b", r#"uid + a"#),
+            make_attribute("c", r#""/home/" + uid"#),
+        ];
+        assert_eq!(
+            check_derived_attributes(&derived_attributes),
+            Err("Recursive derived attribute: a -> b -> a".to_owned())
+        );
+        // Evaluating doesn't loop forever, the recursive attributes are skipped.
+        let mut user = User {
+            user_id: UserId::new("bob"),
+            ..Default::default()
+        };
+        add_derived_attributes(&mut user, &derived_attributes, &make_schema());
+        assert_eq!(get_value(&user, "a"), None);
+        assert_eq!(get_value(&user, "c"), Some("/home/bob".to_owned()));
+    }
+}
@@ -19,6 +19,12 @@ pub enum DomainError {
     Base64DecodeError(#[from] base64::DecodeError),
     #[error("Entity not found: `{0}`")]
     EntityNotFound(String),
+    #[error("Limit exceeded: `{0}`")]
+    LimitExceeded(String),
+    #[error("Conflict: `{0}`")]
+    Conflict(String),
+    #[error("Invalid input: `{0}`")]
+    InvalidInput(String),
     #[error("Internal error: `{0}`")]
     InternalError(String),
 }
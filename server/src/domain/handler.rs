@@ -1,11 +1,12 @@
 use crate::domain::{
     error::Result,
     types::{
-        AttributeType, Group, GroupDetails, GroupId, JpegPhoto, User, UserAndGroups, UserColumn,
-        UserId, Uuid,
+        AttributeType, AttributeValue, Group, GroupDetails, GroupId, JpegPhoto, User,
+        UserAndGroups, UserColumn, UserId, Uuid,
     },
 };
 use async_trait::async_trait;
+use sea_orm::sea_query::LikeExpr;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
@@ -15,7 +16,9 @@ pub struct BindRequest {
     pub password: String,
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub const SQL_LIKE_ESCAPE: char = '!';
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SubStringFilter {
     pub initial: Option<String>,
     pub any: Vec<String>,
@@ -23,7 +26,17 @@ pub struct SubStringFilter {
 }
 
 impl SubStringFilter {
+    /// Builds a lowercase pattern for SQL's LIKE, where the wildcards in the values are escaped
+    /// with `SQL_LIKE_ESCAPE`.
     pub fn to_sql_filter(&self) -> String {
+        fn push_escaped(filter: &mut String, value: &str) {
+            for c in value.to_ascii_lowercase().chars() {
+                if matches!(c, '%' | '_' | SQL_LIKE_ESCAPE) {
+                    filter.push(SQL_LIKE_ESCAPE);
+                }
+                filter.push(c);
+            }
+        }
         let mut filter = String::with_capacity(
             self.initial.as_ref().map(String::len).unwrap_or_default()
                 + 1
@@ -32,18 +45,45 @@ impl SubStringFilter {
                 + self.final_.as_ref().map(String::len).unwrap_or_default(),
         );
         if let Some(f) = &self.initial {
-            filter.push_str(&f.to_ascii_lowercase());
+            push_escaped(&mut filter, f);
         }
         filter.push('%');
         for part in self.any.iter() {
-            filter.push_str(&part.to_ascii_lowercase());
+            push_escaped(&mut filter, part);
             filter.push('%');
         }
         if let Some(f) = &self.final_ {
-            filter.push_str(&f.to_ascii_lowercase());
+            push_escaped(&mut filter, f);
         }
         filter
     }
+
+    pub fn to_sql_like_expr(&self) -> LikeExpr {
+        LikeExpr::new(self.to_sql_filter()).escape(SQL_LIKE_ESCAPE)
+    }
+
+    /// Case-insensitive match, with the same semantics as the SQL filter.
+    pub fn matches(&self, value: &str) -> bool {
+        let value = value.to_ascii_lowercase();
+        let mut rest = value.as_str();
+        if let Some(initial) = &self.initial {
+            match rest.strip_prefix(&initial.to_ascii_lowercase()) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+        }
+        for part in &self.any {
+            let part = part.to_ascii_lowercase();
+            match rest.find(&part) {
+                Some(i) => rest = &rest[i + part.len()..],
+                None => return false,
+            }
+        }
+        match &self.final_ {
+            Some(f) => rest.ends_with(&f.to_ascii_lowercase()),
+            None => true,
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
@@ -54,8 +94,13 @@ pub enum UserRequestFilter {
     UserId(UserId),
     UserIdSubString(SubStringFilter),
     Equality(UserColumn, String),
+    // Case-insensitive version of Equality.
+    EqualityIgnoreCase(UserColumn, String),
     AttributeEquality(String, String),
     SubString(UserColumn, SubStringFilter),
+    AttributeSubString(String, SubStringFilter),
+    // Check that the user has a value for the attribute.
+    AttributePresent(String),
     // Check if a user belongs to a group identified by name.
     MemberOf(String),
     // Same, by id.
@@ -83,6 +128,10 @@ pub enum GroupRequestFilter {
     GroupId(GroupId),
     // Check if the group contains a user identified by uid.
     Member(UserId),
+    // Check if the user is one of the managers of the group.
+    ManagedBy(UserId),
+    // Case-insensitive match on the group email.
+    Email(String),
 }
 
 impl From<bool> for GroupRequestFilter {
@@ -104,6 +153,8 @@ pub struct CreateUserRequest {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub avatar: Option<JpegPhoto>,
+    /// The preferred locale, e.g. `fr` or `pt-BR`.
+    pub locale: Option<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
@@ -115,12 +166,50 @@ pub struct UpdateUserRequest {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub avatar: Option<JpegPhoto>,
+    /// The preferred locale, e.g. `fr` or `pt-BR`.
+    pub locale: Option<String>,
+    /// Custom attributes to set, by schema name.
+    pub insert_attributes: Vec<AttributeValue>,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub struct UpdateGroupRequest {
     pub group_id: GroupId,
     pub display_name: Option<String>,
+    /// An empty string clears the field.
+    pub email: Option<String>,
+    /// An empty string clears the field.
+    pub description: Option<String>,
+    /// Custom attributes to remove, applied before `insert_attributes`.
+    pub delete_attributes: Vec<String>,
+    /// Custom attributes to set, replacing the previous values.
+    pub insert_attributes: Vec<AttributeValue>,
+}
+
+/// A custom attribute to add to the schema.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct CreateAttributeRequest {
+    pub name: String,
+    pub attribute_type: AttributeType,
+    pub is_list: bool,
+    pub is_visible: bool,
+    pub is_editable: bool,
+    pub is_ldap_visible: bool,
+}
+
+/// The flags of an attribute to change, the other ones are kept.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UpdateAttributeRequest {
+    pub name: String,
+    pub is_visible: Option<bool>,
+    pub is_editable: Option<bool>,
+    pub is_ldap_visible: Option<bool>,
+}
+
+impl UpdateAttributeRequest {
+    pub fn is_empty(&self) -> bool {
+        self.is_visible.is_none() && self.is_editable.is_none() && self.is_ldap_visible.is_none()
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
@@ -131,6 +220,8 @@ pub struct AttributeSchema {
     pub is_list: bool,
     pub is_visible: bool,
     pub is_editable: bool,
+    /// Whether the attribute is returned over LDAP.
+    pub is_ldap_visible: bool,
     pub is_hardcoded: bool,
 }
 
@@ -146,6 +237,12 @@ impl AttributeList {
             .find(|a| a.name == name)
             .map(|a| (a.attribute_type, a.is_list))
     }
+
+    pub fn get_attribute_schema(&self, name: &str) -> Option<&AttributeSchema> {
+        self.attributes
+            .iter()
+            .find(|a| a.name.eq_ignore_ascii_case(name))
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
@@ -154,6 +251,19 @@ pub struct Schema {
     pub group_attributes: AttributeList,
 }
 
+/// The size of the database before and after a compaction, in bytes.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct CompactionReport {
+    pub size_before: i64,
+    pub size_after: i64,
+}
+
+impl CompactionReport {
+    pub fn reclaimed(&self) -> i64 {
+        (self.size_before - self.size_after).max(0)
+    }
+}
+
 #[async_trait]
 pub trait LoginHandler: Send + Sync {
     async fn bind(&self, request: BindRequest) -> Result<()>;
@@ -162,6 +272,7 @@ pub trait LoginHandler: Send + Sync {
 #[async_trait]
 pub trait GroupListerBackendHandler: SchemaBackendHandler {
     async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>>;
+    async fn count_groups(&self) -> Result<u64>;
 }
 
 #[async_trait]
@@ -170,6 +281,28 @@ pub trait GroupBackendHandler: SchemaBackendHandler {
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
     async fn create_group(&self, group_name: &str) -> Result<GroupId>;
     async fn delete_group(&self, group_id: GroupId) -> Result<()>;
+    /// The users that can manage the members of the group, without being admins.
+    async fn get_group_managers(&self, group_id: GroupId) -> Result<Vec<UserId>>;
+    async fn add_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()>;
+    async fn remove_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()>;
+    /// The groups whose members can manage the members of the group, without being admins.
+    async fn get_group_manager_groups(&self, group_id: GroupId) -> Result<Vec<GroupId>>;
+    async fn add_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()>;
+    async fn remove_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()>;
+    /// Declares a custom group attribute, that can then be set on all the groups.
+    async fn add_group_attribute(&self, request: CreateAttributeRequest) -> Result<()>;
+    /// Changes the visibility and the editability of a group attribute.
+    async fn update_group_attribute(&self, request: UpdateAttributeRequest) -> Result<()>;
+    /// Removes a custom group attribute from the schema, along with its values.
+    async fn delete_group_attribute(&self, name: &str) -> Result<()>;
 }
 
 #[async_trait]
@@ -179,6 +312,7 @@ pub trait UserListerBackendHandler: SchemaBackendHandler {
         filters: Option<UserRequestFilter>,
         get_groups: bool,
     ) -> Result<Vec<UserAndGroups>>;
+    async fn count_users(&self) -> Result<u64>;
 }
 
 #[async_trait]
@@ -187,11 +321,45 @@ pub trait UserBackendHandler: SchemaBackendHandler {
     async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
     async fn delete_user(&self, user_id: &UserId) -> Result<()>;
+    /// Change the id of a user, keeping their attributes, memberships and password.
+    async fn rename_user(&self, user_id: &UserId, new_user_id: UserId) -> Result<()>;
     async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
     async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+    /// Adds all the users to the group, in a single transaction. Returns the users that were
+    /// added, i.e. without the ones that were already members.
+    async fn add_users_to_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>>;
+    /// Removes all the users from the group, in a single transaction. Returns the users that were
+    /// removed, i.e. without the ones that were not members.
+    async fn remove_users_from_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>>;
+    /// Changes the visibility and the editability of a user attribute. The derived attributes
+    /// can't be changed.
+    async fn update_user_attribute(&self, request: UpdateAttributeRequest) -> Result<()>;
+    /// Set the primary group of the user, which must be one of their groups, or unset it.
+    async fn set_primary_group(&self, user_id: &UserId, group_id: Option<GroupId>) -> Result<()>;
+    /// After the expiry date, the user can't log in or bind anymore. `None` clears it.
+    async fn set_user_expiry_date(
+        &self,
+        user_id: &UserId,
+        expiry_date: Option<chrono::NaiveDateTime>,
+    ) -> Result<()>;
     async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>>;
 }
 
+#[async_trait]
+pub trait MaintenanceHandler: Send + Sync {
+    /// Reclaims the unused space of the database: `VACUUM` for SQLite and PostgreSQL, `OPTIMIZE
+    /// TABLE` for MySQL. Only one compaction runs at a time, across all the instances.
+    async fn compact_database(&self) -> Result<CompactionReport>;
+}
+
 #[async_trait]
 pub trait SchemaBackendHandler {
     async fn get_schema(&self) -> Result<Schema>;
@@ -199,7 +367,8 @@ pub trait SchemaBackendHandler {
 
 #[async_trait]
 pub trait BackendHandler:
-    Send
+    Sized
+    + Send
     + Sync
     + GroupBackendHandler
     + UserBackendHandler
@@ -207,6 +376,10 @@ pub trait BackendHandler:
     + GroupListerBackendHandler
     + SchemaBackendHandler
 {
+    /// Returns a handler whose operations all run in a new transaction. It's rolled back when the
+    /// handler is dropped, unless it's committed.
+    async fn begin_transaction(&self) -> Result<Self>;
+    async fn commit_transaction(self) -> Result<()>;
 }
 
 #[cfg(test)]
@@ -232,6 +405,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_substring_sql_filter_escaping() {
+        let filter = SubStringFilter {
+            initial: Some("50%".to_owned()),
+            any: vec!["a_B".to_owned()],
+            final_: Some("!".to_owned()),
+        };
+        assert_eq!(filter.to_sql_filter(), "50!%%a!_b%!!");
+        assert!(filter.matches("50% off A_b!"));
+        assert!(!filter.matches("500 off A_b!"));
+    }
+
     #[test]
     fn test_jpeg_try_from_bytes() {
         let base64_raw = "/9j/4AAQSkZJRgABAQEASABIAAD/2wBDAP//////////////////////////////////////////////////////////////////////////////////////2wBDAf//////////////////////////////////////////////////////////////////////////////////////wAARCADqATkDASIAAhEBAxEB/8QAFwABAQEBAAAAAAAAAAAAAAAAAAECA//EACQQAQEBAAIBBAMBAQEBAAAAAAABESExQQISUXFhgZGxocHw/8QAFQEBAQAAAAAAAAAAAAAAAAAAAAH/xAAWEQEBAQAAAAAAAAAAAAAAAAAAEQH/2gAMAwEAAhEDEQA/AMriLyCKgg1gQwCgs4FTMOdutepjQak+FzMSVqgxZdRdPPIIvH5WzzGdBriphtTeAXg2ZjKA1pqKDUGZca3foBek8gFv8Ie3fKdA1qb8s7hoL6eLVt51FsAnql3Ut1M7AWbflLMDkEMX/F6/YjK/pADFQAUNA6alYagKk72m/j9p4Bq2fDDSYKLNXPNLoHE/NT6RYC31cJxZ3yWVM+aBYi/S2ZgiAsnYJx5D21vPmqrm3PTfpQQwyAC8JZvSKDni41ZrMuUVVl+Uz9w9v/1QWrZsZ5nFPHYH+JZyureQSF5M+fJ0CAfwRAVRBQA1DAWVUayoJUWoDpsxntPsueBV4+VxhdyAtv8AjOLGpIDMLbeGvbF4iozJfr/WukAVABAXAQXEAAASzVAZdO2WNordm+emFl7XcQSNZiFtv0C9w90nhJf4mA1u+GcJFwIyAqL/AOovwgGNfSRqdIrNa29M0gKCAojU9PAMjWXpckEJFNFEAAXEUBABYz6rZ0ureQc9vyt9XxDF2QAXtABcQAs0AZywkvluJbyipifas52DcyxjlZweAO0xri/hc+wZOEKIu6nSyeToVZyWXwvCg53gW81QQ7aTNAn5dGZJPs1UXURQAUEMCXQLZE93PRZ5hPTgNMrbIzKCm52LZwCs+2M8w2g3sjPuZAXb4IsMAUACzVUGM4/K+md6vEXUUyM5PDR0IxYe6ramih0VNBrS4xoqN8Q1BFQk3yqyAsioioAAKgDSJL4/jQIn5igLrPqtOuf6oOaxbMoAltUAhhIoJiiggrPu+AaOIxtAX3JbaAIaLwi4t9X4T3fg2AFtqcrUUarP20zUDAmqoE0WRBZPNVUVEAAAAVAC8kvih2DSKxOdBqs7Z0l0gI0mKAC4AuHE7ZtBriM+744QAAAAABAFsveIttBICyaikvy1+r/Cen5rWQHIBQa4rIDRqSl5qDWqziqgAAAATA7BpGdqXb2C2+J/UgAtRQBSQtkBWb6vhLbQAAAAAEBRAAAAAUbm+GZNdPxAP+ql2Tjwx7/wIgZ8iKvBk+CJoCXii9gaqZ/qqihAAAEVABGkBFUwBftNkZ3QW34QAAABFAQAVAAAAAARVkl8gs/43sk1jL45LvHArepk+E9XTG35oLqsmIKmLAEygKg0y1AFQBUXwgAAAoBC34S3UAAABAVAAAAAABAUQAVABdRQa1PcYyit2z58M8C4ouM2NXpOEGeWtNZUatiAIoAKIoCoAoG4C9MW6dgIoAIAAAAAAACKWAgL0CAAAALiANCKioNLgM1CrLihmTafkt1EF3SZ5ZVUW4mnIKvAi5fhEURVDWVQBRAAAAAAAAQFRVyAyulgAqCKlF8IqLsEgC9mGoC+IusqCrv5ZEUVOk1RuJfwSLOOkGFi4XPCoYYrNiKauosBGi9ICstM1UAAAAAAFQ0VcTBAXUGgIqGoKhKAzRRUQUAwxoSrGRpkQA/qiosOL9oJptMRRVZa0VUqSiChE6BqMgCwqKqIogAIAqKCKgKoogg0lBFuIKgAAAKNRlf2gqsftsEtZWoAAqAACKoMqAAeSoqp39kL2AqLOlE8rEBFQARYALhigrNC9gGmooLp4TweEQFFBFAECgIoAu0ifIAqAAA//9k=";
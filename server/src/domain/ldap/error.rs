@@ -1,4 +1,6 @@
+use crate::domain::error::DomainError;
 use ldap3_proto::LdapResultCode;
+use tracing::error;
 
 #[derive(Debug, PartialEq)]
 pub struct LdapError {
@@ -6,6 +8,30 @@ pub struct LdapError {
     pub message: String,
 }
 
+impl LdapError {
+    /// An error whose cause is only logged, not returned to the client: it can reveal details of
+    /// the database, of the cryptography, etc.
+    pub fn internal(code: LdapResultCode, message: String, cause: impl std::fmt::Display) -> Self {
+        error!("{}: {:#}", message, cause);
+        Self { code, message }
+    }
+
+    /// Same as `internal`, except that the errors caused by the request, e.g. a conflict, are
+    /// added to the message.
+    pub fn from_domain_error(code: LdapResultCode, message: String, error: &DomainError) -> Self {
+        match error {
+            DomainError::EntityNotFound(_)
+            | DomainError::LimitExceeded(_)
+            | DomainError::Conflict(_)
+            | DomainError::InvalidInput(_) => Self {
+                code,
+                message: format!("{}: {}", message, error),
+            },
+            _ => Self::internal(code, message, error),
+        }
+    }
+}
+
 impl std::fmt::Display for LdapError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.message)
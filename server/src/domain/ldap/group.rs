@@ -1,44 +1,102 @@
+use chrono::TimeZone;
 use ldap3_proto::{
     proto::LdapOp, LdapFilter, LdapPartialAttribute, LdapResultCode, LdapSearchResultEntry,
 };
 use tracing::{debug, instrument, warn};
 
-use crate::domain::{
-    handler::{GroupListerBackendHandler, GroupRequestFilter},
-    ldap::error::LdapError,
-    types::{Group, UserId, Uuid},
+use crate::{
+    domain::{
+        handler::{GroupListerBackendHandler, GroupRequestFilter, Schema},
+        ldap::error::LdapError,
+        types::{Group, UserId, Uuid},
+    },
+    infra::configuration::{EmptyGroupMembers, MembershipValueFormat},
 };
 
 use super::{
     error::LdapResult,
     utils::{
-        expand_attribute_wildcards, get_group_id_from_distinguished_name,
-        get_user_id_from_distinguished_name, map_group_field, LdapInfo,
+        expand_attribute_wildcards, get_custom_attribute, get_extensible_match_rule,
+        get_group_id_from_distinguished_name, get_user_id_from_distinguished_name,
+        make_membership_value, map_group_field, LdapInfo,
     },
 };
 
+fn get_group_members(
+    group: &Group,
+    base_dn_str: &str,
+    user_filter: &Option<UserId>,
+    membership_format: MembershipValueFormat,
+) -> Vec<Vec<u8>> {
+    group
+        .users
+        .iter()
+        .filter(|u| user_filter.as_ref().map(|f| *u == f).unwrap_or(true))
+        .map(|u| make_membership_value(membership_format, "uid", u.as_str(), "people", base_dn_str))
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn get_group_attribute(
     group: &Group,
     base_dn_str: &str,
     attribute: &str,
     user_filter: &Option<UserId>,
     ignored_group_attributes: &[String],
+    additional_object_classes: &[String],
+    empty_group_members: EmptyGroupMembers,
+    placeholder_member: &str,
+    schema: &Schema,
+    membership_format: MembershipValueFormat,
 ) -> Option<Vec<Vec<u8>>> {
     let attribute = attribute.to_ascii_lowercase();
     let attribute_values = match attribute.as_str() {
-        "objectclass" => vec![b"groupOfUniqueNames".to_vec()],
+        "objectclass" => std::iter::once(
+            if empty_group_members == EmptyGroupMembers::ExtensibleObject
+                && get_group_members(group, base_dn_str, user_filter, membership_format).is_empty()
+            {
+                b"extensibleObject".to_vec()
+            } else {
+                b"groupOfUniqueNames".to_vec()
+            },
+        )
+        .chain(
+            additional_object_classes
+                .iter()
+                .map(|c| c.clone().into_bytes()),
+        )
+        .collect(),
         // Always returned as part of the base response.
         "dn" | "distinguishedname" => return None,
         "cn" | "uid" | "id" => vec![group.display_name.clone().into_bytes()],
         "entryuuid" | "uuid" => vec![group.uuid.to_string().into_bytes()],
-        "member" | "uniquemember" => group
-            .users
-            .iter()
-            .filter(|u| user_filter.as_ref().map(|f| *u == f).unwrap_or(true))
-            .map(|u| format!("uid={},ou=people,{}", u, base_dn_str).into_bytes())
-            .collect(),
+        "hassubordinates" => vec![b"FALSE".to_vec()],
+        "mail" | "email" => vec![group.email.clone()?.into_bytes()],
+        "description" => vec![group.description.clone()?.into_bytes()],
+        "gidnumber" => vec![group.id.0.to_string().into_bytes()],
+        "creationdate" | "creation_date" | "createtimestamp" => {
+            vec![chrono::Utc
+                .from_utc_datetime(&group.creation_date)
+                .to_rfc3339()
+                .into_bytes()]
+        }
+        "modifytimestamp" | "modified_date" => vec![chrono::Utc
+            .from_utc_datetime(&group.modified_date)
+            .to_rfc3339()
+            .into_bytes()],
+        "member" | "uniquemember" => {
+            match get_group_members(group, base_dn_str, user_filter, membership_format) {
+                members
+                    if members.is_empty()
+                        && empty_group_members == EmptyGroupMembers::Placeholder =>
+                {
+                    vec![placeholder_member.as_bytes().to_vec()]
+                }
+                members => members,
+            }
+        }
         "1.1" => return None,
-        // We ignore the operational attribute wildcard
+        // The operational attribute wildcard is expanded beforehand.
         "+" => return None,
         "*" => {
             panic!(
@@ -46,18 +104,29 @@ pub fn get_group_attribute(
                 attribute
             )
         }
-        _ => {
-            if !ignored_group_attributes.contains(&attribute) {
-                warn!(
-                    r#"Ignoring unrecognized group attribute: {}\n\
+        // Custom attributes are returned if the schema allows it.
+        _ => match schema.group_attributes.get_attribute_schema(&attribute) {
+            Some(attribute_schema) => get_custom_attribute(
+                &group.attributes,
+                &attribute_schema.name,
+                &schema.group_attributes,
+            )?,
+            None => {
+                if !ignored_group_attributes.contains(&attribute) {
+                    warn!(
+                        r#"Ignoring unrecognized group attribute: {}\n\
                       To disable this warning, add it to "ignored_group_attributes" in the config."#,
-                    attribute
-                );
+                        attribute
+                    );
+                }
+                return None;
             }
-            return None;
-        }
+        },
     };
-    if attribute_values.len() == 1 && attribute_values[0].is_empty() {
+    // Attributes without values, e.g. the members of an empty group, are omitted.
+    if attribute_values.is_empty()
+        || (attribute_values.len() == 1 && attribute_values[0].is_empty())
+    {
         None
     } else {
         Some(attribute_values)
@@ -71,18 +140,38 @@ const ALL_GROUP_ATTRIBUTE_KEYS: &[&str] = &[
     "member",
     "uniquemember",
     "entryuuid",
+    "mail",
+    "description",
+];
+
+/// Returned for "+".
+const OPERATIONAL_GROUP_ATTRIBUTE_KEYS: &[&str] = &[
+    "createtimestamp",
+    "modifytimestamp",
+    "entryuuid",
+    "hassubordinates",
 ];
 
 fn expand_group_attribute_wildcards(attributes: &[String]) -> Vec<&str> {
-    expand_attribute_wildcards(attributes, ALL_GROUP_ATTRIBUTE_KEYS)
+    expand_attribute_wildcards(
+        attributes,
+        ALL_GROUP_ATTRIBUTE_KEYS,
+        OPERATIONAL_GROUP_ATTRIBUTE_KEYS,
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn make_ldap_search_group_result_entry(
     group: Group,
     base_dn_str: &str,
     attributes: &[String],
     user_filter: &Option<UserId>,
     ignored_group_attributes: &[String],
+    additional_object_classes: &[String],
+    empty_group_members: EmptyGroupMembers,
+    placeholder_member: &str,
+    schema: &Schema,
+    membership_format: MembershipValueFormat,
 ) -> LdapSearchResultEntry {
     let expanded_attributes = expand_group_attribute_wildcards(attributes);
 
@@ -97,6 +186,11 @@ fn make_ldap_search_group_result_entry(
                     a,
                     user_filter,
                     ignored_group_attributes,
+                    additional_object_classes,
+                    empty_group_members,
+                    placeholder_member,
+                    schema,
+                    membership_format,
                 )?;
                 Some(LdapPartialAttribute {
                     atype: a.to_string(),
@@ -125,10 +219,13 @@ fn convert_group_filter(
                     )?;
                     Ok(GroupRequestFilter::Member(user_name))
                 }
-                "objectclass" => Ok(GroupRequestFilter::from(matches!(
-                    value.as_str(),
-                    "groupofuniquenames" | "groupofnames"
-                ))),
+                "objectclass" => Ok(GroupRequestFilter::from(
+                    matches!(value.as_str(), "groupofuniquenames" | "groupofnames")
+                        || ldap_info
+                            .additional_group_object_classes
+                            .iter()
+                            .any(|c| c.eq_ignore_ascii_case(value)),
+                )),
                 "dn" => Ok(get_group_id_from_distinguished_name(
                     value.to_ascii_lowercase().as_str(),
                     &ldap_info.base_dn,
@@ -141,6 +238,7 @@ fn convert_group_filter(
                 })),
                 _ => match map_group_field(field) {
                     Some("display_name") => Ok(GroupRequestFilter::DisplayName(value.to_string())),
+                    Some("email") => Ok(GroupRequestFilter::Email(value.to_string())),
                     Some("uuid") => Ok(GroupRequestFilter::Uuid(
                         Uuid::try_from(value.as_str()).map_err(|e| LdapError {
                             code: LdapResultCode::InappropriateMatching,
@@ -191,6 +289,11 @@ fn convert_group_filter(
                 }),
             }
         }
+        LdapFilter::Extensible(assertion) => {
+            // Equality filters already lowercase the value, so both rules behave the same.
+            let (field, _) = get_extensible_match_rule(assertion)?;
+            rec(&LdapFilter::Equality(field, assertion.match_value.clone()))
+        }
         _ => Err(LdapError {
             code: LdapResultCode::UnwillingToPerform,
             message: format!("Unsupported group filter: {:?}", filter),
@@ -208,28 +311,35 @@ pub async fn get_groups_list<Backend: GroupListerBackendHandler>(
     debug!(?ldap_filter);
     let filters = convert_group_filter(ldap_info, ldap_filter)?;
     debug!(?filters);
-    backend
-        .list_groups(Some(filters))
-        .await
-        .map_err(|e| LdapError {
-            code: LdapResultCode::Other,
-            message: format!(r#"Error while listing groups "{}": {:#}"#, base, e),
-        })
+    backend.list_groups(Some(filters)).await.map_err(|e| {
+        LdapError::from_domain_error(
+            LdapResultCode::Other,
+            format!(r#"Error while listing groups "{}""#, base),
+            &e,
+        )
+    })
 }
 
-pub fn convert_groups_to_ldap_op<'a>(
+/// The entries are only converted as the iterator is consumed.
+pub fn convert_groups_to_ldap_op(
     groups: Vec<Group>,
-    attributes: &'a [String],
-    ldap_info: &'a LdapInfo,
-    user_filter: &'a Option<UserId>,
-) -> impl Iterator<Item = LdapOp> + 'a {
+    attributes: Vec<String>,
+    ldap_info: &LdapInfo,
+    user_filter: Option<UserId>,
+    schema: Schema,
+) -> impl Iterator<Item = LdapOp> + '_ {
     groups.into_iter().map(move |g| {
         LdapOp::SearchResultEntry(make_ldap_search_group_result_entry(
             g,
             &ldap_info.base_dn_str,
-            attributes,
-            user_filter,
+            &attributes,
+            &user_filter,
             &ldap_info.ignored_group_attributes,
+            &ldap_info.additional_group_object_classes,
+            ldap_info.empty_group_members,
+            &ldap_info.empty_group_placeholder_member,
+            &schema,
+            ldap_info.membership_value_format,
         ))
     })
 }
@@ -5,61 +5,96 @@ use ldap3_proto::{
 use tracing::{debug, instrument, warn};
 
 use crate::domain::{
+    error::DomainError,
     handler::{Schema, UserListerBackendHandler, UserRequestFilter},
     ldap::{
         error::{LdapError, LdapResult},
         utils::{
-            expand_attribute_wildcards, get_custom_attribute, get_group_id_from_distinguished_name,
-            get_user_id_from_distinguished_name, map_user_field, LdapInfo, UserFieldType,
+            expand_attribute_wildcards, get_custom_attribute, get_extensible_match_rule,
+            get_group_id_from_distinguished_name, get_user_id_from_distinguished_name,
+            make_membership_value, map_user_field, LdapInfo, MatchingRule, UserFieldType,
         },
     },
     types::{GroupDetails, User, UserAndGroups, UserColumn, UserId},
 };
+use crate::infra::configuration::MembershipValueFormat;
 
+/// The object classes of all the users, before the configured ones.
+const USER_OBJECT_CLASSES: &[&str] = &["inetOrgPerson", "posixAccount", "mailAccount", "person"];
+
+#[allow(clippy::too_many_arguments)]
 pub fn get_user_attribute(
     user: &User,
     attribute: &str,
     base_dn_str: &str,
     groups: Option<&[GroupDetails]>,
     ignored_user_attributes: &[String],
+    additional_object_classes: &[String],
     schema: &Schema,
+    password_placeholder: Option<&str>,
+    membership_format: MembershipValueFormat,
 ) -> Option<Vec<Vec<u8>>> {
     let attribute = attribute.to_ascii_lowercase();
     let attribute_values = match attribute.as_str() {
-        "objectclass" => vec![
-            b"inetOrgPerson".to_vec(),
-            b"posixAccount".to_vec(),
-            b"mailAccount".to_vec(),
-            b"person".to_vec(),
-        ],
+        "objectclass" => USER_OBJECT_CLASSES
+            .iter()
+            .map(|c| c.as_bytes().to_vec())
+            .chain(
+                additional_object_classes
+                    .iter()
+                    .map(|c| c.clone().into_bytes()),
+            )
+            .collect(),
         // dn is always returned as part of the base response.
         "dn" | "distinguishedname" => return None,
         "uid" | "user_id" | "id" => vec![user.user_id.to_string().into_bytes()],
         "entryuuid" | "uuid" => vec![user.uuid.to_string().into_bytes()],
+        "hassubordinates" => vec![b"FALSE".to_vec()],
         "mail" | "email" => vec![user.email.clone().into_bytes()],
         "givenname" | "first_name" | "firstname" => {
-            get_custom_attribute(&user.attributes, "first_name", schema)?
+            get_custom_attribute(&user.attributes, "first_name", &schema.user_attributes)?
         }
         "sn" | "last_name" | "lastname" => {
-            get_custom_attribute(&user.attributes, "last_name", schema)?
+            get_custom_attribute(&user.attributes, "last_name", &schema.user_attributes)?
+        }
+        "jpegphoto" | "avatar" => {
+            get_custom_attribute(&user.attributes, "avatar", &schema.user_attributes)?
+        }
+        "preferredlanguage" | "locale" => {
+            get_custom_attribute(&user.attributes, "locale", &schema.user_attributes)?
         }
-        "jpegphoto" | "avatar" => get_custom_attribute(&user.attributes, "avatar", schema)?,
         "memberof" => groups
             .into_iter()
             .flatten()
             .map(|id_and_name| {
-                format!("cn={},ou=groups,{}", &id_and_name.display_name, base_dn_str).into_bytes()
+                make_membership_value(
+                    membership_format,
+                    "cn",
+                    &id_and_name.display_name,
+                    "groups",
+                    base_dn_str,
+                )
             })
             .collect(),
         "cn" | "displayname" => vec![user.display_name.clone()?.into_bytes()],
+        "gidnumber" => vec![user.primary_group?.0.to_string().into_bytes()],
+        "userpassword" => vec![password_placeholder
+            .filter(|_| user.has_password)?
+            .as_bytes()
+            .to_vec()],
         "creationdate" | "creation_date" | "createtimestamp" | "modifytimestamp" => {
             vec![chrono::Utc
                 .from_utc_datetime(&user.creation_date)
                 .to_rfc3339()
                 .into_bytes()]
         }
+        "pwdchangedtime" => vec![chrono::Utc
+            .from_utc_datetime(&user.password_modified_date?)
+            .format("%Y%m%d%H%M%SZ")
+            .to_string()
+            .into_bytes()],
         "1.1" => return None,
-        // We ignore the operational attribute wildcard.
+        // The operational attribute wildcard is expanded beforehand.
         "+" => return None,
         "*" => {
             panic!(
@@ -67,24 +102,75 @@ pub fn get_user_attribute(
                 attribute
             )
         }
-        _ => {
-            if !ignored_user_attributes.contains(&attribute) {
-                warn!(
-                    r#"Ignoring unrecognized group attribute: {}\n\
+        // Custom attributes are returned if the schema allows it.
+        _ => match schema.user_attributes.get_attribute_schema(&attribute) {
+            Some(attribute_schema) => get_custom_attribute(
+                &user.attributes,
+                &attribute_schema.name,
+                &schema.user_attributes,
+            )?,
+            None => {
+                if !ignored_user_attributes.contains(&attribute) {
+                    warn!(
+                        r#"Ignoring unrecognized group attribute: {}\n\
                       To disable this warning, add it to "ignored_user_attributes" in the config."#,
-                    attribute
-                );
+                        attribute
+                    );
+                }
+                return None;
             }
-            return None;
-        }
+        },
     };
-    if attribute_values.len() == 1 && attribute_values[0].is_empty() {
+    // Attributes without values, e.g. the groups of a user without any, are omitted.
+    if attribute_values.is_empty()
+        || (attribute_values.len() == 1 && attribute_values[0].is_empty())
+    {
         None
     } else {
         Some(attribute_values)
     }
 }
 
+/// The names of the attributes handled by `get_user_attribute`, lowercase.
+const BUILT_IN_USER_ATTRIBUTES: &[&str] = &[
+    "objectclass",
+    "dn",
+    "distinguishedname",
+    "uid",
+    "user_id",
+    "id",
+    "entryuuid",
+    "uuid",
+    "hassubordinates",
+    "mail",
+    "email",
+    "givenname",
+    "first_name",
+    "firstname",
+    "sn",
+    "last_name",
+    "lastname",
+    "jpegphoto",
+    "avatar",
+    "preferredlanguage",
+    "locale",
+    "memberof",
+    "cn",
+    "displayname",
+    "gidnumber",
+    "userpassword",
+    "creationdate",
+    "creation_date",
+    "createtimestamp",
+    "modifytimestamp",
+    "pwdchangedtime",
+];
+
+/// Whether the attribute is one of the built-in ones, as opposed to a custom or unknown one.
+pub fn is_built_in_user_attribute(attribute: &str) -> bool {
+    BUILT_IN_USER_ATTRIBUTES.contains(&attribute.to_ascii_lowercase().as_str())
+}
+
 const ALL_USER_ATTRIBUTE_KEYS: &[&str] = &[
     "objectclass",
     "uid",
@@ -95,15 +181,20 @@ const ALL_USER_ATTRIBUTE_KEYS: &[&str] = &[
     "jpegPhoto",
     "createtimestamp",
     "entryuuid",
+    "userpassword",
 ];
 
+#[allow(clippy::too_many_arguments)]
 fn make_ldap_search_user_result_entry(
     user: User,
     base_dn_str: &str,
     attributes: &[String],
     groups: Option<&[GroupDetails]>,
     ignored_user_attributes: &[String],
+    additional_object_classes: &[String],
     schema: &Schema,
+    password_placeholder: Option<&str>,
+    membership_format: MembershipValueFormat,
 ) -> LdapSearchResultEntry {
     let expanded_attributes = expand_user_attribute_wildcards(attributes);
     let dn = format!("uid={},ou=people,{}", user.user_id.as_str(), base_dn_str);
@@ -118,7 +209,10 @@ fn make_ldap_search_user_result_entry(
                     base_dn_str,
                     groups,
                     ignored_user_attributes,
+                    additional_object_classes,
                     schema,
+                    password_placeholder,
+                    membership_format,
                 )?;
                 Some(LdapPartialAttribute {
                     atype: a.to_string(),
@@ -149,10 +243,18 @@ fn convert_user_filter(ldap_info: &LdapInfo, filter: &LdapFilter) -> LdapResult<
                         &ldap_info.base_dn_str,
                     )?,
                 )),
-                "objectclass" => Ok(UserRequestFilter::from(matches!(
-                    value.to_ascii_lowercase().as_str(),
-                    "person" | "inetorgperson" | "posixaccount" | "mailaccount"
-                ))),
+                "objectclass" => Ok(UserRequestFilter::from(
+                    USER_OBJECT_CLASSES
+                        .iter()
+                        .copied()
+                        .chain(
+                            ldap_info
+                                .additional_user_object_classes
+                                .iter()
+                                .map(String::as_str),
+                        )
+                        .any(|c| c.eq_ignore_ascii_case(value)),
+                )),
                 "dn" => Ok(get_user_id_from_distinguished_name(
                     value.to_ascii_lowercase().as_str(),
                     &ldap_info.base_dn,
@@ -189,13 +291,19 @@ fn convert_user_filter(ldap_info: &LdapInfo, filter: &LdapFilter) -> LdapResult<
         }
         LdapFilter::Present(field) => {
             let field = &field.to_ascii_lowercase();
-            // Check that it's a field we support.
-            Ok(UserRequestFilter::from(
-                field == "objectclass"
-                    || field == "dn"
-                    || field == "distinguishedname"
-                    || !matches!(map_user_field(field), UserFieldType::NoMatch),
-            ))
+            match map_user_field(field) {
+                // Attributes are optional, the DB has to check.
+                UserFieldType::Attribute(name) => {
+                    Ok(UserRequestFilter::AttributePresent(name.to_owned()))
+                }
+                // Check that it's a field we support.
+                field_type => Ok(UserRequestFilter::from(
+                    field == "objectclass"
+                        || field == "dn"
+                        || field == "distinguishedname"
+                        || !matches!(field_type, UserFieldType::NoMatch),
+                )),
+            }
         }
         LdapFilter::Substring(field, substring_filter) => {
             let field = &field.to_ascii_lowercase();
@@ -203,6 +311,12 @@ fn convert_user_filter(ldap_info: &LdapInfo, filter: &LdapFilter) -> LdapResult<
                 UserFieldType::PrimaryField(UserColumn::UserId) => Ok(
                     UserRequestFilter::UserIdSubString(substring_filter.clone().into()),
                 ),
+                UserFieldType::Attribute(name) if name != "avatar" => {
+                    Ok(UserRequestFilter::AttributeSubString(
+                        name.to_owned(),
+                        substring_filter.clone().into(),
+                    ))
+                }
                 UserFieldType::NoMatch
                 | UserFieldType::Attribute(_)
                 | UserFieldType::PrimaryField(UserColumn::CreationDate)
@@ -219,6 +333,28 @@ fn convert_user_filter(ldap_info: &LdapInfo, filter: &LdapFilter) -> LdapResult<
                 )),
             }
         }
+        LdapFilter::Extensible(assertion) => {
+            let (field, rule) = get_extensible_match_rule(assertion)?;
+            match (rule, map_user_field(&field)) {
+                (MatchingRule::CaseIgnore, UserFieldType::PrimaryField(column))
+                    if column != UserColumn::UserId =>
+                {
+                    Ok(UserRequestFilter::EqualityIgnoreCase(
+                        column,
+                        assertion.match_value.clone(),
+                    ))
+                }
+                (MatchingRule::CaseIgnore, UserFieldType::Attribute(_)) => Err(LdapError {
+                    code: LdapResultCode::InappropriateMatching,
+                    message: format!(
+                        "Case-insensitive matching is not supported for attribute {:?}",
+                        field
+                    ),
+                }),
+                // User ids, objectClass, DNs and group names are already case-insensitive.
+                _ => rec(&LdapFilter::Equality(field, assertion.match_value.clone())),
+            }
+        }
         _ => Err(LdapError {
             code: LdapResultCode::UnwillingToPerform,
             message: format!("Unsupported user filter: {:?}", filter),
@@ -226,8 +362,22 @@ fn convert_user_filter(ldap_info: &LdapInfo, filter: &LdapFilter) -> LdapResult<
     }
 }
 
+/// Returned for "+".
+const OPERATIONAL_USER_ATTRIBUTE_KEYS: &[&str] = &[
+    "createtimestamp",
+    "modifytimestamp",
+    "pwdchangedtime",
+    "entryuuid",
+    "hassubordinates",
+    "memberof",
+];
+
 fn expand_user_attribute_wildcards(attributes: &[String]) -> Vec<&str> {
-    expand_attribute_wildcards(attributes, ALL_USER_ATTRIBUTE_KEYS)
+    expand_attribute_wildcards(
+        attributes,
+        ALL_USER_ATTRIBUTE_KEYS,
+        OPERATIONAL_USER_ATTRIBUTE_KEYS,
+    )
 }
 
 #[instrument(skip_all, level = "debug")]
@@ -239,31 +389,57 @@ pub async fn get_user_list<Backend: UserListerBackendHandler>(
     backend: &Backend,
 ) -> LdapResult<Vec<UserAndGroups>> {
     debug!(?ldap_filter);
-    let filters = convert_user_filter(ldap_info, ldap_filter)?;
+    let filters = match (
+        convert_user_filter(ldap_info, ldap_filter)?,
+        &ldap_info.visible_user_groups,
+    ) {
+        (filters, None) => filters,
+        (filters, Some(groups)) => UserRequestFilter::And(vec![
+            filters,
+            UserRequestFilter::Or(
+                groups
+                    .iter()
+                    .cloned()
+                    .map(UserRequestFilter::MemberOf)
+                    .collect(),
+            ),
+        ]),
+    };
     debug!(?filters);
     backend
         .list_users(Some(filters), request_groups)
         .await
-        .map_err(|e| LdapError {
-            code: LdapResultCode::Other,
-            message: format!(r#"Error while searching user "{}": {:#}"#, base, e),
+        .map_err(|e| {
+            LdapError::from_domain_error(
+                match e {
+                    DomainError::InvalidInput(_) => LdapResultCode::UnwillingToPerform,
+                    _ => LdapResultCode::Other,
+                },
+                format!(r#"Error while searching user "{}""#, base),
+                &e,
+            )
         })
 }
 
+/// The entries are only converted as the iterator is consumed.
 pub fn convert_users_to_ldap_op<'a>(
     users: Vec<UserAndGroups>,
-    attributes: &'a [String],
+    attributes: Vec<String>,
     ldap_info: &'a LdapInfo,
-    schema: &'a Schema,
+    schema: Schema,
+    password_placeholder: Option<&'a str>,
 ) -> impl Iterator<Item = LdapOp> + 'a {
     users.into_iter().map(move |u| {
         LdapOp::SearchResultEntry(make_ldap_search_user_result_entry(
             u.user,
             &ldap_info.base_dn_str,
-            attributes,
+            &attributes,
             u.groups.as_deref(),
             &ldap_info.ignored_user_attributes,
-            schema,
+            &ldap_info.additional_user_object_classes,
+            &schema,
+            password_placeholder,
+            ldap_info.membership_value_format,
         ))
     })
 }
@@ -1,12 +1,18 @@
 use chrono::{NaiveDateTime, TimeZone};
 use itertools::Itertools;
-use ldap3_proto::{proto::LdapSubstringFilter, LdapResultCode};
+use ldap3_proto::{
+    proto::{LdapFilter, LdapMatchingRuleAssertion, LdapSubstringFilter},
+    LdapResultCode,
+};
 use tracing::{debug, instrument, warn};
 
-use crate::domain::{
-    handler::{Schema, SubStringFilter},
-    ldap::error::{LdapError, LdapResult},
-    types::{AttributeType, AttributeValue, JpegPhoto, UserColumn, UserId},
+use crate::{
+    domain::{
+        handler::{AttributeList, SubStringFilter},
+        ldap::error::{LdapError, LdapResult},
+        types::{AttributeType, AttributeValue, JpegPhoto, UserColumn, UserId},
+    },
+    infra::configuration::{EmptyGroupMembers, MembershipValueFormat},
 };
 
 impl From<LdapSubstringFilter> for SubStringFilter {
@@ -110,6 +116,7 @@ pub fn get_group_id_from_distinguished_name(
 pub fn expand_attribute_wildcards<'a>(
     ldap_attributes: &'a [String],
     all_attribute_keys: &'a [&'static str],
+    operational_attribute_keys: &'a [&'static str],
 ) -> Vec<&'a str> {
     let mut attributes_out = ldap_attributes
         .iter()
@@ -122,6 +129,11 @@ pub fn expand_attribute_wildcards<'a>(
         // Splice in all non-operational attributes
         attributes_out.extend(all_attribute_keys.iter());
     }
+    if attributes_out.iter().any(|&x| x == "+") {
+        // Same for the operational attributes (RFC 3673).
+        attributes_out.retain(|&x| x != "+");
+        attributes_out.extend(operational_attribute_keys.iter());
+    }
 
     // Deduplicate, preserving order
     let resolved_attributes = attributes_out
@@ -153,6 +165,214 @@ pub fn is_subtree(subtree: &[(String, String)], base_tree: &[(String, String)])
     true
 }
 
+/// Parser of the string representation of the LDAP filters (RFC 4515), for the configuration. The
+/// `>=`, `<=` and extensible match items are not supported.
+struct FilterParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl FilterParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.peek() {
+            Some(next) if next == c => {
+                self.pos += c.len_utf8();
+                Ok(())
+            }
+            _ => Err(format!("Expected `{}` at position {}", c, self.pos)),
+        }
+    }
+
+    fn parse_filter(&mut self) -> Result<LdapFilter, String> {
+        self.expect('(')?;
+        let filter = match self.peek() {
+            Some('&') => {
+                self.pos += 1;
+                LdapFilter::And(self.parse_filter_list()?)
+            }
+            Some('|') => {
+                self.pos += 1;
+                LdapFilter::Or(self.parse_filter_list()?)
+            }
+            Some('!') => {
+                self.pos += 1;
+                LdapFilter::Not(Box::new(self.parse_filter()?))
+            }
+            _ => {
+                let end = self.input[self.pos..]
+                    .find(')')
+                    .ok_or_else(|| format!("Unterminated item at position {}", self.pos))?;
+                let item = &self.input[self.pos..self.pos + end];
+                self.pos += end;
+                parse_filter_item(item)?
+            }
+        };
+        self.expect(')')?;
+        Ok(filter)
+    }
+
+    fn parse_filter_list(&mut self) -> Result<Vec<LdapFilter>, String> {
+        let mut filters = Vec::new();
+        while self.peek() == Some('(') {
+            filters.push(self.parse_filter()?);
+        }
+        Ok(filters)
+    }
+}
+
+/// Decodes the `\XX` escapes of a value.
+fn unescape_filter_value(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'\\' {
+            let hex = [iter.next(), iter.next()];
+            let byte = match hex {
+                [Some(h), Some(l)] => std::str::from_utf8(&[h, l])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                _ => None,
+            }
+            .ok_or_else(|| format!("Invalid escape in `{}`", value))?;
+            bytes.push(byte);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("Invalid UTF-8 in `{}`", value))
+}
+
+fn parse_filter_item(item: &str) -> Result<LdapFilter, String> {
+    let (attribute, value) = item
+        .split_once('=')
+        .ok_or_else(|| format!("Missing `=` in `{}`", item))?;
+    let (attribute, approx) = match attribute.strip_suffix('~') {
+        Some(attribute) => (attribute, true),
+        None => (attribute, false),
+    };
+    if attribute.is_empty()
+        || !attribute
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ';')
+    {
+        return Err(format!("Unsupported filter item `{}`", item));
+    }
+    let attribute = attribute.to_owned();
+    if approx {
+        return Ok(LdapFilter::Approx(attribute, unescape_filter_value(value)?));
+    }
+    if value == "*" {
+        return Ok(LdapFilter::Present(attribute));
+    }
+    if !value.contains('*') {
+        return Ok(LdapFilter::Equality(
+            attribute,
+            unescape_filter_value(value)?,
+        ));
+    }
+    let mut parts = value.split('*').map(unescape_filter_value);
+    let initial = parts.next().transpose()?.filter(|s| !s.is_empty());
+    let mut any = parts.collect::<Result<Vec<_>, _>>()?;
+    let final_ = any.pop().filter(|s| !s.is_empty());
+    any.retain(|s| !s.is_empty());
+    Ok(LdapFilter::Substring(
+        attribute,
+        LdapSubstringFilter {
+            initial,
+            any,
+            final_,
+        },
+    ))
+}
+
+/// Parses a filter like `(&(objectClass=person)(mail=*@example.com))`. The outer parentheses are
+/// optional for a single item.
+pub fn parse_ldap_filter(filter: &str) -> LdapResult<LdapFilter> {
+    let filter = filter.trim();
+    (|| {
+        if !filter.starts_with('(') {
+            return parse_filter_item(filter);
+        }
+        let mut parser = FilterParser {
+            input: filter,
+            pos: 0,
+        };
+        let result = parser.parse_filter()?;
+        if parser.pos != filter.len() {
+            return Err(format!("Unexpected content at position {}", parser.pos));
+        }
+        Ok(result)
+    })()
+    .map_err(|message| LdapError {
+        code: LdapResultCode::ProtocolError,
+        message: format!("Invalid filter `{}`: {}", filter, message),
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MatchingRule {
+    Equality,
+    CaseIgnore,
+}
+
+/// Validates an extensible match filter, and returns the (lowercase) attribute with the matching
+/// rule to apply.
+pub fn get_extensible_match_rule(
+    assertion: &LdapMatchingRuleAssertion,
+) -> LdapResult<(String, MatchingRule)> {
+    if assertion.dn_attributes {
+        return Err(LdapError {
+            code: LdapResultCode::UnwillingToPerform,
+            message: "dnAttributes is not supported in extensible matches".to_string(),
+        });
+    }
+    let attribute = assertion
+        .type_
+        .as_ref()
+        .ok_or_else(|| LdapError {
+            code: LdapResultCode::UnwillingToPerform,
+            message: "Extensible matches without an attribute are not supported".to_string(),
+        })?
+        .to_ascii_lowercase();
+    let rule = match assertion
+        .matching_rule
+        .as_ref()
+        .map(|r| r.to_ascii_lowercase())
+        .as_deref()
+    {
+        None
+        | Some("caseexactmatch")
+        | Some("2.5.13.5")
+        | Some("octetstringmatch")
+        | Some("2.5.13.17") => MatchingRule::Equality,
+        Some("caseignorematch")
+        | Some("2.5.13.2")
+        | Some("caseignoreia5match")
+        | Some("1.3.6.1.4.1.1466.109.114.2") => MatchingRule::CaseIgnore,
+        // LDAP_MATCHING_RULE_IN_CHAIN: groups can't be nested, so the transitive membership is the
+        // direct membership.
+        Some("1.2.840.113556.1.4.1941")
+            if matches!(attribute.as_str(), "memberof" | "member" | "uniquemember") =>
+        {
+            MatchingRule::Equality
+        }
+        Some(rule) => {
+            return Err(LdapError {
+                code: LdapResultCode::InappropriateMatching,
+                message: format!(
+                    r#"Unsupported matching rule "{}" for attribute "{}""#,
+                    rule, attribute
+                ),
+            })
+        }
+    };
+    Ok((attribute, rule))
+}
+
 pub enum UserFieldType {
     NoMatch,
     PrimaryField(UserColumn),
@@ -170,6 +390,7 @@ pub fn map_user_field(field: &str) -> UserFieldType {
         "givenname" | "first_name" | "firstname" => UserFieldType::Attribute("first_name"),
         "sn" | "last_name" | "lastname" => UserFieldType::Attribute("last_name"),
         "avatar" | "jpegphoto" => UserFieldType::Attribute("avatar"),
+        "preferredlanguage" | "locale" => UserFieldType::Attribute("locale"),
         "creationdate" | "createtimestamp" | "modifytimestamp" | "creation_date" => {
             UserFieldType::PrimaryField(UserColumn::CreationDate)
         }
@@ -182,8 +403,10 @@ pub fn map_group_field(field: &str) -> Option<&'static str> {
     assert!(field == field.to_ascii_lowercase());
     Some(match field {
         "cn" | "displayname" | "uid" | "display_name" => "display_name",
-        "creationdate" | "createtimestamp" | "modifytimestamp" | "creation_date" => "creation_date",
+        "creationdate" | "createtimestamp" | "creation_date" => "creation_date",
+        "modifytimestamp" | "modified_date" => "modified_date",
         "entryuuid" | "uuid" => "uuid",
+        "mail" | "email" => "email",
         _ => return None,
     })
 }
@@ -193,12 +416,50 @@ pub struct LdapInfo {
     pub base_dn_str: String,
     pub ignored_user_attributes: Vec<String>,
     pub ignored_group_attributes: Vec<String>,
+    pub additional_user_object_classes: Vec<String>,
+    pub additional_group_object_classes: Vec<String>,
+    /// The groups whose members are visible over LDAP, or None if they all are.
+    pub visible_user_groups: Option<Vec<String>>,
+    /// The `userPassword` of the users with a password, if it's exposed.
+    pub user_password_placeholder: Option<String>,
+    /// How the groups without members are returned.
+    pub empty_group_members: EmptyGroupMembers,
+    pub empty_group_placeholder_member: String,
+    pub membership_value_format: MembershipValueFormat,
+}
+
+/// The value of `memberOf` for a group, or of `member` for a user: `naming_attribute` is `cn` or
+/// `uid`, and `ou` is `groups` or `people`.
+pub fn make_membership_value(
+    format: MembershipValueFormat,
+    naming_attribute: &str,
+    name: &str,
+    ou: &str,
+    base_dn_str: &str,
+) -> Vec<u8> {
+    match format {
+        MembershipValueFormat::Dn => {
+            format!("{}={},ou={},{}", naming_attribute, name, ou, base_dn_str)
+        }
+        MembershipValueFormat::Rdn => format!("{}={}", naming_attribute, name),
+        MembershipValueFormat::Name => name.to_owned(),
+    }
+    .into_bytes()
+}
+
+impl LdapInfo {
+    pub fn is_user_visible<'a>(&self, mut groups: impl Iterator<Item = &'a String>) -> bool {
+        match &self.visible_user_groups {
+            None => true,
+            Some(visible_groups) => groups.any(|g| visible_groups.contains(g)),
+        }
+    }
 }
 
 pub fn get_custom_attribute(
     attributes: &[AttributeValue],
     attribute_name: &str,
-    schema: &Schema,
+    attribute_list: &AttributeList,
 ) -> Option<Vec<Vec<u8>>> {
     let convert_date = |date| {
         chrono::Utc
@@ -206,9 +467,10 @@ pub fn get_custom_attribute(
             .to_rfc3339()
             .into_bytes()
     };
-    schema
-        .user_attributes
-        .get_attribute_type(attribute_name)
+    attribute_list
+        .get_attribute_schema(attribute_name)
+        .filter(|attribute_schema| attribute_schema.is_ldap_visible)
+        .map(|attribute_schema| (attribute_schema.attribute_type, attribute_schema.is_list))
         .and_then(|attribute_type| {
             attributes
                 .iter()
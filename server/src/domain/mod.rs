@@ -1,3 +1,4 @@
+pub mod derived_attributes;
 pub mod error;
 pub mod handler;
 pub mod ldap;
@@ -5,6 +6,7 @@ pub mod model;
 pub mod opaque_handler;
 pub mod sql_backend_handler;
 pub mod sql_group_backend_handler;
+pub mod sql_maintenance_handler;
 pub mod sql_migrations;
 pub mod sql_opaque_handler;
 pub mod sql_schema_backend_handler;
@@ -20,6 +20,8 @@ pub struct Model {
     pub is_group_visible: bool,
     #[sea_orm(column_name = "group_attribute_schema_is_group_editable")]
     pub is_group_editable: bool,
+    #[sea_orm(column_name = "group_attribute_schema_is_ldap_visible")]
+    pub is_ldap_visible: bool,
     #[sea_orm(column_name = "group_attribute_schema_is_hardcoded")]
     pub is_hardcoded: bool,
 }
@@ -46,6 +48,7 @@ impl From<Model> for AttributeSchema {
             is_list: value.is_list,
             is_visible: value.is_group_visible,
             is_editable: value.is_group_editable,
+            is_ldap_visible: value.is_ldap_visible,
             is_hardcoded: value.is_hardcoded,
         }
     }
@@ -13,6 +13,10 @@ pub struct Model {
     pub display_name: String,
     pub creation_date: chrono::NaiveDateTime,
     pub uuid: Uuid,
+    pub email: Option<String>,
+    pub description: Option<String>,
+    pub modified_date: chrono::NaiveDateTime,
+    pub unique_display_name: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -36,7 +40,11 @@ impl From<Model> for crate::domain::types::Group {
             display_name: group.display_name,
             creation_date: group.creation_date,
             uuid: group.uuid,
+            email: group.email,
+            description: group.description,
+            modified_date: group.modified_date,
             users: vec![],
+            attributes: vec![],
         }
     }
 }
@@ -48,6 +56,8 @@ impl From<Model> for crate::domain::types::GroupDetails {
             display_name: group.display_name,
             creation_date: group.creation_date,
             uuid: group.uuid,
+            email: group.email,
+            description: group.description,
         }
     }
 }
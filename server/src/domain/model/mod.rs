@@ -2,10 +2,14 @@
 
 pub mod prelude;
 
+pub mod group_manager_groups;
+pub mod group_managers;
 pub mod groups;
 pub mod jwt_refresh_storage;
+pub mod jwt_secrets;
 pub mod jwt_storage;
 pub mod memberships;
+pub mod password_reset_requests;
 pub mod password_reset_tokens;
 pub mod users;
 
@@ -4,14 +4,22 @@ pub use super::group_attribute_schema::Column as GroupAttributeSchemaColumn;
 pub use super::group_attribute_schema::Entity as GroupAttributeSchema;
 pub use super::group_attributes::Column as GroupAttributesColumn;
 pub use super::group_attributes::Entity as GroupAttributes;
+pub use super::group_manager_groups::Column as GroupManagerGroupColumn;
+pub use super::group_manager_groups::Entity as GroupManagerGroup;
+pub use super::group_managers::Column as GroupManagerColumn;
+pub use super::group_managers::Entity as GroupManager;
 pub use super::groups::Column as GroupColumn;
 pub use super::groups::Entity as Group;
 pub use super::jwt_refresh_storage::Column as JwtRefreshStorageColumn;
 pub use super::jwt_refresh_storage::Entity as JwtRefreshStorage;
+pub use super::jwt_secrets::Column as JwtSecretsColumn;
+pub use super::jwt_secrets::Entity as JwtSecrets;
 pub use super::jwt_storage::Column as JwtStorageColumn;
 pub use super::jwt_storage::Entity as JwtStorage;
 pub use super::memberships::Column as MembershipColumn;
 pub use super::memberships::Entity as Membership;
+pub use super::password_reset_requests::Column as PasswordResetRequestsColumn;
+pub use super::password_reset_requests::Entity as PasswordResetRequests;
 pub use super::password_reset_tokens::Column as PasswordResetTokensColumn;
 pub use super::password_reset_tokens::Entity as PasswordResetTokens;
 pub use super::user_attribute_schema::Column as UserAttributeSchemaColumn;
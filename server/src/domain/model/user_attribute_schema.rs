@@ -20,6 +20,8 @@ pub struct Model {
     pub is_user_visible: bool,
     #[sea_orm(column_name = "user_attribute_schema_is_user_editable")]
     pub is_user_editable: bool,
+    #[sea_orm(column_name = "user_attribute_schema_is_ldap_visible")]
+    pub is_ldap_visible: bool,
     #[sea_orm(column_name = "user_attribute_schema_is_hardcoded")]
     pub is_hardcoded: bool,
 }
@@ -46,6 +48,7 @@ impl From<Model> for AttributeSchema {
             is_list: value.is_list,
             is_visible: value.is_user_visible,
             is_editable: value.is_user_editable,
+            is_ldap_visible: value.is_ldap_visible,
             is_hardcoded: value.is_hardcoded,
         }
     }
@@ -3,7 +3,7 @@
 use sea_orm::{entity::prelude::*, sea_query::BlobSize};
 use serde::{Deserialize, Serialize};
 
-use crate::domain::types::{UserId, Uuid};
+use crate::domain::types::{GroupId, UserId, Uuid};
 
 #[derive(Copy, Clone, Default, Debug, DeriveEntity)]
 pub struct Entity;
@@ -20,6 +20,11 @@ pub struct Model {
     pub totp_secret: Option<String>,
     pub mfa_type: Option<String>,
     pub uuid: Uuid,
+    pub tokens_valid_after: Option<chrono::NaiveDateTime>,
+    pub primary_group_id: Option<GroupId>,
+    pub expiry_date: Option<chrono::NaiveDateTime>,
+    pub password_modified_date: Option<chrono::NaiveDateTime>,
+    pub unique_email: Option<String>,
 }
 
 impl EntityName for Entity {
@@ -38,6 +43,11 @@ pub enum Column {
     TotpSecret,
     MfaType,
     Uuid,
+    TokensValidAfter,
+    PrimaryGroupId,
+    ExpiryDate,
+    PasswordModifiedDate,
+    UniqueEmail,
 }
 
 impl ColumnTrait for Column {
@@ -53,6 +63,11 @@ impl ColumnTrait for Column {
             Column::TotpSecret => ColumnType::String(Some(64)),
             Column::MfaType => ColumnType::String(Some(64)),
             Column::Uuid => ColumnType::String(Some(36)),
+            Column::TokensValidAfter => ColumnType::DateTime,
+            Column::PrimaryGroupId => ColumnType::Integer,
+            Column::ExpiryDate => ColumnType::DateTime,
+            Column::PasswordModifiedDate => ColumnType::DateTime,
+            Column::UniqueEmail => ColumnType::String(Some(255)),
         }
         .def()
     }
@@ -117,7 +132,11 @@ impl From<Model> for crate::domain::types::User {
             display_name: user.display_name,
             creation_date: user.creation_date,
             uuid: user.uuid,
+            primary_group: user.primary_group_id,
+            expiry_date: user.expiry_date,
+            password_modified_date: user.password_modified_date,
             attributes: Vec::new(),
+            has_password: user.password_hash.is_some(),
         }
     }
 }
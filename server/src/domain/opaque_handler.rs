@@ -18,6 +18,8 @@ pub trait OpaqueHandler: Send + Sync {
         &self,
         request: registration::ClientRegistrationFinishRequest,
     ) -> Result<()>;
+    /// Removes the password of the user: they can't log in until a new one is set.
+    async fn clear_password(&self, user_id: &UserId) -> Result<()>;
 }
 
 #[cfg(test)]
@@ -41,5 +43,6 @@ mockall::mock! {
             &self,
             request: registration::ClientRegistrationFinishRequest
         ) -> Result<()>;
+        async fn clear_password(&self, user_id: &UserId) -> Result<()>;
     }
 }
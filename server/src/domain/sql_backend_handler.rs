@@ -1,21 +1,209 @@
-use crate::domain::{handler::BackendHandler, sql_tables::DbConnection};
+use crate::domain::{
+    error::{DomainError, Result},
+    handler::BackendHandler,
+    sql_migrations::Metadata,
+    sql_tables::DbConnection,
+};
 use crate::infra::configuration::Configuration;
 use async_trait::async_trait;
+use sea_orm::{
+    sea_query::{Expr, Query},
+    AccessMode, ConnectionTrait, DatabaseTransaction, DbBackend, DbErr, ExecResult, IsolationLevel,
+    QueryResult, Statement, TransactionError, TransactionTrait,
+};
+use std::{future::Future, pin::Pin, sync::Arc};
+
+/// The connection of a handler: the pool, or a transaction that all the operations of the handler
+/// run in, until it's committed. The nested transactions are savepoints.
+#[derive(Clone)]
+pub(crate) enum SqlConnection {
+    Pool(DbConnection),
+    Transaction(Arc<DatabaseTransaction>),
+}
+
+#[async_trait]
+impl ConnectionTrait for SqlConnection {
+    fn get_database_backend(&self) -> DbBackend {
+        match self {
+            Self::Pool(pool) => pool.get_database_backend(),
+            Self::Transaction(transaction) => transaction.get_database_backend(),
+        }
+    }
+
+    async fn execute(&self, stmt: Statement) -> std::result::Result<ExecResult, DbErr> {
+        match self {
+            Self::Pool(pool) => pool.execute(stmt).await,
+            Self::Transaction(transaction) => transaction.execute(stmt).await,
+        }
+    }
+
+    async fn execute_unprepared(&self, sql: &str) -> std::result::Result<ExecResult, DbErr> {
+        match self {
+            Self::Pool(pool) => pool.execute_unprepared(sql).await,
+            Self::Transaction(transaction) => transaction.execute_unprepared(sql).await,
+        }
+    }
+
+    async fn query_one(&self, stmt: Statement) -> std::result::Result<Option<QueryResult>, DbErr> {
+        match self {
+            Self::Pool(pool) => pool.query_one(stmt).await,
+            Self::Transaction(transaction) => transaction.query_one(stmt).await,
+        }
+    }
+
+    async fn query_all(&self, stmt: Statement) -> std::result::Result<Vec<QueryResult>, DbErr> {
+        match self {
+            Self::Pool(pool) => pool.query_all(stmt).await,
+            Self::Transaction(transaction) => transaction.query_all(stmt).await,
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionTrait for SqlConnection {
+    async fn begin(&self) -> std::result::Result<DatabaseTransaction, DbErr> {
+        match self {
+            Self::Pool(pool) => pool.begin().await,
+            Self::Transaction(transaction) => transaction.begin().await,
+        }
+    }
+
+    async fn begin_with_config(
+        &self,
+        isolation_level: Option<IsolationLevel>,
+        access_mode: Option<AccessMode>,
+    ) -> std::result::Result<DatabaseTransaction, DbErr> {
+        match self {
+            Self::Pool(pool) => pool.begin_with_config(isolation_level, access_mode).await,
+            Self::Transaction(transaction) => {
+                transaction
+                    .begin_with_config(isolation_level, access_mode)
+                    .await
+            }
+        }
+    }
+
+    async fn transaction<F, T, E>(&self, callback: F) -> std::result::Result<T, TransactionError<E>>
+    where
+        F: for<'c> FnOnce(
+                &'c DatabaseTransaction,
+            )
+                -> Pin<Box<dyn Future<Output = std::result::Result<T, E>> + Send + 'c>>
+            + Send,
+        T: Send,
+        E: std::error::Error + Send,
+    {
+        match self {
+            Self::Pool(pool) => pool.transaction(callback).await,
+            Self::Transaction(transaction) => transaction.transaction(callback).await,
+        }
+    }
+
+    async fn transaction_with_config<F, T, E>(
+        &self,
+        callback: F,
+        isolation_level: Option<IsolationLevel>,
+        access_mode: Option<AccessMode>,
+    ) -> std::result::Result<T, TransactionError<E>>
+    where
+        F: for<'c> FnOnce(
+                &'c DatabaseTransaction,
+            )
+                -> Pin<Box<dyn Future<Output = std::result::Result<T, E>> + Send + 'c>>
+            + Send,
+        T: Send,
+        E: std::error::Error + Send,
+    {
+        match self {
+            Self::Pool(pool) => {
+                pool.transaction_with_config(callback, isolation_level, access_mode)
+                    .await
+            }
+            Self::Transaction(transaction) => {
+                transaction
+                    .transaction_with_config(callback, isolation_level, access_mode)
+                    .await
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct SqlBackendHandler {
     pub(crate) config: Configuration,
-    pub(crate) sql_pool: DbConnection,
+    pub(crate) sql_pool: SqlConnection,
 }
 
 impl SqlBackendHandler {
     pub fn new(config: Configuration, sql_pool: DbConnection) -> Self {
-        SqlBackendHandler { config, sql_pool }
+        SqlBackendHandler {
+            config,
+            sql_pool: SqlConnection::Pool(sql_pool),
+        }
+    }
+}
+
+/// Checks the length of a new user ID or group name against the configured bounds. A maximum of
+/// 0 means unlimited.
+pub(crate) fn check_name_length(
+    kind: &str,
+    name: &str,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+) -> Result<()> {
+    let length = name.chars().count();
+    if let Some(min_length) = min_length.filter(|min| length < *min) {
+        return Err(DomainError::InvalidInput(format!(
+            "The {} '{}' is too short: {} characters, the minimum is {}",
+            kind, name, length, min_length
+        )));
+    }
+    if let Some(max_length) = max_length.filter(|max| *max > 0 && length > *max) {
+        return Err(DomainError::InvalidInput(format!(
+            "The {} '{}' is too long: {} characters, the maximum is {}",
+            kind, name, length, max_length
+        )));
     }
+    Ok(())
+}
+
+/// Writes to the metadata row, which stays locked until the end of the transaction: the
+/// transactions that count the rows before inserting one can't interleave, and go over a limit.
+pub(crate) async fn lock_for_limit_check(transaction: &DatabaseTransaction) -> Result<()> {
+    let backend = transaction.get_database_backend();
+    transaction
+        .execute(
+            backend.build(
+                Query::update()
+                    .table(Metadata::Table)
+                    .value(Metadata::Version, Expr::col(Metadata::Version)),
+            ),
+        )
+        .await?;
+    Ok(())
 }
 
 #[async_trait]
-impl BackendHandler for SqlBackendHandler {}
+impl BackendHandler for SqlBackendHandler {
+    async fn begin_transaction(&self) -> Result<Self> {
+        Ok(SqlBackendHandler {
+            config: self.config.clone(),
+            sql_pool: SqlConnection::Transaction(Arc::new(self.sql_pool.begin().await?)),
+        })
+    }
+
+    async fn commit_transaction(self) -> Result<()> {
+        match self.sql_pool {
+            SqlConnection::Pool(_) => Ok(()),
+            SqlConnection::Transaction(transaction) => match Arc::try_unwrap(transaction) {
+                Ok(transaction) => Ok(transaction.commit().await?),
+                Err(_) => Err(DomainError::InternalError(
+                    "The transaction is still in use".to_string(),
+                )),
+            },
+        }
+    }
+}
 
 #[cfg(test)]
 pub mod tests {
@@ -146,6 +334,21 @@ pub mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_transaction() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        // Dropped without a commit: rolled back.
+        let transaction = handler.begin_transaction().await.unwrap();
+        insert_user_no_password(&transaction, "bob").await;
+        assert_eq!(get_user_names(&transaction, None).await, vec!["bob"]);
+        drop(transaction);
+        assert!(get_user_names(&handler, None).await.is_empty());
+        let transaction = handler.begin_transaction().await.unwrap();
+        insert_user_no_password(&transaction, "john").await;
+        transaction.commit_transaction().await.unwrap();
+        assert_eq!(get_user_names(&handler, None).await, vec!["john"]);
+    }
+
     #[tokio::test]
     async fn test_sql_injection() {
         let sql_pool = get_initialized_db().await;
@@ -1,19 +1,59 @@
 use crate::domain::{
     error::{DomainError, Result},
     handler::{
-        GroupBackendHandler, GroupListerBackendHandler, GroupRequestFilter, UpdateGroupRequest,
+        CreateAttributeRequest, GroupBackendHandler, GroupListerBackendHandler, GroupRequestFilter,
+        SchemaBackendHandler, UpdateAttributeRequest, UpdateGroupRequest,
     },
-    model::{self, GroupColumn, MembershipColumn},
-    sql_backend_handler::SqlBackendHandler,
-    types::{Group, GroupDetails, GroupId, Uuid},
+    model::{
+        self, GroupAttributeSchemaColumn, GroupAttributesColumn, GroupColumn, GroupManagerColumn,
+        GroupManagerGroupColumn, MembershipColumn, UserColumn,
+    },
+    sql_backend_handler::{check_name_length, lock_for_limit_check, SqlBackendHandler},
+    types::{AttributeValue, Group, GroupDetails, GroupId, UserId, Uuid},
 };
+use crate::infra::configuration::GroupNameUniqueness;
 use async_trait::async_trait;
 use sea_orm::{
-    sea_query::{Alias, Cond, Expr, Func, IntoCondition, SimpleExpr},
-    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
-    QueryTrait,
+    sea_query::{query::OnConflict, Alias, Cond, Expr, Func, IntoCondition, SimpleExpr},
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, QueryTrait, TransactionTrait,
 };
-use tracing::{debug, instrument};
+use std::collections::HashMap;
+use tracing::{debug, instrument, warn};
+
+/// The groups created by LLDAP itself, that don't count towards `max_groups`.
+pub(crate) const BUILT_IN_GROUPS: &[&str] = &[
+    "lldap_admin",
+    "lldap_password_manager",
+    "lldap_strict_readonly",
+    "lldap_read_only_admin",
+];
+
+/// The names of the group fields and LDAP attributes, that custom attributes can't shadow.
+const RESERVED_GROUP_ATTRIBUTE_NAMES: &[&str] = &[
+    "objectclass",
+    "dn",
+    "distinguishedname",
+    "cn",
+    "uid",
+    "id",
+    "display_name",
+    "displayname",
+    "entryuuid",
+    "uuid",
+    "hassubordinates",
+    "mail",
+    "email",
+    "description",
+    "gidnumber",
+    "creationdate",
+    "creation_date",
+    "createtimestamp",
+    "modifytimestamp",
+    "modified_date",
+    "member",
+    "uniquemember",
+];
 
 fn get_group_filter_expr(filter: GroupRequestFilter) -> Cond {
     use GroupRequestFilter::*;
@@ -49,17 +89,99 @@ fn get_group_filter_expr(filter: GroupRequestFilter) -> Cond {
                     .into_query(),
             )
             .into_condition(),
+        // WHERE (group_id in (SELECT group_id FROM group_managers WHERE user_id = user))
+        //    OR (group_id in (SELECT group_id FROM group_manager_groups WHERE manager_group_id in
+        //          (SELECT group_id FROM memberships WHERE user_id = user)))
+        ManagedBy(user) => Cond::any()
+            .add(
+                GroupColumn::GroupId.in_subquery(
+                    model::GroupManager::find()
+                        .select_only()
+                        .column(GroupManagerColumn::GroupId)
+                        .filter(GroupManagerColumn::UserId.eq(user.clone()))
+                        .into_query(),
+                ),
+            )
+            .add(
+                GroupColumn::GroupId.in_subquery(
+                    model::GroupManagerGroup::find()
+                        .select_only()
+                        .column(GroupManagerGroupColumn::GroupId)
+                        .filter(
+                            GroupManagerGroupColumn::ManagerGroupId.in_subquery(
+                                model::Membership::find()
+                                    .select_only()
+                                    .column(MembershipColumn::GroupId)
+                                    .filter(MembershipColumn::UserId.eq(user))
+                                    .into_query(),
+                            ),
+                        )
+                        .into_query(),
+                ),
+            ),
+        Email(email) => {
+            SimpleExpr::FunctionCall(Func::lower(Expr::col((group_table, GroupColumn::Email))))
+                .eq(email.to_ascii_lowercase())
+                .into_condition()
+        }
         DisplayNameSubString(filter) => SimpleExpr::FunctionCall(Func::lower(Expr::col((
             group_table,
             GroupColumn::DisplayName,
         ))))
-        .like(filter.to_sql_filter())
+        .like(filter.to_sql_like_expr())
         .into_condition(),
     }
 }
 
+/// The value of the `unique_display_name` column: the unique index only applies when the
+/// uniqueness is case-insensitive.
+fn get_unique_display_name(policy: GroupNameUniqueness, name: &str) -> Option<String> {
+    (policy == GroupNameUniqueness::CaseInsensitive).then(|| name.to_lowercase())
+}
+
+/// Rejects `name` if another group has it, ignoring the case, when the `group_name_uniqueness`
+/// setting asks for it. The identical names are always rejected by the database, and so are the
+/// case-insensitive duplicates that race with this check.
+async fn check_group_name_uniqueness(
+    connection: &impl ConnectionTrait,
+    policy: GroupNameUniqueness,
+    group_id: Option<GroupId>,
+    name: &str,
+) -> Result<()> {
+    if policy == GroupNameUniqueness::Exact {
+        return Ok(());
+    }
+    let mut query = model::Group::find().filter(
+        SimpleExpr::FunctionCall(Func::lower(Expr::col(GroupColumn::DisplayName)))
+            .eq(name.to_ascii_lowercase()),
+    );
+    if let Some(group_id) = group_id {
+        query = query.filter(GroupColumn::GroupId.ne(group_id));
+    }
+    match query.one(connection).await? {
+        None => Ok(()),
+        Some(group) => Err(DomainError::Conflict(format!(
+            "The group name '{}' is already used by the group '{}'",
+            name, group.display_name
+        ))),
+    }
+}
+
+fn to_optional_value(value: Option<String>) -> ActiveValue<Option<String>> {
+    match value {
+        None => ActiveValue::NotSet,
+        Some(v) if v.is_empty() => ActiveValue::Set(None),
+        Some(v) => ActiveValue::Set(Some(v)),
+    }
+}
+
 #[async_trait]
 impl GroupListerBackendHandler for SqlBackendHandler {
+    #[instrument(skip_all, level = "debug", ret, err)]
+    async fn count_groups(&self) -> Result<u64> {
+        Ok(model::Group::find().count(&self.sql_pool).await?)
+    }
+
     #[instrument(skip_all, level = "debug", ret, err)]
     async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>> {
         debug!(?filters);
@@ -85,12 +207,25 @@ impl GroupListerBackendHandler for SqlBackendHandler {
             )
             .all(&self.sql_pool)
             .await?;
+        let mut attributes = HashMap::<GroupId, Vec<AttributeValue>>::new();
+        for attribute in model::GroupAttributes::find()
+            .filter(GroupAttributesColumn::GroupId.is_in(results.iter().map(|(g, _)| g.group_id)))
+            .order_by_asc(GroupAttributesColumn::AttributeName)
+            .all(&self.sql_pool)
+            .await?
+        {
+            attributes
+                .entry(attribute.group_id)
+                .or_default()
+                .push(attribute.into());
+        }
         Ok(results
             .into_iter()
             .map(|(group, users)| {
                 let users: Vec<_> = users.into_iter().map(|u| u.user_id).collect();
                 Group {
                     users,
+                    attributes: attributes.remove(&group.group_id).unwrap_or_default(),
                     ..group.into()
                 }
             })
@@ -98,6 +233,68 @@ impl GroupListerBackendHandler for SqlBackendHandler {
     }
 }
 
+impl SqlBackendHandler {
+    /// Fills or clears the `unique_display_name` column of all the groups according to the
+    /// `group_name_uniqueness` setting, which can change between two runs. Fails if the uniqueness
+    /// is case-insensitive but some group names only differ by their case.
+    #[instrument(skip_all, level = "debug", err)]
+    pub async fn apply_group_name_uniqueness_policy(&self) -> Result<()> {
+        let policy = self.config.group_name_uniqueness;
+        let unique_display_name = match policy {
+            GroupNameUniqueness::CaseInsensitive => {
+                SimpleExpr::FunctionCall(Func::lower(Expr::col(GroupColumn::DisplayName)))
+            }
+            GroupNameUniqueness::Exact => Expr::value(Option::<String>::None),
+        };
+        model::Group::update_many()
+            .col_expr(GroupColumn::UniqueDisplayName, unique_display_name)
+            .exec(&self.sql_pool)
+            .await
+            .map_err(|e| match policy {
+                GroupNameUniqueness::CaseInsensitive => DomainError::Conflict(format!(
+                    "Cannot make the group names unique ignoring the case, some groups may only \
+                     differ by their case: {}",
+                    e
+                )),
+                GroupNameUniqueness::Exact => e.into(),
+            })?;
+        Ok(())
+    }
+
+    /// Checks the configured bounds of the group names. The built-in and admin groups are exempt,
+    /// since their names don't come from the users.
+    fn check_group_name_length(&self, name: &str) -> Result<()> {
+        if BUILT_IN_GROUPS.contains(&name) || self.config.admin_groups.iter().any(|g| g == name) {
+            return Ok(());
+        }
+        check_name_length(
+            "group name",
+            name,
+            self.config.group_name_min_length,
+            self.config.group_name_max_length,
+        )
+    }
+
+    /// Creates the built-in groups and the configured admin groups, if they don't exist yet.
+    pub async fn ensure_builtin_groups_exist(&self) -> Result<()> {
+        for group_name in BUILT_IN_GROUPS
+            .iter()
+            .copied()
+            .chain(self.config.admin_groups.iter().map(String::as_str))
+        {
+            if self
+                .list_groups(Some(GroupRequestFilter::DisplayName(group_name.to_owned())))
+                .await?
+                .is_empty()
+            {
+                warn!("Could not find {} group, trying to create it", group_name);
+                self.create_group(group_name).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl GroupBackendHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug", ret, err)]
@@ -113,35 +310,155 @@ impl GroupBackendHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug", err)]
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()> {
         debug!(?request.group_id);
+        let group_id = request.group_id;
+        let mut insert_attributes = Vec::new();
+        if !request.insert_attributes.is_empty() {
+            let schema = self.get_schema().await?.group_attributes;
+            for attribute in request.insert_attributes {
+                let name = match schema.get_attribute_schema(&attribute.name) {
+                    None => {
+                        return Err(DomainError::EntityNotFound(format!(
+                            "No such group attribute: '{}'",
+                            attribute.name
+                        )))
+                    }
+                    Some(attribute_schema) => attribute_schema.name.clone(),
+                };
+                insert_attributes.push(model::group_attributes::ActiveModel {
+                    group_id: ActiveValue::Set(group_id),
+                    attribute_name: ActiveValue::Set(name),
+                    value: ActiveValue::Set(attribute.value),
+                });
+            }
+        }
+        let delete_attributes = request.delete_attributes;
+        let display_name = request
+            .display_name
+            .map(|name| self.config.group_name_normalization.normalize(&name));
+        if let Some(name) = &display_name {
+            self.check_group_name_length(name)?;
+        }
+        let group_name_uniqueness = self.config.group_name_uniqueness;
         let update_group = model::groups::ActiveModel {
-            group_id: ActiveValue::Set(request.group_id),
-            display_name: request
-                .display_name
+            group_id: ActiveValue::Set(group_id),
+            display_name: display_name
+                .clone()
                 .map(ActiveValue::Set)
                 .unwrap_or_default(),
+            unique_display_name: display_name
+                .as_deref()
+                .map(|name| ActiveValue::Set(get_unique_display_name(group_name_uniqueness, name)))
+                .unwrap_or_default(),
+            email: to_optional_value(request.email),
+            description: to_optional_value(request.description),
+            modified_date: ActiveValue::Set(chrono::Utc::now().naive_utc()),
             ..Default::default()
         };
-        update_group.update(&self.sql_pool).await?;
+        self.sql_pool
+            .transaction::<_, (), DomainError>(|transaction| {
+                Box::pin(async move {
+                    if let Some(name) = &display_name {
+                        check_group_name_uniqueness(
+                            transaction,
+                            group_name_uniqueness,
+                            Some(group_id),
+                            name,
+                        )
+                        .await?;
+                    }
+                    update_group.update(transaction).await?;
+                    if !delete_attributes.is_empty() {
+                        model::GroupAttributes::delete_many()
+                            .filter(GroupAttributesColumn::GroupId.eq(group_id))
+                            .filter(GroupAttributesColumn::AttributeName.is_in(delete_attributes))
+                            .exec(transaction)
+                            .await?;
+                    }
+                    if !insert_attributes.is_empty() {
+                        model::GroupAttributes::insert_many(insert_attributes)
+                            .on_conflict(
+                                OnConflict::columns([
+                                    GroupAttributesColumn::GroupId,
+                                    GroupAttributesColumn::AttributeName,
+                                ])
+                                .update_column(GroupAttributesColumn::Value)
+                                .to_owned(),
+                            )
+                            .exec(transaction)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
         Ok(())
     }
 
     #[instrument(skip_all, level = "debug", ret, err)]
     async fn create_group(&self, group_name: &str) -> Result<GroupId> {
         debug!(?group_name);
+        let max_groups = self.config.max_groups.filter(|max| *max > 0);
+        let group_name = self.config.group_name_normalization.normalize(group_name);
+        self.check_group_name_length(&group_name)?;
+        let group_name_uniqueness = self.config.group_name_uniqueness;
         let now = chrono::Utc::now().naive_utc();
-        let uuid = Uuid::from_name_and_date(group_name, &now);
-        let new_group = model::groups::ActiveModel {
-            display_name: ActiveValue::Set(group_name.to_owned()),
-            creation_date: ActiveValue::Set(now),
-            uuid: ActiveValue::Set(uuid),
-            ..Default::default()
-        };
-        Ok(new_group.insert(&self.sql_pool).await?.group_id)
+        let uuid = Uuid::from_name_and_date(&group_name, &now);
+        Ok(self
+            .sql_pool
+            .transaction::<_, GroupId, DomainError>(|transaction| {
+                Box::pin(async move {
+                    if let Some(max_groups) =
+                        max_groups.filter(|_| !BUILT_IN_GROUPS.contains(&group_name.as_str()))
+                    {
+                        lock_for_limit_check(transaction).await?;
+                        let groups = model::Group::find()
+                            .filter(
+                                GroupColumn::DisplayName.is_not_in(BUILT_IN_GROUPS.iter().copied()),
+                            )
+                            .count(transaction)
+                            .await?;
+                        if groups >= max_groups {
+                            return Err(DomainError::LimitExceeded(format!(
+                                "Cannot create more than {} groups",
+                                max_groups
+                            )));
+                        }
+                    }
+                    check_group_name_uniqueness(
+                        transaction,
+                        group_name_uniqueness,
+                        None,
+                        &group_name,
+                    )
+                    .await?;
+                    let new_group = model::groups::ActiveModel {
+                        unique_display_name: ActiveValue::Set(get_unique_display_name(
+                            group_name_uniqueness,
+                            &group_name,
+                        )),
+                        display_name: ActiveValue::Set(group_name),
+                        creation_date: ActiveValue::Set(now),
+                        uuid: ActiveValue::Set(uuid),
+                        modified_date: ActiveValue::Set(now),
+                        ..Default::default()
+                    };
+                    Ok(new_group.insert(transaction).await?.group_id)
+                })
+            })
+            .await?)
     }
 
     #[instrument(skip_all, level = "debug", err)]
     async fn delete_group(&self, group_id: GroupId) -> Result<()> {
         debug!(?group_id);
+        model::User::update_many()
+            .col_expr(
+                UserColumn::PrimaryGroupId,
+                Expr::value(Option::<GroupId>::None),
+            )
+            .filter(UserColumn::PrimaryGroupId.eq(group_id))
+            .exec(&self.sql_pool)
+            .await?;
         let res = model::Group::delete_by_id(group_id)
             .exec(&self.sql_pool)
             .await?;
@@ -153,12 +470,210 @@ impl GroupBackendHandler for SqlBackendHandler {
         }
         Ok(())
     }
+
+    #[instrument(skip_all, level = "debug", ret, err)]
+    async fn get_group_managers(&self, group_id: GroupId) -> Result<Vec<UserId>> {
+        debug!(?group_id);
+        Ok(model::GroupManager::find()
+            .filter(GroupManagerColumn::GroupId.eq(group_id))
+            .order_by_asc(GroupManagerColumn::UserId)
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|m| m.user_id)
+            .collect())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn add_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()> {
+        debug!(?group_id, ?user_id);
+        let new_manager = model::group_managers::ActiveModel {
+            group_id: ActiveValue::Set(group_id),
+            user_id: ActiveValue::Set(user_id.clone()),
+        };
+        new_manager.insert(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", ret, err)]
+    async fn get_group_manager_groups(&self, group_id: GroupId) -> Result<Vec<GroupId>> {
+        debug!(?group_id);
+        Ok(model::GroupManagerGroup::find()
+            .filter(GroupManagerGroupColumn::GroupId.eq(group_id))
+            .order_by_asc(GroupManagerGroupColumn::ManagerGroupId)
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|m| m.manager_group_id)
+            .collect())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn add_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()> {
+        debug!(?group_id, ?manager_group_id);
+        let new_manager = model::group_manager_groups::ActiveModel {
+            group_id: ActiveValue::Set(group_id),
+            manager_group_id: ActiveValue::Set(manager_group_id),
+        };
+        new_manager.insert(&self.sql_pool).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn remove_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()> {
+        debug!(?group_id, ?manager_group_id);
+        let res = model::GroupManagerGroup::delete_by_id((group_id, manager_group_id))
+            .exec(&self.sql_pool)
+            .await?;
+        if res.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such group manager: {:?} -> {:?}",
+                manager_group_id, group_id
+            )));
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn remove_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()> {
+        debug!(?group_id, ?user_id);
+        let res = model::GroupManager::delete_by_id((group_id, user_id.clone()))
+            .exec(&self.sql_pool)
+            .await?;
+        if res.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such group manager: '{}' -> {:?}",
+                user_id, group_id
+            )));
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn add_group_attribute(&self, request: CreateAttributeRequest) -> Result<()> {
+        debug!(?request);
+        if RESERVED_GROUP_ATTRIBUTE_NAMES.contains(&request.name.to_ascii_lowercase().as_str()) {
+            return Err(DomainError::Conflict(format!(
+                "'{}' is a builtin group attribute",
+                request.name
+            )));
+        }
+        if self
+            .get_schema()
+            .await?
+            .group_attributes
+            .get_attribute_schema(&request.name)
+            .is_some()
+        {
+            return Err(DomainError::Conflict(format!(
+                "The group attribute '{}' already exists",
+                request.name
+            )));
+        }
+        model::group_attribute_schema::ActiveModel {
+            attribute_name: ActiveValue::Set(request.name),
+            attribute_type: ActiveValue::Set(request.attribute_type),
+            is_list: ActiveValue::Set(request.is_list),
+            is_group_visible: ActiveValue::Set(request.is_visible),
+            is_group_editable: ActiveValue::Set(request.is_editable),
+            is_ldap_visible: ActiveValue::Set(request.is_ldap_visible),
+            is_hardcoded: ActiveValue::Set(false),
+        }
+        .insert(&self.sql_pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn update_group_attribute(&self, request: UpdateAttributeRequest) -> Result<()> {
+        debug!(?request);
+        let name = self
+            .get_schema()
+            .await?
+            .group_attributes
+            .get_attribute_schema(&request.name)
+            .map(|attribute_schema| attribute_schema.name.clone())
+            .ok_or_else(|| {
+                DomainError::EntityNotFound(format!("No such group attribute: '{}'", request.name))
+            })?;
+        if request.is_empty() {
+            return Ok(());
+        }
+        model::GroupAttributeSchema::update_many()
+            .set(model::group_attribute_schema::ActiveModel {
+                is_group_visible: request.is_visible.map(ActiveValue::Set).unwrap_or_default(),
+                is_group_editable: request
+                    .is_editable
+                    .map(ActiveValue::Set)
+                    .unwrap_or_default(),
+                is_ldap_visible: request
+                    .is_ldap_visible
+                    .map(ActiveValue::Set)
+                    .unwrap_or_default(),
+                ..Default::default()
+            })
+            .filter(GroupAttributeSchemaColumn::AttributeName.eq(name))
+            .exec(&self.sql_pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn delete_group_attribute(&self, name: &str) -> Result<()> {
+        debug!(?name);
+        let attribute_schema = self
+            .get_schema()
+            .await?
+            .group_attributes
+            .get_attribute_schema(name)
+            .cloned()
+            .ok_or_else(|| {
+                DomainError::EntityNotFound(format!("No such group attribute: '{}'", name))
+            })?;
+        if attribute_schema.is_hardcoded {
+            return Err(DomainError::Conflict(format!(
+                "The group attribute '{}' cannot be deleted",
+                attribute_schema.name
+            )));
+        }
+        self.sql_pool
+            .transaction::<_, (), DomainError>(|transaction| {
+                Box::pin(async move {
+                    model::GroupAttributes::delete_many()
+                        .filter(GroupAttributesColumn::AttributeName.eq(&attribute_schema.name))
+                        .exec(transaction)
+                        .await?;
+                    model::GroupAttributeSchema::delete_many()
+                        .filter(
+                            GroupAttributeSchemaColumn::AttributeName.eq(&attribute_schema.name),
+                        )
+                        .exec(transaction)
+                        .await?;
+                    Ok(())
+                })
+            })
+            .await?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::{handler::SubStringFilter, sql_backend_handler::tests::*, types::UserId};
+    use crate::domain::{
+        handler::{SubStringFilter, UserBackendHandler},
+        sql_backend_handler::tests::*,
+        types::{AttributeType, Serialized},
+    };
+    use crate::infra::configuration::GroupNameNormalization;
 
     async fn get_group_ids(
         handler: &SqlBackendHandler,
@@ -215,6 +730,31 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_list_groups_email_filter() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_group(UpdateGroupRequest {
+                group_id: fixture.groups[1],
+                display_name: None,
+                email: Some("Worst@bob.bob".to_owned()),
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            get_group_ids(
+                &fixture.handler,
+                Some(GroupRequestFilter::Email("worst@Bob.bob".to_owned()))
+            )
+            .await,
+            vec![fixture.groups[1]]
+        );
+    }
+
     #[tokio::test]
     async fn test_list_groups_negation() {
         let fixture = TestFixture::new().await;
@@ -279,6 +819,30 @@ mod tests {
             .update_group(UpdateGroupRequest {
                 group_id: fixture.groups[0],
                 display_name: Some("Awesomest Group".to_owned()),
+                email: Some("awesome@bob.bob".to_owned()),
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            })
+            .await
+            .unwrap();
+        let details = fixture
+            .handler
+            .get_group_details(fixture.groups[0])
+            .await
+            .unwrap();
+        assert_eq!(details.display_name, "Awesomest Group");
+        assert_eq!(details.email, Some("awesome@bob.bob".to_owned()));
+        assert_eq!(details.description, None);
+        fixture
+            .handler
+            .update_group(UpdateGroupRequest {
+                group_id: fixture.groups[0],
+                display_name: None,
+                email: Some(String::new()),
+                description: Some("The best".to_owned()),
+                delete_attributes: vec![],
+                insert_attributes: vec![],
             })
             .await
             .unwrap();
@@ -288,6 +852,38 @@ mod tests {
             .await
             .unwrap();
         assert_eq!(details.display_name, "Awesomest Group");
+        assert_eq!(details.email, None);
+        assert_eq!(details.description, Some("The best".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_update_group_modified_date() {
+        let fixture = TestFixture::new().await;
+        let get_group = || async {
+            fixture
+                .handler
+                .list_groups(Some(GroupRequestFilter::GroupId(fixture.groups[0])))
+                .await
+                .unwrap()
+                .remove(0)
+        };
+        let group = get_group().await;
+        assert_eq!(group.modified_date, group.creation_date);
+        fixture
+            .handler
+            .update_group(UpdateGroupRequest {
+                group_id: fixture.groups[0],
+                display_name: None,
+                email: None,
+                description: Some("Updated".to_owned()),
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            })
+            .await
+            .unwrap();
+        let updated_group = get_group().await;
+        assert_eq!(updated_group.creation_date, group.creation_date);
+        assert!(updated_group.modified_date > group.modified_date);
     }
 
     #[tokio::test]
@@ -307,4 +903,357 @@ mod tests {
             vec![fixture.groups[2], fixture.groups[1]]
         );
     }
+
+    #[tokio::test]
+    async fn test_max_groups() {
+        let mut config = get_default_config();
+        config.max_groups = Some(1);
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        // The built-in groups don't count, and can be created at the limit.
+        handler.create_group("lldap_admin").await.unwrap();
+        let group = handler.create_group("Best Group").await.unwrap();
+        assert!(matches!(
+            handler.create_group("Worst Group").await,
+            Err(DomainError::LimitExceeded(_))
+        ));
+        handler.create_group("lldap_strict_readonly").await.unwrap();
+        // Deleting a group frees a slot.
+        handler.delete_group(group).await.unwrap();
+        handler.create_group("Worst Group").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_name_length() {
+        let mut config = get_default_config();
+        config.group_name_min_length = Some(3);
+        config.group_name_max_length = Some(20);
+        config.admin_groups = vec!["it".to_owned()];
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        assert!(matches!(
+            handler.create_group("ab").await,
+            Err(DomainError::InvalidInput(_))
+        ));
+        handler.create_group("abc").await.unwrap();
+        handler.create_group("Twenty characters !!").await.unwrap();
+        assert!(matches!(
+            handler.create_group("Twenty-one characters").await,
+            Err(DomainError::InvalidInput(_))
+        ));
+        // The length is counted in characters, not bytes.
+        handler.create_group(&"é".repeat(20)).await.unwrap();
+        // The built-in and admin groups are exempt.
+        handler
+            .create_group("lldap_password_manager")
+            .await
+            .unwrap();
+        handler.create_group("it").await.unwrap();
+        // But not the other names with the same prefix.
+        assert!(matches!(
+            handler.create_group("lldap_metrics_readers").await,
+            Err(DomainError::InvalidInput(_))
+        ));
+        // The renames are checked too.
+        let group = handler.create_group("abcd").await.unwrap();
+        let rename = |name: &str| {
+            handler.update_group(UpdateGroupRequest {
+                group_id: group,
+                display_name: Some(name.to_owned()),
+                email: None,
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            })
+        };
+        assert!(matches!(
+            rename("ab").await,
+            Err(DomainError::InvalidInput(_))
+        ));
+        rename("abcde").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_name_uniqueness() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        handler.create_group("Admins").await.unwrap();
+        handler.create_group("admins").await.unwrap();
+
+        let mut config = get_default_config();
+        config.group_name_uniqueness = GroupNameUniqueness::CaseInsensitive;
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        let admins = handler.create_group("Admins").await.unwrap();
+        assert!(matches!(
+            handler.create_group("admins").await,
+            Err(DomainError::Conflict(_))
+        ));
+        let devs = handler.create_group("Developers").await.unwrap();
+        let rename = |group_id, name: &str| {
+            handler.update_group(UpdateGroupRequest {
+                group_id,
+                display_name: Some(name.to_owned()),
+                email: None,
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            })
+        };
+        assert!(matches!(
+            rename(devs, "ADMINS").await,
+            Err(DomainError::Conflict(_))
+        ));
+        // A group can change the case of its own name.
+        rename(admins, "ADMINS").await.unwrap();
+        assert_eq!(
+            handler
+                .get_group_details(admins)
+                .await
+                .unwrap()
+                .display_name,
+            "ADMINS"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_name_uniqueness_database_constraint() {
+        let sql_pool = get_initialized_db().await;
+        let exact_handler = SqlBackendHandler::new(get_default_config(), sql_pool.clone());
+        exact_handler.create_group("Admins").await.unwrap();
+        let admins = exact_handler.create_group("admins").await.unwrap();
+        let mut config = get_default_config();
+        config.group_name_uniqueness = GroupNameUniqueness::CaseInsensitive;
+        let handler = SqlBackendHandler::new(config, sql_pool.clone());
+        assert!(matches!(
+            handler.apply_group_name_uniqueness_policy().await,
+            Err(DomainError::Conflict(_))
+        ));
+        exact_handler.delete_group(admins).await.unwrap();
+        handler.apply_group_name_uniqueness_policy().await.unwrap();
+        // A write that raced with the check is rejected by the database.
+        let now = chrono::Utc::now().naive_utc();
+        model::groups::ActiveModel {
+            display_name: ActiveValue::Set("ADMINS".to_owned()),
+            unique_display_name: ActiveValue::Set(Some("admins".to_owned())),
+            creation_date: ActiveValue::Set(now),
+            uuid: ActiveValue::Set(Uuid::from_name_and_date("ADMINS", &now)),
+            modified_date: ActiveValue::Set(now),
+            ..Default::default()
+        }
+        .insert(&sql_pool)
+        .await
+        .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_group_name_normalization() {
+        let mut config = get_default_config();
+        config.group_name_normalization = GroupNameNormalization::Trim;
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        let group = handler.create_group(" Dev Team  ").await.unwrap();
+        assert_eq!(
+            handler.get_group_details(group).await.unwrap().display_name,
+            "Dev Team"
+        );
+
+        let mut config = get_default_config();
+        config.group_name_normalization = GroupNameNormalization::Lowercase;
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        let group = handler.create_group(" Dev Team  ").await.unwrap();
+        assert_eq!(
+            handler.get_group_details(group).await.unwrap().display_name,
+            "dev team"
+        );
+        handler
+            .update_group(UpdateGroupRequest {
+                group_id: group,
+                display_name: Some("Ops Team".to_owned()),
+                email: None,
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_group_details(group).await.unwrap().display_name,
+            "ops team"
+        );
+        // The normalized names are identical, so the database rejects the duplicate.
+        handler.create_group("OPS TEAM").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_group_managers() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let group = fixture.groups[0];
+        assert_eq!(handler.get_group_managers(group).await.unwrap(), vec![]);
+        handler
+            .add_group_manager(group, &UserId::new("patrick"))
+            .await
+            .unwrap();
+        handler
+            .add_group_manager(group, &UserId::new("bob"))
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_group_managers(group).await.unwrap(),
+            vec![UserId::new("bob"), UserId::new("patrick")]
+        );
+        assert_eq!(
+            handler.get_group_managers(fixture.groups[1]).await.unwrap(),
+            vec![]
+        );
+        let managed_groups = handler
+            .list_groups(Some(GroupRequestFilter::ManagedBy(UserId::new("bob"))))
+            .await
+            .unwrap();
+        assert_eq!(
+            managed_groups.iter().map(|g| g.id).collect::<Vec<_>>(),
+            vec![group]
+        );
+        handler
+            .remove_group_manager(group, &UserId::new("bob"))
+            .await
+            .unwrap();
+        handler
+            .remove_group_manager(group, &UserId::new("bob"))
+            .await
+            .unwrap_err();
+        // Deleting the user removes them from the managers.
+        handler.delete_user(&UserId::new("patrick")).await.unwrap();
+        assert_eq!(handler.get_group_managers(group).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_group_manager_groups() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let (group, manager_group) = (fixture.groups[0], fixture.groups[1]);
+        assert_eq!(
+            handler.get_group_manager_groups(group).await.unwrap(),
+            vec![]
+        );
+        handler
+            .add_group_manager_group(group, manager_group)
+            .await
+            .unwrap();
+        assert_eq!(
+            handler.get_group_manager_groups(group).await.unwrap(),
+            vec![manager_group]
+        );
+        // The members of the manager group manage the group.
+        let managed_by = |user: &'static str| async move {
+            handler
+                .list_groups(Some(GroupRequestFilter::ManagedBy(UserId::new(user))))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(managed_by("patrick").await, vec![group]);
+        assert_eq!(managed_by("bob").await, vec![]);
+        handler
+            .remove_group_manager_group(group, manager_group)
+            .await
+            .unwrap();
+        handler
+            .remove_group_manager_group(group, manager_group)
+            .await
+            .unwrap_err();
+        assert_eq!(managed_by("patrick").await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_group_attributes() {
+        let fixture = TestFixture::new().await;
+        let handler = &fixture.handler;
+        let group = fixture.groups[0];
+        let get_attributes = || async {
+            handler
+                .list_groups(Some(GroupRequestFilter::GroupId(group)))
+                .await
+                .unwrap()
+                .remove(0)
+                .attributes
+        };
+        let update = |delete_attributes: Vec<String>, insert_attributes: Vec<AttributeValue>| {
+            handler.update_group(UpdateGroupRequest {
+                group_id: group,
+                display_name: None,
+                email: None,
+                description: None,
+                delete_attributes,
+                insert_attributes,
+            })
+        };
+        let attribute = |name: &str, value: &str| AttributeValue {
+            name: name.to_owned(),
+            value: Serialized::from(value),
+        };
+        // The builtin attributes can't be shadowed.
+        let request = CreateAttributeRequest {
+            name: "Member".to_owned(),
+            attribute_type: AttributeType::String,
+            is_list: false,
+            is_visible: true,
+            is_editable: true,
+            is_ldap_visible: true,
+        };
+        assert!(matches!(
+            handler.add_group_attribute(request.clone()).await,
+            Err(DomainError::Conflict(_))
+        ));
+        let request = CreateAttributeRequest {
+            name: "room".to_owned(),
+            ..request
+        };
+        handler.add_group_attribute(request.clone()).await.unwrap();
+        assert!(matches!(
+            handler.add_group_attribute(request).await,
+            Err(DomainError::Conflict(_))
+        ));
+        handler
+            .update_group_attribute(UpdateAttributeRequest {
+                name: "ROOM".to_owned(),
+                is_ldap_visible: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let room_schema = handler
+            .get_schema()
+            .await
+            .unwrap()
+            .group_attributes
+            .get_attribute_schema("room")
+            .cloned()
+            .unwrap();
+        assert!(!room_schema.is_ldap_visible);
+        assert!(room_schema.is_visible);
+        assert!(matches!(
+            update(vec![], vec![attribute("floor", "2")]).await,
+            Err(DomainError::EntityNotFound(_))
+        ));
+        update(vec![], vec![attribute("Room", "1A")]).await.unwrap();
+        assert_eq!(get_attributes().await, vec![attribute("room", "1A")]);
+        update(vec![], vec![attribute("room", "2B")]).await.unwrap();
+        assert_eq!(get_attributes().await, vec![attribute("room", "2B")]);
+        assert!(handler
+            .list_groups(Some(GroupRequestFilter::GroupId(fixture.groups[1])))
+            .await
+            .unwrap()
+            .remove(0)
+            .attributes
+            .is_empty());
+        update(vec!["room".to_owned()], vec![]).await.unwrap();
+        assert_eq!(get_attributes().await, vec![]);
+        update(vec![], vec![attribute("room", "3C")]).await.unwrap();
+        // Deleting the attribute removes its values.
+        handler.delete_group_attribute("ROOM").await.unwrap();
+        assert_eq!(get_attributes().await, vec![]);
+        assert!(matches!(
+            handler.delete_group_attribute("room").await,
+            Err(DomainError::EntityNotFound(_))
+        ));
+    }
 }
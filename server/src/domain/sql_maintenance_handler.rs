@@ -0,0 +1,608 @@
+use super::{
+    error::{DomainError, Result},
+    handler::{CompactionReport, MaintenanceHandler},
+    model::{self, UserColumn},
+    sql_backend_handler::SqlBackendHandler,
+    sql_migrations::Metadata,
+    types::{AttributeType, GroupId, JpegPhoto, Serialized, UserId},
+};
+use async_trait::async_trait;
+use lldap_auth::opaque;
+use sea_orm::{
+    sea_query::{Cond, Expr, Query},
+    ColumnTrait, ConnectionTrait, DatabaseBackend, EntityTrait, QueryFilter, Statement,
+    TransactionTrait,
+};
+use std::collections::{HashMap, HashSet};
+use tracing::{info, instrument, warn};
+
+/// A compaction lock older than that was left by an instance that stopped mid-compaction.
+const COMPACTION_LOCK_TIMEOUT_HOURS: i64 = 6;
+
+impl SqlBackendHandler {
+    /// Takes the compaction lock in the metadata table, shared by all the instances: a compaction
+    /// locks the database, so they are not stacked.
+    async fn acquire_compaction_lock(&self) -> Result<()> {
+        let now = chrono::Utc::now().naive_utc();
+        let stale = now - chrono::Duration::hours(COMPACTION_LOCK_TIMEOUT_HOURS);
+        let backend = self.sql_pool.get_database_backend();
+        let result = self
+            .sql_pool
+            .execute(
+                backend.build(
+                    Query::update()
+                        .table(Metadata::Table)
+                        .value(Metadata::CompactionStarted, now)
+                        .cond_where(
+                            Cond::any()
+                                .add(Expr::col(Metadata::CompactionStarted).is_null())
+                                .add(Expr::col(Metadata::CompactionStarted).lt(stale)),
+                        ),
+                ),
+            )
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(DomainError::Conflict(
+                "A compaction is already running".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn release_compaction_lock(&self) -> Result<()> {
+        let backend = self.sql_pool.get_database_backend();
+        self.sql_pool
+            .execute(backend.build(Query::update().table(Metadata::Table).value(
+                Metadata::CompactionStarted,
+                Option::<chrono::NaiveDateTime>::None,
+            )))
+            .await?;
+        Ok(())
+    }
+
+    async fn run_compaction(&self) -> Result<()> {
+        match self.sql_pool.get_database_backend() {
+            DatabaseBackend::Sqlite | DatabaseBackend::Postgres => {
+                self.sql_pool.execute_unprepared("VACUUM").await?;
+            }
+            DatabaseBackend::MySql => {
+                let tables = self.get_mysql_tables().await?;
+                if !tables.is_empty() {
+                    let tables = tables
+                        .iter()
+                        .map(|t| format!("`{}`", t))
+                        .collect::<Vec<_>>();
+                    self.sql_pool
+                        .execute_unprepared(&format!("OPTIMIZE TABLE {}", tables.join(", ")))
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_database_size(&self) -> Result<i64> {
+        let backend = self.sql_pool.get_database_backend();
+        let query = match backend {
+            DatabaseBackend::Sqlite => {
+                "SELECT page_count * page_size AS size FROM pragma_page_count(), pragma_page_size()"
+            }
+            DatabaseBackend::Postgres => "SELECT pg_database_size(current_database()) AS size",
+            DatabaseBackend::MySql => {
+                "SELECT CAST(COALESCE(SUM(data_length + index_length), 0) AS SIGNED) AS size \
+                 FROM information_schema.tables WHERE table_schema = DATABASE()"
+            }
+        };
+        self.sql_pool
+            .query_one(Statement::from_string(backend, query.to_owned()))
+            .await?
+            .ok_or_else(|| {
+                DomainError::InternalError("Could not get the database size".to_owned())
+            })?
+            .try_get("", "size")
+            .map_err(DomainError::from)
+    }
+
+    async fn get_mysql_tables(&self) -> Result<Vec<String>> {
+        self.sql_pool
+            .query_all(Statement::from_string(
+                DatabaseBackend::MySql,
+                "SELECT table_name AS name FROM information_schema.tables \
+                 WHERE table_schema = DATABASE()"
+                    .to_owned(),
+            ))
+            .await?
+            .into_iter()
+            .map(|row| row.try_get("", "name").map_err(DomainError::from))
+            .collect()
+    }
+}
+
+/// An inconsistency in the database, usually left by a manual edit with the foreign keys disabled.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IntegrityIssue {
+    OrphanedMembership {
+        user_id: UserId,
+        group_id: GroupId,
+    },
+    OrphanedGroupManager {
+        user_id: UserId,
+        group_id: GroupId,
+    },
+    OrphanedUserAttribute {
+        user_id: UserId,
+        name: String,
+    },
+    OrphanedGroupAttribute {
+        group_id: GroupId,
+        name: String,
+    },
+    /// The attribute is not in the schema, or its value doesn't have the type of the schema.
+    InvalidUserAttribute {
+        user_id: UserId,
+        name: String,
+    },
+    InvalidGroupAttribute {
+        group_id: GroupId,
+        name: String,
+    },
+    /// The primary group is not one of the groups of the user.
+    InvalidPrimaryGroup {
+        user_id: UserId,
+        group_id: GroupId,
+    },
+    /// The OPAQUE password file can't be read: the user can't log in.
+    InvalidPasswordFile {
+        user_id: UserId,
+    },
+}
+
+impl std::fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OrphanedMembership { user_id, group_id } => write!(
+                f,
+                "Membership of '{}' in group {} refers to a missing user or group",
+                user_id, group_id.0
+            ),
+            Self::OrphanedGroupManager { user_id, group_id } => write!(
+                f,
+                "Manager '{}' of group {} refers to a missing user or group",
+                user_id, group_id.0
+            ),
+            Self::OrphanedUserAttribute { user_id, name } => write!(
+                f,
+                "Attribute '{}' belongs to the missing user '{}'",
+                name, user_id
+            ),
+            Self::OrphanedGroupAttribute { group_id, name } => write!(
+                f,
+                "Attribute '{}' belongs to the missing group {}",
+                name, group_id.0
+            ),
+            Self::InvalidUserAttribute { user_id, name } => write!(
+                f,
+                "Attribute '{}' of user '{}' doesn't match the user schema",
+                name, user_id
+            ),
+            Self::InvalidGroupAttribute { group_id, name } => write!(
+                f,
+                "Attribute '{}' of group {} doesn't match the group schema",
+                name, group_id.0
+            ),
+            Self::InvalidPrimaryGroup { user_id, group_id } => write!(
+                f,
+                "Primary group {} of '{}' is not one of their groups",
+                group_id.0, user_id
+            ),
+            Self::InvalidPasswordFile { user_id } => {
+                write!(f, "Password file of '{}' is unreadable", user_id)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub issues: Vec<IntegrityIssue>,
+    /// Not an inconsistency: these users just can't log in until a password is set.
+    pub users_without_password: Vec<UserId>,
+    /// Whether the issues were repaired.
+    pub fixed: bool,
+}
+
+impl IntegrityReport {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+fn is_valid_attribute_value(
+    value: &Serialized,
+    attribute_type: AttributeType,
+    is_list: bool,
+) -> bool {
+    match (attribute_type, is_list) {
+        (AttributeType::String, false) => value.convert_to::<String>().is_ok(),
+        (AttributeType::Integer, false) => value.convert_to::<i64>().is_ok(),
+        (AttributeType::JpegPhoto, false) => value.convert_to::<JpegPhoto>().is_ok(),
+        (AttributeType::DateTime, false) => value.convert_to::<chrono::NaiveDateTime>().is_ok(),
+        (AttributeType::String, true) => value.convert_to::<Vec<String>>().is_ok(),
+        (AttributeType::Integer, true) => value.convert_to::<Vec<i64>>().is_ok(),
+        (AttributeType::JpegPhoto, true) => value.convert_to::<Vec<JpegPhoto>>().is_ok(),
+        (AttributeType::DateTime, true) => value.convert_to::<Vec<chrono::NaiveDateTime>>().is_ok(),
+    }
+}
+
+impl SqlBackendHandler {
+    /// Checks the references between the tables, which the foreign keys should guarantee, and the
+    /// stored values. With `fix`, the invalid rows are deleted in a single transaction, the invalid
+    /// primary groups and password files are cleared.
+    #[instrument(skip(self), level = "debug", err)]
+    pub async fn check_integrity(&self, fix: bool) -> Result<IntegrityReport> {
+        let users = model::User::find().all(&self.sql_pool).await?;
+        let group_ids = model::Group::find()
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|g| g.group_id)
+            .collect::<HashSet<_>>();
+        let user_ids = users
+            .iter()
+            .map(|u| u.user_id.clone())
+            .collect::<HashSet<_>>();
+        let memberships = model::Membership::find().all(&self.sql_pool).await?;
+        let user_schema = model::UserAttributeSchema::find()
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|s| (s.attribute_name, (s.attribute_type, s.is_list)))
+            .collect::<HashMap<_, _>>();
+        let group_schema = model::GroupAttributeSchema::find()
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|s| (s.attribute_name, (s.attribute_type, s.is_list)))
+            .collect::<HashMap<_, _>>();
+        let matches_schema =
+            |schema: &HashMap<String, (AttributeType, bool)>, name: &String, value: &Serialized| {
+                schema
+                    .get(name)
+                    .map(|(attribute_type, is_list)| {
+                        is_valid_attribute_value(value, *attribute_type, *is_list)
+                    })
+                    .unwrap_or(false)
+            };
+
+        let mut report = IntegrityReport::default();
+        for m in &memberships {
+            if !user_ids.contains(&m.user_id) || !group_ids.contains(&m.group_id) {
+                report.issues.push(IntegrityIssue::OrphanedMembership {
+                    user_id: m.user_id.clone(),
+                    group_id: m.group_id,
+                });
+            }
+        }
+        for m in model::GroupManager::find().all(&self.sql_pool).await? {
+            if !user_ids.contains(&m.user_id) || !group_ids.contains(&m.group_id) {
+                report.issues.push(IntegrityIssue::OrphanedGroupManager {
+                    user_id: m.user_id,
+                    group_id: m.group_id,
+                });
+            }
+        }
+        for a in model::UserAttributes::find().all(&self.sql_pool).await? {
+            if !user_ids.contains(&a.user_id) {
+                report.issues.push(IntegrityIssue::OrphanedUserAttribute {
+                    user_id: a.user_id,
+                    name: a.attribute_name,
+                });
+            } else if !matches_schema(&user_schema, &a.attribute_name, &a.value) {
+                report.issues.push(IntegrityIssue::InvalidUserAttribute {
+                    user_id: a.user_id,
+                    name: a.attribute_name,
+                });
+            }
+        }
+        for a in model::GroupAttributes::find().all(&self.sql_pool).await? {
+            if !group_ids.contains(&a.group_id) {
+                report.issues.push(IntegrityIssue::OrphanedGroupAttribute {
+                    group_id: a.group_id,
+                    name: a.attribute_name,
+                });
+            } else if !matches_schema(&group_schema, &a.attribute_name, &a.value) {
+                report.issues.push(IntegrityIssue::InvalidGroupAttribute {
+                    group_id: a.group_id,
+                    name: a.attribute_name,
+                });
+            }
+        }
+        let valid_memberships = memberships
+            .iter()
+            .filter(|m| group_ids.contains(&m.group_id))
+            .map(|m| (&m.user_id, m.group_id))
+            .collect::<HashSet<_>>();
+        for user in &users {
+            if let Some(group_id) = user.primary_group_id {
+                if !valid_memberships.contains(&(&user.user_id, group_id)) {
+                    report.issues.push(IntegrityIssue::InvalidPrimaryGroup {
+                        user_id: user.user_id.clone(),
+                        group_id,
+                    });
+                }
+            }
+            match &user.password_hash {
+                None => report.users_without_password.push(user.user_id.clone()),
+                Some(bytes) => {
+                    if opaque::server::ServerRegistration::deserialize(bytes).is_err() {
+                        report.issues.push(IntegrityIssue::InvalidPasswordFile {
+                            user_id: user.user_id.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if fix && !report.issues.is_empty() {
+            self.fix_integrity_issues(report.issues.clone()).await?;
+            report.fixed = true;
+            info!("Repaired {} integrity issues", report.issues.len());
+        }
+        Ok(report)
+    }
+
+    async fn fix_integrity_issues(&self, issues: Vec<IntegrityIssue>) -> Result<()> {
+        self.sql_pool
+            .transaction::<_, (), DomainError>(|transaction| {
+                Box::pin(async move {
+                    for issue in issues {
+                        match issue {
+                            IntegrityIssue::OrphanedMembership { user_id, group_id } => {
+                                model::Membership::delete_by_id((user_id, group_id))
+                                    .exec(transaction)
+                                    .await?;
+                            }
+                            IntegrityIssue::OrphanedGroupManager { user_id, group_id } => {
+                                model::GroupManager::delete_by_id((group_id, user_id))
+                                    .exec(transaction)
+                                    .await?;
+                            }
+                            IntegrityIssue::OrphanedUserAttribute { user_id, name }
+                            | IntegrityIssue::InvalidUserAttribute { user_id, name } => {
+                                model::UserAttributes::delete_by_id((user_id, name))
+                                    .exec(transaction)
+                                    .await?;
+                            }
+                            IntegrityIssue::OrphanedGroupAttribute { group_id, name }
+                            | IntegrityIssue::InvalidGroupAttribute { group_id, name } => {
+                                model::GroupAttributes::delete_by_id((group_id, name))
+                                    .exec(transaction)
+                                    .await?;
+                            }
+                            IntegrityIssue::InvalidPrimaryGroup { user_id, .. } => {
+                                model::User::update_many()
+                                    .col_expr(
+                                        UserColumn::PrimaryGroupId,
+                                        Expr::value(Option::<GroupId>::None),
+                                    )
+                                    .filter(UserColumn::UserId.eq(user_id))
+                                    .exec(transaction)
+                                    .await?;
+                            }
+                            IntegrityIssue::InvalidPasswordFile { user_id } => {
+                                model::User::update_many()
+                                    .col_expr(
+                                        UserColumn::PasswordHash,
+                                        Expr::value(Option::<Vec<u8>>::None),
+                                    )
+                                    .filter(UserColumn::UserId.eq(user_id))
+                                    .exec(transaction)
+                                    .await?;
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MaintenanceHandler for SqlBackendHandler {
+    #[instrument(skip_all, level = "debug", ret, err)]
+    async fn compact_database(&self) -> Result<CompactionReport> {
+        self.acquire_compaction_lock().await?;
+        let result: Result<CompactionReport> = async {
+            let size_before = self.get_database_size().await?;
+            self.run_compaction().await?;
+            Ok(CompactionReport {
+                size_before,
+                size_after: self.get_database_size().await?,
+            })
+        }
+        .await;
+        if let Err(e) = self.release_compaction_lock().await {
+            warn!("Could not release the compaction lock: {:#}", e);
+        }
+        let report = result?;
+        info!(
+            "Compacted the database from {} to {} bytes",
+            report.size_before, report.size_after
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        handler::{UserBackendHandler, UserListerBackendHandler},
+        sql_backend_handler::tests::*,
+    };
+
+    #[tokio::test]
+    async fn test_compact_database() {
+        let fixture = TestFixture::new().await;
+        for i in 0..20 {
+            insert_user_no_password(&fixture.handler, &format!("user{}", i)).await;
+        }
+        for i in 0..20 {
+            fixture
+                .handler
+                .delete_user(&UserId::new(&format!("user{}", i)))
+                .await
+                .unwrap();
+        }
+        let users_before = fixture.handler.list_users(None, true).await.unwrap();
+        // Only one compaction at a time, across all the instances.
+        fixture.handler.acquire_compaction_lock().await.unwrap();
+        assert!(matches!(
+            fixture.handler.compact_database().await,
+            Err(DomainError::Conflict(_))
+        ));
+        fixture.handler.release_compaction_lock().await.unwrap();
+        let report = fixture.handler.compact_database().await.unwrap();
+        assert!(report.size_after <= report.size_before);
+        assert_eq!(report.reclaimed(), report.size_before - report.size_after);
+        // The data is intact.
+        assert_eq!(
+            fixture.handler.list_users(None, true).await.unwrap(),
+            users_before
+        );
+        let integrity = fixture
+            .handler
+            .sql_pool
+            .query_one(Statement::from_string(
+                DatabaseBackend::Sqlite,
+                "PRAGMA integrity_check".to_owned(),
+            ))
+            .await
+            .unwrap()
+            .unwrap()
+            .try_get::<String>("", "integrity_check")
+            .unwrap();
+        assert_eq!(integrity, "ok");
+        // The lock is released afterwards.
+        fixture.handler.acquire_compaction_lock().await.unwrap();
+        fixture.handler.release_compaction_lock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_compaction_lock() {
+        let fixture = TestFixture::new().await;
+        // Left by an instance that stopped mid-compaction.
+        let started = chrono::Utc::now().naive_utc()
+            - chrono::Duration::hours(COMPACTION_LOCK_TIMEOUT_HOURS + 1);
+        fixture
+            .handler
+            .sql_pool
+            .execute(
+                fixture.handler.sql_pool.get_database_backend().build(
+                    Query::update()
+                        .table(Metadata::Table)
+                        .value(Metadata::CompactionStarted, started),
+                ),
+            )
+            .await
+            .unwrap();
+        fixture.handler.compact_database().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_integrity() {
+        let fixture = TestFixture::new().await;
+        let bob = UserId::new("bob");
+        let group_id = fixture.groups[2];
+        insert_membership(&fixture.handler, group_id, "bob").await;
+        fixture
+            .handler
+            .set_primary_group(&bob, Some(group_id))
+            .await
+            .unwrap();
+        let report = fixture.handler.check_integrity(false).await.unwrap();
+        assert!(report.is_healthy(), "{:?}", report.issues);
+        assert!(report.users_without_password.contains(&bob));
+
+        // Delete the group behind the back of the foreign keys.
+        for query in [
+            "PRAGMA foreign_keys = OFF".to_owned(),
+            format!("DELETE FROM groups WHERE group_id = {}", group_id.0),
+            "PRAGMA foreign_keys = ON".to_owned(),
+        ] {
+            fixture
+                .handler
+                .sql_pool
+                .execute_unprepared(&query)
+                .await
+                .unwrap();
+        }
+        let expected_issues = vec![
+            IntegrityIssue::OrphanedMembership {
+                user_id: bob.clone(),
+                group_id,
+            },
+            IntegrityIssue::InvalidPrimaryGroup {
+                user_id: bob.clone(),
+                group_id,
+            },
+        ];
+        let report = fixture.handler.check_integrity(false).await.unwrap();
+        assert_eq!(report.issues, expected_issues);
+        assert!(!report.fixed);
+        // Without `fix`, nothing changes.
+        assert_eq!(
+            fixture.handler.check_integrity(false).await.unwrap().issues,
+            expected_issues
+        );
+
+        let report = fixture.handler.check_integrity(true).await.unwrap();
+        assert_eq!(report.issues, expected_issues);
+        assert!(report.fixed);
+        assert!(model::Membership::find_by_id((bob.clone(), group_id))
+            .one(&fixture.handler.sql_pool)
+            .await
+            .unwrap()
+            .is_none());
+        let user = fixture.handler.get_user_details(&bob).await.unwrap();
+        assert_eq!(user.primary_group, None);
+        assert!(fixture
+            .handler
+            .check_integrity(false)
+            .await
+            .unwrap()
+            .is_healthy());
+        // The valid memberships are kept.
+        assert!(model::Membership::find_by_id((bob, fixture.groups[0]))
+            .one(&fixture.handler.sql_pool)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_is_valid_attribute_value() {
+        let value = Serialized::from("value");
+        assert!(is_valid_attribute_value(
+            &value,
+            AttributeType::String,
+            false
+        ));
+        assert!(!is_valid_attribute_value(
+            &value,
+            AttributeType::String,
+            true
+        ));
+        assert!(!is_valid_attribute_value(
+            &Serialized::from(&1u8),
+            AttributeType::Integer,
+            false
+        ));
+        assert!(is_valid_attribute_value(
+            &Serialized::from(&42i64),
+            AttributeType::Integer,
+            false
+        ));
+    }
+}
@@ -5,7 +5,8 @@ use crate::domain::{
 use itertools::Itertools;
 use sea_orm::{
     sea_query::{
-        self, all, ColumnDef, Expr, ForeignKey, ForeignKeyAction, Func, Index, Query, Table, Value,
+        self, all, ColumnDef, Expr, ForeignKey, ForeignKeyAction, Func, Index, OnConflict, Query,
+        Table, Value,
     },
     ConnectionTrait, DatabaseTransaction, DbErr, FromQueryResult, Iden, Order, Statement,
     TransactionTrait,
@@ -27,6 +28,11 @@ pub enum Users {
     TotpSecret,
     MfaType,
     Uuid,
+    TokensValidAfter,
+    PrimaryGroupId,
+    ExpiryDate,
+    PasswordModifiedDate,
+    UniqueEmail,
 }
 
 #[derive(Iden, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
@@ -36,6 +42,10 @@ pub enum Groups {
     DisplayName,
     CreationDate,
     Uuid,
+    Email,
+    Description,
+    ModifiedDate,
+    UniqueDisplayName,
 }
 
 #[derive(Iden, Clone, Copy)]
@@ -45,6 +55,20 @@ pub enum Memberships {
     GroupId,
 }
 
+#[derive(Iden, Clone, Copy)]
+pub enum GroupManagers {
+    Table,
+    GroupId,
+    UserId,
+}
+
+#[derive(Iden, Clone, Copy)]
+pub enum GroupManagerGroups {
+    Table,
+    GroupId,
+    ManagerGroupId,
+}
+
 #[derive(Iden, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum UserAttributeSchema {
     Table,
@@ -54,6 +78,7 @@ pub enum UserAttributeSchema {
     UserAttributeSchemaIsUserVisible,
     UserAttributeSchemaIsUserEditable,
     UserAttributeSchemaIsHardcoded,
+    UserAttributeSchemaIsLdapVisible,
 }
 
 #[derive(Iden, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
@@ -73,6 +98,7 @@ pub enum GroupAttributeSchema {
     GroupAttributeSchemaIsGroupVisible,
     GroupAttributeSchemaIsGroupEditable,
     GroupAttributeSchemaIsHardcoded,
+    GroupAttributeSchemaIsLdapVisible,
 }
 
 #[derive(Iden, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy)]
@@ -89,6 +115,8 @@ pub enum Metadata {
     Table,
     // Which version of the schema we're at.
     Version,
+    // When the running database compaction started, if any.
+    CompactionStarted,
 }
 
 #[derive(FromQueryResult, PartialEq, Eq, Debug)]
@@ -875,6 +903,367 @@ async fn migrate_to_v5(transaction: DatabaseTransaction) -> Result<DatabaseTrans
     Ok(transaction)
 }
 
+async fn migrate_to_v6(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add optional mailing-list address and description to groups.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Groups::Table)
+                    .add_column(ColumnDef::new(Groups::Email).string_len(255)),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Groups::Table)
+                    .add_column(ColumnDef::new(Groups::Description).string_len(1024)),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v7(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the time before which the tokens of a user are rejected.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::TokensValidAfter).date_time()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v8(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add whether the attributes are returned over LDAP.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(UserAttributeSchema::Table).add_column(
+                    ColumnDef::new(UserAttributeSchema::UserAttributeSchemaIsLdapVisible)
+                        .boolean()
+                        .not_null()
+                        .default(true),
+                ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(GroupAttributeSchema::Table)
+                    .add_column(
+                        ColumnDef::new(GroupAttributeSchema::GroupAttributeSchemaIsLdapVisible)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v9(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the users that can manage the members of a group.
+    transaction
+        .execute(
+            builder.build(
+                Table::create()
+                    .table(GroupManagers::Table)
+                    .col(ColumnDef::new(GroupManagers::GroupId).integer().not_null())
+                    .col(
+                        ColumnDef::new(GroupManagers::UserId)
+                            .string_len(255)
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("GroupManagerGroupForeignKey")
+                            .from(GroupManagers::Table, GroupManagers::GroupId)
+                            .to(Groups::Table, Groups::GroupId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("GroupManagerUserForeignKey")
+                            .from(GroupManagers::Table, GroupManagers::UserId)
+                            .to(Users::Table, Users::UserId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(GroupManagers::GroupId)
+                            .col(GroupManagers::UserId),
+                    ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v10(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the primary group of the users.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::PrimaryGroupId).integer()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v11(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the last modification date of the groups, starting at their creation date.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter().table(Groups::Table).add_column(
+                    ColumnDef::new(Groups::ModifiedDate)
+                        .date_time()
+                        .not_null()
+                        .default(chrono::Utc::now().naive_utc()),
+                ),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Query::update()
+                    .table(Groups::Table)
+                    .value(Groups::ModifiedDate, Expr::col(Groups::CreationDate)),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v12(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The uniqueness of the emails depends on the `email_uniqueness` setting, it's checked when
+    // creating or updating users.
+    transaction
+        .execute(builder.build(Index::drop().name("unique-user-email").table(Users::Table)))
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Index::create()
+                    .if_not_exists()
+                    .name("user-email")
+                    .table(Users::Table)
+                    .col(Users::Email),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v13(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The preferred locale of the users, e.g. for the language of the emails. A custom attribute
+    // with the same name is reused, with its values.
+    transaction
+        .execute(
+            builder.build(
+                Query::insert()
+                    .into_table(UserAttributeSchema::Table)
+                    .columns([
+                        UserAttributeSchema::UserAttributeSchemaName,
+                        UserAttributeSchema::UserAttributeSchemaType,
+                        UserAttributeSchema::UserAttributeSchemaIsList,
+                        UserAttributeSchema::UserAttributeSchemaIsUserVisible,
+                        UserAttributeSchema::UserAttributeSchemaIsUserEditable,
+                        UserAttributeSchema::UserAttributeSchemaIsHardcoded,
+                    ])
+                    .values_panic([
+                        "locale".into(),
+                        AttributeType::String.into(),
+                        false.into(),
+                        true.into(),
+                        true.into(),
+                        true.into(),
+                    ])
+                    .on_conflict(
+                        OnConflict::column(UserAttributeSchema::UserAttributeSchemaName)
+                            .update_column(UserAttributeSchema::UserAttributeSchemaIsHardcoded)
+                            .to_owned(),
+                    ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v14(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the date after which the users can't log in anymore, e.g. for the contractors.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::ExpiryDate).date_time()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v15(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the date of the last password change. It's unknown for the existing passwords.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::PasswordModifiedDate).date_time()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v16(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The lowercase email of the users when the `email_uniqueness` setting enforces it, null
+    // otherwise, so that the database rejects the duplicates even if two requests race. It's
+    // filled on startup, according to the setting.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::UniqueEmail).string_len(255)),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Index::create()
+                    .if_not_exists()
+                    .name("unique-user-unique-email")
+                    .table(Users::Table)
+                    .col(Users::UniqueEmail)
+                    .unique(),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v17(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // Add the groups whose members can manage the members of a group.
+    transaction
+        .execute(
+            builder.build(
+                Table::create()
+                    .table(GroupManagerGroups::Table)
+                    .col(
+                        ColumnDef::new(GroupManagerGroups::GroupId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(GroupManagerGroups::ManagerGroupId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("GroupManagerGroupsGroupForeignKey")
+                            .from(GroupManagerGroups::Table, GroupManagerGroups::GroupId)
+                            .to(Groups::Table, Groups::GroupId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("GroupManagerGroupsManagerForeignKey")
+                            .from(
+                                GroupManagerGroups::Table,
+                                GroupManagerGroups::ManagerGroupId,
+                            )
+                            .to(Groups::Table, Groups::GroupId)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(GroupManagerGroups::GroupId)
+                            .col(GroupManagerGroups::ManagerGroupId),
+                    ),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v18(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The lowercase name of the groups when `group_name_uniqueness` is case-insensitive, null
+    // otherwise, like the unique emails. It's filled on startup, according to the setting.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Groups::Table)
+                    .add_column(ColumnDef::new(Groups::UniqueDisplayName).string_len(255)),
+            ),
+        )
+        .await?;
+    transaction
+        .execute(
+            builder.build(
+                Index::create()
+                    .if_not_exists()
+                    .name("unique-group-unique-display-name")
+                    .table(Groups::Table)
+                    .col(Groups::UniqueDisplayName)
+                    .unique(),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
+async fn migrate_to_v19(transaction: DatabaseTransaction) -> Result<DatabaseTransaction, DbErr> {
+    let builder = transaction.get_database_backend();
+    // The compaction lock, shared by all the instances using the database.
+    transaction
+        .execute(
+            builder.build(
+                Table::alter()
+                    .table(Metadata::Table)
+                    .add_column(ColumnDef::new(Metadata::CompactionStarted).date_time()),
+            ),
+        )
+        .await?;
+    Ok(transaction)
+}
+
 // This is needed to make an array of async functions.
 macro_rules! to_sync {
     ($l:ident) => {
@@ -900,6 +1289,20 @@ pub async fn migrate_from_version(
         to_sync!(migrate_to_v3),
         to_sync!(migrate_to_v4),
         to_sync!(migrate_to_v5),
+        to_sync!(migrate_to_v6),
+        to_sync!(migrate_to_v7),
+        to_sync!(migrate_to_v8),
+        to_sync!(migrate_to_v9),
+        to_sync!(migrate_to_v10),
+        to_sync!(migrate_to_v11),
+        to_sync!(migrate_to_v12),
+        to_sync!(migrate_to_v13),
+        to_sync!(migrate_to_v14),
+        to_sync!(migrate_to_v15),
+        to_sync!(migrate_to_v16),
+        to_sync!(migrate_to_v17),
+        to_sync!(migrate_to_v18),
+        to_sync!(migrate_to_v19),
     ];
     assert_eq!(migrations.len(), (LAST_SCHEMA_VERSION.0 - 1) as usize);
     for migration in 2..=last_version.0 {
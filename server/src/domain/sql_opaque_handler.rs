@@ -49,16 +49,29 @@ impl SqlBackendHandler {
         )?)
     }
 
+    /// The users past their expiry date have no usable password.
     #[instrument(skip_all, level = "debug", err)]
     async fn get_password_file_for_user(&self, user_id: UserId) -> Result<Option<Vec<u8>>> {
         // Fetch the previously registered password file from the DB.
-        Ok(model::User::find_by_id(user_id)
+        let (password_hash, expiry_date) = match model::User::find_by_id(user_id.clone())
             .select_only()
             .column(UserColumn::PasswordHash)
-            .into_tuple::<(Option<Vec<u8>>,)>()
+            .column(UserColumn::ExpiryDate)
+            .into_tuple::<(Option<Vec<u8>>, Option<chrono::NaiveDateTime>)>()
             .one(&self.sql_pool)
             .await?
-            .and_then(|u| u.0))
+        {
+            None => return Ok(None),
+            Some(u) => u,
+        };
+        if expiry_date
+            .map(|date| date <= chrono::Utc::now().naive_utc())
+            .unwrap_or(false)
+        {
+            debug!(r#"The account of "{}" has expired"#, &user_id);
+            return Ok(None);
+        }
+        Ok(password_hash)
     }
 }
 
@@ -193,17 +206,31 @@ impl OpaqueHandler for SqlOpaqueHandler {
         let user_update = model::users::ActiveModel {
             user_id: ActiveValue::Set(UserId::new(&username)),
             password_hash: ActiveValue::Set(Some(password_file.serialize())),
+            password_modified_date: ActiveValue::Set(Some(chrono::Utc::now().naive_utc())),
             ..Default::default()
         };
         user_update.update(&self.sql_pool).await?;
         Ok(())
     }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn clear_password(&self, user_id: &UserId) -> Result<()> {
+        model::users::ActiveModel {
+            user_id: ActiveValue::Set(user_id.clone()),
+            password_hash: ActiveValue::Set(None),
+            password_modified_date: ActiveValue::Set(None),
+            ..Default::default()
+        }
+        .update(&self.sql_pool)
+        .await?;
+        Ok(())
+    }
 }
 
 /// Convenience function to set a user's password.
 #[instrument(skip_all, level = "debug", err)]
-pub(crate) async fn register_password(
-    opaque_handler: &SqlOpaqueHandler,
+pub async fn register_password(
+    opaque_handler: &impl OpaqueHandler,
     username: &UserId,
     password: &SecUtf8,
 ) -> Result<()> {
@@ -231,12 +258,12 @@ pub(crate) async fn register_password(
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::domain::sql_backend_handler::tests::*;
 
-    async fn attempt_login(
-        opaque_handler: &SqlOpaqueHandler,
+    pub async fn attempt_login(
+        opaque_handler: &impl OpaqueHandler,
         username: &str,
         password: &str,
     ) -> Result<()> {
@@ -287,6 +314,104 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_clear_password() -> Result<()> {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlOpaqueHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        attempt_login(&handler, "bob", "bob00").await?;
+        handler.clear_password(&UserId::new("bob")).await?;
+        attempt_login(&handler, "bob", "bob00").await.unwrap_err();
+        handler
+            .clear_password(&UserId::new("patrick"))
+            .await
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_password_modified_date() -> Result<()> {
+        use crate::domain::handler::UserBackendHandler;
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlOpaqueHandler::new(get_default_config(), sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        let bob = UserId::new("bob");
+        assert_eq!(
+            handler.get_user_details(&bob).await?.password_modified_date,
+            None
+        );
+        let before = chrono::Utc::now().naive_utc();
+        register_password(&handler, &bob, &secstr::SecUtf8::from("bob00")).await?;
+        let first_change = handler
+            .get_user_details(&bob)
+            .await?
+            .password_modified_date
+            .unwrap();
+        assert!(first_change >= before);
+
+        // Backdate it, to check that the next change updates it.
+        let old_date = before - chrono::Duration::days(30);
+        model::users::ActiveModel {
+            user_id: ActiveValue::Set(bob.clone()),
+            password_modified_date: ActiveValue::Set(Some(old_date)),
+            ..Default::default()
+        }
+        .update(&sql_pool)
+        .await?;
+        register_password(&handler, &bob, &secstr::SecUtf8::from("bob01")).await?;
+        assert!(
+            handler
+                .get_user_details(&bob)
+                .await?
+                .password_modified_date
+                .unwrap()
+                >= before
+        );
+
+        handler.clear_password(&bob).await?;
+        assert_eq!(
+            handler.get_user_details(&bob).await?.password_modified_date,
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_account() -> Result<()> {
+        use crate::domain::handler::UserBackendHandler;
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlOpaqueHandler::new(get_default_config(), sql_pool);
+        insert_user(&handler, "bob", "bob00").await;
+        let bob = UserId::new("bob");
+        let bind = || {
+            handler.bind(BindRequest {
+                name: bob.clone(),
+                password: "bob00".to_string(),
+            })
+        };
+        let now = chrono::Utc::now().naive_utc();
+        handler
+            .set_user_expiry_date(&bob, Some(now + chrono::Duration::days(1)))
+            .await?;
+        bind().await?;
+        attempt_login(&handler, "bob", "bob00").await?;
+
+        handler
+            .set_user_expiry_date(&bob, Some(now - chrono::Duration::seconds(1)))
+            .await?;
+        bind().await.unwrap_err();
+        attempt_login(&handler, "bob", "bob00").await.unwrap_err();
+
+        handler.set_user_expiry_date(&bob, None).await?;
+        bind().await?;
+        attempt_login(&handler, "bob", "bob00").await?;
+        handler
+            .set_user_expiry_date(&UserId::new("patrick"), None)
+            .await
+            .unwrap_err();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_bind_user() {
         let sql_pool = get_initialized_db().await;
@@ -1,4 +1,5 @@
 use crate::domain::{
+    derived_attributes::get_derived_attributes_schema,
     error::Result,
     handler::{AttributeSchema, Schema, SchemaBackendHandler},
     model,
@@ -25,12 +26,27 @@ impl SchemaBackendHandler for SqlBackendHandler {
 
 impl SqlBackendHandler {
     async fn get_user_attributes(&self) -> Result<Vec<AttributeSchema>> {
-        Ok(model::UserAttributeSchema::find()
+        let derived_attributes = &self.config.derived_user_attributes;
+        let stored_attributes = model::UserAttributeSchema::find()
             .order_by_asc(model::UserAttributeSchemaColumn::AttributeName)
             .all(&self.sql_pool)
             .await?
             .into_iter()
-            .map(|m| m.into())
+            .map(AttributeSchema::from)
+            .collect::<Vec<_>>();
+        Ok(stored_attributes
+            .iter()
+            // Derived attributes take precedence over the stored ones.
+            .filter(|a| {
+                !derived_attributes
+                    .iter()
+                    .any(|d| d.name.eq_ignore_ascii_case(&a.name))
+            })
+            .cloned()
+            .chain(get_derived_attributes_schema(
+                derived_attributes,
+                &stored_attributes,
+            ))
             .collect())
     }
 
@@ -66,6 +82,7 @@ mod tests {
                             is_list: false,
                             is_visible: true,
                             is_editable: true,
+                            is_ldap_visible: true,
                             is_hardcoded: true,
                         },
                         AttributeSchema {
@@ -74,6 +91,7 @@ mod tests {
                             is_list: false,
                             is_visible: true,
                             is_editable: true,
+                            is_ldap_visible: true,
                             is_hardcoded: true,
                         },
                         AttributeSchema {
@@ -82,6 +100,16 @@ mod tests {
                             is_list: false,
                             is_visible: true,
                             is_editable: true,
+                            is_ldap_visible: true,
+                            is_hardcoded: true,
+                        },
+                        AttributeSchema {
+                            name: "locale".to_owned(),
+                            attribute_type: AttributeType::String,
+                            is_list: false,
+                            is_visible: true,
+                            is_editable: true,
+                            is_ldap_visible: true,
                             is_hardcoded: true,
                         }
                     ]
@@ -1,8 +1,12 @@
 use super::sql_migrations::{get_schema_version, migrate_from_version, upgrade_to_v1};
 use sea_orm::Value;
+use tracing::warn;
 
 pub type DbConnection = sea_orm::DatabaseConnection;
 
+/// The longest wait between two connection attempts.
+const MAX_CONNECTION_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+
 #[derive(Copy, PartialEq, Eq, Debug, Clone, PartialOrd, Ord)]
 pub struct SchemaVersion(pub i16);
 
@@ -21,7 +25,33 @@ impl From<SchemaVersion> for Value {
     }
 }
 
-pub const LAST_SCHEMA_VERSION: SchemaVersion = SchemaVersion(5);
+pub const LAST_SCHEMA_VERSION: SchemaVersion = SchemaVersion(19);
+
+/// Connects to the database, e.g. while it's still starting: the wait after a failed attempt is
+/// doubled each time, up to a minute.
+pub async fn connect_with_retry(
+    options: sea_orm::ConnectOptions,
+    attempts: u32,
+    initial_backoff: std::time::Duration,
+) -> Result<DbConnection, sea_orm::DbErr> {
+    let mut backoff = initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match sea_orm::Database::connect(options.clone()).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) if attempt < attempts => {
+                warn!(
+                    "Could not connect to the database (attempt {}/{}), retrying in {:?}: {}",
+                    attempt, attempts, backoff, e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, MAX_CONNECTION_BACKOFF);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
 
 pub async fn init_table(pool: &DbConnection) -> anyhow::Result<()> {
     let version = {
@@ -36,6 +66,26 @@ pub async fn init_table(pool: &DbConnection) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The changes that `init_table` would make to the database, without applying them.
+pub async fn get_init_plan(pool: &DbConnection) -> anyhow::Result<Vec<String>> {
+    let mut plan = Vec::new();
+    let version = match get_schema_version(pool).await {
+        Some(version) => version,
+        None => {
+            plan.push("Create the tables, at schema version 1".to_owned());
+            SchemaVersion(1)
+        }
+    };
+    if version > LAST_SCHEMA_VERSION {
+        anyhow::bail!("DB version downgrading is not supported");
+    }
+    plan.extend(
+        (version.0 + 1..=LAST_SCHEMA_VERSION.0)
+            .map(|v| format!("Upgrade the schema from version {} to {}", v - 1, v)),
+    );
+    Ok(plan)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::domain::{
@@ -107,6 +157,22 @@ mod tests {
         init_table(&sql_pool).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_init_plan() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        let plan = get_init_plan(&sql_pool).await.unwrap();
+        assert_eq!(plan.len(), LAST_SCHEMA_VERSION.0 as usize);
+        assert_eq!(plan[0], "Create the tables, at schema version 1");
+        assert_eq!(plan[1], "Upgrade the schema from version 1 to 2");
+        // The database is left untouched.
+        assert_eq!(get_schema_version(&sql_pool).await, None);
+        assert_eq!(get_init_plan(&sql_pool).await.unwrap(), plan);
+
+        init_table(&sql_pool).await.unwrap();
+        assert!(get_init_plan(&sql_pool).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_migrate_tables() {
         crate::infra::logging::init_for_tests();
@@ -387,6 +453,123 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_migration_to_v6() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(5))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO groups (group_id, display_name, creation_date, uuid)
+                       VALUES (1, "group1", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04")"#,
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(5), SchemaVersion(6))
+            .await
+            .unwrap();
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        pub struct GroupV6 {
+            display_name: String,
+            email: Option<String>,
+            description: Option<String>,
+        }
+        assert_eq!(
+            GroupV6::find_by_statement(raw_statement(
+                r#"SELECT display_name, email, description FROM groups"#
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![GroupV6 {
+                display_name: "group1".to_owned(),
+                email: None,
+                description: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_v7() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(6))
+            .await
+            .unwrap();
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO users (user_id, email, creation_date, uuid)
+                       VALUES ("bob", "bob@bob.com", "1970-01-01 00:00:00", "a02eaf13-48a7-30f6-a3d4-040ff7c52b04")"#,
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(6), SchemaVersion(7))
+            .await
+            .unwrap();
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        pub struct UserV7 {
+            user_id: String,
+            tokens_valid_after: Option<chrono::NaiveDateTime>,
+        }
+        assert_eq!(
+            UserV7::find_by_statement(raw_statement(
+                r#"SELECT user_id, tokens_valid_after FROM users"#
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![UserV7 {
+                user_id: "bob".to_owned(),
+                tokens_valid_after: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_v13() {
+        crate::infra::logging::init_for_tests();
+        let sql_pool = get_in_memory_db().await;
+        upgrade_to_v1(&sql_pool).await.unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(1), SchemaVersion(12))
+            .await
+            .unwrap();
+        // A custom attribute with the same name as the new hardcoded one.
+        sql_pool
+            .execute(raw_statement(
+                r#"INSERT INTO user_attribute_schema (user_attribute_schema_name, user_attribute_schema_type, user_attribute_schema_is_list, user_attribute_schema_is_user_visible, user_attribute_schema_is_user_editable, user_attribute_schema_is_hardcoded)
+                       VALUES ("locale", "String", false, false, false, false)"#,
+            ))
+            .await
+            .unwrap();
+        migrate_from_version(&sql_pool, SchemaVersion(12), SchemaVersion(13))
+            .await
+            .unwrap();
+        #[derive(FromQueryResult, PartialEq, Eq, Debug)]
+        pub struct AttributeV13 {
+            user_attribute_schema_name: String,
+            user_attribute_schema_is_user_visible: bool,
+            user_attribute_schema_is_hardcoded: bool,
+        }
+        assert_eq!(
+            AttributeV13::find_by_statement(raw_statement(
+                r#"SELECT user_attribute_schema_name, user_attribute_schema_is_user_visible, user_attribute_schema_is_hardcoded
+                     FROM user_attribute_schema WHERE user_attribute_schema_name = "locale""#
+            ))
+            .all(&sql_pool)
+            .await
+            .unwrap(),
+            vec![AttributeV13 {
+                user_attribute_schema_name: "locale".to_owned(),
+                user_attribute_schema_is_user_visible: false,
+                user_attribute_schema_is_hardcoded: true,
+            }]
+        );
+    }
+
     #[tokio::test]
     async fn test_too_high_version() {
         let sql_pool = get_in_memory_db().await;
@@ -403,6 +586,28 @@ mod tests {
             ))
             .await
             .unwrap();
+        assert!(get_init_plan(&sql_pool).await.is_err());
         assert!(init_table(&sql_pool).await.is_err());
     }
+
+    #[tokio::test]
+    async fn test_connect_with_retry() {
+        let path = std::env::temp_dir().join(format!("lldap_retry_{}.db", uuid::Uuid::new_v4()));
+        // Without `c` in the mode, the database doesn't exist until the file is created.
+        let options = sea_orm::ConnectOptions::new(format!("sqlite://{}?mode=rw", path.display()));
+        let backoff = std::time::Duration::from_millis(50);
+        connect_with_retry(options.clone(), 1, backoff)
+            .await
+            .unwrap_err();
+        let database_start = {
+            let path = path.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                std::fs::File::create(&path).unwrap();
+            }
+        };
+        let (pool, ()) = tokio::join!(connect_with_retry(options, 10, backoff), database_start);
+        init_table(&pool.unwrap()).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
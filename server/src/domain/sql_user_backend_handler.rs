@@ -1,23 +1,27 @@
 use crate::domain::{
+    derived_attributes::{add_derived_attributes, apply_display_name_fallback},
     error::{DomainError, Result},
     handler::{
-        CreateUserRequest, UpdateUserRequest, UserBackendHandler, UserListerBackendHandler,
-        UserRequestFilter,
+        AttributeList, CreateUserRequest, SchemaBackendHandler, UpdateAttributeRequest,
+        UpdateUserRequest, UserBackendHandler, UserListerBackendHandler, UserRequestFilter,
     },
-    model::{self, GroupColumn, UserColumn},
-    sql_backend_handler::SqlBackendHandler,
+    model::{self, GroupColumn, MembershipColumn, UserAttributeSchemaColumn, UserColumn},
+    sql_backend_handler::{check_name_length, lock_for_limit_check, SqlBackendHandler},
     types::{AttributeValue, GroupDetails, GroupId, Serialized, User, UserAndGroups, UserId, Uuid},
 };
+use crate::infra::configuration::EmailUniqueness;
 use async_trait::async_trait;
+use itertools::Itertools;
 use sea_orm::{
     sea_query::{
         query::OnConflict, Alias, Cond, Expr, Func, IntoColumnRef, IntoCondition, SimpleExpr,
     },
-    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveValue, ModelTrait,
-    QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set, TransactionTrait,
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveValue,
+    ModelTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set,
+    TransactionTrait,
 };
-use std::collections::HashSet;
-use tracing::{debug, instrument};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, instrument, warn};
 
 fn attribute_condition(name: String, value: String) -> Cond {
     Expr::in_subquery(
@@ -32,34 +36,42 @@ fn attribute_condition(name: String, value: String) -> Cond {
     .into_condition()
 }
 
-fn get_user_filter_expr(filter: UserRequestFilter) -> Cond {
+/// Fails with `InvalidInput` if the filter is not supported by the DB.
+fn get_user_filter_expr(filter: UserRequestFilter) -> Result<Cond> {
     use UserRequestFilter::*;
     let group_table = Alias::new("r1");
     fn get_repeated_filter(
         fs: Vec<UserRequestFilter>,
         condition: Cond,
         default_value: bool,
-    ) -> Cond {
+    ) -> Result<Cond> {
         if fs.is_empty() {
-            SimpleExpr::Value(default_value.into()).into_condition()
+            Ok(SimpleExpr::Value(default_value.into()).into_condition())
         } else {
             fs.into_iter()
                 .map(get_user_filter_expr)
-                .fold(condition, Cond::add)
+                .try_fold(condition, |condition, f| Ok(condition.add(f?)))
         }
     }
-    match filter {
-        And(fs) => get_repeated_filter(fs, Cond::all(), true),
-        Or(fs) => get_repeated_filter(fs, Cond::any(), false),
-        Not(f) => get_user_filter_expr(*f).not(),
+    Ok(match filter {
+        And(fs) => get_repeated_filter(fs, Cond::all(), true)?,
+        Or(fs) => get_repeated_filter(fs, Cond::any(), false)?,
+        Not(f) => get_user_filter_expr(*f)?.not(),
         UserId(user_id) => ColumnTrait::eq(&UserColumn::UserId, user_id).into_condition(),
         Equality(s1, s2) => {
             if s1 == UserColumn::UserId {
-                panic!("User id should be wrapped")
+                return Err(DomainError::InvalidInput(
+                    "User ids should be filtered with UserId".to_owned(),
+                ));
             } else {
                 ColumnTrait::eq(&s1, s2).into_condition()
             }
         }
+        EqualityIgnoreCase(s1, s2) => {
+            SimpleExpr::FunctionCall(Func::lower(Expr::col(s1.as_column_ref())))
+                .eq(s2.to_lowercase())
+                .into_condition()
+        }
         AttributeEquality(s1, s2) => attribute_condition(s1, s2),
         MemberOf(group) => Expr::col((group_table, GroupColumn::DisplayName))
             .eq(group)
@@ -67,14 +79,148 @@ fn get_user_filter_expr(filter: UserRequestFilter) -> Cond {
         MemberOfId(group_id) => Expr::col((group_table, GroupColumn::GroupId))
             .eq(group_id)
             .into_condition(),
-        UserIdSubString(filter) => UserColumn::UserId
-            .like(&filter.to_sql_filter())
+        UserIdSubString(filter) => Expr::col(UserColumn::UserId.as_column_ref())
+            .like(filter.to_sql_like_expr())
             .into_condition(),
         SubString(col, filter) => {
             SimpleExpr::FunctionCall(Func::lower(Expr::col(col.as_column_ref())))
-                .like(filter.to_sql_filter())
+                .like(filter.to_sql_like_expr())
                 .into_condition()
         }
+        AttributePresent(name) => Expr::in_subquery(
+            Expr::col(UserColumn::UserId.as_column_ref()),
+            model::UserAttributes::find()
+                .select_only()
+                .column(model::UserAttributesColumn::UserId)
+                .filter(model::UserAttributesColumn::AttributeName.eq(name))
+                .into_query(),
+        )
+        .into_condition(),
+        AttributeSubString(name, _) => {
+            return Err(DomainError::InvalidInput(format!(
+                "Unresolved substring filter on the attribute {}",
+                name
+            )))
+        }
+    })
+}
+
+fn get_substring_attribute_names(filter: &UserRequestFilter, names: &mut HashSet<String>) {
+    use UserRequestFilter::*;
+    match filter {
+        And(fs) | Or(fs) => fs
+            .iter()
+            .for_each(|f| get_substring_attribute_names(f, names)),
+        Not(f) => get_substring_attribute_names(f, names),
+        AttributeSubString(name, _) => {
+            names.insert(name.clone());
+        }
+        _ => (),
+    }
+}
+
+// The attribute values are serialized, so the DB can't match substrings in them: we replace the
+// filter with the list of matching users.
+fn resolve_attribute_substring_filters(
+    filter: UserRequestFilter,
+    values: &HashMap<String, Vec<(UserId, String)>>,
+) -> UserRequestFilter {
+    use UserRequestFilter::*;
+    let rec = |f| resolve_attribute_substring_filters(f, values);
+    match filter {
+        And(fs) => And(fs.into_iter().map(rec).collect()),
+        Or(fs) => Or(fs.into_iter().map(rec).collect()),
+        Not(f) => Not(Box::new(rec(*f))),
+        AttributeSubString(name, filter) => Or(values
+            .get(&name)
+            .into_iter()
+            .flatten()
+            .filter(|(_, value)| filter.matches(value))
+            .map(|(user_id, _)| UserId(user_id.clone()))
+            .collect()),
+        f => f,
+    }
+}
+
+impl SqlBackendHandler {
+    /// Fills or clears the `unique_email` column of all the users according to the
+    /// `email_uniqueness` setting, which can change between two runs. Fails if the uniqueness is
+    /// enforced but some users share an email.
+    #[instrument(skip_all, level = "debug", err)]
+    pub async fn apply_email_uniqueness_policy(&self) -> Result<()> {
+        let policy = self.config.email_uniqueness;
+        let unique_email = match policy {
+            EmailUniqueness::Enforce => {
+                SimpleExpr::FunctionCall(Func::lower(Expr::col(UserColumn::Email)))
+            }
+            _ => Expr::value(Option::<String>::None),
+        };
+        model::User::update_many()
+            .col_expr(UserColumn::UniqueEmail, unique_email)
+            .exec(&self.sql_pool)
+            .await
+            .map_err(|e| match policy {
+                EmailUniqueness::Enforce => DomainError::Conflict(format!(
+                    "Cannot enforce the uniqueness of the emails, some users may share one \
+                     (ignoring the case): {}",
+                    e
+                )),
+                _ => e.into(),
+            })?;
+        Ok(())
+    }
+
+    async fn resolve_user_filter(&self, filter: UserRequestFilter) -> Result<UserRequestFilter> {
+        let mut names = HashSet::new();
+        get_substring_attribute_names(&filter, &mut names);
+        if names.is_empty() {
+            return Ok(filter);
+        }
+        let mut values = HashMap::<String, Vec<(UserId, String)>>::new();
+        for attribute in model::UserAttributes::find()
+            .filter(model::UserAttributesColumn::AttributeName.is_in(names))
+            .all(&self.sql_pool)
+            .await?
+        {
+            if let Ok(value) = attribute.value.convert_to::<String>() {
+                values
+                    .entry(attribute.attribute_name)
+                    .or_default()
+                    .push((attribute.user_id, value));
+            }
+        }
+        Ok(resolve_attribute_substring_filters(filter, &values))
+    }
+
+    async fn compute_derived_attributes<'a>(
+        &self,
+        users: impl IntoIterator<Item = &'a mut User>,
+    ) -> Result<()> {
+        let derived_attributes = &self.config.derived_user_attributes;
+        let display_name_fallback = &self.config.display_name_fallback;
+        if derived_attributes.is_empty() && display_name_fallback.is_empty() {
+            return Ok(());
+        }
+        let schema = self.get_schema().await?.user_attributes;
+        // The display name is shown everywhere: it is only built from the attributes that are.
+        let public_schema = AttributeList {
+            attributes: schema
+                .attributes
+                .iter()
+                .filter(|a| a.is_visible && a.is_ldap_visible)
+                .cloned()
+                .collect(),
+        };
+        for user in users {
+            apply_display_name_fallback(
+                user,
+                display_name_fallback,
+                derived_attributes,
+                &public_schema,
+            );
+            add_derived_attributes(user, derived_attributes, &schema);
+        }
+        Ok(())
     }
 }
 
@@ -89,8 +235,57 @@ fn to_value(opt_name: &Option<String>) -> ActiveValue<Option<String>> {
     }
 }
 
+/// The value of the `unique_email` column: the unique index only applies when the uniqueness is
+/// enforced.
+fn get_unique_email(policy: EmailUniqueness, email: &str) -> Option<String> {
+    (policy == EmailUniqueness::Enforce).then(|| email.to_lowercase())
+}
+
+/// Applies the `email_uniqueness` policy before giving `email` to `user_id`, case-insensitively
+/// like the unique index. A user can always keep their own email.
+async fn check_email_uniqueness(
+    connection: &impl ConnectionTrait,
+    policy: EmailUniqueness,
+    user_id: &UserId,
+    email: &str,
+) -> Result<()> {
+    if policy == EmailUniqueness::None {
+        return Ok(());
+    }
+    let other_users = model::User::find()
+        .filter(
+            SimpleExpr::FunctionCall(Func::lower(Expr::col(UserColumn::Email.as_column_ref())))
+                .eq(email.to_lowercase()),
+        )
+        .filter(UserColumn::UserId.ne(user_id))
+        .all(connection)
+        .await?;
+    if other_users.is_empty() {
+        return Ok(());
+    }
+    match policy {
+        EmailUniqueness::Enforce => Err(DomainError::Conflict(format!(
+            "The email '{}' is already used by another user",
+            email
+        ))),
+        _ => {
+            warn!(
+                "User '{}' has the same email as other users: {}",
+                user_id,
+                other_users.iter().map(|u| u.user_id.as_str()).join(", ")
+            );
+            Ok(())
+        }
+    }
+}
+
 #[async_trait]
 impl UserListerBackendHandler for SqlBackendHandler {
+    #[instrument(skip_all, level = "debug", ret, err)]
+    async fn count_users(&self) -> Result<u64> {
+        Ok(model::User::find().count(&self.sql_pool).await?)
+    }
+
     #[instrument(skip_all, level = "debug", ret, err)]
     async fn list_users(
         &self,
@@ -99,6 +294,10 @@ impl UserListerBackendHandler for SqlBackendHandler {
         _get_groups: bool,
     ) -> Result<Vec<UserAndGroups>> {
         debug!(?filters);
+        let filters = match filters {
+            Some(f) => Some(get_user_filter_expr(self.resolve_user_filter(f).await?)?),
+            None => None,
+        };
         let results = model::User::find()
             .filter(
                 filters
@@ -109,7 +308,7 @@ impl UserListerBackendHandler for SqlBackendHandler {
                                     .find_also_linked(model::memberships::UserToGroup)
                                     .select_only()
                                     .column(UserColumn::UserId)
-                                    .filter(get_user_filter_expr(f))
+                                    .filter(f)
                                     .into_query(),
                             )
                             .into_condition()
@@ -166,6 +365,8 @@ impl UserListerBackendHandler for SqlBackendHandler {
                 .map(AttributeValue::from)
                 .collect();
         }
+        self.compute_derived_attributes(users.iter_mut().map(|u| &mut u.user))
+            .await?;
         Ok(users)
     }
 }
@@ -187,6 +388,8 @@ impl UserBackendHandler for SqlBackendHandler {
             .all(&self.sql_pool)
             .await?;
         user.attributes = attributes.into_iter().map(AttributeValue::from).collect();
+        self.compute_derived_attributes(std::iter::once(&mut user))
+            .await?;
         Ok(user)
     }
 
@@ -208,10 +411,22 @@ impl UserBackendHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug", err)]
     async fn create_user(&self, request: CreateUserRequest) -> Result<()> {
         debug!(user_id = ?request.user_id);
+        if request.user_id != self.config.ldap_user_dn {
+            check_name_length(
+                "user ID",
+                request.user_id.as_str(),
+                self.config.username_min_length,
+                self.config.username_max_length,
+            )?;
+        }
+        let max_users = self.config.max_users.filter(|max| *max > 0);
+        let email_uniqueness = self.config.email_uniqueness;
+        let (user_id, email) = (request.user_id.clone(), request.email.clone());
         let now = chrono::Utc::now().naive_utc();
         let uuid = Uuid::from_name_and_date(request.user_id.as_str(), &now);
         let new_user = model::users::ActiveModel {
             user_id: Set(request.user_id.clone()),
+            unique_email: Set(get_unique_email(email_uniqueness, &request.email)),
             email: Set(request.email),
             display_name: to_value(&request.display_name),
             creation_date: ActiveValue::Set(now),
@@ -233,6 +448,13 @@ impl UserBackendHandler for SqlBackendHandler {
                 value: Set(Serialized::from(&last_name)),
             });
         }
+        if let Some(locale) = request.locale {
+            new_user_attributes.push(model::user_attributes::ActiveModel {
+                user_id: Set(request.user_id.clone()),
+                attribute_name: Set("locale".to_owned()),
+                value: Set(Serialized::from(&locale)),
+            });
+        }
         if let Some(avatar) = request.avatar {
             new_user_attributes.push(model::user_attributes::ActiveModel {
                 user_id: Set(request.user_id),
@@ -243,6 +465,16 @@ impl UserBackendHandler for SqlBackendHandler {
         self.sql_pool
             .transaction::<_, (), DomainError>(|transaction| {
                 Box::pin(async move {
+                    if let Some(max_users) = max_users {
+                        lock_for_limit_check(transaction).await?;
+                        if model::User::find().count(transaction).await? >= max_users {
+                            return Err(DomainError::LimitExceeded(format!(
+                                "Cannot create more than {} users",
+                                max_users
+                            )));
+                        }
+                    }
+                    check_email_uniqueness(transaction, email_uniqueness, &user_id, &email).await?;
                     new_user.insert(transaction).await?;
                     if !new_user_attributes.is_empty() {
                         model::UserAttributes::insert_many(new_user_attributes)
@@ -259,8 +491,15 @@ impl UserBackendHandler for SqlBackendHandler {
     #[instrument(skip_all, level = "debug", err)]
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()> {
         debug!(user_id = ?request.user_id);
+        let email_uniqueness = self.config.email_uniqueness;
+        let email = request.email.clone();
         let update_user = model::users::ActiveModel {
             user_id: ActiveValue::Set(request.user_id.clone()),
+            unique_email: request
+                .email
+                .as_ref()
+                .map(|email| ActiveValue::Set(get_unique_email(email_uniqueness, email)))
+                .unwrap_or_default(),
             email: request.email.map(ActiveValue::Set).unwrap_or_default(),
             display_name: to_value(&request.display_name),
             ..Default::default()
@@ -292,12 +531,27 @@ impl UserBackendHandler for SqlBackendHandler {
         if let Some(value) = to_serialized_value(&request.last_name) {
             process_serialized(value, "last_name");
         }
+        if let Some(value) = to_serialized_value(&request.locale) {
+            process_serialized(value, "locale");
+        }
         if let Some(avatar) = request.avatar {
             process_serialized(avatar.into_active_value(), "avatar");
         }
+        for attribute in request.insert_attributes {
+            process_serialized(ActiveValue::Set(attribute.value), &attribute.name);
+        }
         self.sql_pool
             .transaction::<_, (), DomainError>(|transaction| {
                 Box::pin(async move {
+                    if let Some(email) = &email {
+                        check_email_uniqueness(
+                            transaction,
+                            email_uniqueness,
+                            &request.user_id,
+                            email,
+                        )
+                        .await?;
+                    }
                     update_user.update(transaction).await?;
                     if !update_user_attributes.is_empty() {
                         model::UserAttributes::insert_many(update_user_attributes)
@@ -344,6 +598,24 @@ impl UserBackendHandler for SqlBackendHandler {
         Ok(())
     }
 
+    #[instrument(skip_all, level = "debug", err)]
+    async fn rename_user(&self, user_id: &UserId, new_user_id: UserId) -> Result<()> {
+        debug!(?user_id, ?new_user_id);
+        // The foreign keys cascade on update, so memberships, attributes and tokens follow.
+        let res = model::User::update_many()
+            .col_expr(UserColumn::UserId, Expr::value(new_user_id))
+            .filter(UserColumn::UserId.eq(user_id))
+            .exec(&self.sql_pool)
+            .await?;
+        if res.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such user: '{}'",
+                user_id
+            )));
+        }
+        Ok(())
+    }
+
     #[instrument(skip_all, level = "debug", err)]
     async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
         debug!(?user_id, ?group_id);
@@ -367,6 +639,197 @@ impl UserBackendHandler for SqlBackendHandler {
                 user_id, group_id
             )));
         }
+        // The primary group has to be one of the user's groups.
+        model::User::update_many()
+            .col_expr(
+                UserColumn::PrimaryGroupId,
+                Expr::value(Option::<GroupId>::None),
+            )
+            .filter(UserColumn::UserId.eq(user_id))
+            .filter(UserColumn::PrimaryGroupId.eq(group_id))
+            .exec(&self.sql_pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn add_users_to_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>> {
+        debug!(?user_ids, ?group_id);
+        let user_ids = user_ids.iter().unique().cloned().collect::<Vec<_>>();
+        Ok(self
+            .sql_pool
+            .transaction::<_, Vec<UserId>, DomainError>(|transaction| {
+                Box::pin(async move {
+                    let members = model::Membership::find()
+                        .filter(MembershipColumn::GroupId.eq(group_id))
+                        .filter(MembershipColumn::UserId.is_in(user_ids.iter()))
+                        .all(transaction)
+                        .await?
+                        .into_iter()
+                        .map(|membership| membership.user_id)
+                        .collect::<HashSet<_>>();
+                    let added = user_ids
+                        .into_iter()
+                        .filter(|user_id| !members.contains(user_id))
+                        .collect::<Vec<_>>();
+                    if !added.is_empty() {
+                        model::Membership::insert_many(added.iter().map(|user_id| {
+                            model::memberships::ActiveModel {
+                                user_id: ActiveValue::Set(user_id.clone()),
+                                group_id: ActiveValue::Set(group_id),
+                            }
+                        }))
+                        .exec(transaction)
+                        .await?;
+                    }
+                    Ok(added)
+                })
+            })
+            .await?)
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn remove_users_from_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>> {
+        debug!(?user_ids, ?group_id);
+        let user_ids = user_ids.iter().unique().cloned().collect::<Vec<_>>();
+        Ok(self
+            .sql_pool
+            .transaction::<_, Vec<UserId>, DomainError>(|transaction| {
+                Box::pin(async move {
+                    let members = model::Membership::find()
+                        .filter(MembershipColumn::GroupId.eq(group_id))
+                        .filter(MembershipColumn::UserId.is_in(user_ids.iter()))
+                        .all(transaction)
+                        .await?
+                        .into_iter()
+                        .map(|membership| membership.user_id)
+                        .collect::<HashSet<_>>();
+                    let removed = user_ids
+                        .into_iter()
+                        .filter(|user_id| members.contains(user_id))
+                        .collect::<Vec<_>>();
+                    if !removed.is_empty() {
+                        model::Membership::delete_many()
+                            .filter(MembershipColumn::GroupId.eq(group_id))
+                            .filter(MembershipColumn::UserId.is_in(removed.iter()))
+                            .exec(transaction)
+                            .await?;
+                        // The primary group has to be one of the user's groups.
+                        model::User::update_many()
+                            .col_expr(
+                                UserColumn::PrimaryGroupId,
+                                Expr::value(Option::<GroupId>::None),
+                            )
+                            .filter(UserColumn::UserId.is_in(removed.iter()))
+                            .filter(UserColumn::PrimaryGroupId.eq(group_id))
+                            .exec(transaction)
+                            .await?;
+                    }
+                    Ok(removed)
+                })
+            })
+            .await?)
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn set_primary_group(&self, user_id: &UserId, group_id: Option<GroupId>) -> Result<()> {
+        debug!(?user_id, ?group_id);
+        if let Some(group_id) = group_id {
+            model::Membership::find_by_id((user_id.clone(), group_id))
+                .one(&self.sql_pool)
+                .await?
+                .ok_or_else(|| {
+                    DomainError::EntityNotFound(format!(
+                        "No such membership: '{}' -> {:?}",
+                        user_id, group_id
+                    ))
+                })?;
+        }
+        let res = model::User::update_many()
+            .col_expr(UserColumn::PrimaryGroupId, Expr::value(group_id))
+            .filter(UserColumn::UserId.eq(user_id))
+            .exec(&self.sql_pool)
+            .await?;
+        if res.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such user: '{}'",
+                user_id
+            )));
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn update_user_attribute(&self, request: UpdateAttributeRequest) -> Result<()> {
+        debug!(?request);
+        if self
+            .config
+            .derived_user_attributes
+            .iter()
+            .any(|d| d.name.eq_ignore_ascii_case(&request.name))
+        {
+            return Err(DomainError::Conflict(format!(
+                "The user attribute '{}' is derived, it cannot be changed",
+                request.name
+            )));
+        }
+        let name = self
+            .get_schema()
+            .await?
+            .user_attributes
+            .get_attribute_schema(&request.name)
+            .map(|attribute_schema| attribute_schema.name.clone())
+            .ok_or_else(|| {
+                DomainError::EntityNotFound(format!("No such user attribute: '{}'", request.name))
+            })?;
+        if request.is_empty() {
+            return Ok(());
+        }
+        model::UserAttributeSchema::update_many()
+            .set(model::user_attribute_schema::ActiveModel {
+                is_user_visible: request.is_visible.map(ActiveValue::Set).unwrap_or_default(),
+                is_user_editable: request
+                    .is_editable
+                    .map(ActiveValue::Set)
+                    .unwrap_or_default(),
+                is_ldap_visible: request
+                    .is_ldap_visible
+                    .map(ActiveValue::Set)
+                    .unwrap_or_default(),
+                ..Default::default()
+            })
+            .filter(UserAttributeSchemaColumn::AttributeName.eq(name))
+            .exec(&self.sql_pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug", err)]
+    async fn set_user_expiry_date(
+        &self,
+        user_id: &UserId,
+        expiry_date: Option<chrono::NaiveDateTime>,
+    ) -> Result<()> {
+        debug!(?user_id, ?expiry_date);
+        let res = model::User::update_many()
+            .col_expr(UserColumn::ExpiryDate, Expr::value(expiry_date))
+            .filter(UserColumn::UserId.eq(user_id))
+            .exec(&self.sql_pool)
+            .await?;
+        if res.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such user: '{}'",
+                user_id
+            )));
+        }
         Ok(())
     }
 }
@@ -375,6 +838,7 @@ impl UserBackendHandler for SqlBackendHandler {
 mod tests {
     use super::*;
     use crate::domain::{
+        derived_attributes::DerivedAttribute,
         handler::SubStringFilter,
         sql_backend_handler::tests::*,
         types::{JpegPhoto, UserColumn},
@@ -412,6 +876,192 @@ mod tests {
         assert_eq!(users, vec!["bob"]);
     }
 
+    #[tokio::test]
+    async fn test_list_users_display_name_ignore_case_filter() {
+        let fixture = TestFixture::new().await;
+        let users = get_user_names(
+            &fixture.handler,
+            Some(UserRequestFilter::EqualityIgnoreCase(
+                UserColumn::DisplayName,
+                "DISPLAY john".to_string(),
+            )),
+        )
+        .await;
+        assert_eq!(users, vec!["john"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_attribute_substring_filter() {
+        let fixture = TestFixture::new().await;
+        let get_users = |filter: SubStringFilter| {
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::AttributeSubString(
+                    "first_name".to_string(),
+                    filter,
+                )),
+            )
+        };
+        // Prefix.
+        assert_eq!(
+            get_users(SubStringFilter {
+                initial: Some("First P".to_owned()),
+                ..Default::default()
+            })
+            .await,
+            vec!["patrick"]
+        );
+        // Suffix.
+        assert_eq!(
+            get_users(SubStringFilter {
+                final_: Some("N".to_owned()),
+                ..Default::default()
+            })
+            .await,
+            vec!["john"]
+        );
+        // Contains.
+        assert_eq!(
+            get_users(SubStringFilter {
+                any: vec!["o".to_owned()],
+                ..Default::default()
+            })
+            .await,
+            vec!["bob", "john", "nogroup"]
+        );
+    }
+
+    // Reference implementation of the filters, to check the SQL translation.
+    fn matches_in_memory(user: &UserAndGroups, filter: &UserRequestFilter) -> bool {
+        use UserRequestFilter::*;
+        let attribute = |name: &str| {
+            user.user
+                .attributes
+                .iter()
+                .find(|a| a.name == name)
+                .map(|a| a.value.unwrap::<String>())
+        };
+        let column = |column: &UserColumn| match column {
+            UserColumn::Email => Some(user.user.email.clone()),
+            UserColumn::DisplayName => user.user.display_name.clone(),
+            _ => panic!("Unsupported column in the reference implementation"),
+        };
+        match filter {
+            And(fs) => fs.iter().all(|f| matches_in_memory(user, f)),
+            Or(fs) => fs.iter().any(|f| matches_in_memory(user, f)),
+            Not(f) => !matches_in_memory(user, f),
+            UserId(user_id) => &user.user.user_id == user_id,
+            UserIdSubString(f) => f.matches(user.user.user_id.as_str()),
+            Equality(c, value) => column(c).as_ref() == Some(value),
+            EqualityIgnoreCase(c, value) => column(c)
+                .map(|v| v.to_lowercase() == value.to_lowercase())
+                .unwrap_or(false),
+            SubString(c, f) => column(c).map(|v| f.matches(&v)).unwrap_or(false),
+            AttributeEquality(name, value) => attribute(name).as_ref() == Some(value),
+            AttributeSubString(name, f) => attribute(name).map(|v| f.matches(&v)).unwrap_or(false),
+            AttributePresent(name) => attribute(name).is_some(),
+            MemberOf(group) => user
+                .groups
+                .iter()
+                .flatten()
+                .any(|g| &g.display_name == group),
+            MemberOfId(group_id) => user
+                .groups
+                .iter()
+                .flatten()
+                .any(|g| &g.group_id == group_id),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_users_matches_in_memory_filters() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("patrick"),
+                display_name: Some("50% Patrick_".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let all_users = fixture.handler.list_users(None, true).await.unwrap();
+        let substring =
+            |initial: Option<&str>, any: Vec<&str>, final_: Option<&str>| SubStringFilter {
+                initial: initial.map(str::to_owned),
+                any: any.into_iter().map(str::to_owned).collect(),
+                final_: final_.map(str::to_owned),
+            };
+        let filters = vec![
+            UserRequestFilter::And(vec![]),
+            UserRequestFilter::Or(vec![]),
+            UserRequestFilter::Or(vec![
+                UserRequestFilter::UserId(UserId::new("bob")),
+                UserRequestFilter::Equality(UserColumn::Email, "john@bob.bob".to_owned()),
+            ]),
+            UserRequestFilter::Not(Box::new(UserRequestFilter::MemberOf(
+                "Best Group".to_owned(),
+            ))),
+            UserRequestFilter::And(vec![
+                UserRequestFilter::MemberOfId(fixture.groups[1]),
+                UserRequestFilter::UserIdSubString(substring(None, vec!["o"], None)),
+            ]),
+            UserRequestFilter::SubString(
+                UserColumn::DisplayName,
+                substring(Some("50%"), vec![], None),
+            ),
+            UserRequestFilter::SubString(
+                UserColumn::DisplayName,
+                substring(None, vec![], Some("k_")),
+            ),
+            UserRequestFilter::SubString(UserColumn::DisplayName, substring(None, vec!["_"], None)),
+            UserRequestFilter::EqualityIgnoreCase(
+                UserColumn::DisplayName,
+                "DISPLAY BOB".to_owned(),
+            ),
+            UserRequestFilter::AttributeEquality("last_name".to_owned(), "last bob".to_owned()),
+            UserRequestFilter::AttributeSubString(
+                "last_name".to_owned(),
+                substring(Some("last"), vec!["o"], Some("b")),
+            ),
+            UserRequestFilter::AttributePresent("first_name".to_owned()),
+            UserRequestFilter::AttributePresent("avatar".to_owned()),
+        ];
+        for filter in filters {
+            let expected = all_users
+                .iter()
+                .filter(|u| matches_in_memory(u, &filter))
+                .map(|u| u.user.user_id.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                get_user_names(&fixture.handler, Some(filter.clone())).await,
+                expected,
+                "Mismatch for filter {:?}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn test_unsupported_user_filter() {
+        assert!(matches!(
+            get_user_filter_expr(UserRequestFilter::Not(Box::new(
+                UserRequestFilter::AttributeSubString(
+                    "first_name".to_owned(),
+                    SubStringFilter::default()
+                )
+            ))),
+            Err(DomainError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            get_user_filter_expr(UserRequestFilter::Equality(
+                UserColumn::UserId,
+                "bob".to_owned()
+            )),
+            Err(DomainError::InvalidInput(_))
+        ));
+    }
+
     #[tokio::test]
     async fn test_list_users_other_filter() {
         let fixture = TestFixture::new().await;
@@ -650,6 +1300,310 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_max_users() {
+        let mut config = get_default_config();
+        config.max_users = Some(2);
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        insert_user_no_password(&handler, "bob").await;
+        insert_user_no_password(&handler, "john").await;
+        let create_patrick = || {
+            handler.create_user(CreateUserRequest {
+                user_id: UserId::new("patrick"),
+                email: "patrick@bob.bob".to_owned(),
+                ..Default::default()
+            })
+        };
+        assert!(matches!(
+            create_patrick().await,
+            Err(DomainError::LimitExceeded(_))
+        ));
+        // Deleting a user frees a slot.
+        handler.delete_user(&UserId::new("john")).await.unwrap();
+        create_patrick().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_username_length() {
+        let mut config = get_default_config();
+        config.username_min_length = Some(3);
+        config.username_max_length = Some(5);
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        let create_user = |name: &str| {
+            handler.create_user(CreateUserRequest {
+                user_id: UserId::new(name),
+                email: format!("{}@bob.bob", name),
+                ..Default::default()
+            })
+        };
+        assert!(matches!(
+            create_user("bo").await,
+            Err(DomainError::InvalidInput(_))
+        ));
+        create_user("bob").await.unwrap();
+        create_user("alice").await.unwrap();
+        assert!(matches!(
+            create_user("patrick").await,
+            Err(DomainError::InvalidInput(_))
+        ));
+        // The admin user can always be created.
+        let mut config = get_default_config();
+        config.username_max_length = Some(2);
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("admin"),
+                email: "admin@bob.bob".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_email_uniqueness() {
+        for policy in [
+            EmailUniqueness::None,
+            EmailUniqueness::Warn,
+            EmailUniqueness::Enforce,
+        ] {
+            let mut config = get_default_config();
+            config.email_uniqueness = policy;
+            let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+            insert_user_no_password(&handler, "bob").await;
+            insert_user_no_password(&handler, "john").await;
+            // A user can always keep their own email.
+            handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new("bob"),
+                    email: Some("bob@bob.bob".to_owned()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            let create_patrick = handler
+                .create_user(CreateUserRequest {
+                    user_id: UserId::new("patrick"),
+                    email: "bob@bob.bob".to_owned(),
+                    ..Default::default()
+                })
+                .await;
+            let update_john = handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new("john"),
+                    email: Some("bob@bob.bob".to_owned()),
+                    ..Default::default()
+                })
+                .await;
+            let john_email = handler
+                .get_user_details(&UserId::new("john"))
+                .await
+                .unwrap()
+                .email;
+            if policy == EmailUniqueness::Enforce {
+                assert!(matches!(create_patrick, Err(DomainError::Conflict(_))));
+                assert!(matches!(update_john, Err(DomainError::Conflict(_))));
+                assert_eq!(john_email, "john@bob.bob");
+            } else {
+                create_patrick.unwrap();
+                update_john.unwrap();
+                assert_eq!(john_email, "bob@bob.bob");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_email_uniqueness_database_constraint() {
+        let sql_pool = get_initialized_db().await;
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        let create_patrick = |handler: &SqlBackendHandler| {
+            handler.create_user(CreateUserRequest {
+                user_id: UserId::new("patrick"),
+                email: "Bob@Bob.bob".to_owned(),
+                ..Default::default()
+            })
+        };
+        // The case is ignored.
+        assert!(matches!(
+            create_patrick(&handler).await,
+            Err(DomainError::Conflict(_))
+        ));
+        // A write that raced with the check is rejected by the database.
+        model::users::ActiveModel {
+            user_id: Set(UserId::new("patrick")),
+            email: Set("BOB@bob.bob".to_owned()),
+            unique_email: Set(Some("bob@bob.bob".to_owned())),
+            creation_date: Set(chrono::Utc::now().naive_utc()),
+            uuid: Set(Uuid::from_name_and_date(
+                "patrick",
+                &chrono::Utc::now().naive_utc(),
+            )),
+            ..Default::default()
+        }
+        .insert(&sql_pool)
+        .await
+        .unwrap_err();
+
+        // Without the enforcement, the duplicates are allowed.
+        let mut config = get_default_config();
+        config.email_uniqueness = EmailUniqueness::Warn;
+        let warn_handler = SqlBackendHandler::new(config, sql_pool.clone());
+        warn_handler.apply_email_uniqueness_policy().await.unwrap();
+        create_patrick(&warn_handler).await.unwrap();
+        // Until the enforcement is turned back on.
+        assert!(matches!(
+            handler.apply_email_uniqueness_policy().await,
+            Err(DomainError::Conflict(_))
+        ));
+        warn_handler
+            .update_user(UpdateUserRequest {
+                user_id: UserId::new("patrick"),
+                email: Some("patrick@bob.bob".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler.apply_email_uniqueness_policy().await.unwrap();
+        assert!(matches!(
+            handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new("patrick"),
+                    email: Some("bob@BOB.bob".to_owned()),
+                    ..Default::default()
+                })
+                .await,
+            Err(DomainError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_details_derived_attributes() {
+        let mut config = get_default_config();
+        config.derived_user_attributes = vec![DerivedAttribute {
+            name: "homeDirectory".to_owned(),
+            expression: r#""/home/" + uid"#.to_owned().try_into().unwrap(),
+        }];
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        insert_user_no_password(&handler, "bob").await;
+        let get_home_directory = |user: User| {
+            user.attributes
+                .into_iter()
+                .find(|a| a.name == "homeDirectory")
+                .map(|a| a.value.unwrap::<String>())
+        };
+        assert_eq!(
+            get_home_directory(handler.get_user_details(&UserId::new("bob")).await.unwrap()),
+            Some("/home/bob".to_owned())
+        );
+        handler
+            .rename_user(&UserId::new("bob"), UserId::new("bobby"))
+            .await
+            .unwrap();
+        assert_eq!(
+            get_home_directory(
+                handler
+                    .get_user_details(&UserId::new("bobby"))
+                    .await
+                    .unwrap()
+            ),
+            Some("/home/bobby".to_owned())
+        );
+        let users = handler.list_users(None, false).await.unwrap();
+        assert_eq!(
+            get_home_directory(users.into_iter().next().unwrap().user),
+            Some("/home/bobby".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_derived_attributes_hidden_attributes() {
+        let mut config = get_default_config();
+        config.derived_user_attributes = vec![DerivedAttribute {
+            name: "gecos".to_owned(),
+            expression: r#"first_name + " " + uid"#.to_owned().try_into().unwrap(),
+        }];
+        config.display_name_fallback = vec![
+            r#"first_name + " " + last_name"#.to_owned().try_into().unwrap(),
+            "uid".to_owned().try_into().unwrap(),
+        ];
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("bob"),
+                email: "bob@bob.bob".to_owned(),
+                first_name: Some("Bob".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler
+            .update_user_attribute(UpdateAttributeRequest {
+                name: "first_name".to_owned(),
+                is_visible: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let schema = handler.get_schema().await.unwrap().user_attributes;
+        assert!(!schema.get_attribute_schema("gecos").unwrap().is_visible);
+        // The display name doesn't reveal the hidden first name.
+        assert_eq!(
+            handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .display_name
+                .as_deref(),
+            Some("bob")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_display_name_fallback() {
+        let mut config = get_default_config();
+        config.display_name_fallback = vec![
+            r#"first_name + " " + last_name"#.to_owned().try_into().unwrap(),
+            "uid".to_owned().try_into().unwrap(),
+        ];
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        insert_user_no_password(&handler, "patrick").await;
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("bob"),
+                email: "bob@bob.bob".to_owned(),
+                first_name: Some("Bob".to_owned()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        handler
+            .create_user(CreateUserRequest {
+                user_id: UserId::new("john"),
+                email: "john@bob.bob".to_owned(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            handler
+                .get_user_details(&UserId::new("bob"))
+                .await
+                .unwrap()
+                .display_name
+                .as_deref(),
+            Some("Bob")
+        );
+        let display_names = handler
+            .list_users(None, false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.user.display_name.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(display_names, vec!["Bob", "john", "display patrick"]);
+    }
+
     #[tokio::test]
     async fn test_user_lowercase() {
         let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
@@ -735,6 +1689,8 @@ mod tests {
                 first_name: Some("first_name".to_string()),
                 last_name: Some("last_name".to_string()),
                 avatar: Some(JpegPhoto::for_tests()),
+                locale: None,
+                insert_attributes: Vec::new(),
             })
             .await
             .unwrap();
@@ -857,6 +1813,7 @@ mod tests {
                 first_name: Some("first_name".to_string()),
                 last_name: Some("last_name".to_string()),
                 avatar: Some(JpegPhoto::for_tests()),
+                locale: None,
             })
             .await
             .unwrap();
@@ -907,6 +1864,140 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_add_users_to_group() {
+        let fixture = TestFixture::new().await;
+        let users = ["bob", "john", "nogroup", "john"].map(UserId::new);
+        assert_eq!(
+            fixture
+                .handler
+                .add_users_to_group(&users, fixture.groups[0])
+                .await
+                .unwrap(),
+            vec![UserId::new("john"), UserId::new("nogroup")]
+        );
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::MemberOfId(fixture.groups[0])),
+            )
+            .await,
+            vec!["bob", "john", "nogroup", "patrick"]
+        );
+        // Nothing is added if one of the users doesn't exist.
+        fixture
+            .handler
+            .add_users_to_group(
+                &[UserId::new("bob"), UserId::new("not found")],
+                fixture.groups[2],
+            )
+            .await
+            .expect_err("Should have failed");
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::MemberOfId(fixture.groups[2])),
+            )
+            .await,
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_users_from_group() {
+        let fixture = TestFixture::new().await;
+        let bob = UserId::new("bob");
+        fixture
+            .handler
+            .set_primary_group(&bob, Some(fixture.groups[0]))
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture
+                .handler
+                .remove_users_from_group(&[bob.clone(), UserId::new("john")], fixture.groups[0])
+                .await
+                .unwrap(),
+            vec![bob.clone()]
+        );
+        assert_eq!(
+            get_user_names(
+                &fixture.handler,
+                Some(UserRequestFilter::MemberOfId(fixture.groups[0])),
+            )
+            .await,
+            vec!["patrick"]
+        );
+        let user = fixture.handler.get_user_details(&bob).await.unwrap();
+        assert_eq!(user.primary_group, None);
+    }
+
+    #[tokio::test]
+    async fn test_set_primary_group() {
+        let fixture = TestFixture::new().await;
+        let bob = UserId::new("bob");
+        fixture
+            .handler
+            .set_primary_group(&bob, Some(fixture.groups[0]))
+            .await
+            .unwrap();
+
+        let user = fixture.handler.get_user_details(&bob).await.unwrap();
+        assert_eq!(user.primary_group, Some(fixture.groups[0]));
+        let users = fixture.handler.list_users(None, false).await.unwrap();
+        assert_eq!(
+            users
+                .iter()
+                .find(|u| u.user.user_id == bob)
+                .unwrap()
+                .user
+                .primary_group,
+            Some(fixture.groups[0])
+        );
+
+        // Bob is not a member of the second group.
+        fixture
+            .handler
+            .set_primary_group(&bob, Some(fixture.groups[1]))
+            .await
+            .expect_err("Should have failed");
+
+        // Leaving the group clears the primary group.
+        fixture
+            .handler
+            .remove_user_from_group(&bob, fixture.groups[0])
+            .await
+            .unwrap();
+        let user = fixture.handler.get_user_details(&bob).await.unwrap();
+        assert_eq!(user.primary_group, None);
+    }
+
+    #[tokio::test]
+    async fn test_rename_user() {
+        let fixture = TestFixture::new().await;
+        fixture
+            .handler
+            .rename_user(&UserId::new("bob"), UserId::new("robert"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            get_user_names(&fixture.handler, None).await,
+            vec!["john", "nogroup", "patrick", "robert"]
+        );
+        let groups = fixture
+            .handler
+            .get_user_groups(&UserId::new("robert"))
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        fixture
+            .handler
+            .rename_user(&UserId::new("bob"), UserId::new("bobby"))
+            .await
+            .expect_err("Should have failed");
+    }
+
     #[tokio::test]
     async fn test_delete_user_not_found() {
         let fixture = TestFixture::new().await;
@@ -934,4 +2025,69 @@ mod tests {
             .await
             .expect_err("Should have failed");
     }
+
+    #[tokio::test]
+    async fn test_update_user_attribute() {
+        let mut config = get_default_config();
+        config.derived_user_attributes = vec![DerivedAttribute {
+            name: "homeDirectory".to_owned(),
+            expression: r#""/home/" + uid"#.to_owned().try_into().unwrap(),
+        }];
+        let handler = SqlBackendHandler::new(config, get_initialized_db().await);
+        let get_attribute_schema = || async {
+            handler
+                .get_schema()
+                .await
+                .unwrap()
+                .user_attributes
+                .get_attribute_schema("first_name")
+                .cloned()
+                .unwrap()
+        };
+        handler
+            .update_user_attribute(UpdateAttributeRequest {
+                name: "First_Name".to_owned(),
+                is_ldap_visible: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let attribute_schema = get_attribute_schema().await;
+        assert!(!attribute_schema.is_ldap_visible);
+        // The other flags are kept.
+        assert!(attribute_schema.is_visible);
+        assert!(attribute_schema.is_editable);
+        handler
+            .update_user_attribute(UpdateAttributeRequest {
+                name: "first_name".to_owned(),
+                is_editable: Some(false),
+                is_ldap_visible: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let attribute_schema = get_attribute_schema().await;
+        assert!(attribute_schema.is_ldap_visible);
+        assert!(!attribute_schema.is_editable);
+        assert!(matches!(
+            handler
+                .update_user_attribute(UpdateAttributeRequest {
+                    name: "unknown".to_owned(),
+                    is_ldap_visible: Some(false),
+                    ..Default::default()
+                })
+                .await,
+            Err(DomainError::EntityNotFound(_))
+        ));
+        assert!(matches!(
+            handler
+                .update_user_attribute(UpdateAttributeRequest {
+                    name: "homedirectory".to_owned(),
+                    is_ldap_visible: Some(false),
+                    ..Default::default()
+                })
+                .await,
+            Err(DomainError::Conflict(_))
+        ));
+    }
 }
@@ -147,7 +147,7 @@ impl<'a, T: Serialize + ?Sized> From<&'a T> for Serialized {
 }
 
 impl Serialized {
-    fn convert_to<'a, T: Deserialize<'a>>(&'a self) -> bincode::Result<T> {
+    pub fn convert_to<'a, T: Deserialize<'a>>(&'a self) -> bincode::Result<T> {
         bincode::deserialize(&self.0)
     }
 
@@ -198,7 +198,7 @@ impl ValueType for Serialized {
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Hash, Serialize, Deserialize)]
 #[serde(from = "String")]
 pub struct UserId(String);
 
@@ -428,7 +428,15 @@ pub struct User {
     pub display_name: Option<String>,
     pub creation_date: NaiveDateTime,
     pub uuid: Uuid,
+    /// The group used as the primary group, e.g. for the POSIX `gidNumber`.
+    pub primary_group: Option<GroupId>,
+    /// After this date, the user can't log in or bind anymore.
+    pub expiry_date: Option<NaiveDateTime>,
+    /// When the password was last set, if it was since the column was added.
+    pub password_modified_date: Option<NaiveDateTime>,
     pub attributes: Vec<AttributeValue>,
+    /// Whether the user has a password, i.e. can bind. The password itself is never exposed.
+    pub has_password: bool,
 }
 
 #[cfg(test)]
@@ -441,7 +449,11 @@ impl Default for User {
             display_name: None,
             creation_date: epoch,
             uuid: Uuid::from_name_and_date("", &epoch),
+            primary_group: None,
+            expiry_date: None,
+            password_modified_date: None,
             attributes: Vec::new(),
+            has_password: false,
         }
     }
 }
@@ -479,6 +491,12 @@ impl ValueType for GroupId {
     }
 }
 
+impl Nullable for GroupId {
+    fn null() -> Value {
+        i32::null()
+    }
+}
+
 impl TryFromU64 for GroupId {
     fn try_from_u64(n: u64) -> Result<Self, DbErr> {
         Ok(GroupId(i32::try_from_u64(n)?))
@@ -536,7 +554,11 @@ pub struct Group {
     pub display_name: String,
     pub creation_date: NaiveDateTime,
     pub uuid: Uuid,
+    pub email: Option<String>,
+    pub description: Option<String>,
+    pub modified_date: NaiveDateTime,
     pub users: Vec<UserId>,
+    pub attributes: Vec<AttributeValue>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, FromQueryResult)]
@@ -545,6 +567,8 @@ pub struct GroupDetails {
     pub display_name: String,
     pub creation_date: NaiveDateTime,
     pub uuid: Uuid,
+    pub email: Option<String>,
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -0,0 +1,120 @@
+//! A public API to embed the user and group management of LLDAP in another Rust service,
+//! without starting the LDAP or HTTP servers.
+//!
+//! The [`Configuration`] can be built standalone with [`ConfigurationBuilder`], e.g. setting the
+//! `database_url` and the `key_seed` (or `serialized_server_setup`). Only the fields related to
+//! the database, the server keys and the users/groups are used.
+
+use crate::domain::{
+    handler::{BackendHandler, BindRequest, GroupBackendHandler, LoginHandler, UserBackendHandler},
+    sql_backend_handler::SqlBackendHandler,
+    sql_opaque_handler::register_password,
+    sql_tables::init_table,
+};
+use anyhow::Context;
+use sea_orm::Database;
+use secstr::SecUtf8;
+use std::collections::HashSet;
+
+pub use crate::{
+    domain::{
+        error::{DomainError, Result},
+        handler::CreateUserRequest,
+        types::{AttributeValue, GroupDetails, GroupId, JpegPhoto, Serialized, User, UserId, Uuid},
+    },
+    infra::configuration::{
+        Configuration, ConfigurationBuilder, EmailUniqueness, GroupNameNormalization,
+        GroupNameUniqueness,
+    },
+};
+
+/// The users and groups of an LLDAP database.
+#[derive(Clone)]
+pub struct Lldap {
+    handler: SqlBackendHandler,
+}
+
+impl Lldap {
+    /// Connects to the database of the configuration, and creates or migrates the tables and the
+    /// built-in groups. The admin user is not created.
+    pub async fn connect(config: Configuration) -> anyhow::Result<Self> {
+        let mut sql_opt = sea_orm::ConnectOptions::new(config.database_url.clone());
+        sql_opt.max_connections(5).sqlx_logging(false);
+        let sql_pool = Database::connect(sql_opt).await?;
+        init_table(&sql_pool)
+            .await
+            .context("while creating the tables")?;
+        let handler = SqlBackendHandler::new(config, sql_pool);
+        handler
+            .apply_email_uniqueness_policy()
+            .await
+            .context("while applying the email_uniqueness setting")?;
+        handler
+            .apply_group_name_uniqueness_policy()
+            .await
+            .context("while applying the group_name_uniqueness setting")?;
+        handler
+            .ensure_builtin_groups_exist()
+            .await
+            .context("while creating the built-in groups")?;
+        Ok(Self { handler })
+    }
+
+    /// Creates a user, with a password if given. If setting the password fails, the user is not
+    /// created either.
+    pub async fn create_user(
+        &self,
+        request: CreateUserRequest,
+        password: Option<&SecUtf8>,
+    ) -> Result<()> {
+        let user_id = request.user_id.clone();
+        let transaction = self.handler.begin_transaction().await?;
+        transaction.create_user(request).await?;
+        if let Some(password) = password {
+            register_password(&transaction, &user_id, password).await?;
+        }
+        transaction.commit_transaction().await
+    }
+
+    pub async fn set_password(&self, user_id: &UserId, password: &SecUtf8) -> Result<()> {
+        register_password(&self.handler, user_id, password).await
+    }
+
+    /// Checks the password of the user, returning an `AuthenticationError` if it's wrong.
+    pub async fn authenticate(&self, user_id: &UserId, password: &str) -> Result<()> {
+        self.handler
+            .bind(BindRequest {
+                name: user_id.clone(),
+                password: password.to_owned(),
+            })
+            .await
+    }
+
+    pub async fn get_user(&self, user_id: &UserId) -> Result<User> {
+        self.handler.get_user_details(user_id).await
+    }
+
+    pub async fn delete_user(&self, user_id: &UserId) -> Result<()> {
+        self.handler.delete_user(user_id).await
+    }
+
+    pub async fn create_group(&self, group_name: &str) -> Result<GroupId> {
+        self.handler.create_group(group_name).await
+    }
+
+    pub async fn delete_group(&self, group_id: GroupId) -> Result<()> {
+        self.handler.delete_group(group_id).await
+    }
+
+    pub async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+        self.handler.add_user_to_group(user_id, group_id).await
+    }
+
+    pub async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+        self.handler.remove_user_from_group(user_id, group_id).await
+    }
+
+    pub async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>> {
+        self.handler.get_user_groups(user_id).await
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
 use async_trait::async_trait;
 use tracing::info;
@@ -6,17 +6,20 @@ use tracing::info;
 use crate::domain::{
     error::Result,
     handler::{
-        AttributeSchema, BackendHandler, CreateUserRequest, GroupBackendHandler,
-        GroupListerBackendHandler, GroupRequestFilter, Schema, SchemaBackendHandler,
-        UpdateGroupRequest, UpdateUserRequest, UserBackendHandler, UserListerBackendHandler,
-        UserRequestFilter,
+        AttributeSchema, BackendHandler, CreateAttributeRequest, CreateUserRequest,
+        GroupBackendHandler, GroupListerBackendHandler, GroupRequestFilter, Schema,
+        SchemaBackendHandler, UpdateAttributeRequest, UpdateGroupRequest, UpdateUserRequest,
+        UserBackendHandler, UserListerBackendHandler, UserRequestFilter,
     },
     types::{Group, GroupDetails, GroupId, User, UserAndGroups, UserId},
 };
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum Permission {
     Admin,
+    /// Can read everything an admin can, but can't modify anything.
+    ReadonlyAdmin,
     PasswordManager,
     Readonly,
     Regular,
@@ -26,6 +29,8 @@ pub enum Permission {
 pub struct ValidationResults {
     pub user: UserId,
     pub permission: Permission,
+    /// The admin acting as the user, for the impersonation tokens.
+    pub impersonated_by: Option<UserId>,
 }
 
 impl ValidationResults {
@@ -34,6 +39,7 @@ impl ValidationResults {
         Self {
             user: UserId::new("admin"),
             permission: Permission::Admin,
+            impersonated_by: None,
         }
     }
 
@@ -42,9 +48,21 @@ impl ValidationResults {
         self.permission == Permission::Admin
     }
 
+    #[must_use]
+    pub fn is_readonly_admin(&self) -> bool {
+        self.permission == Permission::ReadonlyAdmin
+    }
+
+    /// Whether the user can see the administration data, like the open LDAP connections.
+    #[must_use]
+    pub fn can_read_admin_data(&self) -> bool {
+        self.permission == Permission::Admin || self.permission == Permission::ReadonlyAdmin
+    }
+
     #[must_use]
     pub fn can_read_all(&self) -> bool {
         self.permission == Permission::Admin
+            || self.permission == Permission::ReadonlyAdmin
             || self.permission == Permission::Readonly
             || self.permission == Permission::PasswordManager
     }
@@ -52,6 +70,7 @@ impl ValidationResults {
     #[must_use]
     pub fn can_read(&self, user: &UserId) -> bool {
         self.permission == Permission::Admin
+            || self.permission == Permission::ReadonlyAdmin
             || self.permission == Permission::PasswordManager
             || self.permission == Permission::Readonly
             || &self.user == user
@@ -66,7 +85,8 @@ impl ValidationResults {
 
     #[must_use]
     pub fn can_write(&self, user: &UserId) -> bool {
-        self.permission == Permission::Admin || &self.user == user
+        self.permission == Permission::Admin
+            || (self.permission != Permission::ReadonlyAdmin && &self.user == user)
     }
 }
 
@@ -84,7 +104,11 @@ pub trait ReadonlyBackendHandler: UserReadableBackendHandler {
         get_groups: bool,
     ) -> Result<Vec<UserAndGroups>>;
     async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>>;
+    async fn count_users(&self) -> Result<u64>;
+    async fn count_groups(&self) -> Result<u64>;
     async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails>;
+    async fn get_group_managers(&self, group_id: GroupId) -> Result<Vec<UserId>>;
+    async fn get_group_manager_groups(&self, group_id: GroupId) -> Result<Vec<GroupId>>;
 }
 
 #[async_trait]
@@ -92,17 +116,57 @@ pub trait UserWriteableBackendHandler: UserReadableBackendHandler {
     async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
 }
 
+#[async_trait]
+pub trait GroupMembershipBackendHandler {
+    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+    async fn add_users_to_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>>;
+    async fn remove_users_from_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>>;
+}
+
 #[async_trait]
 pub trait AdminBackendHandler:
-    UserWriteableBackendHandler + ReadonlyBackendHandler + UserWriteableBackendHandler
+    UserWriteableBackendHandler
+    + ReadonlyBackendHandler
+    + UserWriteableBackendHandler
+    + GroupMembershipBackendHandler
 {
     async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
     async fn delete_user(&self, user_id: &UserId) -> Result<()>;
-    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
-    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+    async fn rename_user(&self, user_id: &UserId, new_user_id: UserId) -> Result<()>;
+    async fn set_primary_group(&self, user_id: &UserId, group_id: Option<GroupId>) -> Result<()>;
+    async fn update_user_attribute(&self, request: UpdateAttributeRequest) -> Result<()>;
+    async fn set_user_expiry_date(
+        &self,
+        user_id: &UserId,
+        expiry_date: Option<chrono::NaiveDateTime>,
+    ) -> Result<()>;
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
     async fn create_group(&self, group_name: &str) -> Result<GroupId>;
     async fn delete_group(&self, group_id: GroupId) -> Result<()>;
+    async fn add_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()>;
+    async fn remove_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()>;
+    async fn add_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()>;
+    async fn remove_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()>;
+    async fn add_group_attribute(&self, request: CreateAttributeRequest) -> Result<()>;
+    async fn update_group_attribute(&self, request: UpdateAttributeRequest) -> Result<()>;
+    async fn delete_group_attribute(&self, name: &str) -> Result<()>;
 }
 
 #[async_trait]
@@ -127,9 +191,21 @@ impl<Handler: BackendHandler> ReadonlyBackendHandler for Handler {
     async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>> {
         <Handler as GroupListerBackendHandler>::list_groups(self, filters).await
     }
+    async fn count_users(&self) -> Result<u64> {
+        <Handler as UserListerBackendHandler>::count_users(self).await
+    }
+    async fn count_groups(&self) -> Result<u64> {
+        <Handler as GroupListerBackendHandler>::count_groups(self).await
+    }
     async fn get_group_details(&self, group_id: GroupId) -> Result<GroupDetails> {
         <Handler as GroupBackendHandler>::get_group_details(self, group_id).await
     }
+    async fn get_group_managers(&self, group_id: GroupId) -> Result<Vec<UserId>> {
+        <Handler as GroupBackendHandler>::get_group_managers(self, group_id).await
+    }
+    async fn get_group_manager_groups(&self, group_id: GroupId) -> Result<Vec<GroupId>> {
+        <Handler as GroupBackendHandler>::get_group_manager_groups(self, group_id).await
+    }
 }
 
 #[async_trait]
@@ -139,6 +215,29 @@ impl<Handler: BackendHandler> UserWriteableBackendHandler for Handler {
     }
 }
 #[async_trait]
+impl<Handler: BackendHandler> GroupMembershipBackendHandler for Handler {
+    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+        <Handler as UserBackendHandler>::add_user_to_group(self, user_id, group_id).await
+    }
+    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
+        <Handler as UserBackendHandler>::remove_user_from_group(self, user_id, group_id).await
+    }
+    async fn add_users_to_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>> {
+        <Handler as UserBackendHandler>::add_users_to_group(self, user_ids, group_id).await
+    }
+    async fn remove_users_from_group(
+        &self,
+        user_ids: &[UserId],
+        group_id: GroupId,
+    ) -> Result<Vec<UserId>> {
+        <Handler as UserBackendHandler>::remove_users_from_group(self, user_ids, group_id).await
+    }
+}
+#[async_trait]
 impl<Handler: BackendHandler> AdminBackendHandler for Handler {
     async fn create_user(&self, request: CreateUserRequest) -> Result<()> {
         <Handler as UserBackendHandler>::create_user(self, request).await
@@ -146,11 +245,21 @@ impl<Handler: BackendHandler> AdminBackendHandler for Handler {
     async fn delete_user(&self, user_id: &UserId) -> Result<()> {
         <Handler as UserBackendHandler>::delete_user(self, user_id).await
     }
-    async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
-        <Handler as UserBackendHandler>::add_user_to_group(self, user_id, group_id).await
+    async fn rename_user(&self, user_id: &UserId, new_user_id: UserId) -> Result<()> {
+        <Handler as UserBackendHandler>::rename_user(self, user_id, new_user_id).await
     }
-    async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()> {
-        <Handler as UserBackendHandler>::remove_user_from_group(self, user_id, group_id).await
+    async fn set_primary_group(&self, user_id: &UserId, group_id: Option<GroupId>) -> Result<()> {
+        <Handler as UserBackendHandler>::set_primary_group(self, user_id, group_id).await
+    }
+    async fn update_user_attribute(&self, request: UpdateAttributeRequest) -> Result<()> {
+        <Handler as UserBackendHandler>::update_user_attribute(self, request).await
+    }
+    async fn set_user_expiry_date(
+        &self,
+        user_id: &UserId,
+        expiry_date: Option<chrono::NaiveDateTime>,
+    ) -> Result<()> {
+        <Handler as UserBackendHandler>::set_user_expiry_date(self, user_id, expiry_date).await
     }
     async fn update_group(&self, request: UpdateGroupRequest) -> Result<()> {
         <Handler as GroupBackendHandler>::update_group(self, request).await
@@ -161,16 +270,54 @@ impl<Handler: BackendHandler> AdminBackendHandler for Handler {
     async fn delete_group(&self, group_id: GroupId) -> Result<()> {
         <Handler as GroupBackendHandler>::delete_group(self, group_id).await
     }
+    async fn add_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()> {
+        <Handler as GroupBackendHandler>::add_group_manager(self, group_id, user_id).await
+    }
+    async fn remove_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()> {
+        <Handler as GroupBackendHandler>::remove_group_manager(self, group_id, user_id).await
+    }
+    async fn add_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()> {
+        <Handler as GroupBackendHandler>::add_group_manager_group(self, group_id, manager_group_id)
+            .await
+    }
+    async fn remove_group_manager_group(
+        &self,
+        group_id: GroupId,
+        manager_group_id: GroupId,
+    ) -> Result<()> {
+        <Handler as GroupBackendHandler>::remove_group_manager_group(
+            self,
+            group_id,
+            manager_group_id,
+        )
+        .await
+    }
+    async fn add_group_attribute(&self, request: CreateAttributeRequest) -> Result<()> {
+        <Handler as GroupBackendHandler>::add_group_attribute(self, request).await
+    }
+    async fn update_group_attribute(&self, request: UpdateAttributeRequest) -> Result<()> {
+        <Handler as GroupBackendHandler>::update_group_attribute(self, request).await
+    }
+    async fn delete_group_attribute(&self, name: &str) -> Result<()> {
+        <Handler as GroupBackendHandler>::delete_group_attribute(self, name).await
+    }
 }
 
 pub struct AccessControlledBackendHandler<Handler> {
     handler: Handler,
+    /// The members of any of these groups are administrators.
+    admin_groups: Arc<[String]>,
 }
 
 impl<Handler: Clone> Clone for AccessControlledBackendHandler<Handler> {
     fn clone(&self) -> Self {
         Self {
             handler: self.handler.clone(),
+            admin_groups: self.admin_groups.clone(),
         }
     }
 }
@@ -179,11 +326,54 @@ impl<Handler> AccessControlledBackendHandler<Handler> {
     pub fn unsafe_get_handler(&self) -> &Handler {
         &self.handler
     }
+
+    pub fn admin_groups(&self) -> &[String] {
+        &self.admin_groups
+    }
+
+    pub fn is_admin_group(&self, group_name: &str) -> bool {
+        self.admin_groups.iter().any(|g| g == group_name)
+    }
+
+    /// Whether the members of the group get special permissions, so that only admins can change
+    /// its members.
+    pub fn is_privileged_group(&self, group_name: &str) -> bool {
+        self.is_admin_group(group_name)
+            || [
+                "lldap_admin",
+                "lldap_read_only_admin",
+                "lldap_password_manager",
+                "lldap_strict_readonly",
+            ]
+            .contains(&group_name)
+    }
 }
 
 impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
     pub fn new(handler: Handler) -> Self {
-        Self { handler }
+        Self {
+            handler,
+            admin_groups: Arc::from(vec!["lldap_admin".to_owned()]),
+        }
+    }
+
+    pub fn with_admin_groups(self, admin_groups: Vec<String>) -> Self {
+        Self {
+            admin_groups: admin_groups.into(),
+            ..self
+        }
+    }
+
+    /// Returns the same handler, with all its operations in a new transaction.
+    pub async fn begin_transaction(&self) -> Result<Self> {
+        Ok(Self {
+            handler: self.handler.begin_transaction().await?,
+            admin_groups: self.admin_groups.clone(),
+        })
+    }
+
+    pub async fn commit_transaction(self) -> Result<()> {
+        self.handler.commit_transaction().await
     }
 
     pub fn get_admin_handler(
@@ -200,6 +390,71 @@ impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
         validation_result.can_read_all().then_some(&self.handler)
     }
 
+    /// Admins can change the members of any group, the managers of a group only the members of
+    /// that group, unless it's a privileged group. The managers are either designated directly,
+    /// or members of a manager group.
+    pub async fn get_group_membership_handler(
+        &self,
+        validation_result: &ValidationResults,
+        group_id: GroupId,
+    ) -> Result<Option<&impl GroupMembershipBackendHandler>> {
+        if validation_result.is_admin() {
+            return Ok(Some(&self.handler));
+        }
+        if validation_result.is_readonly_admin()
+            || !self.is_manager(validation_result, group_id).await?
+        {
+            return Ok(None);
+        }
+        let group = self.handler.get_group_details(group_id).await?;
+        Ok((!self.is_privileged_group(&group.display_name)).then_some(&self.handler))
+    }
+
+    async fn is_manager(
+        &self,
+        validation_result: &ValidationResults,
+        group_id: GroupId,
+    ) -> Result<bool> {
+        if self
+            .handler
+            .get_group_managers(group_id)
+            .await?
+            .contains(&validation_result.user)
+        {
+            return Ok(true);
+        }
+        let manager_groups = self.handler.get_group_manager_groups(group_id).await?;
+        if manager_groups.is_empty() {
+            return Ok(false);
+        }
+        Ok(self
+            .handler
+            .get_user_groups(&validation_result.user)
+            .await?
+            .iter()
+            .any(|g| manager_groups.contains(&g.group_id)))
+    }
+
+    /// The groups whose members the user can change as a manager, i.e. without counting the rights
+    /// of the admins.
+    pub async fn get_managed_groups(
+        &self,
+        validation_result: &ValidationResults,
+    ) -> Result<Vec<Group>> {
+        if validation_result.is_readonly_admin() {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .handler
+            .list_groups(Some(GroupRequestFilter::ManagedBy(
+                validation_result.user.clone(),
+            )))
+            .await?
+            .into_iter()
+            .filter(|g| !self.is_privileged_group(&g.display_name))
+            .collect())
+    }
+
     pub fn get_writeable_handler(
         &self,
         validation_result: &ValidationResults,
@@ -246,8 +501,10 @@ impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
         let is_in_group = |name| groups.clone().any(|g| g == name);
         ValidationResults {
             user: user_id,
-            permission: if is_in_group("lldap_admin") {
+            permission: if groups.clone().any(|g| self.is_admin_group(g)) {
                 Permission::Admin
+            } else if is_in_group("lldap_read_only_admin") {
+                Permission::ReadonlyAdmin
             } else if is_in_group("lldap_password_manager") {
                 Permission::PasswordManager
             } else if is_in_group("lldap_strict_readonly") {
@@ -255,6 +512,7 @@ impl<Handler: BackendHandler> AccessControlledBackendHandler<Handler> {
             } else {
                 Permission::Regular
             },
+            impersonated_by: None,
         }
     }
 }
@@ -334,3 +592,105 @@ impl<'a, Handler: GroupListerBackendHandler + UserListerBackendHandler + Sync>
     UserAndGroupListerBackendHandler for UserRestrictedListerBackendHandler<'a, Handler>
 {
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::test_utils::MockTestBackendHandler;
+
+    #[test]
+    fn test_admin_groups() {
+        let handler = AccessControlledBackendHandler::new(MockTestBackendHandler::new())
+            .with_admin_groups(vec!["admins".to_owned(), "ops".to_owned()]);
+        let permission = |groups: &[&str]| {
+            let groups = groups.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+            handler
+                .get_permissions_from_groups(UserId::new("bob"), groups.iter())
+                .permission
+        };
+        assert_eq!(permission(&["ops"]), Permission::Admin);
+        assert_eq!(permission(&["users", "admins"]), Permission::Admin);
+        assert_eq!(permission(&["users"]), Permission::Regular);
+        // The default admin group is only an admin group if it's configured.
+        assert_eq!(permission(&["lldap_admin"]), Permission::Regular);
+        assert_eq!(
+            permission(&["lldap_password_manager"]),
+            Permission::PasswordManager
+        );
+        assert_eq!(
+            permission(&["lldap_read_only_admin", "lldap_password_manager"]),
+            Permission::ReadonlyAdmin
+        );
+        assert_eq!(
+            permission(&["lldap_read_only_admin", "ops"]),
+            Permission::Admin
+        );
+    }
+
+    fn make_group_handler(
+        group_name: &'static str,
+    ) -> AccessControlledBackendHandler<MockTestBackendHandler> {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_group_managers()
+            .returning(|_| Ok(vec![UserId::new("bob")]));
+        // The members of the group 5 manage the group too.
+        mock.expect_get_group_manager_groups()
+            .returning(|_| Ok(vec![GroupId(5)]));
+        mock.expect_get_user_groups().returning(|user_id| {
+            let group_id = if user_id.as_str() == "patrick" {
+                GroupId(5)
+            } else {
+                GroupId(6)
+            };
+            Ok(HashSet::from([GroupDetails {
+                group_id,
+                display_name: "managers".to_owned(),
+                creation_date: chrono::Utc::now().naive_utc(),
+                uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                email: None,
+                description: None,
+            }]))
+        });
+        mock.expect_get_group_details().returning(move |group_id| {
+            Ok(GroupDetails {
+                group_id,
+                display_name: group_name.to_owned(),
+                creation_date: chrono::Utc::now().naive_utc(),
+                uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                email: None,
+                description: None,
+            })
+        });
+        AccessControlledBackendHandler::new(mock)
+    }
+
+    async fn can_manage(
+        handler: &AccessControlledBackendHandler<MockTestBackendHandler>,
+        user: &str,
+        permission: Permission,
+    ) -> bool {
+        let validation_result = ValidationResults {
+            user: UserId::new(user),
+            permission,
+            impersonated_by: None,
+        };
+        handler
+            .get_group_membership_handler(&validation_result, GroupId(3))
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn test_group_membership_handler() {
+        let handler = make_group_handler("team");
+        assert!(can_manage(&handler, "bob", Permission::Regular).await);
+        assert!(can_manage(&handler, "patrick", Permission::Regular).await);
+        assert!(!can_manage(&handler, "john", Permission::Regular).await);
+        assert!(can_manage(&handler, "admin", Permission::Admin).await);
+        assert!(!can_manage(&handler, "bob", Permission::ReadonlyAdmin).await);
+        // Managers can't grant permissions.
+        let handler = make_group_handler("lldap_password_manager");
+        assert!(!can_manage(&handler, "bob", Permission::Regular).await);
+    }
+}
@@ -16,11 +16,15 @@ use futures::future::{ok, Ready};
 use futures_util::FutureExt;
 use hmac::Hmac;
 use jwt::{SignWithKey, VerifyWithKey};
-use sha2::Sha512;
+use sha2::{Digest, Sha256, Sha512};
 use time::ext::NumericalDuration;
 use tracing::{debug, info, instrument, warn};
 
-use lldap_auth::{login, password_reset, registration, JWTClaims};
+use lldap_auth::{
+    login,
+    opaque::{self, server::ServerSetup},
+    password_reset, registration, JWTClaims,
+};
 
 use crate::{
     domain::{
@@ -30,22 +34,42 @@ use crate::{
         types::{GroupDetails, UserColumn, UserId},
     },
     infra::{
-        access_control::{ReadonlyBackendHandler, UserReadableBackendHandler, ValidationResults},
+        access_control::{
+            Permission, ReadonlyBackendHandler, UserReadableBackendHandler, ValidationResults,
+        },
+        configuration::{JwtClaim, JwtSubject},
+        password_reset_protection::{
+            check_reset_protections, is_reset_allowed, is_reset_email_domain_allowed,
+            make_challenge, rate_limit_history_start,
+        },
+        reset_notification::{get_reset_email_address, make_reset_notifiers, ResetNotification},
         tcp_backend_handler::*,
         tcp_server::{error_to_http_response, AppState, TcpError, TcpResult},
     },
 };
 
 type Token<S> = jwt::Token<jwt::Header, JWTClaims, S>;
-type SignedToken = Token<jwt::token::Signed>;
+pub(crate) type SignedToken = Token<jwt::token::Signed>;
 
-fn create_jwt(key: &Hmac<Sha512>, user: String, groups: HashSet<GroupDetails>) -> SignedToken {
-    let claims = JWTClaims {
-        exp: Utc::now() + chrono::Duration::days(1),
+const IMPERSONATION_TOKEN_LIFETIME_MINUTES: i64 = 15;
+/// How long the session tokens are valid, before they need to be refreshed.
+pub(crate) const JWT_LIFETIME_DAYS: i64 = 1;
+
+fn make_claims(user: String, groups: HashSet<GroupDetails>) -> JWTClaims {
+    JWTClaims {
+        exp: Utc::now() + chrono::Duration::days(JWT_LIFETIME_DAYS),
         iat: Utc::now(),
         user,
         groups: groups.into_iter().map(|g| g.display_name).collect(),
-    };
+        sub: None,
+        email: None,
+        display_name: None,
+        impersonated_by: None,
+        is_admin: false,
+    }
+}
+
+fn sign_claims(key: &Hmac<Sha512>, claims: JWTClaims) -> SignedToken {
     let header = jwt::Header {
         algorithm: jwt::AlgorithmType::Hs512,
         ..Default::default()
@@ -53,17 +77,110 @@ fn create_jwt(key: &Hmac<Sha512>, user: String, groups: HashSet<GroupDetails>) -
     jwt::Token::new(header, claims).sign_with_key(key).unwrap()
 }
 
+fn create_jwt(key: &Hmac<Sha512>, user: String, groups: HashSet<GroupDetails>) -> SignedToken {
+    sign_claims(key, make_claims(user, groups))
+}
+
+/// The claims of the session token of a user, with the subject and the claims of the
+/// `jwt_options`. The token doesn't outlive the expiry date of the account, and the expired
+/// accounts get none.
+async fn make_user_claims<Backend: BackendHandler>(
+    data: &AppState<Backend>,
+    user_id: &UserId,
+    groups: HashSet<GroupDetails>,
+) -> Result<JWTClaims, DomainError> {
+    let options = &data.jwt_options;
+    let user = data
+        .get_readonly_handler()
+        .get_user_details(user_id)
+        .await?;
+    let is_admin = data
+        .backend_handler
+        .get_permissions_from_groups(user_id.clone(), groups.iter().map(|g| &g.display_name))
+        .permission
+        == Permission::Admin;
+    let mut claims = make_claims(user_id.to_string(), groups);
+    claims.is_admin = is_admin;
+    if let Some(expiry_date) = user.expiry_date {
+        let expiry_date = Utc.from_utc_datetime(&expiry_date);
+        if expiry_date <= claims.iat {
+            return Err(DomainError::AuthenticationError(format!(
+                r#"The account of "{}" has expired"#,
+                user_id
+            )));
+        }
+        claims.exp = std::cmp::min(claims.exp, expiry_date);
+    }
+    claims.sub = Some(match options.subject {
+        JwtSubject::UserId => user.user_id.to_string(),
+        JwtSubject::Uuid => user.uuid.to_string(),
+    });
+    for claim in &options.claims {
+        match claim {
+            JwtClaim::Email => claims.email = Some(user.email.clone()),
+            JwtClaim::DisplayName => claims.display_name = user.display_name.clone(),
+        }
+    }
+    Ok(claims)
+}
+
+async fn create_user_jwt<Backend: BackendHandler>(
+    data: &AppState<Backend>,
+    user_id: &UserId,
+    groups: HashSet<GroupDetails>,
+) -> TcpResult<SignedToken> {
+    let claims = make_user_claims(data, user_id, groups).await?;
+    Ok(sign_claims(&data.jwt_keys.signing_key(), claims))
+}
+
+/// A token acting as the user, marked as impersonated by the admin. It can't be refreshed, and
+/// only the regular users can be impersonated.
+pub(crate) async fn create_impersonation_jwt<Backend: BackendHandler>(
+    data: &AppState<Backend>,
+    admin: &UserId,
+    user_id: &UserId,
+) -> Result<SignedToken, DomainError> {
+    let groups = data.get_readonly_handler().get_user_groups(user_id).await?;
+    let permission = data
+        .backend_handler
+        .get_permissions_from_groups(user_id.clone(), groups.iter().map(|g| &g.display_name))
+        .permission;
+    if permission != Permission::Regular {
+        return Err(DomainError::InvalidInput(format!(
+            "Cannot impersonate `{}`: only the regular users can be impersonated",
+            user_id
+        )));
+    }
+    let mut claims = make_user_claims(data, user_id, groups).await?;
+    claims.exp = std::cmp::min(
+        claims.exp,
+        claims.iat + chrono::Duration::minutes(IMPERSONATION_TOKEN_LIFETIME_MINUTES),
+    );
+    claims.impersonated_by = Some(admin.to_string());
+    Ok(sign_claims(&data.jwt_keys.signing_key(), claims))
+}
+
+fn hash_token(token: &str) -> u64 {
+    let mut s = DefaultHasher::new();
+    token.hash(&mut s);
+    s.finish()
+}
+
 fn parse_refresh_token(token: &str) -> TcpResult<(u64, UserId)> {
     match token.split_once('+') {
         None => Err(DomainError::AuthenticationError("Invalid refresh token".to_string()).into()),
-        Some((token, u)) => {
-            let refresh_token_hash = {
-                let mut s = DefaultHasher::new();
-                token.hash(&mut s);
-                s.finish()
-            };
-            Ok((refresh_token_hash, UserId::new(u)))
-        }
+        Some((token, u)) => Ok((hash_token(token), UserId::new(u))),
+    }
+}
+
+/// Only the admin can log in during the first run setup.
+fn check_first_run_login<Backend>(data: &AppState<Backend>, user_id: &UserId) -> TcpResult<()> {
+    if data.first_run.can_log_in(user_id) {
+        Ok(())
+    } else {
+        Err(TcpError::UnauthorizedError(
+            "The admin password must be changed before the other users can log in".to_string(),
+        ))
     }
 }
 
@@ -88,7 +205,6 @@ async fn get_refresh<Backend>(
 where
     Backend: TcpBackendHandler + BackendHandler + 'static,
 {
-    let jwt_key = &data.jwt_key;
     let (refresh_token_hash, user) = get_refresh_token(request)?;
     let found = data
         .get_tcp_handler()
@@ -99,26 +215,45 @@ where
             "Invalid refresh token".to_string(),
         )));
     }
-    Ok(data
-        .get_readonly_handler()
-        .get_user_groups(&user)
-        .await
-        .map(|groups| create_jwt(jwt_key, user.to_string(), groups))
-        .map(|token| {
-            HttpResponse::Ok()
-                .cookie(
-                    Cookie::build("token", token.as_str())
-                        .max_age(1.days())
-                        .path("/")
-                        .http_only(true)
-                        .same_site(SameSite::Strict)
-                        .finish(),
-                )
-                .json(&login::ServerLoginResponse {
-                    token: token.as_str().to_owned(),
-                    refresh_token: None,
-                })
-        })?)
+    check_first_run_login(&data, &user)?;
+    let now = Utc::now();
+    if !data
+        .session_activity
+        .touch_refresh_token(refresh_token_hash, now)
+    {
+        data.get_tcp_handler()
+            .delete_refresh_token(refresh_token_hash)
+            .await?;
+        return Err(TcpError::UnauthorizedError(
+            "Session expired due to inactivity".to_string(),
+        ));
+    }
+    let groups = data.get_readonly_handler().get_user_groups(&user).await?;
+    let token = match create_user_jwt(&data, &user, groups).await {
+        // The account expired since the login: the session is over.
+        Err(TcpError::DomainError(DomainError::AuthenticationError(e))) => {
+            data.get_tcp_handler()
+                .delete_refresh_token(refresh_token_hash)
+                .await?;
+            return Err(TcpError::UnauthorizedError(e));
+        }
+        token => token?,
+    };
+    data.session_activity
+        .add_jwt(refresh_token_hash, hash_token(token.as_str()), now);
+    Ok(HttpResponse::Ok()
+        .cookie(
+            Cookie::build("token", token.as_str())
+                .max_age(1.days())
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .finish(),
+        )
+        .json(&login::ServerLoginResponse {
+            token: token.as_str().to_owned(),
+            refresh_token: None,
+        }))
 }
 
 async fn get_refresh_handler<Backend>(
@@ -145,6 +280,19 @@ where
         .match_info()
         .get("user_id")
         .ok_or_else(|| TcpError::BadRequest("Missing user ID".to_string()))?;
+    let protection_params =
+        web::Query::<password_reset::ResetProtectionParams>::from_query(request.query_string())
+            .map_err(|e| TcpError::BadRequest(format!("{:#?}", e)))?;
+    check_reset_protections(
+        &data.password_reset_options,
+        &data.jwt_keys.signing_key(),
+        &data.used_reset_challenges,
+        user_string,
+        &protection_params,
+        Utc::now(),
+    )
+    .await
+    .map_err(TcpError::BadRequest)?;
     let user_results = data
         .get_readonly_handler()
         .list_users(
@@ -163,6 +311,35 @@ where
         ));
     }
     let user = &user_results[0].user;
+    let email = get_reset_email_address(user, &data.password_reset_options);
+    if !is_reset_email_domain_allowed(&data.password_reset_options, &email) {
+        debug!(user_id = %user.user_id, "Password reset email domain not allowed");
+        // Don't reveal that the user exists.
+        return Ok(());
+    }
+    // The emails sent by all the instances count towards the limits.
+    let now = Utc::now().naive_utc();
+    let recent_requests = data
+        .get_tcp_handler()
+        .get_password_reset_requests(rate_limit_history_start(&data.password_reset_options, now))
+        .await?;
+    if !is_reset_allowed(
+        &recent_requests,
+        &user.user_id,
+        &email,
+        &data.password_reset_options,
+        now,
+    ) {
+        // Don't reveal that the user exists.
+        return Ok(());
+    }
+    data.get_tcp_handler()
+        .add_password_reset_request(PasswordResetRequest {
+            user_id: user.user_id.clone(),
+            address: email.to_lowercase(),
+            date: now,
+        })
+        .await?;
     let token = match data
         .get_tcp_handler()
         .start_password_reset(&user.user_id)
@@ -171,24 +348,47 @@ where
         None => return Ok(()),
         Some(token) => token,
     };
-    if let Err(e) = super::mail::send_password_reset_email(
+    // The notifications are sent in the background, the emails with retries. The response
+    // doesn't depend on the result, to not reveal anything about the user or the configuration:
+    // the errors are only logged, with an ID to find them.
+    let correlation_id = uuid::Uuid::new_v4();
+    info!(%correlation_id, user_id = %user.user_id, "Sending a password reset notification");
+    let notification = ResetNotification::new(
+        user.user_id.to_string(),
+        email,
         user.display_name
-            .as_deref()
-            .unwrap_or_else(|| user.user_id.as_str()),
-        &user.email,
-        &token,
+            .clone()
+            .unwrap_or_else(|| user.user_id.to_string()),
+        user.attributes
+            .iter()
+            .find(|a| a.name == "locale")
+            .and_then(|a| a.value.convert_to::<String>().ok()),
+        token,
+        &data.server_url,
+    );
+    let notifiers = make_reset_notifiers(
         &data.server_url,
+        &data.default_locale,
         &data.mail_options,
-    )
-    .await
-    {
-        warn!("Error sending email: {:#?}", e);
-        info!("Reset token: {}", token);
-        return Err(TcpError::InternalServerError(format!(
-            "Could not send email: {}",
-            e
-        )));
-    }
+        &data.password_reset_options,
+    );
+    actix_rt::spawn(async move {
+        let mut delivered = false;
+        for notifier in notifiers {
+            match notifier.notify(&notification).await {
+                Ok(()) => delivered = true,
+                Err(e) => warn!(
+                    %correlation_id,
+                    "Error sending the password reset {}: {:#}",
+                    notifier.name(),
+                    e
+                ),
+            }
+        }
+        if !delivered {
+            info!(%correlation_id, "Reset token: {}", notification.token);
+        }
+    });
     Ok(())
 }
 
@@ -205,6 +405,74 @@ where
         .unwrap_or_else(error_to_http_response)
 }
 
+/// The OPAQUE parameters advertised to the clients.
+pub(crate) fn make_opaque_configuration(
+    server_setup: &ServerSetup,
+) -> login::ServerOpaqueConfiguration {
+    login::ServerOpaqueConfiguration {
+        version: opaque::OPAQUE_VERSION.to_owned(),
+        cipher_suite: opaque::DefaultSuite::parameters(),
+        server_public_key: server_setup.keypair().public().to_vec(),
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub(crate) fn make_server_key_fingerprint(
+    server_setup: &ServerSetup,
+) -> login::ServerKeyFingerprint {
+    let server_public_key = server_setup.keypair().public().to_vec();
+    login::ServerKeyFingerprint {
+        public_key_fingerprint: sha256_hex(&server_public_key),
+        server_setup_fingerprint: sha256_hex(&server_setup.serialize()),
+        server_public_key,
+    }
+}
+
+async fn get_server_key_fingerprint_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+) -> HttpResponse
+where
+    Backend: BackendHandler + 'static,
+{
+    HttpResponse::Ok().json(&data.server_key_fingerprint)
+}
+
+async fn get_opaque_configuration_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+) -> HttpResponse
+where
+    Backend: BackendHandler + 'static,
+{
+    HttpResponse::Ok().json(&data.opaque_configuration)
+}
+
+/// The challenge is only valid for a reset of the same user ID or email.
+async fn get_password_reset_challenge_handler<Backend>(
+    data: web::Data<AppState<Backend>>,
+    request: HttpRequest,
+) -> HttpResponse
+where
+    Backend: TcpBackendHandler + BackendHandler + 'static,
+{
+    let user_string = match request.match_info().get("user_id") {
+        None => return HttpResponse::BadRequest().body("Missing user ID"),
+        Some(user_string) => user_string,
+    };
+    match data.password_reset_options.proof_of_work_difficulty {
+        None => HttpResponse::NotFound().finish(),
+        Some(difficulty) => HttpResponse::Ok().json(password_reset::ResetChallengeResponse {
+            challenge: make_challenge(&data.jwt_keys.signing_key(), user_string, Utc::now()),
+            difficulty,
+        }),
+    }
+}
+
 #[instrument(skip_all, level = "debug")]
 async fn get_password_reset_step2<Backend>(
     data: web::Data<AppState<Backend>>,
@@ -230,7 +498,7 @@ where
         .delete_password_reset_token(token)
         .await;
     let groups = HashSet::new();
-    let token = create_jwt(&data.jwt_key, user_id.to_string(), groups);
+    let token = create_jwt(&data.jwt_keys.signing_key(), user_id.to_string(), groups);
     Ok(HttpResponse::Ok()
         .cookie(
             Cookie::build("token", token.as_str())
@@ -271,6 +539,7 @@ where
     data.get_tcp_handler()
         .delete_refresh_token(refresh_token_hash)
         .await?;
+    data.session_activity.remove_session(refresh_token_hash);
     let new_blacklisted_jwts = data.get_tcp_handler().blacklist_jwts(&user).await?;
     let mut jwt_blacklist = data.jwt_blacklist.write().unwrap();
     for jwt in new_blacklisted_jwts {
@@ -337,11 +606,19 @@ async fn get_login_successful_response<Backend>(
 where
     Backend: TcpBackendHandler + BackendHandler,
 {
+    check_first_run_login(data, name)?;
     // The authentication was successful, we need to fetch the groups to create the JWT
     // token.
     let groups = data.get_readonly_handler().get_user_groups(name).await?;
     let (refresh_token, max_age) = data.get_tcp_handler().create_refresh_token(name).await?;
-    let token = create_jwt(&data.jwt_key, name.to_string(), groups);
+    let token = create_user_jwt(data, name, groups).await?;
+    let now = Utc::now();
+    data.session_activity.start_session(
+        hash_token(&refresh_token),
+        hash_token(token.as_str()),
+        now + max_age,
+        now,
+    );
     let refresh_token_plus_name = refresh_token + "+" + name.as_str();
 
     Ok(HttpResponse::Ok()
@@ -481,12 +758,19 @@ where
         .get_user_groups(&user_id)
         .await?
         .iter()
-        .any(|g| g.display_name == "lldap_admin");
+        .any(|g| data.backend_handler.is_admin_group(&g.display_name));
     if !validation_result.can_change_password(&user_id, user_is_admin) {
         return Err(TcpError::UnauthorizedError(
             "Not authorized to change the user's password".to_string(),
         ));
     }
+    if let Some(admin) = &validation_result.impersonated_by {
+        warn!(
+            user = %user_id,
+            impersonated_by = %admin,
+            "Password change started with an impersonation token"
+        );
+    }
     Ok(data
         .get_opaque_handler()
         .registration_start(registration_start_request)
@@ -513,11 +797,12 @@ async fn opaque_register_finish<Backend>(
     request: web::Json<registration::ClientRegistrationFinishRequest>,
 ) -> TcpResult<HttpResponse>
 where
-    Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + 'static,
+    Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + LoginHandler + 'static,
 {
     data.get_opaque_handler()
         .registration_finish(request.into_inner())
         .await?;
+    data.first_run.refresh(data.get_login_handler()).await;
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -526,7 +811,7 @@ async fn opaque_register_finish_handler<Backend>(
     request: web::Json<registration::ClientRegistrationFinishRequest>,
 ) -> HttpResponse
 where
-    Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + 'static,
+    Backend: TcpBackendHandler + BackendHandler + OpaqueHandler + LoginHandler + 'static,
 {
     opaque_register_finish(data, request)
         .await
@@ -594,8 +879,13 @@ pub(crate) fn check_if_token_is_valid<Backend: BackendHandler>(
     state: &AppState<Backend>,
     token_str: &str,
 ) -> Result<ValidationResults, actix_web::Error> {
-    let token: Token<_> = VerifyWithKey::verify_with_key(token_str, &state.jwt_key)
-        .map_err(|_| ErrorUnauthorized("Invalid JWT"))?;
+    // Tokens signed with a previous secret are still valid, for rotation.
+    let token: Token<_> = state
+        .jwt_keys
+        .verification_keys(Utc::now())
+        .iter()
+        .find_map(|key| VerifyWithKey::verify_with_key(token_str, key).ok())
+        .ok_or_else(|| ErrorUnauthorized("Invalid JWT"))?;
     if token.claims().exp.lt(&Utc::now()) {
         return Err(ErrorUnauthorized("Expired JWT"));
     }
@@ -605,18 +895,35 @@ pub(crate) fn check_if_token_is_valid<Backend: BackendHandler>(
             token.header().algorithm
         )));
     }
-    let jwt_hash = {
-        let mut s = DefaultHasher::new();
-        token_str.hash(&mut s);
-        s.finish()
-    };
+    let jwt_hash = hash_token(token_str);
     if state.jwt_blacklist.read().unwrap().contains(&jwt_hash) {
         return Err(ErrorUnauthorized("JWT was logged out"));
     }
-    Ok(state.backend_handler.get_permissions_from_groups(
-        UserId::new(&token.claims().user),
-        token.claims().groups.iter(),
-    ))
+    let user = UserId::new(&token.claims().user);
+    let valid_after = state.tokens_valid_after.read().unwrap().get(&user).copied();
+    if matches!(valid_after, Some(valid_after) if token.claims().iat <= valid_after) {
+        return Err(ErrorUnauthorized("JWT was invalidated"));
+    }
+    if !state
+        .session_activity
+        .touch_jwt(jwt_hash, token.claims().exp, Utc::now())
+    {
+        return Err(ErrorUnauthorized("Session expired due to inactivity"));
+    }
+    let mut validation_result = state
+        .backend_handler
+        .get_permissions_from_groups(user, token.claims().groups.iter());
+    if let Some(admin) = &token.claims().impersonated_by {
+        let admin = UserId::new(admin);
+        // Every request made with the token is attributed to the admin.
+        warn!(
+            user = %validation_result.user,
+            impersonated_by = %admin,
+            "Request made with an impersonation token"
+        );
+        validation_result.impersonated_by = Some(admin);
+    }
+    Ok(validation_result)
 }
 
 pub fn configure_server<Backend>(cfg: &mut web::ServiceConfig, enable_password_reset: bool)
@@ -628,6 +935,14 @@ where
             web::resource("/opaque/login/start")
                 .route(web::post().to(opaque_login_start::<Backend>)),
         )
+        .service(
+            web::resource("/opaque/configuration")
+                .route(web::get().to(get_opaque_configuration_handler::<Backend>)),
+        )
+        .service(
+            web::resource("/opaque/fingerprint")
+                .route(web::get().to(get_server_key_fingerprint_handler::<Backend>)),
+        )
         .service(
             web::resource("/opaque/login/finish")
                 .route(web::post().to(opaque_login_finish_handler::<Backend>)),
@@ -651,6 +966,10 @@ where
         );
     if enable_password_reset {
         cfg.service(
+            web::resource("/reset/challenge/{user_id}")
+                .route(web::get().to(get_password_reset_challenge_handler::<Backend>)),
+        )
+        .service(
             web::resource("/reset/step1/{user_id}")
                 .route(web::get().to(get_password_reset_step1_handler::<Backend>)),
         )
@@ -660,3 +979,438 @@ where
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::{
+        access_control::AccessControlledBackendHandler,
+        configuration::{JwtOptions, MailOptions, PasswordResetOptions},
+        first_run::FirstRunGate,
+        graphql::api::SessionInvalidator,
+        jwt_keys::JwtKeys,
+        session_activity::SessionActivity,
+        test_utils::MockTestBackendHandler,
+    };
+    use mockall::predicate::{always, eq};
+    use secstr::SecUtf8;
+
+    fn make_state(secret: &str, previous_secrets: &[&str]) -> AppState<MockTestBackendHandler> {
+        make_state_with_handler(MockTestBackendHandler::new(), secret, previous_secrets)
+    }
+
+    fn make_state_with_handler(
+        handler: MockTestBackendHandler,
+        secret: &str,
+        previous_secrets: &[&str],
+    ) -> AppState<MockTestBackendHandler> {
+        let server_setup = ServerSetup::new(&mut rand::rngs::OsRng);
+        AppState {
+            backend_handler: AccessControlledBackendHandler::new(handler),
+            jwt_keys: JwtKeys::new(
+                &SecUtf8::from(secret),
+                &previous_secrets
+                    .iter()
+                    .map(|s| SecUtf8::from(*s))
+                    .collect::<Vec<_>>(),
+            ),
+            jwt_options: JwtOptions::default(),
+            jwt_blacklist: Default::default(),
+            tokens_valid_after: Default::default(),
+            session_activity: Default::default(),
+            used_reset_challenges: Default::default(),
+            server_url: url::Url::parse("http://localhost").unwrap(),
+            mail_options: MailOptions::default(),
+            password_reset_options: PasswordResetOptions::default(),
+            default_locale: "en".to_owned(),
+            ldap_connections: Default::default(),
+            diagnostics_options: Default::default(),
+            opaque_configuration: make_opaque_configuration(&server_setup),
+            server_key_fingerprint: make_server_key_fingerprint(&server_setup),
+            first_run: Default::default(),
+            enable_impersonation: false,
+        }
+    }
+
+    fn expect_user_details(
+        mock: &mut MockTestBackendHandler,
+        expiry_date: Option<chrono::NaiveDateTime>,
+    ) {
+        mock.expect_get_user_details().returning(move |user_id| {
+            Ok(crate::domain::types::User {
+                user_id: user_id.clone(),
+                expiry_date,
+                ..Default::default()
+            })
+        });
+    }
+
+    #[test]
+    fn test_jwt_secret_rotation() {
+        let old_state = make_state("old secret", &[]);
+        let old_token = create_jwt(
+            &old_state.jwt_keys.signing_key(),
+            "bob".to_string(),
+            HashSet::new(),
+        );
+        let state = make_state("new secret", &["old secret"]);
+        assert_eq!(
+            check_if_token_is_valid(&state, old_token.as_str())
+                .unwrap()
+                .user,
+            UserId::new("bob")
+        );
+        // New tokens are signed with the new secret.
+        let new_token = create_jwt(
+            &state.jwt_keys.signing_key(),
+            "bob".to_string(),
+            HashSet::new(),
+        );
+        check_if_token_is_valid(&old_state, new_token.as_str()).unwrap_err();
+        let new_state = make_state("new secret", &[]);
+        check_if_token_is_valid(&new_state, new_token.as_str()).unwrap();
+        // Once the old secret is dropped, its tokens are rejected.
+        check_if_token_is_valid(&new_state, old_token.as_str()).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_user_sessions() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_invalidate_user_sessions()
+            .with(eq(UserId::new("bob")), always())
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        let state = make_state_with_handler(mock, "secret", &[]);
+        let bob_token = create_jwt(
+            &state.jwt_keys.signing_key(),
+            "bob".to_string(),
+            HashSet::new(),
+        );
+        let john_token = create_jwt(
+            &state.jwt_keys.signing_key(),
+            "john".to_string(),
+            HashSet::new(),
+        );
+        check_if_token_is_valid(&state, bob_token.as_str()).unwrap();
+        state
+            .invalidate_user_sessions(&UserId::new("bob"))
+            .await
+            .unwrap();
+        check_if_token_is_valid(&state, bob_token.as_str()).unwrap_err();
+        // The other users are not affected.
+        check_if_token_is_valid(&state, john_token.as_str()).unwrap();
+        // Tokens issued after the invalidation are valid.
+        let new_token = create_jwt(
+            &state.jwt_keys.signing_key(),
+            "bob".to_string(),
+            HashSet::new(),
+        );
+        check_if_token_is_valid(&state, new_token.as_str()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_idle_timeout() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_check_token().returning(|_, _| Ok(true));
+        mock.expect_delete_refresh_token()
+            .with(eq(hash_token("idle")))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut state = make_state_with_handler(mock, "secret", &[]);
+        state.session_activity = SessionActivity::new(Some(chrono::Duration::minutes(30)));
+        let new_jwt = |refresh_token: &str, last_activity: DateTime<Utc>| {
+            let token = create_jwt(
+                &state.jwt_keys.signing_key(),
+                "bob".to_string(),
+                HashSet::new(),
+            );
+            state.session_activity.start_session(
+                hash_token(refresh_token),
+                hash_token(token.as_str()),
+                last_activity + chrono::Duration::days(30),
+                last_activity,
+            );
+            token
+        };
+        let active_token = new_jwt("active", Utc::now() - chrono::Duration::minutes(20));
+        let idle_token = new_jwt("idle", Utc::now() - chrono::Duration::minutes(40));
+        // Each request counts as activity.
+        check_if_token_is_valid(&state, active_token.as_str()).unwrap();
+        check_if_token_is_valid(&state, active_token.as_str()).unwrap();
+        check_if_token_is_valid(&state, idle_token.as_str()).unwrap_err();
+        // The refresh token of the idle session is deleted.
+        let request = actix_web::test::TestRequest::default()
+            .insert_header(("refresh-token", "idle+bob"))
+            .to_http_request();
+        let response = get_refresh(web::Data::new(state), request).await;
+        assert!(matches!(response, Err(TcpError::UnauthorizedError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_impersonation_token() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| Ok(HashSet::new()));
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("john")))
+            .returning(|_| {
+                Ok(HashSet::from([GroupDetails {
+                    group_id: crate::domain::types::GroupId(1),
+                    display_name: "lldap_admin".to_owned(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    email: None,
+                    description: None,
+                }]))
+            });
+        expect_user_details(&mut mock, None);
+        let state = make_state_with_handler(mock, "secret", &[]);
+        let admin = UserId::new("admin");
+        let token = create_impersonation_jwt(&state, &admin, &UserId::new("bob"))
+            .await
+            .unwrap();
+        let claims = token.claims();
+        assert_eq!(claims.user, "bob");
+        assert_eq!(claims.sub.as_deref(), Some("bob"));
+        assert_eq!(claims.impersonated_by.as_deref(), Some("admin"));
+        assert_eq!(claims.exp - claims.iat, chrono::Duration::minutes(15));
+        // The token acts as the user.
+        let validation = check_if_token_is_valid(&state, token.as_str()).unwrap();
+        assert_eq!(validation.user, UserId::new("bob"));
+        assert_eq!(validation.permission, Permission::Regular);
+        assert_eq!(validation.impersonated_by, Some(admin.clone()));
+        // The admins can't be impersonated.
+        create_impersonation_jwt(&state, &admin, &UserId::new("john"))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_first_run_login() {
+        let admin = UserId::new("admin");
+        let bob = UserId::new("bob");
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .returning(|_| Ok(HashSet::new()));
+        mock.expect_create_refresh_token()
+            .returning(|_| Ok(("refresh".to_owned(), chrono::Duration::days(30))));
+        expect_user_details(&mut mock, None);
+        // The admin password is changed.
+        mock.expect_bind().times(1).return_once(|_| {
+            Err(DomainError::AuthenticationError(
+                "Wrong password".to_string(),
+            ))
+        });
+        let mut state = make_state_with_handler(mock, "secret", &[]);
+        state.first_run = FirstRunGate::new(Some(admin.clone()));
+        let data = web::Data::new(state);
+        // Only the admin can log in, to change the password.
+        get_login_successful_response(&data, &bob)
+            .await
+            .unwrap_err();
+        get_login_successful_response(&data, &admin).await.unwrap();
+        data.first_run.refresh(data.get_login_handler()).await;
+        get_login_successful_response(&data, &bob).await.unwrap();
+    }
+
+    async fn get_login_claims(
+        handler: MockTestBackendHandler,
+        jwt_options: JwtOptions,
+    ) -> JWTClaims {
+        let mut state = make_state_with_handler(handler, "secret", &[]);
+        state.jwt_options = jwt_options;
+        get_login_claims_from_state(state).await
+    }
+
+    async fn get_login_claims_from_state(state: AppState<MockTestBackendHandler>) -> JWTClaims {
+        let data = web::Data::new(state);
+        let response = get_login_successful_response(&data, &UserId::new("bob"))
+            .await
+            .unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let token = serde_json::from_slice::<login::ServerLoginResponse>(&body)
+            .unwrap()
+            .token;
+        token
+            .as_str()
+            .verify_with_key(&data.jwt_keys.signing_key())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_jwt_subject_and_claims() {
+        let make_mock = || {
+            let mut mock = MockTestBackendHandler::new();
+            mock.expect_get_user_groups().returning(|_| {
+                Ok(HashSet::from([GroupDetails {
+                    group_id: crate::domain::types::GroupId(1),
+                    display_name: "Best Group".to_owned(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    email: None,
+                    description: None,
+                }]))
+            });
+            mock.expect_create_refresh_token()
+                .returning(|_| Ok(("refresh".to_owned(), chrono::Duration::days(30))));
+            mock
+        };
+        // By default, the subject is the user ID, and there are no extra claims.
+        let mut mock = make_mock();
+        expect_user_details(&mut mock, None);
+        let claims = get_login_claims(mock, JwtOptions::default()).await;
+        assert_eq!(claims.user, "bob");
+        assert_eq!(claims.groups, HashSet::from(["Best Group".to_owned()]));
+        assert_eq!(claims.sub.as_deref(), Some("bob"));
+        assert_eq!(claims.email, None);
+        assert_eq!(claims.display_name, None);
+        assert!(!claims.is_admin);
+
+        let mut mock = make_mock();
+        mock.expect_get_user_details()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| {
+                Ok(crate::domain::types::User {
+                    user_id: UserId::new("bob"),
+                    email: "bob@bobmail.bob".to_owned(),
+                    display_name: Some("Bôb Böbberson".to_owned()),
+                    uuid: crate::uuid!("698e1d5f-7a40-3151-8745-b9b8a37839da"),
+                    ..Default::default()
+                })
+            });
+        let claims = get_login_claims(
+            mock,
+            JwtOptions {
+                subject: JwtSubject::Uuid,
+                claims: vec![JwtClaim::Email, JwtClaim::DisplayName],
+            },
+        )
+        .await;
+        // The user is still there, for the web UI.
+        assert_eq!(claims.user, "bob");
+        assert_eq!(
+            claims.sub.as_deref(),
+            Some("698e1d5f-7a40-3151-8745-b9b8a37839da")
+        );
+        assert_eq!(claims.email.as_deref(), Some("bob@bobmail.bob"));
+        assert_eq!(claims.display_name.as_deref(), Some("Bôb Böbberson"));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_admin_claim() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups().returning(|_| {
+            Ok(HashSet::from([GroupDetails {
+                group_id: crate::domain::types::GroupId(1),
+                display_name: "ops".to_owned(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                email: None,
+                description: None,
+            }]))
+        });
+        mock.expect_create_refresh_token()
+            .returning(|_| Ok(("refresh".to_owned(), chrono::Duration::days(30))));
+        expect_user_details(&mut mock, None);
+        let mut state = make_state_with_handler(mock, "secret", &[]);
+        state.backend_handler = state
+            .backend_handler
+            .with_admin_groups(vec!["lldap_admin".to_owned(), "ops".to_owned()]);
+        // The web UI shows the admin pages to the members of any admin group.
+        let claims = get_login_claims_from_state(state).await;
+        assert!(claims.is_admin);
+    }
+
+    #[tokio::test]
+    async fn test_account_expiry() {
+        let make_mock = |expiry_date| {
+            let mut mock = MockTestBackendHandler::new();
+            mock.expect_get_user_groups()
+                .returning(|_| Ok(HashSet::new()));
+            mock.expect_create_refresh_token()
+                .returning(|_| Ok(("refresh".to_owned(), chrono::Duration::days(30))));
+            mock.expect_check_token().returning(|_, _| Ok(true));
+            expect_user_details(&mut mock, Some(expiry_date));
+            mock
+        };
+        // The token doesn't outlive the account.
+        let expiry_date = Utc::now() + chrono::Duration::hours(1);
+        let claims =
+            get_login_claims(make_mock(expiry_date.naive_utc()), JwtOptions::default()).await;
+        assert_eq!(claims.exp.timestamp(), expiry_date.timestamp());
+        // Once the account has expired, the session can't be refreshed anymore.
+        let mut mock = make_mock((Utc::now() - chrono::Duration::seconds(1)).naive_utc());
+        mock.expect_delete_refresh_token()
+            .with(eq(hash_token("refresh")))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let request = actix_web::test::TestRequest::default()
+            .insert_header(("refresh-token", "refresh+bob"))
+            .to_http_request();
+        let data = web::Data::new(make_state_with_handler(mock, "secret", &[]));
+        let response = get_refresh(data, request).await;
+        assert!(matches!(response, Err(TcpError::UnauthorizedError(_))));
+    }
+
+    #[test]
+    fn test_opaque_configuration() {
+        let server_setup = ServerSetup::new(&mut rand::rngs::OsRng);
+        let configuration = make_opaque_configuration(&server_setup);
+        assert_eq!(
+            configuration.server_public_key,
+            server_setup.keypair().public().to_vec()
+        );
+        assert_eq!(configuration.version, "opaque-ke 0.6");
+        assert_eq!(
+            configuration.cipher_suite,
+            opaque::CipherSuiteParameters {
+                group: "ristretto255".to_owned(),
+                key_exchange: "3DH".to_owned(),
+                hash: "SHA-512".to_owned(),
+                slow_hash: opaque::SlowHashParameters {
+                    variant: "argon2id".to_owned(),
+                    version: 0x13,
+                    mem_cost: 50 * 1024,
+                    time_cost: 1,
+                    lanes: 1,
+                    hash_length: 128,
+                    salt: "lldap_opaque_salt".to_owned(),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_server_key_fingerprint() {
+        let server_setup = ServerSetup::new(&mut rand::rngs::OsRng);
+        let fingerprint = make_server_key_fingerprint(&server_setup);
+        assert_eq!(
+            fingerprint.server_public_key,
+            server_setup.keypair().public().to_vec()
+        );
+        assert_eq!(fingerprint.public_key_fingerprint.len(), 64);
+        assert_eq!(fingerprint.server_setup_fingerprint.len(), 64);
+        // Stable for a given setup, e.g. on another replica.
+        let reloaded = ServerSetup::deserialize(&server_setup.serialize()).unwrap();
+        assert_eq!(make_server_key_fingerprint(&reloaded), fingerprint);
+        // Different for another setup.
+        let other = make_server_key_fingerprint(&ServerSetup::new(&mut rand::rngs::OsRng));
+        assert_ne!(
+            other.public_key_fingerprint,
+            fingerprint.public_key_fingerprint
+        );
+        assert_ne!(
+            other.server_setup_fingerprint,
+            fingerprint.server_setup_fingerprint
+        );
+        // The private key is not exposed.
+        let private_key = server_setup.keypair().private().to_vec();
+        let serialized = serde_json::to_vec(&fingerprint).unwrap();
+        assert!(!serialized
+            .windows(private_key.len())
+            .any(|w| w == private_key.as_slice()));
+    }
+}
@@ -0,0 +1,52 @@
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+/// What exactly is running, returned by `/version`. The git hash and the build date are set by the
+/// build script.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_date: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_hash: env!("LLDAP_GIT_HASH"),
+    build_date: env!("LLDAP_BUILD_DATE"),
+};
+
+impl BuildInfo {
+    /// The `vendorVersion` advertised in the root DSE.
+    pub fn vendor_version(&self) -> String {
+        format!("lldap_{} ({})", self.version, self.git_hash)
+    }
+}
+
+pub async fn version_handler() -> HttpResponse {
+    HttpResponse::Ok().json(BUILD_INFO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn test_version_handler() {
+        let app =
+            test::init_service(App::new().route("/version", web::get().to(version_handler))).await;
+        let request = test::TestRequest::get().uri("/version").to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "git_hash": BUILD_INFO.git_hash,
+                "build_date": BUILD_INFO.build_date,
+            })
+        );
+        assert!(!BUILD_INFO.git_hash.is_empty());
+        assert!(!BUILD_INFO.build_date.is_empty());
+    }
+}
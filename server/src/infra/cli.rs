@@ -27,9 +27,18 @@ pub enum Command {
     /// Send a test email.
     #[clap(name = "send_test_email")]
     SendTestEmail(TestEmailOpts),
+    /// Connect to the LDAP server, bind as a user and run a sample search.
+    #[clap(name = "test-ldap", alias = "test_ldap")]
+    TestLdap(TestLdapOpts),
     /// Create database schema.
     #[clap(name = "create_schema")]
-    CreateSchema(RunOpts),
+    CreateSchema(CreateSchemaOpts),
+    /// Check the consistency of the database, and optionally repair it.
+    #[clap(name = "doctor", alias = "fsck")]
+    Doctor(DoctorOpts),
+    /// Generate a self-signed certificate for LDAPS, for testing.
+    #[clap(name = "generate-cert", alias = "generate_cert")]
+    GenerateCert(GenerateCertOpts),
 }
 
 #[derive(Debug, Parser, Clone)]
@@ -109,6 +118,86 @@ pub struct TestEmailOpts {
     pub smtp_opts: SmtpOpts,
 }
 
+#[derive(Debug, Parser, Clone)]
+pub struct TestLdapOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// User to bind as: either a user id or a full DN.
+    #[clap(long, env = "LLDAP_TEST_LDAP_USER")]
+    pub user: String,
+
+    /// Password of the user.
+    #[clap(long, env = "LLDAP_TEST_LDAP_PASSWORD")]
+    pub password: String,
+
+    /// Host of the LDAP server.
+    #[clap(long, default_value = "localhost")]
+    pub host: String,
+
+    /// Change ldap port. Default: 3890
+    #[clap(long, env = "LLDAP_LDAP_PORT")]
+    pub ldap_port: Option<u16>,
+
+    /// Connect with LDAPS. The server certificate must be the configured one.
+    #[clap(long)]
+    pub ldaps: bool,
+
+    /// Base DN of the sample search. Default: "ou=people,<ldap_base_dn>"
+    #[clap(long)]
+    pub search_base: Option<String>,
+
+    #[clap(flatten)]
+    pub ldaps_opts: LdapsOpts,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct CreateSchemaOpts {
+    #[clap(flatten)]
+    pub run_opts: RunOpts,
+
+    /// Print the changes to the database schema, without applying them.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct DoctorOpts {
+    #[clap(flatten)]
+    pub run_opts: RunOpts,
+
+    /// Repair the issues: the dangling rows are deleted, the invalid references are cleared.
+    #[clap(long)]
+    pub fix: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct GenerateCertOpts {
+    #[clap(flatten)]
+    pub general_config: GeneralConfigOpts,
+
+    /// Common name (CN) of the certificate.
+    #[clap(long, default_value = "localhost")]
+    pub common_name: String,
+
+    /// Subject alternative name (DNS name or IP address), can be repeated.
+    /// Defaults to the common name.
+    #[clap(long = "san")]
+    pub subject_alt_names: Vec<String>,
+
+    /// Number of days the certificate is valid for.
+    #[clap(long, default_value = "365")]
+    pub validity_days: u32,
+
+    /// The certificate and key are written to the LDAPS cert_file and key_file.
+    #[clap(flatten)]
+    pub ldaps_opts: LdapsOpts,
+
+    /// Print the files that would be written, without generating the certificate.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
 #[derive(Debug, Parser, Clone)]
 #[clap(next_help_heading = Some("LDAPS"))]
 pub struct LdapsOpts {
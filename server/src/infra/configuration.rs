@@ -1,18 +1,40 @@
 use crate::{
-    domain::types::UserId,
-    infra::cli::{GeneralConfigOpts, LdapsOpts, RunOpts, SmtpEncryption, SmtpOpts, TestEmailOpts},
+    domain::{
+        derived_attributes::{check_derived_attributes, DerivedAttribute, Expression},
+        ldap::utils::{parse_distinguished_name, parse_ldap_filter},
+        types::UserId,
+    },
+    infra::{
+        cli::{
+            CreateSchemaOpts, DoctorOpts, GeneralConfigOpts, GenerateCertOpts, LdapsOpts, RunOpts,
+            SmtpEncryption, SmtpOpts, TestEmailOpts, TestLdapOpts,
+        },
+        proxy::{check_proxy_url, set_outbound_proxy},
+        secrets::resolve_secret,
+    },
 };
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use figment::{
     providers::{Env, Format, Serialized, Toml},
+    value::Value,
     Figment,
 };
 use lettre::message::Mailbox;
 use lldap_auth::opaque::{server::ServerSetup, KeyPair};
 use secstr::SecUtf8;
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
 use url::Url;
 
+/// Overrides the sender of one type of email.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MailSenderOptions {
+    #[serde(default)]
+    pub from: Option<Mailbox>,
+    #[serde(default)]
+    pub reply_to: Option<Mailbox>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, derive_builder::Builder)]
 #[builder(pattern = "owned")]
 pub struct MailOptions {
@@ -35,6 +57,9 @@ pub struct MailOptions {
     /// Deprecated.
     #[builder(default = "None")]
     pub tls_required: Option<bool>,
+    /// Sender of the password reset emails, defaults to `from` and `reply_to`.
+    #[builder(default)]
+    pub password_reset: MailSenderOptions,
 }
 
 impl std::default::Default for MailOptions {
@@ -54,6 +79,24 @@ pub struct LdapsOptions {
     pub cert_file: String,
     #[builder(default = r#"String::from("key.pem")"#)]
     pub key_file: String,
+    /// Staple the OCSP response of the certificate. The cert_file must contain the issuer.
+    #[builder(default = "false")]
+    pub ocsp_stapling: bool,
+    /// Overrides the OCSP responder listed in the certificate.
+    #[builder(default = "None")]
+    pub ocsp_responder_url: Option<Url>,
+    /// Warn when the certificate expires in less than this many days.
+    #[builder(default = "30")]
+    pub expiry_warning_days: u32,
+    /// Also send the expiry warnings to this address.
+    #[builder(default = "None")]
+    pub expiry_alert_email: Option<Mailbox>,
+    /// Reject the connections without a client certificate issued by the client_ca_file.
+    #[builder(default = "false")]
+    pub require_client_cert: bool,
+    /// The CA certificates that the client certificates must be issued by.
+    #[builder(default = "None")]
+    pub client_ca_file: Option<String>,
 }
 
 impl std::default::Default for LdapsOptions {
@@ -62,6 +105,369 @@ impl std::default::Default for LdapsOptions {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, derive_builder::Builder)]
+#[builder(pattern = "owned")]
+pub struct MembershipSyncOptions {
+    /// Where to import the group memberships from: an http(s) URL returning JSON, or the path of
+    /// a CSV file. The sync is disabled if not set.
+    #[builder(default = "None")]
+    pub source: Option<String>,
+    #[builder(default = "3600")]
+    pub interval_seconds: u64,
+    /// Also remove the members that are not in the source, otherwise they are only added.
+    #[builder(default = "false")]
+    pub remove_extra_members: bool,
+    /// Only log the changes, without applying them.
+    #[builder(default = "false")]
+    pub dry_run: bool,
+}
+
+impl std::default::Default for MembershipSyncOptions {
+    fn default() -> Self {
+        MembershipSyncOptionsBuilder::default().build().unwrap()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaProvider {
+    HCaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    pub fn default_verify_url(&self) -> Url {
+        Url::parse(match self {
+            CaptchaProvider::HCaptcha => "https://api.hcaptcha.com/siteverify",
+            CaptchaProvider::Turnstile => {
+                "https://challenges.cloudflare.com/turnstile/v0/siteverify"
+            }
+        })
+        .unwrap()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, derive_builder::Builder)]
+#[builder(pattern = "owned")]
+pub struct PasswordResetOptions {
+    /// Number of leading zero bits of the proof-of-work required to request a reset.
+    #[builder(default = "None")]
+    pub proof_of_work_difficulty: Option<u8>,
+    #[builder(default = "None")]
+    pub captcha_provider: Option<CaptchaProvider>,
+    #[builder(default = r#"SecUtf8::from("")"#)]
+    pub captcha_secret: SecUtf8,
+    /// Overrides the verification endpoint of the captcha provider.
+    #[builder(default = "None")]
+    pub captcha_verify_url: Option<Url>,
+    /// Minimum delay between two reset emails for the same user.
+    #[builder(default = "300")]
+    pub rate_limit_seconds: u64,
+    /// Maximum number of reset emails sent to the same address in an hour.
+    #[builder(default = "None")]
+    pub max_per_hour_per_address: Option<u32>,
+    /// Maximum number of reset emails sent in an hour, for all the users.
+    #[builder(default = "None")]
+    pub max_per_hour: Option<u32>,
+    /// If not empty, reset emails are only sent to addresses in these domains.
+    #[builder(default)]
+    pub reset_email_allowed_domains: Vec<String>,
+    /// Reset emails are never sent to addresses in these domains.
+    #[builder(default)]
+    pub reset_email_denied_domains: Vec<String>,
+    /// The reset links are also sent to this URL, as a JSON POST request.
+    #[builder(default = "None")]
+    pub webhook_url: Option<Url>,
+    /// The custom user attribute with the address of the reset emails, e.g. a recovery email. The
+    /// users without it get them at their primary email.
+    #[builder(default = "None")]
+    pub email_attribute: Option<String>,
+}
+
+impl std::default::Default for PasswordResetOptions {
+    fn default() -> Self {
+        PasswordResetOptionsBuilder::default().build().unwrap()
+    }
+}
+
+/// Which part of the tree to search when a client sends an empty base DN.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LdapDefaultSearchScope {
+    /// The whole tree, users and groups.
+    Global,
+    /// Only the users, under "ou=people".
+    People,
+    /// Only the groups, under "ou=groups".
+    Groups,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, derive_builder::Builder)]
+#[builder(pattern = "owned")]
+pub struct LdapSearchOptions {
+    /// Base DNs that are treated as if they were `ldap_base_dn`.
+    #[builder(default)]
+    pub base_dn_aliases: Vec<String>,
+    #[builder(default = "None")]
+    pub default_scope: Option<LdapDefaultSearchScope>,
+    /// Object classes returned for the users, on top of the built-in ones.
+    #[builder(default)]
+    pub additional_user_object_classes: Vec<String>,
+    /// Object classes returned for the groups, on top of the built-in ones.
+    #[builder(default)]
+    pub additional_group_object_classes: Vec<String>,
+    /// If set, only the members of this group, the admins, the read-only admins and the password
+    /// managers are visible over LDAP, and can bind.
+    #[builder(default = "None")]
+    pub visible_group: Option<String>,
+    /// If set, returned as the `userPassword` of the users with a password, e.g. `{OPAQUE}`, for
+    /// the clients that check its presence. Only the binds that can read all users see it.
+    #[builder(default = "None")]
+    pub user_password_placeholder: Option<String>,
+    /// How the groups without members are returned.
+    #[builder(default)]
+    pub empty_group_members: EmptyGroupMembers,
+    /// The member of the empty groups, with `EmptyGroupMembers::Placeholder`.
+    #[builder(default = r#"String::from("cn=nobody")"#)]
+    pub empty_group_placeholder_member: String,
+    /// How the `memberOf` of the users and the `member` of the groups are returned.
+    #[builder(default)]
+    pub membership_value_format: MembershipValueFormat,
+    /// Only accept the bind DNs whose user ID has the case of the stored (lowercase) one. The
+    /// attribute types and the base DN are always case-insensitive.
+    #[builder(default)]
+    pub case_sensitive_bind_dn: bool,
+    /// LDAP filters by user ID: these users only see the users that match their filter, whatever
+    /// they search, e.g. for the service accounts of the applications. The groups are unaffected.
+    #[builder(default)]
+    pub search_filters: HashMap<String, String>,
+    /// What the modify requests do with the user attributes that LLDAP doesn't know, and that are
+    /// not in `ignored_user_attributes`.
+    #[builder(default)]
+    pub unknown_modify_attributes: UnknownModifyAttributes,
+    /// The operations refused with `unwillingToPerform`, even for the admins.
+    #[builder(default)]
+    pub disabled_operations: Vec<LdapOperation>,
+}
+
+impl std::default::Default for LdapSearchOptions {
+    fn default() -> Self {
+        LdapSearchOptionsBuilder::default().build().unwrap()
+    }
+}
+
+impl LdapSearchOptions {
+    /// Checks the base DN aliases and the search filters, which are parsed for each LDAP
+    /// connection.
+    pub fn check(&self) -> Result<()> {
+        for alias in &self.base_dn_aliases {
+            if parse_distinguished_name(&alias.to_ascii_lowercase()).is_err() {
+                bail!(
+                    "Invalid ldap_search_options.base_dn_aliases entry `{}`",
+                    alias
+                );
+            }
+        }
+        for (user, filter) in &self.search_filters {
+            if let Err(e) = parse_ldap_filter(filter) {
+                bail!(
+                    "Invalid ldap_search_options.search_filters entry for `{}`: {}",
+                    user,
+                    e.message
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the groups without members are returned over LDAP: `groupOfUniqueNames` requires at least
+/// one member, which some clients check.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyGroupMembers {
+    /// Omit the member attributes.
+    Omit,
+    /// Return the placeholder member.
+    Placeholder,
+    /// Return the `extensibleObject` object class instead of `groupOfUniqueNames`.
+    ExtensibleObject,
+}
+
+impl std::default::Default for EmptyGroupMembers {
+    fn default() -> Self {
+        Self::Omit
+    }
+}
+
+/// The format of the values of `memberOf` and `member`, for the clients that expect the names of
+/// the groups or users rather than their DNs.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipValueFormat {
+    /// `cn=group,ou=groups,dc=example,dc=com`.
+    Dn,
+    /// `cn=group`.
+    Rdn,
+    /// `group`.
+    Name,
+}
+
+impl std::default::Default for MembershipValueFormat {
+    fn default() -> Self {
+        Self::Dn
+    }
+}
+
+/// The classes of LDAP operations that can be disabled. The binds can't be.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LdapOperation {
+    Search,
+    Compare,
+    Add,
+    Modify,
+    ModifyDn,
+    Delete,
+    /// The extended operations, e.g. the password modify and "Who am I?" operations.
+    Extended,
+}
+
+/// How the modify requests handle the attributes that LLDAP doesn't know.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownModifyAttributes {
+    /// Fail the whole request with `undefinedAttributeType`.
+    Reject,
+    /// Apply the other changes.
+    Ignore,
+    /// Store the values in the custom attribute of the same name. The request fails if the schema
+    /// has no such attribute.
+    Store,
+}
+
+impl std::default::Default for UnknownModifyAttributes {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// Where the server key comes from. With an explicit source, the server refuses to start if it's
+/// unavailable instead of falling back to another one: a new key would invalidate all the
+/// passwords.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// The `server_setup` if it's set, then the `key_seed`, then the `key_file`, generated if it
+    /// doesn't exist.
+    Auto,
+    Seed,
+    /// The `key_file`, which must exist.
+    File,
+    /// The `server_setup` (`LLDAP_SERVER_SETUP`).
+    Env,
+}
+
+impl std::default::Default for KeySource {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+/// The source of the `sub` claim of the JWTs.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JwtSubject {
+    UserId,
+    /// The `entryUUID`, which doesn't change when the user is renamed.
+    Uuid,
+}
+
+impl std::default::Default for JwtSubject {
+    fn default() -> Self {
+        Self::UserId
+    }
+}
+
+/// The optional claims of the JWTs. The `user` and `groups` ones are always there.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JwtClaim {
+    Email,
+    DisplayName,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, derive_builder::Builder)]
+#[builder(pattern = "owned")]
+pub struct JwtOptions {
+    #[builder(default)]
+    pub subject: JwtSubject,
+    #[builder(default)]
+    pub claims: Vec<JwtClaim>,
+}
+
+/// What to do when a user is given the email address of another user.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailUniqueness {
+    /// Allow it.
+    None,
+    /// Allow it, but log a warning.
+    Warn,
+    /// Reject the creation or update of the user.
+    Enforce,
+}
+
+impl std::default::Default for EmailUniqueness {
+    fn default() -> Self {
+        Self::Enforce
+    }
+}
+
+/// How the names of the groups are rewritten when they are created or renamed.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupNameNormalization {
+    /// Keep the name as given.
+    None,
+    /// Remove the leading and trailing whitespace.
+    Trim,
+    /// Remove the leading and trailing whitespace, and convert to lowercase.
+    Lowercase,
+}
+
+impl std::default::Default for GroupNameNormalization {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl GroupNameNormalization {
+    pub fn normalize(&self, name: &str) -> String {
+        match self {
+            Self::None => name.to_owned(),
+            Self::Trim => name.trim().to_owned(),
+            Self::Lowercase => name.trim().to_lowercase(),
+        }
+    }
+}
+
+/// Which group names are considered the same, so that only one group can have them.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupNameUniqueness {
+    /// Only the identical names.
+    Exact,
+    /// The names that only differ by their case, e.g. `Admins` and `admins`.
+    CaseInsensitive,
+}
+
+impl std::default::Default for GroupNameUniqueness {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, derive_builder::Builder)]
 #[builder(pattern = "owned", build_fn(name = "private_build"))]
 pub struct Configuration {
@@ -69,12 +475,81 @@ pub struct Configuration {
     pub ldap_host: String,
     #[builder(default = "3890")]
     pub ldap_port: u16,
+    /// Maximum size of an LDAP message, in bytes. Larger messages are rejected.
+    #[builder(default = "16 * 1024 * 1024")]
+    pub ldap_max_pdu_size: usize,
+    /// Maximum number of open LDAP connections, new ones are refused past it.
+    #[builder(default)]
+    pub ldap_max_connections: Option<usize>,
+    /// Maximum number of open LDAP connections from a single IP address.
+    #[builder(default)]
+    pub ldap_max_connections_per_ip: Option<usize>,
+    /// Close the LDAP connections without any request for this many seconds.
+    #[builder(default)]
+    pub ldap_idle_timeout_seconds: Option<u64>,
+    /// Cut off the LDAP searches that take longer than this many seconds. The writes always run
+    /// to completion.
+    #[builder(default)]
+    pub ldap_operation_timeout_seconds: Option<u64>,
+    /// Maximum number of LDAP connections waiting to be accepted.
+    #[builder(default = "2048")]
+    pub ldap_listen_backlog: u32,
+    /// Set SO_REUSEADDR on the LDAP sockets, to restart without waiting for the old connections.
+    #[builder(default = "true")]
+    pub ldap_reuse_address: bool,
+    /// Set TCP_NODELAY on the LDAP connections, to send the responses without delay.
+    #[builder(default)]
+    pub ldap_tcp_nodelay: bool,
+    /// Also serve LDAP on this Unix domain socket.
+    #[builder(default)]
+    pub ldap_unix_socket_path: Option<String>,
+    /// Permissions of the Unix domain socket, in octal (e.g. "660").
+    #[builder(default)]
+    pub ldap_unix_socket_permissions: Option<String>,
+    /// Only serve LDAP on the Unix domain socket, not over TCP.
+    #[builder(default)]
+    pub ldap_unix_socket_only: bool,
     #[builder(default = r#"String::from("0.0.0.0")"#)]
     pub http_host: String,
     #[builder(default = "17170")]
     pub http_port: u16,
+    /// Maximum size in bytes of the body of the API requests.
+    #[builder(default = "1 << 24")]
+    pub http_max_body_size: usize,
+    /// Maximum nesting of the fields of a GraphQL query, 0 for no limit.
+    #[builder(default = "15")]
+    pub graphql_max_depth: usize,
+    /// Maximum number of fields of a GraphQL query, fragments included, 0 for no limit.
+    #[builder(default = "1000")]
+    pub graphql_max_complexity: usize,
+    /// Don't start the HTTP server at all, only the LDAP one.
+    #[builder(default)]
+    pub disable_http_ui: bool,
+    /// Serve the number of users and groups on `/metrics`, without authentication. They are
+    /// counted at most once every 10 seconds.
+    #[builder(default)]
+    pub enable_metrics: bool,
     #[builder(default = r#"SecUtf8::from("secretjwtsecret")"#)]
     pub jwt_secret: SecUtf8,
+    #[builder(default)]
+    pub jwt_additional_verification_secrets: Vec<SecUtf8>,
+    /// Interval between two reloads of the logged out and invalidated tokens from the database,
+    /// to pick up the ones of the other instances. 0 disables it.
+    #[builder(default = "60")]
+    pub token_revocation_refresh_seconds: u64,
+    /// The web sessions without any request for that long are logged out, whatever the lifetime
+    /// of their tokens. The activity is kept in memory, so it only works with a single instance.
+    /// 0 disables it.
+    #[builder(default)]
+    pub web_session_idle_timeout_minutes: u64,
+    /// Let the admins get a short-lived token acting as a regular user, with the
+    /// `impersonateUser` mutation.
+    #[builder(default)]
+    pub enable_impersonation: bool,
+    /// The SOCKS5 or HTTP proxy of the outbound connections: SMTP, webhooks, secret backends,
+    /// captcha verification, OCSP and membership sync sources.
+    #[builder(default)]
+    pub outbound_proxy: Option<Url>,
     #[builder(default = r#"String::from("dc=example,dc=com")"#)]
     pub ldap_base_dn: String,
     #[builder(default = r#"UserId::new("admin")"#)]
@@ -83,24 +558,91 @@ pub struct Configuration {
     pub ldap_user_email: String,
     #[builder(default = r#"SecUtf8::from("password")"#)]
     pub ldap_user_pass: SecUtf8,
+    /// While the admin has the default password, only allow the admin to log in to the web UI to
+    /// change it, and refuse the other logins and the LDAP binds. Each instance enforces it
+    /// separately.
+    #[builder(default)]
+    pub first_run_setup: bool,
+    /// The members of any of these groups are administrators. The admin user is created in the
+    /// first one.
+    #[builder(default = r#"vec![String::from("lldap_admin")]"#)]
+    pub admin_groups: Vec<String>,
+    /// Maximum number of users, new ones can't be created past it. 0 means unlimited.
+    #[builder(default)]
+    pub max_users: Option<u64>,
+    /// Maximum number of groups, not counting the built-in ones. 0 means unlimited.
+    #[builder(default)]
+    pub max_groups: Option<u64>,
+    /// Bounds of the length of the new user IDs, in characters. The admin user is exempt.
+    #[builder(default)]
+    pub username_min_length: Option<usize>,
+    #[builder(default)]
+    pub username_max_length: Option<usize>,
+    /// Bounds of the length of the new or renamed group names, in characters. The built-in and
+    /// admin groups are exempt.
+    #[builder(default)]
+    pub group_name_min_length: Option<usize>,
+    #[builder(default)]
+    pub group_name_max_length: Option<usize>,
+    #[builder(default)]
+    pub email_uniqueness: EmailUniqueness,
+    #[builder(default)]
+    pub group_name_normalization: GroupNameNormalization,
+    #[builder(default)]
+    pub group_name_uniqueness: GroupNameUniqueness,
+    /// Locale of the emails of the users without a preferred one.
+    #[builder(default = r#"String::from("en")"#)]
+    pub default_locale: String,
+    /// Notice shown on the login page of the web UI, and returned to the LDAP clients on bind.
+    #[builder(default)]
+    pub login_banner: Option<String>,
     #[builder(default = r#"String::from("sqlite://users.db?mode=rwc")"#)]
     pub database_url: String,
+    /// Number of attempts to connect to the database at startup, e.g. while it's starting.
+    #[builder(default = "5")]
+    pub database_connection_attempts: u32,
+    /// Wait after the first failed attempt, doubled after each of the next ones.
+    #[builder(default = "1")]
+    pub database_connection_backoff_seconds: u64,
+    /// Cron schedule of the purge of the expired tokens (sessions, password resets).
+    #[builder(default = r#"String::from("0 0 * * * * *")"#)]
+    pub database_cleanup_schedule: String,
     #[builder(default)]
     pub ignored_user_attributes: Vec<String>,
     #[builder(default)]
     pub ignored_group_attributes: Vec<String>,
+    #[builder(default)]
+    pub ldap_search_options: LdapSearchOptions,
+    /// User attributes computed from the other attributes, e.g. `"/home/" + uid`.
+    #[builder(default)]
+    pub derived_user_attributes: Vec<DerivedAttribute>,
+    /// Expressions tried in order for the display name of the users that don't have one.
+    #[builder(default)]
+    pub display_name_fallback: Vec<Expression>,
     #[builder(default = "false")]
     pub verbose: bool,
+    #[builder(default)]
+    pub key_source: KeySource,
     #[builder(default = r#"String::from("server_key")"#)]
     pub key_file: String,
     // We want an Option to see whether there is a value or not, since the value is printed as
     // "***SECRET***".
     #[builder(default)]
     pub key_seed: Option<SecUtf8>,
+    /// Base64 of the serialized server setup, takes precedence over `key_seed` and `key_file`.
+    #[serde(rename = "server_setup")]
+    #[builder(default)]
+    pub serialized_server_setup: Option<SecUtf8>,
     #[builder(default)]
     pub smtp_options: MailOptions,
     #[builder(default)]
+    pub password_reset_options: PasswordResetOptions,
+    #[builder(default)]
+    pub jwt_options: JwtOptions,
+    #[builder(default)]
     pub ldaps_options: LdapsOptions,
+    #[builder(default)]
+    pub membership_sync_options: MembershipSyncOptions,
     #[builder(default = r#"Url::parse("http://localhost").unwrap()"#)]
     pub http_url: Url,
     #[serde(skip)]
@@ -116,7 +658,12 @@ impl std::default::Default for Configuration {
 
 impl ConfigurationBuilder {
     pub fn build(self) -> Result<Configuration> {
-        let server_setup = get_server_setup(
+        let server_setup = get_server_setup_from_source(
+            self.key_source.unwrap_or_default(),
+            self.serialized_server_setup
+                .as_ref()
+                .and_then(|o| o.as_ref())
+                .map(SecUtf8::unsecure),
             self.key_file.as_deref().unwrap_or("server_key"),
             self.key_seed
                 .as_ref()
@@ -154,20 +701,49 @@ fn generate_random_private_key() -> ServerSetup {
 
 fn write_to_readonly_file(path: &std::path::Path, buffer: &[u8]) -> Result<()> {
     use std::{fs::File, io::Write};
-    assert!(!path.exists());
-    let mut file = File::create(path)?;
-    let mut permissions = file.metadata()?.permissions();
-    permissions.set_readonly(true);
-    if cfg!(unix) {
-        use std::os::unix::fs::PermissionsExt;
-        permissions.set_mode(0o400);
+    if path.exists() {
+        bail!(
+            "Refusing to overwrite the existing file `{}`",
+            path.display()
+        );
     }
-    file.set_permissions(permissions)?;
-    Ok(file.write_all(buffer)?)
+    let write = || -> Result<()> {
+        let mut file = File::create(path)?;
+        let mut permissions = file.metadata()?.permissions();
+        permissions.set_readonly(true);
+        if cfg!(unix) {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(0o400);
+        }
+        file.set_permissions(permissions)?;
+        Ok(file.write_all(buffer)?)
+    };
+    write().map_err(|e| {
+        // Don't leave a partial file behind, it would be read as a corrupt key on the next start.
+        let _ = std::fs::remove_file(path);
+        e
+    })
+}
+
+fn get_server_setup_from_seed(key_seed: &str) -> ServerSetup {
+    let hash = |val: &[u8]| -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+        let mut seed_hasher = Sha256::new();
+        seed_hasher.update(val);
+        seed_hasher.finalize().into()
+    };
+    use rand::SeedableRng;
+    let mut rng = rand_chacha::ChaCha20Rng::from_seed(hash(key_seed.as_bytes()));
+    ServerSetup::new(&mut rng)
+}
+
+fn read_server_setup_file(file_path: &str) -> Result<ServerSetup> {
+    let bytes =
+        std::fs::read(file_path).context(format!("Could not read key file `{}`", file_path))?;
+    Ok(ServerSetup::deserialize(&bytes)?)
 }
 
 fn get_server_setup(file_path: &str, key_seed: &str) -> Result<ServerSetup> {
-    use std::fs::read;
     let path = std::path::Path::new(file_path);
     if !key_seed.is_empty() {
         if file_path != "server_key" || path.exists() {
@@ -175,28 +751,64 @@ fn get_server_setup(file_path: &str, key_seed: &str) -> Result<ServerSetup> {
         } else {
             println!("Got a key_seed, ignoring key_file");
         }
-        let hash = |val: &[u8]| -> [u8; 32] {
-            use sha2::{Digest, Sha256};
-            let mut seed_hasher = Sha256::new();
-            seed_hasher.update(val);
-            seed_hasher.finalize().into()
-        };
-        use rand::SeedableRng;
-        let mut rng = rand_chacha::ChaCha20Rng::from_seed(hash(key_seed.as_bytes()));
-        Ok(ServerSetup::new(&mut rng))
+        Ok(get_server_setup_from_seed(key_seed))
     } else if path.exists() {
-        let bytes = read(file_path).context(format!("Could not read key file `{}`", file_path))?;
-        Ok(ServerSetup::deserialize(&bytes)?)
+        read_server_setup_file(file_path)
     } else {
+        // There is no key yet: if we can't persist the new one, all the passwords would be lost
+        // on the next restart.
         let server_setup = generate_random_private_key();
         write_to_readonly_file(path, &server_setup.serialize()).context(format!(
-            "Could not write the generated server setup to file `{}`",
+            "Could not write the generated server setup to file `{}`. If the filesystem is read-only, set key_seed or server_setup (LLDAP_SERVER_SETUP) instead",
             file_path,
         ))?;
         Ok(server_setup)
     }
 }
 
+/// With an explicit source, the other ones are ignored, and a new key is never generated.
+fn get_server_setup_from_source(
+    key_source: KeySource,
+    serialized: Option<&str>,
+    file_path: &str,
+    key_seed: &str,
+) -> Result<ServerSetup> {
+    match key_source {
+        KeySource::Auto => match serialized {
+            Some(serialized) => get_server_setup_from_base64(serialized),
+            None => get_server_setup(file_path, key_seed),
+        },
+        KeySource::Seed => {
+            if key_seed.is_empty() {
+                bail!("key_source is `seed`, but key_seed (LLDAP_KEY_SEED) is not set");
+            }
+            Ok(get_server_setup_from_seed(key_seed))
+        }
+        KeySource::File => {
+            if !std::path::Path::new(file_path).exists() {
+                bail!(
+                    "key_source is `file`, but the key file `{}` doesn't exist. Refusing to generate a new key: the existing passwords would become invalid",
+                    file_path
+                );
+            }
+            read_server_setup_file(file_path)
+        }
+        KeySource::Env => match serialized {
+            Some(serialized) => get_server_setup_from_base64(serialized),
+            None => bail!("key_source is `env`, but server_setup (LLDAP_SERVER_SETUP) is not set"),
+        },
+    }
+}
+
+fn get_server_setup_from_base64(serialized: &str) -> Result<ServerSetup> {
+    use base64::Engine;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(serialized.trim())
+        .context("The server_setup (LLDAP_SERVER_SETUP) is not valid base64")?;
+    ServerSetup::deserialize(&bytes)
+        .context("The server_setup (LLDAP_SERVER_SETUP) is not a valid serialized server setup")
+}
+
 pub trait ConfigOverrider {
     fn override_config(&self, config: &mut Configuration);
 }
@@ -211,12 +823,36 @@ impl TopLevelCommandOpts for RunOpts {
     }
 }
 
+impl TopLevelCommandOpts for CreateSchemaOpts {
+    fn general_config(&self) -> &GeneralConfigOpts {
+        &self.run_opts.general_config
+    }
+}
+
+impl TopLevelCommandOpts for DoctorOpts {
+    fn general_config(&self) -> &GeneralConfigOpts {
+        &self.run_opts.general_config
+    }
+}
+
 impl TopLevelCommandOpts for TestEmailOpts {
     fn general_config(&self) -> &GeneralConfigOpts {
         &self.general_config
     }
 }
 
+impl TopLevelCommandOpts for TestLdapOpts {
+    fn general_config(&self) -> &GeneralConfigOpts {
+        &self.general_config
+    }
+}
+
+impl TopLevelCommandOpts for GenerateCertOpts {
+    fn general_config(&self) -> &GeneralConfigOpts {
+        &self.general_config
+    }
+}
+
 impl ConfigOverrider for RunOpts {
     fn override_config(&self, config: &mut Configuration) {
         self.general_config.override_config(config);
@@ -249,6 +885,18 @@ impl ConfigOverrider for RunOpts {
     }
 }
 
+impl ConfigOverrider for CreateSchemaOpts {
+    fn override_config(&self, config: &mut Configuration) {
+        self.run_opts.override_config(config);
+    }
+}
+
+impl ConfigOverrider for DoctorOpts {
+    fn override_config(&self, config: &mut Configuration) {
+        self.run_opts.override_config(config);
+    }
+}
+
 impl ConfigOverrider for TestEmailOpts {
     fn override_config(&self, config: &mut Configuration) {
         self.general_config.override_config(config);
@@ -256,6 +904,23 @@ impl ConfigOverrider for TestEmailOpts {
     }
 }
 
+impl ConfigOverrider for TestLdapOpts {
+    fn override_config(&self, config: &mut Configuration) {
+        self.general_config.override_config(config);
+        if let Some(port) = self.ldap_port {
+            config.ldap_port = port;
+        }
+        self.ldaps_opts.override_config(config);
+    }
+}
+
+impl ConfigOverrider for GenerateCertOpts {
+    fn override_config(&self, config: &mut Configuration) {
+        self.general_config.override_config(config);
+        self.ldaps_opts.override_config(config);
+    }
+}
+
 impl ConfigOverrider for LdapsOpts {
     fn override_config(&self, config: &mut Configuration) {
         if let Some(enabled) = self.ldaps_enabled {
@@ -313,6 +978,114 @@ impl ConfigOverrider for SmtpOpts {
     }
 }
 
+/// Parses the permissions of the LDAP Unix domain socket, in octal.
+pub fn parse_unix_socket_permissions(permissions: &str) -> Result<u32> {
+    match u32::from_str_radix(permissions.trim_start_matches("0o"), 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => bail!(
+            "Invalid ldap_unix_socket_permissions `{}`, expected octal permissions like \"660\"",
+            permissions
+        ),
+    }
+}
+
+/// Expands the `${VAR}` references to environment variables, or `${VAR:-default}` to fall back
+/// to a default value. `$${` is kept as a literal `${`.
+fn expand_env_vars(value: &str, get_var: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .with_context(|| format!("Unterminated variable reference in `{}`", value))?;
+            let (name, default) = match reference[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&reference[..end], None),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("Invalid variable name `{}` in `{}`", name, value);
+            }
+            match (get_var(name), default) {
+                (Some(var), _) => expanded.push_str(&var),
+                (None, Some(default)) => expanded.push_str(default),
+                (None, None) => bail!("Undefined environment variable `{}`", name),
+            }
+            rest = &reference[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn expand_env_vars_in_value(
+    value: &mut Value,
+    get_var: &impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    match value {
+        Value::String(_, s) => *s = expand_env_vars(s, get_var)?,
+        Value::Dict(_, dict) => {
+            for (key, value) in dict.iter_mut() {
+                expand_env_vars_in_value(value, get_var)
+                    .with_context(|| format!("in the value of `{}`", key))?;
+            }
+        }
+        Value::Array(_, values) => {
+            for value in values {
+                expand_env_vars_in_value(value, get_var)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Replaces the strings that reference a secret, e.g. `vault://secret/lldap#jwt_secret`, with
+/// the secret returned by `resolve`.
+fn resolve_secrets_in_value(
+    value: &mut Value,
+    resolve: &impl Fn(&str) -> Result<Option<String>>,
+) -> Result<()> {
+    match value {
+        Value::String(_, s) => {
+            if let Some(secret) = resolve(s)? {
+                *s = secret;
+            }
+        }
+        Value::Dict(_, dict) => {
+            for (key, value) in dict.iter_mut() {
+                resolve_secrets_in_value(value, resolve)
+                    .with_context(|| format!("in the value of `{}`", key))?;
+            }
+        }
+        Value::Array(_, values) => {
+            for value in values {
+                resolve_secrets_in_value(value, resolve)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Reads the configuration file, with the environment variables expanded in its strings.
+fn read_config_file(config_file: &str) -> Result<Value> {
+    let mut value: Value = Figment::from(Toml::file(config_file))
+        .extract()
+        .with_context(|| format!("while reading {}", config_file))?;
+    expand_env_vars_in_value(&mut value, &|name| std::env::var(name).ok())
+        .with_context(|| format!("while expanding the variables in {}", config_file))?;
+    Ok(value)
+}
+
 pub fn init<C>(overrides: C) -> Result<Configuration>
 where
     C: TopLevelCommandOpts + ConfigOverrider,
@@ -326,18 +1099,40 @@ where
 
     use figment_file_provider_adapter::FileAdapter;
     let ignore_keys = ["key_file", "cert_file"];
-    let mut config: Configuration = Figment::from(Serialized::defaults(
+    let mut value: Value = Figment::from(Serialized::defaults(
         ConfigurationBuilder::default().private_build().unwrap(),
     ))
-    .merge(FileAdapter::wrap(Toml::file(config_file)).ignore(&ignore_keys))
+    .merge(
+        FileAdapter::wrap(Serialized::defaults(read_config_file(&config_file)?))
+            .ignore(&ignore_keys),
+    )
     .merge(FileAdapter::wrap(Env::prefixed("LLDAP_").split("__")).ignore(&ignore_keys))
     .extract()?;
+    // Read first, since the secrets are fetched through it too.
+    let outbound_proxy = value
+        .find_ref("outbound_proxy")
+        .and_then(Value::as_str)
+        .map(Url::parse)
+        .transpose()
+        .context("Invalid outbound_proxy URL")?;
+    if let Some(proxy) = &outbound_proxy {
+        check_proxy_url(proxy)?;
+    }
+    set_outbound_proxy(outbound_proxy);
+    resolve_secrets_in_value(&mut value, &resolve_secret)
+        .context("while fetching the secrets of the configuration")?;
+    let mut config: Configuration = value.deserialize()?;
 
     overrides.override_config(&mut config);
     if config.verbose {
         println!("Configuration: {:#?}", &config);
     }
-    config.server_setup = Some(get_server_setup(
+    config.server_setup = Some(get_server_setup_from_source(
+        config.key_source,
+        config
+            .serialized_server_setup
+            .as_ref()
+            .map(SecUtf8::unsecure),
         &config.key_file,
         config
             .key_seed
@@ -345,11 +1140,40 @@ where
             .map(SecUtf8::unsecure)
             .unwrap_or_default(),
     )?);
+    if config.admin_groups.is_empty() {
+        bail!("admin_groups cannot be empty");
+    }
+    if let Err(e) = check_derived_attributes(&config.derived_user_attributes) {
+        bail!("Invalid derived_user_attributes: {}", e);
+    }
+    config.ldap_search_options.check()?;
+    if config.membership_sync_options.interval_seconds == 0 {
+        bail!("membership_sync_options.interval_seconds cannot be 0");
+    }
+    if let Err(e) = cron::Schedule::from_str(&config.database_cleanup_schedule) {
+        bail!("Invalid database_cleanup_schedule: {}", e);
+    }
     if config.jwt_secret == SecUtf8::from("secretjwtsecret") {
         println!("WARNING: Default JWT secret used! This is highly unsafe and can allow attackers to log in as admin.");
     }
+    if config.ldap_unix_socket_only && config.ldap_unix_socket_path.is_none() {
+        bail!("ldap_unix_socket_only needs ldap_unix_socket_path");
+    }
+    if let Some(permissions) = &config.ldap_unix_socket_permissions {
+        parse_unix_socket_permissions(permissions)?;
+    }
+    if config.first_run_setup && config.disable_http_ui {
+        bail!("first_run_setup needs the web UI, it cannot be used with disable_http_ui");
+    }
+    if config.disable_http_ui {
+        println!("WARNING: The HTTP server is disabled: the web UI, the GraphQL API and the password reset emails are not available.");
+    }
     if config.ldap_user_pass == SecUtf8::from("password") {
-        println!("WARNING: Unsecure default admin password is used.");
+        if config.first_run_setup {
+            println!("WARNING: Unsecure default admin password is used. Log in to the web UI as the admin to change it, the server is unusable until then.");
+        } else {
+            println!("WARNING: Unsecure default admin password is used.");
+        }
     }
     if config.smtp_options.tls_required.is_some() {
         println!("DEPRECATED: smtp_options.tls_required field is deprecated, it never did anything. You can replace it with smtp_options.smtp_encryption.");
@@ -381,4 +1205,248 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn check_ldap_search_options() {
+        LdapSearchOptions {
+            base_dn_aliases: vec!["dc=company,dc=local".to_owned()],
+            search_filters: HashMap::from([("app".to_owned(), "(uid=bob)".to_owned())]),
+            ..Default::default()
+        }
+        .check()
+        .unwrap();
+        let error = |options: LdapSearchOptions| format!("{:#}", options.check().unwrap_err());
+        assert!(error(LdapSearchOptions {
+            base_dn_aliases: vec!["company".to_owned()],
+            ..Default::default()
+        })
+        .contains("base_dn_aliases entry `company`"));
+        assert!(error(LdapSearchOptions {
+            search_filters: HashMap::from([("app".to_owned(), "(uid=bob".to_owned())]),
+            ..Default::default()
+        })
+        .contains("search_filters entry for `app`"));
+    }
+
+    #[test]
+    fn check_server_setup_read_only_filesystem() {
+        // The key can't be persisted, but the seed is enough.
+        get_server_setup("/doesnt/exist/server_key", "key seed").unwrap();
+        let error = get_server_setup("/doesnt/exist/server_key", "").unwrap_err();
+        assert!(format!("{:#}", error).contains("set key_seed or server_setup"));
+        assert!(!std::path::Path::new("/doesnt/exist/server_key").exists());
+    }
+
+    #[test]
+    fn check_server_setup_key_source() {
+        fn from_source(
+            key_source: KeySource,
+            serialized: Option<&str>,
+            file_path: &str,
+            key_seed: &str,
+        ) -> Result<Vec<u8>> {
+            get_server_setup_from_source(key_source, serialized, file_path, key_seed)
+                .map(|setup| setup.serialize())
+        }
+        let seeded = get_server_setup_from_seed("key seed").serialize();
+        let path = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        let file_path = path.to_str().unwrap();
+        let stored = generate_random_private_key();
+        write_to_readonly_file(&path, &stored.serialize()).unwrap();
+        let serialized = {
+            use base64::Engine;
+            base64::engine::general_purpose::STANDARD.encode(stored.serialize())
+        };
+        let error = |result: Result<Vec<u8>>| format!("{:#}", result.unwrap_err());
+
+        // Auto: the server_setup, then the seed, then the file.
+        assert_eq!(
+            from_source(KeySource::Auto, Some(&serialized), file_path, "key seed").unwrap(),
+            stored.serialize()
+        );
+        assert_eq!(
+            from_source(KeySource::Auto, None, file_path, "key seed").unwrap(),
+            seeded
+        );
+        assert_eq!(
+            from_source(KeySource::Auto, None, file_path, "").unwrap(),
+            stored.serialize()
+        );
+
+        // Seed: the file and the server_setup are ignored.
+        assert_eq!(
+            from_source(KeySource::Seed, Some(&serialized), file_path, "key seed").unwrap(),
+            seeded
+        );
+        assert!(error(from_source(
+            KeySource::Seed,
+            Some(&serialized),
+            file_path,
+            ""
+        ))
+        .contains("key_seed (LLDAP_KEY_SEED) is not set"));
+
+        // File: the seed is ignored, and the missing file is not generated.
+        assert_eq!(
+            from_source(KeySource::File, Some(&serialized), file_path, "key seed").unwrap(),
+            stored.serialize()
+        );
+        let missing = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        assert!(error(from_source(
+            KeySource::File,
+            None,
+            missing.to_str().unwrap(),
+            "key seed"
+        ))
+        .contains("Refusing to generate a new key"));
+        assert!(!missing.exists());
+
+        // Env: only the server_setup.
+        assert_eq!(
+            from_source(KeySource::Env, Some(&serialized), "/doesnt/exist", "").unwrap(),
+            stored.serialize()
+        );
+        assert!(
+            error(from_source(KeySource::Env, None, file_path, "key seed"))
+                .contains("server_setup (LLDAP_SERVER_SETUP) is not set")
+        );
+        assert!(error(from_source(
+            KeySource::Env,
+            Some("AAAA"),
+            file_path,
+            "key seed"
+        ))
+        .contains("not a valid serialized server setup"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_write_to_readonly_file_existing() {
+        let path = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        write_to_readonly_file(&path, b"key").unwrap();
+        let error = write_to_readonly_file(&path, b"other key").unwrap_err();
+        assert!(error.to_string().contains("Refusing to overwrite"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"key");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_parse_unix_socket_permissions() {
+        assert_eq!(parse_unix_socket_permissions("660").unwrap(), 0o660);
+        assert_eq!(parse_unix_socket_permissions("0o600").unwrap(), 0o600);
+        parse_unix_socket_permissions("rw-rw----").unwrap_err();
+        parse_unix_socket_permissions("1777").unwrap_err();
+    }
+
+    #[test]
+    fn check_expand_env_vars() {
+        let get_var = |name: &str| match name {
+            "DB_USER" => Some("lldap".to_owned()),
+            "DB_PASS" => Some("pa$$word".to_owned()),
+            "EMPTY" => Some("".to_owned()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_env_vars("postgres://${DB_USER}:${DB_PASS}@db/lldap", &get_var).unwrap(),
+            "postgres://lldap:pa$$word@db/lldap"
+        );
+        assert_eq!(
+            expand_env_vars("${DB_HOST:-localhost}:${EMPTY:-5432}", &get_var).unwrap(),
+            "localhost:"
+        );
+        assert_eq!(
+            expand_env_vars("$$20 $${DB_USER} $", &get_var).unwrap(),
+            "$$20 ${DB_USER} $"
+        );
+        let error = expand_env_vars("${DB_HOST}", &get_var).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Undefined environment variable `DB_HOST`"
+        );
+        expand_env_vars("${DB_USER", &get_var).unwrap_err();
+        expand_env_vars("${}", &get_var).unwrap_err();
+    }
+
+    #[test]
+    fn check_expand_env_vars_in_value() {
+        let parse = || -> Value {
+            Figment::from(Toml::string(
+                r#"
+                database_url = "postgres://${DB_USER}@db/lldap"
+                ldap_port = 3890
+                [smtp_options]
+                server = "${SMTP_SERVER}"
+                "#,
+            ))
+            .extract()
+            .unwrap()
+        };
+        let mut value = parse();
+        expand_env_vars_in_value(&mut value, &|name| Some(name.to_lowercase())).unwrap();
+        assert_eq!(
+            value.find_ref("database_url").unwrap().as_str(),
+            Some("postgres://db_user@db/lldap")
+        );
+        assert_eq!(
+            value.find_ref("smtp_options.server").unwrap().as_str(),
+            Some("smtp_server")
+        );
+        assert_eq!(value.find_ref("ldap_port").unwrap().to_i128(), Some(3890));
+        let error = expand_env_vars_in_value(&mut parse(), &|name| {
+            (name == "DB_USER").then(|| "lldap".to_owned())
+        })
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "in the value of `smtp_options`: in the value of `server`: Undefined environment variable `SMTP_SERVER`"
+        );
+    }
+
+    #[test]
+    fn check_resolve_secrets_in_value() {
+        let mut value: Value = Figment::from(Serialized::defaults(
+            ConfigurationBuilder::default().private_build().unwrap(),
+        ))
+        .merge(Toml::string(
+            r#"
+            jwt_secret = "vault://secret/lldap#jwt_secret"
+            [smtp_options]
+            password = "vault://secret/lldap#smtp_password"
+            "#,
+        ))
+        .extract()
+        .unwrap();
+        let resolve = |value: &str| -> Result<Option<String>> {
+            match value.strip_prefix("vault://secret/lldap#") {
+                Some(field) => Ok(Some(format!("{} from vault", field))),
+                None => Ok(None),
+            }
+        };
+        resolve_secrets_in_value(&mut value, &resolve).unwrap();
+        let config: Configuration = value.deserialize().unwrap();
+        assert_eq!(config.jwt_secret, SecUtf8::from("jwt_secret from vault"));
+        assert_eq!(
+            config.smtp_options.password,
+            SecUtf8::from("smtp_password from vault")
+        );
+        // The other values are left untouched.
+        assert_eq!(config.ldap_base_dn, "dc=example,dc=com");
+    }
+
+    #[test]
+    fn check_server_setup_from_base64() {
+        use base64::Engine;
+        let server_setup = generate_random_private_key();
+        let serialized = base64::engine::general_purpose::STANDARD.encode(server_setup.serialize());
+        assert_eq!(
+            get_server_setup_from_base64(&serialized)
+                .unwrap()
+                .serialize(),
+            server_setup.serialize()
+        );
+        let error = get_server_setup_from_base64("not base64!").unwrap_err();
+        assert!(format!("{:#}", error).contains("not valid base64"));
+        let error = get_server_setup_from_base64("AAAA").unwrap_err();
+        assert!(format!("{:#}", error).contains("not a valid serialized server setup"));
+    }
 }
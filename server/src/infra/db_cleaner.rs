@@ -1,5 +1,8 @@
 use crate::domain::{
-    model::{self, JwtRefreshStorageColumn, JwtStorageColumn, PasswordResetTokensColumn},
+    model::{
+        self, JwtRefreshStorageColumn, JwtSecretsColumn, JwtStorageColumn,
+        PasswordResetRequestsColumn, PasswordResetTokensColumn,
+    },
     sql_tables::DbConnection,
 };
 use actix::prelude::{Actor, AsyncContext, Context};
@@ -70,6 +73,25 @@ impl Scheduler {
         {
             error!("DB error while cleaning up password reset tokens: {}", e);
         };
+        // Past the rate limits of the password resets.
+        if let Err(e) = model::PasswordResetRequests::delete_many()
+            .filter(
+                PasswordResetRequestsColumn::RequestDate
+                    .lt(chrono::Utc::now().naive_utc() - chrono::Duration::days(1)),
+            )
+            .exec(&sql_pool)
+            .await
+        {
+            error!("DB error while cleaning up password reset requests: {}", e);
+        };
+        // Past their grace period. The signing secret has no expiry date.
+        if let Err(e) = model::JwtSecrets::delete_many()
+            .filter(JwtSecretsColumn::ExpiryDate.lt(chrono::Utc::now().naive_utc()))
+            .exec(&sql_pool)
+            .await
+        {
+            error!("DB error while cleaning up JWT secrets: {}", e);
+        };
         info!("DB cleaned!");
     }
 
@@ -80,3 +102,48 @@ impl Scheduler {
         duration_until.to_std().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::sql_backend_handler::tests::{get_initialized_db, insert_user_no_password},
+        domain::{sql_backend_handler::SqlBackendHandler, types::UserId},
+        infra::configuration::ConfigurationBuilder,
+    };
+    use sea_orm::{ActiveModelTrait, ActiveValue};
+
+    async fn insert_reset_token(sql_pool: &DbConnection, token: &str, expiry: chrono::Duration) {
+        model::password_reset_tokens::ActiveModel {
+            token: ActiveValue::Set(token.to_owned()),
+            user_id: ActiveValue::Set(UserId::new("bob")),
+            expiry_date: ActiveValue::Set(chrono::Utc::now().naive_utc() + expiry),
+        }
+        .insert(sql_pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_expired_reset_tokens() {
+        let sql_pool = get_initialized_db().await;
+        crate::infra::jwt_sql_tables::init_table(&sql_pool)
+            .await
+            .unwrap();
+        let handler = SqlBackendHandler::new(ConfigurationBuilder::for_tests(), sql_pool.clone());
+        insert_user_no_password(&handler, "bob").await;
+        insert_reset_token(&sql_pool, "expired", chrono::Duration::hours(-1)).await;
+        insert_reset_token(&sql_pool, "valid", chrono::Duration::hours(1)).await;
+
+        Scheduler::cleanup_db(sql_pool.clone()).await;
+
+        let tokens = model::PasswordResetTokens::find()
+            .all(&sql_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token)
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, vec!["valid"]);
+    }
+}
@@ -0,0 +1,356 @@
+//! Self-diagnostics for the admins: checks of the database, the SMTP server, the LDAPS
+//! certificate and the keys, to triage issues from the UI. The LDAPS certificate is also checked
+//! periodically, to warn before it expires.
+
+use crate::infra::{
+    access_control::ReadonlyBackendHandler,
+    configuration::{Configuration, KeySource, LdapsOptions, MailOptions},
+    ldap_server::read_certificates,
+    mail::{send_certificate_alert_email, test_smtp_connection},
+};
+use anyhow::{bail, Context, Result};
+use chrono::prelude::*;
+use secstr::SecUtf8;
+use tracing::{info, warn};
+
+/// How often the expiry of the LDAPS certificate is checked.
+const CERTIFICATE_CHECK_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Error,
+    /// The feature is not enabled, so there is nothing to check.
+    Disabled,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiagnosticCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl DiagnosticCheck {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    fn from_result(name: &'static str, result: Result<String>) -> Self {
+        match result {
+            Err(e) => Self::new(name, CheckStatus::Error, format!("{:#}", e)),
+            Ok(detail) => Self::new(name, CheckStatus::Ok, detail),
+        }
+    }
+}
+
+/// The parts of the configuration that are checked.
+#[derive(Clone, Debug, Default)]
+pub struct DiagnosticsOptions {
+    pub smtp_options: MailOptions,
+    pub ldaps_options: LdapsOptions,
+    pub default_jwt_secret: bool,
+    /// Where the server key comes from, for the report.
+    pub server_key_source: String,
+}
+
+impl From<&Configuration> for DiagnosticsOptions {
+    fn from(config: &Configuration) -> Self {
+        let server_key_source = match config.key_source {
+            KeySource::Env => "server_setup".to_owned(),
+            KeySource::Seed => "key_seed".to_owned(),
+            KeySource::File => format!("key_file `{}`", config.key_file),
+            KeySource::Auto if config.serialized_server_setup.is_some() => {
+                "server_setup".to_owned()
+            }
+            KeySource::Auto
+                if config
+                    .key_seed
+                    .as_ref()
+                    .map(|seed| !seed.unsecure().is_empty())
+                    .unwrap_or(false) =>
+            {
+                "key_seed".to_owned()
+            }
+            KeySource::Auto => format!("key_file `{}`", config.key_file),
+        };
+        Self {
+            smtp_options: config.smtp_options.clone(),
+            ldaps_options: config.ldaps_options.clone(),
+            default_jwt_secret: config.jwt_secret == SecUtf8::from("secretjwtsecret"),
+            server_key_source,
+        }
+    }
+}
+
+async fn check_database<Handler: ReadonlyBackendHandler>(handler: &Handler) -> DiagnosticCheck {
+    DiagnosticCheck::from_result(
+        "database",
+        handler
+            .list_groups(Some(false.into()))
+            .await
+            .map(|_| "The database is reachable".to_owned())
+            .context("Could not query the database"),
+    )
+}
+
+async fn check_smtp(options: &MailOptions) -> DiagnosticCheck {
+    if !options.enable_password_reset {
+        return DiagnosticCheck::new("smtp", CheckStatus::Disabled, "Password reset is disabled");
+    }
+    DiagnosticCheck::from_result(
+        "smtp",
+        test_smtp_connection(options)
+            .await
+            .map(|()| format!("Connected to {}:{}", options.server, options.port)),
+    )
+}
+
+fn get_certificate_validity(
+    ldaps_options: &LdapsOptions,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let (certs, _private_key) = read_certificates(ldaps_options)
+        .with_context(|| format!("Could not read `{}`", ldaps_options.cert_file))?;
+    let cert = match certs.first() {
+        None => bail!("No certificate in `{}`", ldaps_options.cert_file),
+        Some(cert) => cert,
+    };
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(&cert.0).context("while parsing the certificate")?;
+    let validity = cert.validity();
+    let to_date = |time: x509_parser::time::ASN1Time| {
+        Utc.timestamp_opt(time.timestamp(), 0)
+            .single()
+            .context("Invalid certificate date")
+    };
+    Ok((to_date(validity.not_before)?, to_date(validity.not_after)?))
+}
+
+pub fn check_ldaps_certificate(
+    ldaps_options: &LdapsOptions,
+    now: DateTime<Utc>,
+) -> DiagnosticCheck {
+    const NAME: &str = "ldaps_certificate";
+    if !ldaps_options.enabled {
+        return DiagnosticCheck::new(NAME, CheckStatus::Disabled, "LDAPS is disabled");
+    }
+    let (not_before, not_after) = match get_certificate_validity(ldaps_options) {
+        Err(e) => return DiagnosticCheck::new(NAME, CheckStatus::Error, format!("{:#}", e)),
+        Ok(validity) => validity,
+    };
+    let days_to_expiry = (not_after - now).num_days();
+    if now < not_before {
+        DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Error,
+            format!("The certificate is not valid before {}", not_before),
+        )
+    } else if now > not_after {
+        DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Error,
+            format!("The certificate expired on {}", not_after),
+        )
+    } else if days_to_expiry < ldaps_options.expiry_warning_days.into() {
+        DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Warning,
+            format!(
+                "The certificate expires in {} days, on {}",
+                days_to_expiry, not_after
+            ),
+        )
+    } else {
+        DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Ok,
+            format!(
+                "The certificate is valid for {} days, until {}",
+                days_to_expiry, not_after
+            ),
+        )
+    }
+}
+
+/// Logs the state of the LDAPS certificate, and sends an alert email if it expires soon.
+async fn warn_about_ldaps_certificate(
+    ldaps_options: &LdapsOptions,
+    smtp_options: &MailOptions,
+    server_url: &url::Url,
+    now: DateTime<Utc>,
+) -> DiagnosticCheck {
+    let check = check_ldaps_certificate(ldaps_options, now);
+    match check.status {
+        CheckStatus::Ok | CheckStatus::Disabled => info!("LDAPS certificate: {}", check.detail),
+        CheckStatus::Warning | CheckStatus::Error => {
+            warn!("LDAPS certificate: {}", check.detail);
+            if let Some(to) = &ldaps_options.expiry_alert_email {
+                if let Err(e) = send_certificate_alert_email(
+                    to.clone(),
+                    &check.detail,
+                    server_url,
+                    smtp_options,
+                )
+                .await
+                {
+                    warn!("Could not send the certificate alert email: {:#}", e);
+                }
+            }
+        }
+    }
+    check
+}
+
+/// Checks the LDAPS certificate at startup, then every day.
+pub async fn monitor_ldaps_certificate(
+    ldaps_options: LdapsOptions,
+    smtp_options: MailOptions,
+    server_url: url::Url,
+) {
+    loop {
+        warn_about_ldaps_certificate(&ldaps_options, &smtp_options, &server_url, Utc::now()).await;
+        tokio::time::sleep(CERTIFICATE_CHECK_INTERVAL).await;
+    }
+}
+
+fn check_keys(options: &DiagnosticsOptions) -> DiagnosticCheck {
+    if options.default_jwt_secret {
+        DiagnosticCheck::new(
+            "keys",
+            CheckStatus::Warning,
+            "The default JWT secret is used, anyone can forge tokens",
+        )
+    } else {
+        DiagnosticCheck::new(
+            "keys",
+            CheckStatus::Ok,
+            format!("Server key from {}", options.server_key_source),
+        )
+    }
+}
+
+/// Runs all the checks. They don't stop at the first error, so that the report is complete.
+pub async fn run_diagnostics<Handler: ReadonlyBackendHandler>(
+    handler: &Handler,
+    options: &DiagnosticsOptions,
+) -> Vec<DiagnosticCheck> {
+    let (database, smtp) =
+        futures::join!(check_database(handler), check_smtp(&options.smtp_options));
+    vec![
+        database,
+        smtp,
+        check_ldaps_certificate(&options.ldaps_options, Utc::now()),
+        check_keys(options),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::ldap_server::generate_self_signed_certificate;
+
+    fn make_ldaps_options() -> LdapsOptions {
+        let dir = std::env::temp_dir();
+        let id = uuid::Uuid::new_v4();
+        LdapsOptions {
+            enabled: true,
+            cert_file: dir
+                .join(format!("lldap_diagnostics_{}_cert.pem", id))
+                .display()
+                .to_string(),
+            key_file: dir
+                .join(format!("lldap_diagnostics_{}_key.pem", id))
+                .display()
+                .to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_ldaps_certificate() {
+        assert_eq!(
+            check_ldaps_certificate(&LdapsOptions::default(), Utc::now()).status,
+            CheckStatus::Disabled
+        );
+        let options = make_ldaps_options();
+        assert_eq!(
+            check_ldaps_certificate(&options, Utc::now()).status,
+            CheckStatus::Error
+        );
+        generate_self_signed_certificate(&options, "localhost", vec!["localhost".to_owned()], 90)
+            .unwrap();
+        let now = Utc::now();
+        let check = |now| check_ldaps_certificate(&options, now).status;
+        assert_eq!(check(now), CheckStatus::Ok);
+        assert_eq!(
+            check(now + chrono::Duration::days(80)),
+            CheckStatus::Warning
+        );
+        assert_eq!(check(now + chrono::Duration::days(100)), CheckStatus::Error);
+        assert_eq!(check(now - chrono::Duration::days(1)), CheckStatus::Error);
+        std::fs::remove_file(&options.cert_file).unwrap();
+        std::fs::remove_file(&options.key_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_certificate_expiring_soon() {
+        let options = LdapsOptions {
+            expiry_warning_days: 15,
+            ..make_ldaps_options()
+        };
+        generate_self_signed_certificate(&options, "localhost", vec!["localhost".to_owned()], 10)
+            .unwrap();
+        let check = warn_about_ldaps_certificate(
+            &options,
+            &MailOptions::default(),
+            &url::Url::parse("http://localhost").unwrap(),
+            Utc::now(),
+        )
+        .await;
+        assert_eq!(check.status, CheckStatus::Warning);
+        assert!(check
+            .detail
+            .starts_with("The certificate expires in 9 days"));
+        let options = LdapsOptions {
+            expiry_warning_days: 5,
+            ..options
+        };
+        let check = warn_about_ldaps_certificate(
+            &options,
+            &MailOptions::default(),
+            &url::Url::parse("http://localhost").unwrap(),
+            Utc::now(),
+        )
+        .await;
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert!(check
+            .detail
+            .starts_with("The certificate is valid for 9 days"));
+        std::fs::remove_file(&options.cert_file).unwrap();
+        std::fs::remove_file(&options.key_file).unwrap();
+    }
+
+    #[test]
+    fn test_check_keys() {
+        let options = DiagnosticsOptions {
+            server_key_source: "key_seed".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_keys(&options),
+            DiagnosticCheck::new("keys", CheckStatus::Ok, "Server key from key_seed")
+        );
+        let options = DiagnosticsOptions {
+            default_jwt_secret: true,
+            ..options
+        };
+        assert_eq!(check_keys(&options).status, CheckStatus::Warning);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::domain::{
+    handler::{BindRequest, LoginHandler},
+    types::UserId,
+};
+use std::sync::{Arc, RwLock};
+use tracing::info;
+
+/// The admin password that the server starts with when none is configured.
+pub const DEFAULT_ADMIN_PASSWORD: &str = "password";
+
+/// The first run setup, shared between the LDAP server and the API: while the admin still has the
+/// default password, only the admin can log in (to the web UI), and only to change it.
+///
+/// The gate is kept in memory: each instance sharing the database ends it when the password is
+/// changed through that instance, or when it restarts. Any password other than the default one
+/// ends it, the server never sees the new password to check its strength.
+#[derive(Clone, Default, Debug)]
+pub struct FirstRunGate {
+    /// The admin user, while the setup is in progress.
+    admin: Arc<RwLock<Option<UserId>>>,
+}
+
+impl FirstRunGate {
+    pub fn new(admin: Option<UserId>) -> Self {
+        Self {
+            admin: Arc::new(RwLock::new(admin)),
+        }
+    }
+
+    /// Starts the setup if it's enabled and the admin still has the default password.
+    pub async fn for_admin<Handler: LoginHandler>(
+        enabled: bool,
+        handler: &Handler,
+        admin: &UserId,
+    ) -> Self {
+        if enabled && has_default_password(handler, admin).await {
+            Self::new(Some(admin.clone()))
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.admin.read().unwrap().is_some()
+    }
+
+    pub fn can_log_in(&self, user_id: &UserId) -> bool {
+        match &*self.admin.read().unwrap() {
+            None => true,
+            Some(admin) => admin == user_id,
+        }
+    }
+
+    /// Ends the setup once the admin password was changed.
+    pub async fn refresh<Handler: LoginHandler>(&self, handler: &Handler) {
+        let admin = match &*self.admin.read().unwrap() {
+            None => return,
+            Some(admin) => admin.clone(),
+        };
+        if !has_default_password(handler, &admin).await {
+            info!("The admin password was changed, the first run setup is complete");
+            *self.admin.write().unwrap() = None;
+        }
+    }
+}
+
+async fn has_default_password<Handler: LoginHandler>(handler: &Handler, admin: &UserId) -> bool {
+    handler
+        .bind(BindRequest {
+            name: admin.clone(),
+            password: DEFAULT_ADMIN_PASSWORD.to_owned(),
+        })
+        .await
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        sql_backend_handler::{tests::*, SqlBackendHandler},
+        sql_opaque_handler::register_password,
+    };
+    use secstr::SecUtf8;
+
+    #[tokio::test]
+    async fn test_first_run_gate() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "admin", DEFAULT_ADMIN_PASSWORD).await;
+        let admin = UserId::new("admin");
+        assert!(!FirstRunGate::for_admin(false, &handler, &admin)
+            .await
+            .is_active());
+        let gate = FirstRunGate::for_admin(true, &handler, &admin).await;
+        assert!(gate.is_active());
+        assert!(gate.can_log_in(&admin));
+        assert!(!gate.can_log_in(&UserId::new("bob")));
+        gate.refresh(&handler).await;
+        assert!(gate.is_active());
+        register_password(&handler, &admin, &SecUtf8::from("a strong password"))
+            .await
+            .unwrap();
+        gate.refresh(&handler).await;
+        assert!(!gate.is_active());
+        assert!(gate.can_log_in(&UserId::new("bob")));
+    }
+}
@@ -1,13 +1,27 @@
 use crate::{
-    domain::{handler::BackendHandler, types::UserId},
+    domain::{
+        error::Result as DomainResult,
+        handler::{BackendHandler, MaintenanceHandler},
+        opaque_handler::OpaqueHandler,
+        sql_opaque_handler::register_password,
+        types::{GroupId, UserId},
+    },
     infra::{
         access_control::{
-            AccessControlledBackendHandler, AdminBackendHandler, ReadonlyBackendHandler,
-            UserReadableBackendHandler, UserWriteableBackendHandler, ValidationResults,
+            AccessControlledBackendHandler, AdminBackendHandler, GroupMembershipBackendHandler,
+            ReadonlyBackendHandler, UserReadableBackendHandler, UserWriteableBackendHandler,
+            ValidationResults,
         },
         auth_service::check_if_token_is_valid,
         cli::ExportGraphQLSchemaOpts,
-        graphql::{mutation::Mutation, query::Query},
+        diagnostics::DiagnosticsOptions,
+        graphql::{
+            mutation::Mutation,
+            query::Query,
+            query_limits::{check_query_limits, QueryLimits},
+        },
+        ldap_connections::LdapConnectionRegistry,
+        tcp_backend_handler::TcpBackendHandler,
         tcp_server::AppState,
     },
 };
@@ -15,6 +29,7 @@ use actix_web::FromRequest;
 use actix_web::HttpMessage;
 use actix_web::{error::JsonPayloadError, web, Error, HttpRequest, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
+use async_trait::async_trait;
 use juniper::{
     http::{
         graphiql::graphiql_source, playground::playground_source, GraphQLBatchRequest,
@@ -22,11 +37,74 @@ use juniper::{
     },
     EmptySubscription, FieldError, RootNode, ScalarValue,
 };
+use secstr::SecUtf8;
+use std::sync::Arc;
 use tracing::debug;
 
+/// Revokes all the sessions of a user.
+#[async_trait]
+pub trait SessionInvalidator: Send + Sync {
+    async fn invalidate_user_sessions(&self, user_id: &UserId) -> DomainResult<()>;
+}
+
+/// Sets or removes the password of a user on the server, running the client side of the OPAQUE
+/// registration there.
+#[async_trait]
+pub trait PasswordSetter: Send + Sync {
+    async fn set_user_password(
+        &self,
+        user_id: &UserId,
+        password: Option<&SecUtf8>,
+    ) -> DomainResult<()>;
+}
+
+#[async_trait]
+impl<Handler: OpaqueHandler> PasswordSetter for Handler {
+    async fn set_user_password(
+        &self,
+        user_id: &UserId,
+        password: Option<&SecUtf8>,
+    ) -> DomainResult<()> {
+        match password {
+            Some(password) => register_password(self, user_id, password).await,
+            None => self.clear_password(user_id).await,
+        }
+    }
+}
+
+/// Changes the secret used to sign the JWTs, and returns when the previous one stops being
+/// accepted.
+#[async_trait]
+pub trait JwtSecretRotator: Send + Sync {
+    async fn rotate_jwt_secret(
+        &self,
+        new_secret: Option<SecUtf8>,
+        grace_period: chrono::Duration,
+    ) -> DomainResult<chrono::DateTime<chrono::Utc>>;
+}
+
+/// Issues a short-lived token acting as the user, marked as impersonated by the admin. Returns
+/// the token and its expiry date.
+#[async_trait]
+pub trait Impersonator: Send + Sync {
+    async fn impersonate(
+        &self,
+        admin: &UserId,
+        user_id: &UserId,
+    ) -> DomainResult<(String, chrono::DateTime<chrono::Utc>)>;
+}
+
 pub struct Context<Handler: BackendHandler> {
     pub handler: AccessControlledBackendHandler<Handler>,
     pub validation_result: ValidationResults,
+    pub session_invalidator: Option<Arc<dyn SessionInvalidator>>,
+    pub password_setter: Option<Arc<dyn PasswordSetter>>,
+    pub maintenance_handler: Option<Arc<dyn MaintenanceHandler>>,
+    pub jwt_secret_rotator: Option<Arc<dyn JwtSecretRotator>>,
+    /// Only set if the impersonation is enabled.
+    pub impersonator: Option<Arc<dyn Impersonator>>,
+    pub ldap_connections: LdapConnectionRegistry,
+    pub diagnostics_options: Arc<DiagnosticsOptions>,
 }
 
 pub fn field_error_callback<'a>(
@@ -45,6 +123,13 @@ impl<Handler: BackendHandler> Context<Handler> {
         Self {
             handler: AccessControlledBackendHandler::new(handler),
             validation_result,
+            session_invalidator: None,
+            password_setter: None,
+            maintenance_handler: None,
+            jwt_secret_rotator: None,
+            impersonator: None,
+            ldap_connections: LdapConnectionRegistry::default(),
+            diagnostics_options: Arc::default(),
         }
     }
 
@@ -56,6 +141,15 @@ impl<Handler: BackendHandler> Context<Handler> {
         self.handler.get_readonly_handler(&self.validation_result)
     }
 
+    pub async fn get_group_membership_handler(
+        &self,
+        group_id: GroupId,
+    ) -> DomainResult<Option<&impl GroupMembershipBackendHandler>> {
+        self.handler
+            .get_group_membership_handler(&self.validation_result, group_id)
+            .await
+    }
+
     pub fn get_writeable_handler(
         &self,
         user_id: &UserId,
@@ -142,11 +236,43 @@ where
     }
 }
 
+fn query_limits_error(message: String) -> Result<HttpResponse, Error> {
+    let body = serde_json::json!({ "errors": [{ "message": message }] });
+    Ok(HttpResponse::BadRequest()
+        .content_type("application/json")
+        .body(body.to_string()))
+}
+
+/// The queries of a JSON request, to check their limits.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsonQueries {
+    Single { query: String },
+    Batch(Vec<JsonQuery>),
+}
+
+#[derive(serde::Deserialize)]
+struct JsonQuery {
+    query: String,
+}
+
+impl JsonQueries {
+    fn check_limits(&self, limits: &QueryLimits) -> Result<(), String> {
+        match self {
+            JsonQueries::Single { query } => check_query_limits(query, limits),
+            JsonQueries::Batch(queries) => queries
+                .iter()
+                .try_for_each(|q| check_query_limits(&q.query, limits)),
+        }
+    }
+}
+
 /// Actix GraphQL Handler for GET requests
 pub async fn get_graphql_handler<Query, Mutation, Subscription, CtxT, S>(
     schema: &juniper::RootNode<'static, Query, Mutation, Subscription, S>,
     context: &CtxT,
     req: HttpRequest,
+    limits: &QueryLimits,
 ) -> Result<HttpResponse, Error>
 where
     Query: juniper::GraphQLTypeAsync<S, Context = CtxT>,
@@ -159,6 +285,9 @@ where
     S: ScalarValue + Send + Sync,
 {
     let get_req = web::Query::<GetGraphQLRequest>::from_query(req.query_string())?;
+    if let Err(message) = check_query_limits(&get_req.query, limits) {
+        return query_limits_error(message);
+    }
     let req = GraphQLRequest::from(get_req.into_inner());
     let gql_response = req.execute(schema, context).await;
     let body_response = serde_json::to_string(&gql_response)?;
@@ -177,6 +306,7 @@ pub async fn post_graphql_handler<Query, Mutation, Subscription, CtxT, S>(
     context: &CtxT,
     req: HttpRequest,
     mut payload: actix_http::Payload,
+    limits: &QueryLimits,
 ) -> Result<HttpResponse, Error>
 where
     Query: juniper::GraphQLTypeAsync<S, Context = CtxT>,
@@ -191,11 +321,20 @@ where
     let req = match req.content_type() {
         "application/json" => {
             let body = String::from_request(&req, &mut payload).await?;
+            // Malformed requests are left to the deserialization below, for the error.
+            if let Ok(queries) = serde_json::from_str::<JsonQueries>(&body) {
+                if let Err(message) = queries.check_limits(limits) {
+                    return query_limits_error(message);
+                }
+            }
             serde_json::from_str::<GraphQLBatchRequest<S>>(&body)
                 .map_err(JsonPayloadError::Deserialize)
         }
         "application/graphql" => {
             let body = String::from_request(&req, &mut payload).await?;
+            if let Err(message) = check_query_limits(&body, limits) {
+                return query_limits_error(message);
+            }
             Ok(GraphQLBatchRequest::Single(GraphQLRequest::new(
                 body, None, None,
             )))
@@ -211,30 +350,55 @@ where
     Ok(response.content_type("application/json").body(gql_response))
 }
 
-async fn graphql_route<Handler: BackendHandler + Clone>(
+async fn graphql_route<Handler>(
     req: actix_web::HttpRequest,
     payload: actix_web::web::Payload,
     data: web::Data<AppState<Handler>>,
-) -> Result<HttpResponse, Error> {
+    limits: web::Data<QueryLimits>,
+) -> Result<HttpResponse, Error>
+where
+    Handler:
+        BackendHandler + TcpBackendHandler + OpaqueHandler + MaintenanceHandler + Clone + 'static,
+{
     let mut inner_payload = payload.into_inner();
     let bearer = BearerAuth::from_request(&req, &mut inner_payload).await?;
     let validation_result = check_if_token_is_valid(&data, bearer.token())?;
+    if data.first_run.is_active() {
+        return Err(actix_web::error::ErrorForbidden(
+            "First run setup: change the admin password to use the API",
+        ));
+    }
     let context = Context::<Handler> {
         handler: data.backend_handler.clone(),
         validation_result,
+        session_invalidator: Some(data.clone().into_inner()),
+        password_setter: Some(data.clone().into_inner()),
+        maintenance_handler: Some(data.clone().into_inner()),
+        jwt_secret_rotator: Some(data.clone().into_inner()),
+        impersonator: data
+            .enable_impersonation
+            .then(|| data.clone().into_inner() as Arc<dyn Impersonator>),
+        ldap_connections: data.ldap_connections.clone(),
+        diagnostics_options: data.diagnostics_options.clone(),
     };
     let schema = &schema();
     let context = &context;
     match *req.method() {
-        actix_http::Method::POST => post_graphql_handler(schema, context, req, inner_payload).await,
-        actix_http::Method::GET => get_graphql_handler(schema, context, req).await,
+        actix_http::Method::POST => {
+            post_graphql_handler(schema, context, req, inner_payload, &limits).await
+        }
+        actix_http::Method::GET => get_graphql_handler(schema, context, req, &limits).await,
         _ => Err(actix_web::error::UrlGenerationError::ResourceNotFound.into()),
     }
 }
 
-pub fn configure_endpoint<Backend>(cfg: &mut web::ServiceConfig)
-where
-    Backend: BackendHandler + Clone + 'static,
+pub fn configure_endpoint<Backend>(
+    cfg: &mut web::ServiceConfig,
+    max_body_size: usize,
+    query_limits: QueryLimits,
+) where
+    Backend:
+        BackendHandler + TcpBackendHandler + OpaqueHandler + MaintenanceHandler + Clone + 'static,
 {
     let json_config = web::JsonConfig::default()
         .limit(4096)
@@ -249,7 +413,9 @@ where
             .into()
         });
     cfg.app_data(json_config);
-    cfg.app_data(web::PayloadConfig::new(1 << 24)); // Max payload size: 16MB, allows for a 12MB image.
+    // Bigger requests are rejected with 413 Payload Too Large.
+    cfg.app_data(web::PayloadConfig::new(max_body_size));
+    cfg.app_data(web::Data::new(query_limits));
     cfg.service(
         web::resource("/graphql")
             .route(web::post().to(graphql_route::<Backend>))
@@ -258,3 +424,71 @@ where
     cfg.service(web::resource("/graphql/playground").route(web::get().to(playground_route)));
     cfg.service(web::resource("/graphql/graphiql").route(web::get().to(graphiql_route)));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::test_utils::MockTestBackendHandler;
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    const NO_QUERY_LIMITS: QueryLimits = QueryLimits {
+        max_depth: 0,
+        max_complexity: 0,
+    };
+
+    async fn post_query(max_body_size: usize, limits: QueryLimits, body: String) -> StatusCode {
+        let (req, payload) = TestRequest::post()
+            .insert_header(actix_web::http::header::ContentType::json())
+            .app_data(web::PayloadConfig::new(max_body_size))
+            .set_payload(body)
+            .to_http_parts();
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            MockTestBackendHandler::new(),
+            ValidationResults::admin(),
+        );
+        match post_graphql_handler(&schema(), &context, req, payload, &limits).await {
+            Ok(response) => response.status(),
+            Err(e) => e.error_response().status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let query = r#"{"query": "{ apiVersion }"}"#.to_owned();
+        assert_eq!(
+            post_query(1024, NO_QUERY_LIMITS, query.clone()).await,
+            StatusCode::OK
+        );
+        // Padding the query with spaces doesn't change it, only its size.
+        let padded_query = format!(r#"{{"query": "{{ apiVersion {} }}"}}"#, " ".repeat(2048));
+        assert_eq!(
+            post_query(4096, NO_QUERY_LIMITS, padded_query.clone()).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_query(1024, NO_QUERY_LIMITS, padded_query).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_limits() {
+        let limits = QueryLimits {
+            max_depth: 2,
+            max_complexity: 10,
+        };
+        let query = r#"{"query": "{ apiVersion }"}"#.to_owned();
+        assert_eq!(post_query(1024, limits, query).await, StatusCode::OK);
+        // Rejected before running it: the mock handler has no expectations.
+        let deep_query = r#"{"query": "{ users { groups { users { id } } } }"}"#.to_owned();
+        assert_eq!(
+            post_query(1024, limits, deep_query.clone()).await,
+            StatusCode::BAD_REQUEST
+        );
+        let batch = format!(r#"[{{"query": "{{ apiVersion }}"}}, {}]"#, deep_query);
+        assert_eq!(
+            post_query(1024, limits, batch).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
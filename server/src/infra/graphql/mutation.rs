@@ -1,20 +1,29 @@
 use crate::{
     domain::{
-        handler::{BackendHandler, CreateUserRequest, UpdateGroupRequest, UpdateUserRequest},
-        types::{GroupId, JpegPhoto, UserId},
+        handler::{
+            AttributeSchema, BackendHandler, CompactionReport, CreateAttributeRequest,
+            CreateUserRequest, MaintenanceHandler, SchemaBackendHandler, UpdateAttributeRequest,
+            UpdateGroupRequest, UpdateUserRequest,
+        },
+        types::{AttributeType, AttributeValue, GroupId, JpegPhoto, Serialized, UserId},
     },
     infra::{
         access_control::{
-            AdminBackendHandler, ReadonlyBackendHandler, UserReadableBackendHandler,
-            UserWriteableBackendHandler,
+            AdminBackendHandler, GroupMembershipBackendHandler, ReadonlyBackendHandler,
+            UserReadableBackendHandler, UserWriteableBackendHandler,
         },
-        graphql::api::field_error_callback,
+        auth_service::JWT_LIFETIME_DAYS,
+        graphql::api::{field_error_callback, Impersonator, JwtSecretRotator, SessionInvalidator},
+        jwt_keys::{MAX_SECRET_LENGTH, MIN_SECRET_LENGTH},
     },
 };
-use anyhow::Context as AnyhowContext;
+use anyhow::{bail, Context as AnyhowContext};
 use base64::Engine;
+use itertools::Itertools;
 use juniper::{graphql_object, FieldResult, GraphQLInputObject, GraphQLObject};
-use tracing::{debug, debug_span, Instrument};
+use secstr::SecUtf8;
+use std::{collections::HashSet, str::FromStr};
+use tracing::{debug, debug_span, info, warn, Instrument};
 
 use super::api::Context;
 
@@ -42,6 +51,8 @@ pub struct CreateUserInput {
     last_name: Option<String>,
     // Base64 encoded JpegPhoto.
     avatar: Option<String>,
+    /// The preferred locale, e.g. `fr` or `pt-BR`, used for the emails.
+    locale: Option<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
@@ -54,6 +65,8 @@ pub struct UpdateUserInput {
     last_name: Option<String>,
     // Base64 encoded JpegPhoto.
     avatar: Option<String>,
+    /// The preferred locale, e.g. `fr` or `pt-BR`, used for the emails.
+    locale: Option<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
@@ -61,6 +74,22 @@ pub struct UpdateUserInput {
 pub struct UpdateGroupInput {
     id: i32,
     display_name: Option<String>,
+    /// Set to an empty string to remove the address.
+    email: Option<String>,
+    /// Set to an empty string to remove the description.
+    description: Option<String>,
+    /// The names of the custom attributes to remove.
+    remove_attributes: Option<Vec<String>>,
+    /// The custom attributes to set, replacing the previous values.
+    insert_attributes: Option<Vec<AttributeValueInput>>,
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
+/// The value of a custom attribute, converted to strings: the pictures are base64 encoded, the
+/// dates are in RFC3339 format. Attributes that are not lists have exactly one value.
+pub struct AttributeValueInput {
+    name: String,
+    value: Vec<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, GraphQLObject)]
@@ -74,6 +103,188 @@ impl Success {
     }
 }
 
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The result of a bulk membership change, for one user.
+pub struct MembershipChange {
+    user_id: String,
+    /// False if there was nothing to do, e.g. the user was already a member.
+    applied: bool,
+}
+
+fn make_membership_changes(user_ids: Vec<UserId>, applied: Vec<UserId>) -> Vec<MembershipChange> {
+    let applied = applied.into_iter().collect::<HashSet<_>>();
+    user_ids
+        .into_iter()
+        .map(|user_id| MembershipChange {
+            applied: applied.contains(&user_id),
+            user_id: user_id.into_string(),
+        })
+        .collect()
+}
+
+#[derive(PartialEq, Debug, GraphQLObject)]
+/// The size of the database around a compaction, in bytes. GraphQL integers are 32 bits, the
+/// floats are exact up to 2^53 bytes.
+pub struct DatabaseCompaction {
+    size_before: f64,
+    size_after: f64,
+    reclaimed: f64,
+}
+
+impl From<CompactionReport> for DatabaseCompaction {
+    fn from(report: CompactionReport) -> Self {
+        Self {
+            size_before: report.size_before as f64,
+            size_after: report.size_after as f64,
+            reclaimed: report.reclaimed() as f64,
+        }
+    }
+}
+
+fn get_maintenance_handler<Handler: BackendHandler>(
+    context: &Context<Handler>,
+) -> FieldResult<&dyn MaintenanceHandler> {
+    context
+        .maintenance_handler
+        .as_deref()
+        .ok_or_else(|| "Database maintenance is not available".into())
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// The result of a rotation of the JWT secret.
+pub struct JwtSecretRotation {
+    /// The tokens signed with the previous secret are accepted until then.
+    previous_secret_valid_until: chrono::DateTime<chrono::Utc>,
+}
+
+fn get_jwt_secret_rotator<Handler: BackendHandler>(
+    context: &Context<Handler>,
+) -> FieldResult<&dyn JwtSecretRotator> {
+    context
+        .jwt_secret_rotator
+        .as_deref()
+        .ok_or_else(|| "JWT secret rotation is not available".into())
+}
+
+#[derive(PartialEq, Eq, Debug, GraphQLObject)]
+/// A token acting as another user.
+pub struct Impersonation {
+    token: String,
+    valid_until: chrono::DateTime<chrono::Utc>,
+}
+
+fn get_impersonator<Handler: BackendHandler>(
+    context: &Context<Handler>,
+) -> FieldResult<&dyn Impersonator> {
+    context
+        .impersonator
+        .as_deref()
+        .ok_or_else(|| "Impersonation is disabled".into())
+}
+
+fn get_session_invalidator<Handler: BackendHandler>(
+    context: &Context<Handler>,
+) -> FieldResult<&dyn SessionInvalidator> {
+    context
+        .session_invalidator
+        .as_deref()
+        .ok_or_else(|| "Session invalidation is not available".into())
+}
+
+/// Read-only administrators can see everything, but all the mutations are rejected for them.
+fn check_not_readonly_admin<Handler: BackendHandler>(
+    context: &Context<Handler>,
+    span: &tracing::Span,
+) -> FieldResult<()> {
+    if context.validation_result.is_readonly_admin() {
+        span.in_scope(|| debug!("Mutation attempted by a read-only administrator"));
+        return Err("Read-only administrators are not allowed to make changes".into());
+    }
+    Ok(())
+}
+
+/// The common part of the administrator mutations setting or removing the password of a user.
+async fn set_password_as_admin<Handler: BackendHandler>(
+    context: &Context<Handler>,
+    span: &tracing::Span,
+    user_id: &UserId,
+    password: Option<&SecUtf8>,
+    unauthorized_message: &str,
+) -> FieldResult<()> {
+    check_not_readonly_admin(context, span)?;
+    span.in_scope(|| {
+        debug!(?user_id);
+    });
+    context
+        .get_admin_handler()
+        .ok_or_else(field_error_callback(span, unauthorized_message))?;
+    context
+        .password_setter
+        .as_deref()
+        .ok_or("Setting passwords is not available")?
+        .set_user_password(user_id, password)
+        .instrument(span.clone())
+        .await?;
+    Ok(())
+}
+
+/// The inverse of the conversion of the attribute values for the queries.
+fn deserialize_attribute_value(
+    value: &[String],
+    schema: &AttributeSchema,
+) -> anyhow::Result<Serialized> {
+    let parse_int = |value: &String| {
+        value
+            .parse::<i64>()
+            .with_context(|| format!("Invalid integer: {}", value))
+    };
+    let parse_photo = |value: &String| JpegPhoto::try_from(value.clone());
+    let parse_date = |value: &String| {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .map(|date| date.naive_utc())
+            .with_context(|| format!("Invalid date: {}", value))
+    };
+    if !schema.is_list && value.len() != 1 {
+        bail!(
+            "The attribute {} is not a list, it needs exactly one value",
+            schema.name
+        );
+    }
+    Ok(match (schema.attribute_type, schema.is_list) {
+        (AttributeType::String, false) => Serialized::from(&value[0]),
+        (AttributeType::Integer, false) => Serialized::from(&parse_int(&value[0])?),
+        (AttributeType::JpegPhoto, false) => Serialized::from(&parse_photo(&value[0])?),
+        (AttributeType::DateTime, false) => Serialized::from(&parse_date(&value[0])?),
+        (AttributeType::String, true) => Serialized::from(value),
+        (AttributeType::Integer, true) => Serialized::from(
+            &value
+                .iter()
+                .map(parse_int)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        ),
+        (AttributeType::JpegPhoto, true) => Serialized::from(
+            &value
+                .iter()
+                .map(parse_photo)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        ),
+        (AttributeType::DateTime, true) => Serialized::from(
+            &value
+                .iter()
+                .map(parse_date)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        ),
+    })
+}
+
+/// Custom attribute names are also LDAP attribute names.
+fn is_valid_attribute_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
 #[graphql_object(context = Context<Handler>)]
 impl<Handler: BackendHandler> Mutation<Handler> {
     async fn create_user(
@@ -81,6 +292,7 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         user: CreateUserInput,
     ) -> FieldResult<super::query::User<Handler>> {
         let span = debug_span!("[GraphQL mutation] create_user");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!("{:?}", &user.id);
         });
@@ -104,6 +316,7 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 first_name: user.first_name,
                 last_name: user.last_name,
                 avatar,
+                locale: user.locale,
             })
             .instrument(span.clone())
             .await?;
@@ -119,6 +332,7 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         name: String,
     ) -> FieldResult<super::query::Group<Handler>> {
         let span = debug_span!("[GraphQL mutation] create_group");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?name);
         });
@@ -138,6 +352,7 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         user: UpdateUserInput,
     ) -> FieldResult<Success> {
         let span = debug_span!("[GraphQL mutation] update_user");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?user.id);
         });
@@ -145,6 +360,30 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         let handler = context
             .get_writeable_handler(&user_id)
             .ok_or_else(field_error_callback(&span, "Unauthorized user update"))?;
+        // The users can only change their own attributes that are editable.
+        if !context.validation_result.is_admin() {
+            let schema = context
+                .handler
+                .get_user_restricted_lister_handler(&context.validation_result)
+                .get_schema()
+                .instrument(span.clone())
+                .await?;
+            for (name, is_changed) in [
+                ("first_name", user.first_name.is_some()),
+                ("last_name", user.last_name.is_some()),
+                ("avatar", user.avatar.is_some()),
+                ("locale", user.locale.is_some()),
+            ] {
+                let is_editable = schema
+                    .user_attributes
+                    .get_attribute_schema(name)
+                    .map_or(false, |attribute_schema| attribute_schema.is_editable);
+                if is_changed && !is_editable {
+                    span.in_scope(|| debug!(name, "Attribute not editable"));
+                    return Err(format!("The attribute `{}` is not editable", name).into());
+                }
+            }
+        }
         let avatar = user
             .avatar
             .map(|bytes| base64::engine::general_purpose::STANDARD.decode(bytes))
@@ -161,6 +400,8 @@ impl<Handler: BackendHandler> Mutation<Handler> {
                 first_name: user.first_name,
                 last_name: user.last_name,
                 avatar,
+                locale: user.locale,
+                insert_attributes: Vec::new(),
             })
             .instrument(span)
             .await?;
@@ -172,6 +413,7 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         group: UpdateGroupInput,
     ) -> FieldResult<Success> {
         let span = debug_span!("[GraphQL mutation] update_group");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?group.id);
         });
@@ -182,27 +424,186 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             span.in_scope(|| debug!("Cannot change admin group details"));
             return Err("Cannot change admin group details".into());
         }
+        let insert_attributes = match group.insert_attributes {
+            None => Vec::new(),
+            Some(attributes) => {
+                let schema = context
+                    .handler
+                    .get_user_restricted_lister_handler(&context.validation_result)
+                    .get_schema()
+                    .instrument(span.clone())
+                    .await?;
+                attributes
+                    .into_iter()
+                    .map(|attribute| {
+                        let attribute_schema = schema
+                            .group_attributes
+                            .get_attribute_schema(&attribute.name)
+                            .with_context(|| {
+                                format!("No such group attribute: {}", attribute.name)
+                            })?;
+                        Ok(AttributeValue {
+                            name: attribute_schema.name.clone(),
+                            value: deserialize_attribute_value(&attribute.value, attribute_schema)?,
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            }
+        };
         handler
             .update_group(UpdateGroupRequest {
                 group_id: GroupId(group.id),
                 display_name: group.display_name,
+                email: group.email,
+                description: group.description,
+                delete_attributes: group.remove_attributes.unwrap_or_default(),
+                insert_attributes,
             })
             .instrument(span)
             .await?;
         Ok(Success::new())
     }
 
+    /// The attribute is returned over LDAP unless `isLdapVisible` is false.
+    async fn add_group_attribute(
+        context: &Context<Handler>,
+        name: String,
+        attribute_type: String,
+        is_list: bool,
+        is_visible: bool,
+        is_editable: bool,
+        is_ldap_visible: Option<bool>,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] add_group_attribute");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?name, ?attribute_type, ?is_list);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized attribute creation",
+            ))?;
+        if !is_valid_attribute_name(&name) {
+            span.in_scope(|| debug!("Invalid attribute name"));
+            return Err(format!("Invalid attribute name: {}", name).into());
+        }
+        let attribute_type = match AttributeType::from_str(&attribute_type) {
+            Err(_) => return Err(format!("Unknown attribute type: {}", attribute_type).into()),
+            Ok(attribute_type) => attribute_type,
+        };
+        handler
+            .add_group_attribute(CreateAttributeRequest {
+                name,
+                attribute_type,
+                is_list,
+                is_visible,
+                is_editable,
+                is_ldap_visible: is_ldap_visible.unwrap_or(true),
+            })
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    /// Change the flags of a group attribute, the omitted ones are kept.
+    async fn update_group_attribute(
+        context: &Context<Handler>,
+        name: String,
+        is_visible: Option<bool>,
+        is_editable: Option<bool>,
+        is_ldap_visible: Option<bool>,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] update_group_attribute");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?name, ?is_visible, ?is_editable, ?is_ldap_visible);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized attribute modification",
+            ))?;
+        handler
+            .update_group_attribute(UpdateAttributeRequest {
+                name,
+                is_visible,
+                is_editable,
+                is_ldap_visible,
+            })
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    /// Change the flags of a user attribute, the omitted ones are kept.
+    async fn update_user_attribute(
+        context: &Context<Handler>,
+        name: String,
+        is_visible: Option<bool>,
+        is_editable: Option<bool>,
+        is_ldap_visible: Option<bool>,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] update_user_attribute");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?name, ?is_visible, ?is_editable, ?is_ldap_visible);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized attribute modification",
+            ))?;
+        handler
+            .update_user_attribute(UpdateAttributeRequest {
+                name,
+                is_visible,
+                is_editable,
+                is_ldap_visible,
+            })
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    async fn delete_group_attribute(
+        context: &Context<Handler>,
+        name: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] delete_group_attribute");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?name);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized attribute deletion",
+            ))?;
+        handler
+            .delete_group_attribute(&name)
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
     async fn add_user_to_group(
         context: &Context<Handler>,
         user_id: String,
         group_id: i32,
     ) -> FieldResult<Success> {
         let span = debug_span!("[GraphQL mutation] add_user_to_group");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?user_id, ?group_id);
         });
         let handler = context
-            .get_admin_handler()
+            .get_group_membership_handler(GroupId(group_id))
+            .await?
             .ok_or_else(field_error_callback(
                 &span,
                 "Unauthorized group membership modification",
@@ -220,11 +621,13 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         group_id: i32,
     ) -> FieldResult<Success> {
         let span = debug_span!("[GraphQL mutation] remove_user_from_group");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?user_id, ?group_id);
         });
         let handler = context
-            .get_admin_handler()
+            .get_group_membership_handler(GroupId(group_id))
+            .await?
             .ok_or_else(field_error_callback(
                 &span,
                 "Unauthorized group membership modification",
@@ -241,8 +644,225 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         Ok(Success::new())
     }
 
+    /// Add several users to a group at once: either all of them are added, or none.
+    async fn add_users_to_group(
+        context: &Context<Handler>,
+        group_id: i32,
+        user_ids: Vec<String>,
+    ) -> FieldResult<Vec<MembershipChange>> {
+        let span = debug_span!("[GraphQL mutation] add_users_to_group");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?group_id, ?user_ids);
+        });
+        let handler = context
+            .get_group_membership_handler(GroupId(group_id))
+            .await?
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized group membership modification",
+            ))?;
+        let user_ids = user_ids
+            .iter()
+            .map(|user_id| UserId::new(user_id))
+            .unique()
+            .collect::<Vec<_>>();
+        let added = handler
+            .add_users_to_group(&user_ids, GroupId(group_id))
+            .instrument(span)
+            .await?;
+        Ok(make_membership_changes(user_ids, added))
+    }
+
+    /// Remove several users from a group at once: either all of them are removed, or none.
+    async fn remove_users_from_group(
+        context: &Context<Handler>,
+        group_id: i32,
+        user_ids: Vec<String>,
+    ) -> FieldResult<Vec<MembershipChange>> {
+        let span = debug_span!("[GraphQL mutation] remove_users_from_group");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?group_id, ?user_ids);
+        });
+        let handler = context
+            .get_group_membership_handler(GroupId(group_id))
+            .await?
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized group membership modification",
+            ))?;
+        let user_ids = user_ids
+            .iter()
+            .map(|user_id| UserId::new(user_id))
+            .unique()
+            .collect::<Vec<_>>();
+        if group_id == 1 && user_ids.contains(&context.validation_result.user) {
+            span.in_scope(|| debug!("Cannot remove admin rights for current user"));
+            return Err("Cannot remove admin rights for current user".into());
+        }
+        let removed = handler
+            .remove_users_from_group(&user_ids, GroupId(group_id))
+            .instrument(span)
+            .await?;
+        Ok(make_membership_changes(user_ids, removed))
+    }
+
+    /// Set the primary group of a user, which must be one of their groups, or unset it.
+    async fn set_user_primary_group(
+        context: &Context<Handler>,
+        user_id: String,
+        group_id: Option<i32>,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] set_user_primary_group");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?user_id, ?group_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized user update"))?;
+        handler
+            .set_primary_group(&UserId::new(&user_id), group_id.map(GroupId))
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    /// Set the date after which the user can't log in or bind anymore, or clear it. The sessions
+    /// that would outlive the new date are revoked.
+    async fn set_user_expiry_date(
+        context: &Context<Handler>,
+        user_id: String,
+        expiry_date: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] set_user_expiry_date");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?user_id, ?expiry_date);
+        });
+        let user_id = UserId::new(&user_id);
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized user update"))?;
+        // The new tokens don't outlive the expiry date, but the current ones might.
+        let session_invalidator = match expiry_date {
+            Some(date) if date < chrono::Utc::now() + chrono::Duration::days(JWT_LIFETIME_DAYS) => {
+                Some(get_session_invalidator(context)?)
+            }
+            _ => None,
+        };
+        handler
+            .set_user_expiry_date(&user_id, expiry_date.map(|date| date.naive_utc()))
+            .instrument(span.clone())
+            .await?;
+        if let Some(session_invalidator) = session_invalidator {
+            session_invalidator
+                .invalidate_user_sessions(&user_id)
+                .instrument(span)
+                .await?;
+        }
+        Ok(Success::new())
+    }
+
+    /// Allow a user to add and remove the members of a group, without being an admin.
+    async fn add_group_manager(
+        context: &Context<Handler>,
+        group_id: i32,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] add_group_manager");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?group_id, ?user_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized group manager modification",
+            ))?;
+        handler
+            .add_group_manager(GroupId(group_id), &UserId::new(&user_id))
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    async fn remove_group_manager(
+        context: &Context<Handler>,
+        group_id: i32,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] remove_group_manager");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?group_id, ?user_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized group manager modification",
+            ))?;
+        handler
+            .remove_group_manager(GroupId(group_id), &UserId::new(&user_id))
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    /// Allow the members of a group to add and remove the members of another group, without
+    /// being admins.
+    async fn add_group_manager_group(
+        context: &Context<Handler>,
+        group_id: i32,
+        manager_group_id: i32,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] add_group_manager_group");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?group_id, ?manager_group_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized group manager modification",
+            ))?;
+        handler
+            .add_group_manager_group(GroupId(group_id), GroupId(manager_group_id))
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    async fn remove_group_manager_group(
+        context: &Context<Handler>,
+        group_id: i32,
+        manager_group_id: i32,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] remove_group_manager_group");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?group_id, ?manager_group_id);
+        });
+        let handler = context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized group manager modification",
+            ))?;
+        handler
+            .remove_group_manager_group(GroupId(group_id), GroupId(manager_group_id))
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
     async fn delete_user(context: &Context<Handler>, user_id: String) -> FieldResult<Success> {
         let span = debug_span!("[GraphQL mutation] delete_user");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?user_id);
         });
@@ -258,8 +878,148 @@ impl<Handler: BackendHandler> Mutation<Handler> {
         Ok(Success::new())
     }
 
+    /// Revoke all the sessions of a user: their existing tokens are rejected.
+    async fn invalidate_user_sessions(
+        context: &Context<Handler>,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] invalidate_user_sessions");
+        check_not_readonly_admin(context, &span)?;
+        span.in_scope(|| {
+            debug!(?user_id);
+        });
+        let user_id = UserId::new(&user_id);
+        context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized session invalidation",
+            ))?;
+        get_session_invalidator(context)?
+            .invalidate_user_sessions(&user_id)
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
+    /// Get a short-lived token acting as a regular user, to see the app as they do. The token is
+    /// marked as impersonated by the admin, and can't be refreshed. Disabled by default.
+    async fn impersonate_user(
+        context: &Context<Handler>,
+        user_id: String,
+    ) -> FieldResult<Impersonation> {
+        let span = debug_span!("[GraphQL mutation] impersonate_user");
+        let user_id = UserId::new(&user_id);
+        // All the attempts are logged, including the rejected ones.
+        span.in_scope(|| {
+            warn!(
+                admin = ?context.validation_result.user,
+                user = ?user_id,
+                "Impersonation requested"
+            )
+        });
+        check_not_readonly_admin(context, &span)?;
+        context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(&span, "Unauthorized impersonation"))?;
+        let (token, valid_until) = get_impersonator(context)?
+            .impersonate(&context.validation_result.user, &user_id)
+            .instrument(span.clone())
+            .await?;
+        span.in_scope(|| {
+            warn!(
+                admin = ?context.validation_result.user,
+                user = ?user_id,
+                %valid_until,
+                "Impersonation token issued"
+            )
+        });
+        Ok(Impersonation { token, valid_until })
+    }
+
+    /// Set the password of a user, or remove it if none is given. For administrators migrating
+    /// users with known passwords: the password is sent in clear text, and registered with OPAQUE
+    /// on the server.
+    async fn set_user_password(
+        context: &Context<Handler>,
+        user_id: String,
+        password: Option<String>,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] set_user_password");
+        if password.as_deref() == Some("") {
+            return Err("The password cannot be empty".into());
+        }
+        let user_id = UserId::new(&user_id);
+        let password = password.map(SecUtf8::from);
+        set_password_as_admin(
+            context,
+            &span,
+            &user_id,
+            password.as_ref(),
+            "Unauthorized password change",
+        )
+        .await?;
+        span.in_scope(|| {
+            info!(
+                admin = ?context.validation_result.user,
+                ?user_id,
+                "Password {} by an administrator",
+                if password.is_some() { "set" } else { "removed" }
+            )
+        });
+        Ok(Success::new())
+    }
+
+    /// Invalidate the password of a user without setting a new one, and revoke their sessions. The
+    /// account can only be used again after a password reset.
+    async fn invalidate_user_password(
+        context: &Context<Handler>,
+        user_id: String,
+    ) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] invalidate_user_password");
+        let user_id = UserId::new(&user_id);
+        // Checked first, not to leave the sessions of the user open once the password is removed.
+        let session_invalidator = get_session_invalidator(context)?;
+        set_password_as_admin(
+            context,
+            &span,
+            &user_id,
+            None,
+            "Unauthorized password invalidation",
+        )
+        .await?;
+        session_invalidator
+            .invalidate_user_sessions(&user_id)
+            .instrument(span.clone())
+            .await?;
+        span.in_scope(|| {
+            info!(
+                admin = ?context.validation_result.user,
+                ?user_id,
+                "Password invalidated by an administrator"
+            )
+        });
+        Ok(Success::new())
+    }
+
+    /// Log the current user out of all their sessions, including the current one.
+    async fn logout_everywhere(context: &Context<Handler>) -> FieldResult<Success> {
+        let span = debug_span!("[GraphQL mutation] logout_everywhere");
+        check_not_readonly_admin(context, &span)?;
+        let user_id = context.validation_result.user.clone();
+        span.in_scope(|| {
+            debug!(?user_id);
+        });
+        get_session_invalidator(context)?
+            .invalidate_user_sessions(&user_id)
+            .instrument(span)
+            .await?;
+        Ok(Success::new())
+    }
+
     async fn delete_group(context: &Context<Handler>, group_id: i32) -> FieldResult<Success> {
         let span = debug_span!("[GraphQL mutation] delete_group");
+        check_not_readonly_admin(context, &span)?;
         span.in_scope(|| {
             debug!(?group_id);
         });
@@ -276,4 +1036,960 @@ impl<Handler: BackendHandler> Mutation<Handler> {
             .await?;
         Ok(Success::new())
     }
+
+    /// Reclaim the unused space of the database. The database is locked while it runs, so the
+    /// other requests wait.
+    async fn compact_database(context: &Context<Handler>) -> FieldResult<DatabaseCompaction> {
+        let span = debug_span!("[GraphQL mutation] compact_database");
+        check_not_readonly_admin(context, &span)?;
+        context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized database compaction",
+            ))?;
+        let report = get_maintenance_handler(context)?
+            .compact_database()
+            .instrument(span.clone())
+            .await?;
+        span.in_scope(|| {
+            info!(
+                admin = ?context.validation_result.user,
+                reclaimed = report.reclaimed(),
+                "Database compacted"
+            )
+        });
+        Ok(report.into())
+    }
+
+    /// Sign the new tokens with another secret, generated if none is given. The tokens signed
+    /// with the previous secret remain valid for the grace period, one day by default.
+    async fn rotate_jwt_secret(
+        context: &Context<Handler>,
+        secret: Option<String>,
+        grace_period_seconds: Option<i32>,
+    ) -> FieldResult<JwtSecretRotation> {
+        let span = debug_span!("[GraphQL mutation] rotate_jwt_secret");
+        check_not_readonly_admin(context, &span)?;
+        context
+            .get_admin_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized JWT secret rotation",
+            ))?;
+        let grace_period = match grace_period_seconds {
+            None => chrono::Duration::days(1),
+            Some(seconds) if seconds >= 0 => chrono::Duration::seconds(seconds.into()),
+            Some(_) => return Err("The grace period cannot be negative".into()),
+        };
+        if let Some(secret) = &secret {
+            if secret.len() < MIN_SECRET_LENGTH {
+                return Err(format!(
+                    "The JWT secret must be at least {} characters long",
+                    MIN_SECRET_LENGTH
+                )
+                .into());
+            }
+            if secret.len() > MAX_SECRET_LENGTH {
+                return Err(format!(
+                    "The JWT secret must be at most {} characters long",
+                    MAX_SECRET_LENGTH
+                )
+                .into());
+            }
+        }
+        let previous_secret_valid_until = get_jwt_secret_rotator(context)?
+            .rotate_jwt_secret(secret.map(SecUtf8::from), grace_period)
+            .instrument(span.clone())
+            .await?;
+        span.in_scope(|| {
+            info!(
+                admin = ?context.validation_result.user,
+                %previous_secret_valid_until,
+                "JWT secret rotated"
+            )
+        });
+        Ok(JwtSecretRotation {
+            previous_secret_valid_until,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{
+            sql_backend_handler::{
+                tests::{
+                    get_default_config, get_initialized_db, insert_group, insert_user,
+                    insert_user_no_password,
+                },
+                SqlBackendHandler,
+            },
+            sql_opaque_handler::tests::attempt_login,
+        },
+        infra::{
+            access_control::{Permission, ValidationResults},
+            graphql::query::Query,
+        },
+    };
+    use juniper::{execute, graphql_value, EmptySubscription, RootNode, Variables};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn set_user_password() {
+        const QUERY: &str = r#"mutation {
+          setUserPassword(userId: "bob", password: "new_password") {
+            ok
+          }
+        }"#;
+
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+
+        let mut context = Context::new_for_tests(
+            handler.clone(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        context.password_setter = Some(Arc::new(handler.clone()));
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        attempt_login(&handler, "bob", "bob00000").await.unwrap();
+
+        let mut context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        context.password_setter = Some(Arc::new(handler.clone()));
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "setUserPassword": { "ok": true } }),
+                vec![]
+            ))
+        );
+        attempt_login(&handler, "bob", "new_password")
+            .await
+            .unwrap();
+        attempt_login(&handler, "bob", "bob00000")
+            .await
+            .unwrap_err();
+    }
+
+    #[derive(Default)]
+    struct RecordingSessionInvalidator(std::sync::Mutex<Vec<UserId>>);
+
+    #[async_trait::async_trait]
+    impl SessionInvalidator for RecordingSessionInvalidator {
+        async fn invalidate_user_sessions(
+            &self,
+            user_id: &UserId,
+        ) -> crate::domain::error::Result<()> {
+            self.0.lock().unwrap().push(user_id.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn invalidate_user_password() {
+        use crate::{
+            domain::sql_opaque_handler::register_password,
+            infra::tcp_backend_handler::TcpBackendHandler,
+        };
+        const QUERY: &str = r#"mutation {
+          invalidateUserPassword(userId: "bob") {
+            ok
+          }
+        }"#;
+
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let session_invalidator = Arc::new(RecordingSessionInvalidator::default());
+        let mut context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        context.password_setter = Some(Arc::new(handler.clone()));
+        context.session_invalidator = Some(session_invalidator.clone());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "invalidateUserPassword": { "ok": true } }),
+                vec![]
+            ))
+        );
+        let bob = UserId::new("bob");
+        assert_eq!(*session_invalidator.0.lock().unwrap(), vec![bob.clone()]);
+        attempt_login(&handler, "bob", "bob00000")
+            .await
+            .unwrap_err();
+        // The password can be set again through the reset flow.
+        let token = handler.start_password_reset(&bob).await.unwrap().unwrap();
+        assert_eq!(
+            handler
+                .get_user_id_for_password_reset_token(&token)
+                .await
+                .unwrap(),
+            bob
+        );
+        register_password(&handler, &bob, &SecUtf8::from("new_password"))
+            .await
+            .unwrap();
+        attempt_login(&handler, "bob", "new_password")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn set_user_expiry_date_revokes_sessions() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let session_invalidator = Arc::new(RecordingSessionInvalidator::default());
+        let mut context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        context.session_invalidator = Some(session_invalidator.clone());
+        let set_expiry_date = |date: chrono::DateTime<chrono::Utc>| {
+            format!(
+                r#"mutation {{
+                  setUserExpiryDate(userId: "bob", expiryDate: "{}") {{
+                    ok
+                  }}
+                }}"#,
+                date.to_rfc3339()
+            )
+        };
+        // The current tokens expire before a distant date.
+        let query = set_expiry_date(chrono::Utc::now() + chrono::Duration::days(30));
+        assert_eq!(
+            execute(&query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "setUserExpiryDate": { "ok": true } }),
+                vec![]
+            ))
+        );
+        assert!(session_invalidator.0.lock().unwrap().is_empty());
+        let query = set_expiry_date(chrono::Utc::now() - chrono::Duration::days(1));
+        assert_eq!(
+            execute(&query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "setUserExpiryDate": { "ok": true } }),
+                vec![]
+            ))
+        );
+        assert_eq!(
+            *session_invalidator.0.lock().unwrap(),
+            vec![UserId::new("bob")]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_user_non_editable_attributes() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user_no_password(&handler, "bob").await;
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let bob_context = Context::new_for_tests(
+            handler.clone(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        let admin_context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        const QUERY: &str =
+            r#"mutation { updateUser(user: { id: "bob", firstName: "Bob" }) { ok } }"#;
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &bob_context).await,
+            Ok((graphql_value!({ "updateUser": { "ok": true } }), vec![]))
+        );
+        handler
+            .update_user_attribute(UpdateAttributeRequest {
+                name: "first_name".to_owned(),
+                is_editable: Some(false),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &bob_context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        // The other attributes are still editable.
+        assert_eq!(
+            execute(
+                r#"mutation { updateUser(user: { id: "bob", lastName: "Bobberson" }) { ok } }"#,
+                None,
+                &schema,
+                &Variables::new(),
+                &bob_context
+            )
+            .await,
+            Ok((graphql_value!({ "updateUser": { "ok": true } }), vec![]))
+        );
+        // The admins can still change it.
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &admin_context).await,
+            Ok((graphql_value!({ "updateUser": { "ok": true } }), vec![]))
+        );
+    }
+
+    struct FakeMaintenanceHandler;
+
+    #[async_trait::async_trait]
+    impl MaintenanceHandler for FakeMaintenanceHandler {
+        async fn compact_database(&self) -> crate::domain::error::Result<CompactionReport> {
+            Ok(CompactionReport {
+                size_before: 4096,
+                size_after: 1024,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn compact_database() {
+        const QUERY: &str = r#"mutation {
+          compactDatabase {
+            sizeBefore
+            sizeAfter
+            reclaimed
+          }
+        }"#;
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let mut context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        context.maintenance_handler = Some(Arc::new(FakeMaintenanceHandler));
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({
+                    "compactDatabase": {
+                        "sizeBefore": 4096.0,
+                        "sizeAfter": 1024.0,
+                        "reclaimed": 3072.0
+                    }
+                }),
+                vec![]
+            ))
+        );
+        // Only the admins can compact the database.
+        let mut context = Context::new_for_tests(
+            handler,
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        context.maintenance_handler = Some(Arc::new(FakeMaintenanceHandler));
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[derive(Default)]
+    struct FakeJwtSecretRotator(std::sync::Mutex<Vec<(Option<SecUtf8>, chrono::Duration)>>);
+
+    #[async_trait::async_trait]
+    impl JwtSecretRotator for FakeJwtSecretRotator {
+        async fn rotate_jwt_secret(
+            &self,
+            new_secret: Option<SecUtf8>,
+            grace_period: chrono::Duration,
+        ) -> crate::domain::error::Result<chrono::DateTime<chrono::Utc>> {
+            use chrono::TimeZone;
+            self.0.lock().unwrap().push((new_secret, grace_period));
+            Ok(chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn rotate_jwt_secret() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let rotator = Arc::new(FakeJwtSecretRotator::default());
+        let mut context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        context.jwt_secret_rotator = Some(rotator.clone());
+        assert_eq!(
+            execute(
+                "mutation { rotateJwtSecret { previousSecretValidUntil } }",
+                None,
+                &schema,
+                &Variables::new(),
+                &context
+            )
+            .await,
+            Ok((
+                graphql_value!({
+                    "rotateJwtSecret": { "previousSecretValidUntil": "2023-11-14T22:13:20+00:00" }
+                }),
+                vec![]
+            ))
+        );
+        execute(
+            r#"mutation {
+              rotateJwtSecret(secret: "a secret long enough", gracePeriodSeconds: 60) {
+                previousSecretValidUntil
+              }
+            }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            *rotator.0.lock().unwrap(),
+            vec![
+                (None, chrono::Duration::days(1)),
+                (
+                    Some(SecUtf8::from("a secret long enough")),
+                    chrono::Duration::seconds(60)
+                )
+            ]
+        );
+        // Invalid arguments.
+        for query in [
+            r#"mutation { rotateJwtSecret(secret: "short") { previousSecretValidUntil } }"#
+                .to_owned(),
+            format!(
+                r#"mutation {{ rotateJwtSecret(secret: "{}") {{ previousSecretValidUntil }} }}"#,
+                "a".repeat(MAX_SECRET_LENGTH + 1)
+            ),
+            "mutation { rotateJwtSecret(gracePeriodSeconds: -1) { previousSecretValidUntil } }"
+                .to_owned(),
+        ] {
+            let (_, errors) = execute(&query, None, &schema, &Variables::new(), &context)
+                .await
+                .unwrap();
+            assert_eq!(errors.len(), 1);
+        }
+        // Only the admins can rotate the secret.
+        let mut context = Context::new_for_tests(
+            handler,
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        context.jwt_secret_rotator = Some(rotator.clone());
+        let (_, errors) = execute(
+            "mutation { rotateJwtSecret { previousSecretValidUntil } }",
+            None,
+            &schema,
+            &Variables::new(),
+            &context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(rotator.0.lock().unwrap().len(), 2);
+    }
+
+    #[derive(Default)]
+    struct FakeImpersonator(std::sync::Mutex<Vec<(UserId, UserId)>>);
+
+    #[async_trait::async_trait]
+    impl Impersonator for FakeImpersonator {
+        async fn impersonate(
+            &self,
+            admin: &UserId,
+            user_id: &UserId,
+        ) -> crate::domain::error::Result<(String, chrono::DateTime<chrono::Utc>)> {
+            use chrono::TimeZone;
+            self.0
+                .lock()
+                .unwrap()
+                .push((admin.clone(), user_id.clone()));
+            Ok((
+                format!("token for {}", user_id),
+                chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn impersonate_user() {
+        const QUERY: &str = r#"mutation { impersonateUser(userId: "bob") { token validUntil } }"#;
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        // Disabled by default.
+        let mut context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+
+        let impersonator = Arc::new(FakeImpersonator::default());
+        context.impersonator = Some(impersonator.clone());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({
+                    "impersonateUser": {
+                        "token": "token for bob",
+                        "validUntil": "2023-11-14T22:13:20+00:00"
+                    }
+                }),
+                vec![]
+            ))
+        );
+        // The token is issued on behalf of the admin.
+        assert_eq!(
+            *impersonator.0.lock().unwrap(),
+            vec![(UserId::new("admin"), UserId::new("bob"))]
+        );
+        // Only the admins can impersonate.
+        let mut context = Context::new_for_tests(
+            handler,
+            ValidationResults {
+                user: UserId::new("john"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        context.impersonator = Some(impersonator.clone());
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(impersonator.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_only_admin_cannot_modify() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let context = Context::new_for_tests(
+            handler.clone(),
+            ValidationResults {
+                user: UserId::new("auditor"),
+                permission: Permission::ReadonlyAdmin,
+                impersonated_by: None,
+            },
+        );
+
+        assert_eq!(
+            execute(
+                "{ users { id } ldapConnections { boundDn } }",
+                None,
+                &schema,
+                &Variables::new(),
+                &context
+            )
+            .await,
+            Ok((
+                graphql_value!({ "users": [{ "id": "bob" }], "ldapConnections": [] }),
+                vec![]
+            ))
+        );
+
+        for mutation in [
+            r#"mutation { createUser(user: { id: "john", email: "john@bob.bob" }) { id } }"#,
+            r#"mutation { deleteUser(userId: "bob") { ok } }"#,
+        ] {
+            let (_, errors) = execute(mutation, None, &schema, &Variables::new(), &context)
+                .await
+                .unwrap();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].error().message(),
+                "Read-only administrators are not allowed to make changes"
+            );
+        }
+        let users = execute(
+            "{ users { id } }",
+            None,
+            &schema,
+            &Variables::new(),
+            &context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            users,
+            (graphql_value!({ "users": [{ "id": "bob" }] }), vec![])
+        );
+    }
+
+    #[tokio::test]
+    async fn group_manager_can_only_manage_their_group() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user_no_password(&handler, "bob").await;
+        insert_user_no_password(&handler, "john").await;
+        let team = insert_group(&handler, "team").await;
+        let other = insert_group(&handler, "other").await;
+        handler
+            .add_group_manager(team, &UserId::new("bob"))
+            .await
+            .unwrap();
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let context = Context::new_for_tests(
+            handler.clone(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        let add_john = |group_id: GroupId| {
+            format!(
+                r#"mutation {{ addUserToGroup(userId: "john", groupId: {}) {{ ok }} }}"#,
+                group_id.0
+            )
+        };
+
+        assert_eq!(
+            execute(&add_john(team), None, &schema, &Variables::new(), &context).await,
+            Ok((graphql_value!({ "addUserToGroup": { "ok": true } }), vec![]))
+        );
+        let (_, errors) = execute(&add_john(other), None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        // Managers can't modify the users themselves.
+        let (_, errors) = execute(
+            r#"mutation { updateUser(user: { id: "john", displayName: "John" }) { ok } }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            handler
+                .get_user_groups(&UserId::new("john"))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.group_id)
+                .collect::<Vec<_>>(),
+            vec![team]
+        );
+
+        let remove_john = format!(
+            r#"mutation {{ removeUserFromGroup(userId: "john", groupId: {}) {{ ok }} }}"#,
+            team.0
+        );
+        assert_eq!(
+            execute(&remove_john, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "removeUserFromGroup": { "ok": true } }),
+                vec![]
+            ))
+        );
+        assert!(handler
+            .get_user_groups(&UserId::new("john"))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn manager_group_members_can_only_manage_their_group() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user_no_password(&handler, "bob").await;
+        insert_user_no_password(&handler, "john").await;
+        let team = insert_group(&handler, "team").await;
+        let other = insert_group(&handler, "other").await;
+        let leads = insert_group(&handler, "leads").await;
+        handler
+            .add_user_to_group(&UserId::new("bob"), leads)
+            .await
+            .unwrap();
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let admin_context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        let add_manager_group = format!(
+            r#"mutation {{ addGroupManagerGroup(groupId: {}, managerGroupId: {}) {{ ok }} }}"#,
+            team.0, leads.0
+        );
+        assert_eq!(
+            execute(
+                &add_manager_group,
+                None,
+                &schema,
+                &Variables::new(),
+                &admin_context
+            )
+            .await,
+            Ok((
+                graphql_value!({ "addGroupManagerGroup": { "ok": true } }),
+                vec![]
+            ))
+        );
+        let context = Context::new_for_tests(
+            handler.clone(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        let add_john = |group_id: GroupId| {
+            format!(
+                r#"mutation {{ addUserToGroup(userId: "john", groupId: {}) {{ ok }} }}"#,
+                group_id.0
+            )
+        };
+        assert_eq!(
+            execute(&add_john(team), None, &schema, &Variables::new(), &context).await,
+            Ok((graphql_value!({ "addUserToGroup": { "ok": true } }), vec![]))
+        );
+        let (_, errors) = execute(&add_john(other), None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        // Leaving the manager group removes the rights.
+        handler
+            .remove_user_from_group(&UserId::new("bob"), leads)
+            .await
+            .unwrap();
+        let remove_john = format!(
+            r#"mutation {{ removeUserFromGroup(userId: "john", groupId: {}) {{ ok }} }}"#,
+            team.0
+        );
+        let (_, errors) = execute(&remove_john, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bulk_group_memberships() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        for user in ["bob", "john", "patrick"] {
+            insert_user_no_password(&handler, user).await;
+        }
+        let team = insert_group(&handler, "team").await;
+        handler
+            .add_user_to_group(&UserId::new("bob"), team)
+            .await
+            .unwrap();
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        let query = format!(
+            r#"mutation {{ addUsersToGroup(groupId: {}, userIds: ["bob", "john", "patrick"]) {{ userId applied }} }}"#,
+            team.0
+        );
+        assert_eq!(
+            execute(&query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "addUsersToGroup": [
+                    { "userId": "bob", "applied": false },
+                    { "userId": "john", "applied": true },
+                    { "userId": "patrick", "applied": true },
+                ] }),
+                vec![]
+            ))
+        );
+        // A missing user fails the whole change.
+        let add_unknown = format!(
+            r#"mutation {{ addUsersToGroup(groupId: {}, userIds: ["unknown"]) {{ userId applied }} }}"#,
+            team.0
+        );
+        let (_, errors) = execute(&add_unknown, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        let query = format!(
+            r#"mutation {{ removeUsersFromGroup(groupId: {}, userIds: ["bob", "john"]) {{ userId applied }} }}"#,
+            team.0
+        );
+        assert_eq!(
+            execute(&query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "removeUsersFromGroup": [
+                    { "userId": "bob", "applied": true },
+                    { "userId": "john", "applied": true },
+                ] }),
+                vec![]
+            ))
+        );
+        assert_eq!(
+            execute(&query, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!({ "removeUsersFromGroup": [
+                    { "userId": "bob", "applied": false },
+                    { "userId": "john", "applied": false },
+                ] }),
+                vec![]
+            ))
+        );
+        assert_eq!(
+            handler
+                .get_user_groups(&UserId::new("patrick"))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.group_id)
+                .collect::<Vec<_>>(),
+            vec![team]
+        );
+    }
+
+    #[tokio::test]
+    async fn group_custom_attributes() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let team = insert_group(&handler, "team").await;
+        let schema = RootNode::new(
+            Query::<SqlBackendHandler>::new(),
+            Mutation::<SqlBackendHandler>::new(),
+            EmptySubscription::<Context<SqlBackendHandler>>::new(),
+        );
+        let context = Context::new_for_tests(handler.clone(), ValidationResults::admin());
+        let run = |query: String| {
+            let schema = &schema;
+            let context = &context;
+            async move {
+                execute(&query, None, schema, &Variables::new(), context)
+                    .await
+                    .unwrap()
+            }
+        };
+        for (name, attribute_type, is_list) in
+            [("room", "String", false), ("floors", "Integer", true)]
+        {
+            assert_eq!(
+                run(format!(
+                    r#"mutation {{ addGroupAttribute(name: "{}", attributeType: "{}", isList: {}, isVisible: true, isEditable: true) {{ ok }} }}"#,
+                    name, attribute_type, is_list
+                ))
+                .await,
+                (graphql_value!({ "addGroupAttribute": { "ok": true } }), vec![])
+            );
+        }
+        let (_, errors) = run(
+            r#"mutation { addGroupAttribute(name: "bad name", attributeType: "String", isList: false, isVisible: true, isEditable: true) { ok } }"#
+                .to_owned(),
+        )
+        .await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            run(
+                r#"mutation { updateGroupAttribute(name: "room", isLdapVisible: false) { ok } }"#
+                    .to_owned()
+            )
+            .await,
+            (
+                graphql_value!({ "updateGroupAttribute": { "ok": true } }),
+                vec![]
+            )
+        );
+        let room_schema = handler
+            .get_schema()
+            .await
+            .unwrap()
+            .group_attributes
+            .get_attribute_schema("room")
+            .cloned()
+            .unwrap();
+        assert!(!room_schema.is_ldap_visible);
+        assert!(room_schema.is_editable);
+        let (_, errors) = run(
+            r#"mutation { updateUserAttribute(name: "unknown", isLdapVisible: false) { ok } }"#
+                .to_owned(),
+        )
+        .await;
+        assert_eq!(errors.len(), 1);
+        let update = |attributes: &str| {
+            format!(
+                r#"mutation {{ updateGroup(group: {{ id: {}, {} }}) {{ ok }} }}"#,
+                team.0, attributes
+            )
+        };
+        assert_eq!(
+            run(update(
+                r#"insertAttributes: [{ name: "room", value: ["1A"] }, { name: "floors", value: ["1", "2"] }]"#
+            ))
+            .await,
+            (graphql_value!({ "updateGroup": { "ok": true } }), vec![])
+        );
+        // The values are checked against the schema.
+        for attributes in [
+            r#"insertAttributes: [{ name: "floors", value: ["first"] }]"#,
+            r#"insertAttributes: [{ name: "room", value: ["1A", "1B"] }]"#,
+            r#"insertAttributes: [{ name: "unknown", value: ["1A"] }]"#,
+        ] {
+            let (_, errors) = run(update(attributes)).await;
+            assert_eq!(errors.len(), 1);
+        }
+        let query = format!(
+            r#"{{ group(groupId: {}) {{ attributes {{ name value }} }} }}"#,
+            team.0
+        );
+        assert_eq!(
+            run(query.clone()).await,
+            (
+                graphql_value!({ "group": { "attributes": [
+                    { "name": "floors", "value": ["1", "2"] },
+                    { "name": "room", "value": ["1A"] },
+                ] } }),
+                vec![]
+            )
+        );
+        run(update(r#"removeAttributes: ["floors"]"#)).await;
+        assert_eq!(
+            run(r#"mutation { deleteGroupAttribute(name: "room") { ok } }"#.to_owned()).await,
+            (
+                graphql_value!({ "deleteGroupAttribute": { "ok": true } }),
+                vec![]
+            )
+        );
+        assert_eq!(
+            run(query).await,
+            (graphql_value!({ "group": { "attributes": [] } }), vec![])
+        );
+    }
 }
@@ -1,11 +1,16 @@
 use crate::{
     domain::{
-        handler::{BackendHandler, SchemaBackendHandler},
+        handler::{
+            BackendHandler, GroupListerBackendHandler, GroupRequestFilter, SchemaBackendHandler,
+        },
         ldap::utils::{map_user_field, UserFieldType},
-        types::{GroupDetails, GroupId, JpegPhoto, UserColumn, UserId},
+        types::{AttributeType, GroupDetails, GroupId, JpegPhoto, UserColumn, UserId},
     },
     infra::{
-        access_control::{ReadonlyBackendHandler, UserReadableBackendHandler},
+        access_control::{
+            Permission, ReadonlyBackendHandler, UserReadableBackendHandler, ValidationResults,
+        },
+        diagnostics::{run_diagnostics, CheckStatus},
         graphql::api::field_error_callback,
         schema::PublicSchema,
     },
@@ -22,6 +27,9 @@ type DomainUserAndGroups = crate::domain::types::UserAndGroups;
 type DomainSchema = crate::infra::schema::PublicSchema;
 type DomainAttributeList = crate::domain::handler::AttributeList;
 type DomainAttributeSchema = crate::domain::handler::AttributeSchema;
+type DomainAttributeValue = crate::domain::types::AttributeValue;
+type DomainLdapConnection = crate::infra::ldap_connections::LdapConnection;
+type DomainDiagnosticCheck = crate::infra::diagnostics::DiagnosticCheck;
 use super::api::Context;
 
 #[derive(PartialEq, Eq, Debug, GraphQLInputObject)]
@@ -219,6 +227,56 @@ impl<Handler: BackendHandler> Query<Handler> {
             .map(Into::<PublicSchema>::into)
             .map(Into::into)?)
     }
+
+    /// The LDAP connections currently open, oldest first.
+    async fn ldap_connections(
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<LdapConnection<Handler>>> {
+        let span = debug_span!("[GraphQL query] ldap_connections");
+        context
+            .validation_result
+            .can_read_admin_data()
+            .then_some(())
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to LDAP connections",
+            ))?;
+        Ok(context
+            .ldap_connections
+            .list()
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Checks the database, the SMTP server (without sending an email), the LDAPS certificate and
+    /// the keys.
+    async fn diagnostics(context: &Context<Handler>) -> FieldResult<Diagnostics<Handler>> {
+        let span = debug_span!("[GraphQL query] diagnostics");
+        let handler = context
+            .validation_result
+            .can_read_admin_data()
+            .then(|| context.get_readonly_handler())
+            .flatten()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to diagnostics",
+            ))?;
+        Ok(Diagnostics {
+            checks: run_diagnostics(handler, &context.diagnostics_options)
+                .instrument(span)
+                .await,
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// The authenticated user, and what they are allowed to do.
+    fn me(context: &Context<Handler>) -> Me<Handler> {
+        Me {
+            validation_result: context.validation_result.clone(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -270,6 +328,16 @@ impl<Handler: BackendHandler> User<Handler> {
             .unwrap_or("")
     }
 
+    /// The preferred locale, e.g. `fr` or `pt-BR`, or an empty string.
+    fn locale(&self) -> &str {
+        self.user
+            .attributes
+            .iter()
+            .find(|a| a.name == "locale")
+            .map(|a| a.value.unwrap())
+            .unwrap_or("")
+    }
+
     fn avatar(&self) -> Option<String> {
         self.user
             .attributes
@@ -286,6 +354,47 @@ impl<Handler: BackendHandler> User<Handler> {
         self.user.uuid.as_str()
     }
 
+    /// The ID of the primary group of the user, if any: it's one of their groups.
+    fn primary_group_id(&self) -> Option<i32> {
+        self.user.primary_group.map(|g| g.0)
+    }
+
+    /// After this date, the user can't log in or bind anymore.
+    fn expiry_date(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.user
+            .expiry_date
+            .map(|date| chrono::Utc.from_utc_datetime(&date))
+    }
+
+    /// The custom and derived attributes of the user.
+    async fn attributes(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<AttributeValue<Handler>>> {
+        let span = debug_span!("[GraphQL query] user::attributes");
+        let schema = context
+            .handler
+            .get_user_restricted_lister_handler(&context.validation_result)
+            .get_schema()
+            .instrument(span)
+            .await?;
+        Ok(self
+            .user
+            .attributes
+            .iter()
+            .filter_map(|attribute| {
+                let attribute_schema = schema
+                    .user_attributes
+                    .get_attribute_schema(&attribute.name)?;
+                Some(AttributeValue {
+                    name: attribute.name.clone(),
+                    value: serialize_attribute_value(attribute, attribute_schema),
+                    _phantom: std::marker::PhantomData,
+                })
+            })
+            .collect())
+    }
+
     /// The groups to which this user belongs.
     async fn groups(&self, context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
         let span = debug_span!("[GraphQL query] user::groups");
@@ -294,7 +403,10 @@ impl<Handler: BackendHandler> User<Handler> {
         });
         let handler = context
             .get_readable_handler(&self.user.user_id)
-            .expect("We shouldn't be able to get there without readable permission");
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to user data",
+            ))?;
         Ok(handler
             .get_user_groups(&self.user.user_id)
             .instrument(span)
@@ -335,7 +447,10 @@ pub struct Group<Handler: BackendHandler> {
     display_name: String,
     creation_date: chrono::NaiveDateTime,
     uuid: String,
+    email: Option<String>,
+    description: Option<String>,
     members: Option<Vec<String>>,
+    attributes: Option<Vec<DomainAttributeValue>>,
     _phantom: std::marker::PhantomData<Box<Handler>>,
 }
 
@@ -353,6 +468,51 @@ impl<Handler: BackendHandler> Group<Handler> {
     fn uuid(&self) -> String {
         self.uuid.clone()
     }
+    /// The mailing-list address of the group, if any.
+    fn email(&self) -> Option<String> {
+        self.email.clone()
+    }
+    fn description(&self) -> Option<String> {
+        self.description.clone()
+    }
+    /// The custom attributes of the group.
+    async fn attributes(
+        &self,
+        context: &Context<Handler>,
+    ) -> FieldResult<Vec<AttributeValue<Handler>>> {
+        let span = debug_span!("[GraphQL query] group::attributes");
+        span.in_scope(|| {
+            debug!(name = %self.display_name);
+        });
+        let handler = context
+            .handler
+            .get_user_restricted_lister_handler(&context.validation_result);
+        let schema = handler.get_schema().instrument(span.clone()).await?;
+        let attributes = match &self.attributes {
+            Some(attributes) => attributes.clone(),
+            None => handler
+                .list_groups(Some(GroupRequestFilter::GroupId(GroupId(self.group_id))))
+                .instrument(span)
+                .await?
+                .into_iter()
+                .next()
+                .map(|group| group.attributes)
+                .unwrap_or_default(),
+        };
+        Ok(attributes
+            .iter()
+            .filter_map(|attribute| {
+                let attribute_schema = schema
+                    .group_attributes
+                    .get_attribute_schema(&attribute.name)?;
+                Some(AttributeValue {
+                    name: attribute.name.clone(),
+                    value: serialize_attribute_value(attribute, attribute_schema),
+                    _phantom: std::marker::PhantomData,
+                })
+            })
+            .collect())
+    }
     /// The groups to which this user belongs.
     async fn users(&self, context: &Context<Handler>) -> FieldResult<Vec<User<Handler>>> {
         let span = debug_span!("[GraphQL query] group::users");
@@ -374,6 +534,63 @@ impl<Handler: BackendHandler> Group<Handler> {
             .await
             .map(|v| v.into_iter().map(Into::into).collect())?)
     }
+    /// The users who can add and remove the members of this group.
+    async fn managers(&self, context: &Context<Handler>) -> FieldResult<Vec<User<Handler>>> {
+        let span = debug_span!("[GraphQL query] group::managers");
+        span.in_scope(|| {
+            debug!(name = %self.display_name);
+        });
+        let handler = context
+            .get_readonly_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to group data",
+            ))?;
+        let managers = handler
+            .get_group_managers(GroupId(self.group_id))
+            .instrument(span.clone())
+            .await?;
+        Ok(handler
+            .list_users(
+                Some(DomainRequestFilter::Or(
+                    managers
+                        .into_iter()
+                        .map(DomainRequestFilter::UserId)
+                        .collect(),
+                )),
+                false,
+            )
+            .instrument(span)
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
+    /// The groups whose members can add and remove the members of this group.
+    async fn manager_groups(&self, context: &Context<Handler>) -> FieldResult<Vec<Group<Handler>>> {
+        let span = debug_span!("[GraphQL query] group::manager_groups");
+        span.in_scope(|| {
+            debug!(name = %self.display_name);
+        });
+        let handler = context
+            .get_readonly_handler()
+            .ok_or_else(field_error_callback(
+                &span,
+                "Unauthorized access to group data",
+            ))?;
+        let manager_groups = handler
+            .get_group_manager_groups(GroupId(self.group_id))
+            .instrument(span.clone())
+            .await?;
+        Ok(handler
+            .list_groups(Some(GroupRequestFilter::Or(
+                manager_groups
+                    .into_iter()
+                    .map(GroupRequestFilter::GroupId)
+                    .collect(),
+            )))
+            .instrument(span)
+            .await
+            .map(|v| v.into_iter().map(Into::into).collect())?)
+    }
 }
 
 impl<Handler: BackendHandler> From<GroupDetails> for Group<Handler> {
@@ -383,7 +600,10 @@ impl<Handler: BackendHandler> From<GroupDetails> for Group<Handler> {
             display_name: group_details.display_name,
             creation_date: group_details.creation_date,
             uuid: group_details.uuid.into_string(),
+            email: group_details.email,
+            description: group_details.description,
             members: None,
+            attributes: None,
             _phantom: std::marker::PhantomData,
         }
     }
@@ -396,12 +616,65 @@ impl<Handler: BackendHandler> From<DomainGroup> for Group<Handler> {
             display_name: group.display_name,
             creation_date: group.creation_date,
             uuid: group.uuid.into_string(),
+            email: group.email,
+            description: group.description,
             members: Some(group.users.into_iter().map(UserId::into_string).collect()),
+            attributes: Some(group.attributes),
             _phantom: std::marker::PhantomData,
         }
     }
 }
 
+fn serialize_attribute_value(
+    attribute: &DomainAttributeValue,
+    schema: &DomainAttributeSchema,
+) -> Vec<String> {
+    let convert_date = |date| chrono::Utc.from_utc_datetime(&date).to_rfc3339();
+    let value = &attribute.value;
+    match (schema.attribute_type, schema.is_list) {
+        (AttributeType::String, false) => vec![value.unwrap::<String>()],
+        (AttributeType::Integer, false) => vec![value.unwrap::<i64>().to_string()],
+        (AttributeType::JpegPhoto, false) => vec![String::from(&value.unwrap::<JpegPhoto>())],
+        (AttributeType::DateTime, false) => {
+            vec![convert_date(value.unwrap::<chrono::NaiveDateTime>())]
+        }
+        (AttributeType::String, true) => value.unwrap::<Vec<String>>(),
+        (AttributeType::Integer, true) => value
+            .unwrap::<Vec<i64>>()
+            .into_iter()
+            .map(|i| i.to_string())
+            .collect(),
+        (AttributeType::JpegPhoto, true) => value
+            .unwrap::<Vec<JpegPhoto>>()
+            .iter()
+            .map(String::from)
+            .collect(),
+        (AttributeType::DateTime, true) => value
+            .unwrap::<Vec<chrono::NaiveDateTime>>()
+            .into_iter()
+            .map(convert_date)
+            .collect(),
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+/// The value of a custom attribute, converted to strings.
+pub struct AttributeValue<Handler: BackendHandler> {
+    name: String,
+    value: Vec<String>,
+    _phantom: std::marker::PhantomData<Box<Handler>>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> AttributeValue<Handler> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn value(&self) -> &[String] {
+        &self.value
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct AttributeSchema<Handler: BackendHandler> {
     schema: DomainAttributeSchema,
@@ -426,6 +699,9 @@ impl<Handler: BackendHandler> AttributeSchema<Handler> {
     fn is_editable(&self) -> bool {
         self.schema.is_editable
     }
+    fn is_ldap_visible(&self) -> bool {
+        self.schema.is_ldap_visible
+    }
     fn is_hardcoded(&self) -> bool {
         self.schema.is_hardcoded
     }
@@ -467,6 +743,163 @@ impl<Handler: BackendHandler> From<DomainAttributeList> for AttributeList<Handle
     }
 }
 
+#[derive(PartialEq, Eq, Debug)]
+/// An open LDAP connection.
+pub struct LdapConnection<Handler: BackendHandler> {
+    connection: DomainLdapConnection,
+    _phantom: std::marker::PhantomData<Box<Handler>>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> LdapConnection<Handler> {
+    /// The DN the connection is bound as, if any.
+    fn bound_dn(&self) -> Option<&str> {
+        self.connection.bound_dn.as_deref()
+    }
+    fn source_ip(&self) -> Option<String> {
+        self.connection.source_ip.map(|ip| ip.to_string())
+    }
+    fn connected_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.connection.connected_at
+    }
+    /// The number of operations handled on the connection.
+    fn operation_count(&self) -> i32 {
+        i32::try_from(self.connection.operation_count).unwrap_or(i32::MAX)
+    }
+}
+
+impl<Handler: BackendHandler> From<DomainLdapConnection> for LdapConnection<Handler> {
+    fn from(connection: DomainLdapConnection) -> Self {
+        Self {
+            connection,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// The report of the self-diagnostics.
+pub struct Diagnostics<Handler: BackendHandler> {
+    checks: Vec<DomainDiagnosticCheck>,
+    _phantom: std::marker::PhantomData<Box<Handler>>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> Diagnostics<Handler> {
+    /// Whether none of the checks failed.
+    fn healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Error)
+    }
+    fn checks(&self) -> Vec<DiagnosticCheck<Handler>> {
+        self.checks.iter().cloned().map(Into::into).collect()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct DiagnosticCheck<Handler: BackendHandler> {
+    check: DomainDiagnosticCheck,
+    _phantom: std::marker::PhantomData<Box<Handler>>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> DiagnosticCheck<Handler> {
+    fn name(&self) -> &str {
+        self.check.name
+    }
+    /// One of ok, warning, error or disabled.
+    fn status(&self) -> &'static str {
+        self.check.status.into()
+    }
+    fn detail(&self) -> &str {
+        &self.check.detail
+    }
+}
+
+impl<Handler: BackendHandler> From<DomainDiagnosticCheck> for DiagnosticCheck<Handler> {
+    fn from(check: DomainDiagnosticCheck) -> Self {
+        Self {
+            check,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// The user the token was issued to.
+pub struct Me<Handler: BackendHandler> {
+    validation_result: ValidationResults,
+    _phantom: std::marker::PhantomData<Box<Handler>>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> Me<Handler> {
+    fn user_id(&self) -> &str {
+        self.validation_result.user.as_str()
+    }
+    async fn permissions(&self, context: &Context<Handler>) -> FieldResult<Permissions<Handler>> {
+        let span = debug_span!("[GraphQL query] permissions");
+        let managed_groups = context
+            .handler
+            .get_managed_groups(&self.validation_result)
+            .instrument(span)
+            .await?;
+        Ok(Permissions {
+            validation_result: self.validation_result.clone(),
+            managed_groups,
+            _phantom: std::marker::PhantomData,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+/// What the user is allowed to do, to hide the unavailable actions in a UI.
+pub struct Permissions<Handler: BackendHandler> {
+    validation_result: ValidationResults,
+    managed_groups: Vec<DomainGroup>,
+    _phantom: std::marker::PhantomData<Box<Handler>>,
+}
+
+#[graphql_object(context = Context<Handler>)]
+impl<Handler: BackendHandler> Permissions<Handler> {
+    /// One of admin, readonly_admin, password_manager, readonly or regular.
+    fn level(&self) -> &'static str {
+        self.validation_result.permission.into()
+    }
+    /// Can read and modify all the users and groups.
+    fn is_admin(&self) -> bool {
+        self.validation_result.is_admin()
+    }
+    /// Can read everything an admin can, but can't modify anything.
+    fn is_readonly_admin(&self) -> bool {
+        self.validation_result.is_readonly_admin()
+    }
+    /// Can list all the users and groups, not only themselves and their groups.
+    fn can_read_all(&self) -> bool {
+        self.validation_result.can_read_all()
+    }
+    /// Can see the administration data, like the open LDAP connections and the diagnostics.
+    fn can_read_admin_data(&self) -> bool {
+        self.validation_result.can_read_admin_data()
+    }
+    /// Can change the password of the other users (except the admins, for password managers).
+    fn can_change_passwords(&self) -> bool {
+        matches!(
+            self.validation_result.permission,
+            Permission::Admin | Permission::PasswordManager
+        )
+    }
+    /// The groups whose members the user can add and remove as a manager.
+    fn managed_groups(&self) -> Vec<Group<Handler>> {
+        self.managed_groups
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect()
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Schema<Handler: BackendHandler> {
     schema: DomainSchema,
@@ -496,7 +929,7 @@ impl<Handler: BackendHandler> From<DomainSchema> for Schema<Handler> {
 mod tests {
     use super::*;
     use crate::{
-        domain::{handler::AttributeList, types::AttributeType},
+        domain::{error::DomainError, handler::AttributeList, types::AttributeType},
         infra::{
             access_control::{Permission, ValidationResults},
             test_utils::{setup_default_schema, MockTestBackendHandler},
@@ -556,12 +989,16 @@ mod tests {
             display_name: "Bobbersons".to_string(),
             creation_date: chrono::Utc.timestamp_nanos(42).naive_utc(),
             uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            email: None,
+            description: None,
         });
         groups.insert(GroupDetails {
             group_id: GroupId(7),
             display_name: "Jefferees".to_string(),
             creation_date: chrono::Utc.timestamp_nanos(12).naive_utc(),
             uuid: crate::uuid!("b1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            email: None,
+            description: None,
         });
         mock.expect_get_user_groups()
             .with(eq(UserId::new("bob")))
@@ -860,6 +1297,7 @@ mod tests {
                         is_list: false,
                         is_visible: false,
                         is_editable: true,
+                        is_ldap_visible: true,
                         is_hardcoded: true,
                     }],
                 },
@@ -874,6 +1312,7 @@ mod tests {
             ValidationResults {
                 user: UserId::new("bob"),
                 permission: Permission::Regular,
+                impersonated_by: None,
             },
         );
 
@@ -899,4 +1338,345 @@ mod tests {
             ))
         );
     }
+
+    #[tokio::test]
+    async fn regular_user_can_only_read_self() {
+        let query = |user_id: &str| {
+            format!(
+                r#"{{ user(userId: "{}") {{ id email groups {{ id }} }} }}"#,
+                user_id
+            )
+        };
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_details()
+            .with(eq(UserId::new("bob")))
+            .times(1)
+            .return_once(|_| {
+                Ok(DomainUser {
+                    user_id: UserId::new("bob"),
+                    email: "bob@bobbers.on".to_string(),
+                    ..Default::default()
+                })
+            });
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .times(1)
+            .return_once(|_| Ok(HashSet::new()));
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            mock,
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(&query("bob"), None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "user": {
+                        "id": "bob",
+                        "email": "bob@bobbers.on",
+                        "groups": [],
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        // Other users, and the list of users, are not accessible.
+        let (data, errors) = execute(&query("john"), None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert!(data.is_null());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].error().message(),
+            "Unauthorized access to user data"
+        );
+        let (_, errors) = execute(
+            r#"{ users { id } }"#,
+            None,
+            &schema,
+            &Variables::new(),
+            &context,
+        )
+        .await
+        .unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn list_ldap_connections() {
+        const QUERY: &str = r#"{
+          ldapConnections {
+            boundDn
+            sourceIp
+            operationCount
+          }
+        }"#;
+
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            MockTestBackendHandler::new(),
+            ValidationResults::admin(),
+        );
+        let connection = context
+            .ldap_connections
+            .register(Some("127.0.0.1".parse().unwrap()))
+            .unwrap();
+        connection.record_operation(Some("uid=bob,ou=people,dc=example,dc=com".to_owned()));
+
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "ldapConnections": [
+                        {
+                            "boundDn": "uid=bob,ou=people,dc=example,dc=com",
+                            "sourceIp": "127.0.0.1",
+                            "operationCount": 1,
+                        },
+                    ]
+                }),
+                vec![]
+            ))
+        );
+        drop(connection);
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((graphql_value!({ "ldapConnections": [] }), vec![]))
+        );
+
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            MockTestBackendHandler::new(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Readonly,
+                impersonated_by: None,
+            },
+        );
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn diagnostics() {
+        const QUERY: &str = r#"{
+          diagnostics {
+            healthy
+            checks {
+              name
+              status
+            }
+          }
+        }"#;
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .times(1)
+            .return_once(|_| Ok(vec![]));
+        let context =
+            Context::<MockTestBackendHandler>::new_for_tests(mock, ValidationResults::admin());
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "diagnostics": {
+                        "healthy": true,
+                        "checks": [
+                            {"name": "database", "status": "ok"},
+                            {"name": "smtp", "status": "disabled"},
+                            {"name": "ldaps_certificate", "status": "disabled"},
+                            {"name": "keys", "status": "ok"},
+                        ]
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        // A database outage is reported, the other checks still run.
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups().times(1).return_once(|_| {
+            Err(DomainError::DatabaseError(sea_orm::DbErr::Conn(
+                sea_orm::RuntimeErr::Internal("Connection refused".to_owned()),
+            )))
+        });
+        let context =
+            Context::<MockTestBackendHandler>::new_for_tests(mock, ValidationResults::admin());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "diagnostics": {
+                        "healthy": false,
+                        "checks": [
+                            {"name": "database", "status": "error"},
+                            {"name": "smtp", "status": "disabled"},
+                            {"name": "ldaps_certificate", "status": "disabled"},
+                            {"name": "keys", "status": "ok"},
+                        ]
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            MockTestBackendHandler::new(),
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Readonly,
+                impersonated_by: None,
+            },
+        );
+        let (_, errors) = execute(QUERY, None, &schema, &Variables::new(), &context)
+            .await
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn me_permissions() {
+        const QUERY: &str = r#"{
+          me {
+            userId
+            permissions {
+              level
+              isAdmin
+              isReadonlyAdmin
+              canReadAll
+              canReadAdminData
+              canChangePasswords
+              managedGroups {
+                displayName
+              }
+            }
+          }
+        }"#;
+
+        let make_group = |id, name: &str| DomainGroup {
+            id: GroupId(id),
+            display_name: name.to_owned(),
+            creation_date: chrono::Utc.timestamp_nanos(42).naive_utc(),
+            uuid: crate::uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            email: None,
+            description: None,
+            modified_date: chrono::Utc.timestamp_nanos(42).naive_utc(),
+            users: vec![],
+            attributes: vec![],
+        };
+        let schema = schema(Query::<MockTestBackendHandler>::new());
+
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(Some(GroupRequestFilter::ManagedBy(UserId::new(
+                "admin",
+            )))))
+            .return_once(|_| Ok(vec![]));
+        let context =
+            Context::<MockTestBackendHandler>::new_for_tests(mock, ValidationResults::admin());
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "me": {
+                        "userId": "admin",
+                        "permissions": {
+                            "level": "admin",
+                            "isAdmin": true,
+                            "isReadonlyAdmin": false,
+                            "canReadAll": true,
+                            "canReadAdminData": true,
+                            "canChangePasswords": true,
+                            "managedGroups": [],
+                        }
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        // The read-only admins can't manage any group, the backend is not queried.
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            MockTestBackendHandler::new(),
+            ValidationResults {
+                user: UserId::new("auditor"),
+                permission: Permission::ReadonlyAdmin,
+                impersonated_by: None,
+            },
+        );
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "me": {
+                        "userId": "auditor",
+                        "permissions": {
+                            "level": "readonly_admin",
+                            "isAdmin": false,
+                            "isReadonlyAdmin": true,
+                            "canReadAll": true,
+                            "canReadAdminData": true,
+                            "canChangePasswords": false,
+                            "managedGroups": [],
+                        }
+                    }
+                }),
+                vec![]
+            ))
+        );
+
+        // The privileged groups can only be changed by the admins, even for their managers.
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(Some(GroupRequestFilter::ManagedBy(UserId::new("bob")))))
+            .return_once(move |_| {
+                Ok(vec![
+                    make_group(3, "team"),
+                    make_group(4, "lldap_password_manager"),
+                ])
+            });
+        let context = Context::<MockTestBackendHandler>::new_for_tests(
+            mock,
+            ValidationResults {
+                user: UserId::new("bob"),
+                permission: Permission::Regular,
+                impersonated_by: None,
+            },
+        );
+        assert_eq!(
+            execute(QUERY, None, &schema, &Variables::new(), &context).await,
+            Ok((
+                graphql_value!(
+                {
+                    "me": {
+                        "userId": "bob",
+                        "permissions": {
+                            "level": "regular",
+                            "isAdmin": false,
+                            "isReadonlyAdmin": false,
+                            "canReadAll": false,
+                            "canReadAdminData": false,
+                            "canChangePasswords": false,
+                            "managedGroups": [{"displayName": "team"}],
+                        }
+                    }
+                }),
+                vec![]
+            ))
+        );
+    }
 }
@@ -0,0 +1,476 @@
+//! Limits on the depth and the complexity of the GraphQL queries, checked before executing them
+//! so that deeply nested queries (users → groups → users → ...) can't be used to load the server.
+//!
+//! The queries are only parsed enough to find their selection sets: the complexity is the number
+//! of fields, with the fragments expanded. The queries that can't be parsed are rejected, as are
+//! the ones nested deeper than `MAX_NESTING` even without a depth limit, to bound the recursion.
+
+use std::collections::HashMap;
+
+/// The deepest nesting of fields, and of fragments, that is ever parsed.
+const MAX_NESTING: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// Maximum nesting of the fields, 0 for no limit.
+    pub max_depth: usize,
+    /// Maximum number of fields, 0 for no limit.
+    pub max_complexity: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Punctuator(u8),
+    Spread,
+    Name(&'a str),
+    /// A number or a string.
+    Value,
+}
+
+fn tokenize(query: &str) -> Option<Vec<Token<'_>>> {
+    let bytes = query.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' if query[i..].starts_with(r#"""""#) => {
+                let end = query[i + 3..].replace(r#"\""""#, "____").find(r#"""""#)?;
+                i += end + 6;
+                tokens.push(Token::Value);
+            }
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i)? {
+                        b'\\' => i += 2,
+                        b'"' => break,
+                        b'\n' => return None,
+                        _ => i += 1,
+                    }
+                }
+                i += 1;
+                tokens.push(Token::Value);
+            }
+            b'.' if query[i..].starts_with("...") => {
+                i += 3;
+                tokens.push(Token::Spread);
+            }
+            c @ (b'{' | b'}' | b'(' | b')' | b'[' | b']' | b':' | b'=' | b'@' | b'$' | b'!'
+            | b'|' | b'&') => {
+                i += 1;
+                tokens.push(Token::Punctuator(c));
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                tokens.push(Token::Name(&query[start..i]));
+            }
+            c if c.is_ascii_digit() || c == b'-' => {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || b"-+._".contains(&bytes[i]))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Value);
+            }
+            // Whitespace, commas and anything juniper will reject anyway.
+            _ => i += 1,
+        }
+    }
+    Some(tokens)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Selection<'a> {
+    Field(Vec<Selection<'a>>),
+    FragmentSpread(&'a str),
+    InlineFragment(Vec<Selection<'a>>),
+}
+
+#[derive(Debug)]
+enum ParseError {
+    /// The query is nested deeper than the limit, there is no need to parse further.
+    TooDeep,
+    Invalid,
+}
+
+#[derive(Debug, Default)]
+struct Document<'a> {
+    operations: Vec<Vec<Selection<'a>>>,
+    fragments: HashMap<&'a str, Vec<Selection<'a>>>,
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    /// The selection sets nested deeper than that are not parsed.
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn advance(&mut self) -> Result<Token<'a>, ParseError> {
+        let token = self.peek().ok_or(ParseError::Invalid)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn skip_arguments(&mut self) -> Result<(), ParseError> {
+        if self.peek() != Some(Token::Punctuator(b'(')) {
+            return Ok(());
+        }
+        let mut depth = 0;
+        loop {
+            match self.advance()? {
+                Token::Punctuator(b'(') => depth += 1,
+                Token::Punctuator(b')') => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_directives(&mut self) -> Result<(), ParseError> {
+        while self.peek() == Some(Token::Punctuator(b'@')) {
+            self.position += 1;
+            self.advance()?;
+            self.skip_arguments()?;
+        }
+        Ok(())
+    }
+
+    /// Skips the name, the variables, the type condition and the directives of a definition.
+    fn skip_to_selection_set(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.peek().ok_or(ParseError::Invalid)? {
+                Token::Punctuator(b'{') => return Ok(()),
+                Token::Punctuator(b'(') => self.skip_arguments()?,
+                _ => self.position += 1,
+            }
+        }
+    }
+
+    /// The depth counts the levels of fields, the nesting also counts the inline fragments.
+    fn parse_selection_set(
+        &mut self,
+        depth: usize,
+        nesting: usize,
+    ) -> Result<Vec<Selection<'a>>, ParseError> {
+        if self.advance()? != Token::Punctuator(b'{') {
+            return Err(ParseError::Invalid);
+        }
+        let mut selections = Vec::new();
+        loop {
+            match self.advance()? {
+                Token::Punctuator(b'}') => return Ok(selections),
+                Token::Spread => match self.peek() {
+                    Some(Token::Name(name)) if name != "on" => {
+                        self.position += 1;
+                        self.skip_directives()?;
+                        selections.push(Selection::FragmentSpread(name));
+                    }
+                    _ => {
+                        if nesting >= self.max_depth.saturating_mul(2) {
+                            return Err(ParseError::TooDeep);
+                        }
+                        self.skip_to_selection_set()?;
+                        selections.push(Selection::InlineFragment(
+                            self.parse_selection_set(depth, nesting + 1)?,
+                        ));
+                    }
+                },
+                Token::Name(_) => {
+                    if self.peek() == Some(Token::Punctuator(b':')) {
+                        self.position += 1;
+                        self.advance()?;
+                    }
+                    self.skip_arguments()?;
+                    self.skip_directives()?;
+                    let children = if self.peek() == Some(Token::Punctuator(b'{')) {
+                        if depth >= self.max_depth {
+                            return Err(ParseError::TooDeep);
+                        }
+                        self.parse_selection_set(depth + 1, nesting + 1)?
+                    } else {
+                        Vec::new()
+                    };
+                    selections.push(Selection::Field(children));
+                }
+                _ => return Err(ParseError::Invalid),
+            }
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Document<'a>, ParseError> {
+        let mut document = Document::default();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Name("fragment") => {
+                    self.position += 1;
+                    let name = match self.advance()? {
+                        Token::Name(name) => name,
+                        _ => return Err(ParseError::Invalid),
+                    };
+                    self.skip_to_selection_set()?;
+                    let selections = self.parse_selection_set(1, 1)?;
+                    document.fragments.insert(name, selections);
+                }
+                Token::Name(_) | Token::Punctuator(b'{') => {
+                    self.skip_to_selection_set()?;
+                    let selections = self.parse_selection_set(1, 1)?;
+                    document.operations.push(selections);
+                }
+                _ => return Err(ParseError::Invalid),
+            }
+        }
+        Ok(document)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Cost {
+    depth: usize,
+    complexity: usize,
+}
+
+impl<'a> Document<'a> {
+    /// The nesting counts the levels of fields, of inline fragments and of fragment spreads.
+    fn selection_set_cost(
+        &self,
+        selections: &[Selection<'a>],
+        fragment_costs: &mut HashMap<&'a str, Option<Cost>>,
+        nesting: usize,
+    ) -> Result<Cost, ParseError> {
+        if nesting > MAX_NESTING * 2 {
+            return Err(ParseError::TooDeep);
+        }
+        let mut cost = Cost::default();
+        for selection in selections {
+            let selection_cost = match selection {
+                Selection::Field(children) => {
+                    let children =
+                        self.selection_set_cost(children, fragment_costs, nesting + 1)?;
+                    Cost {
+                        depth: children.depth + 1,
+                        complexity: children.complexity.saturating_add(1),
+                    }
+                }
+                Selection::InlineFragment(children) => {
+                    self.selection_set_cost(children, fragment_costs, nesting + 1)?
+                }
+                Selection::FragmentSpread(name) => {
+                    self.fragment_cost(name, fragment_costs, nesting + 1)?
+                }
+            };
+            cost.depth = cost.depth.max(selection_cost.depth);
+            cost.complexity = cost.complexity.saturating_add(selection_cost.complexity);
+        }
+        Ok(cost)
+    }
+
+    /// The costs are memoized, to not expand the same fragment again and again.
+    fn fragment_cost(
+        &self,
+        name: &'a str,
+        fragment_costs: &mut HashMap<&'a str, Option<Cost>>,
+        nesting: usize,
+    ) -> Result<Cost, ParseError> {
+        match fragment_costs.get(name) {
+            Some(Some(cost)) => return Ok(*cost),
+            // The fragment includes itself: juniper rejects the query.
+            Some(None) => return Ok(Cost::default()),
+            None => (),
+        }
+        let selections = match self.fragments.get(name) {
+            None => return Ok(Cost::default()),
+            Some(selections) => selections,
+        };
+        fragment_costs.insert(name, None);
+        let cost = self.selection_set_cost(selections, fragment_costs, nesting)?;
+        fragment_costs.insert(name, Some(cost));
+        Ok(cost)
+    }
+
+    /// The highest cost of the operations.
+    fn cost(&self) -> Result<Cost, ParseError> {
+        let mut fragment_costs = HashMap::new();
+        self.operations
+            .iter()
+            .try_fold(Cost::default(), |a, operation| {
+                let b = self.selection_set_cost(operation, &mut fragment_costs, 1)?;
+                Ok(Cost {
+                    depth: a.depth.max(b.depth),
+                    complexity: a.complexity.max(b.complexity),
+                })
+            })
+    }
+}
+
+fn depth_error(depth: impl std::fmt::Display, max_depth: usize) -> String {
+    format!(
+        "The query is too deep: {} levels of fields, the maximum is {}",
+        depth, max_depth
+    )
+}
+
+/// Checks the limits, and returns an error message for the client if the query is over them or
+/// can't be parsed.
+pub fn check_query_limits(query: &str, limits: &QueryLimits) -> Result<(), String> {
+    let max_depth = match limits.max_depth {
+        0 => MAX_NESTING,
+        max_depth => max_depth.min(MAX_NESTING),
+    };
+    let invalid_query_error = || "The query could not be parsed".to_owned();
+    let mut parser = Parser {
+        tokens: tokenize(query).ok_or_else(invalid_query_error)?,
+        position: 0,
+        max_depth,
+    };
+    let cost = match parser.parse_document().and_then(|document| document.cost()) {
+        Err(ParseError::TooDeep) => {
+            return Err(depth_error(format!("more than {}", max_depth), max_depth))
+        }
+        Err(ParseError::Invalid) => return Err(invalid_query_error()),
+        Ok(cost) => cost,
+    };
+    if limits.max_depth != 0 && cost.depth > limits.max_depth {
+        return Err(depth_error(cost.depth, limits.max_depth));
+    }
+    if limits.max_complexity != 0 && cost.complexity > limits.max_complexity {
+        return Err(format!(
+            "The query is too complex: {} fields, the maximum is {}",
+            cost.complexity, limits.max_complexity
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_cost(query: &str) -> Cost {
+        let mut parser = Parser {
+            tokens: tokenize(query).unwrap(),
+            position: 0,
+            max_depth: usize::MAX,
+        };
+        parser.parse_document().unwrap().cost().unwrap()
+    }
+
+    #[test]
+    fn test_query_cost() {
+        assert_eq!(
+            get_cost("{ apiVersion }"),
+            Cost {
+                depth: 1,
+                complexity: 1
+            }
+        );
+        assert_eq!(
+            get_cost(
+                r#"query GetUser($id: String!) {
+                  user(userId: $id) {
+                    id
+                    displayName @include(if: true)
+                    # Comment with a } brace.
+                    groups { id name: displayName }
+                  }
+                }"#
+            ),
+            Cost {
+                depth: 3,
+                complexity: 6
+            }
+        );
+        // The fragments are expanded, in every place they are used.
+        assert_eq!(
+            get_cost(
+                r#"{ users(filters: {eq: {field: "email", value: "}"}}) { ...UserFields } }
+                fragment UserFields on User { id groups { ...GroupFields } }
+                fragment GroupFields on Group { id users { id } ... on Group { uuid } }"#
+            ),
+            Cost {
+                depth: 4,
+                complexity: 7
+            }
+        );
+        // A fragment including itself doesn't loop.
+        assert_eq!(
+            get_cost("{ ...Loop } fragment Loop on Query { user(userId: \"a\") { ...Loop } }"),
+            Cost {
+                depth: 1,
+                complexity: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_query_limits() {
+        let limits = QueryLimits {
+            max_depth: 3,
+            max_complexity: 4,
+        };
+        check_query_limits("{ users { groups { id } } }", &limits).unwrap();
+        assert_eq!(
+            check_query_limits("{ users { groups { users { id } } } }", &limits).unwrap_err(),
+            "The query is too deep: more than 3 levels of fields, the maximum is 3"
+        );
+        // Hidden in a fragment.
+        assert_eq!(
+            check_query_limits(
+                "{ users { ...Groups } } fragment Groups on User { groups { users { id } } }",
+                &limits
+            )
+            .unwrap_err(),
+            "The query is too deep: 4 levels of fields, the maximum is 3"
+        );
+        assert_eq!(
+            check_query_limits("{ users { id email displayName uuid } }", &limits).unwrap_err(),
+            "The query is too complex: 5 fields, the maximum is 4"
+        );
+        // A deeply nested query is rejected without parsing it all.
+        let deep_query = format!("{}{}", "{ users ".repeat(100_000), "}".repeat(100_000));
+        check_query_limits(&deep_query, &limits).unwrap_err();
+        let deep_query = format!(
+            "{{ users {}{{ id }}{} }}",
+            "{ ... on User ".repeat(100_000),
+            "}".repeat(100_000)
+        );
+        check_query_limits(&deep_query, &limits).unwrap_err();
+        // The invalid queries are rejected.
+        assert_eq!(
+            check_query_limits("{ users { id ", &limits).unwrap_err(),
+            "The query could not be parsed"
+        );
+        check_query_limits("{ user(userId: \"bob) { id } }", &limits).unwrap_err();
+        let no_limits = QueryLimits {
+            max_depth: 0,
+            max_complexity: 0,
+        };
+        check_query_limits("{ users { groups { users { id } } } }", &no_limits).unwrap();
+        check_query_limits("{ users { id ", &no_limits).unwrap_err();
+        // Even without limits, the nesting is bounded.
+        let deep_query = format!("{}{}", "{ users ".repeat(100_000), "}".repeat(100_000));
+        assert_eq!(
+            check_query_limits(&deep_query, &no_limits).unwrap_err(),
+            "The query is too deep: more than 256 levels of fields, the maximum is 256"
+        );
+        let fragment_chain = (0..100_000)
+            .map(|i| format!("fragment F{} on Query {{ ...F{} }}", i, i + 1))
+            .collect::<String>();
+        check_query_limits(&format!("{{ ...F0 }} {}", fragment_chain), &no_limits).unwrap_err();
+    }
+}
@@ -1,17 +1,23 @@
-use crate::infra::{configuration::LdapsOptions, ldap_server::read_certificates};
+use crate::infra::{
+    cli::TestLdapOpts,
+    configuration::{Configuration, LdapsOptions},
+    ldap_server::read_certificates,
+};
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use futures_util::SinkExt;
 use ldap3_proto::{
     proto::{
-        LdapDerefAliases, LdapFilter, LdapMsg, LdapOp, LdapSearchRequest, LdapSearchResultEntry,
+        LdapBindCred, LdapBindRequest, LdapBindResponse, LdapDerefAliases, LdapFilter, LdapMsg,
+        LdapOp, LdapResult, LdapResultCode, LdapSearchRequest, LdapSearchResultEntry,
         LdapSearchScope,
     },
     LdapCodec,
 };
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio_rustls::TlsConnector as RustlsTlsConnector;
 use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
 
 async fn check_ldap_endpoint<Stream>(stream: Stream) -> Result<()>
 where
@@ -74,7 +80,7 @@ pub async fn check_ldap(port: u16) -> Result<()> {
     check_ldap_endpoint(TcpStream::connect(format!("localhost:{}", port)).await?).await
 }
 
-fn get_root_certificates() -> rustls::RootCertStore {
+pub(crate) fn get_root_certificates() -> rustls::RootCertStore {
     let mut root_store = rustls::RootCertStore::empty();
     root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
         rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
@@ -132,6 +138,10 @@ pub async fn check_ldaps(ldaps_options: &LdapsOptions) -> Result<()> {
         info!("LDAPS not enabled");
         return Ok(());
     };
+    if ldaps_options.require_client_cert {
+        info!("LDAPS requires a client certificate, skipping the check");
+        return Ok(());
+    }
     let tls_connector =
         get_tls_connector(ldaps_options).context("while preparing the tls connection")?;
     let url = format!("localhost:{}", ldaps_options.port);
@@ -151,10 +161,232 @@ pub async fn check_ldaps(ldaps_options: &LdapsOptions) -> Result<()> {
 }
 
 #[instrument(skip_all, level = "info", err)]
-pub async fn check_api(port: u16) -> Result<()> {
-    reqwest::get(format!("http://localhost:{}/health", port))
+pub async fn check_api(config: &Configuration) -> Result<()> {
+    if config.disable_http_ui {
+        info!("HTTP server disabled");
+        return Ok(());
+    }
+    reqwest::get(format!("http://localhost:{}/health", config.http_port))
         .await?
         .error_for_status()?;
     info!("Success");
     Ok(())
 }
+
+/// Outcome of a successful `test-ldap` run.
+#[derive(Debug)]
+pub struct LdapTestReport {
+    pub bind_duration: Duration,
+    pub search_duration: Duration,
+    pub entries: Vec<String>,
+}
+
+fn check_ldap_result(result: &LdapResult, action: &str) -> Result<()> {
+    ensure!(
+        result.code == LdapResultCode::Success,
+        "{} failed: {:?} {}",
+        action,
+        result.code,
+        result.message
+    );
+    Ok(())
+}
+
+async fn test_ldap_endpoint<Stream>(
+    stream: Stream,
+    bind_dn: &str,
+    password: &str,
+    search_base: &str,
+) -> Result<LdapTestReport>
+where
+    Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    use tokio_stream::StreamExt;
+    let (r, w) = tokio::io::split(stream);
+    let mut requests = FramedRead::new(r, LdapCodec);
+    let mut resp = FramedWrite::new(w, LdapCodec);
+    let no_answer = || anyhow!("No answer from LDAP server");
+    let invalid_answer = "Invalid answer from LDAP server";
+
+    let start = Instant::now();
+    resp.send(LdapMsg {
+        msgid: 1,
+        op: LdapOp::BindRequest(LdapBindRequest {
+            dn: bind_dn.to_string(),
+            cred: LdapBindCred::Simple(password.to_string()),
+        }),
+        ctrl: vec![],
+    })
+    .await?;
+    let msg = requests.next().await.ok_or_else(no_answer)??;
+    debug!("Received message: {:?}", &msg);
+    match msg.op {
+        LdapOp::BindResponse(LdapBindResponse { res, .. }) => check_ldap_result(&res, "Bind")?,
+        _ => bail!(invalid_answer),
+    }
+    let bind_duration = start.elapsed();
+    info!("Bound as {} in {:?}", bind_dn, bind_duration);
+
+    let start = Instant::now();
+    resp.send(LdapMsg {
+        msgid: 2,
+        op: LdapOp::SearchRequest(LdapSearchRequest {
+            base: search_base.to_string(),
+            scope: LdapSearchScope::Subtree,
+            aliases: LdapDerefAliases::Never,
+            sizelimit: 0,
+            timelimit: 0,
+            typesonly: false,
+            filter: LdapFilter::Present("objectClass".to_string()),
+            attrs: vec!["1.1".to_string()],
+        }),
+        ctrl: vec![],
+    })
+    .await?;
+    let mut entries = Vec::new();
+    loop {
+        let msg = requests.next().await.ok_or_else(no_answer)??;
+        debug!("Received message: {:?}", &msg);
+        match msg.op {
+            LdapOp::SearchResultEntry(LdapSearchResultEntry { dn, .. }) => entries.push(dn),
+            LdapOp::SearchResultDone(res) => {
+                check_ldap_result(&res, "Search")?;
+                break;
+            }
+            _ => bail!(invalid_answer),
+        }
+    }
+    let search_duration = start.elapsed();
+    info!(
+        "Search under {} returned {} entries in {:?}",
+        search_base,
+        entries.len(),
+        search_duration
+    );
+    for dn in &entries {
+        info!("  {}", dn);
+    }
+
+    resp.send(LdapMsg {
+        msgid: 3,
+        op: LdapOp::UnbindRequest,
+        ctrl: vec![],
+    })
+    .await?;
+    if let Err(e) = resp.close().await {
+        warn!("Error while closing the connection: {:#}", e);
+    }
+    Ok(LdapTestReport {
+        bind_duration,
+        search_duration,
+        entries,
+    })
+}
+
+/// Binds to the configured LDAP (or LDAPS) server and runs a sample search.
+#[instrument(skip_all, level = "info", err)]
+pub async fn test_ldap(config: &Configuration, opts: &TestLdapOpts) -> Result<LdapTestReport> {
+    let base_dn = &config.ldap_base_dn;
+    let bind_dn = if opts.user.contains('=') {
+        opts.user.clone()
+    } else {
+        format!("uid={},ou=people,{}", opts.user, base_dn)
+    };
+    let search_base = opts
+        .search_base
+        .clone()
+        .unwrap_or_else(|| format!("ou=people,{}", base_dn));
+    let start = Instant::now();
+    if opts.ldaps {
+        let tls_connector = get_tls_connector(&config.ldaps_options)
+            .context("while preparing the tls connection")?;
+        let url = format!("{}:{}", opts.host, config.ldaps_options.port);
+        let stream = tls_connector
+            .connect(
+                rustls::ServerName::try_from(opts.host.as_str())
+                    .context("while parsing the server name")?,
+                TcpStream::connect(&url)
+                    .await
+                    .context("while connecting TCP")?,
+            )
+            .await
+            .context("while connecting TLS")?;
+        info!("Connected to {} in {:?}", url, start.elapsed());
+        test_ldap_endpoint(stream, &bind_dn, opts.password.as_str(), &search_base).await
+    } else {
+        let url = format!("{}:{}", opts.host, config.ldap_port);
+        let stream = TcpStream::connect(&url)
+            .await
+            .context("while connecting TCP")?;
+        info!("Connected to {} in {:?}", url, start.elapsed());
+        test_ldap_endpoint(stream, &bind_dn, opts.password.as_str(), &search_base).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::sql_backend_handler::{
+            tests::{get_default_config, get_initialized_db, insert_user},
+            SqlBackendHandler,
+        },
+        infra::{
+            access_control::AccessControlledBackendHandler,
+            ldap_connections::LdapConnectionRegistry,
+            ldap_server::{handle_ldap_stream, LdapSessionSettings},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_ldap_against_server() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let (client, server) = tokio::io::duplex(4096);
+        let (server, report) = tokio::join!(
+            handle_ldap_stream(
+                server,
+                LdapSessionSettings::new(
+                    AccessControlledBackendHandler::new(handler),
+                    "dc=example,dc=com".to_string()
+                ),
+                LdapConnectionRegistry::default().register(None).unwrap(),
+            ),
+            test_ldap_endpoint(
+                client,
+                "uid=bob,ou=people,dc=example,dc=com",
+                "bob00000",
+                "ou=people,dc=example,dc=com",
+            )
+        );
+        server.unwrap();
+        assert_eq!(
+            report.unwrap().entries,
+            vec!["uid=bob,ou=people,dc=example,dc=com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ldap_wrong_password() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let (client, server) = tokio::io::duplex(4096);
+        let (_, report) = tokio::join!(
+            handle_ldap_stream(
+                server,
+                LdapSessionSettings::new(
+                    AccessControlledBackendHandler::new(handler),
+                    "dc=example,dc=com".to_string()
+                ),
+                LdapConnectionRegistry::default().register(None).unwrap(),
+            ),
+            test_ldap_endpoint(
+                client,
+                "uid=bob,ou=people,dc=example,dc=com",
+                "wrong",
+                "ou=people,dc=example,dc=com",
+            )
+        );
+        report.unwrap_err();
+    }
+}
@@ -0,0 +1,107 @@
+//! The page of the web UI. The configured login banner is added to it in a `<meta>` tag, that the
+//! login form displays.
+
+use actix_files::NamedFile;
+use actix_web::{http::header::ContentType, web, Either, HttpResponse};
+use std::path::PathBuf;
+
+pub const LOGIN_BANNER_META_NAME: &str = "login-banner";
+
+#[derive(Clone, Debug)]
+pub struct IndexPage {
+    pub path: PathBuf,
+    pub login_banner: Option<String>,
+}
+
+fn escape_html_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Adds the banner at the end of the `<head>`, or at the start of the page if there is none.
+fn add_login_banner(page: &str, banner: &str) -> String {
+    let meta = format!(
+        r#"<meta name="{}" content="{}" />"#,
+        LOGIN_BANNER_META_NAME,
+        escape_html_attribute(banner)
+    );
+    match page.find("</head>") {
+        Some(position) => format!("{}{}\n{}", &page[..position], meta, &page[position..]),
+        None => format!("{}\n{}", meta, page),
+    }
+}
+
+pub async fn index_handler(
+    page: web::Data<IndexPage>,
+) -> actix_web::Result<Either<NamedFile, HttpResponse>> {
+    Ok(match &page.login_banner {
+        None => Either::Left(NamedFile::open_async(&page.path).await?),
+        Some(banner) => {
+            let content = tokio::fs::read_to_string(&page.path).await?;
+            Either::Right(
+                HttpResponse::Ok()
+                    .content_type(ContentType::html())
+                    .body(add_login_banner(&content, banner)),
+            )
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    async fn get_page(login_banner: Option<&str>) -> String {
+        let path = std::env::temp_dir().join(format!("lldap_index_{}.html", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "<html>\n<head>\n<title>LLDAP</title>\n</head>\n<body></body>\n</html>\n",
+        )
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(IndexPage {
+                    path: path.clone(),
+                    login_banner: login_banner.map(str::to_owned),
+                }))
+                .default_service(web::get().to(index_handler)),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/login").to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        std::fs::remove_file(&path).unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_login_banner() {
+        assert!(!get_page(None).await.contains(LOGIN_BANNER_META_NAME));
+        let page = get_page(Some(
+            "Authorized use only.\nActivity is \"monitored\" & <logged>.",
+        ))
+        .await;
+        assert!(page.contains(
+            "<meta name=\"login-banner\" content=\"Authorized use only.\nActivity is \
+             &quot;monitored&quot; &amp; &lt;logged&gt;.\" />\n</head>"
+        ));
+    }
+
+    #[test]
+    fn test_add_login_banner_without_head() {
+        assert_eq!(
+            add_login_banner("<body></body>", "Hi"),
+            "<meta name=\"login-banner\" content=\"Hi\" />\n<body></body>"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! The keys used to sign and verify the JWTs, shared by all the workers. They start from the
+//! configured secrets, and can be rotated at runtime: the rotated secrets are stored in the
+//! database, so that they survive a restart and are picked up by the other instances.
+
+use crate::{
+    domain::error::{DomainError, Result},
+    infra::tcp_backend_handler::{JwtSecret, TcpBackendHandler},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::Hmac;
+use secstr::SecUtf8;
+use sha2::Sha512;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+/// The generated secrets are as long as the refresh tokens.
+const GENERATED_SECRET_LENGTH: usize = 64;
+/// Shorter secrets are rejected at runtime.
+pub const MIN_SECRET_LENGTH: usize = 16;
+/// Longer secrets don't fit in the database.
+pub const MAX_SECRET_LENGTH: usize = 255;
+
+fn make_key(secret: &SecUtf8) -> Hmac<Sha512> {
+    hmac::Mac::new_from_slice(secret.unsecure().as_bytes()).unwrap()
+}
+
+pub fn generate_secret() -> SecUtf8 {
+    use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+    SecUtf8::from(
+        OsRng
+            .sample_iter(&Alphanumeric)
+            .take(GENERATED_SECRET_LENGTH)
+            .map(char::from)
+            .collect::<String>(),
+    )
+}
+
+struct Key {
+    secret: SecUtf8,
+    key: Hmac<Sha512>,
+    /// The end of the grace period, if the key is only used for verification.
+    expiry_date: Option<DateTime<Utc>>,
+}
+
+impl Key {
+    fn new(secret: SecUtf8, expiry_date: Option<DateTime<Utc>>) -> Self {
+        Self {
+            key: make_key(&secret),
+            secret,
+            expiry_date,
+        }
+    }
+}
+
+struct KeySet {
+    signing: Key,
+    verification: Vec<Key>,
+}
+
+#[derive(Clone)]
+pub struct JwtKeys {
+    keys: Arc<RwLock<KeySet>>,
+    configured_secret: SecUtf8,
+    configured_verification_secrets: Vec<SecUtf8>,
+}
+
+impl JwtKeys {
+    pub fn new(secret: &SecUtf8, additional_verification_secrets: &[SecUtf8]) -> Self {
+        JwtKeys {
+            keys: Arc::new(RwLock::new(KeySet {
+                signing: Key::new(secret.clone(), None),
+                verification: additional_verification_secrets
+                    .iter()
+                    .map(|secret| Key::new(secret.clone(), None))
+                    .collect(),
+            })),
+            configured_secret: secret.clone(),
+            configured_verification_secrets: additional_verification_secrets.to_vec(),
+        }
+    }
+
+    pub fn signing_key(&self) -> Hmac<Sha512> {
+        self.keys.read().unwrap().signing.key.clone()
+    }
+
+    /// The signing key first, then the previous ones still in their grace period.
+    pub fn verification_keys(&self, now: DateTime<Utc>) -> Vec<Hmac<Sha512>> {
+        let keys = self.keys.read().unwrap();
+        std::iter::once(&keys.signing)
+            .chain(
+                keys.verification
+                    .iter()
+                    .filter(|k| k.expiry_date.map(|date| date > now).unwrap_or(true)),
+            )
+            .map(|k| k.key.clone())
+            .collect()
+    }
+
+    /// A stored signing secret replaces the configured one, and the stored previous secrets are
+    /// kept for verification until they expire. The configured additional secrets always are.
+    /// Returns whether a stored secret overrides the configured one for the first time.
+    fn set_stored_secrets(&self, stored: Vec<JwtSecret>) -> bool {
+        let (signing, previous): (Vec<_>, Vec<_>) =
+            stored.into_iter().partition(|s| s.expiry_date.is_none());
+        let signing = signing
+            .into_iter()
+            .next()
+            .map(|s| s.secret)
+            .unwrap_or_else(|| self.configured_secret.clone());
+        let verification = previous
+            .into_iter()
+            .map(|s| {
+                Key::new(
+                    s.secret,
+                    s.expiry_date.map(|date| Utc.from_utc_datetime(&date)),
+                )
+            })
+            .chain(
+                self.configured_verification_secrets
+                    .iter()
+                    .map(|secret| Key::new(secret.clone(), None)),
+            )
+            .collect();
+        let mut keys = self.keys.write().unwrap();
+        let newly_overridden =
+            keys.signing.secret == self.configured_secret && signing != self.configured_secret;
+        *keys = KeySet {
+            signing: Key::new(signing, None),
+            verification,
+        };
+        newly_overridden
+    }
+
+    /// Loads the secrets stored in the database, including the rotations of the other instances.
+    pub async fn reload<Backend: TcpBackendHandler>(
+        &self,
+        backend_handler: &Backend,
+    ) -> Result<()> {
+        let stored = backend_handler
+            .get_jwt_secrets(Utc::now().naive_utc())
+            .await?;
+        if self.set_stored_secrets(stored) {
+            warn!(
+                "Using the JWT secret rotated at runtime: the configured jwt_secret is ignored, \
+                even if it was changed since. Rotate the secret again with the rotateJwtSecret \
+                mutation to change it"
+            );
+        }
+        Ok(())
+    }
+
+    /// Signs the new tokens with `new_secret` (or a generated one), and keeps accepting the tokens
+    /// signed with the previous secret until the end of the grace period, which is returned.
+    pub async fn rotate<Backend: TcpBackendHandler>(
+        &self,
+        backend_handler: &Backend,
+        new_secret: Option<SecUtf8>,
+        grace_period: chrono::Duration,
+    ) -> Result<DateTime<Utc>> {
+        let new_secret = new_secret.unwrap_or_else(generate_secret);
+        let previous_secret = self.keys.read().unwrap().signing.secret.clone();
+        if previous_secret == new_secret {
+            return Err(DomainError::Conflict(
+                "The new JWT secret is the current one".to_owned(),
+            ));
+        }
+        let expiry_date = Utc::now() + grace_period;
+        backend_handler
+            .rotate_jwt_secret(&previous_secret, &new_secret, expiry_date.naive_utc())
+            .await?;
+        self.reload(backend_handler).await?;
+        Ok(expiry_date)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::sql_backend_handler::{tests::*, SqlBackendHandler},
+        infra::jwt_sql_tables,
+    };
+    use jwt::{SignWithKey, VerifyWithKey};
+    use std::collections::BTreeMap;
+
+    fn sign(key: &Hmac<Sha512>) -> String {
+        BTreeMap::from([("sub", "bob")]).sign_with_key(key).unwrap()
+    }
+
+    fn verifies(keys: &JwtKeys, token: &str, now: DateTime<Utc>) -> bool {
+        keys.verification_keys(now).iter().any(|key| {
+            VerifyWithKey::<BTreeMap<String, String>>::verify_with_key(token, key).is_ok()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_rotate_jwt_secret() {
+        let sql_pool = get_initialized_db().await;
+        jwt_sql_tables::init_table(&sql_pool).await.unwrap();
+        let handler = SqlBackendHandler::new(get_default_config(), sql_pool);
+        let configured_secret = SecUtf8::from("configured secret");
+        let keys = JwtKeys::new(&configured_secret, &[]);
+        keys.reload(&handler).await.unwrap();
+        let old_token = sign(&keys.signing_key());
+
+        let grace_period = chrono::Duration::hours(1);
+        let expiry_date = keys.rotate(&handler, None, grace_period).await.unwrap();
+        let now = Utc::now();
+        let new_token = sign(&keys.signing_key());
+        assert_ne!(new_token, old_token);
+        assert!(verifies(&keys, &new_token, now));
+        // The old tokens are accepted during the grace period only.
+        assert!(verifies(&keys, &old_token, now));
+        assert!(!verifies(&keys, &old_token, expiry_date));
+
+        // After a restart, or on another instance, the rotated secret is used.
+        let restarted_keys = JwtKeys::new(&configured_secret, &[]);
+        restarted_keys.reload(&handler).await.unwrap();
+        assert_eq!(sign(&restarted_keys.signing_key()), new_token);
+        // Even if the configured secret changed, with a warning.
+        let reconfigured_keys = JwtKeys::new(&SecUtf8::from("changed secret"), &[]);
+        assert!(reconfigured_keys.set_stored_secrets(
+            handler
+                .get_jwt_secrets(Utc::now().naive_utc())
+                .await
+                .unwrap()
+        ));
+        assert_eq!(sign(&reconfigured_keys.signing_key()), new_token);
+        assert!(verifies(&restarted_keys, &old_token, now));
+
+        // Rotating again expires the previous secret too.
+        let newer_secret = SecUtf8::from("a newer secret, given by the admin");
+        keys.rotate(&handler, Some(newer_secret.clone()), grace_period)
+            .await
+            .unwrap();
+        assert_eq!(sign(&keys.signing_key()), sign(&make_key(&newer_secret)));
+        assert!(verifies(&keys, &new_token, now));
+        assert!(!verifies(&keys, &new_token, now + grace_period * 2));
+        keys.rotate(&handler, Some(newer_secret), grace_period)
+            .await
+            .unwrap_err();
+    }
+
+    #[test]
+    fn test_configured_verification_secrets() {
+        let old_keys = JwtKeys::new(&SecUtf8::from("old secret"), &[]);
+        let old_token = sign(&old_keys.signing_key());
+        let keys = JwtKeys::new(&SecUtf8::from("new secret"), &[SecUtf8::from("old secret")]);
+        assert!(verifies(
+            &keys,
+            &old_token,
+            Utc::now() + chrono::Duration::days(365)
+        ));
+        assert!(!verifies(
+            &JwtKeys::new(&SecUtf8::from("new secret"), &[]),
+            &old_token,
+            Utc::now()
+        ));
+        assert_eq!(generate_secret().unsecure().len(), GENERATED_SECRET_LENGTH);
+    }
+}
@@ -33,6 +33,26 @@ pub enum PasswordResetTokens {
     ExpiryDate,
 }
 
+/// Contains the password reset emails sent recently, to rate-limit them across all the instances.
+#[derive(Iden)]
+pub enum PasswordResetRequests {
+    Table,
+    RequestId,
+    UserId,
+    Address,
+    RequestDate,
+}
+
+/// Contains the JWT secrets set at runtime, with the previous ones until the end of their grace
+/// period.
+#[derive(Iden)]
+pub enum JwtSecrets {
+    Table,
+    Secret,
+    ExpiryDate,
+    CreationDate,
+}
+
 /// This needs to be initialized after the domain tables are.
 pub async fn init_table(pool: &DbConnection) -> std::result::Result<(), sea_orm::DbErr> {
     let builder = pool.get_database_backend();
@@ -142,5 +162,65 @@ pub async fn init_table(pool: &DbConnection) -> std::result::Result<(), sea_orm:
     )
     .await?;
 
+    pool.execute(
+        builder.build(
+            Table::create()
+                .table(PasswordResetRequests::Table)
+                .if_not_exists()
+                .col(
+                    ColumnDef::new(PasswordResetRequests::RequestId)
+                        .integer()
+                        .auto_increment()
+                        .not_null()
+                        .primary_key(),
+                )
+                .col(
+                    ColumnDef::new(PasswordResetRequests::UserId)
+                        .string_len(255)
+                        .not_null(),
+                )
+                .col(
+                    ColumnDef::new(PasswordResetRequests::Address)
+                        .string_len(255)
+                        .not_null(),
+                )
+                .col(
+                    ColumnDef::new(PasswordResetRequests::RequestDate)
+                        .date_time()
+                        .not_null(),
+                )
+                .foreign_key(
+                    ForeignKey::create()
+                        .name("PasswordResetRequestsUserForeignKey")
+                        .from(PasswordResetRequests::Table, PasswordResetRequests::UserId)
+                        .to(Users::Table, Users::UserId)
+                        .on_delete(ForeignKeyAction::Cascade)
+                        .on_update(ForeignKeyAction::Cascade),
+                ),
+        ),
+    )
+    .await?;
+
+    pool.execute(
+        builder.build(
+            Table::create()
+                .table(JwtSecrets::Table)
+                .if_not_exists()
+                .col(
+                    ColumnDef::new(JwtSecrets::Secret)
+                        .string_len(255)
+                        .not_null()
+                        .primary_key(),
+                )
+                .col(ColumnDef::new(JwtSecrets::ExpiryDate).date_time())
+                .col(
+                    ColumnDef::new(JwtSecrets::CreationDate)
+                        .date_time()
+                        .not_null(),
+                ),
+        ),
+    )
+    .await?;
+
     Ok(())
 }
@@ -0,0 +1,977 @@
+use bytes::{Buf, BytesMut};
+use ldap3_proto::{
+    proto::{LdapFilter, LdapMsg, LdapOp, LdapSearchResultEntry},
+    LdapCodec,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// How many bytes of an invalid message are logged.
+const SNIPPET_LENGTH: usize = 32;
+
+/// The controls unknown to `LdapCodec`: the assertion (RFC 4528), and the pre-read and post-read
+/// (RFC 4527).
+pub const ASSERTION_CONTROL_OID: &str = "1.3.6.1.1.12";
+pub const PRE_READ_CONTROL_OID: &str = "1.3.6.1.1.13.1";
+pub const POST_READ_CONTROL_OID: &str = "1.3.6.1.1.13.2";
+/// The content synchronization (RFC 4533), the only control of `LdapCodec` whose criticality is
+/// kept, so that it can be checked after the decoding.
+pub const SYNC_REQUEST_CONTROL_OID: &str = "1.3.6.1.4.1.4203.1.9.1.1";
+
+/// The BER tags used to find the controls of a message.
+const SEQUENCE_TAG: u8 = 0x30;
+const OCTET_STRING_TAG: u8 = 0x04;
+const BOOLEAN_TAG: u8 = 0x01;
+const CONTROLS_TAG: u8 = 0xa0;
+const ENUMERATED_TAG: u8 = 0x0a;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LdapDecodeError {
+    #[error("Message of {size} bytes exceeds the maximum of {max_size} bytes")]
+    TooLarge { size: usize, max_size: usize },
+    #[error("Malformed message: {0}")]
+    Malformed(String),
+    #[error("Truncated message: {0}")]
+    Truncated(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A decoded message, or the ID and operation tag of a well-formed message whose operation is
+/// not supported, so that the client can get an error response.
+#[derive(Debug, PartialEq)]
+pub enum DecodedMessage {
+    Supported(LdapMsg),
+    /// A message with controls that can't be in `LdapMsg::ctrl`.
+    WithControls {
+        msg: LdapMsg,
+        controls: RequestControls,
+    },
+    Unsupported {
+        msgid: i32,
+        op_tag: u8,
+    },
+}
+
+/// The operation must only be performed if its target entry matches the filter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion {
+    pub criticality: bool,
+    pub filter: LdapFilter,
+}
+
+/// The attributes of the target entry to return with the response. Empty means all the user
+/// attributes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadEntry {
+    pub criticality: bool,
+    pub attributes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestControls {
+    pub assertion: Option<Assertion>,
+    /// The entry before the modification.
+    pub pre_read: Option<ReadEntry>,
+    /// The entry after the modification.
+    pub post_read: Option<ReadEntry>,
+    /// The OID of a critical control that isn't implemented, e.g. the manageDsaIT or the paged
+    /// results: the operation must fail (RFC 4511, section 4.1.11).
+    pub unavailable_critical: Option<String>,
+}
+
+impl RequestControls {
+    pub fn is_critical(&self) -> bool {
+        self.unavailable_critical.is_some()
+            || [
+                self.assertion.as_ref().map(|a| a.criticality),
+                self.pre_read.as_ref().map(|r| r.criticality),
+                self.post_read.as_ref().map(|r| r.criticality),
+            ]
+            .contains(&Some(true))
+    }
+}
+
+/// A response control that `LdapMsg::ctrl` can't hold.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResponseControl {
+    PreRead(LdapSearchResultEntry),
+    PostRead(LdapSearchResultEntry),
+}
+
+/// The `assertionFailed` result code (RFC 4528), that `LdapResultCode` doesn't have.
+pub const ASSERTION_FAILED_RESULT_CODE: u8 = 122;
+
+/// What a response needs on top of what `LdapCodec` can encode.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResponseExtensions {
+    pub controls: Vec<ResponseControl>,
+    /// Replaces the result code of the response, for the codes that `LdapResultCode` lacks.
+    pub result_code: Option<u8>,
+}
+
+impl ResponseExtensions {
+    pub fn is_empty(&self) -> bool {
+        self.controls.is_empty() && self.result_code.is_none()
+    }
+}
+
+/// A response with controls or a result code that `LdapCodec` can't encode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LdapResponse {
+    /// The message, without controls.
+    pub msg: LdapMsg,
+    pub controls: Vec<ResponseControl>,
+    pub result_code: Option<u8>,
+}
+
+fn hex_snippet(bytes: &[u8]) -> String {
+    let mut snippet = bytes
+        .iter()
+        .take(SNIPPET_LENGTH)
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if bytes.len() > SNIPPET_LENGTH {
+        snippet.push_str(" ...");
+    }
+    snippet
+}
+
+/// Reads the BER header of the message to get its total size, or None if the header is not
+/// complete yet.
+fn get_message_size(buf: &[u8]) -> Result<Option<usize>, LdapDecodeError> {
+    let malformed = || LdapDecodeError::Malformed(hex_snippet(buf));
+    // LDAP messages are always a SEQUENCE.
+    match buf.first() {
+        None => return Ok(None),
+        Some(0x30) => (),
+        Some(_) => return Err(malformed()),
+    }
+    let length_byte = match buf.get(1) {
+        None => return Ok(None),
+        Some(b) => *b,
+    };
+    if length_byte < 0x80 {
+        return Ok(Some(2 + length_byte as usize));
+    }
+    // Indefinite lengths (0x80) are not allowed in LDAP.
+    let length_bytes = (length_byte & 0x7f) as usize;
+    if length_bytes == 0 || length_bytes > std::mem::size_of::<usize>() {
+        return Err(malformed());
+    }
+    let length = match buf.get(2..2 + length_bytes) {
+        None => return Ok(None),
+        Some(length) => length
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize),
+    };
+    Ok(Some(length.saturating_add(2 + length_bytes)))
+}
+
+/// Reads the message ID and the operation tag of a complete message.
+fn get_message_id_and_op(message: &[u8]) -> Option<(i32, u8)> {
+    let length_byte = *message.get(1)?;
+    let header_length = 2 + if length_byte & 0x80 != 0 {
+        (length_byte & 0x7f) as usize
+    } else {
+        0
+    };
+    let rest = message.get(header_length..)?;
+    // The message ID is an INTEGER.
+    if *rest.first()? != 0x02 {
+        return None;
+    }
+    let id_length = *rest.get(1)? as usize;
+    if id_length == 0 || id_length > 4 {
+        return None;
+    }
+    let id_bytes = rest.get(2..2 + id_length)?;
+    let sign = if id_bytes[0] & 0x80 != 0 { -1 } else { 0 };
+    let msgid = id_bytes
+        .iter()
+        .fold(sign, |acc: i32, b| (acc << 8) | *b as i32);
+    let op_tag = *rest.get(2 + id_length)?;
+    // The operations are all in the APPLICATION class.
+    if op_tag & 0xc0 != 0x40 {
+        return None;
+    }
+    Some((msgid, op_tag))
+}
+
+/// Splits a BER element into its tag, its value and the rest of the buffer. Only the tags on one
+/// byte are supported, which covers LDAP.
+fn split_element(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buf.first()?;
+    let length_byte = *buf.get(1)?;
+    let (length, header_length) = if length_byte < 0x80 {
+        (length_byte as usize, 2)
+    } else {
+        let length_bytes = (length_byte & 0x7f) as usize;
+        if length_bytes == 0 || length_bytes > std::mem::size_of::<usize>() {
+            return None;
+        }
+        let length = buf
+            .get(2..2 + length_bytes)?
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (length, 2 + length_bytes)
+    };
+    let end = header_length.checked_add(length)?;
+    Some((tag, buf.get(header_length..end)?, buf.get(end..)?))
+}
+
+fn split_elements(mut buf: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut elements = Vec::new();
+    while !buf.is_empty() {
+        let (tag, value, rest) = split_element(buf)?;
+        elements.push((tag, value));
+        buf = rest;
+    }
+    Some(elements)
+}
+
+fn encode_element(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    if value.len() < 0x80 {
+        element.push(value.len() as u8);
+    } else {
+        let length = value.len().to_be_bytes();
+        let length = &length[length.iter().take_while(|b| **b == 0).count()..];
+        element.push(0x80 | length.len() as u8);
+        element.extend_from_slice(length);
+    }
+    element.extend_from_slice(value);
+    element
+}
+
+/// Parses a BER filter by wrapping it in a search request, the only message `LdapCodec` decodes
+/// filters in.
+fn decode_filter(filter: &[u8]) -> Option<LdapFilter> {
+    let search_request = [
+        // Base DN, scope, aliases, size and time limits, types only.
+        &[0x04, 0x00, 0x0a, 0x01, 0x00, 0x0a, 0x01, 0x00][..],
+        &[0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x01, 0x01, 0x00],
+        filter,
+        // No attributes.
+        &[SEQUENCE_TAG, 0x00],
+    ]
+    .concat();
+    let message = [
+        &[0x02, 0x01, 0x01][..],
+        &encode_element(0x63, &search_request),
+    ]
+    .concat();
+    match LdapCodec.decode(&mut BytesMut::from(
+        &encode_element(SEQUENCE_TAG, &message)[..],
+    )) {
+        Ok(Some(LdapMsg {
+            op: LdapOp::SearchRequest(request),
+            ..
+        })) => Some(request.filter),
+        _ => None,
+    }
+}
+
+/// Splits a control into its OID, its criticality and its value.
+fn split_control(control: &[u8]) -> Option<(&[u8], bool, Option<&[u8]>)> {
+    let is_true = |value: &[u8]| value.iter().any(|b| *b != 0);
+    Some(match split_elements(control)?.as_slice() {
+        [(OCTET_STRING_TAG, oid)] => (*oid, false, None),
+        [(OCTET_STRING_TAG, oid), (BOOLEAN_TAG, criticality)] => (*oid, is_true(criticality), None),
+        [(OCTET_STRING_TAG, oid), (OCTET_STRING_TAG, value)] => (*oid, false, Some(*value)),
+        [(OCTET_STRING_TAG, oid), (BOOLEAN_TAG, criticality), (OCTET_STRING_TAG, value)] => {
+            (*oid, is_true(criticality), Some(*value))
+        }
+        _ => return None,
+    })
+}
+
+/// Parses the AttributeSelection of a pre-read or post-read control.
+fn decode_read_entry(criticality: bool, value: &[u8]) -> Option<ReadEntry> {
+    let attributes = match split_element(value)? {
+        (SEQUENCE_TAG, attributes, _) => split_elements(attributes)?
+            .into_iter()
+            .map(|(tag, attribute)| match tag {
+                OCTET_STRING_TAG => String::from_utf8(attribute.to_vec()).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+    Some(ReadEntry {
+        criticality,
+        attributes,
+    })
+}
+
+/// Removes the controls unknown to `LdapCodec` from the message, and returns the rest of the
+/// message with the parsed controls. Returns None if there are none, or if they are invalid.
+/// `LdapCodec` drops the unknown controls, and the criticality of most of the others, so the
+/// critical ones that aren't implemented are recorded here.
+fn extract_controls(message: &[u8]) -> Option<(Vec<u8>, RequestControls)> {
+    let body = match split_element(message)? {
+        (SEQUENCE_TAG, body, _) => body,
+        _ => return None,
+    };
+    let elements = split_elements(body)?;
+    let controls = match elements.last()? {
+        (CONTROLS_TAG, controls) => *controls,
+        _ => return None,
+    };
+    let mut request_controls = RequestControls::default();
+    let mut other_controls = Vec::new();
+    for (tag, control) in split_elements(controls)? {
+        let (oid, criticality, value) = split_control(control)?;
+        match std::str::from_utf8(oid).ok() {
+            Some(ASSERTION_CONTROL_OID) => {
+                request_controls.assertion = Some(Assertion {
+                    criticality,
+                    filter: decode_filter(value?)?,
+                });
+            }
+            Some(PRE_READ_CONTROL_OID) => {
+                request_controls.pre_read = Some(decode_read_entry(criticality, value?)?);
+            }
+            Some(POST_READ_CONTROL_OID) => {
+                request_controls.post_read = Some(decode_read_entry(criticality, value?)?);
+            }
+            Some(SYNC_REQUEST_CONTROL_OID) => other_controls.push(encode_element(tag, control)),
+            oid if criticality => {
+                if request_controls.unavailable_critical.is_none() {
+                    request_controls.unavailable_critical =
+                        Some(oid.unwrap_or("<invalid OID>").to_owned());
+                }
+            }
+            _ => other_controls.push(encode_element(tag, control)),
+        }
+    }
+    if request_controls == RequestControls::default() {
+        return None;
+    }
+    let mut body = elements[..elements.len() - 1]
+        .iter()
+        .flat_map(|(tag, value)| encode_element(*tag, value))
+        .collect::<Vec<_>>();
+    if !other_controls.is_empty() {
+        body.extend(encode_element(CONTROLS_TAG, &other_controls.concat()));
+    }
+    Some((encode_element(SEQUENCE_TAG, &body), request_controls))
+}
+
+fn encode_message(msg: LdapMsg) -> std::io::Result<Vec<u8>> {
+    let mut buf = BytesMut::new();
+    LdapCodec.encode(msg, &mut buf)?;
+    Ok(buf.to_vec())
+}
+
+/// Replaces the result code of an encoded response, the first element of its operation.
+fn replace_result_code(message: &[u8], code: u8) -> std::io::Result<Vec<u8>> {
+    let elements = match split_element(message) {
+        Some((SEQUENCE_TAG, body, _)) => split_elements(body),
+        _ => None,
+    }
+    .ok_or_else(invalid_encoding)?;
+    // The message ID, the operation, then the controls.
+    let (op_tag, op_body) = *elements.get(1).ok_or_else(invalid_encoding)?;
+    let code = [code];
+    let mut op_elements = split_elements(op_body).ok_or_else(invalid_encoding)?;
+    match op_elements.first_mut() {
+        Some(result_code) if result_code.0 == ENUMERATED_TAG => result_code.1 = &code,
+        _ => return Err(invalid_encoding()),
+    }
+    let encode_all = |elements: &[(u8, &[u8])]| {
+        elements
+            .iter()
+            .map(|(tag, value)| encode_element(*tag, value))
+            .collect::<Vec<_>>()
+            .concat()
+    };
+    let encoded_op = encode_all(&op_elements);
+    let op = (op_tag, &encoded_op[..]);
+    Ok(encode_element(
+        SEQUENCE_TAG,
+        &encode_all(
+            &std::iter::once(elements[0])
+                .chain(std::iter::once(op))
+                .chain(elements[2..].iter().copied())
+                .collect::<Vec<_>>(),
+        ),
+    ))
+}
+
+fn invalid_encoding() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Unexpected encoding of an LDAP message",
+    )
+}
+
+impl ResponseControl {
+    fn encode(self) -> std::io::Result<Vec<u8>> {
+        let (oid, entry) = match self {
+            Self::PreRead(entry) => (PRE_READ_CONTROL_OID, entry),
+            Self::PostRead(entry) => (POST_READ_CONTROL_OID, entry),
+        };
+        // The value is the encoded SearchResultEntry, i.e. the operation of a message.
+        let message = encode_message(LdapMsg {
+            msgid: 0,
+            op: LdapOp::SearchResultEntry(entry),
+            ctrl: vec![],
+        })?;
+        let entry = match split_element(&message).and_then(|(_, body, _)| split_elements(body)) {
+            Some(elements) if elements.len() == 2 => encode_element(elements[1].0, elements[1].1),
+            _ => return Err(invalid_encoding()),
+        };
+        Ok(encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(OCTET_STRING_TAG, oid.as_bytes()),
+                encode_element(OCTET_STRING_TAG, &entry),
+            ]
+            .concat(),
+        ))
+    }
+}
+
+/// Encodes the responses like `LdapCodec`, and adds the controls it doesn't support.
+pub struct LdapResponseCodec;
+
+impl Encoder<LdapMsg> for LdapResponseCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, msg: LdapMsg, buf: &mut BytesMut) -> std::io::Result<()> {
+        LdapCodec.encode(msg, buf)
+    }
+}
+
+impl Encoder<LdapResponse> for LdapResponseCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, response: LdapResponse, buf: &mut BytesMut) -> std::io::Result<()> {
+        let mut message = encode_message(response.msg)?;
+        if let Some(code) = response.result_code {
+            message = replace_result_code(&message, code)?;
+        }
+        if response.controls.is_empty() {
+            buf.extend_from_slice(&message);
+            return Ok(());
+        }
+        let body = match split_element(&message) {
+            Some((SEQUENCE_TAG, body, _)) => body,
+            _ => return Err(invalid_encoding()),
+        };
+        let controls = response
+            .controls
+            .into_iter()
+            .map(ResponseControl::encode)
+            .collect::<std::io::Result<Vec<_>>>()?
+            .concat();
+        buf.extend_from_slice(&encode_element(
+            SEQUENCE_TAG,
+            &[body, &encode_element(CONTROLS_TAG, &controls)].concat(),
+        ));
+        Ok(())
+    }
+}
+
+/// Wraps the `LdapCodec` to reject messages that are too large before buffering them, and to
+/// report what was wrong with the invalid ones.
+pub struct LimitedLdapCodec {
+    max_message_size: usize,
+}
+
+impl LimitedLdapCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self { max_message_size }
+    }
+}
+
+impl Decoder for LimitedLdapCodec {
+    type Item = DecodedMessage;
+    type Error = LdapDecodeError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<DecodedMessage>, LdapDecodeError> {
+        let size = match get_message_size(buf)? {
+            None => return Ok(None),
+            Some(size) => size,
+        };
+        if size > self.max_message_size {
+            return Err(LdapDecodeError::TooLarge {
+                size,
+                max_size: self.max_message_size,
+            });
+        }
+        if buf.len() < size {
+            buf.reserve(size - buf.len());
+            return Ok(None);
+        }
+        let remaining = buf.len() - size;
+        // Only the beginning of the message is needed to report errors.
+        let head = buf[..size.min(SNIPPET_LENGTH + 1)].to_vec();
+        let id_and_op = get_message_id_and_op(&buf[..size]);
+        if let Some((message, controls)) = extract_controls(&buf[..size]) {
+            buf.advance(size);
+            return match LdapCodec.decode(&mut BytesMut::from(&message[..])) {
+                Ok(Some(msg)) => Ok(Some(DecodedMessage::WithControls { msg, controls })),
+                _ => match id_and_op {
+                    Some((msgid, op_tag)) => {
+                        Ok(Some(DecodedMessage::Unsupported { msgid, op_tag }))
+                    }
+                    None => Err(LdapDecodeError::Malformed(hex_snippet(&head))),
+                },
+            };
+        }
+        match LdapCodec.decode(buf) {
+            Ok(Some(msg)) => Ok(Some(DecodedMessage::Supported(msg))),
+            // The whole message is there, so it should have been parsed.
+            Ok(None) | Err(_) => {
+                // Skip the message, whether the codec consumed it or not.
+                if buf.len() > remaining {
+                    buf.advance(buf.len() - remaining);
+                }
+                match id_and_op {
+                    Some((msgid, op_tag)) => {
+                        Ok(Some(DecodedMessage::Unsupported { msgid, op_tag }))
+                    }
+                    None => Err(LdapDecodeError::Malformed(hex_snippet(&head))),
+                }
+            }
+        }
+    }
+
+    fn decode_eof(
+        &mut self,
+        buf: &mut BytesMut,
+    ) -> Result<Option<DecodedMessage>, LdapDecodeError> {
+        match self.decode(buf)? {
+            Some(msg) => Ok(Some(msg)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(LdapDecodeError::Truncated(hex_snippet(buf))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_size() {
+        assert_eq!(get_message_size(&[]).unwrap(), None);
+        assert_eq!(get_message_size(&[0x30]).unwrap(), None);
+        assert_eq!(get_message_size(&[0x30, 0x05]).unwrap(), Some(7));
+        assert_eq!(get_message_size(&[0x30, 0x82, 0x01]).unwrap(), None);
+        assert_eq!(
+            get_message_size(&[0x30, 0x82, 0x01, 0x00]).unwrap(),
+            Some(260)
+        );
+        get_message_size(&[0x04, 0x05]).unwrap_err();
+        get_message_size(&[0x30, 0x80]).unwrap_err();
+    }
+
+    #[test]
+    fn test_unsupported_message() {
+        // A SASL bind, with the message ID 5.
+        let mut buf = BytesMut::from(
+            &[
+                0x30, 0x13, 0x02, 0x01, 0x05, 0x60, 0x0e, 0x02, 0x01, 0x03, 0x04, 0x00, 0xa3, 0x07,
+                0x04, 0x05, b'P', b'L', b'A', b'I', b'N', 0x30,
+            ][..],
+        );
+        let mut codec = LimitedLdapCodec::new(1024);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(DecodedMessage::Unsupported {
+                msgid: 5,
+                op_tag: 0x60
+            })
+        );
+        // The message is skipped, the next one is waiting for more data.
+        assert_eq!(&buf[..], &[0x30]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_message_id_and_op() {
+        assert_eq!(
+            get_message_id_and_op(&[0x30, 0x05, 0x02, 0x01, 0x07, 0x42, 0x00]),
+            Some((7, 0x42))
+        );
+        assert_eq!(
+            get_message_id_and_op(&[0x30, 0x81, 0x06, 0x02, 0x02, 0x01, 0x00, 0x77, 0x00]),
+            Some((256, 0x77))
+        );
+        // Not an operation.
+        assert_eq!(
+            get_message_id_and_op(&[0x30, 0x05, 0x02, 0x01, 0x07, 0x04, 0x00]),
+            None
+        );
+        assert_eq!(get_message_id_and_op(&[0x30, 0x02, 0x04, 0x00]), None);
+    }
+
+    #[test]
+    fn test_hex_snippet() {
+        assert_eq!(hex_snippet(&[0x30, 0x0c, 0xff]), "30 0c ff");
+        assert_eq!(
+            hex_snippet(&[0; 40]),
+            format!("{} ...", ["00"; SNIPPET_LENGTH].join(" "))
+        );
+    }
+
+    #[test]
+    fn test_assertion_control() {
+        use ldap3_proto::proto::{
+            LdapModify, LdapModifyRequest, LdapModifyType, LdapPartialAttribute,
+        };
+        // A modify request of "uid=bob" with the assertion "(mail=bob@example.com)", critical.
+        let filter = encode_element(
+            0xa3,
+            &[
+                encode_element(OCTET_STRING_TAG, b"mail"),
+                encode_element(OCTET_STRING_TAG, b"bob@example.com"),
+            ]
+            .concat(),
+        );
+        let control = encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(OCTET_STRING_TAG, ASSERTION_CONTROL_OID.as_bytes()),
+                encode_element(BOOLEAN_TAG, &[0xff]),
+                encode_element(OCTET_STRING_TAG, &filter),
+            ]
+            .concat(),
+        );
+        let change = encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(0x0a, &[0x02]),
+                encode_element(
+                    SEQUENCE_TAG,
+                    &[
+                        encode_element(OCTET_STRING_TAG, b"userPassword"),
+                        encode_element(0x31, &encode_element(OCTET_STRING_TAG, b"password")),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let modify = encode_element(
+            0x66,
+            &[
+                encode_element(OCTET_STRING_TAG, b"uid=bob"),
+                encode_element(SEQUENCE_TAG, &change),
+            ]
+            .concat(),
+        );
+        let message = encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(0x02, &[0x03]),
+                modify,
+                encode_element(CONTROLS_TAG, &control),
+            ]
+            .concat(),
+        );
+        let mut buf = BytesMut::from(&message[..]);
+        assert_eq!(
+            LimitedLdapCodec::new(1024).decode(&mut buf).unwrap(),
+            Some(DecodedMessage::WithControls {
+                msg: LdapMsg {
+                    msgid: 3,
+                    op: LdapOp::ModifyRequest(LdapModifyRequest {
+                        dn: "uid=bob".to_string(),
+                        changes: vec![LdapModify {
+                            operation: LdapModifyType::Replace,
+                            modification: LdapPartialAttribute {
+                                atype: "userPassword".to_string(),
+                                vals: vec![b"password".to_vec()],
+                            },
+                        }],
+                    }),
+                    ctrl: vec![],
+                },
+                controls: RequestControls {
+                    assertion: Some(Assertion {
+                        criticality: true,
+                        filter: LdapFilter::Equality(
+                            "mail".to_string(),
+                            "bob@example.com".to_string()
+                        ),
+                    }),
+                    ..Default::default()
+                },
+            })
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_replace_result_code() {
+        use ldap3_proto::proto::{LdapResultCode, LdapResultOp};
+        let msg = LdapMsg {
+            msgid: 3,
+            op: LdapOp::ModifyResponse(LdapResultOp {
+                code: LdapResultCode::Other,
+                matcheddn: "".to_string(),
+                message: "Assertion failed".to_string(),
+                referral: vec![],
+            }),
+            ctrl: vec![],
+        };
+        let mut buf = BytesMut::new();
+        LdapResponseCodec
+            .encode(
+                LdapResponse {
+                    msg,
+                    controls: vec![],
+                    result_code: Some(ASSERTION_FAILED_RESULT_CODE),
+                },
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(
+            buf.to_vec(),
+            encode_element(
+                SEQUENCE_TAG,
+                &[
+                    encode_element(0x02, &[0x03]),
+                    encode_element(
+                        0x67,
+                        &[
+                            encode_element(ENUMERATED_TAG, &[122]),
+                            encode_element(OCTET_STRING_TAG, b""),
+                            encode_element(OCTET_STRING_TAG, b"Assertion failed"),
+                        ]
+                        .concat(),
+                    ),
+                ]
+                .concat(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_entry_controls() {
+        use ldap3_proto::proto::{LdapPartialAttribute, LdapResultCode, LdapResultOp};
+        // A delete request of "uid=bob" with a non-critical pre-read of "mail".
+        let control = encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(OCTET_STRING_TAG, PRE_READ_CONTROL_OID.as_bytes()),
+                encode_element(
+                    OCTET_STRING_TAG,
+                    &encode_element(SEQUENCE_TAG, &encode_element(OCTET_STRING_TAG, b"mail")),
+                ),
+            ]
+            .concat(),
+        );
+        let message = encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(0x02, &[0x04]),
+                encode_element(0x4a, b"uid=bob"),
+                encode_element(CONTROLS_TAG, &control),
+            ]
+            .concat(),
+        );
+        let mut buf = BytesMut::from(&message[..]);
+        let controls = RequestControls {
+            pre_read: Some(ReadEntry {
+                criticality: false,
+                attributes: vec!["mail".to_string()],
+            }),
+            ..Default::default()
+        };
+        assert!(!controls.is_critical());
+        assert_eq!(
+            LimitedLdapCodec::new(1024).decode(&mut buf).unwrap(),
+            Some(DecodedMessage::WithControls {
+                msg: LdapMsg {
+                    msgid: 4,
+                    op: LdapOp::DelRequest("uid=bob".to_string()),
+                    ctrl: vec![],
+                },
+                controls,
+            })
+        );
+
+        // The response has the entry in its controls.
+        let msg = LdapMsg {
+            msgid: 4,
+            op: LdapOp::ModifyResponse(LdapResultOp {
+                code: LdapResultCode::Success,
+                matcheddn: "".to_string(),
+                message: "".to_string(),
+                referral: vec![],
+            }),
+            ctrl: vec![],
+        };
+        let entry = LdapSearchResultEntry {
+            dn: "uid=bob".to_string(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "mail".to_string(),
+                vals: vec![b"bob@example.com".to_vec()],
+            }],
+        };
+        let encoded_entry = encode_element(
+            0x64,
+            &[
+                encode_element(OCTET_STRING_TAG, b"uid=bob"),
+                encode_element(
+                    SEQUENCE_TAG,
+                    &encode_element(
+                        SEQUENCE_TAG,
+                        &[
+                            encode_element(OCTET_STRING_TAG, b"mail"),
+                            encode_element(
+                                0x31,
+                                &encode_element(OCTET_STRING_TAG, b"bob@example.com"),
+                            ),
+                        ]
+                        .concat(),
+                    ),
+                ),
+            ]
+            .concat(),
+        );
+        let without_controls = encode_message(msg.clone()).unwrap();
+        let mut buf = BytesMut::new();
+        LdapResponseCodec
+            .encode(
+                LdapResponse {
+                    msg,
+                    controls: vec![ResponseControl::PostRead(entry)],
+                    result_code: None,
+                },
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(
+            buf.to_vec(),
+            encode_element(
+                SEQUENCE_TAG,
+                &[
+                    split_element(&without_controls).unwrap().1,
+                    &encode_element(
+                        CONTROLS_TAG,
+                        &encode_element(
+                            SEQUENCE_TAG,
+                            &[
+                                encode_element(OCTET_STRING_TAG, POST_READ_CONTROL_OID.as_bytes()),
+                                encode_element(OCTET_STRING_TAG, &encoded_entry),
+                            ]
+                            .concat(),
+                        ),
+                    ),
+                ]
+                .concat(),
+            )
+        );
+    }
+
+    /// A search of the base "dc=example,dc=com" with the control.
+    fn make_search_with_control(oid: &str, criticality: bool, value: Option<&[u8]>) -> Vec<u8> {
+        let mut control = vec![encode_element(OCTET_STRING_TAG, oid.as_bytes())];
+        if criticality {
+            control.push(encode_element(BOOLEAN_TAG, &[0xff]));
+        }
+        if let Some(value) = value {
+            control.push(encode_element(OCTET_STRING_TAG, value));
+        }
+        let search = encode_element(
+            0x63,
+            &[
+                encode_element(OCTET_STRING_TAG, b"dc=example,dc=com"),
+                encode_element(0x0a, &[0x02]),
+                encode_element(0x0a, &[0x00]),
+                encode_element(0x02, &[0x00]),
+                encode_element(0x02, &[0x00]),
+                encode_element(BOOLEAN_TAG, &[0x00]),
+                encode_element(0x87, b"objectClass"),
+                encode_element(SEQUENCE_TAG, &[]),
+            ]
+            .concat(),
+        );
+        encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(0x02, &[0x02]),
+                search,
+                encode_element(
+                    CONTROLS_TAG,
+                    &encode_element(SEQUENCE_TAG, &control.concat()),
+                ),
+            ]
+            .concat(),
+        )
+    }
+
+    fn decode_controls(message: &[u8]) -> Option<RequestControls> {
+        match LimitedLdapCodec::new(1024)
+            .decode(&mut BytesMut::from(message))
+            .unwrap()
+        {
+            Some(DecodedMessage::WithControls { msg, controls }) => {
+                assert!(matches!(msg.op, LdapOp::SearchRequest(_)));
+                Some(controls)
+            }
+            Some(DecodedMessage::Supported(_)) => None,
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_critical_control() {
+        let controls = decode_controls(&make_search_with_control("1.2.3.4", true, None)).unwrap();
+        assert_eq!(controls.unavailable_critical, Some("1.2.3.4".to_string()));
+        assert!(controls.is_critical());
+        // The manageDsaIT is not implemented either.
+        assert_eq!(
+            decode_controls(&make_search_with_control(
+                "2.16.840.1.113730.3.4.2",
+                true,
+                None
+            ))
+            .unwrap()
+            .unavailable_critical,
+            Some("2.16.840.1.113730.3.4.2".to_string())
+        );
+        // A non-critical one is ignored.
+        assert_eq!(
+            decode_controls(&make_search_with_control("1.2.3.4", false, None)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_critical_paged_results() {
+        // A page size of 10, without a cookie.
+        let value = encode_element(
+            SEQUENCE_TAG,
+            &[
+                encode_element(0x02, &[0x0a]),
+                encode_element(OCTET_STRING_TAG, &[]),
+            ]
+            .concat(),
+        );
+        let controls = decode_controls(&make_search_with_control(
+            "1.2.840.113556.1.4.319",
+            true,
+            Some(&value),
+        ))
+        .unwrap();
+        assert_eq!(
+            controls.unavailable_critical,
+            Some("1.2.840.113556.1.4.319".to_string())
+        );
+    }
+
+    #[test]
+    fn test_encode_element() {
+        assert_eq!(encode_element(0x04, b"ab"), vec![0x04, 0x02, b'a', b'b']);
+        let long = encode_element(0x04, &[0; 300]);
+        assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2c]);
+        assert_eq!(split_element(&long), Some((0x04, &[0; 300][..], &[][..])));
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+
+/// A snapshot of an open LDAP connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LdapConnection {
+    pub id: u64,
+    pub bound_dn: Option<String>,
+    pub source_ip: Option<IpAddr>,
+    pub connected_at: DateTime<Utc>,
+    pub operation_count: u64,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ConnectionLimitError {
+    #[error("Refusing LDAP connection: too many open connections ({0})")]
+    TooManyConnections(usize),
+    #[error("Refusing LDAP connection from {ip}: too many open connections from it ({count})")]
+    TooManyConnectionsFromIp { ip: IpAddr, count: usize },
+}
+
+#[derive(Default)]
+struct RegistryState {
+    next_id: u64,
+    connections: HashMap<u64, LdapConnection>,
+}
+
+/// The LDAP connections currently open, shared between the LDAP server and the API.
+#[derive(Clone, Default)]
+pub struct LdapConnectionRegistry {
+    state: Arc<Mutex<RegistryState>>,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    shutdown: CancellationToken,
+}
+
+impl LdapConnectionRegistry {
+    pub fn new(max_connections: Option<usize>, max_connections_per_ip: Option<usize>) -> Self {
+        Self {
+            state: Default::default(),
+            max_connections,
+            max_connections_per_ip,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Adds a connection to the registry, unless it would exceed the limits. It is removed when
+    /// the returned handle is dropped.
+    pub fn register(
+        &self,
+        source_ip: Option<IpAddr>,
+    ) -> Result<RegisteredConnection, ConnectionLimitError> {
+        let mut state = self.state.lock().unwrap();
+        let count = state.connections.len();
+        if matches!(self.max_connections, Some(max) if count >= max) {
+            return Err(ConnectionLimitError::TooManyConnections(count));
+        }
+        if let (Some(ip), Some(max)) = (source_ip, self.max_connections_per_ip) {
+            let count = state
+                .connections
+                .values()
+                .filter(|c| c.source_ip == Some(ip))
+                .count();
+            if count >= max {
+                return Err(ConnectionLimitError::TooManyConnectionsFromIp { ip, count });
+            }
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.connections.insert(
+            id,
+            LdapConnection {
+                id,
+                bound_dn: None,
+                source_ip,
+                connected_at: Utc::now(),
+                operation_count: 0,
+            },
+        );
+        Ok(RegisteredConnection {
+            registry: self.clone(),
+            id,
+            source_ip,
+        })
+    }
+
+    /// The open connections, oldest first.
+    pub fn list(&self) -> Vec<LdapConnection> {
+        let mut connections = self
+            .state
+            .lock()
+            .unwrap()
+            .connections
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        connections.sort_by_key(|c| c.id);
+        connections
+    }
+
+    /// Asks all the connections to close, e.g. when the server shuts down.
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+pub struct RegisteredConnection {
+    registry: LdapConnectionRegistry,
+    id: u64,
+    source_ip: Option<IpAddr>,
+}
+
+impl RegisteredConnection {
+    pub fn source_ip(&self) -> Option<IpAddr> {
+        self.source_ip
+    }
+
+    /// Counts a handled operation, and updates the DN the connection is bound as.
+    pub fn record_operation(&self, bound_dn: Option<String>) {
+        if let Some(connection) = self
+            .registry
+            .state
+            .lock()
+            .unwrap()
+            .connections
+            .get_mut(&self.id)
+        {
+            connection.operation_count += 1;
+            connection.bound_dn = bound_dn;
+        }
+    }
+
+    /// Completes when the server asks the connections to close.
+    pub async fn shutdown_requested(&self) {
+        self.registry.shutdown.cancelled().await
+    }
+}
+
+impl Drop for RegisteredConnection {
+    fn drop(&mut self) {
+        self.registry
+            .state
+            .lock()
+            .unwrap()
+            .connections
+            .remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_ip_limit() {
+        let registry = LdapConnectionRegistry::new(None, Some(2));
+        let ip: IpAddr = "192.168.1.1".parse().unwrap();
+        let first = registry.register(Some(ip)).unwrap();
+        let _second = registry.register(Some(ip)).unwrap();
+        assert_eq!(
+            registry.register(Some(ip)).err(),
+            Some(ConnectionLimitError::TooManyConnectionsFromIp { ip, count: 2 })
+        );
+        // Other clients are not affected.
+        let _other = registry
+            .register(Some("192.168.1.2".parse().unwrap()))
+            .unwrap();
+        assert_eq!(registry.list().len(), 3);
+        // Closing a connection frees a slot.
+        drop(first);
+        registry.register(Some(ip)).unwrap();
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let registry = LdapConnectionRegistry::new(Some(1), None);
+        let first = registry.register(None).unwrap();
+        assert_eq!(
+            registry.register(None).err(),
+            Some(ConnectionLimitError::TooManyConnections(1))
+        );
+        drop(first);
+        registry.register(None).unwrap();
+    }
+}
@@ -1,35 +1,55 @@
 use crate::{
     domain::{
+        error::DomainError,
         handler::{
             BackendHandler, BindRequest, CreateUserRequest, LoginHandler, SchemaBackendHandler,
+            UpdateGroupRequest, UpdateUserRequest, UserBackendHandler,
         },
         ldap::{
             error::{LdapError, LdapResult},
             group::{convert_groups_to_ldap_op, get_groups_list},
-            user::{convert_users_to_ldap_op, get_user_list},
+            user::{convert_users_to_ldap_op, get_user_list, is_built_in_user_attribute},
             utils::{
-                get_user_id_from_distinguished_name, is_subtree, parse_distinguished_name, LdapInfo,
+                get_group_id_from_distinguished_name, get_user_id_from_distinguished_name,
+                is_subtree, parse_distinguished_name, parse_ldap_filter, LdapInfo,
             },
         },
         opaque_handler::OpaqueHandler,
-        types::{Group, JpegPhoto, UserAndGroups, UserId},
+        types::{
+            AttributeType, AttributeValue, Group, JpegPhoto, Serialized, UserAndGroups, UserId,
+        },
     },
-    infra::access_control::{
-        AccessControlledBackendHandler, AdminBackendHandler, UserAndGroupListerBackendHandler,
-        UserReadableBackendHandler, ValidationResults,
+    infra::{
+        access_control::{
+            AccessControlledBackendHandler, AdminBackendHandler, GroupMembershipBackendHandler,
+            ReadonlyBackendHandler, UserAndGroupListerBackendHandler, UserReadableBackendHandler,
+            UserWriteableBackendHandler, ValidationResults,
+        },
+        build_info::BUILD_INFO,
+        configuration::{
+            LdapDefaultSearchScope, LdapOperation, LdapSearchOptions, UnknownModifyAttributes,
+        },
+        first_run::FirstRunGate,
+        ldap_codec::{
+            ReadEntry, RequestControls, ResponseControl, ResponseExtensions,
+            ASSERTION_FAILED_RESULT_CODE, SYNC_REQUEST_CONTROL_OID,
+        },
     },
 };
 use anyhow::Result;
 use ldap3_proto::proto::{
     LdapAddRequest, LdapBindCred, LdapBindRequest, LdapBindResponse, LdapCompareRequest,
-    LdapDerefAliases, LdapExtendedRequest, LdapExtendedResponse, LdapFilter, LdapModify,
-    LdapModifyRequest, LdapModifyType, LdapOp, LdapPartialAttribute, LdapPasswordModifyRequest,
-    LdapResult as LdapResultOp, LdapResultCode, LdapSearchRequest, LdapSearchResultEntry,
-    LdapSearchScope,
+    LdapControl, LdapDerefAliases, LdapExtendedRequest, LdapExtendedResponse, LdapFilter,
+    LdapModify, LdapModifyDNRequest, LdapModifyRequest, LdapModifyType, LdapOp,
+    LdapPartialAttribute, LdapPasswordModifyRequest, LdapResult as LdapResultOp, LdapResultCode,
+    LdapSearchRequest, LdapSearchResultEntry, LdapSearchScope,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use tracing::{debug, instrument, warn};
 
+/// The responses to a request, converted while they are sent to the client.
+pub type LdapResponses<'a> = Box<dyn Iterator<Item = LdapOp> + 'a>;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 struct LdapDn(String);
 
@@ -77,6 +97,58 @@ fn get_search_scope(base_dn: &[(String, String)], dn_parts: &[(String, String)])
     }
 }
 
+/// Rewrites the base DN of searches from misconfigured clients, see `LdapSearchOptions`.
+#[derive(Debug, Default)]
+struct SearchBaseResolver {
+    base_dn_aliases: Vec<Vec<(String, String)>>,
+    default_search_base: Option<String>,
+}
+
+impl SearchBaseResolver {
+    fn new(options: &LdapSearchOptions, base_dn_str: &str) -> Self {
+        Self {
+            base_dn_aliases: options
+                .base_dn_aliases
+                .iter()
+                // Checked when the configuration is loaded.
+                .map(|alias| {
+                    parse_distinguished_name(&alias.to_ascii_lowercase()).unwrap_or_else(|_| {
+                        panic!(
+                            "Invalid value for ldap_search_options.base_dn_aliases in configuration: {}",
+                            alias
+                        )
+                    })
+                })
+                .collect(),
+            default_search_base: options.default_scope.map(|scope| match scope {
+                LdapDefaultSearchScope::Global => base_dn_str.to_owned(),
+                LdapDefaultSearchScope::People => format!("ou=people,{}", base_dn_str),
+                LdapDefaultSearchScope::Groups => format!("ou=groups,{}", base_dn_str),
+            }),
+        }
+    }
+
+    /// Returns the base to search instead of `base`, if any.
+    fn resolve(&self, base: &str, base_dn: &[(String, String)]) -> Option<String> {
+        if base.is_empty() {
+            return self.default_search_base.clone();
+        }
+        let dn_parts = parse_distinguished_name(&base.to_ascii_lowercase()).ok()?;
+        let alias = self
+            .base_dn_aliases
+            .iter()
+            .find(|alias| is_subtree(&dn_parts, alias))?;
+        Some(
+            dn_parts[..dn_parts.len() - alias.len()]
+                .iter()
+                .chain(base_dn)
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    }
+}
+
 fn make_search_request<S: Into<String>>(
     base: &str,
     filter: LdapFilter,
@@ -107,6 +179,38 @@ fn make_search_error(code: LdapResultCode, message: String) -> LdapOp {
     })
 }
 
+/// Ends the search after `size_limit` entries (0 means no limit) with a sizeLimitExceeded result,
+/// if there are more. The entries are only produced as they are sent.
+fn limit_search_results<'a>(
+    entries: impl Iterator<Item = LdapOp> + 'a,
+    size_limit: i32,
+) -> LdapResponses<'a> {
+    let size_limit = usize::try_from(size_limit).unwrap_or_default();
+    let mut entries = entries.peekable();
+    let mut sent = 0;
+    let mut done = false;
+    Box::new(std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        if size_limit == 0 || sent < size_limit {
+            if let Some(entry) = entries.next() {
+                sent += 1;
+                return Some(entry);
+            }
+        }
+        done = true;
+        Some(if entries.peek().is_some() {
+            make_search_error(
+                LdapResultCode::SizeLimitExceeded,
+                format!("More than {} entries match the search", size_limit),
+            )
+        } else {
+            make_search_success()
+        })
+    }))
+}
+
 fn make_add_error(code: LdapResultCode, message: String) -> LdapOp {
     LdapOp::AddResponse(LdapResultOp {
         code,
@@ -116,6 +220,36 @@ fn make_add_error(code: LdapResultCode, message: String) -> LdapOp {
     })
 }
 
+/// The error for a user or group that couldn't be created.
+fn make_creation_error(entity: &str, error: DomainError) -> LdapError {
+    let code = match error {
+        DomainError::LimitExceeded(_) => LdapResultCode::AdminLimitExceeded,
+        DomainError::Conflict(_) | DomainError::InvalidInput(_) => {
+            LdapResultCode::ConstraintViolation
+        }
+        _ => LdapResultCode::OperationsError,
+    };
+    LdapError::from_domain_error(code, format!("Could not create {}", entity), &error)
+}
+
+fn make_del_response(code: LdapResultCode, message: String) -> LdapOp {
+    LdapOp::DelResponse(LdapResultOp {
+        code,
+        matcheddn: "".to_string(),
+        message,
+        referral: vec![],
+    })
+}
+
+fn make_compare_response(code: LdapResultCode, message: String) -> LdapOp {
+    LdapOp::CompareResult(LdapResultOp {
+        code,
+        matcheddn: "".to_string(),
+        message,
+        referral: vec![],
+    })
+}
+
 fn make_extended_response(code: LdapResultCode, message: String) -> LdapOp {
     LdapOp::ExtendedResponse(LdapExtendedResponse {
         res: LdapResultOp {
@@ -138,6 +272,174 @@ fn make_modify_response(code: LdapResultCode, message: String) -> LdapOp {
     })
 }
 
+fn make_modify_dn_response(code: LdapResultCode, message: String) -> LdapOp {
+    LdapOp::ModifyDNResponse(LdapResultOp {
+        code,
+        matcheddn: "".to_string(),
+        message,
+        referral: vec![],
+    })
+}
+
+/// Returns the error response matching the type of the request, or None for the requests that
+/// don't get a response (unbind and abandon).
+fn make_error_response(request: &LdapOp, code: LdapResultCode, message: String) -> Option<LdapOp> {
+    let result = LdapResultOp {
+        code,
+        matcheddn: "".to_string(),
+        message,
+        referral: vec![],
+    };
+    Some(match request {
+        LdapOp::BindRequest(_) => LdapOp::BindResponse(LdapBindResponse {
+            res: result,
+            saslcreds: None,
+        }),
+        LdapOp::SearchRequest(_) => LdapOp::SearchResultDone(result),
+        LdapOp::ModifyRequest(_) => LdapOp::ModifyResponse(result),
+        LdapOp::AddRequest(_) => LdapOp::AddResponse(result),
+        LdapOp::DelRequest(_) => LdapOp::DelResponse(result),
+        LdapOp::ModifyDNRequest(_) => LdapOp::ModifyDNResponse(result),
+        LdapOp::CompareRequest(_) => LdapOp::CompareResult(result),
+        LdapOp::UnbindRequest | LdapOp::AbandonRequest(_) => return None,
+        _ => make_extended_response(result.code, result.message),
+    })
+}
+
+/// The error response to a request whose operation couldn't be decoded, chosen from the
+/// APPLICATION tag of the operation. Returns None for the requests without a response.
+pub fn make_unsupported_operation_response(op_tag: u8) -> Option<LdapOp> {
+    let result = |code, message: &str| LdapResultOp {
+        code,
+        matcheddn: "".to_string(),
+        message: message.to_string(),
+        referral: vec![],
+    };
+    let unwilling = || result(LdapResultCode::UnwillingToPerform, "Unsupported operation");
+    Some(match op_tag & 0x1f {
+        // Most likely a SASL bind.
+        0 => LdapOp::BindResponse(LdapBindResponse {
+            res: result(
+                LdapResultCode::AuthMethodNotSupported,
+                "Unsupported authentication method",
+            ),
+            saslcreds: None,
+        }),
+        // Unbind and abandon requests.
+        2 | 16 => return None,
+        3 => LdapOp::SearchResultDone(unwilling()),
+        6 => LdapOp::ModifyResponse(unwilling()),
+        8 => LdapOp::AddResponse(unwilling()),
+        10 => LdapOp::DelResponse(unwilling()),
+        12 => LdapOp::ModifyDNResponse(unwilling()),
+        14 => LdapOp::CompareResult(unwilling()),
+        _ => make_extended_response(
+            LdapResultCode::UnwillingToPerform,
+            "Unsupported operation".to_string(),
+        ),
+    })
+}
+
+/// Runs the search, or drops it and returns `timeLimitExceeded` (as if its time limit was
+/// reached) if it takes longer than the timeout. The search entries that are converted while they
+/// are sent are not counted. Only the searches are cut off: dropping a write could leave it half
+/// done.
+async fn run_search_with_timeout<'a>(
+    timeout: std::time::Duration,
+    search: impl std::future::Future<Output = Option<LdapResponses<'a>>>,
+) -> Option<LdapResponses<'a>> {
+    match tokio::time::timeout(timeout, search).await {
+        Ok(responses) => responses,
+        Err(_) => {
+            warn!(?timeout, "LDAP search timed out");
+            Some(Box::new(std::iter::once(make_search_error(
+                LdapResultCode::TimeLimitExceeded,
+                format!("The search took longer than {:?}", timeout),
+            ))))
+        }
+    }
+}
+
+/// None of the controls decoded by ldap3_proto (in practice, the content synchronization) are
+/// implemented, so a critical one must make the operation fail (RFC 4511, section 4.1.11).
+/// Non-critical ones are ignored. The assertion, pre-read and post-read controls are decoded and
+/// handled separately, through `RequestControls`.
+fn check_critical_controls(controls: &[LdapControl]) -> LdapResult<()> {
+    for control in controls {
+        let is_critical = match control {
+            LdapControl::SyncRequest { criticality, .. } => *criticality,
+            _ => false,
+        };
+        if is_critical {
+            return Err(LdapError {
+                code: LdapResultCode::UnavailableCriticalExtension,
+                message: format!(
+                    "Unsupported critical control: content synchronization ({})",
+                    SYNC_REQUEST_CONTROL_OID
+                ),
+            });
+        }
+        debug!(?control, "Ignoring non-critical control");
+    }
+    Ok(())
+}
+
+/// The class of the request, with its name in the configuration, or None for the operations that
+/// can't be disabled.
+fn get_operation_class(op: &LdapOp) -> Option<(LdapOperation, &'static str)> {
+    Some(match op {
+        LdapOp::SearchRequest(_) => (LdapOperation::Search, "search"),
+        LdapOp::CompareRequest(_) => (LdapOperation::Compare, "compare"),
+        LdapOp::AddRequest(_) => (LdapOperation::Add, "add"),
+        LdapOp::ModifyRequest(_) => (LdapOperation::Modify, "modify"),
+        LdapOp::ModifyDNRequest(_) => (LdapOperation::ModifyDn, "modify_dn"),
+        LdapOp::DelRequest(_) => (LdapOperation::Delete, "delete"),
+        LdapOp::ExtendedRequest(_) => (LdapOperation::Extended, "extended"),
+        _ => return None,
+    })
+}
+
+/// The name of the operation, e.g. `SearchResultEntry`, without its content, which can be
+/// sensitive.
+fn get_operation_name(op: &LdapOp) -> String {
+    format!("{:?}", op)
+        .chars()
+        .take_while(char::is_ascii_alphanumeric)
+        .collect()
+}
+
+/// The message of all the failed binds, whatever the reason: the client doesn't learn whether the
+/// user exists.
+const INVALID_CREDENTIALS: &str = "Invalid credentials";
+
+/// The response to a failed assertion: `ldap3_proto` has no `assertionFailed` (122) result code,
+/// so the codec replaces it with `ASSERTION_FAILED_RESULT_CODE`.
+const ASSERTION_FAILED: LdapResultCode = LdapResultCode::Other;
+
+/// Split a single-valued RDN such as "uid=bob" into a lowercase attribute and a value that keeps
+/// its case.
+fn parse_rdn(rdn: &str) -> LdapResult<(String, String)> {
+    match rdn.split_once('=') {
+        Some((attribute, value)) if !value.trim().is_empty() && !rdn.contains(['+', ',']) => Ok((
+            attribute.trim().to_ascii_lowercase(),
+            value.trim().to_owned(),
+        )),
+        _ => Err(LdapError {
+            code: LdapResultCode::InvalidDNSyntax,
+            message: format!(r#"Invalid RDN: "{}""#, rdn),
+        }),
+    }
+}
+
+/// Whether the value of the first RDN of `dn` is exactly the user ID, case included.
+fn has_exact_user_id(dn: &str, user_id: &UserId) -> bool {
+    dn.split(',')
+        .next()
+        .and_then(|rdn| rdn.split_once('='))
+        .map(|(_, value)| value.trim() == user_id.as_str())
+        .unwrap_or(false)
+}
+
 fn root_dse_response(base_dn: &str) -> LdapOp {
     LdapOp::SearchResultEntry(LdapSearchResultEntry {
         dn: "".to_string(),
@@ -152,9 +454,7 @@ fn root_dse_response(base_dn: &str) -> LdapOp {
             },
             LdapPartialAttribute {
                 atype: "vendorVersion".to_string(),
-                vals: vec![concat!("lldap_", env!("CARGO_PKG_VERSION"))
-                    .to_string()
-                    .into_bytes()],
+                vals: vec![BUILD_INFO.vendor_version().into_bytes()],
             },
             LdapPartialAttribute {
                 atype: "supportedLDAPVersion".to_string(),
@@ -194,6 +494,16 @@ pub struct LdapHandler<Backend> {
     user_info: Option<ValidationResults>,
     backend_handler: AccessControlledBackendHandler<Backend>,
     ldap_info: LdapInfo,
+    search_base_resolver: SearchBaseResolver,
+    /// Implicitly ANDed with the user searches of these users.
+    search_filters: HashMap<UserId, LdapFilter>,
+    case_sensitive_bind_dn: bool,
+    unknown_modify_attributes: UnknownModifyAttributes,
+    disabled_operations: Vec<LdapOperation>,
+    first_run: FirstRunGate,
+    operation_timeout: Option<std::time::Duration>,
+    /// Returned in the message of the successful binds.
+    login_banner: Option<String>,
 }
 
 impl<Backend: LoginHandler> LdapHandler<Backend> {
@@ -214,11 +524,42 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         mut ldap_base_dn: String,
         ignored_user_attributes: Vec<String>,
         ignored_group_attributes: Vec<String>,
+        search_options: &LdapSearchOptions,
     ) -> Self {
         ldap_base_dn.make_ascii_lowercase();
+        // The admins, read-only admins and password managers are always visible, so they can
+        // still bind.
+        let visible_user_groups = search_options.visible_group.as_ref().map(|group| {
+            std::iter::once(group.clone())
+                .chain(backend_handler.admin_groups().iter().cloned())
+                .chain(
+                    ["lldap_read_only_admin", "lldap_password_manager"]
+                        .into_iter()
+                        .map(str::to_owned),
+                )
+                .collect()
+        });
         Self {
             user_info: None,
             backend_handler,
+            search_base_resolver: SearchBaseResolver::new(search_options, &ldap_base_dn),
+            search_filters: search_options
+                .search_filters
+                .iter()
+                // Checked when the configuration is loaded.
+                .map(|(user, filter)| {
+                    let filter = parse_ldap_filter(filter).unwrap_or_else(|e| {
+                        panic!(
+                            "Invalid value for ldap_search_options.search_filters in configuration: {}",
+                            e.message
+                        )
+                    });
+                    (UserId::new(user), filter)
+                })
+                .collect(),
+            case_sensitive_bind_dn: search_options.case_sensitive_bind_dn,
+            unknown_modify_attributes: search_options.unknown_modify_attributes,
+            disabled_operations: search_options.disabled_operations.clone(),
             ldap_info: LdapInfo {
                 base_dn: parse_distinguished_name(&ldap_base_dn).unwrap_or_else(|_| {
                     panic!(
@@ -229,10 +570,43 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 base_dn_str: ldap_base_dn,
                 ignored_user_attributes,
                 ignored_group_attributes,
+                additional_user_object_classes: search_options
+                    .additional_user_object_classes
+                    .clone(),
+                additional_group_object_classes: search_options
+                    .additional_group_object_classes
+                    .clone(),
+                visible_user_groups,
+                user_password_placeholder: search_options.user_password_placeholder.clone(),
+                empty_group_members: search_options.empty_group_members,
+                empty_group_placeholder_member: search_options
+                    .empty_group_placeholder_member
+                    .clone(),
+                membership_value_format: search_options.membership_value_format,
             },
+            first_run: FirstRunGate::default(),
+            operation_timeout: None,
+            login_banner: None,
         }
     }
 
+    /// Refuses the binds while the first run setup is in progress.
+    pub fn with_first_run_gate(mut self, first_run: FirstRunGate) -> Self {
+        self.first_run = first_run;
+        self
+    }
+
+    /// Cuts off the operations that take longer than the timeout.
+    pub fn with_operation_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.operation_timeout = timeout;
+        self
+    }
+
+    pub fn with_login_banner(mut self, login_banner: Option<String>) -> Self {
+        self.login_banner = login_banner;
+        self
+    }
+
     #[cfg(test)]
     pub fn new_for_tests(backend_handler: Backend, ldap_base_dn: &str) -> Self {
         Self::new(
@@ -240,9 +614,21 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             ldap_base_dn.to_string(),
             vec![],
             vec![],
+            &LdapSearchOptions::default(),
         )
     }
 
+    /// The DN of the user the connection is bound as, if any.
+    pub fn get_bound_dn(&self) -> Option<String> {
+        self.user_info.as_ref().map(|info| {
+            format!(
+                "uid={},ou=people,{}",
+                info.user.as_str(),
+                self.ldap_info.base_dn_str
+            )
+        })
+    }
+
     #[instrument(skip_all, level = "debug")]
     pub async fn do_bind(&mut self, request: &LdapBindRequest) -> (LdapResultCode, String) {
         debug!("DN: {}", &request.dn);
@@ -254,6 +640,19 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             Ok(s) => s,
             Err(e) => return (LdapResultCode::NamingViolation, e.to_string()),
         };
+        if self.case_sensitive_bind_dn && !has_exact_user_id(&request.dn, &user_id) {
+            debug!("The case of the user ID doesn't match");
+            return (
+                LdapResultCode::InvalidCredentials,
+                INVALID_CREDENTIALS.to_string(),
+            );
+        }
+        if self.first_run.is_active() {
+            return (
+                LdapResultCode::UnwillingToPerform,
+                "The admin password must be changed in the web UI first".to_string(),
+            );
+        }
         let LdapBindCred::Simple(password) = &request.cred;
         match self
             .get_login_handler()
@@ -264,15 +663,41 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             .await
         {
             Ok(()) => {
-                self.user_info = self
+                let groups = self
                     .backend_handler
-                    .get_permissions_for_user(user_id)
+                    .unsafe_get_handler()
+                    .get_user_groups(&user_id)
                     .await
                     .ok();
+                let is_visible = match &groups {
+                    Some(groups) => self
+                        .ldap_info
+                        .is_user_visible(groups.iter().map(|g| &g.display_name)),
+                    None => self.ldap_info.visible_user_groups.is_none(),
+                };
+                if !is_visible {
+                    debug!("User not in the LDAP visible group");
+                    return (
+                        LdapResultCode::InvalidCredentials,
+                        INVALID_CREDENTIALS.to_string(),
+                    );
+                }
+                self.user_info = groups.map(|groups| {
+                    self.backend_handler.get_permissions_from_groups(
+                        user_id,
+                        groups.iter().map(|g| &g.display_name),
+                    )
+                });
                 debug!("Success!");
-                (LdapResultCode::Success, "".to_string())
+                (
+                    LdapResultCode::Success,
+                    self.login_banner.clone().unwrap_or_default(),
+                )
             }
-            Err(_) => (LdapResultCode::InvalidCredentials, "".to_string()),
+            Err(_) => (
+                LdapResultCode::InvalidCredentials,
+                INVALID_CREDENTIALS.to_string(),
+            ),
         }
     }
 
@@ -326,15 +751,15 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                             .expect("Unexpected permission error")
                             .get_user_groups(&uid)
                             .await
-                            .map_err(|e| LdapError {
-                                code: LdapResultCode::OperationsError,
-                                message: format!(
-                                    "Internal error while requesting user's groups: {:#?}",
-                                    e
-                                ),
+                            .map_err(|e| {
+                                LdapError::internal(
+                                    LdapResultCode::OperationsError,
+                                    "Internal error while requesting the user's groups".to_string(),
+                                    e,
+                                )
                             })?
                             .iter()
-                            .any(|g| g.display_name == "lldap_admin");
+                            .any(|g| self.backend_handler.is_admin_group(&g.display_name));
                         if !credentials.can_change_password(&uid, user_is_admin) {
                             Err(LdapError {
                                 code: LdapResultCode::InsufficentAccessRights,
@@ -347,10 +772,11 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                             .change_password(self.get_opaque_handler(), &uid, password.as_bytes())
                             .await
                         {
-                            Err(LdapError {
-                                code: LdapResultCode::Other,
-                                message: format!("Error while changing the password: {:#?}", e),
-                            })
+                            Err(LdapError::internal(
+                                LdapResultCode::Other,
+                                "Error while changing the password".to_string(),
+                                e,
+                            ))
                         } else {
                             Ok(vec![make_extended_response(
                                 LdapResultCode::Success,
@@ -391,9 +817,13 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         user_is_admin: bool,
         change: &LdapModify,
     ) -> LdapResult<()> {
-        if change.modification.atype.to_ascii_lowercase() != "userpassword"
-            || change.operation != LdapModifyType::Replace
-        {
+        let attribute = change.modification.atype.to_ascii_lowercase();
+        if attribute != "userpassword" && !is_built_in_user_attribute(&attribute) {
+            return self
+                .handle_unknown_attribute_change(user_id, credentials, change)
+                .await;
+        }
+        if attribute != "userpassword" || change.operation != LdapModifyType::Replace {
             return Err(LdapError {
                 code: LdapResultCode::UnwillingToPerform,
                 message: format!(
@@ -414,9 +844,12 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         if let [value] = &change.modification.vals.as_slice() {
             self.change_password(self.get_opaque_handler(), user_id, value)
                 .await
-                .map_err(|e| LdapError {
-                    code: LdapResultCode::Other,
-                    message: format!("Error while changing the password: {:#?}", e),
+                .map_err(|e| {
+                    LdapError::internal(
+                        LdapResultCode::Other,
+                        "Error while changing the password".to_string(),
+                        e,
+                    )
                 })?;
         } else {
             return Err(LdapError {
@@ -430,6 +863,118 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         Ok(())
     }
 
+    /// Changes an attribute that LLDAP doesn't know, according to `unknown_modify_attributes`.
+    async fn handle_unknown_attribute_change(
+        &self,
+        user_id: &UserId,
+        credentials: &ValidationResults,
+        change: &LdapModify,
+    ) -> LdapResult<()> {
+        let attribute = &change.modification.atype;
+        if self
+            .ldap_info
+            .ignored_user_attributes
+            .contains(&attribute.to_ascii_lowercase())
+        {
+            debug!(
+                "Ignoring the change of the ignored attribute `{}`",
+                attribute
+            );
+            return Ok(());
+        }
+        let undefined = || LdapError {
+            code: LdapResultCode::UndefinedAttributeType,
+            message: format!("Unknown attribute: `{}`", attribute),
+        };
+        match self.unknown_modify_attributes {
+            UnknownModifyAttributes::Reject => return Err(undefined()),
+            UnknownModifyAttributes::Ignore => {
+                debug!(
+                    "Ignoring the change of the unknown attribute `{}`",
+                    attribute
+                );
+                return Ok(());
+            }
+            UnknownModifyAttributes::Store => (),
+        }
+        let schema = self
+            .backend_handler
+            .get_user_restricted_lister_handler(credentials)
+            .get_schema()
+            .await
+            .map_err(|e| {
+                LdapError::internal(
+                    LdapResultCode::OperationsError,
+                    "Unable to get the schema".to_string(),
+                    e,
+                )
+            })?;
+        let attribute_schema = schema
+            .user_attributes
+            .get_attribute_schema(attribute)
+            .filter(|a| !a.is_hardcoded)
+            .ok_or_else(undefined)?;
+        if attribute_schema.attribute_type != AttributeType::String
+            || attribute_schema.is_list
+            || change.operation != LdapModifyType::Replace
+        {
+            return Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: format!(
+                    "Only the replacement of single-valued string attributes is supported, not \
+                     `{:?}` for `{}`",
+                    change.operation, attribute
+                ),
+            });
+        }
+        let value = match change.modification.vals.as_slice() {
+            [value] => String::from_utf8(value.clone()).map_err(|_| LdapError {
+                code: LdapResultCode::InvalidAttributeSyntax,
+                message: format!("Invalid UTF-8 value for `{}`", attribute),
+            })?,
+            values => {
+                return Err(LdapError {
+                    code: LdapResultCode::InvalidAttributeSyntax,
+                    message: format!(
+                        "Wrong number of values for `{}`: {}",
+                        attribute,
+                        values.len()
+                    ),
+                })
+            }
+        };
+        let can_edit = credentials.is_admin()
+            || (&credentials.user == user_id && attribute_schema.is_editable);
+        let handler = self
+            .backend_handler
+            .get_writeable_handler(credentials, user_id)
+            .filter(|_| can_edit)
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: format!(
+                    "User `{}` cannot modify the attribute `{}` of user `{}`",
+                    &credentials.user, attribute, user_id
+                ),
+            })?;
+        handler
+            .update_user(UpdateUserRequest {
+                user_id: user_id.clone(),
+                insert_attributes: vec![AttributeValue {
+                    name: attribute_schema.name.clone(),
+                    value: Serialized::from(value.as_str()),
+                }],
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| {
+                LdapError::from_domain_error(
+                    LdapResultCode::OperationsError,
+                    format!("Error while modifying `{}`", attribute),
+                    &e,
+                )
+            })
+    }
+
     async fn handle_modify_request(
         &mut self,
         request: &LdapModifyRequest,
@@ -454,12 +999,15 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                     .expect("Unexpected permission error")
                     .get_user_groups(&uid)
                     .await
-                    .map_err(|e| LdapError {
-                        code: LdapResultCode::OperationsError,
-                        message: format!("Internal error while requesting user's groups: {:#?}", e),
+                    .map_err(|e| {
+                        LdapError::internal(
+                            LdapResultCode::OperationsError,
+                            "Internal error while requesting the user's groups".to_string(),
+                            e,
+                        )
                     })?
                     .iter()
-                    .any(|g| g.display_name == "lldap_admin");
+                    .any(|g| self.backend_handler.is_admin_group(&g.display_name));
                 for change in &request.changes {
                     self.handle_modify_change(&uid, &credentials, user_is_admin, change)
                         .await?
@@ -482,28 +1030,176 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             .unwrap_or_else(|e: LdapError| vec![make_modify_response(e.code, e.message)])
     }
 
+    /// Whether the entry matches the filter of an assertion control, with the permissions of the
+    /// bound user.
+    async fn check_assertion(&mut self, dn: &str, filter: &LdapFilter) -> LdapResult<bool> {
+        Ok(self
+            .do_search_or_dse(&make_search_request(dn, filter.clone(), vec!["1.1"]))
+            .await?
+            .iter()
+            .any(|op| matches!(op, LdapOp::SearchResultEntry(_))))
+    }
+
+    /// Reads the target entry of a pre-read or post-read control, with the permissions of the
+    /// bound user.
+    async fn read_entry(
+        &mut self,
+        dn: &str,
+        read_entry: &ReadEntry,
+    ) -> LdapResult<Option<LdapSearchResultEntry>> {
+        Ok(self
+            .do_search_or_dse(&make_search_request(
+                dn,
+                LdapFilter::Present("objectClass".to_string()),
+                read_entry.attributes.clone(),
+            ))
+            .await?
+            .into_iter()
+            .find_map(|op| match op {
+                LdapOp::SearchResultEntry(entry) => Some(entry),
+                _ => None,
+            }))
+    }
+
+    /// Runs a modify request with an assertion, pre-read and/or post-read control, in a single
+    /// transaction: the entry can't change between the assertion, the reads and the modification.
+    /// The read entries are only returned if the modification succeeded.
+    async fn do_modify_request_with_controls(
+        &mut self,
+        request: LdapModifyRequest,
+        controls: &[LdapControl],
+        request_controls: &RequestControls,
+    ) -> LdapResult<(Vec<LdapOp>, ResponseExtensions)> {
+        let transaction = self
+            .backend_handler
+            .begin_transaction()
+            .await
+            .map_err(|e| {
+                LdapError::internal(
+                    LdapResultCode::OperationsError,
+                    "Unable to start a transaction".to_string(),
+                    e,
+                )
+            })?;
+        let backend_handler = std::mem::replace(&mut self.backend_handler, transaction);
+        let result = self
+            .do_modify_request_with_controls_in_transaction(request, controls, request_controls)
+            .await;
+        let transaction = std::mem::replace(&mut self.backend_handler, backend_handler);
+        // The transaction is rolled back if the modification failed.
+        let (responses, extensions) = result?;
+        let succeeded = responses.iter().any(|op| {
+            matches!(op, LdapOp::ModifyResponse(result) if result.code == LdapResultCode::Success)
+        });
+        if succeeded {
+            transaction.commit_transaction().await.map_err(|e| {
+                LdapError::internal(
+                    LdapResultCode::OperationsError,
+                    "Unable to commit the modification".to_string(),
+                    e,
+                )
+            })?;
+        }
+        Ok((responses, extensions))
+    }
+
+    async fn do_modify_request_with_controls_in_transaction(
+        &mut self,
+        request: LdapModifyRequest,
+        controls: &[LdapControl],
+        request_controls: &RequestControls,
+    ) -> LdapResult<(Vec<LdapOp>, ResponseExtensions)> {
+        if let Some(assertion) = &request_controls.assertion {
+            if !self.check_assertion(&request.dn, &assertion.filter).await? {
+                return Ok((
+                    vec![make_modify_response(
+                        ASSERTION_FAILED,
+                        format!("Assertion failed for `{}`", request.dn),
+                    )],
+                    ResponseExtensions {
+                        result_code: Some(ASSERTION_FAILED_RESULT_CODE),
+                        ..Default::default()
+                    },
+                ));
+            }
+        }
+        let mut response_controls = Vec::new();
+        if let Some(pre_read) = &request_controls.pre_read {
+            if let Some(entry) = self.read_entry(&request.dn, pre_read).await? {
+                response_controls.push(ResponseControl::PreRead(entry));
+            }
+        }
+        let dn = request.dn.clone();
+        let responses = self
+            .handle_ldap_message_lazy(LdapOp::ModifyRequest(request), controls)
+            .await
+            .map(|responses| responses.collect::<Vec<_>>())
+            .unwrap_or_default();
+        let succeeded = responses.iter().any(|op| {
+            matches!(op, LdapOp::ModifyResponse(result) if result.code == LdapResultCode::Success)
+        });
+        if !succeeded {
+            return Ok((responses, ResponseExtensions::default()));
+        }
+        if let Some(post_read) = &request_controls.post_read {
+            if let Some(entry) = self.read_entry(&dn, post_read).await? {
+                response_controls.push(ResponseControl::PostRead(entry));
+            }
+        }
+        Ok((
+            responses,
+            ResponseExtensions {
+                controls: response_controls,
+                ..Default::default()
+            },
+        ))
+    }
+
     pub async fn do_search_or_dse(
         &mut self,
         request: &LdapSearchRequest,
     ) -> LdapResult<Vec<LdapOp>> {
+        Ok(self.do_search_or_dse_lazy(request).await?.collect())
+    }
+
+    pub async fn do_search_or_dse_lazy(
+        &self,
+        request: &LdapSearchRequest,
+    ) -> LdapResult<LdapResponses<'_>> {
         if request.base.is_empty() && request.scope == LdapSearchScope::Base {
             if let LdapFilter::Present(attribute) = &request.filter {
                 if attribute.to_ascii_lowercase() == "objectclass" {
                     debug!("rootDSE request");
-                    return Ok(vec![
-                        root_dse_response(&self.ldap_info.base_dn_str),
-                        make_search_success(),
-                    ]);
+                    return Ok(Box::new(
+                        vec![
+                            root_dse_response(&self.ldap_info.base_dn_str),
+                            make_search_success(),
+                        ]
+                        .into_iter(),
+                    ));
                 }
             }
         }
-        self.do_search(request).await
+        if let Some(base) = self
+            .search_base_resolver
+            .resolve(&request.base, &self.ldap_info.base_dn)
+        {
+            debug!(?request.base, ?base, "Rewriting the search base");
+            return self
+                .do_search_lazy(&LdapSearchRequest {
+                    base,
+                    ..request.clone()
+                })
+                .await;
+        }
+        self.do_search_lazy(request).await
     }
 
     async fn do_search_internal(
         &self,
         backend_handler: &impl UserAndGroupListerBackendHandler,
         request: &LdapSearchRequest,
+        user_search_filter: Option<&LdapFilter>,
     ) -> LdapResult<(Option<Vec<UserAndGroups>>, Option<Vec<Group>>)> {
         let dn_parts = parse_distinguished_name(&request.base.to_ascii_lowercase())?;
         let scope = get_search_scope(&self.ldap_info.base_dn, &dn_parts);
@@ -520,10 +1216,14 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             let need_groups = request
                 .attrs
                 .iter()
-                .any(|s| s.to_ascii_lowercase() == "memberof");
+                .any(|s| s == "+" || s.to_ascii_lowercase() == "memberof");
+            let filter = match user_search_filter {
+                None => filter.clone(),
+                Some(search_filter) => LdapFilter::And(vec![search_filter.clone(), filter.clone()]),
+            };
             get_user_list(
                 &self.ldap_info,
-                filter,
+                &filter,
                 need_groups,
                 &request.base,
                 backend_handler,
@@ -566,8 +1266,166 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         })
     }
 
-    #[instrument(skip_all, level = "debug")]
+    /// Returns the users that can be listed, when some of them are hidden from the LDAP searches
+    /// (by the visible group or the search filter of the bound user): the hidden users are removed
+    /// from the members of the groups as well.
+    async fn get_visible_users(
+        &self,
+        backend_handler: &impl UserAndGroupListerBackendHandler,
+        base: &str,
+        user_search_filter: Option<&LdapFilter>,
+    ) -> LdapResult<Option<HashSet<UserId>>> {
+        if self.ldap_info.visible_user_groups.is_none() && user_search_filter.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(
+            get_user_list(
+                &self.ldap_info,
+                user_search_filter.unwrap_or(&LdapFilter::And(vec![])),
+                false,
+                base,
+                backend_handler,
+            )
+            .await?
+            .into_iter()
+            .map(|u| u.user.user_id)
+            .collect(),
+        ))
+    }
+
+    /// Returns the entry for the base DN or one of the OUs, for base searches that probe for the
+    /// entry itself (`(objectClass=*)` or one of its object classes), or that ask for the
+    /// `hasSubordinates` or `numSubordinates` operational attributes. Other searches on these DNs
+    /// return their content.
+    async fn get_container_entry(
+        &self,
+        backend_handler: &impl UserAndGroupListerBackendHandler,
+        request: &LdapSearchRequest,
+        user_search_filter: Option<&LdapFilter>,
+    ) -> LdapResult<Option<LdapOp>> {
+        if request.scope != LdapSearchScope::Base {
+            return Ok(None);
+        }
+        let dn_parts = parse_distinguished_name(&request.base.to_ascii_lowercase())?;
+        let scope = get_search_scope(&self.ldap_info.base_dn, &dn_parts);
+        let (dn, object_classes, (naming_attribute, naming_value)) = match scope {
+            SearchScope::Global => {
+                let (attribute, value) =
+                    self.ldap_info
+                        .base_dn
+                        .first()
+                        .cloned()
+                        .ok_or_else(|| LdapError {
+                            code: LdapResultCode::NoSuchObject,
+                            message: "Empty base DN".to_string(),
+                        })?;
+                let object_classes: &[&str] = match attribute.as_str() {
+                    "dc" => &["top", "domain", "dcObject"],
+                    "o" => &["top", "organization"],
+                    "ou" => &["top", "organizationalUnit"],
+                    _ => &["top"],
+                };
+                (
+                    self.ldap_info.base_dn_str.clone(),
+                    object_classes,
+                    (attribute, value),
+                )
+            }
+            SearchScope::Users => (
+                format!("ou=people,{}", &self.ldap_info.base_dn_str),
+                &["top", "organizationalUnit"][..],
+                ("ou".to_string(), "people".to_string()),
+            ),
+            SearchScope::Groups => (
+                format!("ou=groups,{}", &self.ldap_info.base_dn_str),
+                &["top", "organizationalUnit"][..],
+                ("ou".to_string(), "groups".to_string()),
+            ),
+            _ => return Ok(None),
+        };
+        let is_requested = |name: &str| request.attrs.iter().any(|a| a.eq_ignore_ascii_case(name));
+        let has_subordinates = is_requested("hasSubordinates");
+        let num_subordinates = is_requested("numSubordinates");
+        let is_entry_probe = match &request.filter {
+            LdapFilter::Present(attribute) => attribute.eq_ignore_ascii_case("objectclass"),
+            LdapFilter::Equality(attribute, value) => {
+                attribute.eq_ignore_ascii_case("objectclass")
+                    && object_classes.iter().any(|c| c.eq_ignore_ascii_case(value))
+            }
+            _ => false,
+        };
+        if !(is_entry_probe || has_subordinates || num_subordinates) {
+            return Ok(None);
+        }
+        let all_user_attributes = request.attrs.is_empty() || is_requested("*");
+        let mut attributes = Vec::new();
+        if all_user_attributes || is_requested("objectClass") {
+            attributes.push(LdapPartialAttribute {
+                atype: "objectClass".to_string(),
+                vals: object_classes
+                    .iter()
+                    .map(|c| c.as_bytes().to_vec())
+                    .collect(),
+            });
+        }
+        if all_user_attributes || is_requested(&naming_attribute) {
+            attributes.push(LdapPartialAttribute {
+                atype: naming_attribute,
+                vals: vec![naming_value.into_bytes()],
+            });
+        }
+        if has_subordinates || num_subordinates {
+            let all = LdapFilter::And(vec![]);
+            let count = match scope {
+                // ou=people and ou=groups.
+                SearchScope::Global => 2,
+                SearchScope::Users => get_user_list(
+                    &self.ldap_info,
+                    user_search_filter.unwrap_or(&all),
+                    false,
+                    &request.base,
+                    backend_handler,
+                )
+                .await?
+                .len(),
+                _ => get_groups_list(&self.ldap_info, &all, &request.base, backend_handler)
+                    .await?
+                    .len(),
+            };
+            if has_subordinates {
+                attributes.push(LdapPartialAttribute {
+                    atype: "hasSubordinates".to_string(),
+                    vals: vec![if count > 0 {
+                        b"TRUE".to_vec()
+                    } else {
+                        b"FALSE".to_vec()
+                    }],
+                });
+            }
+            if num_subordinates {
+                attributes.push(LdapPartialAttribute {
+                    atype: "numSubordinates".to_string(),
+                    vals: vec![count.to_string().into_bytes()],
+                });
+            }
+        }
+        Ok(Some(LdapOp::SearchResultEntry(LdapSearchResultEntry {
+            dn,
+            attributes,
+        })))
+    }
+
     pub async fn do_search(&self, request: &LdapSearchRequest) -> LdapResult<Vec<LdapOp>> {
+        Ok(self.do_search_lazy(request).await?.collect())
+    }
+
+    /// Runs the search, but the entries are only converted to LDAP as they are consumed. The
+    /// matching users and groups are still all loaded from the database first.
+    #[instrument(skip_all, level = "debug")]
+    pub async fn do_search_lazy(
+        &self,
+        request: &LdapSearchRequest,
+    ) -> LdapResult<LdapResponses<'_>> {
         let user_info = self.user_info.as_ref().ok_or_else(|| LdapError {
             code: LdapResultCode::InsufficentAccessRights,
             message: "No user currently bound".to_string(),
@@ -575,34 +1433,59 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         let backend_handler = self
             .backend_handler
             .get_user_restricted_lister_handler(user_info);
-        let (users, groups) = self.do_search_internal(&backend_handler, request).await?;
-
-        let schema = backend_handler.get_schema().await.map_err(|e| LdapError {
-            code: LdapResultCode::OperationsError,
-            message: format!("Unable to get schema: {:#}", e),
-        })?;
-        let mut results = Vec::new();
-        if let Some(users) = users {
-            results.extend(convert_users_to_ldap_op(
-                users,
-                &request.attrs,
-                &self.ldap_info,
-                &schema,
-            ));
-        }
-        if let Some(groups) = groups {
-            results.extend(convert_groups_to_ldap_op(
-                groups,
-                &request.attrs,
-                &self.ldap_info,
-                &backend_handler.user_filter,
-            ));
-        }
-        if results.is_empty() || matches!(results[results.len() - 1], LdapOp::SearchResultEntry(_))
+        let user_search_filter = self.search_filters.get(&user_info.user);
+        if let Some(entry) = self
+            .get_container_entry(&backend_handler, request, user_search_filter)
+            .await?
         {
-            results.push(make_search_success());
+            return Ok(Box::new(vec![entry, make_search_success()].into_iter()));
         }
-        Ok(results)
+        let (users, groups) = self
+            .do_search_internal(&backend_handler, request, user_search_filter)
+            .await?;
+        let groups = match groups {
+            Some(mut groups) if !groups.is_empty() => {
+                if let Some(visible_users) = self
+                    .get_visible_users(&backend_handler, &request.base, user_search_filter)
+                    .await?
+                {
+                    for group in groups.iter_mut() {
+                        group.users.retain(|user| visible_users.contains(user));
+                    }
+                }
+                Some(groups)
+            }
+            groups => groups,
+        };
+
+        let schema = backend_handler.get_schema().await.map_err(|e| {
+            LdapError::internal(
+                LdapResultCode::OperationsError,
+                "Unable to get the schema".to_string(),
+                e,
+            )
+        })?;
+        // The unprivileged users don't even learn which accounts have a password.
+        let password_placeholder = self
+            .ldap_info
+            .user_password_placeholder
+            .as_deref()
+            .filter(|_| user_info.can_read_all());
+        let entries = convert_users_to_ldap_op(
+            users.unwrap_or_default(),
+            request.attrs.clone(),
+            &self.ldap_info,
+            schema.clone(),
+            password_placeholder,
+        )
+        .chain(convert_groups_to_ldap_op(
+            groups.unwrap_or_default(),
+            request.attrs.clone(),
+            &self.ldap_info,
+            backend_handler.user_filter.clone(),
+            schema,
+        ));
+        Ok(limit_search_results(entries, request.sizelimit))
     }
 
     async fn do_create_user(&self, request: LdapAddRequest) -> LdapResult<Vec<LdapOp>> {
@@ -646,10 +1529,7 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
             std::str::from_utf8(val)
                 .map_err(|e| LdapError {
                     code: LdapResultCode::ConstraintViolation,
-                    message: format!(
-                        "Attribute value is invalid UTF-8: {:#?} (value {:?})",
-                        e, val
-                    ),
+                    message: format!("Attribute value is invalid UTF-8: {}", e),
                 })
                 .map(str::to_owned)
         }
@@ -669,6 +1549,7 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 display_name: get_attribute("cn").transpose()?,
                 first_name: get_attribute("givenname").transpose()?,
                 last_name: get_attribute("sn").transpose()?,
+                locale: get_attribute("preferredlanguage").transpose()?,
                 avatar: attributes
                     .get("avatar")
                     .map(Vec::as_slice)
@@ -676,17 +1557,345 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                     .transpose()
                     .map_err(|e| LdapError {
                         code: LdapResultCode::ConstraintViolation,
-                        message: format!("Invalid JPEG photo: {:#?}", e),
+                        message: format!("Invalid JPEG photo: {}", e),
                     })?,
             })
             .await
-            .map_err(|e| LdapError {
-                code: LdapResultCode::OperationsError,
-                message: format!("Could not create user: {:#?}", e),
+            .map_err(|e| make_creation_error("user", e))?;
+        Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
+    }
+
+    async fn handle_modify_dn_request(
+        &mut self,
+        request: &LdapModifyDNRequest,
+    ) -> LdapResult<Vec<LdapOp>> {
+        let backend_handler = self
+            .user_info
+            .as_ref()
+            .and_then(|u| self.backend_handler.get_admin_handler(u))
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: "Unauthorized write".to_string(),
+            })?;
+        let dn = request.dn.to_ascii_lowercase();
+        let dn_parts = parse_distinguished_name(&dn)?;
+        if let Some(new_superior) = &request.new_superior {
+            // Entries can only be renamed within their own organizational unit.
+            if parse_distinguished_name(&new_superior.to_ascii_lowercase())? != dn_parts[1..] {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: format!(
+                        r#"Moving "{}" under "{}" is not supported"#,
+                        request.dn, new_superior
+                    ),
+                });
+            }
+        }
+        // The old RDN value is always removed: the naming attributes are single-valued, so
+        // `deleteoldrdn` has no effect.
+        let (rdn_attribute, rdn_value) = parse_rdn(&request.newrdn)?;
+        if let Ok(user_id) = get_user_id_from_distinguished_name(
+            &dn,
+            &self.ldap_info.base_dn,
+            &self.ldap_info.base_dn_str,
+        ) {
+            if rdn_attribute != "uid" {
+                return Err(LdapError {
+                    code: LdapResultCode::NamingViolation,
+                    message: format!(r#"Users must be named by "uid", got "{}""#, rdn_attribute),
+                });
+            }
+            let new_user_id = UserId::new(&rdn_value);
+            if new_user_id != user_id
+                && backend_handler.get_user_details(&new_user_id).await.is_ok()
+            {
+                return Err(LdapError {
+                    code: LdapResultCode::EntryAlreadyExists,
+                    message: format!(r#"User "{}" already exists"#, new_user_id),
+                });
+            }
+            backend_handler
+                .rename_user(&user_id, new_user_id)
+                .await
+                .map_err(|e| {
+                    LdapError::from_domain_error(
+                        LdapResultCode::NoSuchObject,
+                        "Could not rename user".to_string(),
+                        &e,
+                    )
+                })?;
+        } else if let Ok(group_name) = get_group_id_from_distinguished_name(
+            &dn,
+            &self.ldap_info.base_dn,
+            &self.ldap_info.base_dn_str,
+        ) {
+            if rdn_attribute != "cn" {
+                return Err(LdapError {
+                    code: LdapResultCode::NamingViolation,
+                    message: format!(r#"Groups must be named by "cn", got "{}""#, rdn_attribute),
+                });
+            }
+            let groups = backend_handler.list_groups(None).await.map_err(|e| {
+                LdapError::internal(
+                    LdapResultCode::OperationsError,
+                    "Internal error while listing the groups".to_string(),
+                    e,
+                )
+            })?;
+            let group = groups
+                .iter()
+                .find(|g| g.display_name.to_ascii_lowercase() == group_name)
+                .ok_or_else(|| LdapError {
+                    code: LdapResultCode::NoSuchObject,
+                    message: format!(r#"No such group: "{}""#, group_name),
+                })?;
+            if groups
+                .iter()
+                .any(|g| g.id != group.id && g.display_name.eq_ignore_ascii_case(&rdn_value))
+            {
+                return Err(LdapError {
+                    code: LdapResultCode::EntryAlreadyExists,
+                    message: format!(r#"Group "{}" already exists"#, rdn_value),
+                });
+            }
+            if self.backend_handler.is_admin_group(&group.display_name) {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: "Cannot rename the admin group".to_string(),
+                });
+            }
+            backend_handler
+                .update_group(UpdateGroupRequest {
+                    group_id: group.id,
+                    display_name: Some(rdn_value),
+                    email: None,
+                    description: None,
+                    delete_attributes: vec![],
+                    insert_attributes: vec![],
+                })
+                .await
+                .map_err(|e| {
+                    LdapError::from_domain_error(
+                        LdapResultCode::OperationsError,
+                        "Could not rename group".to_string(),
+                        &e,
+                    )
+                })?;
+        } else {
+            return Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: format!(
+                    r#"Only users and groups can be renamed, got "{}""#,
+                    request.dn
+                ),
+            });
+        }
+        Ok(vec![make_modify_dn_response(
+            LdapResultCode::Success,
+            String::new(),
+        )])
+    }
+
+    async fn do_modify_dn_request(&mut self, request: &LdapModifyDNRequest) -> Vec<LdapOp> {
+        self.handle_modify_dn_request(request)
+            .await
+            .unwrap_or_else(|e: LdapError| vec![make_modify_dn_response(e.code, e.message)])
+    }
+
+    async fn do_create_group(&self, request: LdapAddRequest) -> LdapResult<Vec<LdapOp>> {
+        let user_info = self
+            .user_info
+            .as_ref()
+            .filter(|u| self.backend_handler.get_admin_handler(u).is_some())
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: "Unauthorized write".to_string(),
+            })?;
+        get_group_id_from_distinguished_name(
+            &request.dn.to_ascii_lowercase(),
+            &self.ldap_info.base_dn,
+            &self.ldap_info.base_dn_str,
+        )?;
+        // Keep the case of the group name from the original DN.
+        let (_, group_name) = parse_rdn(request.dn.split(',').next().unwrap_or_default())?;
+        let mut members = Vec::new();
+        let mut email = None;
+        let mut description = None;
+        for attribute in &request.attributes {
+            let values = attribute
+                .vals
+                .iter()
+                .map(|v| {
+                    std::str::from_utf8(v).map_err(|e| LdapError {
+                        code: LdapResultCode::ConstraintViolation,
+                        message: format!("Value of `{}` is invalid UTF-8: {}", attribute.atype, e),
+                    })
+                })
+                .collect::<LdapResult<Vec<_>>>()?;
+            match attribute.atype.to_ascii_lowercase().as_str() {
+                "member" | "uniquemember" => {
+                    for value in values {
+                        members.push(get_user_id_from_distinguished_name(
+                            &value.to_ascii_lowercase(),
+                            &self.ldap_info.base_dn,
+                            &self.ldap_info.base_dn_str,
+                        )?);
+                    }
+                }
+                "mail" => email = values.first().map(|v| v.to_string()),
+                "description" => description = values.first().map(|v| v.to_string()),
+                _ => (),
+            }
+        }
+        // The group is only created if its attributes and members can be set too.
+        let transaction = self
+            .backend_handler
+            .begin_transaction()
+            .await
+            .map_err(|e| {
+                LdapError::internal(
+                    LdapResultCode::OperationsError,
+                    "Unable to start a transaction".to_string(),
+                    e,
+                )
             })?;
+        let backend_handler = transaction
+            .get_admin_handler(user_info)
+            .expect("The permissions were checked");
+        let group_id = backend_handler
+            .create_group(&group_name)
+            .await
+            .map_err(|e| make_creation_error("group", e))?;
+        if email.is_some() || description.is_some() {
+            backend_handler
+                .update_group(UpdateGroupRequest {
+                    group_id,
+                    display_name: None,
+                    email,
+                    description,
+                    delete_attributes: vec![],
+                    insert_attributes: vec![],
+                })
+                .await
+                .map_err(|e| {
+                    LdapError::from_domain_error(
+                        LdapResultCode::OperationsError,
+                        "Could not set group attributes".to_string(),
+                        &e,
+                    )
+                })?;
+        }
+        for member in members {
+            backend_handler
+                .add_user_to_group(&member, group_id)
+                .await
+                .map_err(|e| {
+                    LdapError::from_domain_error(
+                        LdapResultCode::OperationsError,
+                        format!("Could not add {} to the group", member),
+                        &e,
+                    )
+                })?;
+        }
+        transaction.commit_transaction().await.map_err(|e| {
+            LdapError::internal(
+                LdapResultCode::OperationsError,
+                "Unable to commit the group creation".to_string(),
+                e,
+            )
+        })?;
         Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
     }
 
+    async fn do_add(&self, request: LdapAddRequest) -> LdapResult<Vec<LdapOp>> {
+        let is_group = parse_distinguished_name(&request.dn.to_ascii_lowercase())?
+            .get(1)
+            .map(|ou| ou == &("ou".to_string(), "groups".to_string()))
+            .unwrap_or(false);
+        if is_group {
+            self.do_create_group(request).await
+        } else {
+            self.do_create_user(request).await
+        }
+    }
+
+    async fn do_delete(&self, dn: &str) -> LdapResult<Vec<LdapOp>> {
+        let credentials = self.user_info.as_ref().ok_or_else(|| LdapError {
+            code: LdapResultCode::InsufficentAccessRights,
+            message: "No user currently bound".to_string(),
+        })?;
+        let backend_handler = self
+            .backend_handler
+            .get_admin_handler(credentials)
+            .ok_or_else(|| LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: "Unauthorized write".to_string(),
+            })?;
+        let dn = dn.to_ascii_lowercase();
+        if let Ok(user_id) = get_user_id_from_distinguished_name(
+            &dn,
+            &self.ldap_info.base_dn,
+            &self.ldap_info.base_dn_str,
+        ) {
+            if user_id == credentials.user {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: "Cannot delete current user".to_string(),
+                });
+            }
+            backend_handler.delete_user(&user_id).await.map_err(|e| {
+                LdapError::from_domain_error(
+                    LdapResultCode::NoSuchObject,
+                    "Could not delete user".to_string(),
+                    &e,
+                )
+            })?;
+        } else if let Ok(group_name) = get_group_id_from_distinguished_name(
+            &dn,
+            &self.ldap_info.base_dn,
+            &self.ldap_info.base_dn_str,
+        ) {
+            let group = backend_handler
+                .list_groups(None)
+                .await
+                .map_err(|e| {
+                    LdapError::internal(
+                        LdapResultCode::OperationsError,
+                        "Internal error while listing the groups".to_string(),
+                        e,
+                    )
+                })?
+                .into_iter()
+                .find(|g| g.display_name.to_ascii_lowercase() == group_name)
+                .ok_or_else(|| LdapError {
+                    code: LdapResultCode::NoSuchObject,
+                    message: format!(r#"No such group: "{}""#, group_name),
+                })?;
+            if self.backend_handler.is_admin_group(&group.display_name) {
+                return Err(LdapError {
+                    code: LdapResultCode::UnwillingToPerform,
+                    message: "Cannot delete the admin group".to_string(),
+                });
+            }
+            backend_handler.delete_group(group.id).await.map_err(|e| {
+                LdapError::from_domain_error(
+                    LdapResultCode::OperationsError,
+                    "Could not delete group".to_string(),
+                    &e,
+                )
+            })?;
+        } else {
+            return Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: format!(r#"Only users and groups can be deleted, got "{}""#, dn),
+            });
+        }
+        Ok(vec![make_del_response(
+            LdapResultCode::Success,
+            String::new(),
+        )])
+    }
+
     pub async fn do_compare(&mut self, request: LdapCompareRequest) -> LdapResult<Vec<LdapOp>> {
         let req = make_search_request::<String>(
             &self.ldap_info.base_dn_str,
@@ -736,7 +1945,141 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
         }
     }
 
+    /// The error response to a request whose operation is disabled in the configuration.
+    fn check_operation_enabled(&self, ldap_op: &LdapOp) -> Option<LdapOp> {
+        let (_, name) = get_operation_class(ldap_op)
+            .filter(|(operation, _)| self.disabled_operations.contains(operation))?;
+        debug!("Refusing the disabled {} operation", name);
+        make_error_response(
+            ldap_op,
+            LdapResultCode::UnwillingToPerform,
+            format!("The {} operations are disabled on this server", name),
+        )
+    }
+
+    pub async fn handle_ldap_message_with_controls(
+        &mut self,
+        ldap_op: LdapOp,
+        controls: &[LdapControl],
+    ) -> Option<Vec<LdapOp>> {
+        if let Err(e) = check_critical_controls(controls) {
+            if let Some(response) = make_error_response(&ldap_op, e.code, e.message) {
+                return Some(vec![response]);
+            }
+        }
+        self.handle_ldap_message(ldap_op).await
+    }
+
+    /// Same as `handle_ldap_message_with_controls`, but the search result entries are converted
+    /// while they are sent, instead of all at once. They are still read from the database at once.
+    pub async fn handle_ldap_message_lazy(
+        &mut self,
+        ldap_op: LdapOp,
+        controls: &[LdapControl],
+    ) -> Option<LdapResponses<'_>> {
+        match self.operation_timeout {
+            Some(timeout) if matches!(ldap_op, LdapOp::SearchRequest(_)) => {
+                run_search_with_timeout(
+                    timeout,
+                    self.handle_ldap_message_without_timeout(ldap_op, controls),
+                )
+                .await
+            }
+            _ => {
+                self.handle_ldap_message_without_timeout(ldap_op, controls)
+                    .await
+            }
+        }
+    }
+
+    /// Same as `handle_ldap_message_lazy`, for a request with the assertion (RFC 4528),
+    /// pre-read or post-read (RFC 4527) controls, and returns the response controls. Only the
+    /// modify requests support them: on the other operations, critical controls fail and
+    /// non-critical ones are ignored.
+    pub async fn handle_ldap_message_with_request_controls(
+        &mut self,
+        ldap_op: LdapOp,
+        controls: &[LdapControl],
+        request_controls: &RequestControls,
+    ) -> Option<(LdapResponses<'_>, ResponseExtensions)> {
+        if let Some(response) = self.check_operation_enabled(&ldap_op) {
+            return Some((
+                Box::new(std::iter::once(response)),
+                ResponseExtensions::default(),
+            ));
+        }
+        if let Some(oid) = &request_controls.unavailable_critical {
+            if let Some(response) = make_error_response(
+                &ldap_op,
+                LdapResultCode::UnavailableCriticalExtension,
+                format!("Unsupported critical control: {}", oid),
+            ) {
+                return Some((
+                    Box::new(std::iter::once(response)),
+                    ResponseExtensions::default(),
+                ));
+            }
+        }
+        let unsupported = match ldap_op {
+            LdapOp::ModifyRequest(request) => {
+                let modify_response = |e: LdapError| vec![make_modify_response(e.code, e.message)];
+                let (responses, extensions) = self
+                    .do_modify_request_with_controls(request, controls, request_controls)
+                    .await
+                    .unwrap_or_else(|e| (modify_response(e), ResponseExtensions::default()));
+                return Some((Box::new(responses.into_iter()), extensions));
+            }
+            ref ldap_op if request_controls.is_critical() => make_error_response(
+                ldap_op,
+                LdapResultCode::UnavailableCriticalExtension,
+                "The assertion, pre-read and post-read controls are only supported on modify \
+                 requests"
+                    .to_string(),
+            ),
+            _ => {
+                debug!("Ignoring non-critical request controls");
+                None
+            }
+        };
+        match unsupported {
+            Some(response) => Some((
+                Box::new(std::iter::once(response)),
+                ResponseExtensions::default(),
+            )),
+            None => self
+                .handle_ldap_message_lazy(ldap_op, controls)
+                .await
+                .map(|responses| (responses, ResponseExtensions::default())),
+        }
+    }
+
+    async fn handle_ldap_message_without_timeout(
+        &mut self,
+        ldap_op: LdapOp,
+        controls: &[LdapControl],
+    ) -> Option<LdapResponses<'_>> {
+        if let Some(response) = self.check_operation_enabled(&ldap_op) {
+            return Some(Box::new(std::iter::once(response)));
+        }
+        match ldap_op {
+            LdapOp::SearchRequest(request) if check_critical_controls(controls).is_ok() => Some(
+                self.do_search_or_dse_lazy(&request)
+                    .await
+                    .unwrap_or_else(|e: LdapError| {
+                        Box::new(std::iter::once(make_search_error(e.code, e.message)))
+                    }),
+            ),
+            op => self
+                .handle_ldap_message_with_controls(op, controls)
+                .await
+                .map(|responses| Box::new(responses.into_iter()) as LdapResponses),
+        }
+    }
+
     pub async fn handle_ldap_message(&mut self, ldap_op: LdapOp) -> Option<Vec<LdapOp>> {
+        if let Some(response) = self.check_operation_enabled(&ldap_op) {
+            return Some(vec![response]);
+        }
         Some(match ldap_op {
             LdapOp::BindRequest(request) => {
                 let (code, message) = self.do_bind(&request).await;
@@ -759,19 +2102,27 @@ impl<Backend: BackendHandler + LoginHandler + OpaqueHandler> LdapHandler<Backend
                 // No need to notify on unbind (per rfc4511)
                 return None;
             }
+            // Abandon never gets a response. Requests that are still being sent are cancelled by the
+            // server loop, otherwise there is nothing left to abandon.
+            LdapOp::AbandonRequest(_) => vec![],
             LdapOp::ModifyRequest(request) => self.do_modify_request(&request).await,
+            LdapOp::ModifyDNRequest(request) => self.do_modify_dn_request(&request).await,
             LdapOp::ExtendedRequest(request) => self.do_extended_request(&request).await,
             LdapOp::AddRequest(request) => self
-                .do_create_user(request)
+                .do_add(request)
                 .await
                 .unwrap_or_else(|e: LdapError| vec![make_add_error(e.code, e.message)]),
+            LdapOp::DelRequest(dn) => self
+                .do_delete(&dn)
+                .await
+                .unwrap_or_else(|e: LdapError| vec![make_del_response(e.code, e.message)]),
             LdapOp::CompareRequest(request) => self
                 .do_compare(request)
                 .await
-                .unwrap_or_else(|e: LdapError| vec![make_search_error(e.code, e.message)]),
+                .unwrap_or_else(|e: LdapError| vec![make_compare_response(e.code, e.message)]),
             op => vec![make_extended_response(
                 LdapResultCode::UnwillingToPerform,
-                format!("Unsupported operation: {:#?}", op),
+                format!("Unsupported operation: {}", get_operation_name(&op)),
             )],
         })
     }
@@ -782,11 +2133,16 @@ mod tests {
     use super::*;
     use crate::{
         domain::{handler::*, types::*},
-        infra::test_utils::{setup_default_schema, MockTestBackendHandler},
+        infra::{
+            ldap_codec::Assertion,
+            test_utils::{setup_default_schema, MockTestBackendHandler},
+        },
         uuid,
     };
     use chrono::TimeZone;
-    use ldap3_proto::proto::{LdapDerefAliases, LdapSearchScope, LdapSubstringFilter};
+    use ldap3_proto::proto::{
+        LdapDerefAliases, LdapMatchingRuleAssertion, LdapSearchScope, LdapSubstringFilter,
+    };
     use mockall::predicate::eq;
     use std::collections::HashSet;
     use tokio;
@@ -808,6 +2164,14 @@ mod tests {
     async fn setup_bound_handler_with_group(
         mut mock: MockTestBackendHandler,
         group: &str,
+    ) -> LdapHandler<MockTestBackendHandler> {
+        setup_default_schema(&mut mock);
+        setup_bound_handler_with_custom_schema(mock, group).await
+    }
+
+    async fn setup_bound_handler_with_custom_schema(
+        mut mock: MockTestBackendHandler,
+        group: &str,
     ) -> LdapHandler<MockTestBackendHandler> {
         mock.expect_bind()
             .with(eq(BindRequest {
@@ -825,10 +2189,11 @@ mod tests {
                     display_name: group,
                     creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                     uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    email: None,
+                    description: None,
                 });
                 Ok(set)
             });
-        setup_default_schema(&mut mock);
         let mut ldap_handler = LdapHandler::new_for_tests(mock, "dc=Example,dc=com");
         let request = LdapBindRequest {
             dn: "uid=test,ou=people,dc=example,dc=coM".to_string(),
@@ -892,6 +2257,167 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_bind_dn_case() {
+        let make_mock = || {
+            let mut mock = MockTestBackendHandler::new();
+            mock.expect_bind()
+                .with(eq(crate::domain::handler::BindRequest {
+                    name: UserId::new("bob"),
+                    password: "pass".to_string(),
+                }))
+                .return_const(Ok(()));
+            mock.expect_get_user_groups()
+                .with(eq(UserId::new("bob")))
+                .returning(|_| Ok(HashSet::new()));
+            mock
+        };
+        async fn bind(
+            ldap_handler: &mut LdapHandler<MockTestBackendHandler>,
+            dn: &str,
+        ) -> LdapResultCode {
+            let request = LdapBindRequest {
+                dn: dn.to_string(),
+                cred: LdapBindCred::Simple("pass".to_string()),
+            };
+            ldap_handler.do_bind(&request).await.0
+        }
+        let dns = [
+            "uid=bob,ou=people,dc=example,dc=com",
+            "UID=bob,OU=People,DC=Example,DC=COM",
+            "Uid=bob, ou=PEOPLE, dc=example, dc=com",
+            "uid=Bob,ou=people,dc=example,dc=com",
+            "cn=BOB,ou=people,dc=example,dc=com",
+        ];
+        let mut ldap_handler = LdapHandler::new_for_tests(make_mock(), "dc=example,dc=com");
+        for dn in dns {
+            assert_eq!(
+                bind(&mut ldap_handler, dn).await,
+                LdapResultCode::Success,
+                "{}",
+                dn
+            );
+        }
+        // When tightened, the user ID must have the stored case.
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(make_mock()),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions {
+                case_sensitive_bind_dn: true,
+                ..Default::default()
+            },
+        );
+        for (dn, code) in dns.into_iter().zip([
+            LdapResultCode::Success,
+            LdapResultCode::Success,
+            LdapResultCode::Success,
+            LdapResultCode::InvalidCredentials,
+            LdapResultCode::InvalidCredentials,
+        ]) {
+            assert_eq!(bind(&mut ldap_handler, dn).await, code, "{}", dn);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_login_banner() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_bind().returning(|request| {
+            if request.password == "pass" {
+                Ok(())
+            } else {
+                Err(DomainError::AuthenticationError(
+                    "Wrong password".to_string(),
+                ))
+            }
+        });
+        mock.expect_get_user_groups()
+            .returning(|_| Ok(HashSet::new()));
+        let mut ldap_handler = LdapHandler::new_for_tests(mock, "dc=example,dc=com")
+            .with_login_banner(Some("Authorized use only".to_string()));
+        let bind = |password: &str| LdapBindRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            cred: LdapBindCred::Simple(password.to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_bind(&bind("pass")).await,
+            (LdapResultCode::Success, "Authorized use only".to_string())
+        );
+        assert_eq!(
+            ldap_handler.do_bind(&bind("wrong")).await,
+            (
+                LdapResultCode::InvalidCredentials,
+                "Invalid credentials".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_operation_timeout() {
+        let timeout = std::time::Duration::from_millis(10);
+        let slow_operation = || async {
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            Some(Box::new(std::iter::empty()) as LdapResponses<'static>)
+        };
+        let start = std::time::Instant::now();
+        assert_eq!(
+            run_search_with_timeout(timeout, slow_operation())
+                .await
+                .map(|responses| responses.collect::<Vec<_>>()),
+            Some(vec![make_search_error(
+                LdapResultCode::TimeLimitExceeded,
+                "The search took longer than 10ms".to_string()
+            )])
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_operation_within_timeout() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_bind().times(1).return_once(|_| Ok(()));
+        mock.expect_get_user_groups()
+            .return_once(|_| Ok(HashSet::new()));
+        let mut ldap_handler = LdapHandler::new_for_tests(mock, "dc=example,dc=com")
+            .with_operation_timeout(Some(std::time::Duration::from_secs(10)));
+        let request = LdapOp::BindRequest(LdapBindRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            cred: LdapBindCred::Simple("pass".to_string()),
+        });
+        let responses = ldap_handler
+            .handle_ldap_message_lazy(request, &[])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            responses.as_slice(),
+            [LdapOp::BindResponse(LdapBindResponse {
+                res: LdapResultOp {
+                    code: LdapResultCode::Success,
+                    ..
+                },
+                ..
+            })]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bind_during_first_run() {
+        let first_run = FirstRunGate::new(Some(UserId::new("admin")));
+        let mut ldap_handler =
+            LdapHandler::new_for_tests(MockTestBackendHandler::new(), "dc=example,dc=com")
+                .with_first_run_gate(first_run);
+        let request = LdapBindRequest {
+            dn: "uid=admin,ou=people,dc=example,dc=com".to_string(),
+            cred: LdapBindCred::Simple("password".to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_bind(&request).await.0,
+            LdapResultCode::UnwillingToPerform
+        );
+    }
+
     #[tokio::test]
     async fn test_admin_bind() {
         let mut mock = MockTestBackendHandler::new();
@@ -911,6 +2437,8 @@ mod tests {
                     display_name: "lldap_admin".to_string(),
                     creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                     uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    email: None,
+                    description: None,
                 });
                 Ok(set)
             });
@@ -997,6 +2525,8 @@ mod tests {
                         display_name: "rockstars".to_string(),
                         creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                         uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                        email: None,
+                        description: None,
                     }]),
                 }])
             });
@@ -1022,8 +2552,76 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_search_user_as_scope() {
-        let mut mock = MockTestBackendHandler::new();
+    async fn test_membership_value_format() {
+        use crate::infra::configuration::MembershipValueFormat;
+        let make_mock = || {
+            let mut mock = MockTestBackendHandler::new();
+            mock.expect_list_users().times(1).return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob"),
+                        ..Default::default()
+                    },
+                    groups: Some(vec![GroupDetails {
+                        group_id: GroupId(42),
+                        display_name: "rockstars".to_string(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                        email: None,
+                        description: None,
+                    }]),
+                }])
+            });
+            mock.expect_list_groups().times(1).return_once(|_| {
+                Ok(vec![Group {
+                    id: GroupId(42),
+                    display_name: "rockstars".to_string(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    users: vec![UserId::new("bob")],
+                    attributes: Vec::new(),
+                    uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                    email: None,
+                    description: None,
+                }])
+            });
+            mock
+        };
+        let get_values = |response: LdapResult<Vec<LdapOp>>| match &response.unwrap()[0] {
+            LdapOp::SearchResultEntry(entry) => entry.attributes[0].vals.clone(),
+            op => panic!("Unexpected response: {:?}", op),
+        };
+        for (format, member_of, member) in [
+            (
+                MembershipValueFormat::Dn,
+                &b"cn=rockstars,ou=groups,dc=example,dc=com"[..],
+                &b"uid=bob,ou=people,dc=example,dc=com"[..],
+            ),
+            (MembershipValueFormat::Rdn, b"cn=rockstars", b"uid=bob"),
+            (MembershipValueFormat::Name, b"rockstars", b"bob"),
+        ] {
+            let mut ldap_handler = setup_bound_readonly_handler(make_mock()).await;
+            ldap_handler.ldap_info.membership_value_format = format;
+            let user_request = make_user_search_request(LdapFilter::And(vec![]), vec!["memberOf"]);
+            assert_eq!(
+                get_values(ldap_handler.do_search_or_dse(&user_request).await),
+                vec![member_of.to_vec()],
+                "{:?}",
+                format
+            );
+            let group_request = make_group_search_request(LdapFilter::And(vec![]), vec!["member"]);
+            assert_eq!(
+                get_values(ldap_handler.do_search_or_dse(&group_request).await),
+                vec![member.to_vec()],
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_user_as_scope() {
+        let mut mock = MockTestBackendHandler::new();
         mock.expect_list_users()
             .with(
                 eq(Some(UserRequestFilter::And(vec![
@@ -1132,6 +2730,84 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_user_password_placeholder() {
+        let make_mock = || {
+            let mut mock = MockTestBackendHandler::new();
+            mock.expect_list_users().times(1).return_once(|_, _| {
+                Ok(vec![
+                    UserAndGroups {
+                        user: User {
+                            user_id: UserId::new("bob"),
+                            has_password: true,
+                            ..Default::default()
+                        },
+                        groups: None,
+                    },
+                    UserAndGroups {
+                        user: User {
+                            user_id: UserId::new("john"),
+                            ..Default::default()
+                        },
+                        groups: None,
+                    },
+                ])
+            });
+            mock
+        };
+        let request =
+            make_user_search_request(LdapFilter::And(vec![]), vec!["uid", "userPassword"]);
+        let uid_entry = |uid: &str| {
+            LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                dn: format!("uid={},ou=people,dc=example,dc=com", uid),
+                attributes: vec![LdapPartialAttribute {
+                    atype: "uid".to_string(),
+                    vals: vec![uid.as_bytes().to_vec()],
+                }],
+            })
+        };
+        let without_password = vec![uid_entry("bob"), uid_entry("john"), make_search_success()];
+
+        let mut ldap_handler = setup_bound_admin_handler(make_mock()).await;
+        ldap_handler.ldap_info.user_password_placeholder = Some("{OPAQUE}".to_owned());
+        // Only the users with a password get the placeholder.
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "uid".to_string(),
+                            vals: vec![b"bob".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "userPassword".to_string(),
+                            vals: vec![b"{OPAQUE}".to_vec()],
+                        },
+                    ],
+                }),
+                uid_entry("john"),
+                make_search_success(),
+            ])
+        );
+
+        // Disabled by default.
+        let mut ldap_handler = setup_bound_admin_handler(make_mock()).await;
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(without_password.clone())
+        );
+
+        // Withheld from the unprivileged binds.
+        let mut ldap_handler = setup_bound_handler_with_group(make_mock(), "regular").await;
+        ldap_handler.ldap_info.user_password_placeholder = Some("{OPAQUE}".to_owned());
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(without_password)
+        );
+    }
+
     #[tokio::test]
     async fn test_search_users() {
         use chrono::prelude::*;
@@ -1182,6 +2858,10 @@ mod tests {
                             .with_ymd_and_hms(2014, 7, 8, 9, 10, 11)
                             .unwrap()
                             .naive_utc(),
+                        primary_group: None,
+                        expiry_date: None,
+                        password_modified_date: None,
+                        has_password: false,
                     },
                     groups: None,
                 },
@@ -1311,15 +2991,23 @@ mod tests {
                         id: GroupId(1),
                         display_name: "group_1".to_string(),
                         creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                         users: vec![UserId::new("bob"), UserId::new("john")],
+                        attributes: Vec::new(),
                         uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        email: None,
+                        description: None,
                     },
                     Group {
                         id: GroupId(3),
                         display_name: "BestGroup".to_string(),
                         creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                         users: vec![UserId::new("john")],
+                        attributes: Vec::new(),
                         uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        email: None,
+                        description: None,
                     },
                 ])
             });
@@ -1381,6 +3069,165 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_empty_groups() {
+        let make_mock = || {
+            let mut mock = MockTestBackendHandler::new();
+            mock.expect_list_groups().times(1).return_once(|_| {
+                Ok(vec![
+                    Group {
+                        id: GroupId(1),
+                        display_name: "empty".to_string(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        users: vec![],
+                        attributes: Vec::new(),
+                        uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        email: None,
+                        description: None,
+                    },
+                    Group {
+                        id: GroupId(2),
+                        display_name: "full".to_string(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        users: vec![UserId::new("bob")],
+                        attributes: Vec::new(),
+                        uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        email: None,
+                        description: None,
+                    },
+                ])
+            });
+            mock
+        };
+        let request =
+            make_group_search_request(LdapFilter::And(vec![]), vec!["objectClass", "member"]);
+        let make_entry = |name: &str, object_class: &[u8], members: &[&[u8]]| {
+            let mut attributes = vec![LdapPartialAttribute {
+                atype: "objectClass".to_string(),
+                vals: vec![object_class.to_vec()],
+            }];
+            if !members.is_empty() {
+                attributes.push(LdapPartialAttribute {
+                    atype: "member".to_string(),
+                    vals: members.iter().map(|m| m.to_vec()).collect(),
+                });
+            }
+            LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                dn: format!("cn={},ou=groups,dc=example,dc=com", name),
+                attributes,
+            })
+        };
+        let full_group = make_entry(
+            "full",
+            b"groupOfUniqueNames",
+            &[b"uid=bob,ou=people,dc=example,dc=com"],
+        );
+
+        // By default, the members are omitted.
+        let mut ldap_handler = setup_bound_admin_handler(make_mock()).await;
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                make_entry("empty", b"groupOfUniqueNames", &[]),
+                full_group.clone(),
+                make_search_success(),
+            ])
+        );
+
+        let mut ldap_handler = setup_bound_admin_handler(make_mock()).await;
+        ldap_handler.ldap_info.empty_group_members =
+            crate::infra::configuration::EmptyGroupMembers::Placeholder;
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                make_entry("empty", b"groupOfUniqueNames", &[b"cn=nobody"]),
+                full_group.clone(),
+                make_search_success(),
+            ])
+        );
+
+        let mut ldap_handler = setup_bound_admin_handler(make_mock()).await;
+        ldap_handler.ldap_info.empty_group_members =
+            crate::infra::configuration::EmptyGroupMembers::ExtensibleObject;
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                make_entry("empty", b"extensibleObject", &[]),
+                full_group,
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_groups_mail_and_description() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(Some(true.into())))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![
+                    Group {
+                        id: GroupId(1),
+                        display_name: "group_1".to_string(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        users: vec![],
+                        attributes: Vec::new(),
+                        uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        email: Some("group_1@example.com".to_string()),
+                        description: Some("The first group".to_string()),
+                    },
+                    Group {
+                        id: GroupId(3),
+                        display_name: "BestGroup".to_string(),
+                        creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                        users: vec![],
+                        attributes: Vec::new(),
+                        uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                        email: None,
+                        description: None,
+                    },
+                ])
+            });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request =
+            make_group_search_request(LdapFilter::And(vec![]), vec!["cn", "mail", "description"]);
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec![b"group_1".to_vec()]
+                        },
+                        LdapPartialAttribute {
+                            atype: "mail".to_string(),
+                            vals: vec![b"group_1@example.com".to_vec()]
+                        },
+                        LdapPartialAttribute {
+                            atype: "description".to_string(),
+                            vals: vec![b"The first group".to_vec()]
+                        },
+                    ],
+                }),
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=BestGroup,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "cn".to_string(),
+                        vals: vec![b"BestGroup".to_vec()]
+                    },],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
     #[tokio::test]
     async fn test_search_groups_filter() {
         let mut mock = MockTestBackendHandler::new();
@@ -1409,8 +3256,12 @@ mod tests {
                     display_name: "group_1".to_string(),
                     id: GroupId(1),
                     creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                     users: vec![],
+                    attributes: Vec::new(),
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
                 }])
             });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
@@ -1479,8 +3330,12 @@ mod tests {
                     display_name: "group_1".to_string(),
                     id: GroupId(1),
                     creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                     users: vec![],
+                    attributes: Vec::new(),
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
                 }])
             });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
@@ -1506,6 +3361,51 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_search_group_timestamps() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(Some(GroupRequestFilter::And(vec![true.into()]))))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![Group {
+                    display_name: "group_1".to_string(),
+                    id: GroupId(1),
+                    creation_date: chrono::Utc.timestamp_opt(42, 0).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(84, 0).unwrap().naive_utc(),
+                    users: vec![],
+                    attributes: Vec::new(),
+                    uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
+                }])
+            });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_group_search_request(
+            LdapFilter::And(vec![LdapFilter::Present("modifyTimestamp".to_string())]),
+            vec!["createTimestamp", "modifyTimestamp"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "createTimestamp".to_string(),
+                            vals: vec![b"1970-01-01T00:00:42+00:00".to_vec()]
+                        },
+                        LdapPartialAttribute {
+                            atype: "modifyTimestamp".to_string(),
+                            vals: vec![b"1970-01-01T00:01:24+00:00".to_vec()]
+                        },
+                    ],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
     #[tokio::test]
     async fn test_search_group_as_scope() {
         let mut mock = MockTestBackendHandler::new();
@@ -1575,9 +3475,9 @@ mod tests {
         );
         assert_eq!(
             ldap_handler.do_search_or_dse(&request).await,
-            Err(LdapError{
+            Err(LdapError {
                 code: LdapResultCode::Other,
-                message: r#"Error while listing groups "ou=groups,dc=example,dc=com": Internal error: `Error getting groups`"#.to_string()
+                message: r#"Error while listing groups "ou=groups,dc=example,dc=com""#.to_string()
             })
         );
     }
@@ -1848,8 +3748,12 @@ mod tests {
                     id: GroupId(1),
                     display_name: "group_1".to_string(),
                     creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                     users: vec![UserId::new("bob"), UserId::new("john")],
+                    attributes: Vec::new(),
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
                 }])
             });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
@@ -1898,14 +3802,234 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_search_wildcards() {
+    async fn test_search_gid_number() {
         let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().times(1).return_once(|_, _| {
+            Ok(vec![
+                UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob"),
+                        primary_group: Some(GroupId(3)),
+                        ..Default::default()
+                    },
+                    groups: None,
+                },
+                UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("john"),
+                        ..Default::default()
+                    },
+                    groups: None,
+                },
+            ])
+        });
+        mock.expect_list_groups().times(1).return_once(|_| {
+            Ok(vec![Group {
+                id: GroupId(3),
+                display_name: "group_1".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                users: vec![UserId::new("bob")],
+                attributes: Vec::new(),
+                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                email: None,
+                description: None,
+            }])
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_search_request(
+            "dc=example,dc=com",
+            LdapFilter::And(vec![]),
+            vec!["gidNumber"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "gidNumber".to_string(),
+                        vals: vec![b"3".to_vec()]
+                    }],
+                }),
+                // Users without a primary group don't have a gidNumber.
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=john,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![],
+                }),
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "gidNumber".to_string(),
+                        vals: vec![b"3".to_vec()]
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
 
+    #[tokio::test]
+    async fn test_search_hides_non_ldap_visible_attributes() {
+        let mut mock = MockTestBackendHandler::new();
+        let make_attribute_schema = |name: &str, is_ldap_visible| AttributeSchema {
+            name: name.to_owned(),
+            attribute_type: AttributeType::String,
+            is_list: false,
+            is_visible: true,
+            is_editable: true,
+            is_ldap_visible,
+            is_hardcoded: false,
+        };
+        let schema = Schema {
+            user_attributes: AttributeList {
+                attributes: vec![
+                    make_attribute_schema("first_name", true),
+                    make_attribute_schema("nickname", true),
+                    make_attribute_schema("employee_id", false),
+                ],
+            },
+            group_attributes: AttributeList {
+                attributes: Vec::new(),
+            },
+        };
+        mock.expect_get_schema()
+            .returning(move || Ok(schema.clone()));
         mock.expect_list_users().returning(|_, _| {
             Ok(vec![UserAndGroups {
                 user: User {
                     user_id: UserId::new("bob_1"),
-                    email: "bob@bobmail.bob".to_string(),
+                    attributes: vec![
+                        AttributeValue {
+                            name: "first_name".to_owned(),
+                            value: Serialized::from("Bob"),
+                        },
+                        AttributeValue {
+                            name: "nickname".to_owned(),
+                            value: Serialized::from("Bobby"),
+                        },
+                        AttributeValue {
+                            name: "employee_id".to_owned(),
+                            value: Serialized::from("1234"),
+                        },
+                    ],
+                    ..Default::default()
+                },
+                groups: None,
+            }])
+        });
+        let mut ldap_handler = setup_bound_handler_with_custom_schema(mock, "lldap_admin").await;
+        let request = make_user_search_request(
+            LdapFilter::And(vec![]),
+            vec!["givenName", "nickname", "employee_id"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "givenName".to_string(),
+                            vals: vec![b"Bob".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "nickname".to_string(),
+                            vals: vec![b"Bobby".to_vec()],
+                        },
+                    ],
+                }),
+                make_search_success()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_group_custom_attributes() {
+        let mut mock = MockTestBackendHandler::new();
+        let make_attribute_schema = |name: &str, attribute_type, is_list| AttributeSchema {
+            name: name.to_owned(),
+            attribute_type,
+            is_list,
+            is_visible: true,
+            is_editable: true,
+            is_ldap_visible: true,
+            is_hardcoded: false,
+        };
+        let schema = Schema {
+            user_attributes: AttributeList {
+                attributes: Vec::new(),
+            },
+            group_attributes: AttributeList {
+                attributes: vec![
+                    make_attribute_schema("room", AttributeType::String, false),
+                    make_attribute_schema("floors", AttributeType::Integer, true),
+                ],
+            },
+        };
+        mock.expect_get_schema()
+            .returning(move || Ok(schema.clone()));
+        mock.expect_list_groups().returning(|_| {
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "group_1".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                users: vec![],
+                attributes: vec![
+                    AttributeValue {
+                        name: "floors".to_owned(),
+                        value: Serialized::from(&vec![1i64, 2]),
+                    },
+                    AttributeValue {
+                        name: "room".to_owned(),
+                        value: Serialized::from("1A"),
+                    },
+                ],
+                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                email: None,
+                description: None,
+            }])
+        });
+        let mut ldap_handler = setup_bound_handler_with_custom_schema(mock, "lldap_admin").await;
+        let request = make_group_search_request(
+            LdapFilter::And(vec![]),
+            vec!["cn", "Room", "floors", "unknown"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "cn".to_string(),
+                            vals: vec![b"group_1".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "Room".to_string(),
+                            vals: vec![b"1A".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "floors".to_string(),
+                            vals: vec![b"1".to_vec(), b"2".to_vec()],
+                        },
+                    ],
+                }),
+                make_search_success()
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_wildcards() {
+        let mut mock = MockTestBackendHandler::new();
+
+        mock.expect_list_users().returning(|_, _| {
+            Ok(vec![UserAndGroups {
+                user: User {
+                    user_id: UserId::new("bob_1"),
+                    email: "bob@bobmail.bob".to_string(),
                     display_name: Some("Bôb Böbberson".to_string()),
                     attributes: vec![
                         AttributeValue {
@@ -1930,18 +4054,20 @@ mod tests {
                     id: GroupId(1),
                     display_name: "group_1".to_string(),
                     creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
                     users: vec![UserId::new("bob"), UserId::new("john")],
+                    attributes: Vec::new(),
                     uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
                 }])
             });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
 
         // Test simple wildcard
-        let request =
-            make_search_request("dc=example,dc=com", LdapFilter::And(vec![]), vec!["*", "+"]);
+        let request = make_search_request("dc=example,dc=com", LdapFilter::And(vec![]), vec!["*"]);
 
         // all: "objectclass", "dn", "uid", "mail", "givenname", "sn", "cn"
-        // Operational: "createtimestamp"
 
         let expected_result = Ok(vec![
             LdapOp::SearchResultEntry(LdapSearchResultEntry {
@@ -2047,17 +4173,6 @@ mod tests {
             expected_result
         );
 
-        let request3 = make_search_request(
-            "dc=example,dc=com",
-            LdapFilter::And(vec![]),
-            vec!["*", "+", "+"],
-        );
-
-        assert_eq!(
-            ldap_handler.do_search_or_dse(&request3).await,
-            expected_result
-        );
-
         let request4 =
             make_search_request("dc=example,dc=com", LdapFilter::And(vec![]), vec![""; 0]);
 
@@ -2079,577 +4194,2614 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_search_wrong_base() {
-        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
-        let request = make_search_request(
-            "ou=users,dc=example,dc=com",
-            LdapFilter::And(vec![]),
-            vec!["objectClass"],
-        );
+    async fn test_search_lazy() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().times(2).returning(|_, _| {
+            Ok((0..10_000)
+                .map(|i| UserAndGroups {
+                    user: User {
+                        user_id: UserId::new(&format!("user{}", i)),
+                        ..Default::default()
+                    },
+                    groups: None,
+                })
+                .collect())
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_user_search_request(LdapFilter::And(vec![]), vec!["uid"]);
+        {
+            let mut responses = ldap_handler
+                .handle_ldap_message_lazy(LdapOp::SearchRequest(request.clone()), &[])
+                .await
+                .unwrap();
+            assert_eq!(
+                responses.next(),
+                Some(LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=user0,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "uid".to_string(),
+                        vals: vec![b"user0".to_vec()],
+                    }],
+                }))
+            );
+            // The entries keep the order of the backend, and the result comes last.
+            let mut count = 1;
+            for (i, response) in responses.by_ref().take(9_999).enumerate() {
+                match response {
+                    LdapOp::SearchResultEntry(entry) => assert_eq!(
+                        entry.dn,
+                        format!("uid=user{},ou=people,dc=example,dc=com", i + 1)
+                    ),
+                    response => panic!("Unexpected response: {:?}", response),
+                }
+                count += 1;
+            }
+            assert_eq!(count, 10_000);
+            assert_eq!(responses.next(), Some(make_search_success()));
+            assert_eq!(responses.next(), None);
+        }
+        let responses = ldap_handler
+            .handle_ldap_message_lazy(
+                LdapOp::SearchRequest(LdapSearchRequest {
+                    sizelimit: 10,
+                    ..request
+                }),
+                &[],
+            )
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(responses.len(), 11);
         assert_eq!(
-            ldap_handler.do_search_or_dse(&request).await,
-            Ok(vec![make_search_success()])
+            responses.last(),
+            Some(&make_search_error(
+                LdapResultCode::SizeLimitExceeded,
+                "More than 10 entries match the search".to_string()
+            ))
         );
     }
 
-    #[tokio::test]
-    async fn test_search_unsupported_filters() {
-        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
-        let request = make_user_search_request(
-            LdapFilter::Approx("uid".to_owned(), "value".to_owned()),
-            vec!["objectClass"],
+    #[test]
+    fn test_limit_search_results() {
+        let entries = || {
+            vec![
+                make_search_success(),
+                make_search_success(),
+                make_search_success(),
+            ]
+            .into_iter()
+        };
+        assert_eq!(limit_search_results(entries(), 0).count(), 4);
+        assert_eq!(limit_search_results(entries(), 3).count(), 4);
+        assert_eq!(
+            limit_search_results(entries(), 3).last(),
+            Some(make_search_success())
         );
         assert_eq!(
-            ldap_handler.do_search_or_dse(&request).await,
-            Err(LdapError {
-                code: LdapResultCode::UnwillingToPerform,
-                message: r#"Unsupported user filter: Approx("uid", "value")"#.to_string()
-            })
+            limit_search_results(entries(), 2).last(),
+            Some(make_search_error(
+                LdapResultCode::SizeLimitExceeded,
+                "More than 2 entries match the search".to_string()
+            ))
         );
     }
 
     #[tokio::test]
-    async fn test_password_change() {
+    async fn test_search_requested_attributes() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_get_user_groups()
-            .with(eq(UserId::new("bob")))
-            .returning(|_| Ok(HashSet::new()));
-        use lldap_auth::*;
-        let mut rng = rand::rngs::OsRng;
-        let registration_start_request =
-            opaque::client::registration::start_registration("password".as_bytes(), &mut rng)
-                .unwrap();
-        let request = registration::ClientRegistrationStartRequest {
-            username: "bob".to_string(),
-            registration_start_request: registration_start_request.message,
-        };
-        let start_response = opaque::server::registration::start_registration(
-            &opaque::server::ServerSetup::new(&mut rng),
-            request.registration_start_request,
-            &request.username,
-        )
-        .unwrap();
-        mock.expect_registration_start().times(1).return_once(|_| {
-            Ok(registration::ServerRegistrationStartResponse {
-                server_data: "".to_string(),
-                registration_response: start_response.message,
-            })
+        mock.expect_list_users().returning(|_, _| {
+            Ok(vec![UserAndGroups {
+                user: User {
+                    user_id: UserId::new("bob_1"),
+                    email: "bob@bobmail.bob".to_string(),
+                    uuid: uuid!("b4ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    ..Default::default()
+                },
+                groups: Some(vec![GroupDetails {
+                    group_id: GroupId(1),
+                    display_name: "group_1".to_string(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 0).unwrap().naive_utc(),
+                    uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
+                }]),
+            }])
+        });
+        mock.expect_list_groups().returning(|_| {
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "group_1".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 0).unwrap().naive_utc(),
+                modified_date: chrono::Utc.timestamp_opt(84, 0).unwrap().naive_utc(),
+                users: vec![UserId::new("bob_1")],
+                attributes: Vec::new(),
+                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                email: None,
+                description: None,
+            }])
         });
-        mock.expect_registration_finish()
-            .times(1)
-            .return_once(|_| Ok(()));
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = LdapOp::ExtendedRequest(
-            LdapPasswordModifyRequest {
-                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
-                old_password: None,
-                new_password: Some("password".to_string()),
-            }
-            .into(),
+        let attribute = |atype: &str, value: &str| LdapPartialAttribute {
+            atype: atype.to_string(),
+            vals: vec![value.as_bytes().to_vec()],
+        };
+        fn make_entries(
+            user_attributes: Vec<LdapPartialAttribute>,
+            group_attributes: Vec<LdapPartialAttribute>,
+        ) -> LdapResult<Vec<LdapOp>> {
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
+                    attributes: user_attributes,
+                }),
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: group_attributes,
+                }),
+                make_search_success(),
+            ])
+        }
+        let search = |attributes: Vec<&str>| {
+            make_search_request("dc=example,dc=com", LdapFilter::And(vec![]), attributes)
+        };
+        // No attributes at all, only the DNs.
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&search(vec!["1.1"])).await,
+            make_entries(vec![], vec![])
         );
+        // Only the requested ones.
         assert_eq!(
-            ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
-                LdapResultCode::Success,
-                "".to_string(),
-            )])
+            ldap_handler
+                .do_search_or_dse(&search(vec!["uid", "1.1"]))
+                .await,
+            make_entries(
+                vec![attribute("uid", "bob_1")],
+                vec![attribute("uid", "group_1")]
+            )
+        );
+        let user_operational_attributes = vec![
+            attribute("createtimestamp", "1970-01-01T00:00:00+00:00"),
+            attribute("modifytimestamp", "1970-01-01T00:00:00+00:00"),
+            attribute("entryuuid", "b4ac75e0-2900-3e21-926c-2f732c26b3fc"),
+            attribute("hassubordinates", "FALSE"),
+            attribute("memberof", "cn=group_1,ou=groups,dc=example,dc=com"),
+        ];
+        let group_operational_attributes = vec![
+            attribute("createtimestamp", "1970-01-01T00:00:42+00:00"),
+            attribute("modifytimestamp", "1970-01-01T00:01:24+00:00"),
+            attribute("entryuuid", "04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+            attribute("hassubordinates", "FALSE"),
+        ];
+        // Only the operational attributes.
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&search(vec!["+"])).await,
+            make_entries(
+                user_operational_attributes.clone(),
+                group_operational_attributes.clone()
+            )
+        );
+        // All the attributes, without duplicates.
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&search(vec!["*", "+", "+"]))
+                .await,
+            make_entries(
+                vec![
+                    LdapPartialAttribute {
+                        atype: "objectclass".to_string(),
+                        vals: vec![
+                            b"inetOrgPerson".to_vec(),
+                            b"posixAccount".to_vec(),
+                            b"mailAccount".to_vec(),
+                            b"person".to_vec(),
+                        ],
+                    },
+                    attribute("uid", "bob_1"),
+                    attribute("mail", "bob@bobmail.bob"),
+                    attribute("createtimestamp", "1970-01-01T00:00:00+00:00"),
+                    attribute("entryuuid", "b4ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    attribute("modifytimestamp", "1970-01-01T00:00:00+00:00"),
+                    attribute("hassubordinates", "FALSE"),
+                    attribute("memberof", "cn=group_1,ou=groups,dc=example,dc=com"),
+                ],
+                vec![
+                    attribute("objectclass", "groupOfUniqueNames"),
+                    attribute("uid", "group_1"),
+                    attribute("cn", "group_1"),
+                    attribute("member", "uid=bob_1,ou=people,dc=example,dc=com"),
+                    attribute("uniquemember", "uid=bob_1,ou=people,dc=example,dc=com"),
+                    attribute("entryuuid", "04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    attribute("createtimestamp", "1970-01-01T00:00:42+00:00"),
+                    attribute("modifytimestamp", "1970-01-01T00:01:24+00:00"),
+                    attribute("hassubordinates", "FALSE"),
+                ]
+            )
         );
     }
 
     #[tokio::test]
-    async fn test_password_change_modify_request() {
+    async fn test_search_pwd_changed_time() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_get_user_groups()
-            .with(eq(UserId::new("bob")))
-            .returning(|_| Ok(HashSet::new()));
-        use lldap_auth::*;
-        let mut rng = rand::rngs::OsRng;
-        let registration_start_request =
-            opaque::client::registration::start_registration("password".as_bytes(), &mut rng)
-                .unwrap();
-        let request = registration::ClientRegistrationStartRequest {
-            username: "bob".to_string(),
-            registration_start_request: registration_start_request.message,
-        };
-        let start_response = opaque::server::registration::start_registration(
-            &opaque::server::ServerSetup::new(&mut rng),
-            request.registration_start_request,
-            &request.username,
-        )
-        .unwrap();
-        mock.expect_registration_start().times(1).return_once(|_| {
-            Ok(registration::ServerRegistrationStartResponse {
-                server_data: "".to_string(),
-                registration_response: start_response.message,
-            })
-        });
-        mock.expect_registration_finish()
+        mock.expect_list_users().times(1).return_once(|_, _| {
+            Ok(vec![
+                UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob_1"),
+                        password_modified_date: Some(
+                            Utc.with_ymd_and_hms(2023, 4, 5, 6, 7, 8)
+                                .unwrap()
+                                .naive_utc(),
+                        ),
+                        ..Default::default()
+                    },
+                    groups: None,
+                },
+                UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("jim"),
+                        ..Default::default()
+                    },
+                    groups: None,
+                },
+            ])
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_user_search_request(LdapFilter::And(vec![]), vec!["pwdChangedTime"]);
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob_1,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "pwdChangedTime".to_string(),
+                        vals: vec![b"20230405060708Z".to_vec()],
+                    }],
+                }),
+                // Unknown for the passwords set before the column was added.
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=jim,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_wrong_base() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = make_search_request(
+            "ou=users,dc=example,dc=com",
+            LdapFilter::And(vec![]),
+            vec!["objectClass"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![make_search_success()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_aliased_base() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(true.into())), eq(false))
+            .times(1)
+            .return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob"),
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_search_request(
+            "ou=people,dc=Company,dc=local",
+            LdapFilter::And(vec![]),
+            vec!["uid"],
+        );
+        // Disabled by default.
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![make_search_success()])
+        );
+        ldap_handler.search_base_resolver = SearchBaseResolver::new(
+            &LdapSearchOptions {
+                base_dn_aliases: vec!["dc=company,dc=local".to_string()],
+                ..Default::default()
+            },
+            "dc=example,dc=com",
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "uid".to_string(),
+                        vals: vec![b"bob".to_vec()]
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_default_scope() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(Some(true.into())))
+            .times(1)
+            .return_once(|_| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        ldap_handler.search_base_resolver = SearchBaseResolver::new(
+            &LdapSearchOptions {
+                default_scope: Some(LdapDefaultSearchScope::Groups),
+                ..Default::default()
+            },
+            "dc=example,dc=com",
+        );
+        let mut request = make_search_request("", LdapFilter::And(vec![]), vec!["cn"]);
+        request.scope = LdapSearchScope::Subtree;
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![make_search_success()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_additional_object_classes() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(true.into())), eq(false))
+            .times(1)
+            .return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob"),
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
+        mock.expect_list_groups()
+            .with(eq(Some(true.into())))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![Group {
+                    id: GroupId(1),
+                    display_name: "group_1".to_string(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    users: vec![],
+                    attributes: Vec::new(),
+                    uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
+                }])
+            });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        ldap_handler.ldap_info.additional_user_object_classes = vec!["shadowAccount".to_string()];
+        ldap_handler.ldap_info.additional_group_object_classes = vec!["posixGroup".to_string()];
+        // The configured object classes can be used in filters, regardless of the case.
+        let request = make_search_request(
+            "ou=people,dc=example,dc=com",
+            LdapFilter::Equality("objectClass".to_string(), "shadowaccount".to_string()),
+            vec!["objectClass"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "objectClass".to_string(),
+                        vals: vec![
+                            b"inetOrgPerson".to_vec(),
+                            b"posixAccount".to_vec(),
+                            b"mailAccount".to_vec(),
+                            b"person".to_vec(),
+                            b"shadowAccount".to_vec(),
+                        ]
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+        let request = make_search_request(
+            "ou=groups,dc=example,dc=com",
+            LdapFilter::Equality("objectClass".to_string(), "posixGroup".to_string()),
+            vec!["objectClass"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "objectClass".to_string(),
+                        vals: vec![b"groupOfUniqueNames".to_vec(), b"posixGroup".to_vec()]
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_visible_group() {
+        use crate::{
+            domain::sql_backend_handler::{tests::*, SqlBackendHandler},
+            infra::configuration::ConfigurationBuilder,
+        };
+        let handler = SqlBackendHandler::new(
+            ConfigurationBuilder::for_tests(),
+            get_initialized_db().await,
+        );
+        insert_user(&handler, "bob", "bob00000").await;
+        insert_user(&handler, "admin", "admin000").await;
+        let visible_group = insert_group(&handler, "lldap_ldap_visible").await;
+        let admin_group = insert_group(&handler, "lldap_admin").await;
+        insert_membership(&handler, admin_group, "admin").await;
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(handler.clone()),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions {
+                visible_group: Some("lldap_ldap_visible".to_string()),
+                ..Default::default()
+            },
+        );
+        let bind_request = |name: &str, password: &str| LdapBindRequest {
+            dn: format!("uid={},ou=people,dc=example,dc=com", name),
+            cred: LdapBindCred::Simple(password.to_string()),
+        };
+        let search_request = make_user_search_request(LdapFilter::And(vec![]), vec!["1.1"]);
+        async fn search_users(
+            ldap_handler: &mut LdapHandler<SqlBackendHandler>,
+            request: &LdapSearchRequest,
+        ) -> Vec<String> {
+            ldap_handler
+                .do_search_or_dse(request)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter_map(|op| match op {
+                    LdapOp::SearchResultEntry(entry) => Some(entry.dn),
+                    _ => None,
+                })
+                .collect()
+        }
+        // The users outside of the group are hidden, but the admins are visible.
+        assert_eq!(
+            ldap_handler
+                .do_bind(&bind_request("bob", "bob00000"))
+                .await
+                .0,
+            LdapResultCode::InvalidCredentials
+        );
+        assert_eq!(
+            ldap_handler
+                .do_bind(&bind_request("admin", "admin000"))
+                .await
+                .0,
+            LdapResultCode::Success
+        );
+        assert_eq!(
+            search_users(&mut ldap_handler, &search_request).await,
+            vec!["uid=admin,ou=people,dc=example,dc=com"]
+        );
+        // Adding the user to the group makes them visible.
+        handler
+            .add_user_to_group(&UserId::new("bob"), visible_group)
+            .await
+            .unwrap();
+        assert_eq!(
+            search_users(&mut ldap_handler, &search_request).await,
+            vec![
+                "uid=admin,ou=people,dc=example,dc=com",
+                "uid=bob,ou=people,dc=example,dc=com"
+            ]
+        );
+        assert_eq!(
+            ldap_handler
+                .do_bind(&bind_request("bob", "bob00000"))
+                .await
+                .0,
+            LdapResultCode::Success
+        );
+        // Removing them hides them again.
+        handler
+            .remove_user_from_group(&UserId::new("bob"), visible_group)
+            .await
+            .unwrap();
+        // Search as the admin again, bob can only see himself.
+        ldap_handler
+            .do_bind(&bind_request("admin", "admin000"))
+            .await;
+        assert_eq!(
+            search_users(&mut ldap_handler, &search_request).await,
+            vec!["uid=admin,ou=people,dc=example,dc=com"]
+        );
+        // The hidden users are not listed as members of the groups either.
+        let team = insert_group(&handler, "team").await;
+        insert_membership(&handler, team, "admin").await;
+        insert_membership(&handler, team, "bob").await;
+        let group_request = make_group_search_request(
+            LdapFilter::Equality("cn".to_string(), "team".to_string()),
+            vec!["member", "uniquemember"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&group_request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=team,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "member".to_string(),
+                            vals: vec![b"uid=admin,ou=people,dc=example,dc=com".to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "uniquemember".to_string(),
+                            vals: vec![b"uid=admin,ou=people,dc=example,dc=com".to_vec()],
+                        },
+                    ],
+                }),
+                make_search_success(),
+            ])
+        );
+        assert_eq!(
+            ldap_handler
+                .do_bind(&bind_request("bob", "bob00000"))
+                .await
+                .0,
+            LdapResultCode::InvalidCredentials
+        );
+        // The read-only admins and password managers can bind outside of the group too.
+        for (user, group) in [
+            ("reader", "lldap_read_only_admin"),
+            ("manager", "lldap_password_manager"),
+        ] {
+            insert_user(&handler, user, "pass0000").await;
+            let group = insert_group(&handler, group).await;
+            insert_membership(&handler, group, user).await;
+            assert_eq!(
+                ldap_handler
+                    .do_bind(&bind_request(user, "pass0000"))
+                    .await
+                    .0,
+                LdapResultCode::Success
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_filters() {
+        use crate::{
+            domain::sql_backend_handler::{tests::*, SqlBackendHandler},
+            infra::configuration::ConfigurationBuilder,
+        };
+        let handler = SqlBackendHandler::new(
+            ConfigurationBuilder::for_tests(),
+            get_initialized_db().await,
+        );
+        insert_user(&handler, "app", "app00000").await;
+        let readonly_group = insert_group(&handler, "lldap_strict_readonly").await;
+        insert_membership(&handler, readonly_group, "app").await;
+        for user in ["bob", "john", "patrick"] {
+            insert_user_no_password(&handler, user).await;
+        }
+        let team = insert_group(&handler, "team").await;
+        for user in ["bob", "patrick"] {
+            insert_membership(&handler, team, user).await;
+        }
+        for user in ["bob", "john"] {
+            handler
+                .update_user(UpdateUserRequest {
+                    user_id: UserId::new(user),
+                    email: Some(format!("{}@engineering.example.com", user)),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(handler.clone()),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions {
+                search_filters: HashMap::from([(
+                    "App".to_owned(),
+                    "(mail=*@engineering.example.com)".to_owned(),
+                )]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            ldap_handler
+                .do_bind(&LdapBindRequest {
+                    dn: "uid=app,ou=people,dc=example,dc=com".to_string(),
+                    cred: LdapBindCred::Simple("app00000".to_string()),
+                })
+                .await
+                .0,
+            LdapResultCode::Success
+        );
+        let search = |base: &str, filter: LdapFilter| {
+            let request = make_search_request(base, filter, vec!["1.1"]);
+            let ldap_handler = &ldap_handler;
+            async move {
+                ldap_handler
+                    .do_search_or_dse(&request)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|op| match op {
+                        LdapOp::SearchResultEntry(entry) => Some(entry.dn),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        let permitted = vec![
+            "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+            "uid=john,ou=people,dc=example,dc=com".to_owned(),
+        ];
+        // Whatever the base and the filter, only the permitted users are returned.
+        assert_eq!(
+            search("ou=people,dc=example,dc=com", LdapFilter::And(vec![])).await,
+            permitted
+        );
+        let mut global = search("dc=example,dc=com", LdapFilter::And(vec![])).await;
+        global.retain(|dn| dn.ends_with(",ou=people,dc=example,dc=com"));
+        assert_eq!(global, permitted);
+        assert!(search(
+            "ou=people,dc=example,dc=com",
+            LdapFilter::Equality("uid".to_owned(), "patrick".to_owned())
+        )
+        .await
+        .is_empty());
+        assert!(search(
+            "uid=app,ou=people,dc=example,dc=com",
+            LdapFilter::And(vec![])
+        )
+        .await
+        .is_empty());
+        // The groups are still visible, but only with the permitted members.
+        assert_eq!(
+            search("ou=groups,dc=example,dc=com", LdapFilter::And(vec![])).await,
+            vec![
+                "cn=lldap_strict_readonly,ou=groups,dc=example,dc=com",
+                "cn=team,ou=groups,dc=example,dc=com"
+            ]
+        );
+        let group_request = make_group_search_request(
+            LdapFilter::Equality("cn".to_string(), "team".to_string()),
+            vec!["member"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&group_request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "cn=team,ou=groups,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "member".to_string(),
+                        vals: vec![b"uid=bob,ou=people,dc=example,dc=com".to_vec()],
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_ldap_filter() {
+        assert_eq!(
+            parse_ldap_filter(
+                "(&(objectClass=person)(!(uid=bob))(|(mail=*@example.com)(cn~=Bob)))"
+            )
+            .unwrap(),
+            LdapFilter::And(vec![
+                LdapFilter::Equality("objectClass".to_owned(), "person".to_owned()),
+                LdapFilter::Not(Box::new(LdapFilter::Equality(
+                    "uid".to_owned(),
+                    "bob".to_owned()
+                ))),
+                LdapFilter::Or(vec![
+                    LdapFilter::Substring(
+                        "mail".to_owned(),
+                        LdapSubstringFilter {
+                            initial: None,
+                            any: vec![],
+                            final_: Some("@example.com".to_owned()),
+                        }
+                    ),
+                    LdapFilter::Approx("cn".to_owned(), "Bob".to_owned()),
+                ]),
+            ])
+        );
+        assert_eq!(
+            parse_ldap_filter("department=R\\26D").unwrap(),
+            LdapFilter::Equality("department".to_owned(), "R&D".to_owned())
+        );
+        assert_eq!(
+            parse_ldap_filter("(cn=a*b*c*)").unwrap(),
+            LdapFilter::Substring(
+                "cn".to_owned(),
+                LdapSubstringFilter {
+                    initial: Some("a".to_owned()),
+                    any: vec!["b".to_owned(), "c".to_owned()],
+                    final_: None,
+                }
+            )
+        );
+        assert_eq!(
+            parse_ldap_filter("(mail=*)").unwrap(),
+            LdapFilter::Present("mail".to_owned())
+        );
+        for invalid in [
+            "",
+            "(uid=bob",
+            "(uid=bob))",
+            "(&(uid=bob)",
+            "(age>=3)",
+            "(=bob)",
+        ] {
+            parse_ldap_filter(invalid).unwrap_err();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_subordinates() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(true.into())), eq(false))
+            .times(1)
+            .return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("bob"),
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
+        mock.expect_list_groups()
+            .with(eq(Some(true.into())))
+            .times(1)
+            .return_once(|_| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let make_request = |base: &str| {
+            make_search_request(
+                base,
+                LdapFilter::Present("objectClass".to_string()),
+                vec!["hasSubordinates", "numSubordinates"],
+            )
+        };
+        let make_result = |dn: &str, has: &[u8], num: &[u8]| {
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: dn.to_string(),
+                    attributes: vec![
+                        LdapPartialAttribute {
+                            atype: "hasSubordinates".to_string(),
+                            vals: vec![has.to_vec()],
+                        },
+                        LdapPartialAttribute {
+                            atype: "numSubordinates".to_string(),
+                            vals: vec![num.to_vec()],
+                        },
+                    ],
+                }),
+                make_search_success(),
+            ])
+        };
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_request("dc=example,dc=com"))
+                .await,
+            make_result("dc=example,dc=com", b"TRUE", b"2")
+        );
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_request("ou=people,dc=example,dc=com"))
+                .await,
+            make_result("ou=people,dc=example,dc=com", b"TRUE", b"1")
+        );
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_request("ou=groups,dc=example,dc=com"))
+                .await,
+            make_result("ou=groups,dc=example,dc=com", b"FALSE", b"0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_structural_entries() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let make_entry = |dn: &str, attributes: Vec<(&str, Vec<&str>)>| {
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: dn.to_string(),
+                    attributes: attributes
+                        .into_iter()
+                        .map(|(atype, vals)| LdapPartialAttribute {
+                            atype: atype.to_string(),
+                            vals: vals.into_iter().map(|v| v.as_bytes().to_vec()).collect(),
+                        })
+                        .collect(),
+                }),
+                make_search_success(),
+            ])
+        };
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_search_request(
+                    "dc=example,dc=com",
+                    LdapFilter::Present("objectClass".to_string()),
+                    vec!["objectClass", "dc"],
+                ))
+                .await,
+            make_entry(
+                "dc=example,dc=com",
+                vec![
+                    ("objectClass", vec!["top", "domain", "dcObject"]),
+                    ("dc", vec!["example"]),
+                ]
+            )
+        );
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_search_request(
+                    "ou=people,dc=example,dc=com",
+                    LdapFilter::Equality(
+                        "objectclass".to_string(),
+                        "organizationalUnit".to_string()
+                    ),
+                    Vec::<String>::new(),
+                ))
+                .await,
+            make_entry(
+                "ou=people,dc=example,dc=com",
+                vec![
+                    ("objectClass", vec!["top", "organizationalUnit"]),
+                    ("ou", vec!["people"]),
+                ]
+            )
+        );
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_search_request(
+                    "ou=groups,dc=example,dc=com",
+                    LdapFilter::Present("objectClass".to_string()),
+                    vec!["1.1"],
+                ))
+                .await,
+            make_entry("ou=groups,dc=example,dc=com", vec![])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_unsupported_filters() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = make_user_search_request(
+            LdapFilter::Approx("uid".to_owned(), "value".to_owned()),
+            vec!["objectClass"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: r#"Unsupported user filter: Approx("uid", "value")"#.to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_change() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| Ok(HashSet::new()));
+        use lldap_auth::*;
+        let mut rng = rand::rngs::OsRng;
+        let registration_start_request =
+            opaque::client::registration::start_registration("password".as_bytes(), &mut rng)
+                .unwrap();
+        let request = registration::ClientRegistrationStartRequest {
+            username: "bob".to_string(),
+            registration_start_request: registration_start_request.message,
+        };
+        let start_response = opaque::server::registration::start_registration(
+            &opaque::server::ServerSetup::new(&mut rng),
+            request.registration_start_request,
+            &request.username,
+        )
+        .unwrap();
+        mock.expect_registration_start().times(1).return_once(|_| {
+            Ok(registration::ServerRegistrationStartResponse {
+                server_data: "".to_string(),
+                registration_response: start_response.message,
+            })
+        });
+        mock.expect_registration_finish()
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapOp::ExtendedRequest(
+            LdapPasswordModifyRequest {
+                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
+                old_password: None,
+                new_password: Some("password".to_string()),
+            }
+            .into(),
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::Success,
+                "".to_string(),
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_change_modify_request() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| Ok(HashSet::new()));
+        use lldap_auth::*;
+        let mut rng = rand::rngs::OsRng;
+        let registration_start_request =
+            opaque::client::registration::start_registration("password".as_bytes(), &mut rng)
+                .unwrap();
+        let request = registration::ClientRegistrationStartRequest {
+            username: "bob".to_string(),
+            registration_start_request: registration_start_request.message,
+        };
+        let start_response = opaque::server::registration::start_registration(
+            &opaque::server::ServerSetup::new(&mut rng),
+            request.registration_start_request,
+            &request.username,
+        )
+        .unwrap();
+        mock.expect_registration_start().times(1).return_once(|_| {
+            Ok(registration::ServerRegistrationStartResponse {
+                server_data: "".to_string(),
+                registration_response: start_response.message,
+            })
+        });
+        mock.expect_registration_finish()
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapOp::ModifyRequest(LdapModifyRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            changes: vec![LdapModify {
+                operation: LdapModifyType::Replace,
+                modification: LdapPartialAttribute {
+                    atype: "userPassword".to_owned(),
+                    vals: vec!["password".as_bytes().to_vec()],
+                },
+            }],
+        });
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_modify_response(
+                LdapResultCode::Success,
+                "".to_string(),
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_with_assertion() {
+        use crate::{
+            domain::sql_backend_handler::{tests::*, SqlBackendHandler},
+            infra::configuration::ConfigurationBuilder,
+        };
+        let handler = SqlBackendHandler::new(
+            ConfigurationBuilder::for_tests(),
+            get_initialized_db().await,
+        );
+        insert_user(&handler, "bob", "bob00000").await;
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(handler),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions::default(),
+        );
+        let bind = |password: &str| LdapBindRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            cred: LdapBindCred::Simple(password.to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_bind(&bind("bob00000")).await.0,
+            LdapResultCode::Success
+        );
+        let change_password = |password: &str| {
+            LdapOp::ModifyRequest(LdapModifyRequest {
+                dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                changes: vec![LdapModify {
+                    operation: LdapModifyType::Replace,
+                    modification: LdapPartialAttribute {
+                        atype: "userPassword".to_owned(),
+                        vals: vec![password.as_bytes().to_vec()],
+                    },
+                }],
+            })
+        };
+        let assertion = |mail: &str| RequestControls {
+            assertion: Some(Assertion {
+                criticality: true,
+                filter: LdapFilter::Equality("mail".to_string(), mail.to_string()),
+            }),
+            ..Default::default()
+        };
+        async fn modify(
+            ldap_handler: &mut LdapHandler<SqlBackendHandler>,
+            request: LdapOp,
+            request_controls: &RequestControls,
+        ) -> Vec<LdapOp> {
+            ldap_handler
+                .handle_ldap_message_with_request_controls(request, &[], request_controls)
+                .await
+                .unwrap()
+                .0
+                .collect()
+        }
+
+        assert_eq!(
+            modify(
+                &mut ldap_handler,
+                change_password("new_pass"),
+                &assertion("bob@bob.bob")
+            )
+            .await,
+            vec![make_modify_response(
+                LdapResultCode::Success,
+                "".to_string()
+            )]
+        );
+        assert_eq!(
+            modify(
+                &mut ldap_handler,
+                change_password("other_pass"),
+                &assertion("alice@bob.bob")
+            )
+            .await,
+            vec![make_modify_response(
+                ASSERTION_FAILED,
+                "Assertion failed for `uid=bob,ou=people,dc=example,dc=com`".to_string()
+            )]
+        );
+        // The codec sends `assertionFailed` instead of the result code of the response.
+        assert_eq!(
+            ldap_handler
+                .handle_ldap_message_with_request_controls(
+                    change_password("other_pass"),
+                    &[],
+                    &assertion("alice@bob.bob")
+                )
+                .await
+                .unwrap()
+                .1
+                .result_code,
+            Some(ASSERTION_FAILED_RESULT_CODE)
+        );
+        // Only the first change was applied.
+        assert_eq!(
+            ldap_handler.do_bind(&bind("new_pass")).await.0,
+            LdapResultCode::Success
+        );
+        assert_eq!(
+            ldap_handler.do_bind(&bind("other_pass")).await.0,
+            LdapResultCode::InvalidCredentials
+        );
+        // The other operations don't support it.
+        assert_eq!(
+            modify(
+                &mut ldap_handler,
+                LdapOp::DelRequest("uid=bob,ou=people,dc=example,dc=com".to_string()),
+                &assertion("bob@bob.bob")
+            )
+            .await,
+            vec![make_del_response(
+                LdapResultCode::UnavailableCriticalExtension,
+                "The assertion, pre-read and post-read controls are only supported on modify \
+                 requests"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_with_read_entry() {
+        use crate::{
+            domain::sql_backend_handler::{tests::*, SqlBackendHandler},
+            infra::configuration::ConfigurationBuilder,
+        };
+        let handler = SqlBackendHandler::new(
+            ConfigurationBuilder::for_tests(),
+            get_initialized_db().await,
+        );
+        insert_user(&handler, "bob", "bob00000").await;
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(handler),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions::default(),
+        );
+        let dn = "uid=bob,ou=people,dc=example,dc=com";
+        assert_eq!(
+            ldap_handler
+                .do_bind(&LdapBindRequest {
+                    dn: dn.to_string(),
+                    cred: LdapBindCred::Simple("bob00000".to_string()),
+                })
+                .await
+                .0,
+            LdapResultCode::Success
+        );
+        let change_password = |password: &str| {
+            LdapOp::ModifyRequest(LdapModifyRequest {
+                dn: dn.to_string(),
+                changes: vec![LdapModify {
+                    operation: LdapModifyType::Replace,
+                    modification: LdapPartialAttribute {
+                        atype: "userPassword".to_owned(),
+                        vals: vec![password.as_bytes().to_vec()],
+                    },
+                }],
+            })
+        };
+        let read_entry = |attributes: &[&str]| {
+            Some(ReadEntry {
+                criticality: true,
+                attributes: attributes.iter().map(|a| a.to_string()).collect(),
+            })
+        };
+        let expected_entry = LdapSearchResultEntry {
+            dn: dn.to_string(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "mail".to_string(),
+                vals: vec![b"bob@bob.bob".to_vec()],
+            }],
+        };
+        let (responses, extensions) = ldap_handler
+            .handle_ldap_message_with_request_controls(
+                change_password("new_pass"),
+                &[],
+                &RequestControls {
+                    pre_read: read_entry(&["mail"]),
+                    post_read: read_entry(&["mail"]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            responses.collect::<Vec<_>>(),
+            vec![make_modify_response(
+                LdapResultCode::Success,
+                "".to_string()
+            )]
+        );
+        assert_eq!(
+            extensions.controls,
+            vec![
+                ResponseControl::PreRead(expected_entry.clone()),
+                ResponseControl::PostRead(expected_entry)
+            ]
+        );
+        // A failed modification returns no entry.
+        let (responses, extensions) = ldap_handler
+            .handle_ldap_message_with_request_controls(
+                LdapOp::ModifyRequest(LdapModifyRequest {
+                    dn: dn.to_string(),
+                    changes: vec![LdapModify {
+                        operation: LdapModifyType::Replace,
+                        modification: LdapPartialAttribute {
+                            atype: "mail".to_owned(),
+                            vals: vec![b"bob@example.com".to_vec()],
+                        },
+                    }],
+                }),
+                &[],
+                &RequestControls {
+                    pre_read: read_entry(&["mail"]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            responses.collect::<Vec<_>>().as_slice(),
+            [LdapOp::ModifyResponse(LdapResultOp {
+                code: LdapResultCode::UnwillingToPerform,
+                ..
+            })]
+        ));
+        assert_eq!(extensions, ResponseExtensions::default());
+    }
+
+    #[tokio::test]
+    async fn test_modify_unknown_attribute() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_schema().returning(|| {
+            Ok(Schema {
+                user_attributes: AttributeList {
+                    attributes: vec![AttributeSchema {
+                        name: "nickname".to_owned(),
+                        attribute_type: AttributeType::String,
+                        is_list: false,
+                        is_visible: true,
+                        is_editable: true,
+                        is_ldap_visible: true,
+                        is_hardcoded: false,
+                    }],
+                },
+                group_attributes: AttributeList {
+                    attributes: Vec::new(),
+                },
+            })
+        });
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| Ok(HashSet::new()));
+        mock.expect_update_user()
+            .with(eq(UpdateUserRequest {
+                user_id: UserId::new("bob"),
+                insert_attributes: vec![AttributeValue {
+                    name: "nickname".to_owned(),
+                    value: Serialized::from("Bobby"),
+                }],
+                ..Default::default()
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_handler_with_custom_schema(mock, "lldap_admin").await;
+        let modify = |attribute: &str| LdapModifyRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            changes: vec![LdapModify {
+                operation: LdapModifyType::Replace,
+                modification: LdapPartialAttribute {
+                    atype: attribute.to_owned(),
+                    vals: vec![b"Bobby".to_vec()],
+                },
+            }],
+        };
+        let success = || vec![make_modify_response(LdapResultCode::Success, String::new())];
+        let undefined = |attribute: &str| {
+            vec![make_modify_response(
+                LdapResultCode::UndefinedAttributeType,
+                format!("Unknown attribute: `{}`", attribute),
+            )]
+        };
+
+        // Rejected by default.
+        assert_eq!(
+            ldap_handler.do_modify_request(&modify("nickname")).await,
+            undefined("nickname")
+        );
+        // Unless the attribute is ignored.
+        ldap_handler.ldap_info.ignored_user_attributes = vec!["shoesize".to_owned()];
+        assert_eq!(
+            ldap_handler.do_modify_request(&modify("shoeSize")).await,
+            success()
+        );
+
+        ldap_handler.unknown_modify_attributes = UnknownModifyAttributes::Ignore;
+        assert_eq!(
+            ldap_handler.do_modify_request(&modify("nickname")).await,
+            success()
+        );
+
+        // Stored if it's in the schema.
+        ldap_handler.unknown_modify_attributes = UnknownModifyAttributes::Store;
+        assert_eq!(
+            ldap_handler.do_modify_request(&modify("nickName")).await,
+            success()
+        );
+        assert_eq!(
+            ldap_handler.do_modify_request(&modify("department")).await,
+            undefined("department")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_change_password_manager() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| Ok(HashSet::new()));
+        use lldap_auth::*;
+        let mut rng = rand::rngs::OsRng;
+        let registration_start_request =
+            opaque::client::registration::start_registration("password".as_bytes(), &mut rng)
+                .unwrap();
+        let request = registration::ClientRegistrationStartRequest {
+            username: "bob".to_string(),
+            registration_start_request: registration_start_request.message,
+        };
+        let start_response = opaque::server::registration::start_registration(
+            &opaque::server::ServerSetup::new(&mut rng),
+            request.registration_start_request,
+            &request.username,
+        )
+        .unwrap();
+        mock.expect_registration_start().times(1).return_once(|_| {
+            Ok(registration::ServerRegistrationStartResponse {
+                server_data: "".to_string(),
+                registration_response: start_response.message,
+            })
+        });
+        mock.expect_registration_finish()
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_password_manager_handler(mock).await;
+        let request = LdapOp::ExtendedRequest(
+            LdapPasswordModifyRequest {
+                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
+                old_password: None,
+                new_password: Some("password".to_string()),
+            }
+            .into(),
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::Success,
+                "".to_string(),
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_change_errors() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .returning(|_| Ok(HashSet::new()));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapOp::ExtendedRequest(
+            LdapPasswordModifyRequest {
+                user_identity: None,
+                old_password: None,
+                new_password: None,
+            }
+            .into(),
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::ConstraintViolation,
+                "Missing either user_id or password".to_string(),
+            )])
+        );
+        let request = LdapOp::ExtendedRequest(
+            LdapPasswordModifyRequest {
+                user_identity: Some("uid=bob,ou=groups,ou=people,dc=example,dc=com".to_string()),
+                old_password: None,
+                new_password: Some("password".to_string()),
+            }
+            .into(),
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::InvalidDNSyntax,
+                r#"Invalid username: Unexpected DN format. Got "uid=bob,ou=groups,ou=people,dc=example,dc=com", expected: "uid=id,ou=people,dc=example,dc=com""#.to_string(),
+            )])
+        );
+        let request = LdapOp::ExtendedRequest(LdapExtendedRequest {
+            name: "test".to_string(),
+            value: None,
+        });
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::UnwillingToPerform,
+                "Unsupported extended operation: test".to_string(),
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_change_unauthorized_password_manager() {
+        let mut mock = MockTestBackendHandler::new();
+        let mut groups = HashSet::new();
+        groups.insert(GroupDetails {
+            group_id: GroupId(0),
+            display_name: "lldap_admin".to_string(),
+            creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+            uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+            email: None,
+            description: None,
+        });
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .times(1)
+            .return_once(|_| Ok(groups));
+        let mut ldap_handler = setup_bound_password_manager_handler(mock).await;
+        let request = LdapOp::ExtendedRequest(
+            LdapPasswordModifyRequest {
+                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
+                old_password: Some("pass".to_string()),
+                new_password: Some("password".to_string()),
+            }
+            .into(),
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::InsufficentAccessRights,
+                "User `test` cannot modify the password of user `bob`".to_string(),
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_change_unauthorized_readonly() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_groups()
+            .with(eq(UserId::new("bob")))
+            .times(1)
+            .return_once(|_| Ok(HashSet::new()));
+        let mut ldap_handler = setup_bound_readonly_handler(mock).await;
+        let request = LdapOp::ExtendedRequest(
+            LdapPasswordModifyRequest {
+                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
+                old_password: Some("pass".to_string()),
+                new_password: Some("password".to_string()),
+            }
+            .into(),
+        );
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_extended_response(
+                LdapResultCode::InsufficentAccessRights,
+                "User `test` cannot modify the password of user `bob`".to_string(),
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_root_dse() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = LdapSearchRequest {
+            base: "".to_string(),
+            scope: LdapSearchScope::Base,
+            aliases: LdapDerefAliases::Never,
+            sizelimit: 0,
+            timelimit: 0,
+            typesonly: false,
+            filter: LdapFilter::Present("objectClass".to_string()),
+            attrs: vec!["supportedExtension".to_string()],
+        };
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                root_dse_response("dc=example,dc=com"),
+                make_search_success()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_root_dse_vendor_version() {
+        let entry = match root_dse_response("dc=example,dc=com") {
+            LdapOp::SearchResultEntry(entry) => entry,
+            op => panic!("Expected a search result entry, got {:?}", op),
+        };
+        let vendor_version = entry
+            .attributes
+            .iter()
+            .find(|a| a.atype == "vendorVersion")
+            .unwrap();
+        assert_eq!(
+            vendor_version.vals,
+            vec![format!(
+                "lldap_{} ({})",
+                env!("CARGO_PKG_VERSION"),
+                BUILD_INFO.git_hash
+            )
+            .into_bytes()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_create_user()
+            .with(eq(CreateUserRequest {
+                user_id: UserId::new("bob"),
+                email: "".to_owned(),
+                display_name: Some("Bob".to_string()),
+                ..Default::default()
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapAddRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "cn".to_owned(),
+                vals: vec![b"Bob".to_vec()],
+            }],
+        };
+        assert_eq!(
+            ldap_handler.do_create_user(request).await,
+            Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_wrong_ou() {
+        let ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = LdapAddRequest {
+            dn: "uid=bob,ou=groups,dc=example,dc=com".to_owned(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "cn".to_owned(),
+                vals: vec![b"Bob".to_vec()],
+            }],
+        };
+        assert_eq!(
+            ldap_handler.do_create_user(request).await,
+            Err(LdapError{ code: LdapResultCode::InvalidDNSyntax, message: r#"Unexpected DN format. Got "uid=bob,ou=groups,dc=example,dc=com", expected: "uid=id,ou=people,dc=example,dc=com""#.to_string() })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_limit_exceeded() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_create_user().times(1).return_once(|_| {
+            Err(DomainError::LimitExceeded(
+                "Cannot create more than 2 users".to_owned(),
+            ))
+        });
+        let ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapAddRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+            attributes: vec![],
+        };
+        assert_eq!(
+            ldap_handler
+                .do_create_user(request)
+                .await
+                .map_err(|e| e.code),
+            Err(LdapResultCode::AdminLimitExceeded)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_multiple_object_class() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_create_user()
+            .with(eq(CreateUserRequest {
+                user_id: UserId::new("bob"),
+                email: "".to_owned(),
+                display_name: Some("Bob".to_string()),
+                ..Default::default()
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapAddRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
+            attributes: vec![
+                LdapPartialAttribute {
+                    atype: "cn".to_owned(),
+                    vals: vec![b"Bob".to_vec()],
+                },
+                LdapPartialAttribute {
+                    atype: "objectClass".to_owned(),
+                    vals: vec![
+                        b"top".to_vec(),
+                        b"person".to_vec(),
+                        b"inetOrgPerson".to_vec(),
+                    ],
+                },
+            ],
+        };
+        assert_eq!(
+            ldap_handler.do_create_user(request).await,
+            Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_filter_non_attribute() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users()
+            .with(eq(Some(true.into())), eq(false))
+            .times(1)
+            .return_once(|_, _| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = make_user_search_request(
+            LdapFilter::Present("displayname".to_owned()),
+            vec!["objectClass"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![make_search_success()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_user() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|f, g| {
+            assert_eq!(f, Some(UserRequestFilter::UserId(UserId::new("bob"))));
+            assert!(!g);
+            Ok(vec![UserAndGroups {
+                user: User {
+                    user_id: UserId::new("bob"),
+                    email: "bob@bobmail.bob".to_string(),
+                    ..Default::default()
+                },
+                groups: None,
+            }])
+        });
+        mock.expect_list_groups().returning(|_| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let dn = "uid=bob,ou=people,dc=example,dc=com";
+        let request = LdapCompareRequest {
+            dn: dn.to_string(),
+            atype: "uid".to_owned(),
+            val: b"bob".to_vec(),
+        };
+        assert_eq!(
+            ldap_handler.do_compare(request).await,
+            Ok(vec![LdapOp::CompareResult(LdapResultOp {
+                code: LdapResultCode::CompareTrue,
+                matcheddn: dn.to_string(),
+                message: "".to_string(),
+                referral: vec![],
+            })])
+        );
+        // Non-canonical attribute.
+        let request = LdapCompareRequest {
+            dn: dn.to_string(),
+            atype: "eMail".to_owned(),
+            val: b"bob@bobmail.bob".to_vec(),
+        };
+        assert_eq!(
+            ldap_handler.do_compare(request).await,
+            Ok(vec![LdapOp::CompareResult(LdapResultOp {
+                code: LdapResultCode::CompareTrue,
+                matcheddn: dn.to_string(),
+                message: "".to_string(),
+                referral: vec![],
+            })])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_group() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|_, _| Ok(vec![]));
+        mock.expect_list_groups().returning(|f| {
+            assert_eq!(f, Some(GroupRequestFilter::DisplayName("group".to_owned())));
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "group".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                users: vec![UserId::new("bob")],
+                attributes: Vec::new(),
+                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                email: None,
+                description: None,
+            }])
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let dn = "uid=group,ou=groups,dc=example,dc=com";
+        let request = LdapCompareRequest {
+            dn: dn.to_string(),
+            atype: "uid".to_owned(),
+            val: b"group".to_vec(),
+        };
+        assert_eq!(
+            ldap_handler.do_compare(request).await,
+            Ok(vec![LdapOp::CompareResult(LdapResultOp {
+                code: LdapResultCode::CompareTrue,
+                matcheddn: dn.to_string(),
+                message: "".to_string(),
+                referral: vec![],
+            })])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_not_found() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|f, g| {
+            assert_eq!(f, Some(UserRequestFilter::UserId(UserId::new("bob"))));
+            assert!(!g);
+            Ok(vec![])
+        });
+        mock.expect_list_groups().returning(|_| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let dn = "uid=bob,ou=people,dc=example,dc=com";
+        let request = LdapCompareRequest {
+            dn: dn.to_string(),
+            atype: "uid".to_owned(),
+            val: b"bob".to_vec(),
+        };
+        assert_eq!(
+            ldap_handler.do_compare(request).await,
+            Ok(vec![LdapOp::CompareResult(LdapResultOp {
+                code: LdapResultCode::NoSuchObject,
+                matcheddn: "dc=example,dc=com".to_owned(),
+                message: "".to_string(),
+                referral: vec![],
+            })])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_no_match() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|f, g| {
+            assert_eq!(f, Some(UserRequestFilter::UserId(UserId::new("bob"))));
+            assert!(!g);
+            Ok(vec![UserAndGroups {
+                user: User {
+                    user_id: UserId::new("bob"),
+                    email: "bob@bobmail.bob".to_string(),
+                    ..Default::default()
+                },
+                groups: None,
+            }])
+        });
+        mock.expect_list_groups().returning(|_| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let dn = "uid=bob,ou=people,dc=example,dc=com";
+        let request = LdapCompareRequest {
+            dn: dn.to_string(),
+            atype: "mail".to_owned(),
+            val: b"bob@bob".to_vec(),
+        };
+        assert_eq!(
+            ldap_handler.do_compare(request).await,
+            Ok(vec![LdapOp::CompareResult(LdapResultOp {
+                code: LdapResultCode::CompareFalse,
+                matcheddn: dn.to_string(),
+                message: "".to_string(),
+                referral: vec![],
+            })])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_error() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|_, _| {
+            Err(DomainError::InternalError(
+                "Error getting users".to_string(),
+            ))
+        });
+        mock.expect_list_groups().returning(|_| {
+            Err(DomainError::InternalError(
+                "Error getting groups".to_string(),
+            ))
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapCompareRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            atype: "uid".to_owned(),
+            val: b"bob".to_vec(),
+        };
+        // The error comes in a CompareResult, not in a SearchResultDone.
+        let response = ldap_handler
+            .handle_ldap_message(LdapOp::CompareRequest(request))
+            .await
+            .unwrap();
+        assert!(
+            matches!(
+                response.as_slice(),
+                [LdapOp::CompareResult(LdapResultOp {
+                    code: LdapResultCode::Other,
+                    ..
+                })]
+            ),
+            "{:?}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_group_member() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_users().returning(|_, _| Ok(vec![]));
+        mock.expect_list_groups().returning(|f| {
+            assert_eq!(f, Some(GroupRequestFilter::DisplayName("group".to_owned())));
+            Ok(vec![Group {
+                id: GroupId(1),
+                display_name: "group".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                users: vec![UserId::new("bob")],
+                attributes: Vec::new(),
+                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                email: None,
+                description: None,
+            }])
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let dn = "uid=group,ou=groups,dc=example,dc=com";
+        let request = LdapCompareRequest {
+            dn: dn.to_string(),
+            atype: "uniqueMember".to_owned(),
+            val: b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
+        };
+        assert_eq!(
+            ldap_handler.do_compare(request).await,
+            Ok(vec![LdapOp::CompareResult(LdapResultOp {
+                code: LdapResultCode::CompareTrue,
+                matcheddn: dn.to_owned(),
+                message: "".to_string(),
+                referral: vec![],
+            })])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_dn_user() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_user_details()
+            .with(eq(UserId::new("robert")))
             .times(1)
-            .return_once(|_| Ok(()));
+            .return_once(|_| {
+                Err(crate::domain::error::DomainError::EntityNotFound(
+                    "robert".to_string(),
+                ))
+            });
+        mock.expect_rename_user()
+            .with(eq(UserId::new("bob")), eq(UserId::new("robert")))
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        mock.expect_list_users()
+            .with(
+                eq(Some(UserRequestFilter::And(vec![
+                    true.into(),
+                    UserRequestFilter::UserId(UserId::new("robert")),
+                ]))),
+                eq(false),
+            )
+            .times(1)
+            .return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("robert"),
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = LdapOp::ModifyRequest(LdapModifyRequest {
+        let request = LdapOp::ModifyDNRequest(LdapModifyDNRequest {
             dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
-            changes: vec![LdapModify {
-                operation: LdapModifyType::Replace,
-                modification: LdapPartialAttribute {
-                    atype: "userPassword".to_owned(),
-                    vals: vec!["password".as_bytes().to_vec()],
-                },
-            }],
+            newrdn: "uid=Robert".to_string(),
+            deleteoldrdn: true,
+            new_superior: None,
         });
         assert_eq!(
             ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_modify_response(
+            Some(vec![make_modify_dn_response(
                 LdapResultCode::Success,
-                "".to_string(),
+                String::new()
             )])
         );
+        let request = make_search_request(
+            "uid=robert,ou=people,dc=example,dc=com",
+            LdapFilter::And(vec![]),
+            vec!["uid"],
+        );
+        assert_eq!(
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=robert,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "uid".to_string(),
+                        vals: vec![b"robert".to_vec()]
+                    }],
+                }),
+                make_search_success(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_dn_group() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_list_groups()
+            .with(eq(None))
+            .times(1)
+            .return_once(|_| {
+                Ok(vec![Group {
+                    id: GroupId(2),
+                    display_name: "Group_1".to_string(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    users: vec![],
+                    attributes: Vec::new(),
+                    uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
+                }])
+            });
+        mock.expect_update_group()
+            .with(eq(UpdateGroupRequest {
+                group_id: GroupId(2),
+                display_name: Some("Best Group".to_string()),
+                email: None,
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapModifyDNRequest {
+            dn: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+            newrdn: "cn=Best Group".to_string(),
+            deleteoldrdn: true,
+            new_superior: Some("ou=Groups,dc=example,dc=com".to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_modify_dn_request(&request).await,
+            vec![make_modify_dn_response(
+                LdapResultCode::Success,
+                String::new()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_dn_move_subtree() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = LdapModifyDNRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            newrdn: "uid=bob".to_string(),
+            deleteoldrdn: true,
+            new_superior: Some("ou=groups,dc=example,dc=com".to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_modify_dn_request(&request).await,
+            vec![make_modify_dn_response(
+                LdapResultCode::UnwillingToPerform,
+                r#"Moving "uid=bob,ou=people,dc=example,dc=com" under "ou=groups,dc=example,dc=com" is not supported"#.to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_dn_wrong_rdn_attribute() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = LdapModifyDNRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            newrdn: "cn=bob".to_string(),
+            deleteoldrdn: true,
+            new_superior: None,
+        };
+        assert_eq!(
+            ldap_handler.do_modify_dn_request(&request).await,
+            vec![make_modify_dn_response(
+                LdapResultCode::NamingViolation,
+                r#"Users must be named by "uid", got "cn""#.to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_group() {
+        let mut transaction = MockTestBackendHandler::new();
+        transaction
+            .expect_create_group()
+            .with(eq("Best Group"))
+            .times(1)
+            .return_once(|_| Ok(GroupId(5)));
+        transaction
+            .expect_update_group()
+            .with(eq(UpdateGroupRequest {
+                group_id: GroupId(5),
+                display_name: None,
+                email: Some("best@example.com".to_string()),
+                description: None,
+                delete_attributes: vec![],
+                insert_attributes: vec![],
+            }))
+            .times(1)
+            .return_once(|_| Ok(()));
+        transaction
+            .expect_add_user_to_group()
+            .with(eq(UserId::new("bob")), eq(GroupId(5)))
+            .times(1)
+            .return_once(|_, _| Ok(()));
+        transaction
+            .expect_commit_transaction()
+            .times(1)
+            .return_once(|| Ok(()));
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_begin_transaction()
+            .times(1)
+            .return_once(|| Ok(transaction));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapOp::AddRequest(LdapAddRequest {
+            dn: "cn=Best Group,ou=groups,dc=example,dc=com".to_owned(),
+            attributes: vec![
+                LdapPartialAttribute {
+                    atype: "objectClass".to_owned(),
+                    vals: vec![b"groupOfNames".to_vec()],
+                },
+                LdapPartialAttribute {
+                    atype: "member".to_owned(),
+                    vals: vec![b"uid=bob,ou=people,dc=example,dc=com".to_vec()],
+                },
+                LdapPartialAttribute {
+                    atype: "mail".to_owned(),
+                    vals: vec![b"best@example.com".to_vec()],
+                },
+            ],
+        });
+        assert_eq!(
+            ldap_handler.handle_ldap_message(request).await,
+            Some(vec![make_add_error(LdapResultCode::Success, String::new())])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_group_rollback() {
+        use crate::domain::{
+            handler::GroupListerBackendHandler,
+            sql_backend_handler::{tests::*, SqlBackendHandler},
+        };
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "admin", "admin000").await;
+        let admin_group = insert_group(&handler, "lldap_admin").await;
+        insert_membership(&handler, admin_group, "admin").await;
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(handler.clone()),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions::default(),
+        );
+        ldap_handler
+            .do_bind(&LdapBindRequest {
+                dn: "uid=admin,ou=people,dc=example,dc=com".to_string(),
+                cred: LdapBindCred::Simple("admin000".to_string()),
+            })
+            .await;
+        let request = LdapOp::AddRequest(LdapAddRequest {
+            dn: "cn=Best Group,ou=groups,dc=example,dc=com".to_owned(),
+            attributes: vec![LdapPartialAttribute {
+                atype: "member".to_owned(),
+                vals: vec![b"uid=unknown,ou=people,dc=example,dc=com".to_vec()],
+            }],
+        });
+        assert!(matches!(
+            ldap_handler.handle_ldap_message(request).await.as_deref(),
+            Some([LdapOp::AddResponse(LdapResultOp {
+                code: LdapResultCode::OperationsError,
+                ..
+            })])
+        ));
+        // The group was not created without its member.
+        assert_eq!(
+            handler
+                .list_groups(None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|g| g.display_name)
+                .collect::<Vec<_>>(),
+            vec!["lldap_admin"]
+        );
     }
 
     #[tokio::test]
-    async fn test_password_change_password_manager() {
+    async fn test_delete_user() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_get_user_groups()
+        mock.expect_delete_user()
             .with(eq(UserId::new("bob")))
-            .returning(|_| Ok(HashSet::new()));
-        use lldap_auth::*;
-        let mut rng = rand::rngs::OsRng;
-        let registration_start_request =
-            opaque::client::registration::start_registration("password".as_bytes(), &mut rng)
-                .unwrap();
-        let request = registration::ClientRegistrationStartRequest {
-            username: "bob".to_string(),
-            registration_start_request: registration_start_request.message,
-        };
-        let start_response = opaque::server::registration::start_registration(
-            &opaque::server::ServerSetup::new(&mut rng),
-            request.registration_start_request,
-            &request.username,
-        )
-        .unwrap();
-        mock.expect_registration_start().times(1).return_once(|_| {
-            Ok(registration::ServerRegistrationStartResponse {
-                server_data: "".to_string(),
-                registration_response: start_response.message,
-            })
-        });
-        mock.expect_registration_finish()
             .times(1)
             .return_once(|_| Ok(()));
-        let mut ldap_handler = setup_bound_password_manager_handler(mock).await;
-        let request = LdapOp::ExtendedRequest(
-            LdapPasswordModifyRequest {
-                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
-                old_password: None,
-                new_password: Some("password".to_string()),
-            }
-            .into(),
-        );
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapOp::DelRequest("uid=bob,ou=people,dc=example,dc=com".to_owned());
         assert_eq!(
             ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
+            Some(vec![make_del_response(
                 LdapResultCode::Success,
-                "".to_string(),
+                String::new()
             )])
         );
     }
 
     #[tokio::test]
-    async fn test_password_change_errors() {
-        let mut mock = MockTestBackendHandler::new();
-        mock.expect_get_user_groups()
-            .with(eq(UserId::new("bob")))
-            .returning(|_| Ok(HashSet::new()));
-        let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = LdapOp::ExtendedRequest(
-            LdapPasswordModifyRequest {
-                user_identity: None,
-                old_password: None,
-                new_password: None,
-            }
-            .into(),
-        );
-        assert_eq!(
-            ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
-                LdapResultCode::ConstraintViolation,
-                "Missing either user_id or password".to_string(),
-            )])
-        );
-        let request = LdapOp::ExtendedRequest(
-            LdapPasswordModifyRequest {
-                user_identity: Some("uid=bob,ou=groups,ou=people,dc=example,dc=com".to_string()),
-                old_password: None,
-                new_password: Some("password".to_string()),
-            }
-            .into(),
-        );
-        assert_eq!(
-            ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
-                LdapResultCode::InvalidDNSyntax,
-                r#"Invalid username: Unexpected DN format. Got "uid=bob,ou=groups,ou=people,dc=example,dc=com", expected: "uid=id,ou=people,dc=example,dc=com""#.to_string(),
-            )])
-        );
-        let request = LdapOp::ExtendedRequest(LdapExtendedRequest {
-            name: "test".to_string(),
-            value: None,
-        });
+    async fn test_delete_current_user() {
+        let ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
         assert_eq!(
-            ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
-                LdapResultCode::UnwillingToPerform,
-                "Unsupported extended operation: test".to_string(),
-            )])
+            ldap_handler
+                .do_delete("uid=test,ou=people,dc=example,dc=com")
+                .await,
+            Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: "Cannot delete current user".to_string(),
+            })
         );
     }
 
     #[tokio::test]
-    async fn test_password_change_unauthorized_password_manager() {
+    async fn test_delete_group() {
         let mut mock = MockTestBackendHandler::new();
-        let mut groups = HashSet::new();
-        groups.insert(GroupDetails {
-            group_id: GroupId(0),
-            display_name: "lldap_admin".to_string(),
-            creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
-            uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
-        });
-        mock.expect_get_user_groups()
-            .with(eq(UserId::new("bob")))
+        mock.expect_list_groups()
+            .with(eq(None))
             .times(1)
-            .return_once(|_| Ok(groups));
-        let mut ldap_handler = setup_bound_password_manager_handler(mock).await;
-        let request = LdapOp::ExtendedRequest(
-            LdapPasswordModifyRequest {
-                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
-                old_password: Some("pass".to_string()),
-                new_password: Some("password".to_string()),
-            }
-            .into(),
-        );
+            .return_once(|_| {
+                Ok(vec![Group {
+                    id: GroupId(5),
+                    display_name: "Best Group".to_string(),
+                    creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                    users: vec![],
+                    attributes: Vec::new(),
+                    uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
+                    email: None,
+                    description: None,
+                }])
+            });
+        mock.expect_delete_group()
+            .with(eq(GroupId(5)))
+            .times(1)
+            .return_once(|_| Ok(()));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        let request = LdapOp::DelRequest("cn=Best Group,ou=groups,dc=example,dc=com".to_owned());
         assert_eq!(
             ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
-                LdapResultCode::InsufficentAccessRights,
-                "User `test` cannot modify the password of user `bob`".to_string(),
+            Some(vec![make_del_response(
+                LdapResultCode::Success,
+                String::new()
             )])
         );
     }
 
     #[tokio::test]
-    async fn test_password_change_unauthorized_readonly() {
+    async fn test_configured_admin_groups() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_get_user_groups()
-            .with(eq(UserId::new("bob")))
-            .times(1)
-            .return_once(|_| Ok(HashSet::new()));
-        let mut ldap_handler = setup_bound_readonly_handler(mock).await;
-        let request = LdapOp::ExtendedRequest(
-            LdapPasswordModifyRequest {
-                user_identity: Some("uid=bob,ou=people,dc=example,dc=com".to_string()),
-                old_password: Some("pass".to_string()),
-                new_password: Some("password".to_string()),
-            }
-            .into(),
+        mock.expect_bind().return_once(|_| Ok(()));
+        mock.expect_get_user_groups().return_once(|_| {
+            Ok(HashSet::from([GroupDetails {
+                group_id: GroupId(5),
+                display_name: "ops".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                email: None,
+                description: None,
+            }]))
+        });
+        mock.expect_list_groups().return_once(|_| {
+            Ok(vec![Group {
+                id: GroupId(5),
+                display_name: "ops".to_string(),
+                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                modified_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
+                users: vec![],
+                attributes: Vec::new(),
+                uuid: uuid!("a1a2a3a4b1b2c1c2d1d2d3d4d5d6d7d8"),
+                email: None,
+                description: None,
+            }])
+        });
+        let mut ldap_handler = LdapHandler::new(
+            AccessControlledBackendHandler::new(mock)
+                .with_admin_groups(vec!["admins".to_owned(), "ops".to_owned()]),
+            "dc=example,dc=com".to_string(),
+            vec![],
+            vec![],
+            &LdapSearchOptions::default(),
+        );
+        let request = LdapBindRequest {
+            dn: "uid=test,ou=people,dc=example,dc=com".to_string(),
+            cred: LdapBindCred::Simple("pass".to_string()),
+        };
+        assert_eq!(
+            ldap_handler.do_bind(&request).await.0,
+            LdapResultCode::Success
         );
+        assert!(ldap_handler.user_info.as_ref().unwrap().is_admin());
+        // The configured admin groups are protected like the default one.
         assert_eq!(
-            ldap_handler.handle_ldap_message(request).await,
-            Some(vec![make_extended_response(
-                LdapResultCode::InsufficentAccessRights,
-                "User `test` cannot modify the password of user `bob`".to_string(),
-            )])
+            ldap_handler
+                .do_delete("cn=ops,ou=groups,dc=example,dc=com")
+                .await,
+            Err(LdapError {
+                code: LdapResultCode::UnwillingToPerform,
+                message: "Cannot delete the admin group".to_string(),
+            })
         );
     }
 
     #[tokio::test]
-    async fn test_search_root_dse() {
-        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
-        let request = LdapSearchRequest {
-            base: "".to_string(),
-            scope: LdapSearchScope::Base,
-            aliases: LdapDerefAliases::Never,
-            sizelimit: 0,
-            timelimit: 0,
-            typesonly: false,
-            filter: LdapFilter::Present("objectClass".to_string()),
-            attrs: vec!["supportedExtension".to_string()],
-        };
+    async fn test_delete_unauthorized() {
+        let ldap_handler = setup_bound_readonly_handler(MockTestBackendHandler::new()).await;
         assert_eq!(
-            ldap_handler.do_search_or_dse(&request).await,
-            Ok(vec![
-                root_dse_response("dc=example,dc=com"),
-                make_search_success()
-            ])
+            ldap_handler
+                .do_delete("uid=bob,ou=people,dc=example,dc=com")
+                .await,
+            Err(LdapError {
+                code: LdapResultCode::InsufficentAccessRights,
+                message: "Unauthorized write".to_string(),
+            })
         );
     }
 
     #[tokio::test]
-    async fn test_create_user() {
-        let mut mock = MockTestBackendHandler::new();
-        mock.expect_create_user()
-            .with(eq(CreateUserRequest {
-                user_id: UserId::new("bob"),
-                email: "".to_owned(),
-                display_name: Some("Bob".to_string()),
-                ..Default::default()
-            }))
-            .times(1)
-            .return_once(|_| Ok(()));
-        let ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = LdapAddRequest {
-            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
-            attributes: vec![LdapPartialAttribute {
-                atype: "cn".to_owned(),
-                vals: vec![b"Bob".to_vec()],
-            }],
+    async fn test_critical_control_unavailable() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = make_user_search_request::<String>(LdapFilter::And(vec![]), vec![]);
+        let control = LdapControl::SyncRequest {
+            criticality: true,
+            mode: ldap3_proto::proto::SyncRequestMode::RefreshOnly,
+            cookie: None,
+            reload_hint: false,
         };
         assert_eq!(
-            ldap_handler.do_create_user(request).await,
-            Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
+            ldap_handler
+                .handle_ldap_message_with_controls(LdapOp::SearchRequest(request), &[control])
+                .await,
+            Some(vec![make_search_error(
+                LdapResultCode::UnavailableCriticalExtension,
+                "Unsupported critical control: content synchronization \
+                 (1.3.6.1.4.1.4203.1.9.1.1)"
+                    .to_string()
+            )])
         );
     }
 
     #[tokio::test]
-    async fn test_create_user_wrong_ou() {
-        let ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
-        let request = LdapAddRequest {
-            dn: "uid=bob,ou=groups,dc=example,dc=com".to_owned(),
-            attributes: vec![LdapPartialAttribute {
-                atype: "cn".to_owned(),
-                vals: vec![b"Bob".to_vec()],
-            }],
+    async fn test_unknown_critical_control_unavailable() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let controls = |oid: &str| RequestControls {
+            unavailable_critical: Some(oid.to_string()),
+            ..Default::default()
         };
+        let request = make_user_search_request::<String>(LdapFilter::And(vec![]), vec![]);
+        let (responses, extensions) = ldap_handler
+            .handle_ldap_message_with_request_controls(
+                LdapOp::SearchRequest(request),
+                &[],
+                &controls("1.2.3.4"),
+            )
+            .await
+            .unwrap();
         assert_eq!(
-            ldap_handler.do_create_user(request).await,
-            Err(LdapError{ code: LdapResultCode::InvalidDNSyntax, message: r#"Unexpected DN format. Got "uid=bob,ou=groups,dc=example,dc=com", expected: "uid=id,ou=people,dc=example,dc=com""#.to_string() })
+            responses.collect::<Vec<_>>(),
+            vec![make_search_error(
+                LdapResultCode::UnavailableCriticalExtension,
+                "Unsupported critical control: 1.2.3.4".to_string()
+            )]
+        );
+        assert_eq!(extensions, ResponseExtensions::default());
+        // A critical paged results request doesn't return all the entries, nor does a modify go
+        // through.
+        let request = make_user_search_request::<String>(LdapFilter::And(vec![]), vec![]);
+        let (responses, _) = ldap_handler
+            .handle_ldap_message_with_request_controls(
+                LdapOp::SearchRequest(request),
+                &[],
+                &controls("1.2.840.113556.1.4.319"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            responses.collect::<Vec<_>>(),
+            vec![make_search_error(
+                LdapResultCode::UnavailableCriticalExtension,
+                "Unsupported critical control: 1.2.840.113556.1.4.319".to_string()
+            )]
+        );
+        let request = LdapModifyRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            changes: vec![],
+        };
+        let (responses, _) = ldap_handler
+            .handle_ldap_message_with_request_controls(
+                LdapOp::ModifyRequest(request),
+                &[],
+                &controls("2.16.840.1.113730.3.4.2"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            responses.collect::<Vec<_>>(),
+            vec![make_modify_response(
+                LdapResultCode::UnavailableCriticalExtension,
+                "Unsupported critical control: 2.16.840.1.113730.3.4.2".to_string()
+            )]
         );
     }
 
     #[tokio::test]
-    async fn test_create_user_multiple_object_class() {
+    async fn test_diagnostic_messages() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_create_user()
-            .with(eq(CreateUserRequest {
-                user_id: UserId::new("bob"),
-                email: "".to_owned(),
-                display_name: Some("Bob".to_string()),
-                ..Default::default()
-            }))
-            .times(1)
-            .return_once(|_| Ok(()));
-        let ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = LdapAddRequest {
-            dn: "uid=bob,ou=people,dc=example,dc=com".to_owned(),
-            attributes: vec![
-                LdapPartialAttribute {
-                    atype: "cn".to_owned(),
-                    vals: vec![b"Bob".to_vec()],
-                },
-                LdapPartialAttribute {
-                    atype: "objectClass".to_owned(),
-                    vals: vec![
-                        b"top".to_vec(),
-                        b"person".to_vec(),
-                        b"inetOrgPerson".to_vec(),
-                    ],
-                },
-            ],
-        };
+        mock.expect_bind()
+            .with(eq(BindRequest {
+                name: UserId::new("bob"),
+                password: "secret".to_string(),
+            }))
+            .returning(|_| {
+                Err(DomainError::AuthenticationError(
+                    "Wrong password for bob".to_string(),
+                ))
+            });
+        mock.expect_list_users().returning(|_, _| {
+            Err(DomainError::InternalError(
+                "Cannot open /data/users.db".to_string(),
+            ))
+        });
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        // The failed binds don't say why.
         assert_eq!(
-            ldap_handler.do_create_user(request).await,
-            Ok(vec![make_add_error(LdapResultCode::Success, String::new())])
+            ldap_handler
+                .do_bind(&LdapBindRequest {
+                    dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                    cred: LdapBindCred::Simple("secret".to_string()),
+                })
+                .await,
+            (
+                LdapResultCode::InvalidCredentials,
+                "Invalid credentials".to_string()
+            )
+        );
+        // The internal errors are only logged.
+        assert_eq!(
+            ldap_handler
+                .do_search_or_dse(&make_user_search_request::<String>(
+                    LdapFilter::And(vec![]),
+                    vec![]
+                ))
+                .await,
+            Err(LdapError {
+                code: LdapResultCode::Other,
+                message: r#"Error while searching user "ou=people,dc=example,dc=com""#.to_string()
+            })
+        );
+        // The errors caused by the request are explained.
+        assert_eq!(
+            make_creation_error(
+                "user",
+                DomainError::Conflict("The user `bob` already exists".to_string())
+            ),
+            LdapError {
+                code: LdapResultCode::ConstraintViolation,
+                message: "Could not create user: Conflict: `The user `bob` already exists`"
+                    .to_string()
+            }
+        );
+        assert_eq!(
+            get_operation_name(&LdapOp::BindRequest(LdapBindRequest {
+                dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                cred: LdapBindCred::Simple("secret".to_string()),
+            })),
+            "BindRequest"
         );
     }
 
     #[tokio::test]
-    async fn test_search_filter_non_attribute() {
+    async fn test_non_critical_control_ignored() {
         let mut mock = MockTestBackendHandler::new();
         mock.expect_list_users()
             .with(eq(Some(true.into())), eq(false))
             .times(1)
             .return_once(|_, _| Ok(vec![]));
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let request = make_user_search_request(
-            LdapFilter::Present("displayname".to_owned()),
-            vec!["objectClass"],
-        );
+        let request =
+            make_user_search_request::<String>(LdapFilter::And(vec![]), vec!["1.1".to_string()]);
+        let control = LdapControl::SyncRequest {
+            criticality: false,
+            mode: ldap3_proto::proto::SyncRequestMode::RefreshOnly,
+            cookie: None,
+            reload_hint: false,
+        };
         assert_eq!(
-            ldap_handler.do_search_or_dse(&request).await,
-            Ok(vec![make_search_success()])
+            ldap_handler
+                .handle_ldap_message_with_controls(LdapOp::SearchRequest(request), &[control])
+                .await,
+            Some(vec![make_search_success()])
         );
     }
 
     #[tokio::test]
-    async fn test_compare_user() {
+    async fn test_disabled_operations() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_list_users().returning(|f, g| {
-            assert_eq!(f, Some(UserRequestFilter::UserId(UserId::new("bob"))));
-            assert!(!g);
-            Ok(vec![UserAndGroups {
-                user: User {
-                    user_id: UserId::new("bob"),
-                    email: "bob@bobmail.bob".to_string(),
-                    ..Default::default()
+        mock.expect_list_users()
+            .with(eq(Some(true.into())), eq(false))
+            .times(1)
+            .return_once(|_, _| Ok(vec![]));
+        let mut ldap_handler = setup_bound_admin_handler(mock).await;
+        ldap_handler.disabled_operations = vec![LdapOperation::Modify, LdapOperation::Extended];
+        let modify = LdapOp::ModifyRequest(LdapModifyRequest {
+            dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+            changes: vec![LdapModify {
+                operation: LdapModifyType::Replace,
+                modification: LdapPartialAttribute {
+                    atype: "userPassword".to_owned(),
+                    vals: vec![b"password".to_vec()],
                 },
-                groups: None,
-            }])
+            }],
         });
-        mock.expect_list_groups().returning(|_| Ok(vec![]));
-        let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let dn = "uid=bob,ou=people,dc=example,dc=com";
-        let request = LdapCompareRequest {
-            dn: dn.to_string(),
-            atype: "uid".to_owned(),
-            val: b"bob".to_vec(),
-        };
         assert_eq!(
-            ldap_handler.do_compare(request).await,
-            Ok(vec![LdapOp::CompareResult(LdapResultOp {
-                code: LdapResultCode::CompareTrue,
-                matcheddn: dn.to_string(),
-                message: "".to_string(),
-                referral: vec![],
-            })])
+            ldap_handler
+                .handle_ldap_message_lazy(modify, &[])
+                .await
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![make_modify_response(
+                LdapResultCode::UnwillingToPerform,
+                "The modify operations are disabled on this server".to_string()
+            )]
         );
-        // Non-canonical attribute.
-        let request = LdapCompareRequest {
-            dn: dn.to_string(),
-            atype: "eMail".to_owned(),
-            val: b"bob@bobmail.bob".to_vec(),
-        };
+        let search =
+            make_user_search_request::<String>(LdapFilter::And(vec![]), vec!["1.1".to_string()]);
         assert_eq!(
-            ldap_handler.do_compare(request).await,
-            Ok(vec![LdapOp::CompareResult(LdapResultOp {
-                code: LdapResultCode::CompareTrue,
-                matcheddn: dn.to_string(),
-                message: "".to_string(),
-                referral: vec![],
-            })])
+            ldap_handler
+                .handle_ldap_message_lazy(LdapOp::SearchRequest(search), &[])
+                .await
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![make_search_success()]
         );
     }
 
     #[tokio::test]
-    async fn test_compare_group() {
+    async fn test_search_extensible_match_case_ignore() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_list_users().returning(|_, _| Ok(vec![]));
-        mock.expect_list_groups().returning(|f| {
-            assert_eq!(f, Some(GroupRequestFilter::DisplayName("group".to_owned())));
-            Ok(vec![Group {
-                id: GroupId(1),
-                display_name: "group".to_string(),
-                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
-                users: vec![UserId::new("bob")],
-                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
-            }])
-        });
+        mock.expect_list_users()
+            .with(
+                eq(Some(UserRequestFilter::Or(vec![
+                    UserRequestFilter::EqualityIgnoreCase(
+                        UserColumn::DisplayName,
+                        "Alice".to_string(),
+                    ),
+                    UserRequestFilter::MemberOf("group_1".to_string()),
+                ]))),
+                eq(false),
+            )
+            .times(1)
+            .return_once(|_, _| {
+                Ok(vec![UserAndGroups {
+                    user: User {
+                        user_id: UserId::new("alice"),
+                        display_name: Some("alice".to_string()),
+                        ..Default::default()
+                    },
+                    groups: None,
+                }])
+            });
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let dn = "uid=group,ou=groups,dc=example,dc=com";
-        let request = LdapCompareRequest {
-            dn: dn.to_string(),
-            atype: "uid".to_owned(),
-            val: b"group".to_vec(),
-        };
-        assert_eq!(
-            ldap_handler.do_compare(request).await,
-            Ok(vec![LdapOp::CompareResult(LdapResultOp {
-                code: LdapResultCode::CompareTrue,
-                matcheddn: dn.to_string(),
-                message: "".to_string(),
-                referral: vec![],
-            })])
+        let request = make_user_search_request(
+            LdapFilter::Or(vec![
+                LdapFilter::Extensible(LdapMatchingRuleAssertion {
+                    matching_rule: Some("caseIgnoreMatch".to_string()),
+                    type_: Some("cn".to_string()),
+                    match_value: "Alice".to_string(),
+                    dn_attributes: false,
+                }),
+                LdapFilter::Extensible(LdapMatchingRuleAssertion {
+                    matching_rule: Some("1.2.840.113556.1.4.1941".to_string()),
+                    type_: Some("memberOf".to_string()),
+                    match_value: "cn=group_1,ou=groups,dc=example,dc=com".to_string(),
+                    dn_attributes: false,
+                }),
+            ]),
+            vec!["cn"],
         );
-    }
-
-    #[tokio::test]
-    async fn test_compare_not_found() {
-        let mut mock = MockTestBackendHandler::new();
-        mock.expect_list_users().returning(|f, g| {
-            assert_eq!(f, Some(UserRequestFilter::UserId(UserId::new("bob"))));
-            assert!(!g);
-            Ok(vec![])
-        });
-        mock.expect_list_groups().returning(|_| Ok(vec![]));
-        let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let dn = "uid=bob,ou=people,dc=example,dc=com";
-        let request = LdapCompareRequest {
-            dn: dn.to_string(),
-            atype: "uid".to_owned(),
-            val: b"bob".to_vec(),
-        };
         assert_eq!(
-            ldap_handler.do_compare(request).await,
-            Ok(vec![LdapOp::CompareResult(LdapResultOp {
-                code: LdapResultCode::NoSuchObject,
-                matcheddn: "dc=example,dc=com".to_owned(),
-                message: "".to_string(),
-                referral: vec![],
-            })])
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![
+                LdapOp::SearchResultEntry(LdapSearchResultEntry {
+                    dn: "uid=alice,ou=people,dc=example,dc=com".to_string(),
+                    attributes: vec![LdapPartialAttribute {
+                        atype: "cn".to_string(),
+                        vals: vec![b"alice".to_vec()]
+                    }],
+                }),
+                make_search_success(),
+            ])
         );
     }
 
     #[tokio::test]
-    async fn test_compare_no_match() {
-        let mut mock = MockTestBackendHandler::new();
-        mock.expect_list_users().returning(|f, g| {
-            assert_eq!(f, Some(UserRequestFilter::UserId(UserId::new("bob"))));
-            assert!(!g);
-            Ok(vec![UserAndGroups {
-                user: User {
-                    user_id: UserId::new("bob"),
-                    email: "bob@bobmail.bob".to_string(),
-                    ..Default::default()
-                },
-                groups: None,
-            }])
-        });
-        mock.expect_list_groups().returning(|_| Ok(vec![]));
-        let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let dn = "uid=bob,ou=people,dc=example,dc=com";
-        let request = LdapCompareRequest {
-            dn: dn.to_string(),
-            atype: "mail".to_owned(),
-            val: b"bob@bob".to_vec(),
-        };
+    async fn test_search_extensible_match_unsupported_rule() {
+        let mut ldap_handler = setup_bound_admin_handler(MockTestBackendHandler::new()).await;
+        let request = make_user_search_request(
+            LdapFilter::Extensible(LdapMatchingRuleAssertion {
+                matching_rule: Some("2.5.13.11".to_string()),
+                type_: Some("cn".to_string()),
+                match_value: "Alice".to_string(),
+                dn_attributes: false,
+            }),
+            vec!["cn"],
+        );
         assert_eq!(
-            ldap_handler.do_compare(request).await,
-            Ok(vec![LdapOp::CompareResult(LdapResultOp {
-                code: LdapResultCode::CompareFalse,
-                matcheddn: dn.to_string(),
-                message: "".to_string(),
-                referral: vec![],
-            })])
+            ldap_handler.do_search_or_dse(&request).await,
+            Err(LdapError {
+                code: LdapResultCode::InappropriateMatching,
+                message: r#"Unsupported matching rule "2.5.13.11" for attribute "cn""#.to_string(),
+            })
         );
     }
 
     #[tokio::test]
-    async fn test_compare_group_member() {
+    async fn test_search_substring_filters() {
         let mut mock = MockTestBackendHandler::new();
-        mock.expect_list_users().returning(|_, _| Ok(vec![]));
-        mock.expect_list_groups().returning(|f| {
-            assert_eq!(f, Some(GroupRequestFilter::DisplayName("group".to_owned())));
-            Ok(vec![Group {
-                id: GroupId(1),
-                display_name: "group".to_string(),
-                creation_date: chrono::Utc.timestamp_opt(42, 42).unwrap().naive_utc(),
-                users: vec![UserId::new("bob")],
-                uuid: uuid!("04ac75e0-2900-3e21-926c-2f732c26b3fc"),
-            }])
-        });
+        mock.expect_list_users()
+            .with(
+                eq(Some(UserRequestFilter::Or(vec![
+                    UserRequestFilter::SubString(
+                        UserColumn::Email,
+                        SubStringFilter {
+                            initial: Some("alice".to_owned()),
+                            ..Default::default()
+                        },
+                    ),
+                    UserRequestFilter::SubString(
+                        UserColumn::DisplayName,
+                        SubStringFilter {
+                            any: vec!["smith".to_owned()],
+                            ..Default::default()
+                        },
+                    ),
+                    UserRequestFilter::UserIdSubString(SubStringFilter {
+                        final_: Some("ice".to_owned()),
+                        ..Default::default()
+                    }),
+                    UserRequestFilter::AttributeSubString(
+                        "first_name".to_owned(),
+                        SubStringFilter {
+                            initial: Some("Al".to_owned()),
+                            ..Default::default()
+                        },
+                    ),
+                ]))),
+                eq(false),
+            )
+            .times(1)
+            .return_once(|_, _| Ok(vec![]));
         let mut ldap_handler = setup_bound_admin_handler(mock).await;
-        let dn = "uid=group,ou=groups,dc=example,dc=com";
-        let request = LdapCompareRequest {
-            dn: dn.to_string(),
-            atype: "uniqueMember".to_owned(),
-            val: b"uid=bob,ou=people,dc=example,dc=com".to_vec(),
-        };
+        let substring =
+            |initial: Option<&str>, any: Vec<&str>, final_: Option<&str>| LdapSubstringFilter {
+                initial: initial.map(str::to_owned),
+                any: any.into_iter().map(str::to_owned).collect(),
+                final_: final_.map(str::to_owned),
+            };
+        let request = make_user_search_request(
+            LdapFilter::Or(vec![
+                LdapFilter::Substring("mail".to_owned(), substring(Some("alice"), vec![], None)),
+                LdapFilter::Substring(
+                    "displayName".to_owned(),
+                    substring(None, vec!["smith"], None),
+                ),
+                LdapFilter::Substring("uid".to_owned(), substring(None, vec![], Some("ice"))),
+                LdapFilter::Substring("givenName".to_owned(), substring(Some("Al"), vec![], None)),
+            ]),
+            vec!["1.1"],
+        );
         assert_eq!(
-            ldap_handler.do_compare(request).await,
-            Ok(vec![LdapOp::CompareResult(LdapResultOp {
-                code: LdapResultCode::CompareTrue,
-                matcheddn: dn.to_owned(),
-                message: "".to_string(),
-                referral: vec![],
-            })])
+            ldap_handler.do_search_or_dse(&request).await,
+            Ok(vec![make_search_success()])
         );
     }
 }
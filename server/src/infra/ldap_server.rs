@@ -5,89 +5,408 @@ use crate::{
     },
     infra::{
         access_control::AccessControlledBackendHandler,
-        configuration::{Configuration, LdapsOptions},
-        ldap_handler::LdapHandler,
+        configuration::{
+            parse_unix_socket_permissions, Configuration, LdapSearchOptions, LdapsOptions,
+        },
+        diagnostics::monitor_ldaps_certificate,
+        first_run::FirstRunGate,
+        ldap_codec::{
+            DecodedMessage, LdapDecodeError, LdapResponse, LdapResponseCodec, LimitedLdapCodec,
+            RequestControls, ResponseExtensions,
+        },
+        ldap_connections::{LdapConnectionRegistry, RegisteredConnection},
+        ldap_handler::{make_unsupported_operation_response, LdapHandler},
+        ocsp::{refresh_ocsp_staple, OcspStaplingResolver},
     },
 };
 use actix_rt::net::TcpStream;
 use actix_server::ServerBuilder;
 use actix_service::{fn_service, ServiceFactoryExt};
-use anyhow::{anyhow, Context, Result};
-use ldap3_proto::{proto::LdapMsg, LdapCodec};
+use anyhow::{anyhow, bail, Context, Result};
+use ldap3_proto::proto::{LdapExtendedResponse, LdapMsg, LdapOp, LdapResult, LdapResultCode};
 use rustls::PrivateKey;
+use std::collections::VecDeque;
 use tokio_rustls::TlsAcceptor as RustlsTlsAcceptor;
 use tokio_util::codec::{FramedRead, FramedWrite};
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
+
+type LdapRequest = Result<DecodedMessage, LdapDecodeError>;
+
+/// The APPLICATION tag of an unbind request.
+const UNBIND_REQUEST_TAG: u8 = 0x42;
+
+/// How long an LDAPS client has to complete the TLS handshake, so that slow or silent clients
+/// can't hold connections open.
+const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Checks, without blocking, whether the client abandoned the request `msgid`. The other requests
+/// received in the meantime are queued in `pending`.
+fn is_abandoned<Requests>(
+    msgid: i32,
+    requests: &mut Requests,
+    pending: &mut VecDeque<LdapRequest>,
+) -> bool
+where
+    Requests: futures_util::Stream<Item = LdapRequest> + Unpin,
+{
+    use futures_util::{FutureExt, StreamExt};
+    while let Some(Some(msg)) = requests.next().now_or_never() {
+        match msg {
+            Ok(DecodedMessage::Supported(LdapMsg {
+                op: LdapOp::AbandonRequest(id),
+                ..
+            })) if id == msgid => return true,
+            msg => pending.push_back(msg),
+        }
+    }
+    false
+}
+
+/// Sends the responses one by one, and stops as soon as the request is abandoned.
+async fn send_responses<Writer, Requests>(
+    msgid: i32,
+    responses: impl IntoIterator<Item = LdapOp>,
+    resp: &mut Writer,
+    requests: &mut Requests,
+    pending: &mut VecDeque<LdapRequest>,
+) -> Result<()>
+where
+    Writer: futures_util::Sink<LdapMsg> + Unpin,
+    <Writer as futures_util::Sink<LdapMsg>>::Error: std::error::Error + Send + Sync + 'static,
+    Requests: futures_util::Stream<Item = LdapRequest> + Unpin,
+{
+    use futures_util::SinkExt;
+    for response in responses {
+        debug!(?response);
+        resp.send(LdapMsg {
+            msgid,
+            op: response,
+            ctrl: vec![],
+        })
+        .await
+        .context("while sending a response: {:#}")?;
+        if is_abandoned(msgid, requests, pending) {
+            debug!("Request abandoned by the client");
+            break;
+        }
+    }
+    resp.flush().await.context("while flushing responses: {:#}")
+}
+
+/// Sends the responses, with the response controls and result code on the last one.
+async fn send_responses_with_extensions<Writer>(
+    msgid: i32,
+    responses: impl IntoIterator<Item = LdapOp>,
+    mut extensions: ResponseExtensions,
+    resp: &mut Writer,
+) -> Result<()>
+where
+    Writer: futures_util::Sink<LdapResponse> + Unpin,
+    <Writer as futures_util::Sink<LdapResponse>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    use futures_util::SinkExt;
+    let mut responses = responses.into_iter().peekable();
+    while let Some(response) = responses.next() {
+        let ResponseExtensions {
+            controls,
+            result_code,
+        } = match responses.peek() {
+            None => std::mem::take(&mut extensions),
+            Some(_) => ResponseExtensions::default(),
+        };
+        debug!(?response, ?controls, ?result_code);
+        resp.send(LdapResponse {
+            msg: LdapMsg {
+                msgid,
+                op: response,
+                ctrl: vec![],
+            },
+            controls,
+            result_code,
+        })
+        .await
+        .context("while sending a response: {:#}")?;
+    }
+    resp.flush().await.context("while flushing responses: {:#}")
+}
+
+/// Tells the client that the server is closing the connection (RFC 4511, section 4.4.1).
+async fn send_notice_of_disconnection<Writer>(
+    resp: &mut Writer,
+    code: LdapResultCode,
+    message: String,
+) -> Result<()>
+where
+    Writer: futures_util::Sink<LdapMsg> + Unpin,
+    <Writer as futures_util::Sink<LdapMsg>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    use futures_util::SinkExt;
+    resp.send(LdapMsg {
+        msgid: 0,
+        op: LdapOp::ExtendedResponse(LdapExtendedResponse {
+            res: LdapResult {
+                code,
+                matcheddn: "".to_string(),
+                message,
+                referral: vec![],
+            },
+            name: Some("1.3.6.1.4.1.1466.20036".to_string()),
+            value: None,
+        }),
+        ctrl: vec![],
+    })
+    .await
+    .context("while sending the notice of disconnection")
+}
 
 #[instrument(skip_all, level = "info", name = "LDAP request")]
-async fn handle_ldap_message<Backend, Writer>(
-    msg: Result<LdapMsg, std::io::Error>,
+async fn handle_ldap_message<Backend, Writer, Requests>(
+    msg: LdapMsg,
+    request_controls: Option<&RequestControls>,
     resp: &mut Writer,
     session: &mut LdapHandler<Backend>,
+    requests: &mut Requests,
+    pending: &mut VecDeque<LdapRequest>,
 ) -> Result<bool>
 where
     Backend: BackendHandler + LoginHandler + OpaqueHandler,
-    Writer: futures_util::Sink<LdapMsg> + Unpin,
+    Writer: futures_util::Sink<LdapMsg> + futures_util::Sink<LdapResponse> + Unpin,
     <Writer as futures_util::Sink<LdapMsg>>::Error: std::error::Error + Send + Sync + 'static,
+    <Writer as futures_util::Sink<LdapResponse>>::Error: std::error::Error + Send + Sync + 'static,
+    Requests: futures_util::Stream<Item = LdapRequest> + Unpin,
 {
-    use futures_util::SinkExt;
-    let msg = msg.context("while receiving LDAP op")?;
-    debug!(?msg);
-    match session.handle_ldap_message(msg.op).await {
+    debug!(?msg, ?request_controls);
+    let responses = match request_controls {
+        None => session
+            .handle_ldap_message_lazy(msg.op, &msg.ctrl)
+            .await
+            .map(|result| (result, Vec::new())),
+        Some(request_controls) => {
+            session
+                .handle_ldap_message_with_request_controls(msg.op, &msg.ctrl, request_controls)
+                .await
+        }
+    };
+    match responses {
         None => return Ok(false),
-        Some(result) => {
-            if result.is_empty() {
+        Some((result, extensions)) if !extensions.is_empty() => {
+            send_responses_with_extensions(msg.msgid, result, extensions, resp).await?
+        }
+        Some((result, _)) => {
+            let mut result = result.peekable();
+            if result.peek().is_none() {
                 debug!("No response");
             }
-            for response in result.into_iter() {
-                debug!(?response);
-                resp.send(LdapMsg {
-                    msgid: msg.msgid,
-                    op: response,
-                    ctrl: vec![],
-                })
-                .await
-                .context("while sending a response: {:#}")?
-            }
-
-            resp.flush()
-                .await
-                .context("while flushing responses: {:#}")?
+            send_responses(msg.msgid, result, resp, requests, pending).await?
         }
     }
     Ok(true)
 }
 
+/// Settings shared by all the LDAP sessions, whatever the listener they come from.
+#[derive(Clone)]
+pub(crate) struct LdapSessionSettings<Backend> {
+    pub backend_handler: AccessControlledBackendHandler<Backend>,
+    pub ldap_base_dn: String,
+    pub ignored_user_attributes: Vec<String>,
+    pub ignored_group_attributes: Vec<String>,
+    pub search_options: LdapSearchOptions,
+    pub max_pdu_size: usize,
+    pub idle_timeout: Option<std::time::Duration>,
+    pub operation_timeout: Option<std::time::Duration>,
+    pub first_run: FirstRunGate,
+    pub login_banner: Option<String>,
+}
+
+impl<Backend> LdapSessionSettings<Backend> {
+    /// Settings with no timeout, no banner and the default limits.
+    #[cfg(test)]
+    pub fn new(
+        backend_handler: AccessControlledBackendHandler<Backend>,
+        ldap_base_dn: String,
+    ) -> Self {
+        Self {
+            backend_handler,
+            ldap_base_dn,
+            ignored_user_attributes: Vec::new(),
+            ignored_group_attributes: Vec::new(),
+            search_options: LdapSearchOptions::default(),
+            max_pdu_size: 16 * 1024 * 1024,
+            idle_timeout: None,
+            operation_timeout: None,
+            first_run: FirstRunGate::default(),
+            login_banner: None,
+        }
+    }
+
+    pub fn from_config(
+        config: &Configuration,
+        backend_handler: Backend,
+        first_run: FirstRunGate,
+    ) -> Self {
+        Self {
+            backend_handler: AccessControlledBackendHandler::new(backend_handler)
+                .with_admin_groups(config.admin_groups.clone()),
+            ldap_base_dn: config.ldap_base_dn.clone(),
+            ignored_user_attributes: config.ignored_user_attributes.clone(),
+            ignored_group_attributes: config.ignored_group_attributes.clone(),
+            search_options: config.ldap_search_options.clone(),
+            max_pdu_size: config.ldap_max_pdu_size,
+            idle_timeout: config
+                .ldap_idle_timeout_seconds
+                .map(std::time::Duration::from_secs),
+            operation_timeout: config
+                .ldap_operation_timeout_seconds
+                .map(std::time::Duration::from_secs),
+            first_run,
+            login_banner: config
+                .login_banner
+                .clone()
+                .filter(|banner| !banner.is_empty()),
+        }
+    }
+}
+
 #[instrument(skip_all, level = "info", name = "LDAP session")]
-async fn handle_ldap_stream<Stream, Backend>(
+pub(crate) async fn handle_ldap_stream<Stream, Backend>(
     stream: Stream,
-    backend_handler: Backend,
-    ldap_base_dn: String,
-    ignored_user_attributes: Vec<String>,
-    ignored_group_attributes: Vec<String>,
+    settings: LdapSessionSettings<Backend>,
+    connection: RegisteredConnection,
 ) -> Result<Stream>
 where
     Backend: BackendHandler + LoginHandler + OpaqueHandler + 'static,
     Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite + std::marker::Unpin,
 {
     use tokio_stream::StreamExt;
+    let LdapSessionSettings {
+        backend_handler,
+        ldap_base_dn,
+        ignored_user_attributes,
+        ignored_group_attributes,
+        search_options,
+        max_pdu_size,
+        idle_timeout,
+        operation_timeout,
+        first_run,
+        login_banner,
+    } = settings;
     let (r, w) = tokio::io::split(stream);
     // Configure the codec etc.
-    let mut requests = FramedRead::new(r, LdapCodec);
-    let mut resp = FramedWrite::new(w, LdapCodec);
+    let mut requests = FramedRead::new(r, LimitedLdapCodec::new(max_pdu_size));
+    let mut resp = FramedWrite::new(w, LdapResponseCodec);
 
     let mut session = LdapHandler::new(
-        AccessControlledBackendHandler::new(backend_handler),
+        backend_handler,
         ldap_base_dn,
         ignored_user_attributes,
         ignored_group_attributes,
-    );
+        &search_options,
+    )
+    .with_first_run_gate(first_run)
+    .with_operation_timeout(operation_timeout)
+    .with_login_banner(login_banner);
 
-    while let Some(msg) = requests.next().await {
-        if !handle_ldap_message(msg, &mut resp, &mut session)
-            .await
-            .context("while handling incoming messages")?
-        {
+    let mut pending = VecDeque::new();
+    loop {
+        let msg = match pending.pop_front() {
+            Some(msg) => msg,
+            None => {
+                // None if the connection was idle for too long.
+                let next = async {
+                    match idle_timeout {
+                        Some(idle_timeout) => tokio::time::timeout(idle_timeout, requests.next())
+                            .await
+                            .ok(),
+                        None => Some(requests.next().await),
+                    }
+                };
+                let next = tokio::select! {
+                    next = next => next,
+                    () = connection.shutdown_requested() => {
+                        debug!(
+                            source_ip = ?connection.source_ip(),
+                            "Server shutting down, closing the LDAP connection"
+                        );
+                        send_notice_of_disconnection(
+                            &mut resp,
+                            LdapResultCode::Unavailable,
+                            "The server is shutting down".to_string(),
+                        )
+                        .await?;
+                        break;
+                    }
+                };
+                match next {
+                    Some(Some(msg)) => msg,
+                    Some(None) => break,
+                    None => {
+                        debug!(
+                            source_ip = ?connection.source_ip(),
+                            "Closing idle LDAP connection"
+                        );
+                        break;
+                    }
+                }
+            }
+        };
+        let (msg, request_controls) = match msg {
+            Ok(DecodedMessage::Supported(msg)) => (msg, None),
+            Ok(DecodedMessage::WithControls { msg, controls }) => (msg, Some(controls)),
+            Ok(DecodedMessage::Unsupported { msgid, op_tag }) => {
+                warn!(
+                    source_ip = ?connection.source_ip(),
+                    msgid,
+                    "Unsupported LDAP operation with tag {:#04x}", op_tag
+                );
+                if op_tag == UNBIND_REQUEST_TAG {
+                    break;
+                }
+                if let Some(response) = make_unsupported_operation_response(op_tag) {
+                    send_responses(
+                        msgid,
+                        vec![response],
+                        &mut resp,
+                        &mut requests,
+                        &mut pending,
+                    )
+                    .await
+                    .context("while handling incoming messages")?;
+                }
+                connection.record_operation(session.get_bound_dn());
+                continue;
+            }
+            Err(LdapDecodeError::Io(e)) => {
+                return Err(e).context("while reading the next LDAP message from the socket")
+            }
+            Err(e) => {
+                warn!(
+                    source_ip = ?connection.source_ip(),
+                    "Invalid LDAP message, closing the connection: {}", e
+                );
+                // A truncated message means the client already closed the connection.
+                if !matches!(e, LdapDecodeError::Truncated(_)) {
+                    send_notice_of_disconnection(
+                        &mut resp,
+                        LdapResultCode::ProtocolError,
+                        e.to_string(),
+                    )
+                    .await?;
+                }
+                break;
+            }
+        };
+        let keep_going = handle_ldap_message(
+            msg,
+            request_controls.as_ref(),
+            &mut resp,
+            &mut session,
+            &mut requests,
+            &mut pending,
+        )
+        .await
+        .context("while handling incoming messages")?;
+        connection.record_operation(session.get_bound_dn());
+        if !keep_going {
             break;
         }
     }
@@ -139,58 +458,339 @@ pub fn read_certificates(
     Ok((certs, private_key))
 }
 
+fn write_new_file(path: &str, contents: &[u8], private: bool) -> Result<()> {
+    use std::{fs::OpenOptions, io::Write};
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("while writing {}", path))
+}
+
+/// Checks that generating a certificate wouldn't overwrite the files configured in
+/// `ldaps_options`, before doing anything else.
+pub fn check_certificate_paths(ldaps_options: &LdapsOptions) -> Result<()> {
+    for path in [&ldaps_options.cert_file, &ldaps_options.key_file] {
+        if std::path::Path::new(path).exists() {
+            return Err(anyhow!("Refusing to overwrite the existing file {}", path));
+        }
+    }
+    Ok(())
+}
+
+/// Writes a self-signed certificate and its private key to the files configured in
+/// `ldaps_options`. Existing files are not overwritten.
+pub fn generate_self_signed_certificate(
+    ldaps_options: &LdapsOptions,
+    common_name: &str,
+    subject_alt_names: Vec<String>,
+    validity_days: u32,
+) -> Result<()> {
+    use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+    check_certificate_paths(ldaps_options)?;
+    let mut params = CertificateParams::default();
+    // The IP addresses must be IP SANs, the clients don't match them against DNS names.
+    params.subject_alt_names = subject_alt_names
+        .into_iter()
+        .map(|name| match name.parse::<std::net::IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(name),
+        })
+        .collect();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    params.not_before = time::OffsetDateTime::now_utc();
+    params.not_after = params.not_before + time::Duration::days(validity_days.into());
+    let certificate =
+        Certificate::from_params(params).context("while generating the certificate")?;
+    write_new_file(
+        &ldaps_options.key_file,
+        certificate.serialize_private_key_pem().as_bytes(),
+        true,
+    )?;
+    write_new_file(
+        &ldaps_options.cert_file,
+        certificate.serialize_pem()?.as_bytes(),
+        false,
+    )
+}
+
+/// Reads the CA certificates that the client certificates must be issued by.
+fn read_client_ca_certificates(ca_file: &str) -> Result<rustls::RootCertStore> {
+    use std::{fs::File, io::BufReader};
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(ca_file)?))?;
+    let mut roots = rustls::RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(&certs);
+    if ignored > 0 {
+        warn!("Ignored {} invalid CA certificates in {}", ignored, ca_file);
+    }
+    if added == 0 {
+        bail!("No CA certificate in {}", ca_file);
+    }
+    Ok(roots)
+}
+
 fn get_tls_acceptor(ldaps_options: &LdapsOptions) -> Result<RustlsTlsAcceptor> {
     let (certs, private_key) = read_certificates(ldaps_options)?;
-    let server_config = std::sync::Arc::new(
-        rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certs, private_key)?,
-    );
-    Ok(server_config.into())
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = if ldaps_options.require_client_cert {
+        let ca_file = ldaps_options
+            .client_ca_file
+            .as_deref()
+            .context("The client_ca_file is required to check the client certificates")?;
+        let roots = read_client_ca_certificates(ca_file)
+            .with_context(|| format!("while reading the client CA file {}", ca_file))?;
+        builder.with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(roots))
+    } else {
+        builder.with_no_client_auth()
+    };
+    let server_config = if ldaps_options.ocsp_stapling {
+        let resolver = std::sync::Arc::new(OcspStaplingResolver::new(certs, &private_key)?);
+        actix_rt::spawn(refresh_ocsp_staple(
+            resolver.clone(),
+            ldaps_options.ocsp_responder_url.clone(),
+        ));
+        builder.with_cert_resolver(resolver)
+    } else {
+        builder.with_single_cert(certs, private_key)?
+    };
+    Ok(std::sync::Arc::new(server_config).into())
+}
+
+async fn accept_tls<IO>(
+    acceptor: &RustlsTlsAcceptor,
+    stream: IO,
+    timeout: std::time::Duration,
+) -> Result<tokio_rustls::server::TlsStream<IO>>
+where
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    tokio::time::timeout(timeout, acceptor.accept(stream))
+        .await
+        .map_err(|_| anyhow!("The TLS handshake took longer than {:?}", timeout))?
+        .context("TLS handshake failed")
+}
+
+/// Enables TCP keepalive, so that the connections of vanished clients are eventually closed, and
+/// optionally TCP_NODELAY.
+fn configure_stream(stream: &TcpStream, tcp_nodelay: bool) {
+    let socket = socket2::SockRef::from(stream);
+    if let Err(e) = socket.set_keepalive(true) {
+        warn!("Could not enable TCP keepalive: {:#}", e);
+    }
+    if tcp_nodelay {
+        if let Err(e) = socket.set_nodelay(true) {
+            warn!("Could not enable TCP_NODELAY: {:#}", e);
+        }
+    }
+}
+
+/// Creates the listening sockets for all the addresses of the host, with the configured backlog
+/// and options.
+fn create_listeners(
+    config: &Configuration,
+    host: &str,
+    port: u16,
+) -> Result<Vec<std::net::TcpListener>> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    use std::net::ToSocketAddrs;
+    let backlog = i32::try_from(config.ldap_listen_backlog).unwrap_or(i32::MAX);
+    (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("while resolving {}", host))?
+        .map(|address| -> Result<std::net::TcpListener> {
+            let socket = Socket::new(
+                Domain::for_address(address),
+                Type::STREAM,
+                Some(Protocol::TCP),
+            )?;
+            socket.set_reuse_address(config.ldap_reuse_address)?;
+            socket.bind(&address.into())?;
+            socket.listen(backlog)?;
+            socket.set_nonblocking(true)?;
+            Ok(socket.into())
+        })
+        .collect()
+}
+
+/// Creates the Unix domain socket of the LDAP server, replacing the one left by a previous run.
+/// With `permissions`, the socket is bound in a private directory and only moved to `path` once
+/// it has them, so that it's never reachable with the default ones.
+#[cfg(unix)]
+fn create_unix_listener(
+    path: &str,
+    permissions: Option<u32>,
+) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} already exists and is not a socket", path);
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = match permissions {
+        None => std::os::unix::net::UnixListener::bind(path)?,
+        Some(mode) => {
+            let path = std::path::Path::new(path);
+            let private_dir = path.with_file_name(format!(
+                ".{}.{}",
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .context("Invalid socket path")?,
+                std::process::id()
+            ));
+            // Left by a previous run that crashed, e.g. as PID 1 in a container.
+            if std::fs::symlink_metadata(&private_dir).is_ok() {
+                std::fs::remove_dir_all(&private_dir)?;
+            }
+            std::fs::DirBuilder::new()
+                .mode(0o700)
+                .create(&private_dir)
+                .with_context(|| format!("while creating {}", private_dir.display()))?;
+            let bind = || -> Result<std::os::unix::net::UnixListener> {
+                let private_path = private_dir.join("socket");
+                let listener = std::os::unix::net::UnixListener::bind(&private_path)?;
+                std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(mode))?;
+                std::fs::rename(&private_path, path)?;
+                Ok(listener)
+            };
+            let listener = bind();
+            // Anything left behind by a failure goes with it.
+            std::fs::remove_dir_all(&private_dir)?;
+            listener?
+        }
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+async fn wait_for_shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Sends a notice of disconnection to the connected clients when the server is asked to stop,
+/// instead of just closing the sockets.
+pub async fn disconnect_on_shutdown(connections: LdapConnectionRegistry) {
+    match wait_for_shutdown_signal().await {
+        Err(e) => warn!("Could not listen for the shutdown signals: {:#}", e),
+        Ok(()) => {
+            info!(
+                connections = connections.list().len(),
+                "Shutting down, closing the LDAP connections"
+            );
+            connections.shutdown();
+        }
+    }
 }
 
 pub fn build_ldap_server<Backend>(
     config: &Configuration,
     backend_handler: Backend,
+    connections: LdapConnectionRegistry,
+    first_run: FirstRunGate,
     server_builder: ServerBuilder,
 ) -> Result<ServerBuilder>
 where
     Backend: BackendHandler + LoginHandler + OpaqueHandler + Clone + 'static,
 {
+    actix_rt::spawn(disconnect_on_shutdown(connections.clone()));
     let context = (
-        backend_handler,
-        config.ldap_base_dn.clone(),
-        config.ignored_user_attributes.clone(),
-        config.ignored_group_attributes.clone(),
+        LdapSessionSettings::from_config(config, backend_handler, first_run),
+        connections,
     );
 
     let context_for_tls = context.clone();
+    #[cfg(unix)]
+    let context_for_unix = context.clone();
+    let tcp_nodelay = config.ldap_tcp_nodelay;
 
     let binder = move || {
         let context = context.clone();
         fn_service(move |stream: TcpStream| {
             let context = context.clone();
             async move {
-                let (handler, base_dn, ignored_user_attributes, ignored_group_attributes) = context;
-                handle_ldap_stream(
-                    stream,
-                    handler,
-                    base_dn,
-                    ignored_user_attributes,
-                    ignored_group_attributes,
-                )
-                .await
+                let (settings, connections) = context;
+                let connection = connections.register(stream.peer_addr().ok().map(|a| a.ip()))?;
+                configure_stream(&stream, tcp_nodelay);
+                handle_ldap_stream(stream, settings, connection).await
             }
         })
         .map_err(|err: anyhow::Error| error!("[LDAP] Service Error: {:#}", err))
     };
 
-    info!("Starting the LDAP server on port {}", config.ldap_port);
-    let server_builder = server_builder
-        .bind("ldap", (config.ldap_host.clone(), config.ldap_port), binder)
-        .with_context(|| format!("while binding to the port {}", config.ldap_port));
+    let mut server_builder = server_builder;
+    if !config.ldap_unix_socket_only {
+        info!("Starting the LDAP server on port {}", config.ldap_port);
+        for listener in create_listeners(config, &config.ldap_host, config.ldap_port)
+            .with_context(|| format!("while binding to the port {}", config.ldap_port))?
+        {
+            server_builder = server_builder
+                .listen("ldap", listener, binder.clone())
+                .with_context(|| format!("while binding to the port {}", config.ldap_port))?;
+        }
+    }
+    if let Some(path) = &config.ldap_unix_socket_path {
+        #[cfg(unix)]
+        {
+            let unix_binder = move || {
+                let context = context_for_unix.clone();
+                fn_service(move |stream: actix_rt::net::UnixStream| {
+                    let context = context.clone();
+                    async move {
+                        let (settings, connections) = context;
+                        let connection = connections.register(None)?;
+                        handle_ldap_stream(stream, settings, connection).await
+                    }
+                })
+                .map_err(|err: anyhow::Error| error!("[LDAP] Service Error: {:#}", err))
+            };
+            let permissions = config
+                .ldap_unix_socket_permissions
+                .as_deref()
+                .map(parse_unix_socket_permissions)
+                .transpose()?;
+            info!("Starting the LDAP server on the Unix socket {}", path);
+            server_builder = server_builder
+                .listen_uds(
+                    "ldap_unix",
+                    create_unix_listener(path, permissions)
+                        .with_context(|| format!("while creating the Unix socket {}", path))?,
+                    unix_binder,
+                )
+                .with_context(|| format!("while listening on the Unix socket {}", path))?;
+        }
+        #[cfg(not(unix))]
+        bail!(
+            "Unix domain sockets are not supported on this platform: {}",
+            path
+        );
+    }
     if config.ldaps_options.enabled {
+        actix_rt::spawn(monitor_ldaps_certificate(
+            config.ldaps_options.clone(),
+            config.smtp_options.clone(),
+            config.http_url.clone(),
+        ));
         let tls_context = (
             context_for_tls,
             get_tls_acceptor(&config.ldaps_options)
@@ -201,19 +801,13 @@ where
             fn_service(move |stream: TcpStream| {
                 let tls_context = tls_context.clone();
                 async move {
-                    let (
-                        (handler, base_dn, ignored_user_attributes, ignored_group_attributes),
-                        tls_acceptor,
-                    ) = tls_context;
-                    let tls_stream = tls_acceptor.accept(stream).await?;
-                    handle_ldap_stream(
-                        tls_stream,
-                        handler,
-                        base_dn,
-                        ignored_user_attributes,
-                        ignored_group_attributes,
-                    )
-                    .await
+                    let ((settings, connections), tls_acceptor) = tls_context;
+                    let connection =
+                        connections.register(stream.peer_addr().ok().map(|a| a.ip()))?;
+                    configure_stream(&stream, tcp_nodelay);
+                    let tls_stream =
+                        accept_tls(&tls_acceptor, stream, TLS_HANDSHAKE_TIMEOUT).await?;
+                    handle_ldap_stream(tls_stream, settings, connection).await
                 }
             })
             .map_err(|err: anyhow::Error| error!("[LDAPS] Service Error: {:#}", err))
@@ -223,15 +817,671 @@ where
             "Starting the LDAPS server on port {}",
             config.ldaps_options.port
         );
-        server_builder.and_then(|s| {
-            s.bind(
-                "ldaps",
-                (config.ldap_host.clone(), config.ldaps_options.port),
-                tls_binder,
+        let port = config.ldaps_options.port;
+        for listener in create_listeners(config, &config.ldap_host, port)
+            .with_context(|| format!("while binding to the port {}", port))?
+        {
+            server_builder = server_builder
+                .listen("ldaps", listener, tls_binder.clone())
+                .with_context(|| format!("while binding to the port {}", port))?;
+        }
+    }
+    Ok(server_builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::sql_backend_handler::{
+            tests::{get_default_config, get_initialized_db, insert_user},
+            SqlBackendHandler,
+        },
+        infra::test_utils::MockTestBackendHandler,
+    };
+    use ldap3_proto::{
+        proto::{
+            LdapBindCred, LdapBindRequest, LdapBindResponse, LdapDerefAliases, LdapExtendedRequest,
+            LdapFilter, LdapSearchRequest, LdapSearchResultEntry, LdapSearchScope,
+        },
+        LdapCodec,
+    };
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    fn make_entry(name: &str) -> LdapOp {
+        LdapOp::SearchResultEntry(LdapSearchResultEntry {
+            dn: format!("uid={},ou=people,dc=example,dc=com", name),
+            attributes: vec![],
+        })
+    }
+
+    #[test]
+    fn test_generate_self_signed_certificate() {
+        let dir = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let ldaps_options = LdapsOptions {
+            cert_file: dir.join("cert.pem").to_str().unwrap().to_owned(),
+            key_file: dir.join("key.pem").to_str().unwrap().to_owned(),
+            ..Default::default()
+        };
+        generate_self_signed_certificate(
+            &ldaps_options,
+            "ldap.example.com",
+            vec!["ldap.example.com".to_owned(), "localhost".to_owned()],
+            30,
+        )
+        .unwrap();
+        let (certs, _) = read_certificates(&ldaps_options).unwrap();
+        assert_eq!(certs.len(), 1);
+        get_tls_acceptor(&ldaps_options).unwrap();
+        std::fs::remove_file(&ldaps_options.cert_file).unwrap();
+        std::fs::remove_file(&ldaps_options.key_file).unwrap();
+        // The IP addresses are IP SANs.
+        generate_self_signed_certificate(
+            &ldaps_options,
+            "ldap.example.com",
+            vec!["ldap.example.com".to_owned(), "127.0.0.1".to_owned()],
+            30,
+        )
+        .unwrap();
+        let (certs, _) = read_certificates(&ldaps_options).unwrap();
+        {
+            use x509_parser::prelude::GeneralName;
+            let (_, cert) = x509_parser::parse_x509_certificate(&certs[0].0).unwrap();
+            assert_eq!(
+                cert.subject_alternative_name()
+                    .unwrap()
+                    .unwrap()
+                    .value
+                    .general_names,
+                vec![
+                    GeneralName::DNSName("ldap.example.com"),
+                    GeneralName::IPAddress(&[127, 0, 0, 1]),
+                ]
+            );
+        }
+        // The files are not overwritten.
+        generate_self_signed_certificate(&ldaps_options, "localhost", vec![], 30).unwrap_err();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_listeners() {
+        let mut config = get_default_config();
+        config.ldap_listen_backlog = 200;
+        let listeners = create_listeners(&config, "127.0.0.1", 0).unwrap();
+        assert_eq!(listeners.len(), 1);
+        let listener = &listeners[0];
+        assert!(socket2::SockRef::from(listener).reuse_address().unwrap());
+        // The backlog itself can't be read back, but that many connections can wait to be
+        // accepted.
+        let address = listener.local_addr().unwrap();
+        let _pending = (0..100)
+            .map(|_| {
+                std::net::TcpStream::connect_timeout(&address, std::time::Duration::from_secs(1))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        config.ldap_reuse_address = false;
+        let listeners = create_listeners(&config, "127.0.0.1", 0).unwrap();
+        assert!(!socket2::SockRef::from(&listeners[0])
+            .reuse_address()
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_abandon_search() {
+        let responses = vec![
+            make_entry("bob"),
+            make_entry("john"),
+            LdapOp::SearchResultDone(LdapResult {
+                code: LdapResultCode::Success,
+                matcheddn: "".to_string(),
+                message: "".to_string(),
+                referral: vec![],
+            }),
+        ];
+        let mut requests = futures_util::stream::iter(vec![
+            Ok(DecodedMessage::Supported(LdapMsg {
+                msgid: 3,
+                op: LdapOp::UnbindRequest,
+                ctrl: vec![],
+            })),
+            Ok(DecodedMessage::Supported(LdapMsg {
+                msgid: 4,
+                op: LdapOp::AbandonRequest(2),
+                ctrl: vec![],
+            })),
+        ]);
+        let mut pending = VecDeque::new();
+        let mut sent: Vec<LdapMsg> = Vec::new();
+        send_responses(2, responses, &mut sent, &mut requests, &mut pending)
+            .await
+            .unwrap();
+        assert_eq!(
+            sent,
+            vec![LdapMsg {
+                msgid: 2,
+                op: make_entry("bob"),
+                ctrl: vec![],
+            }]
+        );
+        // The other requests are kept for later.
+        assert_eq!(pending.len(), 1);
+        assert!(matches!(
+            pending.pop_front(),
+            Some(Ok(DecodedMessage::Supported(LdapMsg {
+                op: LdapOp::UnbindRequest,
+                ..
+            })))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_abandon_other_request() {
+        let mut requests =
+            futures_util::stream::iter(vec![Ok(DecodedMessage::Supported(LdapMsg {
+                msgid: 4,
+                op: LdapOp::AbandonRequest(1),
+                ctrl: vec![],
+            }))]);
+        let mut pending = VecDeque::new();
+        let mut sent: Vec<LdapMsg> = Vec::new();
+        send_responses(
+            2,
+            vec![make_entry("bob"), make_entry("john")],
+            &mut sent,
+            &mut requests,
+            &mut pending,
+        )
+        .await
+        .unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_registry() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        let registry = LdapConnectionRegistry::default();
+        let (client, server) = tokio::io::duplex(4096);
+        let client_registry = registry.clone();
+        let client = async move {
+            use futures_util::{SinkExt, StreamExt};
+            let (r, w) = tokio::io::split(client);
+            let mut responses = FramedRead::new(r, LdapCodec);
+            let mut requests = FramedWrite::new(w, LdapCodec);
+            requests
+                .send(LdapMsg {
+                    msgid: 1,
+                    op: LdapOp::BindRequest(LdapBindRequest {
+                        dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                        cred: LdapBindCred::Simple("bob00000".to_string()),
+                    }),
+                    ctrl: vec![],
+                })
+                .await
+                .unwrap();
+            let response = responses.next().await.unwrap().unwrap();
+            assert!(matches!(
+                response.op,
+                LdapOp::BindResponse(LdapBindResponse {
+                    res: LdapResult {
+                        code: LdapResultCode::Success,
+                        ..
+                    },
+                    ..
+                })
+            ));
+            let connections = client_registry.list();
+            assert_eq!(connections.len(), 1);
+            assert_eq!(
+                connections[0].bound_dn.as_deref(),
+                Some("uid=bob,ou=people,dc=example,dc=com")
+            );
+            assert_eq!(connections[0].operation_count, 1);
+            // Dropping the client closes the connection.
+        };
+        let (server, ()) = tokio::join!(
+            handle_ldap_stream(
+                server,
+                LdapSessionSettings::new(
+                    AccessControlledBackendHandler::new(handler),
+                    "dc=example,dc=com".to_string()
+                ),
+                registry.register(None).unwrap(),
+            ),
+            client
+        );
+        server.unwrap();
+        assert!(registry.list().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket() {
+        use futures_util::{SinkExt, StreamExt};
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("ldap.sock").to_str().unwrap().to_owned();
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_user(&handler, "bob", "bob00000").await;
+        // A socket left by a previous run is replaced.
+        drop(create_unix_listener(&path, None).unwrap());
+        let listener =
+            tokio::net::UnixListener::from_std(create_unix_listener(&path, Some(0o600)).unwrap())
+                .unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // Only the socket is left in the directory.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let client = async {
+            let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+            let (r, w) = tokio::io::split(stream);
+            let mut responses = FramedRead::new(r, LdapCodec);
+            let mut requests = FramedWrite::new(w, LdapCodec);
+            requests
+                .send(LdapMsg {
+                    msgid: 1,
+                    op: LdapOp::BindRequest(LdapBindRequest {
+                        dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                        cred: LdapBindCred::Simple("bob00000".to_string()),
+                    }),
+                    ctrl: vec![],
+                })
+                .await
+                .unwrap();
+            assert!(matches!(
+                responses.next().await.unwrap().unwrap().op,
+                LdapOp::BindResponse(LdapBindResponse {
+                    res: LdapResult {
+                        code: LdapResultCode::Success,
+                        ..
+                    },
+                    ..
+                })
+            ));
+            requests
+                .send(LdapMsg {
+                    msgid: 2,
+                    op: LdapOp::SearchRequest(LdapSearchRequest {
+                        base: "ou=people,dc=example,dc=com".to_string(),
+                        scope: LdapSearchScope::Subtree,
+                        aliases: LdapDerefAliases::Never,
+                        sizelimit: 0,
+                        timelimit: 0,
+                        typesonly: false,
+                        filter: LdapFilter::Equality("uid".to_string(), "bob".to_string()),
+                        attrs: vec!["uid".to_string()],
+                    }),
+                    ctrl: vec![],
+                })
+                .await
+                .unwrap();
+            match responses.next().await.unwrap().unwrap().op {
+                LdapOp::SearchResultEntry(entry) => {
+                    assert_eq!(entry.dn, "uid=bob,ou=people,dc=example,dc=com")
+                }
+                op => panic!("Expected a search result entry, got {:?}", op),
+            }
+            assert!(matches!(
+                responses.next().await.unwrap().unwrap().op,
+                LdapOp::SearchResultDone(LdapResult {
+                    code: LdapResultCode::Success,
+                    ..
+                })
+            ));
+        };
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_ldap_stream(
+                stream,
+                LdapSessionSettings::new(
+                    AccessControlledBackendHandler::new(handler),
+                    "dc=example,dc=com".to_string(),
+                ),
+                LdapConnectionRegistry::default().register(None).unwrap(),
+            )
+            .await
+        };
+        let (server, ()) = tokio::join!(server, client);
+        server.unwrap();
+        // Other files are not replaced.
+        let file = dir.join("file").to_str().unwrap().to_owned();
+        std::fs::write(&file, b"").unwrap();
+        create_unix_listener(&file, None).unwrap_err();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn serve_with_mock(server: DuplexStream) -> Result<DuplexStream> {
+        serve_with_mock_and_timeout(server, None).await
+    }
+
+    async fn serve_with_mock_and_timeout(
+        server: DuplexStream,
+        idle_timeout: Option<std::time::Duration>,
+    ) -> Result<DuplexStream> {
+        handle_ldap_stream(
+            server,
+            LdapSessionSettings {
+                max_pdu_size: 1024,
+                idle_timeout,
+                ..LdapSessionSettings::new(
+                    AccessControlledBackendHandler::new(MockTestBackendHandler::new()),
+                    "dc=example,dc=com".to_string(),
+                )
+            },
+            LdapConnectionRegistry::default().register(None).unwrap(),
+        )
+        .await
+    }
+
+    fn make_unknown_extended_request(msgid: i32) -> LdapMsg {
+        LdapMsg {
+            msgid,
+            op: LdapOp::ExtendedRequest(LdapExtendedRequest {
+                name: "1.2.3.4".to_string(),
+                value: None,
+            }),
+            ctrl: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_closed() {
+        use futures_util::StreamExt;
+        let (client, server) = tokio::io::duplex(4096);
+        // The server returns without the client doing anything.
+        let server =
+            serve_with_mock_and_timeout(server, Some(std::time::Duration::from_millis(100)))
+                .await
+                .unwrap();
+        drop(server);
+        assert!(FramedRead::new(client, LdapCodec).next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_notice_of_disconnection_on_shutdown() {
+        use futures_util::StreamExt;
+        let (client, server) = tokio::io::duplex(4096);
+        let connections = LdapConnectionRegistry::default();
+        let connection = connections.register(None).unwrap();
+        let client = async {
+            let mut responses = FramedRead::new(client, LdapCodec);
+            connections.shutdown();
+            responses.next().await
+        };
+        let server = handle_ldap_stream(
+            server,
+            LdapSessionSettings {
+                max_pdu_size: 1024,
+                ..LdapSessionSettings::new(
+                    AccessControlledBackendHandler::new(MockTestBackendHandler::new()),
+                    "dc=example,dc=com".to_string(),
+                )
+            },
+            connection,
+        );
+        let (server, response) = tokio::join!(server, client);
+        server.unwrap();
+        let response = response.unwrap().unwrap();
+        assert_eq!(response.msgid, 0);
+        assert!(matches!(
+            response.op,
+            LdapOp::ExtendedResponse(LdapExtendedResponse {
+                res: LdapResult {
+                    code: LdapResultCode::Unavailable,
+                    ..
+                },
+                name: Some(name),
+                ..
+            }) if name == "1.3.6.1.4.1.1466.20036"
+        ));
+        // The connection was closed.
+        assert!(connections.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_active_connection_kept() {
+        let (client, server) = tokio::io::duplex(4096);
+        let client = async move {
+            use futures_util::{SinkExt, StreamExt};
+            let (r, w) = tokio::io::split(client);
+            let mut responses = FramedRead::new(r, LdapCodec);
+            let mut requests = FramedWrite::new(w, LdapCodec);
+            // Each request resets the timer, the connection outlives the timeout.
+            for msgid in 1..=4 {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                requests
+                    .send(make_unknown_extended_request(msgid))
+                    .await
+                    .unwrap();
+                assert_eq!(responses.next().await.unwrap().unwrap().msgid, msgid);
+            }
+        };
+        let (server, ()) = tokio::join!(
+            serve_with_mock_and_timeout(server, Some(std::time::Duration::from_millis(250))),
+            client
+        );
+        server.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message() {
+        let (client, server) = tokio::io::duplex(4096);
+        let client = async move {
+            use futures_util::StreamExt;
+            let (r, mut w) = tokio::io::split(client);
+            // A SEQUENCE announcing a length of 2000 bytes.
+            w.write_all(&[0x30, 0x82, 0x07, 0xd0, 0x02, 0x01, 0x01])
+                .await
+                .unwrap();
+            FramedRead::new(r, LdapCodec).next().await
+        };
+        let (server, response) = tokio::join!(serve_with_mock(server), client);
+        server.unwrap();
+        let response = response.unwrap().unwrap();
+        assert_eq!(response.msgid, 0);
+        assert!(matches!(
+            response.op,
+            LdapOp::ExtendedResponse(LdapExtendedResponse {
+                res: LdapResult {
+                    code: LdapResultCode::ProtocolError,
+                    ..
+                },
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_operations() {
+        let (client, server) = tokio::io::duplex(4096);
+        let client = async move {
+            use futures_util::{SinkExt, StreamExt};
+            let (r, mut w) = tokio::io::split(client);
+            let mut responses = FramedRead::new(r, LdapCodec);
+            // A SASL bind, which can't be decoded.
+            w.write_all(&[
+                0x30, 0x13, 0x02, 0x01, 0x05, 0x60, 0x0e, 0x02, 0x01, 0x03, 0x04, 0x00, 0xa3, 0x07,
+                0x04, 0x05, b'P', b'L', b'A', b'I', b'N',
+            ])
+            .await
+            .unwrap();
+            let response = responses.next().await.unwrap().unwrap();
+            assert_eq!(response.msgid, 5);
+            assert!(matches!(
+                response.op,
+                LdapOp::BindResponse(LdapBindResponse {
+                    res: LdapResult {
+                        code: LdapResultCode::AuthMethodNotSupported,
+                        ..
+                    },
+                    ..
+                })
+            ));
+            // The connection is still open for other requests.
+            let mut requests = FramedWrite::new(w, LdapCodec);
+            requests
+                .send(make_unknown_extended_request(6))
+                .await
+                .unwrap();
+            let response = responses.next().await.unwrap().unwrap();
+            assert_eq!(response.msgid, 6);
+            assert!(matches!(
+                response.op,
+                LdapOp::ExtendedResponse(LdapExtendedResponse {
+                    res: LdapResult {
+                        code: LdapResultCode::UnwillingToPerform,
+                        ..
+                    },
+                    ..
+                })
+            ));
+            requests
+                .send(make_unknown_extended_request(7))
+                .await
+                .unwrap();
+            assert_eq!(responses.next().await.unwrap().unwrap().msgid, 7);
+        };
+        let (server, ()) = tokio::join!(serve_with_mock(server), client);
+        server.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_truncated_message() {
+        use tokio_util::codec::Encoder;
+        let mut message = bytes::BytesMut::new();
+        LdapCodec
+            .encode(
+                LdapMsg {
+                    msgid: 1,
+                    op: LdapOp::BindRequest(LdapBindRequest {
+                        dn: "uid=bob,ou=people,dc=example,dc=com".to_string(),
+                        cred: LdapBindCred::Simple("bob00000".to_string()),
+                    }),
+                    ctrl: vec![],
+                },
+                &mut message,
             )
-            .with_context(|| format!("while binding to the port {}", config.ldaps_options.port))
+            .unwrap();
+        let (mut client, server) = tokio::io::duplex(4096);
+        let client = async move {
+            client
+                .write_all(&message[..message.len() / 2])
+                .await
+                .unwrap();
+            // The client goes away in the middle of the message.
+            client.shutdown().await.unwrap();
+            client
+        };
+        let (server, _client) = tokio::join!(serve_with_mock(server), client);
+        server.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_timeout() {
+        let dir = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        std::fs::write(path("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(path("key.pem"), cert.serialize_private_key_pem()).unwrap();
+        let acceptor = get_tls_acceptor(&LdapsOptions {
+            enabled: true,
+            cert_file: path("cert.pem"),
+            key_file: path("key.pem"),
+            ..Default::default()
         })
-    } else {
-        server_builder
+        .unwrap();
+        // The client connects but never starts the handshake.
+        let (_client, server) = tokio::io::duplex(16384);
+        let error = accept_tls(&acceptor, server, std::time::Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("took longer"), "{:#}", error);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Runs a TLS handshake with the acceptor, and returns whether the server accepted it.
+    async fn server_accepts_handshake(
+        acceptor: &RustlsTlsAcceptor,
+        client_config: rustls::ClientConfig,
+    ) -> bool {
+        let (client, server) = tokio::io::duplex(16384);
+        let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client_config));
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+        let (server, _client) = tokio::join!(
+            acceptor.accept(server),
+            connector.connect(server_name, client)
+        );
+        server.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_required() {
+        use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+        let make_ca = || {
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Certificate::from_params(params).unwrap()
+        };
+        let ca = make_ca();
+        let other_ca = make_ca();
+        let dir = std::env::temp_dir().join(format!("lldap_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        let server_cert =
+            Certificate::from_params(CertificateParams::new(vec!["localhost".to_owned()])).unwrap();
+        std::fs::write(path("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        std::fs::write(
+            path("cert.pem"),
+            server_cert.serialize_pem_with_signer(&ca).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(path("key.pem"), server_cert.serialize_private_key_pem()).unwrap();
+        let mut ldaps_options = LdapsOptions {
+            enabled: true,
+            cert_file: path("cert.pem"),
+            key_file: path("key.pem"),
+            require_client_cert: true,
+            ..Default::default()
+        };
+        // The CA file is required.
+        get_tls_acceptor(&ldaps_options).unwrap_err();
+        ldaps_options.client_ca_file = Some(path("ca.pem"));
+        let acceptor = get_tls_acceptor(&ldaps_options).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+            .unwrap();
+        let client_config = || {
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots.clone())
+        };
+        let with_client_cert = |signer: &Certificate| {
+            let client_cert = Certificate::from_params(CertificateParams::new(vec![])).unwrap();
+            client_config()
+                .with_single_cert(
+                    vec![rustls::Certificate(
+                        client_cert.serialize_der_with_signer(signer).unwrap(),
+                    )],
+                    rustls::PrivateKey(client_cert.serialize_private_key_der()),
+                )
+                .unwrap()
+        };
+        // No client certificate.
+        assert!(!server_accepts_handshake(&acceptor, client_config().with_no_client_auth()).await);
+        // A certificate from another CA.
+        assert!(!server_accepts_handshake(&acceptor, with_client_cert(&other_ca)).await);
+        assert!(server_accepts_handshake(&acceptor, with_client_cert(&ca)).await);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
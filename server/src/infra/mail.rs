@@ -1,28 +1,60 @@
-use crate::infra::{cli::SmtpEncryption, configuration::MailOptions};
-use anyhow::{anyhow, Ok, Result};
+use crate::infra::{
+    cli::SmtpEncryption,
+    configuration::{MailOptions, MailSenderOptions},
+    healthcheck::get_root_certificates,
+    proxy::{connect_through_proxy, outbound_proxy},
+};
+use anyhow::{anyhow, Context, Ok, Result};
 use lettre::{
-    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncTransport, Message, Tokio1Executor,
+    message::Mailbox,
+    transport::smtp::{
+        authentication::{Credentials, DEFAULT_MECHANISMS},
+        client::{AsyncSmtpConnection, AsyncTokioStream, TlsParameters},
+        extension::ClientId,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
 };
-use tracing::debug;
+use tracing::{debug, warn};
 
-async fn send_email(
+/// How many times a password reset email is tried before giving up.
+const PASSWORD_RESET_EMAIL_ATTEMPTS: u32 = 3;
+/// The delay before retrying to send an email, doubled after each attempt.
+const PASSWORD_RESET_EMAIL_BACKOFF: Duration = Duration::from_secs(5);
+
+fn make_email(
     to: Mailbox,
     subject: &str,
     body: String,
+    sender: Option<&MailSenderOptions>,
     options: &MailOptions,
     server_url: &url::Url,
-) -> Result<()> {
-    let from = options
-        .from
-        .clone()
+) -> Result<Message> {
+    let from = sender
+        .and_then(|s| s.from.clone())
+        .or_else(|| options.from.clone())
         .unwrap_or_else(|| "LLDAP <nobody@lldap>".parse().unwrap());
-    let reply_to = options.reply_to.clone().unwrap_or_else(|| from.clone());
+    let reply_to = sender
+        .and_then(|s| s.reply_to.clone())
+        .or_else(|| options.reply_to.clone())
+        .unwrap_or_else(|| from.clone());
     debug!(
         "Sending email to '{}' as '{}' via '{}'@'{}':'{}'",
         &to, &from, &options.user, &options.server, options.port
     );
-    let email = Message::builder()
+    Ok(Message::builder()
         .message_id(Some(format!(
             "<{}@{}>",
             uuid::Uuid::new_v1(
@@ -39,7 +71,19 @@ async fn send_email(
             lettre::message::SinglePart::builder()
                 .header(lettre::message::header::ContentType::TEXT_PLAIN)
                 .body(body),
-        )?;
+        )?)
+}
+
+fn get_credentials(options: &MailOptions) -> Option<Credentials> {
+    (options.user.as_str() != "").then(|| {
+        Credentials::new(
+            options.user.clone(),
+            options.password.unsecure().to_string(),
+        )
+    })
+}
+
+fn make_mailer(options: &MailOptions) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
     let mut mailer = match options.smtp_encryption {
         SmtpEncryption::None => {
             AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&options.server)
@@ -49,66 +93,506 @@ async fn send_email(
             AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&options.server)?
         }
     };
-    if options.user.as_str() != "" {
-        let creds = Credentials::new(
-            options.user.clone(),
-            options.password.unsecure().to_string(),
-        );
+    if let Some(creds) = get_credentials(options) {
         mailer = mailer.credentials(creds)
     }
+    Ok(mailer.port(options.port).build())
+}
+
+/// The stream of the SMTP servers with implicit TLS, since lettre only encrypts the connections
+/// it opens itself.
+#[derive(Debug)]
+struct ProxiedTlsStream(tokio_rustls::client::TlsStream<TcpStream>);
 
-    if let Err(e) = mailer.port(options.port).build().send(email).await {
-        if e.to_string().contains("CorruptMessage") {
+impl AsyncRead for ProxiedTlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedTlsStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl AsyncTokioStream for ProxiedTlsStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.0.get_ref().0.peer_addr()
+    }
+}
+
+/// Lettre's transport can't use a proxy: the connection is opened through it and handed to
+/// lettre, which then takes care of the SMTP session. The certificate is still checked against
+/// the name of the server.
+async fn connect_smtp_through_proxy(
+    options: &MailOptions,
+    proxy: &url::Url,
+) -> Result<AsyncSmtpConnection> {
+    let hello_name = ClientId::default();
+    let stream = connect_through_proxy(proxy, &options.server, options.port).await?;
+    let mut connection = match options.smtp_encryption {
+        SmtpEncryption::Tls => {
+            let client_config = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(get_root_certificates())
+                .with_no_client_auth();
+            let server_name = rustls::ServerName::try_from(options.server.as_str())
+                .with_context(|| format!("Invalid SMTP server name `{}`", options.server))?;
+            let stream = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+                .connect(server_name, stream)
+                .await
+                .context("TLS handshake with the SMTP server failed")?;
+            AsyncSmtpConnection::connect_with_transport(
+                Box::new(ProxiedTlsStream(stream)),
+                &hello_name,
+            )
+            .await?
+        }
+        _ => AsyncSmtpConnection::connect_with_transport(Box::new(stream), &hello_name).await?,
+    };
+    if let SmtpEncryption::StartTls = options.smtp_encryption {
+        connection
+            .starttls(TlsParameters::new(options.server.clone())?, &hello_name)
+            .await?;
+    }
+    if let Some(creds) = get_credentials(options) {
+        connection.auth(DEFAULT_MECHANISMS, &creds).await?;
+    }
+    Ok(connection)
+}
+
+async fn quit(mut connection: AsyncSmtpConnection) {
+    if let Err(e) = connection.quit().await {
+        debug!("Could not close the SMTP connection: {:#}", e);
+    }
+}
+
+/// Connects to the SMTP server, without sending anything.
+pub async fn test_smtp_connection(options: &MailOptions) -> Result<()> {
+    let connected = match outbound_proxy() {
+        None => make_mailer(options)?.test_connection().await?,
+        Some(proxy) => {
+            let mut connection = connect_smtp_through_proxy(options, &proxy).await?;
+            let connected = connection.test_connected().await;
+            quit(connection).await;
+            connected
+        }
+    };
+    if connected {
+        Ok(())
+    } else {
+        Err(anyhow!("The SMTP server refused the connection"))
+    }
+}
+
+async fn send_email(email: Message, options: &MailOptions) -> Result<()> {
+    let result = match outbound_proxy() {
+        None => {
+            async {
+                make_mailer(options)?.send(email).await?;
+                Ok(())
+            }
+            .await
+        }
+        Some(proxy) => {
+            async {
+                let mut connection = connect_smtp_through_proxy(options, &proxy).await?;
+                let result = connection.send(email.envelope(), &email.formatted()).await;
+                quit(connection).await;
+                result?;
+                Ok(())
+            }
+            .await
+        }
+    };
+    if let Err(e) = result {
+        if format!("{:#}", e).contains("CorruptMessage") {
             Err(anyhow!("CorruptMessage returned by lettre, this usually means the SMTP encryption setting is wrong.").context(e))
         } else {
-            Err(e.into())
+            Err(e)
         }
     } else {
         Ok(())
     }
 }
 
-pub async fn send_password_reset_email(
-    username: &str,
-    to: &str,
-    token: &str,
-    server_url: &url::Url,
-    options: &MailOptions,
-) -> Result<()> {
-    let to = to.parse()?;
-    let mut reset_url = server_url.clone();
-    reset_url
-        .path_segments_mut()
-        .unwrap()
-        .extend(["reset-password", "step2", token]);
-    let body = format!(
-        "Hello {},
+/// Calls `send` until it succeeds, at most `attempts` times.
+async fn retry_with_backoff<F, Fut>(attempts: u32, mut backoff: Duration, mut send: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match send().await {
+            Err(e) => e,
+            _ => return Ok(()),
+        };
+        if attempt >= attempts {
+            return Err(error);
+        }
+        warn!(
+            "Could not send the email (attempt {}/{}), retrying in {:?}: {:#}",
+            attempt, attempts, backoff, error
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// The subject and body of an email, with `{username}` and `{url}` placeholders.
+struct EmailTemplate {
+    subject: &'static str,
+    body: &'static str,
+}
+
+/// The password reset email, by language. The first one is the fallback.
+const PASSWORD_RESET_TEMPLATES: &[(&str, EmailTemplate)] = &[
+    (
+        "en",
+        EmailTemplate {
+            subject: "[LLDAP] Password reset requested",
+            body: "Hello {username},
 This email has been sent to you in order to validate your identity.
 If you did not initiate the process your credentials might have been
 compromised. You should reset your password and contact an administrator.
 
-To reset your password please visit the following URL: {}
+To reset your password please visit the following URL: {url}
 
 Please contact an administrator if you did not initiate the process.",
-        username, reset_url
+        },
+    ),
+    (
+        "fr",
+        EmailTemplate {
+            subject: "[LLDAP] Demande de réinitialisation du mot de passe",
+            body: "Bonjour {username},
+Cet email vous a été envoyé afin de vérifier votre identité.
+Si vous n'êtes pas à l'origine de cette demande, vos identifiants ont
+peut-être été compromis. Vous devriez changer votre mot de passe et
+contacter un administrateur.
+
+Pour réinitialiser votre mot de passe, veuillez visiter l'adresse suivante : {url}
+
+Veuillez contacter un administrateur si vous n'êtes pas à l'origine de cette demande.",
+        },
+    ),
+];
+
+/// The locales to try, in order: the one of the user, then the default one, each followed by its
+/// language alone (`fr` for `fr-CA`). The locales are lowercased, with `-` as the separator.
+fn locale_fallback_chain(user_locale: Option<&str>, default_locale: &str) -> Vec<String> {
+    let mut chain = Vec::new();
+    for locale in user_locale
+        .into_iter()
+        .chain(std::iter::once(default_locale))
+    {
+        let locale = locale.trim().to_ascii_lowercase().replace('_', "-");
+        if locale.is_empty() {
+            continue;
+        }
+        let language = locale.split('-').next().unwrap_or_default().to_owned();
+        for candidate in [locale, language] {
+            if !chain.contains(&candidate) {
+                chain.push(candidate);
+            }
+        }
+    }
+    chain
+}
+
+fn select_template<'a>(
+    templates: &'a [(&str, EmailTemplate)],
+    locales: &[String],
+) -> &'a EmailTemplate {
+    &locales
+        .iter()
+        .find_map(|locale| templates.iter().find(|(l, _)| l == locale))
+        .unwrap_or(&templates[0])
+        .1
+}
+
+/// The link of the web UI to finish the password reset.
+pub fn make_password_reset_url(server_url: &url::Url, token: &str) -> url::Url {
+    let mut reset_url = server_url.clone();
+    reset_url
+        .path_segments_mut()
+        .unwrap()
+        .extend(["reset-password", "step2", token]);
+    reset_url
+}
+
+fn make_password_reset_email(
+    username: &str,
+    to: &str,
+    token: &str,
+    server_url: &url::Url,
+    user_locale: Option<&str>,
+    default_locale: &str,
+    options: &MailOptions,
+) -> Result<Message> {
+    let to = to.parse()?;
+    let reset_url = make_password_reset_url(server_url, token);
+    let template = select_template(
+        PASSWORD_RESET_TEMPLATES,
+        &locale_fallback_chain(user_locale, default_locale),
     );
-    send_email(
+    let body = template
+        .body
+        .replace("{url}", reset_url.as_str())
+        .replace("{username}", username);
+    make_email(
         to,
-        "[LLDAP] Password reset requested",
+        template.subject,
         body,
+        Some(&options.password_reset),
         options,
         server_url,
     )
+}
+
+pub async fn send_password_reset_email(
+    username: &str,
+    to: &str,
+    token: &str,
+    server_url: &url::Url,
+    user_locale: Option<&str>,
+    default_locale: &str,
+    options: &MailOptions,
+) -> Result<()> {
+    let email = make_password_reset_email(
+        username,
+        to,
+        token,
+        server_url,
+        user_locale,
+        default_locale,
+        options,
+    )?;
+    retry_with_backoff(
+        PASSWORD_RESET_EMAIL_ATTEMPTS,
+        PASSWORD_RESET_EMAIL_BACKOFF,
+        || send_email(email.clone(), options),
+    )
     .await
 }
 
 pub async fn send_test_email(to: Mailbox, options: &MailOptions) -> Result<()> {
-    send_email(
+    let email = make_email(
         to,
         "LLDAP test email",
         "The test is successful! You can send emails from LLDAP".to_string(),
+        None,
         options,
         &url::Url::parse("http://localhost").unwrap(),
-    )
-    .await
+    )?;
+    send_email(email, options).await
+}
+
+pub async fn send_certificate_alert_email(
+    to: Mailbox,
+    detail: &str,
+    server_url: &url::Url,
+    options: &MailOptions,
+) -> Result<()> {
+    let email = make_email(
+        to,
+        "[LLDAP] LDAPS certificate expiry",
+        format!(
+            "Please renew the LDAPS certificate of LLDAP at {}: {}",
+            server_url, detail
+        ),
+        None,
+        options,
+        server_url,
+    )?;
+    send_email(email, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_headers(email: &Message) -> String {
+        String::from_utf8(email.formatted()).unwrap()
+    }
+
+    #[test]
+    fn test_password_reset_sender() {
+        let server_url = url::Url::parse("http://localhost").unwrap();
+        let mut options = MailOptions {
+            from: Some("LLDAP <lldap@example.com>".parse().unwrap()),
+            ..Default::default()
+        };
+        let email = make_password_reset_email(
+            "bob",
+            "bob@bob.bob",
+            "token",
+            &server_url,
+            None,
+            "en",
+            &options,
+        )
+        .unwrap();
+        assert!(get_headers(&email).contains("From: LLDAP <lldap@example.com>"));
+        assert!(get_headers(&email).contains("Reply-To: LLDAP <lldap@example.com>"));
+
+        options.password_reset.from = Some("Reset <reset@example.com>".parse().unwrap());
+        let email = make_password_reset_email(
+            "bob",
+            "bob@bob.bob",
+            "token",
+            &server_url,
+            None,
+            "en",
+            &options,
+        )
+        .unwrap();
+        assert!(get_headers(&email).contains("From: Reset <reset@example.com>"));
+        assert!(get_headers(&email).contains("Reply-To: Reset <reset@example.com>"));
+    }
+
+    #[test]
+    fn test_locale_fallback_chain() {
+        assert_eq!(
+            locale_fallback_chain(Some("fr_CA"), "de-DE"),
+            vec!["fr-ca", "fr", "de-de", "de"]
+        );
+        assert_eq!(locale_fallback_chain(Some(""), "en"), vec!["en"]);
+        assert_eq!(locale_fallback_chain(None, "FR"), vec!["fr"]);
+    }
+
+    #[test]
+    fn test_password_reset_locale() {
+        let server_url = url::Url::parse("http://localhost").unwrap();
+        let options = MailOptions::default();
+        let make_email = |user_locale, default_locale| {
+            let email = make_password_reset_email(
+                "bob",
+                "bob@bob.bob",
+                "token",
+                &server_url,
+                user_locale,
+                default_locale,
+                &options,
+            )
+            .unwrap();
+            String::from_utf8(email.formatted()).unwrap()
+        };
+        // The french email is encoded, only its ASCII first line is checked.
+        assert!(make_email(Some("fr-CA"), "en").contains("Bonjour bob"));
+        assert!(make_email(None, "fr").contains("Bonjour bob"));
+        // The locale of the user wins over the default one.
+        assert!(make_email(Some("en"), "fr").contains("Hello bob"));
+        // Unknown locales fall back to the default one, then to English.
+        assert!(make_email(Some("de"), "fr").contains("Bonjour bob"));
+        assert!(make_email(Some("de"), "es").contains("Hello bob"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff() {
+        let backoff = Duration::from_millis(1);
+        let attempts = std::cell::Cell::new(0);
+        let send = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 3 {
+                    Err(anyhow!("Connection refused"))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        retry_with_backoff(5, backoff, send).await.unwrap();
+        assert_eq!(attempts.get(), 3);
+
+        // The number of attempts is bounded.
+        attempts.set(0);
+        retry_with_backoff(2, backoff, send).await.unwrap_err();
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_smtp_server() {
+        let options = MailOptions {
+            server: "127.0.0.1".to_owned(),
+            port: 1,
+            smtp_encryption: SmtpEncryption::None,
+            ..Default::default()
+        };
+        let server_url = url::Url::parse("http://localhost").unwrap();
+        let email = make_password_reset_email(
+            "bob",
+            "bob@bob.bob",
+            "token",
+            &server_url,
+            None,
+            "en",
+            &options,
+        )
+        .unwrap();
+        let attempts = std::cell::Cell::new(0);
+        retry_with_backoff(2, Duration::from_millis(1), || {
+            attempts.set(attempts.get() + 1);
+            send_email(email.clone(), &options)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_smtp_through_proxy() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        // The proxy answers as the SMTP server.
+        let (proxy, target) =
+            crate::infra::proxy::tests::start_mock_socks5_proxy(|stream| async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer
+                    .write_all(b"220 smtp.example.com ESMTP\r\n")
+                    .await
+                    .unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.starts_with("QUIT") {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    }
+                    writer.write_all(b"250 OK\r\n").await.unwrap();
+                }
+            })
+            .await;
+        let options = MailOptions {
+            server: "smtp.example.com".to_owned(),
+            port: 25,
+            smtp_encryption: SmtpEncryption::None,
+            ..Default::default()
+        };
+        let mut connection = connect_smtp_through_proxy(&options, &proxy).await.unwrap();
+        assert!(connection.test_connected().await);
+        quit(connection).await;
+        assert_eq!(
+            target.lock().unwrap().as_deref(),
+            Some("smtp.example.com:25")
+        );
+    }
 }
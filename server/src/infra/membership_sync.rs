@@ -0,0 +1,386 @@
+//! Periodic import of the group memberships from an external source, e.g. an HR system. The
+//! groups listed by the source get the members it lists; the other groups are left untouched.
+
+use crate::{
+    domain::{
+        handler::{
+            BackendHandler, GroupListerBackendHandler, UserBackendHandler, UserListerBackendHandler,
+        },
+        types::UserId,
+    },
+    infra::{configuration::MembershipSyncOptions, proxy::http_client},
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use tracing::{debug, info, instrument, warn};
+
+/// The members of each group, by group name.
+pub type Memberships = BTreeMap<String, BTreeSet<UserId>>;
+
+#[async_trait]
+pub trait MembershipSource: Send + Sync {
+    async fn get_memberships(&self) -> Result<Memberships>;
+}
+
+/// A CSV file with one `group,user` pair per line. A `group,user` header, empty lines and lines
+/// starting with `#` are ignored.
+pub struct CsvFileSource {
+    pub path: String,
+}
+
+fn parse_csv(content: &str) -> Result<Memberships> {
+    let mut memberships = Memberships::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || (number == 0 && line.eq_ignore_ascii_case("group,user"))
+        {
+            continue;
+        }
+        let (group, user) = line
+            .split_once(',')
+            .with_context(|| format!("Line {}: expected `group,user`", number + 1))?;
+        memberships
+            .entry(group.trim().to_owned())
+            .or_default()
+            .insert(UserId::new(user.trim()));
+    }
+    Ok(memberships)
+}
+
+#[async_trait]
+impl MembershipSource for CsvFileSource {
+    async fn get_memberships(&self) -> Result<Memberships> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Could not read `{}`", self.path))?;
+        parse_csv(&content).with_context(|| format!("Invalid CSV file `{}`", self.path))
+    }
+}
+
+/// An HTTP endpoint returning a JSON object with the list of members of each group, e.g.
+/// `{"developers": ["alice", "bob"]}`. A group with an empty list has no members.
+pub struct HttpJsonSource {
+    pub url: String,
+}
+
+#[async_trait]
+impl MembershipSource for HttpJsonSource {
+    async fn get_memberships(&self) -> Result<Memberships> {
+        let body = async {
+            http_client()?
+                .get(&self.url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        }
+        .await
+        .with_context(|| format!("Could not fetch `{}`", self.url))?;
+        let groups = serde_json::from_slice::<BTreeMap<String, Vec<String>>>(&body)
+            .with_context(|| format!("Invalid JSON from `{}`", self.url))?;
+        Ok(groups
+            .into_iter()
+            .map(|(group, users)| (group, users.iter().map(|u| UserId::new(u)).collect()))
+            .collect())
+    }
+}
+
+/// The source is an HTTP endpoint if it's a URL, a CSV file otherwise.
+pub fn make_source(source: &str) -> Box<dyn MembershipSource> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        Box::new(HttpJsonSource {
+            url: source.to_owned(),
+        })
+    } else {
+        Box::new(CsvFileSource {
+            path: source.to_owned(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupSyncReport {
+    pub group: String,
+    pub added: Vec<UserId>,
+    pub removed: Vec<UserId>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// The changes were only computed, not applied.
+    pub dry_run: bool,
+    /// The groups with changes.
+    pub groups: Vec<GroupSyncReport>,
+    /// The groups of the source that don't exist, or that are protected.
+    pub skipped_groups: Vec<String>,
+    /// The users of the source that don't exist.
+    pub unknown_users: Vec<UserId>,
+}
+
+impl std::fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Group memberships sync{}: ",
+            if self.dry_run { " (dry run)" } else { "" }
+        )?;
+        if self.groups.is_empty() {
+            write!(f, "no changes")?;
+        }
+        for (i, group) in self.groups.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}:", group.group)?;
+            for user in &group.added {
+                write!(f, " +{}", user)?;
+            }
+            for user in &group.removed {
+                write!(f, " -{}", user)?;
+            }
+        }
+        if !self.skipped_groups.is_empty() {
+            write!(f, "; skipped groups: {}", self.skipped_groups.join(", "))?;
+        }
+        if !self.unknown_users.is_empty() {
+            let users = self.unknown_users.iter().map(UserId::as_str);
+            write!(
+                f,
+                "; unknown users: {}",
+                users.collect::<Vec<_>>().join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The built-in groups and the admin groups are never changed, to not lock out the admins.
+fn is_protected_group(name: &str, admin_groups: &[String]) -> bool {
+    name.starts_with("lldap_") || admin_groups.iter().any(|g| g == name)
+}
+
+/// Makes the members of the groups of the source match it: the missing members are added, and
+/// the extra ones removed if `remove_extra_members` is set.
+#[instrument(skip_all, level = "debug", err)]
+pub async fn reconcile_memberships<Handler: BackendHandler>(
+    handler: &Handler,
+    memberships: &Memberships,
+    options: &MembershipSyncOptions,
+    admin_groups: &[String],
+) -> Result<SyncReport> {
+    let groups = handler.list_groups(None).await?;
+    let users = handler
+        .list_users(None, false)
+        .await?
+        .into_iter()
+        .map(|u| u.user.user_id)
+        .collect::<HashSet<_>>();
+    let mut report = SyncReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    for (name, members) in memberships {
+        let group = match groups.iter().find(|g| &g.display_name == name) {
+            Some(group) if !is_protected_group(name, admin_groups) => group,
+            _ => {
+                report.skipped_groups.push(name.clone());
+                continue;
+            }
+        };
+        let (known, unknown): (Vec<_>, Vec<_>) = members.iter().partition(|u| users.contains(*u));
+        report.unknown_users.extend(unknown.into_iter().cloned());
+        let mut added = known
+            .into_iter()
+            .filter(|u| !group.users.contains(*u))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut removed = if options.remove_extra_members {
+            group
+                .users
+                .iter()
+                .filter(|u| !members.contains(*u))
+                .cloned()
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        if !options.dry_run {
+            if !added.is_empty() {
+                added = handler.add_users_to_group(&added, group.id).await?;
+            }
+            if !removed.is_empty() {
+                removed = handler.remove_users_from_group(&removed, group.id).await?;
+            }
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            report.groups.push(GroupSyncReport {
+                group: name.clone(),
+                added,
+                removed,
+            });
+        }
+    }
+    report.unknown_users.sort();
+    report.unknown_users.dedup();
+    Ok(report)
+}
+
+/// Synchronizes the memberships at startup, then at every interval.
+pub async fn sync_memberships_periodically<Handler: BackendHandler>(
+    handler: Handler,
+    options: MembershipSyncOptions,
+    admin_groups: Vec<String>,
+) {
+    let source = match &options.source {
+        None => return,
+        Some(source) => make_source(source),
+    };
+    loop {
+        match source.get_memberships().await {
+            Err(e) => warn!("Could not read the group memberships: {:#}", e),
+            Ok(memberships) => {
+                debug!(groups = memberships.len(), "Read the group memberships");
+                match reconcile_memberships(&handler, &memberships, &options, &admin_groups).await {
+                    Err(e) => warn!("Could not synchronize the group memberships: {:#}", e),
+                    Ok(report) => info!("{}", report),
+                }
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(options.interval_seconds)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::tests::*;
+
+    fn make_memberships(groups: &[(&str, &[&str])]) -> Memberships {
+        groups
+            .iter()
+            .map(|(group, users)| {
+                (
+                    group.to_string(),
+                    users.iter().map(|u| UserId::new(u)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    async fn get_members(fixture: &TestFixture, group: usize) -> Vec<UserId> {
+        let mut users = fixture
+            .handler
+            .list_groups(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|g| g.id == fixture.groups[group])
+            .unwrap()
+            .users;
+        users.sort();
+        users
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        assert_eq!(
+            parse_csv("group,user\n# Comment\nBest Group, Bob\n\nBest Group,john\nOther,bob\n")
+                .unwrap(),
+            make_memberships(&[("Best Group", &["bob", "john"]), ("Other", &["bob"])])
+        );
+        parse_csv("Best Group;bob").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_memberships() {
+        let fixture = TestFixture::new().await;
+        // Best Group: bob, patrick. Worst Group: john, patrick. Empty Group: nobody.
+        let memberships = make_memberships(&[
+            ("Best Group", &["bob", "john"]),
+            ("Empty Group", &["nogroup", "unknown"]),
+            ("Missing Group", &["bob"]),
+            ("lldap_admin", &["bob"]),
+        ]);
+        let mut options = MembershipSyncOptions {
+            remove_extra_members: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let expected = SyncReport {
+            dry_run: true,
+            groups: vec![
+                GroupSyncReport {
+                    group: "Best Group".to_owned(),
+                    added: vec![UserId::new("john")],
+                    removed: vec![UserId::new("patrick")],
+                },
+                GroupSyncReport {
+                    group: "Empty Group".to_owned(),
+                    added: vec![UserId::new("nogroup")],
+                    removed: vec![],
+                },
+            ],
+            skipped_groups: vec!["Missing Group".to_owned(), "lldap_admin".to_owned()],
+            unknown_users: vec![UserId::new("unknown")],
+        };
+        let admin_groups = ["lldap_admin".to_owned()];
+        let reconcile = |options| {
+            let memberships = &memberships;
+            let handler = &fixture.handler;
+            let admin_groups = &admin_groups;
+            async move {
+                reconcile_memberships(handler, memberships, &options, admin_groups)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(reconcile(options.clone()).await, expected);
+        // Nothing changed in dry run mode.
+        assert_eq!(
+            get_members(&fixture, 0).await,
+            vec![UserId::new("bob"), UserId::new("patrick")]
+        );
+
+        options.dry_run = false;
+        assert_eq!(
+            reconcile(options.clone()).await,
+            SyncReport {
+                dry_run: false,
+                ..expected.clone()
+            }
+        );
+        assert_eq!(
+            get_members(&fixture, 0).await,
+            vec![UserId::new("bob"), UserId::new("john")]
+        );
+        assert_eq!(get_members(&fixture, 2).await, vec![UserId::new("nogroup")]);
+        // The groups that are not in the source are untouched.
+        assert_eq!(
+            get_members(&fixture, 1).await,
+            vec![UserId::new("john"), UserId::new("patrick")]
+        );
+        // Once in sync, there is nothing to do.
+        assert!(reconcile(options.clone()).await.groups.is_empty());
+
+        // Without remove_extra_members, the members are only added.
+        fixture
+            .handler
+            .add_user_to_group(&UserId::new("patrick"), fixture.groups[0])
+            .await
+            .unwrap();
+        options.remove_extra_members = false;
+        assert!(reconcile(options).await.groups.is_empty());
+        assert_eq!(
+            get_members(&fixture, 0).await,
+            vec![
+                UserId::new("bob"),
+                UserId::new("john"),
+                UserId::new("patrick")
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Gauges in the Prometheus text format, served on `/metrics`. They are counted in the database at
+//! most once every `SAMPLING_INTERVAL`, since the endpoint is unauthenticated, and have no
+//! per-user or per-group labels, to keep the cardinality low.
+
+use crate::{
+    domain::{error::Result, handler::BackendHandler},
+    infra::{
+        access_control::ReadonlyBackendHandler,
+        tcp_server::{error_to_http_response, AppState, TcpError},
+    },
+};
+use actix_web::{web, HttpResponse};
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// The scrapes in between get the previous sample.
+const SAMPLING_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Gauges {
+    pub users: u64,
+    pub groups: u64,
+}
+
+impl Gauges {
+    pub async fn sample<Handler: ReadonlyBackendHandler>(handler: &Handler) -> Result<Self> {
+        let (users, groups) = futures::try_join!(handler.count_users(), handler.count_groups())?;
+        Ok(Self { users, groups })
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, value) in [
+            ("lldap_users", "Number of users.", self.users),
+            ("lldap_groups", "Number of groups.", self.groups),
+        ] {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
+
+/// The last sample, shared by all the workers.
+#[derive(Default)]
+pub struct GaugesCache(Mutex<Option<(Instant, Gauges)>>);
+
+impl GaugesCache {
+    /// Samples the gauges, unless the last sample is more recent than `SAMPLING_INTERVAL`. The
+    /// concurrent scrapes wait for the same sample.
+    pub async fn get<Handler: ReadonlyBackendHandler>(
+        &self,
+        handler: &Handler,
+        now: Instant,
+    ) -> Result<Gauges> {
+        let mut last_sample = self.0.lock().await;
+        if let Some((date, gauges)) = *last_sample {
+            if now.saturating_duration_since(date) < SAMPLING_INTERVAL {
+                return Ok(gauges);
+            }
+        }
+        let gauges = Gauges::sample(handler).await?;
+        *last_sample = Some((now, gauges));
+        Ok(gauges)
+    }
+}
+
+pub(crate) async fn metrics_handler<Backend: BackendHandler>(
+    data: web::Data<AppState<Backend>>,
+    cache: web::Data<GaugesCache>,
+) -> HttpResponse {
+    match cache.get(data.get_readonly_handler(), Instant::now()).await {
+        Ok(gauges) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(gauges.render()),
+        Err(e) => error_to_http_response(TcpError::DomainError(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::sql_backend_handler::{tests::*, SqlBackendHandler};
+
+    #[tokio::test]
+    async fn test_creating_a_user_increments_the_gauge() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        insert_group(&handler, "Best Group").await;
+        let before = Gauges::sample(&handler).await.unwrap();
+        assert_eq!(
+            before,
+            Gauges {
+                users: 0,
+                groups: 1
+            }
+        );
+        insert_user(&handler, "bob", "bob00000").await;
+        let after = Gauges::sample(&handler).await.unwrap();
+        assert_eq!(after.users, before.users + 1);
+        assert_eq!(after.groups, before.groups);
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        let handler = SqlBackendHandler::new(get_default_config(), get_initialized_db().await);
+        let cache = GaugesCache::default();
+        let start = Instant::now();
+        assert_eq!(cache.get(&handler, start).await.unwrap().users, 0);
+        insert_user(&handler, "bob", "bob00000").await;
+        // The previous sample is reused for a while.
+        assert_eq!(cache.get(&handler, start).await.unwrap().users, 0);
+        assert_eq!(
+            cache
+                .get(&handler, start + SAMPLING_INTERVAL)
+                .await
+                .unwrap()
+                .users,
+            1
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            Gauges {
+                users: 3,
+                groups: 2
+            }
+            .render(),
+            "# HELP lldap_users Number of users.\n\
+             # TYPE lldap_users gauge\n\
+             lldap_users 3\n\
+             # HELP lldap_groups Number of groups.\n\
+             # TYPE lldap_groups gauge\n\
+             lldap_groups 2\n"
+        );
+    }
+}
@@ -1,19 +1,36 @@
 pub mod access_control;
 pub mod auth_service;
+pub mod build_info;
 pub mod cli;
 pub mod configuration;
 pub mod db_cleaner;
+pub mod diagnostics;
+pub mod first_run;
 pub mod graphql;
 pub mod healthcheck;
+pub mod index_page;
+pub mod jwt_keys;
 pub mod jwt_sql_tables;
+pub mod ldap_codec;
+pub mod ldap_connections;
 pub mod ldap_handler;
 pub mod ldap_server;
 pub mod logging;
 pub mod mail;
+pub mod membership_sync;
+pub mod metrics;
+pub mod ocsp;
+pub mod password_reset_protection;
+pub mod proxy;
+pub mod reset_notification;
 pub mod schema;
+pub mod secrets;
+pub mod session_activity;
 pub mod sql_backend_handler;
 pub mod tcp_backend_handler;
 pub mod tcp_server;
 
+#[cfg(test)]
+mod replicas_tests;
 #[cfg(test)]
 pub mod test_utils;
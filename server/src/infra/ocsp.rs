@@ -0,0 +1,490 @@
+use crate::infra::proxy::http_client;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, PrivateKey,
+};
+use std::sync::{Arc, RwLock};
+use tracing::{debug, info, instrument, warn};
+use url::Url;
+
+/// How often the staple is refreshed when the response doesn't have a nextUpdate, i.e. when newer
+/// information is always available.
+const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(12 * 60 * 60);
+/// How long to wait before retrying after a failed fetch, and the minimum delay between two
+/// fetches.
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// OID of the OCSP access method, in the Authority Information Access extension.
+const OCSP_ACCESS_METHOD: &str = "1.3.6.1.5.5.7.48.1";
+
+/// Serves the certificate with the latest OCSP staple, until its nextUpdate.
+pub struct OcspStaplingResolver {
+    /// Without any staple.
+    certified_key: Arc<CertifiedKey>,
+    /// With the latest staple, and its nextUpdate if any.
+    stapled_key: RwLock<Option<(Arc<CertifiedKey>, Option<DateTime<Utc>>)>>,
+}
+
+impl OcspStaplingResolver {
+    pub fn new(certs: Vec<Certificate>, private_key: &PrivateKey) -> Result<Self> {
+        let key = rustls::sign::any_supported_type(private_key)
+            .map_err(|_| anyhow!("Unsupported private key type"))?;
+        Ok(Self {
+            certified_key: Arc::new(CertifiedKey::new(certs, key)),
+            stapled_key: RwLock::new(None),
+        })
+    }
+
+    pub fn certificates(&self) -> Vec<Certificate> {
+        self.certified_key.cert.clone()
+    }
+
+    pub fn set_staple(&self, staple: Vec<u8>, next_update: Option<DateTime<Utc>>) {
+        let mut new_key = CertifiedKey::clone(&self.certified_key);
+        new_key.ocsp = Some(staple);
+        *self.stapled_key.write().unwrap() = Some((Arc::new(new_key), next_update));
+    }
+
+    fn get_certified_key(&self, now: DateTime<Utc>) -> Arc<CertifiedKey> {
+        match &*self.stapled_key.read().unwrap() {
+            Some((key, next_update)) if next_update.map(|date| date > now).unwrap_or(true) => {
+                key.clone()
+            }
+            // An expired staple would make the clients reject the certificate.
+            _ => self.certified_key.clone(),
+        }
+    }
+}
+
+impl ResolvesServerCert for OcspStaplingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.get_certified_key(Utc::now()))
+    }
+}
+
+fn der_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let len_bytes = len.to_be_bytes();
+        let len_bytes = &len_bytes[len_bytes.iter().take_while(|&&b| b == 0).count()..];
+        out.push(0x80 | len_bytes.len() as u8);
+        out.extend_from_slice(len_bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn der_sequence(elements: &[Vec<u8>]) -> Vec<u8> {
+    der_tlv(0x30, &elements.concat())
+}
+
+/// Reads the tag and content of the first DER element, returning the rest of the input too.
+fn read_der_tlv(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let error = || anyhow!("Invalid DER encoding");
+    let (&tag, rest) = input.split_first().ok_or_else(error)?;
+    let (&first_len, rest) = rest.split_first().ok_or_else(error)?;
+    let (len, rest) = if first_len < 0x80 {
+        (first_len as usize, rest)
+    } else {
+        let num_bytes = (first_len & 0x7f) as usize;
+        if num_bytes > std::mem::size_of::<usize>() || rest.len() < num_bytes {
+            return Err(error());
+        }
+        let (len_bytes, rest) = rest.split_at(num_bytes);
+        (
+            len_bytes
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize),
+            rest,
+        )
+    };
+    if rest.len() < len {
+        return Err(error());
+    }
+    let (content, rest) = rest.split_at(len);
+    Ok((tag, content, rest))
+}
+
+/// Reads the content of the first DER element, which must have the given tag, returning the rest
+/// of the input too.
+fn read_der_element(input: &[u8], expected_tag: u8) -> Result<(&[u8], &[u8])> {
+    match read_der_tlv(input)? {
+        (tag, content, rest) if tag == expected_tag => Ok((content, rest)),
+        (tag, _, _) => bail!(
+            "Unexpected DER tag {:#04x} instead of {:#04x}",
+            tag,
+            expected_tag
+        ),
+    }
+}
+
+fn parse_generalized_time(time: &[u8]) -> Result<DateTime<Utc>> {
+    let time = std::str::from_utf8(time)?;
+    // YYYYMMDDHHMMSS, optionally followed by fractional seconds, in UTC.
+    match (time.get(..14), time.ends_with('Z')) {
+        (Some(seconds), true) => Ok(Utc.from_utc_datetime(
+            &NaiveDateTime::parse_from_str(seconds, "%Y%m%d%H%M%S")
+                .with_context(|| format!("Invalid time {}", time))?,
+        )),
+        _ => bail!("Invalid time {}", time),
+    }
+}
+
+/// Checks that the OCSP response has a "successful" status, and returns the earliest nextUpdate
+/// of its single responses (RFC 6960, section 4.2.1), if any.
+fn check_ocsp_response(response: &[u8]) -> Result<Option<DateTime<Utc>>> {
+    let (tag, content, _) = read_der_tlv(response)?;
+    if tag != 0x30 {
+        bail!("The OCSP response is not a sequence");
+    }
+    let response_bytes = match read_der_tlv(content)? {
+        (0x0a, [0], rest) => rest,
+        (0x0a, [status], _) => bail!("The OCSP responder returned the status {}", status),
+        _ => bail!("The OCSP response has no status"),
+    };
+    if response_bytes.is_empty() {
+        return Ok(None);
+    }
+    let (response_bytes, _) = read_der_element(response_bytes, 0xa0)?;
+    let (response_bytes, _) = read_der_element(response_bytes, 0x30)?;
+    let (_response_type, rest) = read_der_element(response_bytes, 0x06)?;
+    let (basic_response, _) = read_der_element(rest, 0x04)?;
+    let (basic_response, _) = read_der_element(basic_response, 0x30)?;
+    let (response_data, _) = read_der_element(basic_response, 0x30)?;
+    // The version is optional.
+    let response_data = match read_der_tlv(response_data)? {
+        (0xa0, _, rest) => rest,
+        _ => response_data,
+    };
+    let (_responder_id, _, rest) = read_der_tlv(response_data)?;
+    let (_produced_at, rest) = read_der_element(rest, 0x18)?;
+    let (mut single_responses, _) = read_der_element(rest, 0x30)?;
+    let mut next_update: Option<DateTime<Utc>> = None;
+    while !single_responses.is_empty() {
+        let (single_response, rest) = read_der_element(single_responses, 0x30)?;
+        single_responses = rest;
+        let (_cert_id, single_response) = read_der_element(single_response, 0x30)?;
+        let (_cert_status, _, single_response) = read_der_tlv(single_response)?;
+        let (_this_update, single_response) = read_der_element(single_response, 0x18)?;
+        if let Ok((date, _)) = read_der_element(single_response, 0xa0) {
+            let date = parse_generalized_time(read_der_element(date, 0x18)?.0)?;
+            next_update = Some(next_update.map_or(date, |d| d.min(date)));
+        }
+    }
+    Ok(next_update)
+}
+
+/// The refresh happens halfway to the nextUpdate, to leave time to retry before the staple
+/// expires.
+fn get_refresh_delay(
+    next_update: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> std::time::Duration {
+    match next_update {
+        None => REFRESH_INTERVAL,
+        Some(next_update) => ((next_update - now) / 2)
+            .to_std()
+            .unwrap_or_default()
+            .max(RETRY_INTERVAL),
+    }
+}
+
+/// Builds the DER-encoded OCSP request for the first certificate, issued by the second one.
+fn make_ocsp_request(certs: &[Certificate]) -> Result<(Vec<u8>, Option<Url>)> {
+    use sha1::{Digest, Sha1};
+    use x509_parser::{extensions::ParsedExtension, prelude::GeneralName};
+    let parse = |cert: &Certificate| -> Result<_> {
+        Ok(x509_parser::parse_x509_certificate(&cert.0)
+            .context("while parsing the certificate")?
+            .1)
+    };
+    let (leaf, issuer) = match certs {
+        [leaf, issuer, ..] => (parse(leaf)?, parse(issuer)?),
+        _ => bail!("The certificate file must contain the issuer certificate for OCSP stapling"),
+    };
+    let responder_url = leaf
+        .extensions()
+        .iter()
+        .filter_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })
+        .flat_map(|aia| aia.accessdescs.iter())
+        .filter(|desc| desc.access_method.to_id_string() == OCSP_ACCESS_METHOD)
+        .find_map(|desc| match desc.access_location {
+            GeneralName::URI(uri) => Url::parse(uri).ok(),
+            _ => None,
+        });
+    // SHA-1, as recommended by RFC 5019.
+    let sha1_algorithm = der_sequence(&[
+        der_tlv(0x06, &[0x2b, 0x0e, 0x03, 0x02, 0x1a]),
+        der_tlv(0x05, &[]),
+    ]);
+    let cert_id = der_sequence(&[
+        sha1_algorithm,
+        der_tlv(0x04, &Sha1::digest(leaf.tbs_certificate.issuer.as_raw())),
+        der_tlv(
+            0x04,
+            &Sha1::digest(&issuer.public_key().subject_public_key.data),
+        ),
+        der_tlv(0x02, leaf.tbs_certificate.raw_serial()),
+    ]);
+    let request_list = der_sequence(&[der_sequence(&[cert_id])]);
+    let tbs_request = der_sequence(&[request_list]);
+    Ok((der_sequence(&[tbs_request]), responder_url))
+}
+
+/// Fetches an OCSP response for the first certificate of the chain, from `responder_url` or from
+/// the responder listed in the certificate. Returns it with its nextUpdate, if any.
+#[instrument(skip_all, level = "debug")]
+pub async fn fetch_ocsp_staple(
+    certs: &[Certificate],
+    responder_url: Option<&Url>,
+) -> Result<(Vec<u8>, Option<DateTime<Utc>>)> {
+    let (request, certificate_responder_url) = make_ocsp_request(certs)?;
+    let url = responder_url
+        .or(certificate_responder_url.as_ref())
+        .ok_or_else(|| anyhow!("The certificate doesn't specify an OCSP responder"))?;
+    debug!(%url, "Fetching the OCSP staple");
+    let response = http_client()?
+        .post(url.clone())
+        .header("Content-Type", "application/ocsp-request")
+        .body(request)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("while querying the OCSP responder {}", url))?
+        .bytes()
+        .await?
+        .to_vec();
+    let next_update = check_ocsp_response(&response)?;
+    Ok((response, next_update))
+}
+
+/// Keeps the staple of the resolver up to date. Once its nextUpdate is past, it's not stapled
+/// anymore until a fetch succeeds.
+pub async fn refresh_ocsp_staple(resolver: Arc<OcspStaplingResolver>, responder_url: Option<Url>) {
+    let certs = resolver.certificates();
+    loop {
+        let delay = match fetch_ocsp_staple(&certs, responder_url.as_ref()).await {
+            Ok((staple, next_update)) => {
+                info!(?next_update, "Refreshed the OCSP staple");
+                resolver.set_staple(staple, next_update);
+                get_refresh_delay(next_update, Utc::now())
+            }
+            Err(e) => {
+                warn!("Could not fetch the OCSP staple: {:#}", e);
+                RETRY_INTERVAL
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const FAKE_OCSP_RESPONSE: &[u8] = &[0x30, 0x03, 0x0a, 0x01, 0x00];
+
+    fn make_certificates() -> (Vec<Certificate>, PrivateKey) {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa};
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let leaf = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        (
+            vec![
+                Certificate(leaf.serialize_der_with_signer(&ca).unwrap()),
+                Certificate(ca.serialize_der().unwrap()),
+            ],
+            PrivateKey(leaf.serialize_private_key_der()),
+        )
+    }
+
+    /// Answers a single HTTP request with a fake OCSP response, and returns the request body.
+    fn start_mock_responder() -> (Url, std::thread::JoinHandle<Vec<u8>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/ocsp-response\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                FAKE_OCSP_RESPONSE.len()
+            )
+            .unwrap();
+            stream.write_all(FAKE_OCSP_RESPONSE).unwrap();
+            body
+        });
+        (url, handle)
+    }
+
+    struct RecordingVerifier(Arc<Mutex<Vec<u8>>>);
+
+    impl rustls::client::ServerCertVerifier for RecordingVerifier {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _server_name: &rustls::ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            ocsp_response: &[u8],
+            _now: std::time::SystemTime,
+        ) -> std::result::Result<rustls::client::ServerCertVerified, rustls::Error> {
+            *self.0.lock().unwrap() = ocsp_response.to_vec();
+            Ok(rustls::client::ServerCertVerified::assertion())
+        }
+    }
+
+    fn make_ocsp_response(next_updates: &[&[u8]]) -> Vec<u8> {
+        let single_responses = next_updates
+            .iter()
+            .map(|next_update| {
+                der_sequence(&[
+                    der_sequence(&[]),
+                    // "good" status.
+                    der_tlv(0x80, &[]),
+                    der_tlv(0x18, b"20260101000000Z"),
+                    der_tlv(0xa0, &der_tlv(0x18, next_update)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let response_data = der_sequence(&[
+            der_tlv(0xa2, &der_tlv(0x04, &[0; 20])),
+            der_tlv(0x18, b"20260101000000Z"),
+            der_sequence(&single_responses),
+        ]);
+        let basic_response = der_sequence(&[response_data, der_sequence(&[]), der_tlv(0x03, &[0])]);
+        der_sequence(&[
+            der_tlv(0x0a, &[0]),
+            der_tlv(
+                0xa0,
+                &der_sequence(&[
+                    // id-pkix-ocsp-basic
+                    der_tlv(
+                        0x06,
+                        &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01],
+                    ),
+                    der_tlv(0x04, &basic_response),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_check_ocsp_response() {
+        assert_eq!(check_ocsp_response(FAKE_OCSP_RESPONSE).unwrap(), None);
+        assert_eq!(
+            check_ocsp_response(&make_ocsp_response(&[
+                b"20260108000000Z",
+                b"20260105120000.5Z"
+            ]))
+            .unwrap(),
+            Some(Utc.with_ymd_and_hms(2026, 1, 5, 12, 0, 0).unwrap())
+        );
+        check_ocsp_response(&make_ocsp_response(&[b"2026"])).unwrap_err();
+        // "unauthorized" status.
+        check_ocsp_response(&[0x30, 0x03, 0x0a, 0x01, 0x06]).unwrap_err();
+        check_ocsp_response(&[0x30, 0x05, 0x0a]).unwrap_err();
+    }
+
+    #[test]
+    fn test_refresh_delay() {
+        let now = Utc::now();
+        assert_eq!(get_refresh_delay(None, now), REFRESH_INTERVAL);
+        assert_eq!(
+            get_refresh_delay(Some(now + chrono::Duration::days(4)), now),
+            std::time::Duration::from_secs(2 * 24 * 60 * 60)
+        );
+        // Expired, or about to.
+        assert_eq!(
+            get_refresh_delay(Some(now - chrono::Duration::days(1)), now),
+            RETRY_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_expired_staple_is_dropped() {
+        let (certs, private_key) = make_certificates();
+        let resolver = OcspStaplingResolver::new(certs, &private_key).unwrap();
+        let now = Utc::now();
+        assert_eq!(resolver.get_certified_key(now).ocsp, None);
+        resolver.set_staple(
+            FAKE_OCSP_RESPONSE.to_vec(),
+            Some(now + chrono::Duration::hours(1)),
+        );
+        assert_eq!(
+            resolver.get_certified_key(now).ocsp.as_deref(),
+            Some(FAKE_OCSP_RESPONSE)
+        );
+        assert_eq!(
+            resolver
+                .get_certified_key(now + chrono::Duration::hours(2))
+                .ocsp,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_staple_is_presented() {
+        let (certs, private_key) = make_certificates();
+        let (url, responder) = start_mock_responder();
+        let (staple, next_update) = fetch_ocsp_staple(&certs, Some(&url)).await.unwrap();
+        assert_eq!(staple, FAKE_OCSP_RESPONSE);
+        assert_eq!(next_update, None);
+        let (request, _) = make_ocsp_request(&certs).unwrap();
+        assert_eq!(responder.join().unwrap(), request);
+
+        let resolver = Arc::new(OcspStaplingResolver::new(certs, &private_key).unwrap());
+        resolver.set_staple(staple, next_update);
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver);
+        let received_staple = Arc::new(Mutex::new(vec![]));
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(RecordingVerifier(received_staple.clone())))
+            .with_no_client_auth();
+
+        let (client_stream, server_stream) = tokio::io::duplex(16384);
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let (server, client) = tokio::join!(
+            acceptor.accept(server_stream),
+            connector.connect(
+                rustls::ServerName::try_from("localhost").unwrap(),
+                client_stream
+            )
+        );
+        server.unwrap();
+        client.unwrap();
+        assert_eq!(*received_staple.lock().unwrap(), FAKE_OCSP_RESPONSE);
+    }
+}
@@ -0,0 +1,591 @@
+use crate::{
+    domain::types::UserId,
+    infra::{
+        configuration::{CaptchaProvider, PasswordResetOptions},
+        proxy::http_client,
+        tcp_backend_handler::PasswordResetRequest,
+    },
+};
+use base64::Engine;
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use lldap_auth::password_reset::{is_proof_of_work_valid, ResetProtectionParams};
+use serde::Deserialize;
+use sha2::Sha512;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, warn};
+
+/// How long a proof-of-work challenge can be used, in seconds.
+const CHALLENGE_VALIDITY_SECONDS: i64 = 10 * 60;
+
+/// The proof-of-work challenges already used, with their timestamp, shared by all the workers
+/// until they expire. Each instance has its own: a challenge can still be used once on each of
+/// the other instances, but only for the same user, whose reset emails are limited in the
+/// database.
+#[derive(Clone, Default)]
+pub struct UsedChallenges(Arc<Mutex<HashMap<String, i64>>>);
+
+impl UsedChallenges {
+    /// Returns false if the challenge was already used.
+    fn consume(&self, challenge: &str, timestamp: i64, now: i64) -> bool {
+        let mut used = self.0.lock().unwrap();
+        used.retain(|_, used_timestamp| now - *used_timestamp <= CHALLENGE_VALIDITY_SECONDS);
+        used.insert(challenge.to_owned(), timestamp).is_none()
+    }
+}
+
+/// The user ID or email the challenge is issued for, case-insensitive like the lookup of the
+/// user.
+fn make_challenge_mac(
+    key: &Hmac<Sha512>,
+    timestamp: i64,
+    salt: &str,
+    identity: &str,
+) -> Hmac<Sha512> {
+    let mut mac = key.clone();
+    mac.update(
+        format!(
+            "password_reset_challenge:{}:{}:{}",
+            timestamp,
+            salt,
+            identity.to_lowercase()
+        )
+        .as_bytes(),
+    );
+    mac
+}
+
+/// Creates a stateless challenge for a reset of the user ID or email: the timestamp and a random
+/// salt, signed with the server key.
+pub fn make_challenge(key: &Hmac<Sha512>, identity: &str, now: DateTime<Utc>) -> String {
+    let timestamp = now.timestamp();
+    let salt = format!("{:016x}", rand::random::<u64>());
+    let signature = make_challenge_mac(key, timestamp, &salt, identity)
+        .finalize()
+        .into_bytes();
+    format!(
+        "{}.{}.{}",
+        timestamp,
+        salt,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature)
+    )
+}
+
+fn check_proof_of_work(
+    key: &Hmac<Sha512>,
+    used_challenges: &UsedChallenges,
+    identity: &str,
+    challenge: &str,
+    nonce: u64,
+    difficulty: u8,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let malformed = || "Malformed proof-of-work challenge".to_owned();
+    let (timestamp, salt, signature) = match challenge.splitn(3, '.').collect::<Vec<_>>()[..] {
+        [timestamp, salt, signature] => (timestamp, salt, signature),
+        _ => return Err(malformed()),
+    };
+    let timestamp: i64 = timestamp.parse().map_err(|_| malformed())?;
+    let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| malformed())?;
+    make_challenge_mac(key, timestamp, salt, identity)
+        .verify_slice(&signature)
+        .map_err(|_| "Invalid proof-of-work challenge".to_owned())?;
+    let age = now.timestamp() - timestamp;
+    if !(0..=CHALLENGE_VALIDITY_SECONDS).contains(&age) {
+        return Err("Expired proof-of-work challenge".to_owned());
+    }
+    if !is_proof_of_work_valid(challenge, nonce, difficulty) {
+        return Err("Invalid proof of work".to_owned());
+    }
+    if !used_challenges.consume(challenge, timestamp, now.timestamp()) {
+        return Err("Proof-of-work challenge already used".to_owned());
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct CaptchaVerifyResponse {
+    success: bool,
+}
+
+async fn verify_captcha(
+    options: &PasswordResetOptions,
+    provider: CaptchaProvider,
+    response: &str,
+) -> Result<(), String> {
+    let url = options
+        .captcha_verify_url
+        .clone()
+        .unwrap_or_else(|| provider.default_verify_url());
+    let verification = async {
+        let body = http_client()?
+            .post(url)
+            .form(&[
+                ("secret", options.captcha_secret.unsecure()),
+                ("response", response),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok::<_, anyhow::Error>(serde_json::from_slice::<CaptchaVerifyResponse>(&body)?)
+    }
+    .await
+    .map_err(|e| {
+        warn!("Could not verify the captcha: {:#}", e);
+        "Could not verify the captcha".to_owned()
+    })?;
+    if verification.success {
+        Ok(())
+    } else {
+        Err("Invalid captcha".to_owned())
+    }
+}
+
+/// Checks the proof of work and captcha of a password reset request for the user ID or email, if
+/// they are enabled.
+pub async fn check_reset_protections(
+    options: &PasswordResetOptions,
+    key: &Hmac<Sha512>,
+    used_challenges: &UsedChallenges,
+    identity: &str,
+    params: &ResetProtectionParams,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    if let Some(difficulty) = options.proof_of_work_difficulty {
+        match (&params.pow_challenge, params.pow_nonce) {
+            (Some(challenge), Some(nonce)) => check_proof_of_work(
+                key,
+                used_challenges,
+                identity,
+                challenge,
+                nonce,
+                difficulty,
+                now,
+            )?,
+            _ => return Err("Missing proof of work".to_owned()),
+        }
+    }
+    if let Some(provider) = options.captcha_provider {
+        let response = params
+            .captcha_response
+            .as_deref()
+            .ok_or_else(|| "Missing captcha response".to_owned())?;
+        verify_captcha(options, provider, response).await?;
+    }
+    Ok(())
+}
+
+/// The window of the hourly caps.
+fn rate_limit_window() -> chrono::Duration {
+    chrono::Duration::hours(1)
+}
+
+/// The oldest reset emails that [`is_reset_allowed`] needs to know about.
+pub fn rate_limit_history_start(
+    options: &PasswordResetOptions,
+    now: NaiveDateTime,
+) -> NaiveDateTime {
+    now - std::cmp::max(
+        rate_limit_window(),
+        chrono::Duration::seconds(options.rate_limit_seconds as i64),
+    )
+}
+
+fn is_under_cap(count: usize, cap: Option<u32>) -> bool {
+    cap.map(|cap| count < cap as usize).unwrap_or(true)
+}
+
+/// Returns whether a reset email can be sent to the user, given the ones recently sent by all the
+/// instances.
+///
+/// Limits the number of reset emails sent to each user and address, and overall.
+pub fn is_reset_allowed(
+    sent: &[PasswordResetRequest],
+    user_id: &UserId,
+    address: &str,
+    options: &PasswordResetOptions,
+    now: NaiveDateTime,
+) -> bool {
+    let min_delay = chrono::Duration::seconds(options.rate_limit_seconds as i64);
+    let address = address.to_lowercase();
+    let in_window = |request: &&PasswordResetRequest| now - request.date < rate_limit_window();
+    if sent
+        .iter()
+        .any(|request| &request.user_id == user_id && now - request.date < min_delay)
+    {
+        debug!(?user_id, "Password reset rate-limited for the user");
+        false
+    } else if !is_under_cap(
+        sent.iter()
+            .filter(in_window)
+            .filter(|request| request.address == address)
+            .count(),
+        options.max_per_hour_per_address,
+    ) {
+        debug!(?user_id, "Password reset rate-limited for the address");
+        false
+    } else if !is_under_cap(sent.iter().filter(in_window).count(), options.max_per_hour) {
+        warn!("Global password reset email cap reached, not sending the email");
+        false
+    } else {
+        true
+    }
+}
+
+fn email_domain_matches(domains: &[String], domain: &str) -> bool {
+    domains
+        .iter()
+        .any(|allowed| allowed.trim_start_matches('@').eq_ignore_ascii_case(domain))
+}
+
+/// Returns whether the recipient domain of a reset email is allowed by the configuration.
+pub fn is_reset_email_domain_allowed(options: &PasswordResetOptions, address: &str) -> bool {
+    let domain = match address.rsplit_once('@') {
+        None => return false,
+        Some((_, domain)) => domain,
+    };
+    !email_domain_matches(&options.reset_email_denied_domains, domain)
+        && (options.reset_email_allowed_domains.is_empty()
+            || email_domain_matches(&options.reset_email_allowed_domains, domain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lldap_auth::password_reset::solve_proof_of_work;
+
+    fn make_key() -> Hmac<Sha512> {
+        Hmac::new_from_slice(b"secret").unwrap()
+    }
+
+    fn make_options(difficulty: Option<u8>) -> PasswordResetOptions {
+        PasswordResetOptions {
+            proof_of_work_difficulty: difficulty,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_protection() {
+        check_reset_protections(
+            &make_options(None),
+            &make_key(),
+            &UsedChallenges::default(),
+            "bob",
+            &ResetProtectionParams::default(),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+    }
+
+    fn make_params(challenge: &str, nonce: u64) -> ResetProtectionParams {
+        ResetProtectionParams {
+            pow_challenge: Some(challenge.to_owned()),
+            pow_nonce: Some(nonce),
+            captcha_response: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proof_of_work() {
+        let key = &make_key();
+        let used = &UsedChallenges::default();
+        let options = &make_options(Some(8));
+        let now = Utc::now();
+        let check = move |identity: &'static str, params: ResetProtectionParams, now| async move {
+            check_reset_protections(options, key, used, identity, &params, now).await
+        };
+        let challenge = make_challenge(key, "bob", now);
+        let nonce = solve_proof_of_work(&challenge, 8);
+        assert_eq!(
+            check("bob", ResetProtectionParams::default(), now).await,
+            Err("Missing proof of work".to_owned())
+        );
+        let wrong_nonce = (0..)
+            .find(|&n| !is_proof_of_work_valid(&challenge, n, 8))
+            .unwrap();
+        assert_eq!(
+            check("bob", make_params(&challenge, wrong_nonce), now).await,
+            Err("Invalid proof of work".to_owned())
+        );
+        // Challenges not signed by the server are rejected.
+        let forged = format!("{}.0000000000000000.AAAA", now.timestamp());
+        let forged_nonce = solve_proof_of_work(&forged, 8);
+        assert_eq!(
+            check("bob", make_params(&forged, forged_nonce), now).await,
+            Err("Invalid proof-of-work challenge".to_owned())
+        );
+        assert_eq!(
+            check(
+                "bob",
+                make_params(&challenge, nonce),
+                now + chrono::Duration::hours(1)
+            )
+            .await,
+            Err("Expired proof-of-work challenge".to_owned())
+        );
+        // The identity is case-insensitive, like the lookup of the user.
+        check("BOB", make_params(&challenge, nonce), now)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_proof_of_work_replay() {
+        let key = make_key();
+        let used = UsedChallenges::default();
+        let options = make_options(Some(8));
+        let now = Utc::now();
+        let challenge = make_challenge(&key, "bob", now);
+        let params = make_params(&challenge, solve_proof_of_work(&challenge, 8));
+        // The challenge can't be used for another account.
+        assert_eq!(
+            check_reset_protections(&options, &key, &used, "john", &params, now).await,
+            Err("Invalid proof-of-work challenge".to_owned())
+        );
+        check_reset_protections(&options, &key, &used, "bob", &params, now)
+            .await
+            .unwrap();
+        // Nor again for the same one, while it's valid.
+        assert_eq!(
+            check_reset_protections(
+                &options,
+                &key,
+                &used,
+                "bob",
+                &params,
+                now + chrono::Duration::minutes(5)
+            )
+            .await,
+            Err("Proof-of-work challenge already used".to_owned())
+        );
+        // Each challenge is different, even at the same time.
+        let other_challenge = make_challenge(&key, "bob", now);
+        assert_ne!(other_challenge, challenge);
+        check_reset_protections(
+            &options,
+            &key,
+            &used,
+            "bob",
+            &make_params(&other_challenge, solve_proof_of_work(&other_challenge, 8)),
+            now,
+        )
+        .await
+        .unwrap();
+        // The expired challenges are forgotten.
+        let later = now + chrono::Duration::minutes(11);
+        used.consume("other", later.timestamp(), later.timestamp());
+        assert_eq!(used.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_missing_captcha() {
+        let options = PasswordResetOptions {
+            captcha_provider: Some(CaptchaProvider::Turnstile),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_reset_protections(
+                &options,
+                &make_key(),
+                &UsedChallenges::default(),
+                "bob",
+                &ResetProtectionParams::default(),
+                Utc::now()
+            )
+            .await,
+            Err("Missing captcha response".to_owned())
+        );
+    }
+
+    /// Records the email if it's allowed, like the password reset handler.
+    fn try_acquire(
+        sent: &mut Vec<PasswordResetRequest>,
+        user: &str,
+        address: &str,
+        options: &PasswordResetOptions,
+        now: NaiveDateTime,
+    ) -> bool {
+        let user_id = UserId::new(user);
+        let allowed = is_reset_allowed(sent, &user_id, address, options, now);
+        if allowed {
+            sent.push(PasswordResetRequest {
+                user_id,
+                address: address.to_lowercase(),
+                date: now,
+            });
+        }
+        allowed
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut sent = Vec::new();
+        let options = PasswordResetOptions {
+            rate_limit_seconds: 60,
+            ..Default::default()
+        };
+        let now = Utc::now().naive_utc();
+        assert!(try_acquire(&mut sent, "bob", "bob@bob.bob", &options, now));
+        assert!(!try_acquire(&mut sent, "bob", "bob@bob.bob", &options, now));
+        assert!(try_acquire(
+            &mut sent,
+            "john",
+            "john@bob.bob",
+            &options,
+            now
+        ));
+        assert!(try_acquire(
+            &mut sent,
+            "bob",
+            "bob@bob.bob",
+            &options,
+            now + chrono::Duration::seconds(60)
+        ));
+        let options = PasswordResetOptions {
+            rate_limit_seconds: 0,
+            ..Default::default()
+        };
+        assert!(try_acquire(&mut sent, "bob", "bob@bob.bob", &options, now));
+    }
+
+    #[test]
+    fn test_rate_limiter_per_address() {
+        let mut sent = Vec::new();
+        let options = PasswordResetOptions {
+            rate_limit_seconds: 0,
+            max_per_hour_per_address: Some(3),
+            ..Default::default()
+        };
+        let start = Utc::now().naive_utc();
+        let at = |minutes| start + chrono::Duration::minutes(minutes);
+        for minute in 0..3 {
+            assert!(try_acquire(
+                &mut sent,
+                "bob",
+                "bob@bob.bob",
+                &options,
+                at(minute)
+            ));
+        }
+        // The 4th request is suppressed, even with a different case.
+        assert!(!try_acquire(
+            &mut sent,
+            "bob",
+            "BOB@bob.bob",
+            &options,
+            at(3)
+        ));
+        assert!(try_acquire(
+            &mut sent,
+            "john",
+            "john@bob.bob",
+            &options,
+            at(3)
+        ));
+        // The first request is out of the window.
+        assert!(try_acquire(
+            &mut sent,
+            "bob",
+            "bob@bob.bob",
+            &options,
+            at(60)
+        ));
+        assert!(!try_acquire(
+            &mut sent,
+            "bob",
+            "bob@bob.bob",
+            &options,
+            at(60)
+        ));
+    }
+
+    #[test]
+    fn test_rate_limiter_global() {
+        let mut sent = Vec::new();
+        let options = PasswordResetOptions {
+            rate_limit_seconds: 0,
+            max_per_hour: Some(2),
+            ..Default::default()
+        };
+        let start = Utc::now().naive_utc();
+        assert!(try_acquire(
+            &mut sent,
+            "bob",
+            "bob@bob.bob",
+            &options,
+            start
+        ));
+        assert!(try_acquire(
+            &mut sent,
+            "john",
+            "john@bob.bob",
+            &options,
+            start
+        ));
+        assert!(!try_acquire(
+            &mut sent,
+            "patrick",
+            "patrick@bob.bob",
+            &options,
+            start
+        ));
+        assert!(try_acquire(
+            &mut sent,
+            "patrick",
+            "patrick@bob.bob",
+            &options,
+            start + rate_limit_window()
+        ));
+    }
+
+    #[test]
+    fn test_rate_limit_history_start() {
+        let now = Utc::now().naive_utc();
+        let options = PasswordResetOptions {
+            rate_limit_seconds: 60,
+            ..Default::default()
+        };
+        assert_eq!(
+            rate_limit_history_start(&options, now),
+            now - chrono::Duration::hours(1)
+        );
+        let options = PasswordResetOptions {
+            rate_limit_seconds: 24 * 60 * 60,
+            ..Default::default()
+        };
+        assert_eq!(
+            rate_limit_history_start(&options, now),
+            now - chrono::Duration::days(1)
+        );
+    }
+
+    #[test]
+    fn test_reset_email_domain() {
+        let options = PasswordResetOptions::default();
+        assert!(is_reset_email_domain_allowed(&options, "bob@example.com"));
+        assert!(!is_reset_email_domain_allowed(&options, "bob"));
+        let options = PasswordResetOptions {
+            reset_email_allowed_domains: vec!["example.com".to_owned(), "@bob.bob".to_owned()],
+            reset_email_denied_domains: vec!["bob.bob".to_owned()],
+            ..Default::default()
+        };
+        assert!(is_reset_email_domain_allowed(&options, "bob@example.com"));
+        assert!(is_reset_email_domain_allowed(&options, "bob@Example.COM"));
+        assert!(!is_reset_email_domain_allowed(&options, "bob@evil.com"));
+        assert!(!is_reset_email_domain_allowed(
+            &options,
+            "bob@sub.example.com"
+        ));
+        // The denied domains take precedence.
+        assert!(!is_reset_email_domain_allowed(&options, "bob@bob.bob"));
+    }
+}
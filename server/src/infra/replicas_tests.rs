@@ -0,0 +1,190 @@
+//! Several LLDAP instances sharing the same database, as behind a load balancer.
+
+use crate::{
+    domain::{
+        handler::{
+            BindRequest, CreateUserRequest, GroupBackendHandler, LoginHandler, UserBackendHandler,
+            UserListerBackendHandler, UserRequestFilter,
+        },
+        sql_backend_handler::SqlBackendHandler,
+        sql_opaque_handler::register_password,
+        sql_tables::init_table,
+        types::UserId,
+    },
+    infra::{
+        configuration::{ConfigurationBuilder, PasswordResetOptions},
+        jwt_sql_tables,
+        password_reset_protection::{is_reset_allowed, rate_limit_history_start},
+        tcp_backend_handler::{PasswordResetRequest, TcpBackendHandler},
+    },
+};
+use secstr::SecUtf8;
+
+struct TempDatabase(std::path::PathBuf);
+
+impl TempDatabase {
+    fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("lldap_replicas_{}.db", uuid::Uuid::new_v4())))
+    }
+
+    fn url(&self) -> String {
+        format!("sqlite://{}?mode=rwc", self.0.display())
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Starts an instance with its own connection pool, like a separate server would.
+async fn start_instance(database: &TempDatabase) -> SqlBackendHandler {
+    let config = ConfigurationBuilder::default()
+        .database_url(database.url())
+        .key_seed(Some(SecUtf8::from("shared test seed")))
+        .build()
+        .unwrap();
+    let sql_pool = sea_orm::Database::connect(database.url()).await.unwrap();
+    init_table(&sql_pool).await.unwrap();
+    jwt_sql_tables::init_table(&sql_pool).await.unwrap();
+    SqlBackendHandler::new(config, sql_pool)
+}
+
+async fn create_user(handler: &SqlBackendHandler, name: &str) -> crate::domain::error::Result<()> {
+    handler
+        .create_user(CreateUserRequest {
+            user_id: UserId::new(name),
+            email: format!("{}@example.com", name),
+            ..Default::default()
+        })
+        .await
+}
+
+#[tokio::test]
+async fn concurrent_mutations() {
+    let database = TempDatabase::new();
+    let first = start_instance(&database).await;
+    let second = start_instance(&database).await;
+    let group = first.create_group("developers").await.unwrap();
+    let names = (0..10).map(|i| format!("user{}", i)).collect::<Vec<_>>();
+    futures::future::join_all(names.iter().enumerate().map(|(i, name)| {
+        let instance = if i % 2 == 0 { &first } else { &second };
+        async move {
+            create_user(instance, name).await.unwrap();
+            instance
+                .add_user_to_group(&UserId::new(name), group)
+                .await
+                .unwrap();
+        }
+    }))
+    .await;
+    // Creating the same user on both instances at the same time only succeeds once.
+    let (from_first, from_second) =
+        futures::join!(create_user(&first, "bob"), create_user(&second, "bob"));
+    assert_eq!(
+        [from_first.is_ok(), from_second.is_ok()]
+            .iter()
+            .filter(|ok| **ok)
+            .count(),
+        1
+    );
+    for instance in [&first, &second] {
+        assert_eq!(instance.list_users(None, false).await.unwrap().len(), 11);
+        let users = instance
+            .list_users(Some(UserRequestFilter::MemberOfId(group)), false)
+            .await
+            .unwrap();
+        assert_eq!(users.len(), 10);
+    }
+}
+
+#[tokio::test]
+async fn passwords_and_sessions_are_shared() {
+    let database = TempDatabase::new();
+    let first = start_instance(&database).await;
+    let second = start_instance(&database).await;
+    let bob = UserId::new("bob");
+    create_user(&first, "bob").await.unwrap();
+    // The server key comes from the shared configuration, so a password set on one instance can
+    // be checked by the other.
+    register_password(&first, &bob, &SecUtf8::from("bob00000"))
+        .await
+        .unwrap();
+    second
+        .bind(BindRequest {
+            name: bob.clone(),
+            password: "bob00000".to_owned(),
+        })
+        .await
+        .unwrap();
+
+    let (refresh_token, _) = first.create_refresh_token(&bob).await.unwrap();
+    let refresh_token_hash = {
+        use std::hash::{Hash, Hasher};
+        let mut s = std::collections::hash_map::DefaultHasher::new();
+        refresh_token.hash(&mut s);
+        s.finish()
+    };
+    assert!(second.check_token(refresh_token_hash, &bob).await.unwrap());
+    // Invalidating the sessions on one instance is visible on the other.
+    let now = chrono::Utc::now().naive_utc();
+    second.invalidate_user_sessions(&bob, now).await.unwrap();
+    assert!(!first.check_token(refresh_token_hash, &bob).await.unwrap());
+    assert!(first
+        .get_tokens_valid_after()
+        .await
+        .unwrap()
+        .contains_key(&bob));
+
+    let reset_token = first.start_password_reset(&bob).await.unwrap().unwrap();
+    assert_eq!(
+        second
+            .get_user_id_for_password_reset_token(&reset_token)
+            .await
+            .unwrap(),
+        bob
+    );
+    second
+        .delete_password_reset_token(&reset_token)
+        .await
+        .unwrap();
+    first
+        .get_user_id_for_password_reset_token(&reset_token)
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn password_reset_rate_limit_is_shared() {
+    let database = TempDatabase::new();
+    let first = start_instance(&database).await;
+    let second = start_instance(&database).await;
+    let bob = UserId::new("bob");
+    create_user(&first, "bob").await.unwrap();
+    let options = PasswordResetOptions {
+        rate_limit_seconds: 60,
+        ..Default::default()
+    };
+    let now = chrono::Utc::now().naive_utc();
+    first
+        .add_password_reset_request(PasswordResetRequest {
+            user_id: bob.clone(),
+            address: "bob@example.com".to_owned(),
+            date: now,
+        })
+        .await
+        .unwrap();
+    let sent = second
+        .get_password_reset_requests(rate_limit_history_start(&options, now))
+        .await
+        .unwrap();
+    assert_eq!(sent.len(), 1);
+    assert!(!is_reset_allowed(
+        &sent,
+        &bob,
+        "bob@example.com",
+        &options,
+        now
+    ));
+}
@@ -0,0 +1,318 @@
+//! Delivery of the password reset links: by email, and/or to a webhook that an operator can wire
+//! to SMS, a chat, etc. The tokens are generated the same way whatever the channel.
+
+use crate::{
+    domain::types::User,
+    infra::{
+        configuration::{MailOptions, PasswordResetOptions},
+        mail::{make_password_reset_url, send_password_reset_email},
+        proxy::http_client,
+    },
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use url::Url;
+
+/// What the user needs to reset their password.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ResetNotification {
+    pub user_id: String,
+    pub email: String,
+    pub display_name: String,
+    pub locale: Option<String>,
+    pub token: String,
+    /// The link of the web UI to finish the reset.
+    pub url: Url,
+}
+
+impl ResetNotification {
+    pub fn new(
+        user_id: String,
+        email: String,
+        display_name: String,
+        locale: Option<String>,
+        token: String,
+        server_url: &Url,
+    ) -> Self {
+        let url = make_password_reset_url(server_url, &token);
+        Self {
+            user_id,
+            email,
+            display_name,
+            locale,
+            token,
+            url,
+        }
+    }
+}
+
+#[async_trait]
+pub trait ResetNotifier: Send + Sync {
+    /// The name of the channel, for the logs.
+    fn name(&self) -> &'static str;
+    async fn notify(&self, notification: &ResetNotification) -> Result<()>;
+}
+
+pub struct EmailNotifier {
+    pub server_url: Url,
+    pub default_locale: String,
+    pub mail_options: MailOptions,
+}
+
+#[async_trait]
+impl ResetNotifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn notify(&self, notification: &ResetNotification) -> Result<()> {
+        send_password_reset_email(
+            &notification.display_name,
+            &notification.email,
+            &notification.token,
+            &self.server_url,
+            notification.locale.as_deref(),
+            &self.default_locale,
+            &self.mail_options,
+        )
+        .await
+    }
+}
+
+/// POSTs the notification as JSON to the URL.
+pub struct WebhookNotifier {
+    pub url: Url,
+}
+
+#[async_trait]
+impl ResetNotifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn notify(&self, notification: &ResetNotification) -> Result<()> {
+        http_client()?
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(notification)?)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("Could not call the webhook `{}`", self.url))?;
+        Ok(())
+    }
+}
+
+/// Where to send the reset emails of the user: the configured attribute if it's set, the primary
+/// email otherwise.
+pub fn get_reset_email_address(
+    user: &User,
+    password_reset_options: &PasswordResetOptions,
+) -> String {
+    password_reset_options
+        .email_attribute
+        .as_ref()
+        .and_then(|name| user.attributes.iter().find(|a| &a.name == name))
+        .and_then(|a| a.value.convert_to::<String>().ok())
+        .filter(|address| !address.is_empty())
+        .unwrap_or_else(|| user.email.clone())
+}
+
+/// Whether the reset links can be delivered at all, i.e. the reset endpoints are enabled.
+pub fn is_reset_enabled(
+    mail_options: &MailOptions,
+    password_reset_options: &PasswordResetOptions,
+) -> bool {
+    mail_options.enable_password_reset || password_reset_options.webhook_url.is_some()
+}
+
+/// The configured channels: the email if the password reset is enabled in the SMTP options, and
+/// the webhook if there is one.
+pub fn make_reset_notifiers(
+    server_url: &Url,
+    default_locale: &str,
+    mail_options: &MailOptions,
+    password_reset_options: &PasswordResetOptions,
+) -> Vec<Box<dyn ResetNotifier>> {
+    let mut notifiers: Vec<Box<dyn ResetNotifier>> = Vec::new();
+    if mail_options.enable_password_reset {
+        notifiers.push(Box::new(EmailNotifier {
+            server_url: server_url.clone(),
+            default_locale: default_locale.to_owned(),
+            mail_options: mail_options.clone(),
+        }));
+    }
+    if let Some(url) = &password_reset_options.webhook_url {
+        notifiers.push(Box::new(WebhookNotifier { url: url.clone() }));
+    }
+    notifiers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers a single HTTP request with the given status, and returns the request.
+    fn start_mock_webhook(status: &'static str) -> (Url, std::thread::JoinHandle<String>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                request.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            write!(
+                reader.into_inner(),
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    fn make_notification() -> ResetNotification {
+        ResetNotification::new(
+            "bob".to_owned(),
+            "bob@example.com".to_owned(),
+            "Bob".to_owned(),
+            Some("fr".to_owned()),
+            "reset-token".to_owned(),
+            &Url::parse("https://lldap.example.com").unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_get_reset_email_address() {
+        use crate::domain::types::{AttributeValue, Serialized};
+        let user = User {
+            email: "bob@example.com".to_owned(),
+            attributes: vec![AttributeValue {
+                name: "recovery_email".to_owned(),
+                value: Serialized::from("bob@personal.example.org"),
+            }],
+            ..Default::default()
+        };
+        let options = |attribute: Option<&str>| PasswordResetOptions {
+            email_attribute: attribute.map(str::to_owned),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_reset_email_address(&user, &options(None)),
+            "bob@example.com"
+        );
+        assert_eq!(
+            get_reset_email_address(&user, &options(Some("recovery_email"))),
+            "bob@personal.example.org"
+        );
+        // Without the attribute, the primary email is used.
+        assert_eq!(
+            get_reset_email_address(&user, &options(Some("other_email"))),
+            "bob@example.com"
+        );
+        let user = User {
+            attributes: vec![AttributeValue {
+                name: "recovery_email".to_owned(),
+                value: Serialized::from(""),
+            }],
+            ..user
+        };
+        assert_eq!(
+            get_reset_email_address(&user, &options(Some("recovery_email"))),
+            "bob@example.com"
+        );
+    }
+
+    #[test]
+    fn test_make_reset_notifiers() {
+        let server_url = Url::parse("http://localhost").unwrap();
+        let names = |mail_options: &MailOptions, reset_options: &PasswordResetOptions| {
+            make_reset_notifiers(&server_url, "en", mail_options, reset_options)
+                .iter()
+                .map(|n| n.name())
+                .collect::<Vec<_>>()
+        };
+        let no_email = MailOptions::default();
+        let email = MailOptions {
+            enable_password_reset: true,
+            ..Default::default()
+        };
+        let no_webhook = PasswordResetOptions::default();
+        let webhook = PasswordResetOptions {
+            webhook_url: Some(Url::parse("https://hooks.example.com").unwrap()),
+            ..Default::default()
+        };
+        assert!(names(&no_email, &no_webhook).is_empty());
+        assert!(!is_reset_enabled(&no_email, &no_webhook));
+        assert_eq!(names(&email, &no_webhook), vec!["email"]);
+        assert_eq!(names(&no_email, &webhook), vec!["webhook"]);
+        assert!(is_reset_enabled(&no_email, &webhook));
+        assert_eq!(names(&email, &webhook), vec!["email", "webhook"]);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_receives_token() {
+        let (url, webhook) = start_mock_webhook("200 OK");
+        let notifiers = make_reset_notifiers(
+            &Url::parse("https://lldap.example.com").unwrap(),
+            "en",
+            &MailOptions::default(),
+            &PasswordResetOptions {
+                webhook_url: Some(url),
+                ..Default::default()
+            },
+        );
+        let notification = make_notification();
+        assert_eq!(
+            notification.url.as_str(),
+            "https://lldap.example.com/reset-password/step2/reset-token"
+        );
+        notifiers[0].notify(&notification).await.unwrap();
+        let request = webhook.join().unwrap();
+        assert!(request.starts_with("POST /hook "));
+        assert!(request
+            .to_ascii_lowercase()
+            .contains("content-type: application/json"));
+        let body = &request[request.find('{').unwrap()..];
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+            serde_json::json!({
+                "user_id": "bob",
+                "email": "bob@example.com",
+                "display_name": "Bob",
+                "locale": "fr",
+                "token": "reset-token",
+                "url": "https://lldap.example.com/reset-password/step2/reset-token",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_error() {
+        let (url, webhook) = start_mock_webhook("500 Internal Server Error");
+        WebhookNotifier { url }
+            .notify(&make_notification())
+            .await
+            .unwrap_err();
+        webhook.join().unwrap();
+    }
+}
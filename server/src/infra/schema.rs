@@ -22,6 +22,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: false,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -30,6 +31,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: false,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -38,6 +40,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: true,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -46,6 +49,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: false,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -54,6 +58,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: true,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
         ]);
@@ -68,6 +73,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: false,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -76,6 +82,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: false,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -84,6 +91,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: false,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
             AttributeSchema {
@@ -92,6 +100,7 @@ impl From<Schema> for PublicSchema {
                 is_list: false,
                 is_visible: true,
                 is_editable: true,
+                is_ldap_visible: true,
                 is_hardcoded: true,
             },
         ]);
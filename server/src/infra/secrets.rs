@@ -0,0 +1,135 @@
+//! Secrets referenced in the configuration instead of their value, and fetched at startup:
+//! `vault://path#field` for HashiCorp Vault, authenticated with the `VAULT_ADDR` and
+//! `VAULT_TOKEN` environment variables.
+
+use crate::infra::proxy::http_client;
+use anyhow::{bail, Context, Result};
+use secstr::SecUtf8;
+use url::Url;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SecretReference<'a> {
+    /// A field of a Vault secret, from a KV engine (version 1 or 2).
+    Vault { path: &'a str, field: &'a str },
+    /// A secret of AWS Secrets Manager.
+    AwsSecretsManager { name: &'a str },
+}
+
+/// Parses a secret reference, or returns None if the value is not a reference.
+pub fn parse_secret_reference(value: &str) -> Result<Option<SecretReference>> {
+    if let Some(reference) = value.strip_prefix("vault://") {
+        match reference.split_once('#') {
+            Some((path, field)) if !path.is_empty() && !field.is_empty() => {
+                Ok(Some(SecretReference::Vault { path, field }))
+            }
+            _ => bail!(
+                "Invalid Vault reference `{}`, expected `vault://path#field`",
+                value
+            ),
+        }
+    } else if let Some(name) = value.strip_prefix("awssm://") {
+        if name.is_empty() {
+            bail!("Invalid AWS Secrets Manager reference `{}`", value);
+        }
+        Ok(Some(SecretReference::AwsSecretsManager { name }))
+    } else {
+        Ok(None)
+    }
+}
+
+struct VaultClient {
+    address: Url,
+    token: SecUtf8,
+}
+
+impl VaultClient {
+    fn from_env() -> Result<Self> {
+        let address = std::env::var("VAULT_ADDR")
+            .context("VAULT_ADDR must be set to read secrets from Vault")?;
+        let token = std::env::var("VAULT_TOKEN")
+            .context("VAULT_TOKEN must be set to read secrets from Vault")?;
+        Ok(Self {
+            address: Url::parse(&address).context("VAULT_ADDR is not a valid URL")?,
+            token: SecUtf8::from(token),
+        })
+    }
+
+    async fn read_field(&self, path: &str, field: &str) -> Result<String> {
+        let url = self
+            .address
+            .join(&format!("v1/{}", path.trim_start_matches('/')))?;
+        let body = http_client()?
+            .get(url)
+            .header("X-Vault-Token", self.token.unsecure())
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let secret: serde_json::Value = serde_json::from_slice(&body)?;
+        get_vault_field(&secret, field)
+            .with_context(|| format!("No field `{}` in the Vault secret `{}`", field, path))
+    }
+}
+
+/// The KV version 2 engine nests the fields in another `data` object.
+fn get_vault_field(secret: &serde_json::Value, field: &str) -> Option<String> {
+    let data = &secret["data"];
+    data["data"][field]
+        .as_str()
+        .or_else(|| data[field].as_str())
+        .map(str::to_owned)
+}
+
+/// Fetches the secret referenced by the value, or returns None if it is not a reference.
+pub fn resolve_secret(value: &str) -> Result<Option<String>> {
+    match parse_secret_reference(value)? {
+        None => Ok(None),
+        Some(SecretReference::Vault { path, field }) => {
+            let client = VaultClient::from_env()?;
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime
+                .block_on(client.read_field(path, field))
+                .with_context(|| format!("while reading `{}` from Vault", value))
+                .map(Some)
+        }
+        Some(SecretReference::AwsSecretsManager { .. }) => bail!(
+            "AWS Secrets Manager references are not supported yet: `{}`",
+            value
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_secret_reference() {
+        assert_eq!(
+            parse_secret_reference("vault://secret/data/lldap#jwt_secret").unwrap(),
+            Some(SecretReference::Vault {
+                path: "secret/data/lldap",
+                field: "jwt_secret"
+            })
+        );
+        assert_eq!(
+            parse_secret_reference("awssm://lldap/jwt").unwrap(),
+            Some(SecretReference::AwsSecretsManager { name: "lldap/jwt" })
+        );
+        assert_eq!(parse_secret_reference("my secret").unwrap(), None);
+        parse_secret_reference("vault://secret/data/lldap").unwrap_err();
+        parse_secret_reference("vault://#field").unwrap_err();
+    }
+
+    #[test]
+    fn test_get_vault_field() {
+        let kv_v2 = serde_json::json!({"data": {"data": {"password": "v2"}, "metadata": {}}});
+        assert_eq!(get_vault_field(&kv_v2, "password"), Some("v2".to_owned()));
+        let kv_v1 = serde_json::json!({"data": {"password": "v1"}});
+        assert_eq!(get_vault_field(&kv_v1, "password"), Some("v1".to_owned()));
+        assert_eq!(get_vault_field(&kv_v1, "other"), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Sliding expiry of the web sessions: a session without any request for longer than the idle
+//! timeout is rejected, even if its tokens haven't expired yet. A session is a refresh token and
+//! the JWTs issued with it. The activity is only kept in memory, so after a restart the refresh
+//! tokens issued before are rejected and the users have to log in again, and it only works with a
+//! single instance: the other instances sharing the database don't see the requests made to this
+//! one.
+
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+struct Session {
+    last_activity: DateTime<Utc>,
+    /// Past it, none of the tokens of the session are valid anymore, and it can be forgotten.
+    expiry_date: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    /// By hash of the refresh token, or of the JWT for the JWTs issued before a restart.
+    sessions: HashMap<u64, Session>,
+    /// The session of each JWT, by hash.
+    jwts: HashMap<u64, u64>,
+}
+
+impl Sessions {
+    /// Whether the session is still active, in which case its activity is updated. An unknown
+    /// session is not active: its activity can't be checked.
+    fn touch(&mut self, key: u64, idle_timeout: Duration, now: DateTime<Utc>) -> bool {
+        match self.sessions.get_mut(&key) {
+            Some(session) if now - session.last_activity <= idle_timeout => {
+                session.last_activity = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn remove_expired(&mut self, now: DateTime<Utc>) {
+        self.sessions.retain(|_, session| session.expiry_date > now);
+        let sessions = &self.sessions;
+        self.jwts
+            .retain(|_, session| sessions.contains_key(session));
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SessionActivity {
+    /// None disables the tracking.
+    idle_timeout: Option<Duration>,
+    sessions: Arc<RwLock<Sessions>>,
+}
+
+impl SessionActivity {
+    pub fn new(idle_timeout: Option<Duration>) -> Self {
+        Self {
+            idle_timeout,
+            sessions: Default::default(),
+        }
+    }
+
+    /// Starts the session of a refresh token on login, with its first JWT. The session is kept
+    /// until the refresh token expires, at `expiry_date`.
+    pub fn start_session(
+        &self,
+        refresh_token_hash: u64,
+        jwt_hash: u64,
+        expiry_date: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) {
+        if self.idle_timeout.is_none() {
+            return;
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.remove_expired(now);
+        sessions.sessions.insert(
+            refresh_token_hash,
+            Session {
+                last_activity: now,
+                expiry_date,
+            },
+        );
+        sessions.jwts.insert(jwt_hash, refresh_token_hash);
+    }
+
+    /// Records a JWT issued for the session of the refresh token, on refresh. It counts as
+    /// activity. Nothing is recorded if the session is unknown.
+    pub fn add_jwt(&self, refresh_token_hash: u64, jwt_hash: u64, now: DateTime<Utc>) {
+        if self.idle_timeout.is_none() {
+            return;
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.remove_expired(now);
+        if let Some(session) = sessions.sessions.get_mut(&refresh_token_hash) {
+            session.last_activity = now;
+            sessions.jwts.insert(jwt_hash, refresh_token_hash);
+        }
+    }
+
+    /// Whether the session of the JWT is still active, in which case the request counts as
+    /// activity. An unknown JWT starts its own session, that ends with it.
+    pub fn touch_jwt(&self, jwt_hash: u64, expiry_date: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let idle_timeout = match self.idle_timeout {
+            None => return true,
+            Some(timeout) => timeout,
+        };
+        let mut sessions = self.sessions.write().unwrap();
+        let session = match sessions.jwts.get(&jwt_hash) {
+            Some(session) => *session,
+            None => {
+                sessions.jwts.insert(jwt_hash, jwt_hash);
+                sessions.sessions.insert(
+                    jwt_hash,
+                    Session {
+                        last_activity: now,
+                        expiry_date,
+                    },
+                );
+                return true;
+            }
+        };
+        sessions.touch(session, idle_timeout, now)
+    }
+
+    /// Whether the session of the refresh token is still active, in which case the request counts
+    /// as activity. The refresh tokens without a session, e.g. issued before a restart, are
+    /// rejected.
+    pub fn touch_refresh_token(&self, refresh_token_hash: u64, now: DateTime<Utc>) -> bool {
+        match self.idle_timeout {
+            None => true,
+            Some(idle_timeout) => {
+                self.sessions
+                    .write()
+                    .unwrap()
+                    .touch(refresh_token_hash, idle_timeout, now)
+            }
+        }
+    }
+
+    /// On logout.
+    pub fn remove_session(&self, refresh_token_hash: u64) {
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.sessions.remove(&refresh_token_hash);
+        sessions
+            .jwts
+            .retain(|_, session| *session != refresh_token_hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn make_activity() -> (SessionActivity, DateTime<Utc>) {
+        let login = Utc.timestamp_opt(1_000_000, 0).unwrap();
+        let activity = SessionActivity::new(Some(Duration::minutes(30)));
+        activity.start_session(1, 10, login + Duration::days(30), login);
+        (activity, login)
+    }
+
+    #[test]
+    fn test_session_rejected_after_inactivity() {
+        let (activity, login) = make_activity();
+        assert!(activity.touch_jwt(10, login + Duration::days(1), login + Duration::minutes(29)));
+        let idle = login + Duration::minutes(29) + Duration::minutes(31);
+        assert!(!activity.touch_jwt(10, login + Duration::days(1), idle));
+        // The refresh token can't be used to get a new JWT either, and it stays rejected.
+        assert!(!activity.touch_refresh_token(1, idle));
+        assert!(!activity.touch_jwt(10, login + Duration::days(1), idle + Duration::minutes(1)));
+    }
+
+    #[test]
+    fn test_session_kept_alive_by_activity() {
+        let (activity, login) = make_activity();
+        let jwt_expiry = login + Duration::days(1);
+        for i in 1..=10 {
+            assert!(activity.touch_jwt(10, jwt_expiry, login + Duration::minutes(20 * i)));
+        }
+        // Refreshing the JWT keeps the session alive too, for all its JWTs.
+        let refresh = login + Duration::minutes(220);
+        assert!(activity.touch_refresh_token(1, refresh));
+        activity.add_jwt(1, 11, refresh);
+        let now = refresh + Duration::minutes(20);
+        assert!(activity.touch_jwt(10, jwt_expiry, now));
+        assert!(activity.touch_jwt(11, now + Duration::days(1), now));
+        // The other sessions are not affected.
+        assert!(activity.touch_jwt(20, now + Duration::days(1), now));
+    }
+
+    #[test]
+    fn test_session_idle_past_the_jwt_lifetime() {
+        let (activity, login) = make_activity();
+        // Another login after the first JWT expired purges the expired sessions.
+        let later = login + Duration::days(1) + Duration::hours(1);
+        activity.start_session(2, 20, later + Duration::days(30), later);
+        // The idle session is still known, and rejected.
+        assert!(!activity.touch_refresh_token(1, later));
+        assert!(activity.touch_refresh_token(2, later + Duration::minutes(1)));
+    }
+
+    #[test]
+    fn test_unknown_refresh_token_rejected() {
+        let (activity, login) = make_activity();
+        assert!(!activity.touch_refresh_token(2, login));
+        // The JWT issued for it is not recorded.
+        activity.add_jwt(2, 20, login);
+        assert!(activity.touch_refresh_token(1, login + Duration::minutes(1)));
+        assert!(!activity.touch_refresh_token(2, login + Duration::minutes(1)));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let activity = SessionActivity::new(None);
+        let now = Utc::now();
+        activity.start_session(1, 10, now + Duration::days(30), now);
+        assert!(activity.touch_jwt(10, now + Duration::days(1), now + Duration::days(1)));
+        assert!(activity.touch_refresh_token(1, now + Duration::days(30)));
+    }
+}
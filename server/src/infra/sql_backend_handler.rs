@@ -1,16 +1,21 @@
-use super::tcp_backend_handler::TcpBackendHandler;
+use super::tcp_backend_handler::{JwtSecret, PasswordResetRequest, TcpBackendHandler};
 use crate::domain::{
     error::*,
-    model::{self, JwtRefreshStorageColumn, JwtStorageColumn, PasswordResetTokensColumn},
+    model::{
+        self, JwtRefreshStorageColumn, JwtSecretsColumn, JwtStorageColumn,
+        PasswordResetRequestsColumn, PasswordResetTokensColumn, UserColumn,
+    },
     sql_backend_handler::SqlBackendHandler,
     types::UserId,
 };
 use async_trait::async_trait;
 use sea_orm::{
     sea_query::{Cond, Expr},
-    ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, QuerySelect,
+    ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    QuerySelect, TransactionTrait,
 };
-use std::collections::HashSet;
+use secstr::SecUtf8;
+use std::collections::{HashMap, HashSet};
 use tracing::{debug, instrument};
 
 fn gen_random_string(len: usize) -> String {
@@ -107,6 +112,47 @@ impl TcpBackendHandler for SqlBackendHandler {
         Ok(())
     }
 
+    #[instrument(skip_all, level = "debug")]
+    async fn get_tokens_valid_after(
+        &self,
+    ) -> anyhow::Result<HashMap<UserId, chrono::NaiveDateTime>> {
+        Ok(model::User::find()
+            .select_only()
+            .column(UserColumn::UserId)
+            .column(UserColumn::TokensValidAfter)
+            .filter(UserColumn::TokensValidAfter.is_not_null())
+            .into_tuple::<(UserId, chrono::NaiveDateTime)>()
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn invalidate_user_sessions(
+        &self,
+        user: &UserId,
+        valid_after: chrono::NaiveDateTime,
+    ) -> Result<()> {
+        debug!(?user);
+        let result = model::User::update_many()
+            .col_expr(UserColumn::TokensValidAfter, Expr::value(valid_after))
+            .filter(UserColumn::UserId.eq(user))
+            .exec(&self.sql_pool)
+            .await?;
+        if result.rows_affected == 0 {
+            return Err(DomainError::EntityNotFound(format!(
+                "No such user: '{}'",
+                user
+            )));
+        }
+        model::JwtRefreshStorage::delete_many()
+            .filter(JwtRefreshStorageColumn::UserId.eq(user))
+            .exec(&self.sql_pool)
+            .await?;
+        Ok(())
+    }
+
     #[instrument(skip_all, level = "debug")]
     async fn start_password_reset(&self, user: &UserId) -> Result<Option<String>> {
         debug!(?user);
@@ -155,4 +201,111 @@ impl TcpBackendHandler for SqlBackendHandler {
         }
         Ok(())
     }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn get_password_reset_requests(
+        &self,
+        since: chrono::NaiveDateTime,
+    ) -> Result<Vec<PasswordResetRequest>> {
+        Ok(model::PasswordResetRequests::find()
+            .filter(PasswordResetRequestsColumn::RequestDate.gt(since))
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|r| PasswordResetRequest {
+                user_id: r.user_id,
+                address: r.address,
+                date: r.request_date,
+            })
+            .collect())
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn add_password_reset_request(&self, request: PasswordResetRequest) -> Result<()> {
+        debug!(user = ?request.user_id);
+        model::password_reset_requests::ActiveModel {
+            user_id: ActiveValue::Set(request.user_id),
+            address: ActiveValue::Set(request.address),
+            request_date: ActiveValue::Set(request.date),
+            ..Default::default()
+        }
+        .insert(&self.sql_pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn get_jwt_secrets(&self, now: chrono::NaiveDateTime) -> Result<Vec<JwtSecret>> {
+        Ok(model::JwtSecrets::find()
+            .filter(
+                Cond::any()
+                    .add(JwtSecretsColumn::ExpiryDate.is_null())
+                    .add(JwtSecretsColumn::ExpiryDate.gt(now)),
+            )
+            .all(&self.sql_pool)
+            .await?
+            .into_iter()
+            .map(|s| JwtSecret {
+                secret: SecUtf8::from(s.secret),
+                expiry_date: s.expiry_date,
+            })
+            .collect())
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    async fn rotate_jwt_secret(
+        &self,
+        previous_secret: &SecUtf8,
+        new_secret: &SecUtf8,
+        expiry_date: chrono::NaiveDateTime,
+    ) -> Result<()> {
+        debug!(?expiry_date);
+        let previous_secret = previous_secret.unsecure().to_owned();
+        let new_secret = new_secret.unsecure().to_owned();
+        let now = chrono::Utc::now().naive_utc();
+        self.sql_pool
+            .transaction::<_, (), DomainError>(|transaction| {
+                Box::pin(async move {
+                    if model::JwtSecrets::find_by_id(new_secret.clone())
+                        .one(transaction)
+                        .await?
+                        .is_some()
+                    {
+                        return Err(DomainError::Conflict(
+                            "The new JWT secret was already used".to_owned(),
+                        ));
+                    }
+                    model::JwtSecrets::update_many()
+                        .col_expr(JwtSecretsColumn::ExpiryDate, Expr::value(expiry_date))
+                        .filter(JwtSecretsColumn::ExpiryDate.is_null())
+                        .exec(transaction)
+                        .await?;
+                    if model::JwtSecrets::find_by_id(previous_secret.clone())
+                        .one(transaction)
+                        .await?
+                        .is_none()
+                    {
+                        model::jwt_secrets::Model {
+                            secret: previous_secret,
+                            expiry_date: Some(expiry_date),
+                            creation_date: now,
+                        }
+                        .into_active_model()
+                        .insert(transaction)
+                        .await?;
+                    }
+                    model::jwt_secrets::Model {
+                        secret: new_secret,
+                        expiry_date: None,
+                        creation_date: now,
+                    }
+                    .into_active_model()
+                    .insert(transaction)
+                    .await?;
+                    Ok(())
+                })
+            })
+            .await?;
+        Ok(())
+    }
 }
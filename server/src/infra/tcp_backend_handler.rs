@@ -1,8 +1,26 @@
 use async_trait::async_trait;
-use std::collections::HashSet;
+use secstr::SecUtf8;
+use std::collections::{HashMap, HashSet};
 
 use crate::domain::{error::Result, types::UserId};
 
+/// A password reset email sent to a user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PasswordResetRequest {
+    pub user_id: UserId,
+    /// The lowercase email address.
+    pub address: String,
+    pub date: chrono::NaiveDateTime,
+}
+
+/// A JWT secret set at runtime.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JwtSecret {
+    pub secret: SecUtf8,
+    /// `None` for the signing secret, the end of the grace period for the previous ones.
+    pub expiry_date: Option<chrono::NaiveDateTime>,
+}
+
 #[async_trait]
 pub trait TcpBackendHandler: Sync {
     async fn get_jwt_blacklist(&self) -> anyhow::Result<HashSet<u64>>;
@@ -11,6 +29,19 @@ pub trait TcpBackendHandler: Sync {
     async fn blacklist_jwts(&self, user: &UserId) -> Result<HashSet<u64>>;
     async fn delete_refresh_token(&self, refresh_token_hash: u64) -> Result<()>;
 
+    /// Get the time before which each user's tokens are invalid, for the users that have one.
+    async fn get_tokens_valid_after(
+        &self,
+    ) -> anyhow::Result<HashMap<UserId, chrono::NaiveDateTime>>;
+
+    /// Reject all the tokens of the user issued before `valid_after`, and delete their refresh
+    /// tokens.
+    async fn invalidate_user_sessions(
+        &self,
+        user: &UserId,
+        valid_after: chrono::NaiveDateTime,
+    ) -> Result<()>;
+
     /// Request a token to reset a user's password.
     /// If the user doesn't exist, returns `Ok(None)`, otherwise `Ok(Some(token))`.
     async fn start_password_reset(&self, user: &UserId) -> Result<Option<String>>;
@@ -19,4 +50,26 @@ pub trait TcpBackendHandler: Sync {
     async fn get_user_id_for_password_reset_token(&self, token: &str) -> Result<UserId>;
 
     async fn delete_password_reset_token(&self, token: &str) -> Result<()>;
+
+    /// Get the password reset emails sent since the given time, by any instance.
+    async fn get_password_reset_requests(
+        &self,
+        since: chrono::NaiveDateTime,
+    ) -> Result<Vec<PasswordResetRequest>>;
+
+    /// Record a password reset email sent to the user.
+    async fn add_password_reset_request(&self, request: PasswordResetRequest) -> Result<()>;
+
+    /// Get the JWT secrets set at runtime that haven't expired yet, by any instance.
+    async fn get_jwt_secrets(&self, now: chrono::NaiveDateTime) -> Result<Vec<JwtSecret>>;
+
+    /// Make `new_secret` the signing secret. The previous ones, including `previous_secret` if it
+    /// wasn't stored (e.g. it came from the configuration), are only used to verify the tokens
+    /// until `expiry_date`.
+    async fn rotate_jwt_secret(
+        &self,
+        previous_secret: &SecUtf8,
+        new_secret: &SecUtf8,
+        expiry_date: chrono::NaiveDateTime,
+    ) -> Result<()>;
 }
@@ -1,34 +1,44 @@
 use crate::{
     domain::{
-        error::DomainError,
-        handler::{BackendHandler, LoginHandler},
+        error::{DomainError, Result as DomainResult},
+        handler::{BackendHandler, CompactionReport, LoginHandler, MaintenanceHandler},
         opaque_handler::OpaqueHandler,
+        types::UserId,
     },
     infra::{
         access_control::{AccessControlledBackendHandler, ReadonlyBackendHandler},
-        auth_service,
-        configuration::{Configuration, MailOptions},
+        auth_service, build_info,
+        configuration::{Configuration, JwtOptions, MailOptions, PasswordResetOptions},
+        diagnostics::DiagnosticsOptions,
+        first_run::FirstRunGate,
+        graphql::{
+            api::{Impersonator, JwtSecretRotator, PasswordSetter, SessionInvalidator},
+            query_limits::QueryLimits,
+        },
+        index_page::{index_handler, IndexPage},
+        jwt_keys::JwtKeys,
+        ldap_connections::LdapConnectionRegistry,
         logging::CustomRootSpanBuilder,
+        metrics::{metrics_handler, GaugesCache},
+        password_reset_protection::UsedChallenges,
+        reset_notification::is_reset_enabled,
+        session_activity::SessionActivity,
         tcp_backend_handler::*,
     },
 };
-use actix_files::{Files, NamedFile};
+use actix_files::Files;
 use actix_http::{header, HttpServiceBuilder};
 use actix_server::ServerBuilder;
 use actix_service::map_config;
 use actix_web::{dev::AppConfig, guard, web, App, HttpResponse, Responder};
 use anyhow::{Context, Result};
-use hmac::Hmac;
-use sha2::Sha512;
-use std::collections::HashSet;
-use std::path::PathBuf;
-use std::sync::RwLock;
-use tracing::info;
-
-async fn index() -> actix_web::Result<NamedFile> {
-    let path = PathBuf::from(r"app/index.html");
-    Ok(NamedFile::open(path)?)
-}
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use lldap_auth::login;
+use secstr::SecUtf8;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
 
 #[derive(thiserror::Error, Debug)]
 pub enum TcpError {
@@ -58,7 +68,10 @@ pub(crate) fn error_to_http_response(error: TcpError) -> HttpResponse {
             | DomainError::UnknownCryptoError(_) => HttpResponse::InternalServerError(),
             DomainError::Base64DecodeError(_)
             | DomainError::BinarySerializationError(_)
-            | DomainError::EntityNotFound(_) => HttpResponse::BadRequest(),
+            | DomainError::EntityNotFound(_)
+            | DomainError::LimitExceeded(_)
+            | DomainError::Conflict(_)
+            | DomainError::InvalidInput(_) => HttpResponse::BadRequest(),
         },
         TcpError::BadRequest(_) => HttpResponse::BadRequest(),
         TcpError::NotFoundError(_) => HttpResponse::NotFound(),
@@ -82,28 +95,71 @@ async fn wasm_handler_compressed() -> actix_web::Result<impl Responder> {
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn http_config<Backend>(
     cfg: &mut web::ServiceConfig,
-    backend_handler: Backend,
-    jwt_secret: secstr::SecUtf8,
-    jwt_blacklist: HashSet<u64>,
+    backend_handler: AccessControlledBackendHandler<Backend>,
+    jwt_keys: JwtKeys,
+    jwt_options: JwtOptions,
+    jwt_blacklist: JwtBlacklist,
+    tokens_valid_after: TokensValidAfter,
+    session_activity: SessionActivity,
+    used_reset_challenges: UsedChallenges,
     server_url: url::Url,
     mail_options: MailOptions,
+    password_reset_options: PasswordResetOptions,
+    default_locale: String,
+    ldap_connections: LdapConnectionRegistry,
+    diagnostics_options: Arc<DiagnosticsOptions>,
+    opaque_configuration: login::ServerOpaqueConfiguration,
+    server_key_fingerprint: login::ServerKeyFingerprint,
+    first_run: FirstRunGate,
+    enable_impersonation: bool,
+    max_body_size: usize,
+    query_limits: QueryLimits,
+    index_page: IndexPage,
+    gauges_cache: Option<web::Data<GaugesCache>>,
 ) where
-    Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + Clone + 'static,
+    Backend: TcpBackendHandler
+        + BackendHandler
+        + LoginHandler
+        + OpaqueHandler
+        + MaintenanceHandler
+        + Clone
+        + 'static,
 {
-    let enable_password_reset = mail_options.enable_password_reset;
+    let enable_password_reset = is_reset_enabled(&mail_options, &password_reset_options);
     cfg.app_data(web::Data::new(AppState::<Backend> {
-        backend_handler: AccessControlledBackendHandler::new(backend_handler),
-        jwt_key: hmac::Mac::new_from_slice(jwt_secret.unsecure().as_bytes()).unwrap(),
-        jwt_blacklist: RwLock::new(jwt_blacklist),
+        backend_handler,
+        jwt_keys,
+        jwt_options,
+        jwt_blacklist,
+        tokens_valid_after,
+        session_activity,
+        used_reset_challenges,
         server_url,
         mail_options,
+        password_reset_options,
+        default_locale,
+        ldap_connections,
+        diagnostics_options,
+        opaque_configuration,
+        server_key_fingerprint,
+        first_run,
+        enable_impersonation,
     }))
+    .app_data(web::Data::new(index_page))
     .route(
         "/health",
         web::get().to(|| async { HttpResponse::Ok().finish() }),
     )
+    .route("/version", web::get().to(build_info::version_handler))
+    .configure(move |cfg| {
+        if let Some(gauges_cache) = gauges_cache {
+            cfg.app_data(gauges_cache)
+                .route("/metrics", web::get().to(metrics_handler::<Backend>));
+        }
+    })
     .service(
         web::scope("/auth")
             .configure(|cfg| auth_service::configure_server::<Backend>(cfg, enable_password_reset)),
@@ -112,7 +168,9 @@ fn http_config<Backend>(
     .service(
         web::scope("/api")
             .wrap(auth_service::CookieToHeaderTranslatorFactory)
-            .configure(super::graphql::api::configure_endpoint::<Backend>),
+            .configure(|cfg| {
+                super::graphql::api::configure_endpoint::<Backend>(cfg, max_body_size, query_limits)
+            }),
     )
     .service(
         web::resource("/pkg/lldap_app_bg.wasm.gz").route(web::route().to(wasm_handler_compressed)),
@@ -125,15 +183,35 @@ fn http_config<Backend>(
     // Serve static fonts
     .service(Files::new("/static/fonts", "./app/static/fonts"))
     // Default to serve index.html for unknown routes, to support routing.
-    .default_service(web::route().guard(guard::Get()).to(index));
+    .default_service(web::route().guard(guard::Get()).to(index_handler));
 }
 
+/// Time before which the tokens of a user are rejected, shared by all the workers.
+pub(crate) type TokensValidAfter = Arc<RwLock<HashMap<UserId, DateTime<Utc>>>>;
+/// Hashes of the logged out tokens, shared by all the workers.
+pub(crate) type JwtBlacklist = Arc<RwLock<HashSet<u64>>>;
+
 pub(crate) struct AppState<Backend> {
     pub backend_handler: AccessControlledBackendHandler<Backend>,
-    pub jwt_key: Hmac<Sha512>,
-    pub jwt_blacklist: RwLock<HashSet<u64>>,
+    pub jwt_keys: JwtKeys,
+    /// The subject and the optional claims of the JWTs.
+    pub jwt_options: JwtOptions,
+    pub jwt_blacklist: JwtBlacklist,
+    pub tokens_valid_after: TokensValidAfter,
+    /// The last request of each web session, for the idle timeout.
+    pub session_activity: SessionActivity,
+    pub used_reset_challenges: UsedChallenges,
     pub server_url: url::Url,
     pub mail_options: MailOptions,
+    pub password_reset_options: PasswordResetOptions,
+    /// The locale of the emails of the users without one.
+    pub default_locale: String,
+    pub ldap_connections: LdapConnectionRegistry,
+    pub diagnostics_options: Arc<DiagnosticsOptions>,
+    pub opaque_configuration: login::ServerOpaqueConfiguration,
+    pub server_key_fingerprint: login::ServerKeyFingerprint,
+    pub first_run: FirstRunGate,
+    pub enable_impersonation: bool,
 }
 
 impl<Backend: BackendHandler> AppState<Backend> {
@@ -146,32 +224,205 @@ impl<Backend: TcpBackendHandler> AppState<Backend> {
         self.backend_handler.unsafe_get_handler()
     }
 }
+#[async_trait]
+impl<Backend: TcpBackendHandler + Send + Sync> SessionInvalidator for AppState<Backend> {
+    async fn invalidate_user_sessions(&self, user_id: &UserId) -> DomainResult<()> {
+        let now = Utc::now();
+        self.get_tcp_handler()
+            .invalidate_user_sessions(user_id, now.naive_utc())
+            .await?;
+        self.tokens_valid_after
+            .write()
+            .unwrap()
+            .insert(user_id.clone(), now);
+        Ok(())
+    }
+}
+#[async_trait]
+impl<Backend: TcpBackendHandler + Send + Sync> JwtSecretRotator for AppState<Backend> {
+    async fn rotate_jwt_secret(
+        &self,
+        new_secret: Option<SecUtf8>,
+        grace_period: chrono::Duration,
+    ) -> DomainResult<DateTime<Utc>> {
+        self.jwt_keys
+            .rotate(self.get_tcp_handler(), new_secret, grace_period)
+            .await
+    }
+}
+#[async_trait]
+impl<Backend: BackendHandler> Impersonator for AppState<Backend> {
+    async fn impersonate(
+        &self,
+        admin: &UserId,
+        user_id: &UserId,
+    ) -> DomainResult<(String, DateTime<Utc>)> {
+        let token = auth_service::create_impersonation_jwt(self, admin, user_id).await?;
+        Ok((token.as_str().to_owned(), token.claims().exp))
+    }
+}
 impl<Backend: OpaqueHandler> AppState<Backend> {
     pub fn get_opaque_handler(&self) -> &impl OpaqueHandler {
         self.backend_handler.unsafe_get_handler()
     }
 }
+#[async_trait]
+impl<Backend: OpaqueHandler> PasswordSetter for AppState<Backend> {
+    async fn set_user_password(
+        &self,
+        user_id: &UserId,
+        password: Option<&SecUtf8>,
+    ) -> DomainResult<()> {
+        self.get_opaque_handler()
+            .set_user_password(user_id, password)
+            .await
+    }
+}
+impl<Backend: MaintenanceHandler> AppState<Backend> {
+    pub fn get_maintenance_handler(&self) -> &impl MaintenanceHandler {
+        self.backend_handler.unsafe_get_handler()
+    }
+}
+#[async_trait]
+impl<Backend: MaintenanceHandler> MaintenanceHandler for AppState<Backend> {
+    async fn compact_database(&self) -> DomainResult<CompactionReport> {
+        self.get_maintenance_handler().compact_database().await
+    }
+}
 impl<Backend: LoginHandler> AppState<Backend> {
     pub fn get_login_handler(&self) -> &impl LoginHandler {
         self.backend_handler.unsafe_get_handler()
     }
 }
 
+/// Loads the logged out tokens and the session invalidations from the database, including the ones
+/// of the other instances. The ones known locally are kept, in case they raced with the query. The
+/// logged out tokens that expired were deleted from the database by the cleanup job, and are
+/// forgotten.
+async fn refresh_revocations<Backend: TcpBackendHandler>(
+    backend_handler: &Backend,
+    jwt_blacklist: &JwtBlacklist,
+    tokens_valid_after: &TokensValidAfter,
+) -> Result<()> {
+    let known_before = jwt_blacklist.read().unwrap().clone();
+    let blacklisted = backend_handler
+        .get_jwt_blacklist()
+        .await
+        .context("while getting the jwt blacklist")?;
+    let valid_after = backend_handler
+        .get_tokens_valid_after()
+        .await
+        .context("while getting the token invalidation times")?;
+    {
+        let mut jwt_blacklist = jwt_blacklist.write().unwrap();
+        jwt_blacklist.retain(|jwt| blacklisted.contains(jwt) || !known_before.contains(jwt));
+        jwt_blacklist.extend(blacklisted);
+    }
+    let mut tokens_valid_after = tokens_valid_after.write().unwrap();
+    for (user, time) in valid_after {
+        let time = Utc.from_utc_datetime(&time);
+        let entry = tokens_valid_after.entry(user).or_insert(time);
+        *entry = std::cmp::max(*entry, time);
+    }
+    Ok(())
+}
+
+/// Also reloads the JWT secrets, to pick up the rotations of the other instances.
+async fn refresh_revocations_periodically<Backend: TcpBackendHandler>(
+    backend_handler: Backend,
+    jwt_blacklist: JwtBlacklist,
+    tokens_valid_after: TokensValidAfter,
+    jwt_keys: JwtKeys,
+    interval: std::time::Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) =
+            refresh_revocations(&backend_handler, &jwt_blacklist, &tokens_valid_after).await
+        {
+            warn!("Could not refresh the revoked tokens: {:#}", e);
+        }
+        if let Err(e) = jwt_keys.reload(&backend_handler).await {
+            warn!("Could not reload the JWT secrets: {:#}", e);
+        }
+    }
+}
+
 pub async fn build_tcp_server<Backend>(
     config: &Configuration,
     backend_handler: Backend,
+    ldap_connections: LdapConnectionRegistry,
+    first_run: FirstRunGate,
     server_builder: ServerBuilder,
 ) -> Result<ServerBuilder>
 where
-    Backend: TcpBackendHandler + BackendHandler + LoginHandler + OpaqueHandler + Clone + 'static,
+    Backend: TcpBackendHandler
+        + BackendHandler
+        + LoginHandler
+        + OpaqueHandler
+        + MaintenanceHandler
+        + Clone
+        + 'static,
 {
-    let jwt_secret = config.jwt_secret.clone();
-    let jwt_blacklist = backend_handler
-        .get_jwt_blacklist()
+    let jwt_keys = JwtKeys::new(
+        &config.jwt_secret,
+        &config.jwt_additional_verification_secrets,
+    );
+    jwt_keys
+        .reload(&backend_handler)
         .await
-        .context("while getting the jwt blacklist")?;
+        .context("while loading the JWT secrets")?;
+    let jwt_blacklist = JwtBlacklist::default();
+    let tokens_valid_after = TokensValidAfter::default();
+    refresh_revocations(&backend_handler, &jwt_blacklist, &tokens_valid_after).await?;
+    if config.token_revocation_refresh_seconds > 0 {
+        actix_rt::spawn(refresh_revocations_periodically(
+            backend_handler.clone(),
+            jwt_blacklist.clone(),
+            tokens_valid_after.clone(),
+            jwt_keys.clone(),
+            std::time::Duration::from_secs(config.token_revocation_refresh_seconds),
+        ));
+    }
+    if config.web_session_idle_timeout_minutes > 0 {
+        warn!(
+            "The activity of the sessions for web_session_idle_timeout_minutes is only kept in \
+            memory: it doesn't work with several LLDAP instances sharing the database"
+        );
+    }
+    let session_activity = SessionActivity::new(
+        Some(config.web_session_idle_timeout_minutes)
+            .filter(|minutes| *minutes > 0)
+            .map(|minutes| chrono::Duration::minutes(minutes as i64)),
+    );
+    let used_reset_challenges = UsedChallenges::default();
+    let backend_handler = AccessControlledBackendHandler::new(backend_handler)
+        .with_admin_groups(config.admin_groups.clone());
+    let jwt_options = config.jwt_options.clone();
     let server_url = config.http_url.clone();
     let mail_options = config.smtp_options.clone();
+    let password_reset_options = config.password_reset_options.clone();
+    let default_locale = config.default_locale.clone();
+    let diagnostics_options = Arc::new(DiagnosticsOptions::from(config));
+    let opaque_configuration = auth_service::make_opaque_configuration(config.get_server_setup());
+    let server_key_fingerprint =
+        auth_service::make_server_key_fingerprint(config.get_server_setup());
+    let max_body_size = config.http_max_body_size;
+    let query_limits = QueryLimits {
+        max_depth: config.graphql_max_depth,
+        max_complexity: config.graphql_max_complexity,
+    };
+    let index_page = IndexPage {
+        path: r"app/index.html".into(),
+        login_banner: config
+            .login_banner
+            .clone()
+            .filter(|banner| !banner.is_empty()),
+    };
+    let gauges_cache = config
+        .enable_metrics
+        .then(|| web::Data::new(GaugesCache::default()));
+    let enable_impersonation = config.enable_impersonation;
     let verbose = config.verbose;
     info!("Starting the API/web server on port {}", config.http_port);
     server_builder
@@ -180,10 +431,23 @@ where
             (config.http_host.clone(), config.http_port),
             move || {
                 let backend_handler = backend_handler.clone();
-                let jwt_secret = jwt_secret.clone();
+                let jwt_keys = jwt_keys.clone();
+                let jwt_options = jwt_options.clone();
                 let jwt_blacklist = jwt_blacklist.clone();
+                let tokens_valid_after = tokens_valid_after.clone();
+                let session_activity = session_activity.clone();
+                let used_reset_challenges = used_reset_challenges.clone();
                 let server_url = server_url.clone();
                 let mail_options = mail_options.clone();
+                let password_reset_options = password_reset_options.clone();
+                let default_locale = default_locale.clone();
+                let ldap_connections = ldap_connections.clone();
+                let diagnostics_options = diagnostics_options.clone();
+                let opaque_configuration = opaque_configuration.clone();
+                let server_key_fingerprint = server_key_fingerprint.clone();
+                let first_run = first_run.clone();
+                let index_page = index_page.clone();
+                let gauges_cache = gauges_cache.clone();
                 HttpServiceBuilder::default()
                     .finish(map_config(
                         App::new()
@@ -195,10 +459,26 @@ where
                                 http_config(
                                     cfg,
                                     backend_handler,
-                                    jwt_secret,
+                                    jwt_keys,
+                                    jwt_options,
                                     jwt_blacklist,
+                                    tokens_valid_after,
+                                    session_activity,
+                                    used_reset_challenges,
                                     server_url,
                                     mail_options,
+                                    password_reset_options,
+                                    default_locale,
+                                    ldap_connections,
+                                    diagnostics_options,
+                                    opaque_configuration,
+                                    server_key_fingerprint,
+                                    first_run,
+                                    enable_impersonation,
+                                    max_body_size,
+                                    query_limits,
+                                    index_page,
+                                    gauges_cache,
                                 )
                             }),
                         |_| AppConfig::default(),
@@ -213,3 +493,27 @@ where
             )
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::test_utils::MockTestBackendHandler;
+
+    #[tokio::test]
+    async fn test_refresh_revocations_forgets_expired_tokens() {
+        let mut mock = MockTestBackendHandler::new();
+        mock.expect_get_jwt_blacklist()
+            .times(1)
+            .return_once(|| Ok(HashSet::from([2, 3])));
+        mock.expect_get_tokens_valid_after()
+            .times(1)
+            .return_once(|| Ok(HashMap::new()));
+        // 1 expired and was deleted from the database, 2 is still blacklisted.
+        let jwt_blacklist = JwtBlacklist::new(RwLock::new(HashSet::from([1, 2])));
+        let tokens_valid_after = TokensValidAfter::default();
+        refresh_revocations(&mock, &jwt_blacklist, &tokens_valid_after)
+            .await
+            .unwrap();
+        assert_eq!(*jwt_blacklist.read().unwrap(), HashSet::from([2, 3]));
+    }
+}
@@ -1,7 +1,11 @@
-use crate::domain::{error::Result, handler::*, opaque_handler::*, types::*};
+use crate::{
+    domain::{error::Result, handler::*, opaque_handler::*, types::*},
+    infra::tcp_backend_handler::{JwtSecret, PasswordResetRequest, TcpBackendHandler},
+};
 
 use async_trait::async_trait;
-use std::collections::HashSet;
+use secstr::SecUtf8;
+use std::collections::{HashMap, HashSet};
 
 mockall::mock! {
     pub TestBackendHandler{}
@@ -15,6 +19,7 @@ mockall::mock! {
     #[async_trait]
     impl GroupListerBackendHandler for TestBackendHandler {
         async fn list_groups(&self, filters: Option<GroupRequestFilter>) -> Result<Vec<Group>>;
+        async fn count_groups(&self) -> Result<u64>;
     }
     #[async_trait]
     impl GroupBackendHandler for TestBackendHandler {
@@ -22,10 +27,20 @@ mockall::mock! {
         async fn update_group(&self, request: UpdateGroupRequest) -> Result<()>;
         async fn create_group(&self, group_name: &str) -> Result<GroupId>;
         async fn delete_group(&self, group_id: GroupId) -> Result<()>;
+        async fn get_group_managers(&self, group_id: GroupId) -> Result<Vec<UserId>>;
+        async fn add_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()>;
+        async fn remove_group_manager(&self, group_id: GroupId, user_id: &UserId) -> Result<()>;
+        async fn get_group_manager_groups(&self, group_id: GroupId) -> Result<Vec<GroupId>>;
+        async fn add_group_manager_group(&self, group_id: GroupId, manager_group_id: GroupId) -> Result<()>;
+        async fn remove_group_manager_group(&self, group_id: GroupId, manager_group_id: GroupId) -> Result<()>;
+        async fn add_group_attribute(&self, request: CreateAttributeRequest) -> Result<()>;
+        async fn update_group_attribute(&self, request: UpdateAttributeRequest) -> Result<()>;
+        async fn delete_group_attribute(&self, name: &str) -> Result<()>;
     }
     #[async_trait]
     impl UserListerBackendHandler for TestBackendHandler {
         async fn list_users(&self, filters: Option<UserRequestFilter>, get_groups: bool) -> Result<Vec<UserAndGroups>>;
+        async fn count_users(&self) -> Result<u64>;
     }
     #[async_trait]
     impl UserBackendHandler for TestBackendHandler {
@@ -33,16 +48,25 @@ mockall::mock! {
         async fn create_user(&self, request: CreateUserRequest) -> Result<()>;
         async fn update_user(&self, request: UpdateUserRequest) -> Result<()>;
         async fn delete_user(&self, user_id: &UserId) -> Result<()>;
+        async fn rename_user(&self, user_id: &UserId, new_user_id: UserId) -> Result<()>;
         async fn get_user_groups(&self, user_id: &UserId) -> Result<HashSet<GroupDetails>>;
         async fn add_user_to_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
         async fn remove_user_from_group(&self, user_id: &UserId, group_id: GroupId) -> Result<()>;
+        async fn add_users_to_group(&self, user_ids: &[UserId], group_id: GroupId) -> Result<Vec<UserId>>;
+        async fn remove_users_from_group(&self, user_ids: &[UserId], group_id: GroupId) -> Result<Vec<UserId>>;
+        async fn update_user_attribute(&self, request: UpdateAttributeRequest) -> Result<()>;
+        async fn set_primary_group(&self, user_id: &UserId, group_id: Option<GroupId>) -> Result<()>;
+        async fn set_user_expiry_date(&self, user_id: &UserId, expiry_date: Option<chrono::NaiveDateTime>) -> Result<()>;
     }
     #[async_trait]
     impl SchemaBackendHandler for TestBackendHandler {
         async fn get_schema(&self) -> Result<Schema>;
     }
     #[async_trait]
-    impl BackendHandler for TestBackendHandler {}
+    impl BackendHandler for TestBackendHandler {
+        async fn begin_transaction(&self) -> Result<Self>;
+        async fn commit_transaction(self) -> Result<()>;
+    }
     #[async_trait]
     impl OpaqueHandler for TestBackendHandler {
         async fn login_start(
@@ -58,6 +82,36 @@ mockall::mock! {
             &self,
             request: registration::ClientRegistrationFinishRequest
         ) -> Result<()>;
+        async fn clear_password(&self, user_id: &UserId) -> Result<()>;
+    }
+    #[async_trait]
+    impl TcpBackendHandler for TestBackendHandler {
+        async fn get_jwt_blacklist(&self) -> anyhow::Result<HashSet<u64>>;
+        async fn create_refresh_token(&self, user: &UserId) -> Result<(String, chrono::Duration)>;
+        async fn check_token(&self, refresh_token_hash: u64, user: &UserId) -> Result<bool>;
+        async fn blacklist_jwts(&self, user: &UserId) -> Result<HashSet<u64>>;
+        async fn delete_refresh_token(&self, refresh_token_hash: u64) -> Result<()>;
+        async fn get_tokens_valid_after(&self) -> anyhow::Result<HashMap<UserId, chrono::NaiveDateTime>>;
+        async fn invalidate_user_sessions(
+            &self,
+            user: &UserId,
+            valid_after: chrono::NaiveDateTime,
+        ) -> Result<()>;
+        async fn start_password_reset(&self, user: &UserId) -> Result<Option<String>>;
+        async fn get_user_id_for_password_reset_token(&self, token: &str) -> Result<UserId>;
+        async fn delete_password_reset_token(&self, token: &str) -> Result<()>;
+        async fn get_password_reset_requests(
+            &self,
+            since: chrono::NaiveDateTime,
+        ) -> Result<Vec<PasswordResetRequest>>;
+        async fn add_password_reset_request(&self, request: PasswordResetRequest) -> Result<()>;
+        async fn get_jwt_secrets(&self, now: chrono::NaiveDateTime) -> Result<Vec<JwtSecret>>;
+        async fn rotate_jwt_secret(
+            &self,
+            previous_secret: &SecUtf8,
+            new_secret: &SecUtf8,
+            expiry_date: chrono::NaiveDateTime,
+        ) -> Result<()>;
     }
 }
 
@@ -72,6 +126,7 @@ pub fn setup_default_schema(mock: &mut MockTestBackendHandler) {
                         is_list: false,
                         is_visible: true,
                         is_editable: true,
+                        is_ldap_visible: true,
                         is_hardcoded: true,
                     },
                     AttributeSchema {
@@ -80,6 +135,7 @@ pub fn setup_default_schema(mock: &mut MockTestBackendHandler) {
                         is_list: false,
                         is_visible: true,
                         is_editable: true,
+                        is_ldap_visible: true,
                         is_hardcoded: true,
                     },
                     AttributeSchema {
@@ -88,6 +144,7 @@ pub fn setup_default_schema(mock: &mut MockTestBackendHandler) {
                         is_list: false,
                         is_visible: true,
                         is_editable: true,
+                        is_ldap_visible: true,
                         is_hardcoded: true,
                     },
                 ],
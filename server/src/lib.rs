@@ -0,0 +1,13 @@
+#![forbid(unsafe_code)]
+#![forbid(non_ascii_idents)]
+// TODO: Remove next line once ubuntu upgrades rustc to >=1.67.1
+#![allow(clippy::uninlined_format_args)]
+
+//! The LLDAP server. See [`embedded`] to use its user and group management in other services.
+
+// Public for the `lldap` binary, but not a stable API: use [`embedded`] instead.
+#[doc(hidden)]
+pub mod domain;
+pub mod embedded;
+#[doc(hidden)]
+pub mod infra;
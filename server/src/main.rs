@@ -5,27 +5,26 @@
 
 use std::time::Duration;
 
-use crate::{
+use actix::Actor;
+use actix_server::ServerBuilder;
+use anyhow::{anyhow, Context, Result};
+use futures_util::TryFutureExt;
+use lldap::{
     domain::{
+        self,
         handler::{
-            CreateUserRequest, GroupBackendHandler, GroupListerBackendHandler, GroupRequestFilter,
-            UserBackendHandler,
+            CreateUserRequest, GroupListerBackendHandler, GroupRequestFilter, UserBackendHandler,
         },
         sql_backend_handler::SqlBackendHandler,
         sql_opaque_handler::register_password,
     },
-    infra::{cli::*, configuration::Configuration, db_cleaner::Scheduler, healthcheck, mail},
+    infra::{
+        self, build_info::BUILD_INFO, cli::*, configuration::Configuration, db_cleaner::Scheduler,
+        first_run::FirstRunGate, healthcheck, mail,
+    },
 };
-use actix::Actor;
-use actix_server::ServerBuilder;
-use anyhow::{anyhow, Context, Result};
-use futures_util::TryFutureExt;
-use sea_orm::Database;
 use tracing::*;
 
-mod domain;
-mod infra;
-
 async fn create_admin_user(handler: &SqlBackendHandler, config: &Configuration) -> Result<()> {
     let pass_length = config.ldap_user_pass.unsecure().len();
     assert!(
@@ -45,7 +44,7 @@ async fn create_admin_user(handler: &SqlBackendHandler, config: &Configuration)
         .context("Error creating admin user")?;
     let groups = handler
         .list_groups(Some(GroupRequestFilter::DisplayName(
-            "lldap_admin".to_owned(),
+            config.admin_groups[0].clone(),
         )))
         .await?;
     assert_eq!(groups.len(), 1);
@@ -55,24 +54,12 @@ async fn create_admin_user(handler: &SqlBackendHandler, config: &Configuration)
         .context("Error adding admin user to group")
 }
 
-async fn ensure_group_exists(handler: &SqlBackendHandler, group_name: &str) -> Result<()> {
-    if handler
-        .list_groups(Some(GroupRequestFilter::DisplayName(group_name.to_owned())))
-        .await?
-        .is_empty()
-    {
-        warn!("Could not find {} group, trying to create it", group_name);
-        handler
-            .create_group(group_name)
-            .await
-            .context(format!("while creating {} group", group_name))?;
-    }
-    Ok(())
-}
-
 #[instrument(skip_all)]
 async fn set_up_server(config: Configuration) -> Result<ServerBuilder> {
-    info!("Starting LLDAP version {}", env!("CARGO_PKG_VERSION"));
+    info!(
+        "Starting LLDAP version {} ({}, built on {})",
+        BUILD_INFO.version, BUILD_INFO.git_hash, BUILD_INFO.build_date
+    );
 
     let sql_pool = {
         let mut sql_opt = sea_orm::ConnectOptions::new(config.database_url.clone());
@@ -80,15 +67,24 @@ async fn set_up_server(config: Configuration) -> Result<ServerBuilder> {
             .max_connections(5)
             .sqlx_logging(true)
             .sqlx_logging_level(log::LevelFilter::Debug);
-        Database::connect(sql_opt).await?
+        connect_to_database(sql_opt, &config).await?
     };
     domain::sql_tables::init_table(&sql_pool)
         .await
         .context("while creating the tables")?;
     let backend_handler = SqlBackendHandler::new(config.clone(), sql_pool.clone());
-    ensure_group_exists(&backend_handler, "lldap_admin").await?;
-    ensure_group_exists(&backend_handler, "lldap_password_manager").await?;
-    ensure_group_exists(&backend_handler, "lldap_strict_readonly").await?;
+    backend_handler
+        .apply_email_uniqueness_policy()
+        .await
+        .context("while applying the email_uniqueness setting")?;
+    backend_handler
+        .apply_group_name_uniqueness_policy()
+        .await
+        .context("while applying the group_name_uniqueness setting")?;
+    backend_handler
+        .ensure_builtin_groups_exist()
+        .await
+        .context("while creating the built-in groups")?;
     if let Err(e) = backend_handler.get_user_details(&config.ldap_user_dn).await {
         warn!("Could not get admin user, trying to create it: {:#}", e);
         create_admin_user(&backend_handler, &config)
@@ -96,19 +92,50 @@ async fn set_up_server(config: Configuration) -> Result<ServerBuilder> {
             .map_err(|e| anyhow!("Error setting up admin login/account: {:#}", e))
             .context("while creating the admin user")?;
     }
+    let first_run = FirstRunGate::for_admin(
+        config.first_run_setup,
+        &backend_handler,
+        &config.ldap_user_dn,
+    )
+    .await;
+    if first_run.is_active() {
+        warn!("First run setup: change the admin password in the web UI to enable the server");
+    }
+    let ldap_connections = infra::ldap_connections::LdapConnectionRegistry::new(
+        config.ldap_max_connections,
+        config.ldap_max_connections_per_ip,
+    );
     let server_builder = infra::ldap_server::build_ldap_server(
         &config,
         backend_handler.clone(),
+        ldap_connections.clone(),
+        first_run.clone(),
         actix_server::Server::build(),
     )
     .context("while binding the LDAP server")?;
     infra::jwt_sql_tables::init_table(&sql_pool).await?;
-    let server_builder =
-        infra::tcp_server::build_tcp_server(&config, backend_handler, server_builder)
-            .await
-            .context("while binding the TCP server")?;
-    // Run every hour.
-    let scheduler = Scheduler::new("0 0 * * * * *", sql_pool);
+    if config.membership_sync_options.source.is_some() {
+        actix_rt::spawn(infra::membership_sync::sync_memberships_periodically(
+            backend_handler.clone(),
+            config.membership_sync_options.clone(),
+            config.admin_groups.clone(),
+        ));
+    }
+    let server_builder = if config.disable_http_ui {
+        info!("The HTTP server is disabled");
+        server_builder
+    } else {
+        infra::tcp_server::build_tcp_server(
+            &config,
+            backend_handler,
+            ldap_connections,
+            first_run,
+            server_builder,
+        )
+        .await
+        .context("while binding the TCP server")?
+    };
+    let scheduler = Scheduler::new(&config.database_cleanup_schedule, sql_pool);
     scheduler.start();
     Ok(server_builder)
 }
@@ -153,6 +180,32 @@ fn send_test_email_command(opts: TestEmailOpts) -> Result<()> {
     Ok(())
 }
 
+fn test_ldap_command(opts: TestLdapOpts) -> Result<()> {
+    let config = infra::configuration::init(opts.clone())?;
+    infra::logging::init(&config)?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let start = std::time::Instant::now();
+    let report = runtime
+        .block_on(tokio::time::timeout(
+            Duration::from_secs(30),
+            healthcheck::test_ldap(&config, &opts),
+        ))
+        .context("LDAP test timed out")?
+        .context("LDAP test failed")?;
+    info!(
+        "LDAP test successful in {:?} (bind: {:?}, search: {:?}, {} entries)",
+        start.elapsed(),
+        report.bind_duration,
+        report.search_duration,
+        report.entries.len()
+    );
+    Ok(())
+}
+
 fn run_healthcheck(opts: RunOpts) -> Result<()> {
     debug!("CLI: {:#?}", &opts);
     let config = infra::configuration::init(opts)?;
@@ -170,7 +223,7 @@ fn run_healthcheck(opts: RunOpts) -> Result<()> {
         tokio::join!(
             timeout(delay, healthcheck::check_ldap(config.ldap_port)),
             timeout(delay, healthcheck::check_ldaps(&config.ldaps_options)),
-            timeout(delay, healthcheck::check_api(config.http_port)),
+            timeout(delay, healthcheck::check_api(&config)),
         )
     });
 
@@ -189,15 +242,41 @@ fn run_healthcheck(opts: RunOpts) -> Result<()> {
     std::process::exit(i32::from(failure))
 }
 
-async fn create_schema(database_url: String) -> Result<()> {
+async fn connect_to_database(
+    options: sea_orm::ConnectOptions,
+    config: &Configuration,
+) -> Result<domain::sql_tables::DbConnection> {
+    domain::sql_tables::connect_with_retry(
+        options,
+        config.database_connection_attempts,
+        Duration::from_secs(config.database_connection_backoff_seconds),
+    )
+    .await
+    .context("while connecting to the database")
+}
+
+async fn create_schema(config: Configuration, dry_run: bool) -> Result<()> {
     let sql_pool = {
-        let mut sql_opt = sea_orm::ConnectOptions::new(database_url.clone());
+        let mut sql_opt = sea_orm::ConnectOptions::new(config.database_url.clone());
         sql_opt
             .max_connections(1)
             .sqlx_logging(true)
             .sqlx_logging_level(log::LevelFilter::Debug);
-        Database::connect(sql_opt).await?
+        connect_to_database(sql_opt, &config).await?
     };
+    if dry_run {
+        let plan = domain::sql_tables::get_init_plan(&sql_pool)
+            .await
+            .context("while reading the schema version")?;
+        if plan.is_empty() {
+            info!("Dry run: the schema is up to date");
+        }
+        for step in plan {
+            info!("Dry run: {}", step);
+        }
+        info!("Dry run: Create the JWT tables, if they don't exist");
+        return Ok(());
+    }
     domain::sql_tables::init_table(&sql_pool)
         .await
         .context("while creating base tables")?;
@@ -207,17 +286,105 @@ async fn create_schema(database_url: String) -> Result<()> {
     Ok(())
 }
 
-fn create_schema_command(opts: RunOpts) -> Result<()> {
+fn create_schema_command(opts: CreateSchemaOpts) -> Result<()> {
     debug!("CLI: {:#?}", &opts);
+    let dry_run = opts.dry_run;
     let config = infra::configuration::init(opts)?;
     infra::logging::init(&config)?;
-    let database_url = config.database_url;
 
     actix::run(
-        create_schema(database_url).unwrap_or_else(|e| error!("Could not create schema: {:#}", e)),
+        create_schema(config, dry_run)
+            .unwrap_or_else(|e| error!("Could not create schema: {:#}", e)),
     )?;
 
-    info!("Schema created successfully.");
+    if !dry_run {
+        info!("Schema created successfully.");
+    }
+    Ok(())
+}
+
+async fn check_database(config: Configuration, fix: bool) -> Result<bool> {
+    let sql_pool = {
+        let mut sql_opt = sea_orm::ConnectOptions::new(config.database_url.clone());
+        sql_opt
+            .max_connections(1)
+            .sqlx_logging(true)
+            .sqlx_logging_level(log::LevelFilter::Debug);
+        connect_to_database(sql_opt, &config).await?
+    };
+    let report = SqlBackendHandler::new(config, sql_pool)
+        .check_integrity(fix)
+        .await
+        .context("while checking the database")?;
+    for issue in &report.issues {
+        warn!("{}", issue);
+    }
+    if !report.users_without_password.is_empty() {
+        info!(
+            "Users without a password (they can't log in): {}",
+            report
+                .users_without_password
+                .iter()
+                .map(|u| u.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if report.is_healthy() {
+        info!("No issues found");
+    } else if report.fixed {
+        info!("Repaired {} issues", report.issues.len());
+    } else {
+        error!(
+            "Found {} issues, run with --fix to repair them",
+            report.issues.len()
+        );
+    }
+    Ok(report.is_healthy() || report.fixed)
+}
+
+fn doctor_command(opts: DoctorOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let fix = opts.fix;
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    let healthy = actix::run(check_database(config, fix))??;
+    std::process::exit(i32::from(!healthy))
+}
+
+fn generate_cert_command(opts: GenerateCertOpts) -> Result<()> {
+    debug!("CLI: {:#?}", &opts);
+    let mut subject_alt_names = opts.subject_alt_names.clone();
+    if subject_alt_names.is_empty() {
+        subject_alt_names.push(opts.common_name.clone());
+    }
+    let common_name = opts.common_name.clone();
+    let validity_days = opts.validity_days;
+    let dry_run = opts.dry_run;
+    let config = infra::configuration::init(opts)?;
+    infra::logging::init(&config)?;
+    if dry_run {
+        infra::ldap_server::check_certificate_paths(&config.ldaps_options)?;
+        info!(
+            "Dry run: would write a certificate for {} ({}), valid for {} days, to {} and its key to {}",
+            common_name,
+            subject_alt_names.join(", "),
+            validity_days,
+            config.ldaps_options.cert_file,
+            config.ldaps_options.key_file
+        );
+        return Ok(());
+    }
+    infra::ldap_server::generate_self_signed_certificate(
+        &config.ldaps_options,
+        &common_name,
+        subject_alt_names,
+        validity_days,
+    )?;
+    info!(
+        "Certificate written to {} and key written to {}",
+        config.ldaps_options.cert_file, config.ldaps_options.key_file
+    );
     Ok(())
 }
 
@@ -228,6 +395,9 @@ fn main() -> Result<()> {
         Command::Run(opts) => run_server_command(opts),
         Command::HealthCheck(opts) => run_healthcheck(opts),
         Command::SendTestEmail(opts) => send_test_email_command(opts),
+        Command::TestLdap(opts) => test_ldap_command(opts),
         Command::CreateSchema(opts) => create_schema_command(opts),
+        Command::Doctor(opts) => doctor_command(opts),
+        Command::GenerateCert(opts) => generate_cert_command(opts),
     }
 }
@@ -41,25 +41,76 @@ pub struct LLDAPFixture {
 
 const MAX_HEALTHCHECK_ATTEMPS: u8 = 10;
 
-impl LLDAPFixture {
-    pub fn new() -> Self {
-        let mut cmd = create_lldap_command();
-        cmd.arg("run");
-        cmd.arg("--verbose");
-        let child = cmd.spawn().expect("Unable to start server");
-        let mut started = false;
-        for _ in 0..MAX_HEALTHCHECK_ATTEMPS {
-            let status = create_lldap_command()
-                .arg("healthcheck")
-                .status()
-                .expect("healthcheck fail");
-            if status.success() {
-                started = true;
+/// Starts the server with the extra environment variables, and waits for it to be healthy.
+pub fn start_lldap(envs: &[(&str, &str)]) -> ChildProcess {
+    let mut cmd = create_lldap_command();
+    cmd.arg("run");
+    cmd.arg("--verbose");
+    cmd.envs(envs.iter().copied());
+    let child = cmd.spawn().expect("Unable to start server");
+    let mut started = false;
+    for _ in 0..MAX_HEALTHCHECK_ATTEMPS {
+        let status = create_lldap_command()
+            .arg("healthcheck")
+            .envs(envs.iter().copied())
+            .status()
+            .expect("healthcheck fail");
+        if status.success() {
+            started = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(1000));
+    }
+    assert!(started);
+    child
+}
+
+/// Asks the server to exit, and kills it if it doesn't.
+pub fn stop_lldap(child: &mut ChildProcess) {
+    let result = signal::kill(
+        Pid::from_raw(child.id().try_into().unwrap()),
+        Signal::SIGTERM,
+    );
+    if let Err(err) = result {
+        println!("Failed to send kill signal: {:?}", err);
+        let _ = child
+            .kill()
+            .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
+        return;
+    }
+
+    for _ in 0..10 {
+        let status = child.try_wait();
+        if status.is_err() {}
+        match status {
+            Err(e) => {
+                println!(
+                    "Failed to get status while waiting for graceful exit: {}",
+                    e
+                );
                 break;
             }
-            thread::sleep(Duration::from_millis(1000));
+            Ok(None) => {
+                println!("LLDAP still running, sleeping for 1 second.");
+            }
+            Ok(Some(status)) => {
+                if !status.success() {
+                    println!("LLDAP exited with status {}", status)
+                }
+                return;
+            }
         }
-        assert!(started);
+        thread::sleep(Duration::from_millis(1000));
+    }
+    println!("LLDAP alive after 10 seconds, forcing exit.");
+    let _ = child
+        .kill()
+        .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
+}
+
+impl LLDAPFixture {
+    pub fn new() -> Self {
+        let child = start_lldap(&[]);
         let client = ClientBuilder::new()
             .connect_timeout(std::time::Duration::from_secs(2))
             .timeout(std::time::Duration::from_secs(5))
@@ -108,6 +159,7 @@ impl LLDAPFixture {
                     display_name: None,
                     first_name: None,
                     last_name: None,
+                    locale: None,
                 },
             },
         )
@@ -176,47 +228,7 @@ impl Drop for LLDAPFixture {
         for group in groups.keys() {
             self.delete_group(group);
         }
-        let result = signal::kill(
-            Pid::from_raw(self.child.id().try_into().unwrap()),
-            Signal::SIGTERM,
-        );
-        if let Err(err) = result {
-            println!("Failed to send kill signal: {:?}", err);
-            let _ = self
-                .child
-                .kill()
-                .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
-            return;
-        }
-
-        for _ in 0..10 {
-            let status = self.child.try_wait();
-            if status.is_err() {}
-            match status {
-                Err(e) => {
-                    println!(
-                        "Failed to get status while waiting for graceful exit: {}",
-                        e
-                    );
-                    break;
-                }
-                Ok(None) => {
-                    println!("LLDAP still running, sleeping for 1 second.");
-                }
-                Ok(Some(status)) => {
-                    if !status.success() {
-                        println!("LLDAP exited with status {}", status)
-                    }
-                    return;
-                }
-            }
-            thread::sleep(Duration::from_millis(1000));
-        }
-        println!("LLDAP alive after 10 seconds, forcing exit.");
-        let _ = self
-            .child
-            .kill()
-            .map_err(|err| println!("Failed to kill LLDAP: {:?}", err));
+        stop_lldap(&mut self.child);
     }
 }
 
@@ -0,0 +1,106 @@
+use lldap::embedded::{ConfigurationBuilder, CreateUserRequest, DomainError, Lldap, UserId};
+use secstr::SecUtf8;
+
+struct TempDatabase(std::path::PathBuf);
+
+impl TempDatabase {
+    fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("lldap_embedded_{}.db", uuid::Uuid::new_v4())))
+    }
+
+    fn url(&self) -> String {
+        format!("sqlite://{}?mode=rwc", self.0.display())
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+async fn connect(database: &TempDatabase) -> Lldap {
+    let config = ConfigurationBuilder::default()
+        .database_url(database.url())
+        .key_seed(Some(SecUtf8::from("embedded test seed")))
+        .build()
+        .unwrap();
+    Lldap::connect(config).await.unwrap()
+}
+
+#[tokio::test]
+async fn create_and_authenticate_user() {
+    let database = TempDatabase::new();
+    let lldap = connect(&database).await;
+    let bob = UserId::new("bob");
+    lldap
+        .create_user(
+            CreateUserRequest {
+                user_id: bob.clone(),
+                email: "bob@example.com".to_owned(),
+                ..Default::default()
+            },
+            Some(&SecUtf8::from("bob00000")),
+        )
+        .await
+        .unwrap();
+    assert_eq!(lldap.get_user(&bob).await.unwrap().email, "bob@example.com");
+    lldap.authenticate(&bob, "bob00000").await.unwrap();
+    assert!(matches!(
+        lldap.authenticate(&bob, "wrong password").await,
+        Err(DomainError::AuthenticationError(_))
+    ));
+}
+
+#[tokio::test]
+async fn manage_groups() {
+    let database = TempDatabase::new();
+    let lldap = connect(&database).await;
+    let bob = UserId::new("bob");
+    lldap
+        .create_user(
+            CreateUserRequest {
+                user_id: bob.clone(),
+                email: "bob@example.com".to_owned(),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+    let group = lldap.create_group("developers").await.unwrap();
+    lldap.add_user_to_group(&bob, group).await.unwrap();
+    let groups = lldap.get_user_groups(&bob).await.unwrap();
+    assert_eq!(
+        groups.iter().map(|g| g.group_id).collect::<Vec<_>>(),
+        vec![group]
+    );
+    lldap.remove_user_from_group(&bob, group).await.unwrap();
+    assert!(lldap.get_user_groups(&bob).await.unwrap().is_empty());
+    // A user without a password can't log in.
+    lldap.authenticate(&bob, "").await.unwrap_err();
+}
+
+#[tokio::test]
+async fn reconnect_keeps_the_data() {
+    let database = TempDatabase::new();
+    let bob = UserId::new("bob");
+    {
+        let lldap = connect(&database).await;
+        lldap
+            .create_user(
+                CreateUserRequest {
+                    user_id: bob.clone(),
+                    email: "bob@example.com".to_owned(),
+                    ..Default::default()
+                },
+                Some(&SecUtf8::from("bob00000")),
+            )
+            .await
+            .unwrap();
+    }
+    // The built-in groups are not duplicated, and the password is still valid with the same
+    // key seed.
+    let lldap = connect(&database).await;
+    lldap.authenticate(&bob, "bob00000").await.unwrap();
+}
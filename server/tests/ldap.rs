@@ -2,7 +2,7 @@ use std::collections::{HashMap, HashSet};
 
 use crate::common::{
     env,
-    fixture::{new_id, LLDAPFixture, User},
+    fixture::{new_id, start_lldap, stop_lldap, LLDAPFixture, User},
 };
 use ldap3::{LdapConn, Scope, SearchEntry, SearchResult};
 use serial_test::file_serial;
@@ -110,3 +110,21 @@ fn get_users_and_groups(results: SearchResult) -> HashMap<String, HashSet<String
     }
     found_users
 }
+
+#[test]
+#[file_serial]
+fn ldap_without_http() {
+    let mut child = start_lldap(&[("LLDAP_DISABLE_HTTP_UI", "true")]);
+    let http_address = env::http_url().replace("http://", "");
+    let http_connection = std::net::TcpStream::connect(http_address);
+    let bind_dn = format!("uid={},ou=people,{}", env::admin_dn(), env::base_dn());
+    let ldap_bind = LdapConn::new(env::ldap_url().as_str()).and_then(|mut ldap| {
+        ldap.simple_bind(bind_dn.as_str(), env::admin_password().as_str())?
+            .success()?;
+        ldap.unbind()
+    });
+    // Stop the server before checking, to not leave it running for the other tests.
+    stop_lldap(&mut child);
+    assert!(http_connection.is_err());
+    ldap_bind.expect("failed to bind to ldap");
+}